@@ -10,18 +10,24 @@
 
 use clap::{arg_enum, crate_authors, crate_version, value_t, App, Arg, ArgGroup, ArgMatches};
 use crossbeam_channel::{select, Receiver, Sender};
+use dfw::inventory::{ContainerEvent, ContainerHistory, EventTracker};
+use dfw::preflight::{preflight, HostProbes};
 use dfw::types::DFW;
 use dfw::util::*;
-use dfw::{ContainerFilter, ProcessContext, ProcessingOptions};
+use dfw::validate::{lint, validate};
+use dfw::{
+    nft_binary, ContainerFilter, ProcessContext, ProcessingOptions, RuleHistory, RuleSet, Sections,
+};
 use failure::bail;
 use shiplift::builder::{EventFilter, EventFilterType, EventsOptions};
 use shiplift::Docker;
-use slog::{debug, error, info, o, trace, Logger};
+use slog::{debug, error, info, o, trace, warn, Logger};
 use sloggers::terminal::{Destination, TerminalLoggerBuilder};
 use sloggers::types::Severity;
 use sloggers::Build;
+use std::path::Path;
 use std::thread;
-use std::time::{Duration, Instant};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
 mod errors {
     use failure::Error;
@@ -41,11 +47,11 @@ arg_enum! {
     }
 }
 
-fn load_config(matches: &ArgMatches) -> Result<DFW> {
+fn load_config(matches: &ArgMatches, logger: &Logger) -> Result<DFW> {
     let toml: DFW = if matches.is_present("config-file") {
-        load_file(matches.value_of("config-file").unwrap())?
+        load_config_file(matches.value_of("config-file").unwrap())?
     } else if matches.is_present("config-path") {
-        load_path(matches.value_of("config-path").unwrap())?
+        load_config_path(matches.value_of("config-path").unwrap())?
     } else {
         // This statement should be unreachable, since clap verifies that either config-file or
         // config-path is populated.
@@ -53,6 +59,15 @@ fn load_config(matches: &ArgMatches) -> Result<DFW> {
         bail!("neither config-file nor config-path specified");
     };
 
+    if let Some(note) = check_config_version(&toml)? {
+        warn!(logger, "{}", note;
+              o!("version" => toml.version));
+    }
+    validate(&toml)?;
+    for message in lint(&toml) {
+        warn!(logger, "{}", message);
+    }
+
     Ok(toml)
 }
 
@@ -114,6 +129,7 @@ fn spawn_burst_monitor(
 
 fn spawn_event_monitor(
     docker_url: Option<String>,
+    mut event_tracker: EventTracker,
     s_event: Sender<()>,
     logger: &Logger,
 ) -> thread::JoinHandle<()> {
@@ -125,34 +141,56 @@ fn spawn_event_monitor(
             None => Docker::new(),
         };
         loop {
-            trace!(logger, "Waiting for events");
-            for event in docker
-                .events(
-                    &EventsOptions::builder()
-                        .filter(vec![EventFilter::Type(EventFilterType::Container)])
-                        .build(),
-                )
-                .unwrap()
-            {
+            let mut options = EventsOptions::builder();
+            options.filter(vec![EventFilter::Type(EventFilterType::Container)]);
+            // Request the events since the last processed event, such that no events are missed
+            // between two requests or across restarts.
+            if let Some(since) = event_tracker.since() {
+                options.since(&since);
+            }
+            trace!(logger, "Waiting for events";
+                   o!("since" => event_tracker.since()));
+            let events = docker.events(&options.build()).unwrap().map(|event| {
                 trace!(logger, "Received event";
                        o!("event" => format!("{:?}", &event)));
-                match event.status {
-                    Some(ref status) => match &**status {
-                        "create" | "destroy" | "start" | "restart" | "die" | "stop" => {
-                            trace!(logger, "Trigger channel about event";
-                                       o!("event" => format!("{:?}", event)));
-                            s_event.send(()).expect("Failed to send trigger event");
-                            break;
-                        }
-                        _ => continue,
-                    },
-                    None => continue,
+                ContainerEvent::from(event)
+            });
+            if let Some(event) = event_tracker.next_trigger(events) {
+                trace!(logger, "Trigger channel about event";
+                       o!("event" => format!("{:?}", event)));
+                if let Err(error) = event_tracker.persist() {
+                    warn!(logger, "Failed to persist the last processed event";
+                          o!("error" => error.to_string()));
                 }
+                s_event.send(()).expect("Failed to send trigger event");
             }
         }
     })
 }
 
+/// Record the applied rules as seen now in the rule history, if one is kept.
+///
+/// No rules are applied during a dry run, nothing is recorded then. Failing to record the rules is
+/// only logged, they have been applied already.
+fn record_rule_history(
+    applied: Option<RuleSet>,
+    rule_history: Option<&RuleHistory>,
+    logger: &Logger,
+) {
+    let (ruleset, rule_history) = match (applied, rule_history) {
+        (Some(ruleset), Some(rule_history)) => (ruleset, rule_history),
+        _ => return,
+    };
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_secs());
+    rule_history.record(&ruleset, now);
+    if let Err(error) = rule_history.persist() {
+        warn!(logger, "Failed to record the first-seen times of the rules";
+              o!("error" => error.to_string()));
+    }
+}
+
 #[cfg(unix)]
 fn run<'a>(
     matches: &ArgMatches<'a>,
@@ -163,10 +201,14 @@ fn run<'a>(
            o!("version" => crate_version!(),
               "started_at" => format!("{}", time::OffsetDateTime::now().format("%FT%T%z"))));
 
-    let toml = load_config(&matches);
+    let toml = load_config(&matches, root_logger);
     if matches.is_present("check-config") {
         return toml.map(|_| ());
     }
+    if matches.is_present("print-config") {
+        print!("{}", normalized_config(&toml?)?);
+        return Ok(());
+    }
 
     let toml = toml?;
     debug!(root_logger, "Initial configuration loaded";
@@ -208,7 +250,38 @@ fn run<'a>(
         Some("running") => ContainerFilter::Running,
         Some(_) | None => bail!("wrong or no container filter specified"),
     };
-    let processing_options = ProcessingOptions { container_filter };
+    let sections = match matches.value_of("sections") {
+        Some(sections) => sections.parse()?,
+        None => Sections::ALL,
+    };
+    trace!(root_logger, "Sections to process: {}", sections;
+           o!("sections" => sections.to_string()));
+    let processing_options = ProcessingOptions {
+        container_filter,
+        sections,
+    };
+
+    if matches.is_present("explain") {
+        let ctx = ProcessContext::new(&docker, &toml, &processing_options, root_logger, true)?;
+        let mut explained = ctx.explain()?;
+        if let Some(rule_state_file) = matches.value_of("rule-state-file") {
+            RuleHistory::load(Path::new(rule_state_file))?.annotate(&mut explained);
+        }
+        for (rule, explanation) in explained {
+            println!("{}\n    # {}", rule, explanation);
+        }
+        return Ok(());
+    }
+
+    if matches.is_present("self-test") {
+        let ctx = ProcessContext::new(&docker, &toml, &processing_options, root_logger, true)?;
+        ctx.self_test()?;
+        info!(
+            root_logger,
+            "Self-test succeeded, nft accepted the generated rules"
+        );
+        return Ok(());
+    }
 
     let monitor_events = !matches.is_present("disable-event-monitoring");
     trace!(root_logger, "Monitoring events: {}", monitor_events;
@@ -222,49 +295,110 @@ fn run<'a>(
     trace!(root_logger, "Dry run: {}", dry_run;
            o!("dry_run" => dry_run));
 
+    if dry_run {
+        // Rules are not applied during a dry run, missing prerequisites are thus reported instead
+        // of failing the run.
+        let report = preflight(&HostProbes {
+            nft: nft_binary(&toml),
+        });
+        if report.passed() {
+            info!(root_logger, "Preflight checks passed";
+                  o!("nft_version" => format!("{:?}", report.nft_version)));
+        }
+        for problem in &report.problems {
+            warn!(root_logger, "Prerequisite of applying rules is not met";
+                  o!("prerequisite" => problem.prerequisite.to_string(),
+                     "problem" => problem.message.clone()));
+        }
+    }
+
+    // The first-seen times of the rules are tracked across all reconciles.
+    let rule_history = match matches.value_of("rule-state-file") {
+        Some(rule_state_file) => Some(RuleHistory::load(Path::new(rule_state_file))?),
+        None => None,
+    };
+
     let processing_logger = root_logger.new(o!());
-    let process: Box<Fn() -> Result<()>> = match value_t!(matches.value_of("load-mode"), LoadMode)?
-    {
-        LoadMode::Once => {
-            trace!(root_logger, "Creating process closure according to load mode";
+    // Vanished containers are tracked across all reconciles, such that their rules can be kept for
+    // the configured grace period.
+    let container_history = ContainerHistory::default();
+    let process: Box<dyn Fn() -> Result<()>> =
+        match value_t!(matches.value_of("load-mode"), LoadMode)? {
+            LoadMode::Once => {
+                trace!(root_logger, "Creating process closure according to load mode";
                    o!("load_mode" => "once"));
-            Box::new(|| {
-                ProcessContext::new(
-                    &docker,
-                    &toml,
-                    &processing_options,
-                    &processing_logger,
-                    dry_run,
-                )?
-                .process()
-                .map_err(From::from)
-            })
-        }
-        LoadMode::Always => {
-            trace!(root_logger, "Creating process closure according to load mode";
+                Box::new(|| {
+                    let ctx = ProcessContext::with_history(
+                        &docker,
+                        &toml,
+                        &processing_options,
+                        &container_history,
+                        &processing_logger,
+                        dry_run,
+                    )?;
+                    let applied = ctx.process()?;
+                    record_rule_history(applied, rule_history.as_ref(), &processing_logger);
+                    Ok(())
+                })
+            }
+            LoadMode::Always => {
+                trace!(root_logger, "Creating process closure according to load mode";
                    o!("load_mode" => "always"));
-            Box::new(|| {
-                let toml = load_config(&matches)?;
-                debug!(root_logger, "Reloaded configuration before processing";
+                Box::new(|| {
+                    let toml = load_config(&matches, root_logger)?;
+                    debug!(root_logger, "Reloaded configuration before processing";
                        o!("config" => format!("{:#?}", toml)));
 
-                ProcessContext::new(
-                    &docker,
-                    &toml,
-                    &processing_options,
-                    &processing_logger,
-                    dry_run,
-                )?
-                .process()
-                .map_err(From::from)
-            })
-        }
-    };
+                    let ctx = ProcessContext::with_history(
+                        &docker,
+                        &toml,
+                        &processing_options,
+                        &container_history,
+                        &processing_logger,
+                        dry_run,
+                    )?;
+                    let applied = ctx.process()?;
+                    record_rule_history(applied, rule_history.as_ref(), &processing_logger);
+                    Ok(())
+                })
+            }
+        };
     trace!(
         root_logger,
         "Load mode: {:?}",
         matches.value_of("load-mode")
     );
+    let process = retry_stale_addresses(process, root_logger);
+
+    let metrics_address = toml
+        .runtime
+        .as_ref()
+        .and_then(|runtime| runtime.metrics_address);
+    #[cfg(feature = "metrics")]
+    let process = match metrics_address {
+        Some(metrics_address) => with_metrics(process, metrics_address, &toml, root_logger)?,
+        None => process,
+    };
+    #[cfg(not(feature = "metrics"))]
+    {
+        if let Some(metrics_address) = metrics_address {
+            warn!(root_logger, "Metrics address configured, but DFW was built without the `metrics` feature";
+                  o!("metrics_address" => metrics_address.to_string()));
+        }
+    }
+    #[cfg(not(feature = "swarm"))]
+    {
+        if toml
+            .defaults
+            .as_ref()
+            .map_or(false, |defaults| defaults.swarm_services)
+        {
+            warn!(
+                root_logger,
+                "Swarm services enabled, but DFW was built without the `swarm` feature"
+            );
+        }
+    }
 
     info!(root_logger, "Application started";
           "version" => crate_version!(),
@@ -274,11 +408,19 @@ fn run<'a>(
     debug!(root_logger, "Start first processing");
     process()?;
 
-    if run_once || (!monitor_events && load_interval == 0) {
-        // Either run-once is specified or both events are not monitored and rules aren't processed
-        // regularly -- process once, then exit.
+    if run_once {
+        // Reconcile exactly once, neither Docker events nor the load interval are waited for.
+        info!(root_logger, "Run once specified, exiting after the first processing";
+              o!("version" => crate_version!(),
+                 "exited_at" => format!("{}", time::OffsetDateTime::now().format("%FT%T%z"))));
+        return Ok(());
+    }
+
+    if !monitor_events && load_interval == 0 {
+        // Events are not monitored and rules aren't processed regularly -- process once, then
+        // exit.
         info!(root_logger,
-              "Run once specified (or load-interval is zero and events aren't monitored), exiting";
+              "Load-interval is zero and events aren't monitored, exiting";
               o!("version" => crate_version!(),
                  "exited_at" => format!("{}", time::OffsetDateTime::now().format("%FT%T%z"))));
         return Ok(());
@@ -293,6 +435,14 @@ fn run<'a>(
         let (s_event, r_event) = crossbeam_channel::bounded(0);
         let docker_url = matches.value_of("docker-url").map(|s| s.to_owned());
         let burst_timeout = value_t!(matches.value_of("burst-timeout"), u64)?;
+        let event_tracker = match matches.value_of("event-state-file") {
+            Some(event_state_file) => EventTracker::load(Path::new(event_state_file))?,
+            None => EventTracker::default(),
+        };
+        if let Some(since) = event_tracker.since() {
+            info!(root_logger, "Replaying Docker events missed since the last processed event";
+                  o!("since" => since));
+        }
 
         trace!(root_logger, "Start burst monitoring thread";
                o!("burst_timeout" => burst_timeout));
@@ -300,7 +450,7 @@ fn run<'a>(
 
         trace!(root_logger, "Start event monitoring thread";
                o!("docker_url" => &docker_url));
-        spawn_event_monitor(docker_url, s_event, root_logger);
+        spawn_event_monitor(docker_url, event_tracker, s_event, root_logger);
 
         // Note: we need both spawned threads for the entirety of the programs lifetime. As such we
         // do not bother cleaning them up, but rather let the OS handle the cleanup once we exit the
@@ -353,6 +503,54 @@ fn run<'a>(
     Ok(())
 }
 
+/// Wrap the process closure such that a run is repeated if a container got a new address while the
+/// rules were generated, see `ProcessContext::verify_addresses`. Every run captures the inventory
+/// anew.
+fn retry_stale_addresses<'a>(
+    process: Box<dyn Fn() -> Result<()> + 'a>,
+    logger: &'a Logger,
+) -> Box<dyn Fn() -> Result<()> + 'a> {
+    Box::new(move || dfw::retry_stale_addresses(&process, logger))
+}
+
+/// Wrap the process closure such that every run is recorded in the metrics, which are served on
+/// the given address.
+#[cfg(feature = "metrics")]
+fn with_metrics<'a>(
+    process: Box<dyn Fn() -> Result<()> + 'a>,
+    metrics_address: std::net::SocketAddr,
+    toml: &DFW,
+    logger: &'a Logger,
+) -> Result<Box<dyn Fn() -> Result<()> + 'a>> {
+    use dfw::metrics::{serve, Metrics};
+    use std::sync::Arc;
+
+    let nft_binary = nft_binary(toml);
+    let metrics = Arc::new(Metrics::with_ruleset(move || {
+        let output =
+            dfw::nftables::nft_command(nft_binary.netns.as_deref(), nft_binary.nft_path.as_deref())
+                .args(["--handle", "list", "ruleset"])
+                .output()?;
+        if !output.status.success() {
+            bail!(
+                "failed to list ruleset: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }));
+    let bound_address = serve(metrics_address, Arc::clone(&metrics))?;
+    info!(logger, "Serving metrics";
+          o!("metrics_address" => bound_address.to_string()));
+
+    Ok(Box::new(move || {
+        let started_at = Instant::now();
+        let result = process();
+        metrics.record_reconcile(started_at.elapsed(), result.is_ok());
+        result
+    }))
+}
+
 fn get_arg_matches<'a>() -> ArgMatches<'a> {
     App::new("dfw")
         .version(crate_version!())
@@ -435,6 +633,33 @@ fn get_arg_matches<'a>() -> ArgMatches<'a> {
                      milliseconds",
                 ),
         )
+        .arg(
+            Arg::with_name("event-state-file")
+                .takes_value(true)
+                .long("event-state-file")
+                .value_name("FILE")
+                .conflicts_with("disable-event-monitoring")
+                .help("Record the last processed Docker event in the given file")
+                .long_help(
+                    "Record the last processed Docker event in the given file. After a restart, \
+                     the events missed since then are replayed and trigger processing, in \
+                     addition to the processing on startup.",
+                ),
+        )
+        .arg(
+            Arg::with_name("rule-state-file")
+                .takes_value(true)
+                .long("rule-state-file")
+                .value_name("FILE")
+                .help("Record the time every rule was first applied at in the given file")
+                .long_help(
+                    "Record the time every rule was first applied at in the given file, in \
+                     seconds since the epoch. Rules that are still generated keep their time \
+                     across reconciles and restarts, rules that are no longer generated are \
+                     removed from the file. Together with --explain, the recorded times are \
+                     shown for every rule.",
+                ),
+        )
         .arg(
             Arg::with_name("container-filter")
                 .takes_value(true)
@@ -444,6 +669,18 @@ fn get_arg_matches<'a>() -> ArgMatches<'a> {
                 .default_value("running")
                 .help("Filter the containers to be included during processing"),
         )
+        .arg(
+            Arg::with_name("sections")
+                .takes_value(true)
+                .long("sections")
+                .value_name("SECTIONS")
+                .help("Only apply the given, comma-separated sections (e.g. wider_world_to_container)")
+                .long_help(
+                    "Only apply the given, comma-separated sections (e.g. wider_world_to_container). \
+                     The rules of the selected sections are replaced in the current ruleset, the \
+                     rules of all other sections are left untouched.",
+                ),
+        )
         .arg(
             Arg::with_name("disable-event-monitoring")
                 .takes_value(false)
@@ -454,7 +691,11 @@ fn get_arg_matches<'a>() -> ArgMatches<'a> {
             Arg::with_name("run-once")
                 .takes_value(false)
                 .long("run-once")
-                .help("Process rules once, then exit."),
+                .help("Process rules once, then exit.")
+                .long_help(
+                    "Process rules once, then exit. Neither Docker events nor signals are waited \
+                     for, the exit code reflects whether the rules were applied successfully."
+                ),
         )
         .arg(
             Arg::with_name("dry-run")
@@ -464,7 +705,37 @@ fn get_arg_matches<'a>() -> ArgMatches<'a> {
                 .long_help(
                     "Don't touch nft, just show what would be done. Note that this requires Docker \
                      and the containers/networks referenced in the configuration to be available. \
-                     If you want to check the config for validity, specify --check-config instead."
+                     If you want to check the config for validity, specify --check-config instead. \
+                     Prerequisites of applying the rules that are not met, e.g. a missing \
+                     CAP_NET_ADMIN capability or an incompatible nft binary, are reported as \
+                     warnings."
+                ),
+        )
+        .arg(
+            Arg::with_name("explain")
+                .takes_value(false)
+                .long("explain")
+                .conflicts_with_all(&["check-config", "print-config"])
+                .help("Print the rules that would be applied with their origin, exit afterwards.")
+                .long_help(
+                    "Print the rules that would be applied, each followed by an explanation of \
+                     its origin: the rule of the configuration that produced it, the expansion of \
+                     the wildcard network that applied and the containers and networks it was \
+                     resolved to. No rules are applied, exits afterwards. Like --dry-run, this \
+                     requires Docker to be available."
+                ),
+        )
+        .arg(
+            Arg::with_name("self-test")
+                .takes_value(false)
+                .long("self-test")
+                .conflicts_with_all(&["check-config", "print-config", "explain"])
+                .help("Apply the rules in a throwaway network namespace, exit afterwards.")
+                .long_help(
+                    "Apply the rules that would be applied within a throwaway network namespace, \
+                     reporting any errors of nft. The namespace is deleted afterwards, the rules \
+                     of the host are not touched. Requires root privileges and, like --dry-run, \
+                     Docker to be available."
                 ),
         )
         .arg(
@@ -473,6 +744,18 @@ fn get_arg_matches<'a>() -> ArgMatches<'a> {
                 .long("check-config")
                 .help("Verify if the provided configuration is valid, exit afterwards."),
         )
+        .arg(
+            Arg::with_name("print-config")
+                .takes_value(false)
+                .long("print-config")
+                .conflicts_with("check-config")
+                .help("Print the configuration as DFW understands it, exit afterwards.")
+                .long_help(
+                    "Print the configuration as DFW understands it, exit afterwards. The \
+                     configuration is printed as TOML after all files of the configuration path \
+                     have been merged and defaults have been applied."
+                ),
+        )
         .get_matches()
 }
 fn main() {
@@ -499,9 +782,17 @@ fn main() {
         .build()
         .expect("Failed to setup logging");
 
-    if let Err(ref e) = run(&matches, &r_signal, &root_logger) {
+    let result = run(&matches, &r_signal, &root_logger);
+    if let Err(ref e) = result {
         error!(root_logger, "Encountered error";
                o!("error" => format!("{}", e),
                   "backtrace" => format!("{}", e.backtrace())));
     }
+
+    // The exit code reflects whether the processing succeeded. Dropping the logger beforehand
+    // flushes the pending log records.
+    drop(root_logger);
+    if result.is_err() {
+        std::process::exit(1);
+    }
 }
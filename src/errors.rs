@@ -20,6 +20,13 @@ pub enum DFWError {
     NFTablesError { stdout: String, stderr: String },
     #[fail(display = "trait method unimplemented: {}", method)]
     TraitMethodUnimplemented { method: String },
+    #[fail(display = "invalid configuration: {}", message)]
+    ConfigError { message: String },
+    #[fail(
+        display = "container addresses changed since the inventory was captured: {}",
+        addresses
+    )]
+    StaleAddresses { addresses: String },
 }
 
 pub type Result<E> = ::std::result::Result<E, Error>;
@@ -0,0 +1,1549 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module holds the types describing the environment DFW generates its rules for, i.e. the
+//! containers and networks known to Docker, the current ruleset and the host DFW is running on.
+//!
+//! All external data used during rule generation is retrieved through a [`ContainerInventory`],
+//! which allows generating rules without talking to Docker or nftables, see
+//! [`generate`](../process/fn.generate.html).
+//!
+//! [`ContainerInventory`]: trait.ContainerInventory.html
+
+use crate::errors::*;
+use crate::process::{ContainerFilter, HostFacts};
+use crate::types::HOST_NETWORK;
+use crate::util::read_cidr_file;
+use failure::{bail, ResultExt};
+use serde::Deserialize;
+use shiplift::builder::{ContainerFilter as ContainerFilterShiplift, ContainerListOptions};
+use shiplift::Docker;
+use std::collections::hash_map::Entry;
+use std::collections::{BTreeMap, BTreeSet, HashMap as Map};
+use std::convert::TryFrom;
+use std::fs::{self, File};
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{IpAddr, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+use strum_macros::{Display, EnumString};
+
+/// A container known to the inventory.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Container {
+    /// ID of the container.
+    pub id: String,
+    /// Names of the container. A leading slash, as reported by Docker, is ignored.
+    pub names: Vec<String>,
+    /// Labels of the container.
+    pub labels: Map<String, String>,
+    /// Health of the container, `None` if the container doesn't define a healthcheck.
+    pub health: Option<HealthStatus>,
+    /// Ports the container publishes on the host, see
+    /// [`PublishedPort`](struct.PublishedPort.html).
+    pub published_ports: Vec<PublishedPort>,
+}
+
+/// A port a container publishes on the host, e.g. through `docker run --publish 53:53/udp`.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
+pub struct PublishedPort {
+    /// Port on the host the container port is published on.
+    pub host_port: u16,
+    /// Port of the container that is published.
+    pub container_port: u16,
+    /// Protocol of the published port, e.g. `tcp` or `udp`.
+    ///
+    /// Can be left blank in a [`StaticInventory`](struct.StaticInventory.html), `tcp` will be used
+    /// as default.
+    #[serde(default = "default_published_port_protocol")]
+    pub protocol: String,
+    /// Address of the host the port is published on, `None` if it is published on all addresses.
+    pub host_ip: Option<IpAddr>,
+}
+
+fn default_published_port_protocol() -> String {
+    "tcp".to_owned()
+}
+
+impl PublishedPort {
+    /// Get the published ports from the ports of a container as listed by Docker.
+    ///
+    /// Ports that are not published on the host are skipped. Docker lists ports published on all
+    /// addresses once per address family, these result in a single published port.
+    fn from_docker_ports(ports: Vec<shiplift::rep::Port>) -> Vec<PublishedPort> {
+        let published_ports = ports
+            .into_iter()
+            .filter_map(|port| {
+                Some(PublishedPort {
+                    host_port: u16::try_from(port.PublicPort?).ok()?,
+                    container_port: u16::try_from(port.PrivatePort).ok()?,
+                    protocol: port.Type.to_lowercase(),
+                    host_ip: port
+                        .IP
+                        .and_then(|host_ip| host_ip.parse::<IpAddr>().ok())
+                        .filter(|host_ip| !host_ip.is_unspecified()),
+                })
+            })
+            .collect::<BTreeSet<_>>();
+        published_ports.into_iter().collect()
+    }
+}
+
+/// A port the image of a container declares through `EXPOSE`, e.g. `EXPOSE 53/udp`, without
+/// publishing it on the host.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(deny_unknown_fields)]
+pub struct ImagePort {
+    /// Port of the container that is exposed.
+    pub port: u16,
+    /// Protocol of the exposed port, e.g. `tcp` or `udp`.
+    ///
+    /// Can be left blank in a [`StaticInventory`](struct.StaticInventory.html), `tcp` will be used
+    /// as default.
+    #[serde(default = "default_published_port_protocol")]
+    pub protocol: String,
+}
+
+impl FromStr for ImagePort {
+    type Err = String;
+
+    /// Convert a port as it is reported by Docker, e.g. `80/tcp`, into an
+    /// [`ImagePort`](struct.ImagePort.html).
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut split = s.splitn(2, '/');
+        let port = split
+            .next()
+            .unwrap_or_default()
+            .parse()
+            .map_err(|e| format!("invalid exposed port '{}': {}", s, e))?;
+        let protocol = split
+            .next()
+            .map_or_else(default_published_port_protocol, str::to_lowercase);
+
+        Ok(ImagePort { port, protocol })
+    }
+}
+
+/// Health of a container, as determined by the healthcheck of the container.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "snake_case")]
+pub enum HealthStatus {
+    /// The healthcheck has not succeeded yet.
+    Starting,
+    /// The healthcheck succeeds.
+    Healthy,
+    /// The healthcheck fails.
+    Unhealthy,
+}
+
+impl HealthStatus {
+    /// Get the health from the status of a container as listed by Docker, e.g.
+    /// `Up 5 minutes (healthy)`. Returns `None` if the status doesn't include the health, i.e. the
+    /// container doesn't define a healthcheck.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::inventory::HealthStatus;
+    /// assert_eq!(
+    ///     Some(HealthStatus::Starting),
+    ///     HealthStatus::from_docker_status("Up 3 seconds (health: starting)")
+    /// );
+    /// assert_eq!(None, HealthStatus::from_docker_status("Up 5 minutes"));
+    /// ```
+    pub fn from_docker_status(status: &str) -> Option<HealthStatus> {
+        let health = status
+            .trim_end()
+            .strip_suffix(')')
+            .and_then(|status| status.rsplit('(').next())?;
+        match health.trim_start_matches("health:").trim() {
+            "starting" => Some(HealthStatus::Starting),
+            "healthy" => Some(HealthStatus::Healthy),
+            "unhealthy" => Some(HealthStatus::Unhealthy),
+            _ => None,
+        }
+    }
+}
+
+/// A network known to the inventory.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Network {
+    /// ID of the network.
+    pub id: String,
+    /// Name of the network.
+    pub name: String,
+    /// Driver of the network, e.g. `bridge`. Empty if unknown.
+    pub driver: String,
+    /// Driver options of the network, e.g. `com.docker.network.bridge.name`.
+    pub options: Map<String, String>,
+    /// Subnets of the network in CIDR notation, e.g. `172.18.0.0/16`.
+    pub subnets: Vec<String>,
+    /// Endpoints of the containers attached to this network, keyed by container ID.
+    pub containers: Map<String, NetworkEndpoint>,
+}
+
+impl Network {
+    /// Whether the network is backed by a Linux bridge on the host.
+    ///
+    /// If the driver is unknown, every network except the `host` and `none` networks of Docker is
+    /// assumed to be a bridge.
+    pub fn is_bridge(&self) -> bool {
+        match self.driver.as_str() {
+            "" => self.name != HOST_NETWORK && self.name != "none",
+            driver => driver == "bridge",
+        }
+    }
+}
+
+/// The endpoint of a container within a network.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct NetworkEndpoint {
+    /// IPv4 address of the container in CIDR notation, e.g. `172.18.0.2/16`.
+    pub ipv4_address: String,
+    /// IPv6 address of the container in CIDR notation, empty if IPv6 is not enabled.
+    pub ipv6_address: String,
+    /// Further IPv4 addresses of the container within the network in CIDR notation, if the
+    /// container has more than one.
+    ///
+    /// Rules matching on the address of the container match on all of its addresses, DNAT rules
+    /// translate to the primary `ipv4_address` unless the rule pins another one, see
+    /// [`WiderWorldToContainerRule.dst_ip`](../types/struct.WiderWorldToContainerRule.html#structfield.dst_ip).
+    pub secondary_ipv4_addresses: Vec<String>,
+}
+
+impl NetworkEndpoint {
+    /// Get the distinct IPv4 addresses of the container without their prefix length, starting
+    /// with the primary address followed by the secondary addresses in ascending order.
+    pub fn ipv4_addresses(&self) -> Vec<&str> {
+        let mut addresses = vec![strip_prefix_length(&self.ipv4_address)];
+        let mut secondary_addresses = self
+            .secondary_ipv4_addresses
+            .iter()
+            .map(|address| strip_prefix_length(address))
+            .collect::<Vec<_>>();
+        secondary_addresses.sort();
+        addresses.append(&mut secondary_addresses);
+
+        let mut distinct_addresses: Vec<&str> = Vec::new();
+        for address in addresses {
+            if !address.is_empty() && !distinct_addresses.contains(&address) {
+                distinct_addresses.push(address);
+            }
+        }
+        distinct_addresses
+    }
+
+    /// Merge the addresses of another endpoint of the same container into this one.
+    fn merge(&mut self, other: NetworkEndpoint) {
+        if self.ipv4_address.is_empty() {
+            self.ipv4_address = other.ipv4_address;
+        } else {
+            self.secondary_ipv4_addresses.push(other.ipv4_address);
+        }
+        if self.ipv6_address.is_empty() {
+            self.ipv6_address = other.ipv6_address;
+        }
+        self.secondary_ipv4_addresses
+            .extend(other.secondary_ipv4_addresses);
+
+        let primary_address = strip_prefix_length(&self.ipv4_address).to_owned();
+        self.secondary_ipv4_addresses.retain(|address| {
+            let address = strip_prefix_length(address);
+            !address.is_empty() && address != primary_address
+        });
+        self.secondary_ipv4_addresses.sort();
+        self.secondary_ipv4_addresses.dedup();
+    }
+}
+
+fn strip_prefix_length(address: &str) -> &str {
+    address.split('/').next().unwrap_or_default()
+}
+
+/// Source of all external data DFW bases its rules on.
+///
+/// The methods with default implementations describe data that is optional for rule generation:
+/// no container aliases, no image ports, no current ruleset and empty host facts. Hostnames are
+/// resolved through the resolver of the host by default.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::inventory::{Container, ContainerInventory, Network};
+/// # use failure::Error;
+/// struct StaticInventory;
+///
+/// impl ContainerInventory for StaticInventory {
+///     fn containers(&self) -> Result<Vec<Container>, Error> {
+///         Ok(vec![Container {
+///             id: "4f4a1e3a9b2c".to_owned(),
+///             names: vec!["web".to_owned()],
+///             ..Default::default()
+///         }])
+///     }
+///
+///     fn networks(&self) -> Result<Vec<Network>, Error> {
+///         Ok(Vec::new())
+///     }
+/// }
+/// ```
+pub trait ContainerInventory {
+    /// List the containers to generate rules for.
+    fn containers(&self) -> Result<Vec<Container>>;
+
+    /// List the networks, including the endpoints of their attached containers.
+    fn networks(&self) -> Result<Vec<Network>>;
+
+    /// Retrieve the network-scoped aliases of the containers. This is only called if a rule selects
+    /// a container by alias.
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        Ok(ContainerAliases::default())
+    }
+
+    /// Retrieve the ports the images of the containers declare through `EXPOSE`. This is only
+    /// called if a rule exposes the ports declared by the image of a container.
+    fn image_ports(&self) -> Result<ImagePorts> {
+        Ok(ImagePorts::default())
+    }
+
+    /// Retrieve the current ruleset, as listed by `nft --handle list ruleset`, if available.
+    fn current_ruleset(&self) -> Option<String> {
+        None
+    }
+
+    /// Retrieve the facts about the host the rules are generated for.
+    fn host_facts(&self) -> Result<HostFacts> {
+        Ok(HostFacts::default())
+    }
+
+    /// Resolve the hostname to its addresses. This is only called if a rule allows its sources by
+    /// hostname, see [`CIDR_DNS_PREFIX`](../types/constant.CIDR_DNS_PREFIX.html).
+    fn resolve_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        resolve_hostname_on_host(hostname)
+    }
+
+    /// Read the contents of the CIDR file. This is only called if a rule references a CIDR file,
+    /// see [`CIDR_FILE_PREFIX`](../types/constant.CIDR_FILE_PREFIX.html).
+    fn cidr_file(&self, file: &str) -> Result<String> {
+        read_cidr_file(file)
+    }
+}
+
+/// Resolve the hostname through the resolver of the host, returning its distinct addresses in
+/// ascending order.
+fn resolve_hostname_on_host(hostname: &str) -> Result<Vec<IpAddr>> {
+    let addresses = (hostname, 0)
+        .to_socket_addrs()?
+        .map(|address| address.ip())
+        .collect::<BTreeSet<_>>();
+    Ok(addresses.into_iter().collect())
+}
+
+impl<T> ContainerInventory for &T
+where
+    T: ContainerInventory + ?Sized,
+{
+    fn containers(&self) -> Result<Vec<Container>> {
+        (**self).containers()
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        (**self).networks()
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        (**self).container_aliases()
+    }
+
+    fn image_ports(&self) -> Result<ImagePorts> {
+        (**self).image_ports()
+    }
+
+    fn current_ruleset(&self) -> Option<String> {
+        (**self).current_ruleset()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts> {
+        (**self).host_facts()
+    }
+
+    fn resolve_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        (**self).resolve_hostname(hostname)
+    }
+
+    fn cidr_file(&self, file: &str) -> Result<String> {
+        (**self).cidr_file(file)
+    }
+}
+
+/// Inventory data captured from another inventory at a single point in time.
+///
+/// The snapshot is captured once at the start of a reconcile and consumed by all sections, which
+/// ensures that the rules of all sections are generated from the same view of the environment and
+/// that the backing inventory is queried only once. Container aliases, image ports, resolved
+/// hostnames and CIDR files are the exception: they are only retrieved from the backing inventory
+/// if a rule requires them.
+///
+/// Networks reported multiple times (by ID) are merged into one. If a container is reported with
+/// multiple endpoints on the same network, the first endpoint provides the primary address, all
+/// further distinct addresses become secondary addresses (see
+/// [`NetworkEndpoint`](struct.NetworkEndpoint.html)).
+pub struct InventorySnapshot<'a> {
+    inventory: Box<dyn ContainerInventory + 'a>,
+    containers: Vec<Container>,
+    networks: Vec<Network>,
+    current_ruleset: Option<String>,
+    host_facts: HostFacts,
+}
+
+impl<'a> InventorySnapshot<'a> {
+    /// Capture the containers, networks, current ruleset and host facts of the given inventory.
+    pub fn capture(inventory: Box<dyn ContainerInventory + 'a>) -> Result<InventorySnapshot<'a>> {
+        Ok(InventorySnapshot {
+            containers: inventory.containers()?,
+            networks: merge_networks(inventory.networks()?),
+            current_ruleset: inventory.current_ruleset(),
+            host_facts: inventory.host_facts()?,
+            inventory,
+        })
+    }
+
+    /// Query the networks of the backing inventory again, returning the addresses of the snapshot
+    /// that are no longer assigned to the same container within the same network.
+    ///
+    /// Docker can assign a new address to a container when it is restarted. Rules generated from
+    /// the snapshot then reference an address that is stale, or even assigned to another container.
+    pub fn stale_addresses(&self) -> Result<Vec<String>> {
+        let current_networks = merge_networks(self.inventory.networks()?);
+
+        let mut stale_addresses = Vec::new();
+        for network in &self.networks {
+            let current_network = current_networks
+                .iter()
+                .find(|current_network| current_network.id == network.id);
+            for (container_id, endpoint) in &network.containers {
+                let current_addresses = current_network
+                    .and_then(|current_network| current_network.containers.get(container_id))
+                    .map(endpoint_addresses)
+                    .unwrap_or_default();
+                for address in endpoint_addresses(endpoint) {
+                    if !current_addresses.contains(&address)
+                        && !stale_addresses.iter().any(|stale| stale == address)
+                    {
+                        stale_addresses.push(address.to_owned());
+                    }
+                }
+            }
+        }
+
+        Ok(stale_addresses)
+    }
+}
+
+/// Get the IPv4 and IPv6 addresses of the endpoint without their prefix length.
+fn endpoint_addresses(endpoint: &NetworkEndpoint) -> Vec<&str> {
+    let mut addresses = endpoint.ipv4_addresses();
+    let ipv6_address = strip_prefix_length(&endpoint.ipv6_address);
+    if !ipv6_address.is_empty() {
+        addresses.push(ipv6_address);
+    }
+    addresses
+}
+
+impl<'a> ContainerInventory for InventorySnapshot<'a> {
+    fn containers(&self) -> Result<Vec<Container>> {
+        Ok(self.containers.clone())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        Ok(self.networks.clone())
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        self.inventory.container_aliases()
+    }
+
+    fn image_ports(&self) -> Result<ImagePorts> {
+        self.inventory.image_ports()
+    }
+
+    fn current_ruleset(&self) -> Option<String> {
+        self.current_ruleset.clone()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts> {
+        Ok(self.host_facts.clone())
+    }
+
+    fn resolve_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        self.inventory.resolve_hostname(hostname)
+    }
+
+    fn cidr_file(&self, file: &str) -> Result<String> {
+        self.inventory.cidr_file(file)
+    }
+}
+
+/// Merge networks with the same ID and the endpoints of containers within them.
+fn merge_networks(networks: Vec<Network>) -> Vec<Network> {
+    let mut merged: Vec<Network> = Vec::new();
+    for network in networks {
+        let existing = match merged.iter_mut().find(|existing| existing.id == network.id) {
+            Some(existing) => existing,
+            None => {
+                merged.push(network);
+                continue;
+            }
+        };
+        for subnet in network.subnets {
+            if !existing.subnets.contains(&subnet) {
+                existing.subnets.push(subnet);
+            }
+        }
+        for (container_id, endpoint) in network.containers {
+            match existing.containers.entry(container_id) {
+                Entry::Occupied(mut entry) => entry.get_mut().merge(endpoint),
+                Entry::Vacant(entry) => {
+                    entry.insert(endpoint);
+                }
+            }
+        }
+    }
+    merged
+}
+
+/// Inventory retrieving its data from Docker, nftables and the host DFW is running on.
+pub struct DockerInventory<'a> {
+    docker: &'a Docker,
+    container_filter: ContainerFilter,
+    concurrency: usize,
+}
+
+impl<'a> DockerInventory<'a> {
+    /// Create a new inventory querying the given Docker instance, sending at most `concurrency`
+    /// requests to it at the same time.
+    pub fn new(
+        docker: &'a Docker,
+        container_filter: ContainerFilter,
+        concurrency: usize,
+    ) -> DockerInventory<'a> {
+        DockerInventory {
+            docker,
+            container_filter,
+            concurrency,
+        }
+    }
+}
+
+impl<'a> ContainerInventory for DockerInventory<'a> {
+    fn containers(&self) -> Result<Vec<Container>> {
+        let container_list_options = match self.container_filter {
+            ContainerFilter::All => Default::default(),
+            ContainerFilter::Running => ContainerListOptions::builder()
+                .filter(vec![ContainerFilterShiplift::Status("running".to_owned())])
+                .build(),
+        };
+
+        Ok(self
+            .docker
+            .containers()
+            .list(&container_list_options)?
+            .into_iter()
+            .map(|container| Container {
+                health: HealthStatus::from_docker_status(&container.Status),
+                id: container.Id,
+                names: container.Names,
+                labels: container.Labels,
+                published_ports: PublishedPort::from_docker_ports(container.Ports),
+            })
+            .collect())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        // Listing networks doesn't include the attached containers, we have to inspect every
+        // network to retrieve them.
+        let networks = self.docker.networks().list(&Default::default())?;
+        let details = fetch_bounded(networks, self.concurrency, |network| {
+            Ok(self.docker.networks().get(&network.Id).inspect()?)
+        })?;
+
+        Ok(details
+            .into_iter()
+            .map(|details| Network {
+                id: details.Id,
+                name: details.Name,
+                driver: details.Driver,
+                options: details.Options.unwrap_or_default(),
+                subnets: details
+                    .IPAM
+                    .Config
+                    .iter()
+                    .filter_map(|config| config.get("Subnet").cloned())
+                    .collect(),
+                containers: details
+                    .Containers
+                    .into_iter()
+                    .map(|(container_id, endpoint)| {
+                        (
+                            container_id,
+                            NetworkEndpoint {
+                                ipv4_address: endpoint.IPv4Address,
+                                ipv6_address: endpoint.IPv6Address,
+                                secondary_ipv4_addresses: Vec::new(),
+                            },
+                        )
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        let mut container_ids = self
+            .containers()?
+            .into_iter()
+            .map(|container| container.id)
+            .collect::<Vec<_>>();
+        container_ids.sort();
+        container_ids.dedup();
+
+        let output = Command::new("docker")
+            .arg("inspect")
+            .arg("--format")
+            .arg(CONTAINER_ALIASES_FORMAT)
+            .args(&container_ids)
+            .output()
+            .context("failed to query container aliases, is the docker CLI available?")?;
+        if !output.status.success() {
+            bail!(
+                "failed to query container aliases: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(ContainerAliases::from_inspect_output(
+            &String::from_utf8_lossy(&output.stdout),
+        ))
+    }
+
+    fn image_ports(&self) -> Result<ImagePorts> {
+        let mut container_ids = self
+            .containers()?
+            .into_iter()
+            .map(|container| container.id)
+            .collect::<Vec<_>>();
+        container_ids.sort();
+        container_ids.dedup();
+
+        let output = Command::new("docker")
+            .arg("inspect")
+            .arg("--format")
+            .arg(IMAGE_PORTS_FORMAT)
+            .args(&container_ids)
+            .output()
+            .context("failed to query exposed ports, is the docker CLI available?")?;
+        if !output.status.success() {
+            bail!(
+                "failed to query exposed ports: {}",
+                String::from_utf8_lossy(&output.stderr)
+            );
+        }
+
+        Ok(ImagePorts::from_inspect_output(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    fn current_ruleset(&self) -> Option<String> {
+        // Include the rule handles, they are required to selectively replace rules.
+        let output = Command::new("nft")
+            .args(["--handle", "list", "ruleset"])
+            .output()
+            .ok()?;
+        Some(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    fn host_facts(&self) -> Result<HostFacts> {
+        HostFacts::collect()
+    }
+}
+
+/// Inventory retrying the failed queries of another inventory.
+///
+/// Transient failures, e.g. while the Docker daemon restarts, would otherwise abort the reconcile
+/// and leave the rules stale. Containers, networks, container aliases and image ports are retried
+/// up to `retries` times, the delay before every retry starts at `backoff` and doubles with every
+/// further retry. The current ruleset, the host facts, hostnames and CIDR files are not retried,
+/// failing to resolve a hostname is handled by
+/// [`Defaults.dns_failure_policy`](../types/struct.Defaults.html#structfield.dns_failure_policy).
+pub struct RetryInventory<'a> {
+    inventory: Box<dyn ContainerInventory + 'a>,
+    retries: usize,
+    backoff: Duration,
+}
+
+impl<'a> RetryInventory<'a> {
+    /// Create a new inventory retrying the failed queries of the given inventory.
+    pub fn new(
+        inventory: Box<dyn ContainerInventory + 'a>,
+        retries: usize,
+        backoff: Duration,
+    ) -> RetryInventory<'a> {
+        RetryInventory {
+            inventory,
+            retries,
+            backoff,
+        }
+    }
+}
+
+impl<'a> ContainerInventory for RetryInventory<'a> {
+    fn containers(&self) -> Result<Vec<Container>> {
+        retry_with_backoff(self.retries, self.backoff, || self.inventory.containers())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        retry_with_backoff(self.retries, self.backoff, || self.inventory.networks())
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        retry_with_backoff(self.retries, self.backoff, || {
+            self.inventory.container_aliases()
+        })
+    }
+
+    fn image_ports(&self) -> Result<ImagePorts> {
+        retry_with_backoff(self.retries, self.backoff, || self.inventory.image_ports())
+    }
+
+    fn current_ruleset(&self) -> Option<String> {
+        self.inventory.current_ruleset()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts> {
+        self.inventory.host_facts()
+    }
+
+    fn resolve_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        self.inventory.resolve_hostname(hostname)
+    }
+
+    fn cidr_file(&self, file: &str) -> Result<String> {
+        self.inventory.cidr_file(file)
+    }
+}
+
+/// Call `query` until it succeeds, retrying it at most `retries` times.
+///
+/// The delay before the first retry is `backoff`, it doubles with every further retry. If all
+/// attempts fail, the error of the last attempt is returned.
+fn retry_with_backoff<R, F>(retries: usize, backoff: Duration, query: F) -> Result<R>
+where
+    F: Fn() -> Result<R>,
+{
+    let mut delay = backoff;
+    let mut attempt = 0;
+    loop {
+        match query() {
+            Ok(result) => return Ok(result),
+            Err(error) if attempt >= retries => return Err(error),
+            Err(_) => {
+                thread::sleep(delay);
+                delay = delay.checked_mul(2).unwrap_or(delay);
+                attempt += 1;
+            }
+        }
+    }
+}
+
+/// Containers observed while capturing the inventory, see
+/// [`GracePeriodInventory`](struct.GracePeriodInventory.html).
+///
+/// The history has to outlive the individual reconciles, e.g. by being created once when DFW
+/// starts.
+#[derive(Debug, Default)]
+pub struct ContainerHistory {
+    containers: Mutex<BTreeMap<String, ObservedContainer>>,
+}
+
+/// A container as it was last observed, keyed by its ID within the history.
+#[derive(Debug, Clone)]
+struct ObservedContainer {
+    container: Container,
+    /// Endpoints of the container, keyed by the ID of their network.
+    endpoints: BTreeMap<String, NetworkEndpoint>,
+    last_seen: Instant,
+}
+
+/// Inventory retaining the containers of another inventory for a grace period after they vanished.
+///
+/// A container that is briefly gone, e.g. while it restarts after a crash, would otherwise lose its
+/// rules until it is back, interrupting connectivity and causing the rules to churn. A container
+/// that vanished is reported with its last known endpoints until `grace_period` has elapsed since
+/// it was last observed, unless another container took over one of its names or addresses.
+///
+/// The containers are observed as of `now`, which has to be the same for the containers and the
+/// networks of a single capture. The networks only contain the endpoints of the containers retained
+/// by the last listing of the containers.
+pub struct GracePeriodInventory<'a> {
+    inventory: Box<dyn ContainerInventory + 'a>,
+    history: &'a ContainerHistory,
+    grace_period: Duration,
+    now: Instant,
+    retained: Mutex<BTreeSet<String>>,
+}
+
+impl<'a> GracePeriodInventory<'a> {
+    /// Create a new inventory retaining the containers of the given inventory that vanished less
+    /// than `grace_period` before `now`.
+    pub fn new(
+        inventory: Box<dyn ContainerInventory + 'a>,
+        history: &'a ContainerHistory,
+        grace_period: Duration,
+        now: Instant,
+    ) -> GracePeriodInventory<'a> {
+        GracePeriodInventory {
+            inventory,
+            history,
+            grace_period,
+            now,
+            retained: Mutex::new(BTreeSet::new()),
+        }
+    }
+}
+
+impl<'a> ContainerInventory for GracePeriodInventory<'a> {
+    fn containers(&self) -> Result<Vec<Container>> {
+        let mut containers = self.inventory.containers()?;
+        let mut history = self
+            .history
+            .containers
+            .lock()
+            .expect("history lock poisoned");
+        for container in &containers {
+            // The endpoints are recorded once the networks are listed
+            history.insert(
+                container.id.clone(),
+                ObservedContainer {
+                    container: container.clone(),
+                    endpoints: BTreeMap::new(),
+                    last_seen: self.now,
+                },
+            );
+        }
+        let now = self.now;
+        let grace_period = self.grace_period;
+        history.retain(|_, observed| {
+            now.checked_duration_since(observed.last_seen)
+                .is_none_or(|elapsed| elapsed < grace_period)
+        });
+
+        let names = containers
+            .iter()
+            .flat_map(|container| container.names.iter().cloned())
+            .collect::<BTreeSet<_>>();
+        let mut retained = self.retained.lock().expect("retained lock poisoned");
+        retained.clear();
+        for (id, observed) in history.iter() {
+            if observed.last_seen == self.now
+                || observed
+                    .container
+                    .names
+                    .iter()
+                    .any(|name| names.contains(name))
+            {
+                continue;
+            }
+            retained.insert(id.clone());
+            containers.push(observed.container.clone());
+        }
+
+        Ok(containers)
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        let mut networks = self.inventory.networks()?;
+        let mut history = self
+            .history
+            .containers
+            .lock()
+            .expect("history lock poisoned");
+        let retained = self.retained.lock().expect("retained lock poisoned");
+
+        // Record the current endpoints of the observed containers
+        for network in &networks {
+            for (id, endpoint) in &network.containers {
+                if let Some(observed) = history.get_mut(id) {
+                    if observed.last_seen == self.now {
+                        observed
+                            .endpoints
+                            .insert(network.id.clone(), endpoint.clone());
+                    }
+                }
+            }
+        }
+
+        // Add the last known endpoints of the retained containers, unless their addresses have been
+        // assigned to another container in the meantime
+        for network in &mut networks {
+            for id in retained.iter() {
+                let endpoint = match history
+                    .get(id)
+                    .and_then(|observed| observed.endpoints.get(&network.id))
+                {
+                    Some(endpoint) => endpoint,
+                    None => continue,
+                };
+                let addresses = endpoint_addresses(endpoint);
+                let reassigned = network.containers.values().any(|other| {
+                    endpoint_addresses(other)
+                        .iter()
+                        .any(|address| addresses.contains(address))
+                });
+                if !reassigned {
+                    network.containers.insert(id.clone(), endpoint.clone());
+                }
+            }
+        }
+
+        Ok(networks)
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        self.inventory.container_aliases()
+    }
+
+    fn image_ports(&self) -> Result<ImagePorts> {
+        self.inventory.image_ports()
+    }
+
+    fn current_ruleset(&self) -> Option<String> {
+        self.inventory.current_ruleset()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts> {
+        self.inventory.host_facts()
+    }
+
+    fn resolve_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        self.inventory.resolve_hostname(hostname)
+    }
+
+    fn cidr_file(&self, file: &str) -> Result<String> {
+        self.inventory.cidr_file(file)
+    }
+}
+
+/// Statuses of the container events that change the rules, i.e. of containers being created,
+/// started, stopped or removed.
+const TRIGGERING_EVENTS: &[&str] = &["create", "destroy", "start", "restart", "die", "stop"];
+
+/// A Docker event of a container, as far as DFW is concerned.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContainerEvent {
+    /// Status of the event, e.g. `start`.
+    pub status: Option<String>,
+    /// Time of the event in nanoseconds since the epoch, which orders the events.
+    pub time_nano: u64,
+}
+
+impl ContainerEvent {
+    /// Check if the event changes the rules, i.e. if it triggers processing.
+    pub fn triggers_processing(&self) -> bool {
+        self.status
+            .as_ref()
+            .is_some_and(|status| TRIGGERING_EVENTS.contains(&status.as_str()))
+    }
+}
+
+impl From<shiplift::rep::Event> for ContainerEvent {
+    fn from(event: shiplift::rep::Event) -> ContainerEvent {
+        ContainerEvent {
+            status: event.status,
+            time_nano: event.timeNano,
+        }
+    }
+}
+
+/// Tracks the last Docker event DFW has processed, such that the events it missed, e.g. while it
+/// was restarting after a crash, are replayed instead of leaving the rules stale until the next
+/// event.
+///
+/// The events are requested from Docker [`since`](#method.since) the last processed event, which
+/// includes the events of that second again. Events that were processed already are skipped by
+/// [`next_trigger`](#method.next_trigger). If the tracker is [loaded](#method.load) from a file, the
+/// last processed event is persisted in it across restarts.
+#[derive(Debug, Default)]
+pub struct EventTracker {
+    path: Option<PathBuf>,
+    last_time_nano: Option<u64>,
+}
+
+impl EventTracker {
+    /// Create a tracker persisting the last processed event in the given file, continuing after
+    /// the event recorded in it. If the file doesn't exist, no event has been processed yet.
+    pub fn load(path: &Path) -> Result<EventTracker> {
+        let last_time_nano = match fs::read_to_string(path) {
+            Ok(contents) => Some(
+                contents
+                    .trim()
+                    .parse::<u64>()
+                    .with_context(|_| format!("invalid event state file {}", path.display()))?,
+            ),
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => None,
+            Err(error) => bail!(
+                "failed to read event state file {}: {}",
+                path.display(),
+                error
+            ),
+        };
+
+        Ok(EventTracker {
+            path: Some(path.to_owned()),
+            last_time_nano,
+        })
+    }
+
+    /// Get the time in seconds since the epoch the events have to be requested since, or `None`
+    /// if no event has been processed yet.
+    pub fn since(&self) -> Option<u64> {
+        self.last_time_nano
+            .map(|last_time_nano| last_time_nano / 1_000_000_000)
+    }
+
+    /// Get the first event of the stream that was not processed yet and triggers processing,
+    /// recording it and all events before it as processed. Events that were processed already
+    /// are skipped.
+    ///
+    /// Returns `None` if the stream ends without such an event.
+    pub fn next_trigger<I>(&mut self, events: I) -> Option<ContainerEvent>
+    where
+        I: IntoIterator<Item = ContainerEvent>,
+    {
+        for event in events {
+            if self
+                .last_time_nano
+                .is_some_and(|last_time_nano| event.time_nano <= last_time_nano)
+            {
+                continue;
+            }
+            self.last_time_nano = Some(event.time_nano);
+            if event.triggers_processing() {
+                return Some(event);
+            }
+        }
+
+        None
+    }
+
+    /// Persist the last processed event in the file of the tracker, if it has one.
+    pub fn persist(&self) -> Result<()> {
+        let (path, last_time_nano) = match (&self.path, self.last_time_nano) {
+            (Some(path), Some(last_time_nano)) => (path, last_time_nano),
+            _ => return Ok(()),
+        };
+        // The file is replaced at once, such that a crash cannot leave a truncated file behind.
+        let mut temporary_path = path.clone().into_os_string();
+        temporary_path.push(".tmp");
+        fs::write(&temporary_path, format!("{}\n", last_time_nano))?;
+        fs::rename(&temporary_path, path)?;
+
+        Ok(())
+    }
+}
+
+/// Inventory serving a static mapping of containers to their networks and addresses, e.g. for
+/// generating rules without access to a Docker daemon.
+///
+/// The mapping is usually loaded from a TOML- or JSON-file, see
+/// [`StaticInventory::load`](struct.StaticInventory.html#method.load). Every network a container
+/// is attached to has to be defined.
+///
+/// # Example
+///
+/// ```toml
+/// [networks.frontend]
+/// id = "6d4c1b5e9f0a8c3d2e1f"
+/// subnets = ["172.18.0.0/16"]
+///
+/// [networks.bridge]
+/// id = "0a1b2c3d4e5f6a7b8c9d"
+/// options = { "com.docker.network.bridge.name" = "docker0" }
+///
+/// [containers.web]
+/// labels = { "com.example.role" = "web" }
+/// networks.frontend = { ipv4_address = "172.18.0.2/16", aliases = ["www"] }
+///
+/// [host_facts]
+/// hostname = "edge-1"
+/// env = { ROLE = "edge" }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StaticInventory {
+    /// Containers, keyed by their name.
+    #[serde(default)]
+    pub containers: BTreeMap<String, StaticContainer>,
+    /// Networks, keyed by their name.
+    #[serde(default)]
+    pub networks: BTreeMap<String, StaticNetwork>,
+    /// Facts about the host the rules are generated for.
+    #[serde(default)]
+    pub host_facts: HostFacts,
+}
+
+/// A container of a [`StaticInventory`](struct.StaticInventory.html).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StaticContainer {
+    /// ID of the container.
+    ///
+    /// Can be left blank, the name of the container will then be used as its ID.
+    pub id: Option<String>,
+    /// Labels of the container.
+    #[serde(default)]
+    pub labels: Map<String, String>,
+    /// Health of the container, can be left blank if the container doesn't define a healthcheck.
+    pub health: Option<HealthStatus>,
+    /// Endpoints of the container, keyed by the name of their network.
+    #[serde(default)]
+    pub networks: BTreeMap<String, StaticEndpoint>,
+    /// Ports the container publishes on the host.
+    #[serde(default)]
+    pub published_ports: Vec<PublishedPort>,
+    /// Ports the image of the container declares through `EXPOSE`, see
+    /// [`ImagePort`](struct.ImagePort.html).
+    #[serde(default)]
+    pub exposed_ports: Vec<ImagePort>,
+}
+
+/// A network of a [`StaticInventory`](struct.StaticInventory.html).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StaticNetwork {
+    /// ID of the network, the name of the bridge is derived from it.
+    pub id: String,
+    /// Driver of the network, e.g. `bridge`.
+    #[serde(default)]
+    pub driver: String,
+    /// Driver options of the network, e.g. `com.docker.network.bridge.name`.
+    #[serde(default)]
+    pub options: Map<String, String>,
+    /// Subnets of the network in CIDR notation, e.g. `172.18.0.0/16`.
+    #[serde(default)]
+    pub subnets: Vec<String>,
+}
+
+/// The endpoint of a container within a network of a
+/// [`StaticInventory`](struct.StaticInventory.html).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct StaticEndpoint {
+    /// IPv4 address of the container in CIDR notation, e.g. `172.18.0.2/16`.
+    ///
+    /// Can be left blank for the [host network](../types/constant.HOST_NETWORK.html), whose
+    /// containers have no address of their own.
+    #[serde(default)]
+    pub ipv4_address: String,
+    /// IPv6 address of the container in CIDR notation.
+    #[serde(default)]
+    pub ipv6_address: String,
+    /// Network-scoped aliases of the container.
+    #[serde(default)]
+    pub aliases: Vec<String>,
+}
+
+impl StaticInventory {
+    /// Load the inventory from a file. Files ending in `.json` are parsed as JSON, all other files
+    /// as TOML.
+    pub fn load(path: &str) -> Result<StaticInventory> {
+        let mut contents = String::new();
+        let mut file = BufReader::new(File::open(path)?);
+        file.read_to_string(&mut contents)?;
+
+        let inventory: StaticInventory = if path.ends_with(".json") {
+            serde_json::from_str(&contents)?
+        } else {
+            toml::from_str(&contents)?
+        };
+        inventory.validate()?;
+
+        Ok(inventory)
+    }
+
+    /// Verify that every network a container is attached to is defined.
+    pub fn validate(&self) -> Result<()> {
+        for (container_name, container) in &self.containers {
+            for network_name in container.networks.keys() {
+                if !self.networks.contains_key(network_name) {
+                    bail!(
+                        "network `{}` of container `{}` is not defined",
+                        network_name,
+                        container_name
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn container_id(&self, container_name: &str) -> String {
+        self.containers
+            .get(container_name)
+            .and_then(|container| container.id.clone())
+            .unwrap_or_else(|| container_name.to_owned())
+    }
+}
+
+impl ContainerInventory for StaticInventory {
+    fn containers(&self) -> Result<Vec<Container>> {
+        Ok(self
+            .containers
+            .iter()
+            .map(|(container_name, container)| Container {
+                id: self.container_id(container_name),
+                names: vec![container_name.to_owned()],
+                labels: container.labels.clone(),
+                health: container.health,
+                published_ports: container.published_ports.clone(),
+            })
+            .collect())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        self.validate()?;
+
+        Ok(self
+            .networks
+            .iter()
+            .map(|(network_name, network)| Network {
+                id: network.id.clone(),
+                name: network_name.to_owned(),
+                driver: network.driver.clone(),
+                options: network.options.clone(),
+                subnets: network.subnets.clone(),
+                containers: self
+                    .containers
+                    .iter()
+                    .filter_map(|(container_name, container)| {
+                        container.networks.get(network_name).map(|endpoint| {
+                            (
+                                self.container_id(container_name),
+                                NetworkEndpoint {
+                                    ipv4_address: endpoint.ipv4_address.clone(),
+                                    ipv6_address: endpoint.ipv6_address.clone(),
+                                    secondary_ipv4_addresses: Vec::new(),
+                                },
+                            )
+                        })
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        let mut aliases = ContainerAliases::default();
+        for (container_name, container) in &self.containers {
+            for (network_name, endpoint) in &container.networks {
+                for alias in &endpoint.aliases {
+                    aliases.insert(network_name, alias, container_name);
+                }
+            }
+        }
+
+        Ok(aliases)
+    }
+
+    fn image_ports(&self) -> Result<ImagePorts> {
+        let mut image_ports = ImagePorts::default();
+        for (container_name, container) in &self.containers {
+            for image_port in &container.exposed_ports {
+                image_ports.insert(container_name, image_port.clone());
+            }
+        }
+
+        Ok(image_ports)
+    }
+
+    fn host_facts(&self) -> Result<HostFacts> {
+        Ok(self.host_facts.clone())
+    }
+}
+
+/// Apply `fetch` to all items, with at most `concurrency` calls in flight at the same time.
+///
+/// The results are returned in the order of the items. If any call fails, the first error in the
+/// order of the items is returned.
+fn fetch_bounded<T, R, F>(items: Vec<T>, concurrency: usize, fetch: F) -> Result<Vec<R>>
+where
+    T: Send,
+    R: Send,
+    F: Fn(T) -> Result<R> + Sync,
+{
+    let workers = concurrency.max(1).min(items.len());
+    let queue = Mutex::new(items.into_iter().enumerate());
+
+    let mut results = thread::scope(|scope| {
+        let handles = (0..workers)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = Vec::new();
+                    loop {
+                        let next = queue.lock().expect("fetch queue poisoned").next();
+                        match next {
+                            Some((index, item)) => results.push((index, fetch(item))),
+                            None => return results,
+                        }
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("fetch worker panicked"))
+            .collect::<Vec<_>>()
+    });
+    results.sort_by_key(|(index, _)| *index);
+
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
+/// Go-template used with `docker inspect` to list the network-scoped aliases of containers. Every
+/// container is printed on its own line, in the form `/<name> <network>=<alias>,<alias> ...`.
+const CONTAINER_ALIASES_FORMAT: &str = "{{.Name}}{{range $network, $settings := \
+                                        .NetworkSettings.Networks}} \
+                                        {{$network}}={{join $settings.Aliases \",\"}}{{end}}";
+
+/// Network-scoped aliases of containers, mapping network names to aliases to container names.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ContainerAliases(Map<String, Map<String, Vec<String>>>);
+
+impl ContainerAliases {
+    fn from_inspect_output(output: &str) -> ContainerAliases {
+        let mut aliases = ContainerAliases::default();
+        for line in output.lines() {
+            let mut parts = line.split_whitespace();
+            let container_name = match parts.next() {
+                Some(container_name) => container_name.trim_start_matches('/'),
+                None => continue,
+            };
+            for network in parts {
+                let mut network = network.splitn(2, '=');
+                let (network_name, network_aliases) = match (network.next(), network.next()) {
+                    (Some(network_name), Some(network_aliases)) => (network_name, network_aliases),
+                    _ => continue,
+                };
+                for alias in network_aliases.split(',').filter(|alias| !alias.is_empty()) {
+                    aliases.insert(network_name, alias, container_name);
+                }
+            }
+        }
+
+        aliases
+    }
+
+    /// Register the alias for the container on the given network.
+    pub fn insert(&mut self, network_name: &str, alias: &str, container_name: &str) {
+        let container_names = self
+            .0
+            .entry(network_name.to_owned())
+            .or_default()
+            .entry(alias.to_owned())
+            .or_default();
+        container_names.push(container_name.to_owned());
+        container_names.sort();
+    }
+
+    /// Get the names of the containers carrying the alias on the given network, ordered by name.
+    pub fn resolve(&self, network_name: &str, alias: &str) -> Vec<String> {
+        self.0
+            .get(network_name)
+            .and_then(|aliases| aliases.get(alias))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// Go-template used with `docker inspect` to list the ports the images of containers declare
+/// through `EXPOSE`. Every container is printed on its own line, in the form
+/// `/<name> <port>/<protocol> ...`.
+const IMAGE_PORTS_FORMAT: &str = "{{.Name}}{{range $port, $_ := .Config.ExposedPorts}} \
+                                  {{$port}}{{end}}";
+
+/// Ports the images of containers declare through `EXPOSE`, mapping container names to their
+/// exposed ports.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ImagePorts(Map<String, Vec<ImagePort>>);
+
+impl ImagePorts {
+    fn from_inspect_output(output: &str) -> ImagePorts {
+        let mut image_ports = ImagePorts::default();
+        for line in output.lines() {
+            let mut parts = line.split_whitespace();
+            let container_name = match parts.next() {
+                Some(container_name) => container_name.trim_start_matches('/'),
+                None => continue,
+            };
+            for image_port in parts.filter_map(|image_port| image_port.parse().ok()) {
+                image_ports.insert(container_name, image_port);
+            }
+        }
+
+        image_ports
+    }
+
+    /// Register the exposed port for the container.
+    pub fn insert(&mut self, container_name: &str, image_port: ImagePort) {
+        let image_ports = self.0.entry(container_name.to_owned()).or_default();
+        if !image_ports.contains(&image_port) {
+            image_ports.push(image_port);
+            image_ports.sort();
+        }
+    }
+
+    /// Get the exposed ports of the container, ordered by port and protocol.
+    pub fn resolve(&self, container_name: &str) -> Vec<ImagePort> {
+        self.0.get(container_name).cloned().unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn fetch_bounded_limits_concurrency() {
+        let in_flight = AtomicUsize::new(0);
+        let max_in_flight = AtomicUsize::new(0);
+
+        let results = fetch_bounded((0..32).collect(), 4, |item: u32| {
+            let current = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            max_in_flight.fetch_max(current, Ordering::SeqCst);
+            thread::sleep(Duration::from_millis(5));
+            in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(item * 2)
+        })
+        .unwrap();
+
+        assert_eq!((0..32).map(|item| item * 2).collect::<Vec<_>>(), results);
+        assert!(max_in_flight.load(Ordering::SeqCst) <= 4);
+    }
+
+    #[test]
+    fn fetch_bounded_error() {
+        let error = fetch_bounded(vec![1, 2, 3, 4], 2, |item: u32| {
+            if item % 2 == 0 {
+                bail!("failed to fetch item {}", item);
+            }
+            Ok(item)
+        })
+        .unwrap_err();
+
+        assert_eq!("failed to fetch item 2", error.to_string());
+    }
+
+    #[test]
+    fn fetch_bounded_empty() {
+        let results = fetch_bounded(Vec::<u32>::new(), 4, Ok).unwrap();
+
+        assert!(results.is_empty());
+    }
+
+    #[test]
+    fn network_endpoint_merge() {
+        let mut endpoint = NetworkEndpoint {
+            ipv4_address: "172.18.0.2/16".to_owned(),
+            ..Default::default()
+        };
+        for address in &[
+            "172.18.0.9/16",
+            "172.18.0.2/16",
+            "172.18.0.5/16",
+            "172.18.0.9/16",
+        ] {
+            endpoint.merge(NetworkEndpoint {
+                ipv4_address: (*address).to_owned(),
+                ipv6_address: "fd00::2/64".to_owned(),
+                ..Default::default()
+            });
+        }
+
+        assert_eq!("172.18.0.2/16", endpoint.ipv4_address);
+        assert_eq!("fd00::2/64", endpoint.ipv6_address);
+        assert_eq!(
+            vec!["172.18.0.5/16", "172.18.0.9/16"],
+            endpoint.secondary_ipv4_addresses
+        );
+        assert_eq!(
+            vec!["172.18.0.2", "172.18.0.5", "172.18.0.9"],
+            endpoint.ipv4_addresses()
+        );
+    }
+
+    #[test]
+    fn container_aliases_resolve() {
+        let output = "/dfw_a_1 dfw_default=a,0123456789ab other=\n\
+                      /dfw_b_1 dfw_default=b,db,fedcba987654 other=db,b\n\
+                      /dfw_c_1 bridge=\n";
+        let aliases = ContainerAliases::from_inspect_output(output);
+
+        assert_eq!(vec!["dfw_b_1"], aliases.resolve("dfw_default", "db"));
+        assert_eq!(vec!["dfw_a_1"], aliases.resolve("dfw_default", "a"));
+        assert_eq!(vec!["dfw_b_1"], aliases.resolve("other", "db"));
+        assert!(aliases.resolve("other", "a").is_empty());
+        assert!(aliases.resolve("bridge", "dfw_c_1").is_empty());
+        assert!(aliases.resolve("unknown", "db").is_empty());
+    }
+
+    #[test]
+    fn image_ports_resolve() {
+        let output = "/dfw_a_1 80/tcp 53/udp 53/tcp\n/dfw_b_1\n/dfw_c_1 invalid 8080/tcp\n";
+        let image_ports = ImagePorts::from_inspect_output(output);
+
+        let image_port = |port, protocol: &str| ImagePort {
+            port,
+            protocol: protocol.to_owned(),
+        };
+        assert_eq!(
+            vec![
+                image_port(53, "tcp"),
+                image_port(53, "udp"),
+                image_port(80, "tcp")
+            ],
+            image_ports.resolve("dfw_a_1")
+        );
+        assert!(image_ports.resolve("dfw_b_1").is_empty());
+        assert_eq!(
+            vec![image_port(8080, "tcp")],
+            image_ports.resolve("dfw_c_1")
+        );
+        assert!(image_ports.resolve("unknown").is_empty());
+    }
+
+    #[test]
+    fn container_aliases_resolve_ambiguous() {
+        let output = "/dfw_b_2 dfw_default=db\n/dfw_b_1 dfw_default=db\n";
+        let aliases = ContainerAliases::from_inspect_output(output);
+
+        assert_eq!(
+            vec!["dfw_b_1", "dfw_b_2"],
+            aliases.resolve("dfw_default", "db")
+        );
+    }
+}
@@ -12,6 +12,14 @@
 //!
 //! [github-readme]: https://github.com/pitkley/dfw#readme
 //!
+//! ## Features
+//!
+//! The processing and application of the rules, including the Docker inventory, is part of the
+//! `backend` feature, which is enabled by default. Tools that only need to parse and validate
+//! configurations can depend on DFW with `default-features = false`, leaving them with the
+//! [`types`](types/index.html), [`util`](util/index.html) and [`schema`](schema/index.html)
+//! modules.
+//!
 //! ## License
 //!
 //! DFW is licensed under either of
@@ -27,11 +35,26 @@
 
 // declare modules
 pub mod errors;
+#[cfg(feature = "backend")]
+pub mod inventory;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod nftables;
+#[cfg(feature = "backend")]
+pub mod preflight;
+#[cfg(feature = "backend")]
 pub mod process;
+#[cfg(feature = "backend")]
 pub mod rule;
+pub mod schema;
+#[cfg(feature = "backend")]
+pub mod simulate;
+#[cfg(feature = "swarm")]
+pub mod swarm;
 pub mod types;
 pub mod util;
+pub mod validate;
 
 // re-export process types
+#[cfg(feature = "backend")]
 pub use process::*;
@@ -0,0 +1,267 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module collects statistics about the reconciliation runs and the rules managed by DFW and
+//! serves them in the Prometheus text exposition format.
+//!
+//! The endpoint is configured through
+//! [`Runtime::metrics_address`](../types/struct.Runtime.html#structfield.metrics_address) and is
+//! only available if DFW was built with the `metrics` feature.
+
+use crate::errors::*;
+use crate::process::{parse_listed_rules, ListedRule, RuleId};
+use crate::types::Section;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+/// Time a client may take to send its request or receive the response.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Packet- and byte-counter of a single rule within one of the DFW tables.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleCounter {
+    /// Family of the table the rule is part of.
+    pub family: String,
+    /// Chain the rule is part of.
+    pub chain: String,
+    /// Section that created the rule.
+    pub section: Section,
+    /// Identity of the rule, see [`RuleId`](../process/struct.RuleId.html).
+    pub rule_id: RuleId,
+    /// Number of packets that matched the rule.
+    pub packets: u64,
+    /// Number of bytes that matched the rule.
+    pub bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    reconciles: u64,
+    reconcile_errors: u64,
+    last_reconcile_duration: Option<Duration>,
+}
+
+type ListRuleset = Box<dyn Fn() -> Result<String> + Send + Sync>;
+
+/// Statistics of the reconciliation runs and the rules managed by DFW.
+///
+/// The statistics can be shared between the processing loop recording them and the listener
+/// serving them, see [`serve`](fn.serve.html).
+#[derive(Default)]
+pub struct Metrics {
+    state: Mutex<State>,
+    list_ruleset: Option<ListRuleset>,
+}
+
+impl Metrics {
+    /// Create a new, empty instance of `Metrics` without rule statistics.
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Create a new, empty instance of `Metrics`, collecting the rule statistics from the ruleset
+    /// returned by `list_ruleset` whenever the metrics are rendered. The ruleset has to be listed
+    /// including the rule handles, i.e. as by `nft --handle list ruleset`.
+    pub fn with_ruleset<F>(list_ruleset: F) -> Metrics
+    where
+        F: Fn() -> Result<String> + Send + Sync + 'static,
+    {
+        Metrics {
+            list_ruleset: Some(Box::new(list_ruleset)),
+            ..Default::default()
+        }
+    }
+
+    /// Record a finished reconciliation run.
+    pub fn record_reconcile(&self, duration: Duration, success: bool) {
+        let mut state = self.state.lock().expect("metrics lock poisoned");
+        state.reconciles += 1;
+        if !success {
+            state.reconcile_errors += 1;
+        }
+        state.last_reconcile_duration = Some(duration);
+    }
+
+    /// Render the metrics in the Prometheus text exposition format.
+    ///
+    /// The rule statistics are collected from the current ruleset, rendering fails if it cannot be
+    /// listed.
+    pub fn render(&self) -> Result<String> {
+        let listed_rules = match self.list_ruleset {
+            Some(ref list_ruleset) => parse_listed_rules(&list_ruleset()?),
+            None => Vec::new(),
+        };
+        let mut rules = BTreeMap::new();
+        for section in listed_rules
+            .iter()
+            .filter_map(|listed_rule| listed_rule.section)
+        {
+            *rules.entry(section.to_string()).or_insert(0) += 1;
+        }
+        let counters = listed_rules
+            .into_iter()
+            .filter_map(rule_counter)
+            .collect::<Vec<_>>();
+
+        let state = self.state.lock().expect("metrics lock poisoned");
+        let mut output = String::new();
+
+        // Writing into a `String` cannot fail.
+        let _ = writeln!(
+            output,
+            "# HELP dfw_reconciles_total Number of reconciliation runs.\n\
+             # TYPE dfw_reconciles_total counter\n\
+             dfw_reconciles_total {}",
+            state.reconciles
+        );
+        let _ = writeln!(
+            output,
+            "# HELP dfw_reconcile_errors_total Number of failed reconciliation runs.\n\
+             # TYPE dfw_reconcile_errors_total counter\n\
+             dfw_reconcile_errors_total {}",
+            state.reconcile_errors
+        );
+        if let Some(duration) = state.last_reconcile_duration {
+            let _ = writeln!(
+                output,
+                "# HELP dfw_reconcile_duration_seconds Duration of the last reconciliation run.\n\
+                 # TYPE dfw_reconcile_duration_seconds gauge\n\
+                 dfw_reconcile_duration_seconds {}",
+                duration.as_secs_f64()
+            );
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP dfw_rules Number of rules managed by DFW per section.\n\
+             # TYPE dfw_rules gauge"
+        );
+        for (section, rules) in &rules {
+            let _ = writeln!(output, "dfw_rules{{section=\"{}\"}} {}", section, rules);
+        }
+
+        let _ = writeln!(
+            output,
+            "# HELP dfw_rule_packets_total Number of packets matched by a rule.\n\
+             # TYPE dfw_rule_packets_total counter"
+        );
+        for counter in &counters {
+            let _ = writeln!(
+                output,
+                "dfw_rule_packets_total{{{}}} {}",
+                counter_labels(counter),
+                counter.packets
+            );
+        }
+        let _ = writeln!(
+            output,
+            "# HELP dfw_rule_bytes_total Number of bytes matched by a rule.\n\
+             # TYPE dfw_rule_bytes_total counter"
+        );
+        for counter in &counters {
+            let _ = writeln!(
+                output,
+                "dfw_rule_bytes_total{{{}}} {}",
+                counter_labels(counter),
+                counter.bytes
+            );
+        }
+
+        Ok(output)
+    }
+}
+
+fn counter_labels(counter: &RuleCounter) -> String {
+    format!(
+        "section=\"{}\",rule=\"{}\",family=\"{}\",chain=\"{}\"",
+        counter.section, counter.rule_id, counter.family, counter.chain
+    )
+}
+
+/// Parse the rules of the DFW tables carrying a `counter` statement from the output of
+/// `nft --handle list ruleset`.
+///
+/// Rules without a counter, a section or an identity are skipped.
+pub fn parse_rule_counters(ruleset: &str) -> Vec<RuleCounter> {
+    parse_listed_rules(ruleset)
+        .into_iter()
+        .filter_map(rule_counter)
+        .collect()
+}
+
+fn rule_counter(listed_rule: ListedRule) -> Option<RuleCounter> {
+    let (packets, bytes) = listed_rule.counter?;
+    Some(RuleCounter {
+        family: listed_rule.family,
+        chain: listed_rule.chain,
+        section: listed_rule.section?,
+        rule_id: listed_rule.rule_id?,
+        packets,
+        bytes,
+    })
+}
+
+/// Serve the metrics on the given address.
+///
+/// The listener is bound before this function returns, every connection is then answered on a
+/// separate thread, such that a slow client cannot hold up the others. The address the listener was
+/// bound to is returned, which allows binding to port 0.
+pub fn serve(address: SocketAddr, metrics: Arc<Metrics>) -> Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let local_address = listener.local_addr()?;
+    thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let metrics = Arc::clone(&metrics);
+            // A misbehaving client must not stop the listener, errors are thus ignored.
+            thread::spawn(move || respond(stream, &metrics));
+        }
+    });
+
+    Ok(local_address)
+}
+
+fn respond(stream: TcpStream, metrics: &Metrics) -> Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    stream.set_write_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    // Consume the headers of the request, the body (if any) is ignored.
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 0 && header.trim() != "" {
+        header.clear();
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => match metrics.render() {
+            Ok(body) => ("200 OK", body),
+            Err(e) => ("500 Internal Server Error", format!("{}\n", e)),
+        },
+        _ => ("404 Not Found", "not found\n".to_owned()),
+    };
+
+    let mut stream = stream;
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        CONTENT_TYPE,
+        body.len(),
+        body
+    )?;
+    stream.flush()?;
+
+    Ok(())
+}
@@ -7,10 +7,25 @@
 // except according to those terms.
 
 //! This module abstracts various nftables concepts into native Rust types.
+//!
+//! Applying rules through the `nft` binary, see [`NftBinary`](struct.NftBinary.html), is only
+//! available if DFW was built with the `backend` feature.
 
-use serde::Deserialize;
+use crate::errors;
+#[cfg(feature = "backend")]
+use failure::bail;
+use serde::{Deserialize, Serialize};
 use slog;
-use strum_macros::{Display, EnumString};
+use std::convert::TryFrom;
+use std::fmt;
+#[cfg(feature = "backend")]
+use std::io::Write;
+#[cfg(feature = "backend")]
+use std::process::Command;
+use std::str::FromStr;
+use strum_macros::Display;
+#[cfg(feature = "backend")]
+use tempfile;
 
 /// Represenation of nftables table-families.
 #[derive(Debug, Clone, Copy, Display)]
@@ -105,24 +120,35 @@ pub enum Hook {
 ///
 /// Parts of the documentation have been taken from
 /// <https://wiki.nftables.org/wiki-nftables/index.php/Configuring_chains>.
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, Default)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "snake_case")]
 pub enum ChainPolicy {
     /// The accept verdict means that the packet will keep traversing the network stack.
-    #[strum(to_string = "accept", serialize = "accept", serialize = "ACCEPT")]
+    #[strum(to_string = "accept")]
     #[serde(alias = "ACCEPT")]
+    #[default]
     Accept,
     /// The drop verdict means that the packet is discarded if the packet reaches the end of the
     /// base chain.
-    #[strum(to_string = "drop", serialize = "drop", serialize = "DROP")]
+    #[strum(to_string = "drop")]
     #[serde(alias = "DROP")]
     Drop,
 }
 
-impl Default for ChainPolicy {
-    fn default() -> ChainPolicy {
-        ChainPolicy::Accept
+impl FromStr for ChainPolicy {
+    type Err = String;
+
+    /// Parse a chain policy, accepting the same spellings as the configuration.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" | "ACCEPT" => Ok(ChainPolicy::Accept),
+            "drop" | "DROP" => Ok(ChainPolicy::Drop),
+            _ => Err(format!(
+                "invalid chain policy '{}', expected one of: accept, drop",
+                s
+            )),
+        }
     }
 }
 
@@ -131,9 +157,9 @@ impl slog::Value for ChainPolicy {
         &self,
         record: &slog::Record,
         key: slog::Key,
-        serializer: &mut slog::Serializer,
+        serializer: &mut dyn slog::Serializer,
     ) -> slog::Result {
-        self.to_string().serialize(record, key, serializer)
+        slog::Value::serialize(&self.to_string(), record, key, serializer)
     }
 }
 
@@ -143,29 +169,79 @@ impl slog::Value for ChainPolicy {
 ///
 /// Parts of the documentation have been taken from
 /// <https://wiki.nftables.org/wiki-nftables/index.php/Configuring_chains>.
-#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display, Default)]
 #[serde(rename_all = "lowercase")]
 #[strum(serialize_all = "snake_case")]
 pub enum RuleVerdict {
     /// The accept verdict means that the packet will keep traversing the network stack.
     #[serde(alias = "ACCEPT")]
-    #[strum(to_string = "accept", serialize = "accept", serialize = "ACCEPT")]
+    #[strum(to_string = "accept")]
+    #[default]
     Accept,
     /// The drop verdict means that the packet is discarded if the packet reaches the end of the
     /// base chain.
     #[serde(alias = "DROP")]
-    #[strum(to_string = "drop", serialize = "drop", serialize = "DROP")]
+    #[strum(to_string = "drop")]
     Drop,
     /// The reject verdict means that the packet is responded to with an ICMP message stating that
     /// it was rejected.
     #[serde(alias = "REJECT")]
-    #[strum(to_string = "reject", serialize = "reject", serialize = "REJECT")]
+    #[strum(to_string = "reject")]
     Reject,
+    /// The log pseudo-verdict logs the packet without deciding on it, i.e. the packet continues to
+    /// be evaluated by the subsequent rules. It is only valid as the verdict of a rule applying to
+    /// all connections, not as a policy.
+    #[serde(alias = "LOG")]
+    #[strum(to_string = "log")]
+    Log,
+}
+
+impl RuleVerdict {
+    /// Check if the verdict decides on the packet, i.e. if it isn't the
+    /// [`Log`](#variant.Log) pseudo-verdict.
+    pub fn is_terminal(self) -> bool {
+        self != RuleVerdict::Log
+    }
+}
+
+/// Level of the log messages of a `log` statement.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Display)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "snake_case")]
+pub enum LogLevel {
+    /// System is unusable.
+    Emerg,
+    /// Action must be taken immediately.
+    Alert,
+    /// Critical conditions.
+    Crit,
+    /// Error conditions.
+    Err,
+    /// Warning conditions.
+    Warn,
+    /// Normal, but significant, condition.
+    Notice,
+    /// Informational message.
+    Info,
+    /// Debug-level message.
+    Debug,
 }
 
-impl Default for RuleVerdict {
-    fn default() -> RuleVerdict {
-        RuleVerdict::Accept
+impl FromStr for RuleVerdict {
+    type Err = String;
+
+    /// Parse a rule verdict, accepting the same spellings as the configuration.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "accept" | "ACCEPT" => Ok(RuleVerdict::Accept),
+            "drop" | "DROP" => Ok(RuleVerdict::Drop),
+            "reject" | "REJECT" => Ok(RuleVerdict::Reject),
+            "log" | "LOG" => Ok(RuleVerdict::Log),
+            _ => Err(format!(
+                "invalid verdict '{}', expected one of: accept, drop, reject, log",
+                s
+            )),
+        }
     }
 }
 
@@ -174,9 +250,203 @@ impl slog::Value for RuleVerdict {
         &self,
         record: &slog::Record,
         key: slog::Key,
-        serializer: &mut slog::Serializer,
+        serializer: &mut dyn slog::Serializer,
     ) -> slog::Result {
-        self.to_string().serialize(record, key, serializer)
+        slog::Value::serialize(&self.to_string(), record, key, serializer)
+    }
+}
+
+/// Version of the `nft` binary, used to determine which constructs the host supports.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(try_from = "String")]
+pub struct NftVersion {
+    /// Major version
+    pub major: u32,
+    /// Minor version
+    pub minor: u32,
+    /// Patch version
+    pub patch: u32,
+}
+
+impl NftVersion {
+    /// First version supporting the negation of anonymous sets, e.g. `ip daddr != { ... }`.
+    pub const NEGATED_SETS: NftVersion = NftVersion::new(0, 9, 1);
+
+    /// First version supporting concatenations in NAT statements, e.g.
+    /// `dnat ip addr . port to tcp dport map { ... }`.
+    pub const NAT_CONCATENATIONS: NftVersion = NftVersion::new(0, 9, 4);
+
+    /// Create a new version.
+    pub const fn new(major: u32, minor: u32, patch: u32) -> NftVersion {
+        NftVersion {
+            major,
+            minor,
+            patch,
+        }
+    }
+}
+
+impl FromStr for NftVersion {
+    type Err = String;
+
+    /// Convert a version string into a [`NftVersion`](struct.NftVersion.html).
+    ///
+    /// Both the plain version (`0.9.3`) and the output of `nft --version`
+    /// (`nftables v0.9.3 (Topsy)`) are accepted, a missing patch version is assumed to be `0`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let version = s
+            .split_whitespace()
+            .map(|token| token.trim_start_matches('v'))
+            .find(|token| token.starts_with(|c: char| c.is_ascii_digit()))
+            .ok_or_else(|| format!("nft version has invalid format '{}'", s))?;
+        let components = version
+            .split('.')
+            .map(u32::from_str)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| format!("nft version has invalid format '{}'", s))?;
+        match components.as_slice() {
+            [major, minor] => Ok(NftVersion::new(*major, *minor, 0)),
+            [major, minor, patch] => Ok(NftVersion::new(*major, *minor, *patch)),
+            _ => Err(format!("nft version has invalid format '{}'", s)),
+        }
+    }
+}
+
+impl TryFrom<String> for NftVersion {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for NftVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Name of the `nft` binary, which is looked up through the `PATH` unless a path is configured.
+#[cfg(feature = "backend")]
+const NFT_BINARY: &str = "nft";
+
+/// Construct the process invoking `nft`, optionally within the given network namespace.
+///
+/// The namespace is entered using `ip netns exec`, i.e. it has to be a named namespace as listed
+/// by `ip netns list`. If `nft_path` is given, that binary is invoked instead of looking up `nft`
+/// through the `PATH`.
+#[cfg(feature = "backend")]
+pub fn nft_command(netns: Option<&str>, nft_path: Option<&str>) -> Command {
+    let nft = nft_path.unwrap_or(NFT_BINARY);
+    match netns {
+        Some(netns) => {
+            let mut command = Command::new("ip");
+            command.args(["netns", "exec", netns, nft]);
+            command
+        }
+        None => Command::new(nft),
+    }
+}
+
+const SCRIPT_SHEBANG: &str = "#!/usr/sbin/nft -f";
+const SCRIPT_HEADER: &str = "# Generated by DFW, changes will be overwritten.";
+
+/// Assemble the commands into a script in `nft -f` syntax.
+///
+/// `nft -f` applies a script within a single transaction: if any of its commands fails, none of
+/// them are applied.
+pub fn script(commands: &[String]) -> String {
+    let mut script = format!("{}\n{}\n", SCRIPT_SHEBANG, SCRIPT_HEADER);
+    for command in commands {
+        script.push_str(command);
+        script.push('\n');
+    }
+    script
+}
+
+/// Applies scripts in `nft -f` syntax, see [`script`](fn.script.html).
+pub trait ScriptRunner {
+    /// Apply the script within a single transaction.
+    fn run_script(&self, script: &str) -> errors::Result<()>;
+}
+
+/// Applies scripts through a single invocation of the `nft` binary, optionally within the given
+/// network namespace, see [`nft_command`](fn.nft_command.html).
+#[cfg(feature = "backend")]
+#[derive(Debug, Clone, Default)]
+pub struct NftBinary {
+    /// Network namespace to apply the scripts in, the namespace of DFW if `None`.
+    pub netns: Option<String>,
+    /// Path of the `nft` binary, `nft` is looked up through the `PATH` if `None`.
+    pub nft_path: Option<String>,
+}
+
+#[cfg(feature = "backend")]
+impl ScriptRunner for NftBinary {
+    fn run_script(&self, script: &str) -> errors::Result<()> {
+        // `nft` reads the script from a file, passing it through stdin is not supported by all
+        // versions.
+        let mut script_file = tempfile::Builder::new().tempfile()?;
+        script_file.write_all(script.as_bytes())?;
+        script_file.flush()?;
+
+        let output = nft_command(self.netns.as_deref(), self.nft_path.as_deref())
+            .arg("-f")
+            .arg(script_file.path())
+            .output()?;
+        if !output.status.success() {
+            return Err(errors::DFWError::NFTablesError {
+                stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
+                stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Network namespace that exists for the lifetime of the value, it is deleted when dropped.
+///
+/// This allows applying rules without affecting the network stack of the host, e.g. to verify that
+/// `nft` accepts them. Creating and deleting network namespaces requires root privileges.
+#[cfg(feature = "backend")]
+#[derive(Debug)]
+pub struct TemporaryNetns {
+    name: String,
+}
+
+#[cfg(feature = "backend")]
+impl TemporaryNetns {
+    /// Create a new network namespace, named after the given prefix and the current process-ID.
+    pub fn create(prefix: &str) -> errors::Result<TemporaryNetns> {
+        let name = format!("{}-{}", prefix, std::process::id());
+        let output = Command::new("ip").args(["netns", "add", &name]).output()?;
+        if !output.status.success() {
+            bail!(
+                "failed to create network namespace {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        Ok(TemporaryNetns { name })
+    }
+
+    /// Get the name of the network namespace.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+#[cfg(feature = "backend")]
+impl Drop for TemporaryNetns {
+    fn drop(&mut self) {
+        // There is nothing left to do if the namespace cannot be deleted, the error is thus
+        // ignored.
+        let _ = Command::new("ip")
+            .args(["netns", "delete", &self.name])
+            .output();
     }
 }
 
@@ -223,6 +493,82 @@ pub fn set_chain_policy(family: Family, table: &str, chain: &str, policy: ChainP
     )
 }
 
+/// Construct nft command for adding a named set of IPv4 prefixes. Overlapping prefixes are merged.
+pub fn add_ipv4_prefix_set(family: Family, table: &str, set: &str) -> String {
+    format!(
+        "add set {} {} {} {{ type ipv4_addr ; flags interval ; auto-merge ; }}",
+        family, table, set
+    )
+}
+
+/// Construct nft command for adding a named set of IPv6 prefixes. Overlapping prefixes are merged.
+pub fn add_ipv6_prefix_set(family: Family, table: &str, set: &str) -> String {
+    format!(
+        "add set {} {} {} {{ type ipv6_addr ; flags interval ; auto-merge ; }}",
+        family, table, set
+    )
+}
+
+/// Construct nft command for removing all elements from a named set.
+pub fn flush_set(family: Family, table: &str, set: &str) -> String {
+    format!("flush set {} {} {}", family, table, set)
+}
+
+/// Construct nft command for adding elements to a named set.
+pub fn add_elements(family: Family, table: &str, set: &str, elements: &[String]) -> String {
+    format!(
+        "add element {} {} {} {{ {} }}",
+        family,
+        table,
+        set,
+        elements.join(", ")
+    )
+}
+
+/// Construct nft command for adding a named limit object.
+pub fn add_limit(family: Family, table: &str, limit: &str, rate: &str) -> String {
+    format!(
+        "add limit {} {} {} {{ rate {} ; }}",
+        family, table, limit, rate
+    )
+}
+
+/// Construct nft command for adding a named quota object. The quota either matches once it is
+/// exceeded (`over`) or until it is exceeded.
+pub fn add_quota(family: Family, table: &str, quota: &str, bytes: &str, over: bool) -> String {
+    format!(
+        "add quota {} {} {} {{ {} {} ; }}",
+        family,
+        table,
+        quota,
+        if over { "over" } else { "until" },
+        bytes
+    )
+}
+
+/// Construct nft command for adding a named conntrack timeout object, which sets the timeouts in
+/// seconds per conntrack state of IPv4 connections of the given protocol.
+pub fn add_ct_timeout(
+    family: Family,
+    table: &str,
+    ct_timeout: &str,
+    protocol: &str,
+    timeouts: &[(String, u64)],
+) -> String {
+    format!(
+        "add ct timeout {} {} {} {{ protocol {} ; l3proto ip ; policy = {{ {} }} ; }}",
+        family,
+        table,
+        ct_timeout,
+        protocol,
+        timeouts
+            .iter()
+            .map(|(state, seconds)| format!("{}: {}", state, seconds))
+            .collect::<Vec<_>>()
+            .join(", ")
+    )
+}
+
 /// Construct nft command for adding a rule to a chain.
 pub fn add_rule(family: Family, table: &str, chain: &str, rule: &str) -> String {
     format!("add rule {} {} {} {}", family, table, chain, rule)
@@ -252,15 +598,61 @@ pub fn insert_rule(
 
 #[cfg(test)]
 mod test {
-    use super::{ChainPolicy, RuleVerdict};
+    use super::{ChainPolicy, NftVersion, RuleVerdict};
     use std::str::FromStr;
 
+    #[test]
+    fn nftversion_fromstr() {
+        assert_eq!(
+            NftVersion::new(0, 9, 3),
+            FromStr::from_str("nftables v0.9.3 (Topsy)").unwrap()
+        );
+        assert_eq!(NftVersion::new(1, 0, 0), FromStr::from_str("1.0").unwrap());
+        assert!(NftVersion::from_str("nftables").is_err());
+        assert!(NftVersion::new(0, 9, 0) < NftVersion::NEGATED_SETS);
+    }
+
+    #[test]
+    #[cfg(feature = "backend")]
+    fn nft_command_netns() {
+        let command = super::nft_command(None, None);
+        assert_eq!("nft", command.get_program());
+        assert_eq!(0, command.get_args().count());
+
+        let command = super::nft_command(Some("tenant-a"), None);
+        assert_eq!("ip", command.get_program());
+        assert_eq!(
+            vec!["netns", "exec", "tenant-a", "nft"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "backend")]
+    fn nft_command_path() {
+        let command = super::nft_command(None, Some("/opt/nftables/sbin/nft"));
+        assert_eq!("/opt/nftables/sbin/nft", command.get_program());
+        assert_eq!(0, command.get_args().count());
+
+        let command = super::nft_command(Some("tenant-a"), Some("/opt/nftables/sbin/nft"));
+        assert_eq!("ip", command.get_program());
+        assert_eq!(
+            vec!["netns", "exec", "tenant-a", "/opt/nftables/sbin/nft"],
+            command.get_args().collect::<Vec<_>>()
+        );
+    }
+
     #[test]
     fn chainpolicy_fromstr() {
         assert_eq!(ChainPolicy::Accept, FromStr::from_str("accept").unwrap());
         assert_eq!(ChainPolicy::Accept, FromStr::from_str("ACCEPT").unwrap());
         assert_eq!(ChainPolicy::Drop, FromStr::from_str("drop").unwrap());
         assert_eq!(ChainPolicy::Drop, FromStr::from_str("DROP").unwrap());
+        assert_eq!(
+            Err("invalid chain policy 'reject', expected one of: accept, drop".to_owned()),
+            ChainPolicy::from_str("reject")
+        );
+        assert!(ChainPolicy::from_str("Accept").is_err());
     }
 
     #[test]
@@ -277,6 +669,13 @@ mod test {
         assert_eq!(RuleVerdict::Drop, FromStr::from_str("DROP").unwrap());
         assert_eq!(RuleVerdict::Reject, FromStr::from_str("reject").unwrap());
         assert_eq!(RuleVerdict::Reject, FromStr::from_str("REJECT").unwrap());
+        assert_eq!(RuleVerdict::Log, FromStr::from_str("log").unwrap());
+        assert_eq!(RuleVerdict::Log, FromStr::from_str("LOG").unwrap());
+        assert_eq!(
+            Err("invalid verdict 'deny', expected one of: accept, drop, reject, log".to_owned()),
+            RuleVerdict::from_str("deny")
+        );
+        assert!(RuleVerdict::from_str("").is_err());
     }
 
     #[test]
@@ -284,5 +683,6 @@ mod test {
         assert_eq!("accept", &RuleVerdict::Accept.to_string());
         assert_eq!("drop", &RuleVerdict::Drop.to_string());
         assert_eq!("reject", &RuleVerdict::Reject.to_string());
+        assert_eq!("log", &RuleVerdict::Log.to_string());
     }
 }
@@ -0,0 +1,262 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module checks the prerequisites of applying rules before any rule is applied, such that a
+//! missing prerequisite is reported as such instead of as a failure of `nft`.
+//!
+//! The checks query the host through [`Probes`](trait.Probes.html), which allows evaluating them
+//! against a host other than the current one, e.g. in tests.
+
+use crate::errors::*;
+use crate::nftables::{nft_command, NftBinary, NftVersion};
+use failure::{bail, format_err};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+/// Oldest version of the `nft` binary DFW generates rules for.
+pub const MINIMUM_NFT_VERSION: NftVersion = NftVersion::new(0, 9, 0);
+
+/// Kernel modules the rules generated by DFW depend on.
+pub const REQUIRED_KERNEL_MODULES: [&str; 3] = ["nf_tables", "nf_conntrack", "nf_nat"];
+
+/// Number of the `CAP_NET_ADMIN` capability, see `linux/capability.h`.
+const CAP_NET_ADMIN: u32 = 12;
+
+/// Status of the DFW process, carrying its effective capabilities.
+const PROC_SELF_STATUS: &str = "/proc/self/status";
+/// Release of the running kernel, naming its directory of modules.
+const PROC_OSRELEASE: &str = "/proc/sys/kernel/osrelease";
+/// Directory listing the loaded and built-in kernel modules.
+const SYS_MODULE: &str = "/sys/module";
+/// Directory containing the modules of every installed kernel.
+const LIB_MODULES: &str = "/lib/modules";
+
+/// Queries the host for the state the prerequisites depend on, see
+/// [`preflight`](fn.preflight.html).
+pub trait Probes {
+    /// Get the effective capabilities of the DFW process, as a bit set indexed by the number of
+    /// the capability.
+    fn effective_capabilities(&self) -> Result<u64>;
+
+    /// Get the version of the `nft` binary, failing if it cannot be invoked.
+    fn nft_version(&self) -> Result<NftVersion>;
+
+    /// Check whether the kernel module is loaded, built into the kernel or can be loaded on
+    /// demand.
+    fn kernel_module_available(&self, module: &str) -> Result<bool>;
+}
+
+/// Probes of the host DFW is currently running on.
+#[derive(Debug, Clone, Default)]
+pub struct HostProbes {
+    /// The `nft` binary the rules are applied through.
+    pub nft: NftBinary,
+}
+
+impl Probes for HostProbes {
+    fn effective_capabilities(&self) -> Result<u64> {
+        let status = fs::read_to_string(PROC_SELF_STATUS)?;
+        parse_effective_capabilities(&status)
+    }
+
+    fn nft_version(&self) -> Result<NftVersion> {
+        let output = nft_command(self.nft.netns.as_deref(), self.nft.nft_path.as_deref())
+            .arg("--version")
+            .output()
+            .map_err(|e| format_err!("failed to invoke nft: {}", e))?;
+        if !output.status.success() {
+            bail!(
+                "failed to invoke nft: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+
+        String::from_utf8_lossy(&output.stdout)
+            .parse()
+            .map_err(|problem| format_err!("{}", problem))
+    }
+
+    fn kernel_module_available(&self, module: &str) -> Result<bool> {
+        if Path::new(SYS_MODULE).join(module).exists() {
+            return Ok(true);
+        }
+
+        // Modules that are neither loaded nor built-in might still be loaded on demand.
+        let release = fs::read_to_string(PROC_OSRELEASE)?;
+        let directory = Path::new(LIB_MODULES).join(release.trim());
+        for index in &["modules.builtin", "modules.dep"] {
+            let contents = fs::read_to_string(directory.join(index)).unwrap_or_default();
+            if lists_module(&contents, module) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+/// Get the effective capabilities from the contents of `/proc/<pid>/status`.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::preflight::parse_effective_capabilities;
+/// let status = "Name:\tdfw\nCapInh:\t0000000000000000\nCapEff:\t0000000000001000\n";
+/// assert_eq!(1 << 12, parse_effective_capabilities(status).unwrap());
+/// ```
+pub fn parse_effective_capabilities(status: &str) -> Result<u64> {
+    let capabilities = status
+        .lines()
+        .find_map(|line| line.strip_prefix("CapEff:"))
+        .ok_or_else(|| format_err!("process status lacks the effective capabilities"))?;
+
+    u64::from_str_radix(capabilities.trim(), 16).map_err(|_| {
+        format_err!(
+            "effective capabilities '{}' are invalid",
+            capabilities.trim()
+        )
+    })
+}
+
+/// Check whether a module index like `modules.builtin` or `modules.dep` lists the module.
+///
+/// The index lists the paths of the modules, in which dashes and underscores are interchangeable.
+fn lists_module(index: &str, module: &str) -> bool {
+    let module = module.replace('-', "_");
+    index
+        .lines()
+        .filter_map(|line| line.split(':').next())
+        .filter_map(|path| path.rsplit('/').next())
+        .filter_map(|file| file.split(".ko").next())
+        .any(|name| name.replace('-', "_") == module)
+}
+
+/// A prerequisite of applying rules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Prerequisite {
+    /// The DFW process has the `CAP_NET_ADMIN` capability.
+    CapNetAdmin,
+    /// The `nft` binary can be invoked and is at least of version
+    /// [`MINIMUM_NFT_VERSION`](constant.MINIMUM_NFT_VERSION.html).
+    NftBinary,
+    /// The kernel module is available.
+    KernelModule(String),
+}
+
+impl fmt::Display for Prerequisite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Prerequisite::CapNetAdmin => write!(f, "capability CAP_NET_ADMIN"),
+            Prerequisite::NftBinary => write!(f, "nft binary"),
+            Prerequisite::KernelModule(module) => write!(f, "kernel module {}", module),
+        }
+    }
+}
+
+/// A prerequisite that is not met, with a description of why.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Problem {
+    /// The prerequisite that is not met.
+    pub prerequisite: Prerequisite,
+    /// Description of why the prerequisite is not met.
+    pub message: String,
+}
+
+/// Outcome of the preflight checks, see [`preflight`](fn.preflight.html).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Report {
+    /// Version of the `nft` binary, `None` if it could not be determined.
+    pub nft_version: Option<NftVersion>,
+    /// The prerequisites that are not met, in the order they were checked in.
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    /// Check whether all prerequisites are met.
+    pub fn passed(&self) -> bool {
+        self.problems.is_empty()
+    }
+
+    fn problem(&mut self, prerequisite: Prerequisite, message: String) {
+        self.problems.push(Problem {
+            prerequisite,
+            message,
+        });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.passed() {
+            return write!(f, "all prerequisites are met");
+        }
+        let problems = self
+            .problems
+            .iter()
+            .map(|problem| format!("{}: {}", problem.prerequisite, problem.message))
+            .collect::<Vec<_>>();
+        write!(f, "{}", problems.join("; "))
+    }
+}
+
+/// Check the prerequisites of applying rules: the DFW process has the `CAP_NET_ADMIN` capability,
+/// the `nft` binary can be invoked and is recent enough, and the kernel modules the rules depend
+/// on are available.
+///
+/// Every prerequisite is checked, such that the report lists all problems at once. Failing to
+/// probe a prerequisite is reported as a problem of that prerequisite.
+pub fn preflight(probes: &dyn Probes) -> Report {
+    let mut report = Report::default();
+
+    match probes.effective_capabilities() {
+        Ok(capabilities) if capabilities & (1 << CAP_NET_ADMIN) != 0 => {}
+        Ok(_) => report.problem(
+            Prerequisite::CapNetAdmin,
+            "the process lacks the capability, run DFW as root or grant it CAP_NET_ADMIN"
+                .to_owned(),
+        ),
+        Err(e) => report.problem(
+            Prerequisite::CapNetAdmin,
+            format!("failed to determine the capabilities of the process: {}", e),
+        ),
+    }
+
+    match probes.nft_version() {
+        Ok(version) => {
+            report.nft_version = Some(version);
+            if version < MINIMUM_NFT_VERSION {
+                report.problem(
+                    Prerequisite::NftBinary,
+                    format!(
+                        "version {} is not supported, at least version {} is required",
+                        version, MINIMUM_NFT_VERSION
+                    ),
+                );
+            }
+        }
+        Err(e) => report.problem(Prerequisite::NftBinary, e.to_string()),
+    }
+
+    for module in &REQUIRED_KERNEL_MODULES {
+        let prerequisite = Prerequisite::KernelModule((*module).to_owned());
+        match probes.kernel_module_available(module) {
+            Ok(true) => {}
+            Ok(false) => report.problem(
+                prerequisite,
+                "the module is neither loaded, built into the kernel nor installed".to_owned(),
+            ),
+            Err(e) => report.problem(
+                prerequisite,
+                format!("failed to determine whether the module is available: {}", e),
+            ),
+        }
+    }
+
+    report
+}
@@ -9,27 +9,45 @@
 //! This module holds the types related to configuration processing and rule creation.
 
 use crate::errors::*;
-use crate::nftables::{self, Family, Hook, RuleVerdict, Type};
+use crate::inventory::{
+    Container, ContainerAliases, ContainerHistory, ContainerInventory, DockerInventory,
+    GracePeriodInventory, HealthStatus, ImagePort, ImagePorts, InventorySnapshot, Network,
+    NetworkEndpoint, RetryInventory,
+};
+use crate::nftables::{self, Family, Hook, NftVersion, RuleVerdict, ScriptRunner, Type};
 use crate::rule::*;
+use crate::simulate;
 use crate::types::*;
-use failure::{bail, format_err, ResultExt};
-use shiplift::builder::{ContainerFilter as ContainerFilterShiplift, ContainerListOptions};
-use shiplift::rep::Container;
-use shiplift::rep::{NetworkContainerDetails, NetworkDetails};
+use crate::util::{ct_timeout_policy, hostname, parse_cidr_file, size_in_bytes};
+use crate::validate::bridging_rule;
+use failure::{bail, format_err, Error, ResultExt};
+use serde::Deserialize;
 use shiplift::Docker;
 use slog::Logger;
-use slog::{debug, info, o, trace};
-use std::collections::HashMap as Map;
+use slog::{debug, info, o, trace, warn};
+use std::cell::{Cell, RefCell};
+use std::collections::{BTreeMap, BTreeSet, HashMap as Map};
+use std::fmt;
+use std::fs;
 use std::io::prelude::*;
-use std::io::BufWriter;
+use std::iter::FromIterator;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
 use std::process::Command;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
 use tempfile;
 use time;
 
+pub use crate::types::{section_order, Section};
+
+const NF_IP_PRI_RAW: i16 = -300;
 const NF_IP_PRI_NAT_DST: i16 = -100;
 const NF_IP_PRI_FILTER: i16 = 0;
 const NF_IP_PRI_NAT_SRC: i16 = 100;
 
+const NF_PRIORITY_INET_RAW_PREROUTING_DFW: i16 = NF_IP_PRI_RAW - 5;
 const NF_PRIORITY_IP_NAT_PREROUTING_DFW: i16 = NF_IP_PRI_NAT_DST - 5;
 const NF_PRIORITY_IP6_NAT_PREROUTING_DFW: i16 = NF_IP_PRI_NAT_DST - 5;
 const NF_PRIORITY_INET_FILTER_ANY_DFW: i16 = NF_IP_PRI_FILTER - 5;
@@ -37,6 +55,17 @@ const NF_PRIORITY_IP_NAT_POSTROUTING_DFW: i16 = NF_IP_PRI_NAT_SRC - 5;
 const NF_PRIORITY_IP6_NAT_POSTROUTING_DFW: i16 = NF_IP_PRI_NAT_SRC - 5;
 
 pub(crate) const DFW_MARK: &str = "0xdf";
+/// Name of the limit object shared by all rules logging packets, see
+/// [`Defaults.log_rate`](../types/struct.Defaults.html#structfield.log_rate).
+pub(crate) const LOG_LIMIT: &str = "dfw_log";
+
+/// Prefix of the network namespace the rule set is applied in by
+/// [`RuleSet::self_test`](struct.RuleSet.html#method.self_test).
+const SELF_TEST_NETNS_PREFIX: &str = "dfw-self-test";
+
+/// Number of times [`retry_stale_addresses`](fn.retry_stale_addresses.html) attempts to
+/// reconcile if container addresses keep changing in between.
+pub const STALE_ADDRESS_ATTEMPTS: usize = 3;
 
 /// This trait allows a type to define its own processing rules. It is expected to return a list
 /// of rules that can be applied with nft.
@@ -66,6 +95,12 @@ pub trait Process {
     ///
     /// [`ProcessContext`]: struct.ProcessContext.html
     fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>>;
+
+    /// Get the location in the configuration the current type was defined at, if known. The rules
+    /// generated for it are annotated with it if `annotate_rules` is set.
+    fn provenance(&self) -> Option<&Provenance> {
+        None
+    }
 }
 
 impl<T> Process for Option<T>
@@ -74,7 +109,7 @@ where
 {
     fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
         match self {
-            Some(t) => t.process(&ctx),
+            Some(t) => t.process(ctx),
             None => Ok(None),
         }
     }
@@ -87,7 +122,10 @@ where
     fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
         let mut rules = Vec::new();
         for rule in self {
-            if let Some(mut sub_rules) = rule.process(&ctx)? {
+            if let Some(mut sub_rules) = rule.process(ctx)? {
+                if let Some(provenance) = rule.provenance().filter(|_| ctx.annotate_rules()) {
+                    sub_rules = annotate_provenance(provenance, sub_rules);
+                }
                 rules.append(&mut sub_rules);
             }
         }
@@ -98,1146 +136,5354 @@ where
 
 impl Process for DFW {
     fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        info!(ctx.logger, "Starting processing";
-              o!("started_processing_at" => format!("{}", time::OffsetDateTime::now().format("%FT%T%z"))));
-        let mut rules = vec![
-            nftables::add_table(Family::Inet, "dfw"),
-            nftables::flush_table(Family::Inet, "dfw"),
-            nftables::add_base_chain(
-                Family::Inet,
-                "dfw",
-                "input",
-                Type::Filter,
-                Hook::Input,
-                NF_PRIORITY_INET_FILTER_ANY_DFW,
-            ),
-            nftables::add_rule(Family::Inet, "dfw", "input", "ct state invalid drop"),
-            nftables::add_rule(
-                Family::Inet,
-                "dfw",
-                "input",
-                "ct state { related, established } accept",
-            ),
-            nftables::add_base_chain(
-                Family::Inet,
-                "dfw",
-                "forward",
-                Type::Filter,
-                Hook::Forward,
-                NF_PRIORITY_INET_FILTER_ANY_DFW,
-            ),
-            nftables::add_rule(Family::Inet, "dfw", "forward", "ct state invalid drop"),
-            nftables::add_rule(
-                Family::Inet,
-                "dfw",
-                "forward",
-                "ct state { related, established } accept",
-            ),
-            nftables::add_table(Family::Ip, "dfw"),
-            nftables::flush_table(Family::Ip, "dfw"),
-            nftables::add_base_chain(
-                Family::Ip,
-                "dfw",
-                "prerouting",
-                Type::Nat,
-                Hook::Prerouting,
-                NF_PRIORITY_IP_NAT_PREROUTING_DFW,
-            ),
-            nftables::add_base_chain(
-                Family::Ip,
-                "dfw",
-                "postrouting",
-                Type::Nat,
-                Hook::Postrouting,
-                NF_PRIORITY_IP_NAT_POSTROUTING_DFW,
-            ),
-            nftables::add_table(Family::Ip6, "dfw"),
-            nftables::flush_table(Family::Ip6, "dfw"),
-            nftables::add_base_chain(
-                Family::Ip6,
-                "dfw",
-                "prerouting",
-                Type::Nat,
-                Hook::Prerouting,
-                NF_PRIORITY_IP6_NAT_PREROUTING_DFW,
-            ),
-            nftables::add_base_chain(
-                Family::Ip6,
-                "dfw",
-                "postrouting",
-                Type::Nat,
-                Hook::Postrouting,
-                NF_PRIORITY_IP6_NAT_POSTROUTING_DFW,
-            ),
-        ];
-        for sub_rules in vec![
-            self.initialization.process(&ctx)?,
-            self.defaults.process(&ctx)?,
-            self.container_to_container.process(&ctx)?,
-            self.container_to_wider_world.process(&ctx)?,
-            self.container_to_host.process(&ctx)?,
-            self.wider_world_to_container.process(&ctx)?,
-            self.container_dnat.process(&ctx)?,
-        ] {
-            if let Some(mut sub_rules) = sub_rules {
-                rules.append(&mut sub_rules);
-            }
-        }
-
-        info!(ctx.logger, "Finished processing";
-             o!("finished_processing_at" => format!("{}", time::OffsetDateTime::now().format("%FT%T%z"))));
-
-        Ok(Some(rules))
-    }
-}
-
-impl Process for Initialization {
-    fn process(&self, _ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        Ok(self.rules.clone())
+        let ruleset = generate_ruleset(self, ctx)?;
+        ruleset_commands(self, ctx, &ruleset).map(Some)
     }
 }
 
-impl Process for Defaults {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
-
-        // Hook into other chains if requested
-        if let Some(ref custom_tables) = self.custom_tables {
-            for custom_table in custom_tables {
-                for chain in &custom_table.chains {
-                    let mut additional_rules = Vec::new();
-
-                    // This is a small helper macro to de-duplicate the "add if it doesn't already
-                    // exist" logic. It essentially generates a marker which uniquely identifies a
-                    // rule, checks if that marker is already in the current ruleset, and if not,
-                    // adds it. This enables us to not duplicate rules that are outside of our table
-                    // where flushing is not an option.
-                    macro_rules! m {
-                        ($mark:expr, $rule:expr) => {
-                            let marker =
-                                generate_marker(&["defaults", &custom_table.name, chain, $mark]);
-                            if !ctx.marker_in_current_ruleset(&marker) {
-                                additional_rules.push(nftables::insert_rule(
-                                    Family::Inet,
-                                    &custom_table.name,
-                                    chain,
-                                    &format!("{} comment \"{}\"", $rule, marker),
-                                    None,
-                                ));
-                            }
-                        };
-                    }
-                    // Handle `ct state invalid drop` rule
-                    m!("ct-state-invalid-drop", "ct state invalid drop");
-                    // Handle `ct state { related, established } accept` rule
-                    m!(
-                        "ct-state-relatedestablished-accept",
-                        "ct state { related, established } accept"
+/// Get the commands applying the rule set generated for the configuration, replacing only the rules
+/// of the selected sections if not all of them are processed.
+fn ruleset_commands(dfw: &DFW, ctx: &ProcessContext, ruleset: &RuleSet) -> Result<Vec<String>> {
+    if ctx.sections == Sections::ALL {
+        if dfw
+            .defaults
+            .as_ref()
+            .is_some_and(|defaults| defaults.preserve_foreign_rules)
+        {
+            match ctx.current_ruleset {
+                Some(ref current_ruleset) => {
+                    debug!(
+                        ctx.logger,
+                        "Replace rules marked by DFW, preserve foreign rules"
                     );
-                    // Handle `meta mark ... accept` rule
-                    m!(
-                        "meta-mark",
-                        &format!("meta mark and {} == {} accept", DFW_MARK, DFW_MARK)
+                    return Ok(ruleset.preserve_foreign_rules(current_ruleset));
+                }
+                None => {
+                    warn!(
+                        ctx.logger,
+                        "Current ruleset is not available, foreign rules are not preserved"
                     );
-
-                    // The rules above are not added at the end, but inserted at the top. We thus
-                    // reverse the order of the rules here to effectively retain the initial order
-                    // of the vec.
-                    additional_rules.reverse();
-                    rules.append(&mut additional_rules);
                 }
             }
         }
+        return Ok(ruleset.commands());
+    }
 
-        // Enforce policy for default Docker-bridge (usually docker0) to access host-resources
-        if let Some(bridge_network) = ctx.network_map.get("bridge") {
-            if let Some(bridge_name) = bridge_network
-                .Options
-                .as_ref()
-                .ok_or_else(|| format_err!("couldn't get network options"))?
-                .get("com.docker.network.bridge.name")
-            {
-                // Set policy for input-chain
-                rules.push(nftables::add_rule(
-                    Family::Inet,
-                    "dfw",
-                    "input",
-                    &format!(
-                        "meta iifname {} meta mark set {} {}",
-                        bridge_name, DFW_MARK, self.default_docker_bridge_to_host_policy,
-                    ),
-                ));
+    // Only a subset of the sections is to be applied. Instead of rebuilding the tables, we replace
+    // the rules of the selected sections in the current ruleset, leaving all other rules untouched.
+    if dfw
+        .defaults
+        .as_ref()
+        .is_some_and(|defaults| defaults.network_chains || defaults.external_interface_chains)
+    {
+        // The chains per interface are part of the preamble, which isn't reapplied.
+        bail!(
+            "sections cannot be applied selectively if `network_chains` or \
+             `external_interface_chains` is set"
+        );
+    }
+    let current_ruleset = ctx.current_ruleset.as_ref().ok_or_else(|| {
+        format_err!("current ruleset is not available, cannot apply sections selectively")
+    })?;
+    let order = section_order(dfw).map_err(|problem| format_err!("{}", problem))?;
+    let rules = ruleset.reconcile(current_ruleset, &order);
+    debug!(ctx.logger, "Reconciled selected sections with current ruleset";
+           o!("sections" => ctx.sections.to_string()));
+
+    Ok(rules)
+}
 
-                // Set policy for forward-chain, divided by the external network interfaces.
-                if let Some(ref external_network_interfaces) = self.external_network_interfaces {
-                    for external_network_interface in external_network_interfaces {
-                        rules.push(nftables::add_rule(
-                            Family::Inet,
-                            "dfw",
-                            "forward",
-                            &format!(
-                                "meta iifname {} oifname {} meta mark set {} {}",
-                                bridge_name,
-                                external_network_interface,
-                                DFW_MARK,
-                                self.default_docker_bridge_to_host_policy,
-                            ),
-                        ))
-                    }
+/// Rules generated for a configuration, see [`generate`](fn.generate.html).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RuleSet {
+    /// Commands setting up the DFW tables and their base chains.
+    pub preamble: Vec<String>,
+    /// Commands generated for the individual sections, in the order they were processed in.
+    pub sections: Vec<(Section, Vec<String>)>,
+    /// Names of the containers and networks that the addresses, subnets and bridges within the
+    /// rules were resolved from, e.g. `container:web` for an address of the container `web`. The
+    /// rules are identified by these names rather than the values they resolved to, see
+    /// [`rule_ids`](#method.rule_ids).
+    pub resolved_names: BTreeMap<String, String>,
+}
+
+impl RuleSet {
+    /// Get all commands required to rebuild the DFW tables from scratch.
+    pub fn commands(&self) -> Vec<String> {
+        let mut commands = self.preamble.clone();
+        for (_, rules) in &self.sections {
+            commands.extend(rules.iter().cloned());
+        }
+        commands
+    }
+
+    /// Get the tables and chains the rule set creates, including the chains depending on the rules,
+    /// see [`managed_objects`](fn.managed_objects.html).
+    pub fn managed_objects(&self) -> ManagedObjects {
+        let mut chains = Vec::new();
+        for command in self.commands() {
+            let words = command.split_whitespace().take(5).collect::<Vec<_>>();
+            if let ["add", "chain", family, "dfw", name] = words[..] {
+                let chain = ManagedChain {
+                    family: family.to_owned(),
+                    name: name.to_owned(),
+                };
+                if !chains.contains(&chain) {
+                    chains.push(chain);
                 }
             }
         }
 
-        // Configure postrouting
-        if let Some(ref external_network_interfaces) = self.external_network_interfaces {
-            for external_network_interface in external_network_interfaces {
-                // Configure postrouting
-                rules.push(nftables::add_rule(
-                    Family::Ip,
-                    "dfw",
-                    "postrouting",
-                    &format!(
-                        "meta oifname {} meta mark set {} masquerade",
-                        external_network_interface, DFW_MARK,
-                    ),
-                ));
-                rules.push(nftables::add_rule(
-                    Family::Ip6,
-                    "dfw",
-                    "postrouting",
-                    &format!(
-                        "meta oifname {} meta mark set {} masquerade",
-                        external_network_interface, DFW_MARK,
-                    ),
-                ));
-            }
+        ManagedObjects {
+            table: "dfw".to_owned(),
+            chains,
         }
-        Ok(Some(rules))
     }
-}
 
-impl Process for ContainerToContainer {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
+    /// Render the rule set as nftables script in `nft -f` syntax, e.g. to be loaded by
+    /// `nftables.service`.
+    ///
+    /// The script rebuilds the DFW tables from scratch: every table is added and flushed before its
+    /// chains and rules are added. Since `nft -f` applies a script within a single transaction, the
+    /// ruleset is replaced atomically.
+    pub fn render(&self) -> String {
+        nftables::script(&self.commands())
+    }
 
-        // Enforce default policy for container-to-container communication.
-        rules.push(nftables::set_chain_policy(
-            Family::Inet,
-            "dfw",
-            "forward",
-            self.default_policy,
-        ));
+    /// Write the rendered rule set to the given path, see [`render`](#method.render).
+    ///
+    /// The file is written to a temporary file next to the path first and then moved into place,
+    /// such that the file at the path is never partially written.
+    pub fn write_file<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let directory = match path.parent() {
+            Some(directory) if !directory.as_os_str().is_empty() => directory,
+            _ => Path::new("."),
+        };
 
-        if let Some(mut ctc_rules) = self.rules.process(&ctx)? {
-            rules.append(&mut ctc_rules);
-        }
+        let mut rule_file = tempfile::Builder::new()
+            .prefix(".dfw")
+            .tempfile_in(directory)
+            .context(format!(
+                "failed to create temporary file in {}",
+                directory.display()
+            ))?;
+        rule_file.write_all(self.render().as_bytes())?;
+        rule_file.as_file().sync_all()?;
+        rule_file
+            .persist(path)
+            .context(format!("failed to write rules to {}", path.display()))?;
 
-        Ok(Some(rules))
+        Ok(())
     }
-}
 
-impl Process for ContainerToContainerRule {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
-        let mut nft_rule = RuleBuilder::default();
-        let network = match ctx.network_map.get(&self.network) {
-            Some(network) => network,
-            None => return Ok(None),
-        };
-        trace!(ctx.logger, "Got network";
-                    o!("network_name" => &self.network,
-                        "network" => format!("{:?}", network)));
-        let bridge_name = get_bridge_name(&network.Id)?;
-        trace!(ctx.logger, "Got bridge name";
-                    o!("network_name" => &network.Name,
-                        "bridge_name" => &bridge_name));
+    /// Apply the rule set within a throwaway network namespace, verifying that `nft` accepts it
+    /// without touching the ruleset of the host. The namespace is deleted afterwards, regardless
+    /// of the outcome.
+    ///
+    /// Since the namespace starts out without any tables, rules of the initialization section
+    /// referencing tables that are not created by the rule set themselves will be reported as
+    /// errors. This requires root privileges.
+    pub fn self_test(&self) -> Result<()> {
+        let netns = nftables::TemporaryNetns::create(SELF_TEST_NETNS_PREFIX)?;
+
+        nftables::NftBinary {
+            netns: Some(netns.name().to_owned()),
+            nft_path: None,
+        }
+        .run_script(&self.render())
+    }
 
-        nft_rule
-            .in_interface(&bridge_name)
-            .out_interface(&bridge_name);
+    /// Get the commands replacing the rules of the generated sections within the given ruleset, as
+    /// listed by `nft --handle list ruleset`. The rules of all other sections are left untouched.
+    ///
+    /// The order has to contain all sections in the order they are processed in, see
+    /// [`section_order`](../types/fn.section_order.html).
+    pub fn reconcile(&self, current_ruleset: &str, order: &[Section]) -> Vec<String> {
+        let sections = self.sections.iter().map(|(section, _)| *section).collect();
+        reconcile_sections(current_ruleset, sections, self.sections.clone(), order)
+    }
 
-        if let Some(ref src_container) = self.src_container {
-            let src_network = match get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                src_container,
-                &network.Id,
-            )? {
-                Some(src_network) => src_network,
-                None => return Ok(None),
+    /// Get the commands replacing all rules of the DFW tables within the given ruleset, as listed by
+    /// `nft --handle list ruleset`, while leaving the rules not created by DFW untouched, see
+    /// [`Defaults.preserve_foreign_rules`](../types/struct.Defaults.html#structfield.preserve_foreign_rules).
+    ///
+    /// Instead of flushing the DFW tables, the rules carrying a DFW marker are deleted and the rules
+    /// of the rule set are added again. The rules of the preamble are marked as well, such that
+    /// they are recognized on the next run.
+    ///
+    /// The foreign rules keep their position relative to the rules of the preamble and the
+    /// sections: a rule of the rule set is inserted in front of the first foreign rule of its chain
+    /// that followed a marked rule of the same or a later section, or added to the end of the chain
+    /// if there is none. Foreign rules in between the rules of a single section thus end up after
+    /// all rules of that section.
+    pub fn preserve_foreign_rules(&self, current_ruleset: &str) -> Vec<String> {
+        let preamble_marker = generate_marker(&["preamble"]);
+        let listed_rules = parse_listed_rules(current_ruleset);
+
+        // The preamble is ranked first, followed by the sections in the order they are processed
+        // in.
+        let rank = |section: Option<Section>| match section {
+            Some(section) => self
+                .sections
+                .iter()
+                .position(|(other, _)| *other == section)
+                .map(|position| position + 1),
+            None => Some(0),
+        };
+        // Every foreign rule is paired with the rank of the last marked rule preceding it in its
+        // chain, if any.
+        let mut foreign_rules = Vec::new();
+        let mut preceding: Option<(&str, &str, Option<usize>)> = None;
+        for listed_rule in &listed_rules {
+            let preceding_rank = match preceding {
+                Some((family, chain, preceding_rank))
+                    if family == listed_rule.family && chain == listed_rule.chain =>
+                {
+                    preceding_rank
+                }
+                _ => None,
             };
-            trace!(ctx.logger, "Got source network";
-                        o!("network_name" => &network.Name,
-                            "src_network" => format!("{:?}", src_network)));
-
-            let bridge_name = get_bridge_name(&network.Id)?;
-            trace!(ctx.logger, "Got bridge name";
-                        o!("network_name" => &network.Name,
-                            "bridge_name" => &bridge_name));
-
-            nft_rule
-                .in_interface(&bridge_name)
-                .out_interface(&bridge_name)
-                .source_address(
-                    src_network
-                        .IPv4Address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
+            let preceding_rank = if listed_rule.marked {
+                rank(listed_rule.section).or(preceding_rank)
+            } else {
+                foreign_rules.push((listed_rule, preceding_rank));
+                preceding_rank
+            };
+            preceding = Some((&listed_rule.family, &listed_rule.chain, preceding_rank));
         }
-
-        if let Some(ref dst_container) = self.dst_container {
-            let dst_network = match get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                dst_container,
-                &network.Id,
-            )? {
-                Some(dst_network) => dst_network,
-                None => return Ok(None),
+        let place = |command: String, rank: usize| {
+            let anchor = match split_rule_command(&command) {
+                Some(("add", family, "dfw", chain, _)) => {
+                    foreign_rules.iter().find(|(listed_rule, preceding_rank)| {
+                        listed_rule.family == family
+                            && listed_rule.chain == chain
+                            && *preceding_rank >= Some(rank)
+                    })
+                }
+                _ => None,
             };
-            trace!(ctx.logger, "Got destination network";
-                        o!("network_name" => &network.Name,
-                            "dst_network" => format!("{:?}", dst_network)));
-
-            let bridge_name = get_bridge_name(&network.Id)?;
-            trace!(ctx.logger, "Got bridge name";
-                        o!("network_name" => &network.Name,
-                            "bridge_name" => &bridge_name));
+            match (anchor, split_rule_command(&command)) {
+                (Some((anchor, _)), Some((_, family, table, chain, rule))) => format!(
+                    "insert rule {} {} {} position {} {}",
+                    family, table, chain, anchor.handle, rule
+                ),
+                _ => command,
+            }
+        };
 
-            nft_rule.out_interface(&bridge_name).destination_address(
-                dst_network
-                    .IPv4Address
-                    .split('/')
-                    .next()
-                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-            );
+        let mut commands = listed_rules
+            .iter()
+            .filter(|listed_rule| listed_rule.marked)
+            .map(|listed_rule| {
+                format!(
+                    "delete rule {} dfw {} handle {}",
+                    listed_rule.family, listed_rule.chain, listed_rule.handle
+                )
+            })
+            .collect::<Vec<_>>();
+        for command in &self.preamble {
+            match split_rule_command(command) {
+                Some((_, _, "dfw", _, _)) => commands.push(place(
+                    format!("{} comment \"{}\"", command, preamble_marker),
+                    0,
+                )),
+                _ => match command.split_whitespace().collect::<Vec<_>>()[..] {
+                    ["flush", "table", _, "dfw"] => {}
+                    _ => commands.push(command.clone()),
+                },
+            }
         }
-
-        if let Some(matches) = &self.matches {
-            nft_rule.matches(matches);
+        for (position, (_, rules)) in self.sections.iter().enumerate() {
+            commands.extend(rules.iter().map(|rule| place(rule.clone(), position + 1)));
         }
-        nft_rule.verdict(self.verdict);
 
-        let rule = nft_rule.build()?;
-        rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
+        commands
+    }
 
-        Ok(Some(rules))
+    /// Get the rules of all sections together with their [`RuleId`](struct.RuleId.html), in the
+    /// order they were processed in.
+    ///
+    /// The identity is calculated from the rule with its addresses, subnets and bridges replaced by
+    /// the [names](#structfield.resolved_names) of the containers and networks they were resolved
+    /// from. A rule thus keeps its identity if a container is addressed anew or a network is
+    /// recreated.
+    ///
+    /// Rules that are generated more than once within the same section are told apart by their
+    /// occurrence, such that every rule of the rule set has a distinct identity. Rules carrying their
+    /// identity in their comment, as they do while counters are collected for the
+    /// [metrics](../types/struct.Runtime.html#structfield.metrics_address), keep that identity.
+    pub fn rule_ids(&self) -> Vec<(RuleId, Section, &str)> {
+        let mut occurrences: Map<(Section, String), usize> = Map::new();
+        let mut rule_ids = Vec::new();
+        for (section, rules) in &self.sections {
+            for rule in rules {
+                let rule_id = embedded_rule_id(rule).unwrap_or_else(|| {
+                    let named_rule = name_resolved_values(rule, &self.resolved_names);
+                    let occurrence = occurrences
+                        .entry((*section, named_rule.clone()))
+                        .or_insert(0);
+                    *occurrence += 1;
+                    RuleId::with_occurrence(*section, &named_rule, *occurrence - 1)
+                });
+                rule_ids.push((rule_id, *section, rule.as_str()));
+            }
+        }
+        rule_ids
+    }
+
+    /// Get the rendered rules of all sections keyed by their [`RuleId`](struct.RuleId.html), see
+    /// [`rule_ids`](#method.rule_ids).
+    ///
+    /// The map identifies every rule of the rule set independently of the order it was generated
+    /// in, which allows external tools to persist it and diff the rule sets of different runs.
+    pub fn identity_map(&self) -> BTreeMap<RuleId, String> {
+        self.rule_ids()
+            .into_iter()
+            .map(|(rule_id, _, rule)| (rule_id, rule.to_owned()))
+            .collect()
     }
 }
 
-impl Process for ContainerToWiderWorld {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
+/// Stable identity of a generated rule.
+///
+/// The identity is derived from the section and the rule with the values it was resolved to
+/// replaced by the names of the containers and networks they were resolved from, i.e. from the
+/// inputs the rule was generated from (containers, networks, ports, verdict and matches). It does
+/// not depend on the order the rules were generated in, nor on the addresses of the containers or
+/// the bridges of the networks, and is the same across generation runs and DFW versions, see
+/// [`RuleSet::rule_ids`](struct.RuleSet.html#method.rule_ids).
+///
+/// # Example
+///
+/// ```
+/// # use dfw::process::{RuleId, Section};
+/// let rule = "add rule inet dfw forward tcp dport 443 accept";
+/// let id = RuleId::new(Section::WiderWorldToContainer, rule);
+/// assert_eq!(id, RuleId::new(Section::WiderWorldToContainer, rule));
+/// assert_ne!(id, RuleId::new(Section::ContainerToContainer, rule));
+/// assert_eq!(id, id.to_string().parse().unwrap());
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct RuleId(u64);
+
+impl RuleId {
+    /// Calculate the identity of the rule within the section, given the rule as named by
+    /// [`RuleSet::rule_ids`](struct.RuleSet.html#method.rule_ids).
+    pub fn new(section: Section, rule: &str) -> RuleId {
+        RuleId::with_occurrence(section, rule, 0)
+    }
 
-        if let Some(mut ctww_rules) = self.rules.process(&ctx)? {
-            rules.append(&mut ctww_rules);
+    fn with_occurrence(section: Section, rule: &str, occurrence: usize) -> RuleId {
+        let mut data = format!("{}\0{}", section, rule);
+        if occurrence > 0 {
+            data.push_str(&format!("\0{}", occurrence));
         }
+        RuleId(fnv1a(data.as_bytes()))
+    }
+}
 
-        // Enforce default policy for container-to-wider-world communication.
-        if let Some(external_network_interfaces) = &ctx.external_network_interfaces {
-            debug!(ctx.logger, "Set default policy for external network interfaces";
-                   o!("part" => "container_to_wider_world",
-                      "external_network_interfaces" => format!("{:?}", external_network_interfaces),
-                      "default_policy" => &self.default_policy));
-            for external_network_interface in external_network_interfaces {
-                trace!(ctx.logger, "Process default policy for external network interface";
-                       o!("part" => "container_to_wider_world",
-                          "external_network_interface" => external_network_interface,
-                          "default_policy" => &self.default_policy));
-                for network in ctx.network_map.values() {
-                    let bridge_name = get_bridge_name(&network.Id)?;
-                    trace!(ctx.logger, "Got bridge name";
-                           o!("network_name" => &network.Name,
-                              "bridge_name" => &bridge_name));
-
-                    let rule = RuleBuilder::default()
-                        .in_interface(&bridge_name)
-                        .out_interface(external_network_interface)
-                        .verdict(self.default_policy)
-                        .build()?;
+impl fmt::Display for RuleId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:016x}", self.0)
+    }
+}
 
-                    debug!(ctx.logger, "Add forward rule for default policy";
-                           o!("part" => "container_to_wider_world",
-                              "external_network_interface" => external_network_interface,
-                              "default_policy" => &self.default_policy,
-                              "rule" => &rule));
+impl FromStr for RuleId {
+    type Err = String;
 
-                    rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
-                }
-            }
+    /// Parse a rule identity as formatted by its `Display` implementation, i.e. 16 hexadecimal
+    /// digits.
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.len() != 16 {
+            return Err(format!("rule ID '{}' has invalid length", s));
         }
-
-        Ok(Some(rules))
+        u64::from_str_radix(s, 16)
+            .map(RuleId)
+            .map_err(|_| format!("rule ID '{}' is not hexadecimal", s))
     }
 }
 
-impl Process for ContainerToWiderWorldRule {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
-        debug!(ctx.logger, "Process rule";
-                   o!("part" => "container_to_wider_world",
-                      "rule" => format!("{:?}", self)));
-        let mut nft_rule = RuleBuilder::default();
-
-        if let Some(ref network) = self.network {
-            if let Some(network) = ctx.network_map.get(network) {
-                if let Some(ref src_container) = self.src_container {
-                    if let Some(src_network) = get_network_for_container(
-                        ctx.docker,
-                        &ctx.container_map,
-                        src_container,
-                        &network.Id,
-                    )? {
-                        trace!(ctx.logger, "Got source network";
-                                   o!("network_name" => &network.Name,
-                                      "src_network" => format!("{:?}", src_network)));
+/// Times the rules were first generated at, tracked across the rule sets of all reconciles, e.g.
+/// for auditing how long a rule has been in place.
+///
+/// Rules are identified by their [`RuleId`](struct.RuleId.html), a rule thus keeps its first-seen
+/// time as long as it is generated from the same inputs, even if its containers are addressed anew.
+/// Rules that are no longer generated are forgotten, such that a rule generated again later counts
+/// as new. Only the rules of the sections part of a recorded rule set are affected, the rules of all
+/// other sections are left untouched.
+///
+/// The history has to outlive the individual reconciles. If it is [loaded](#method.load) from a
+/// file, the first-seen times are persisted in it across restarts.
+#[derive(Debug, Default)]
+pub struct RuleHistory {
+    path: Option<PathBuf>,
+    first_seen: Mutex<BTreeMap<RuleId, (Section, u64)>>,
+}
 
-                        let bridge_name = get_bridge_name(&network.Id)?;
-                        trace!(ctx.logger, "Got bridge name";
-                                   o!("network_name" => &network.Name,
-                                      "bridge_name" => &bridge_name));
+impl RuleHistory {
+    /// Create a history persisting the first-seen times in the given file, continuing with the
+    /// times recorded in it. If the file doesn't exist, no rule has been seen yet.
+    pub fn load(path: &Path) -> Result<RuleHistory> {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref error) if error.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(error) => bail!(
+                "failed to read rule state file {}: {}",
+                path.display(),
+                error
+            ),
+        };
 
-                        nft_rule.in_interface(&bridge_name).source_address(
-                            src_network
-                                .IPv4Address
-                                .split('/')
-                                .next()
-                                .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                        );
+        let mut first_seen = BTreeMap::new();
+        for (index, line) in contents.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let entry = match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [rule_id, section, time] => {
+                    match (rule_id.parse(), section.parse(), time.parse()) {
+                        (Ok(rule_id), Ok(section), Ok(time)) => Some((rule_id, section, time)),
+                        _ => None,
                     }
-                } else {
-                    let bridge_name = get_bridge_name(&network.Id)?;
-                    trace!(ctx.logger, "Got bridge name";
-                               o!("network_name" => &network.Name,
-                                  "bridge_name" => &bridge_name));
-
-                    nft_rule.in_interface(&bridge_name);
                 }
+                _ => None,
+            };
+            match entry {
+                Some((rule_id, section, time)) => {
+                    first_seen.insert(rule_id, (section, time));
+                }
+                None => bail!(
+                    "line {} of rule state file {} is invalid: '{}'",
+                    index + 1,
+                    path.display(),
+                    line
+                ),
             }
         }
 
-        if let Some(ref matches) = self.matches {
-            nft_rule.matches(matches);
-        }
+        Ok(RuleHistory {
+            path: Some(path.to_owned()),
+            first_seen: Mutex::new(first_seen),
+        })
+    }
 
-        nft_rule.verdict(self.verdict);
+    /// Record the rules of the rule set as seen at `now`, in seconds since the epoch. Rules seen
+    /// before keep their first-seen time, the rules of the sections of the rule set that are no
+    /// longer generated are forgotten.
+    pub fn record(&self, ruleset: &RuleSet, now: u64) {
+        let rule_ids = ruleset.rule_ids();
+        let current = rule_ids
+            .iter()
+            .map(|(rule_id, _, _)| *rule_id)
+            .collect::<BTreeSet<_>>();
+        let sections = ruleset
+            .sections
+            .iter()
+            .map(|(section, _)| *section)
+            .collect::<BTreeSet<_>>();
 
-        // Try to build the rule without the out_interface defined to see if any of the other
-        // mandatory fields has been populated.
-        debug!(ctx.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", nft_rule)));
-        // TODO: maybe add a `verify` method to `Rule`
-        nft_rule.build().context(format!(
-            "failed to build rule, maybe the network `{:?}` or container `{:?}` doesn't exist",
-            self.network, self.src_container
-        ))?;
+        let mut first_seen = self.first_seen.lock().expect("history lock poisoned");
+        let vanished = first_seen
+            .iter()
+            .filter(|(rule_id, (section, _))| {
+                sections.contains(section) && !current.contains(*rule_id)
+            })
+            .map(|(rule_id, _)| *rule_id)
+            .collect::<Vec<_>>();
+        for rule_id in vanished {
+            first_seen.remove(&rule_id);
+        }
+        for (rule_id, section, _) in rule_ids {
+            first_seen.entry(rule_id).or_insert((section, now));
+        }
+    }
 
-        if let Some(ref external_network_interface) = self.external_network_interface {
-            trace!(ctx.logger, "Rule has specific external network interface";
-                       o!("external_network_interface" => external_network_interface));
-            nft_rule.out_interface(external_network_interface);
-        } else if let Some(ref primary_external_network_interface) =
-            ctx.primary_external_network_interface
-        {
-            trace!(ctx.logger, "Rule uses primary external network interface";
-                       o!("external_network_interface" => primary_external_network_interface));
-            nft_rule.out_interface(primary_external_network_interface);
+    /// Get the time the rule was first seen at, in seconds since the epoch, or `None` if the rule
+    /// is not part of the recorded rule sets.
+    pub fn first_seen(&self, rule_id: RuleId) -> Option<u64> {
+        self.first_seen
+            .lock()
+            .expect("history lock poisoned")
+            .get(&rule_id)
+            .map(|(_, time)| *time)
+    }
+
+    /// Add the first-seen times of the rules to their explanations, see
+    /// [`explain`](fn.explain.html).
+    pub fn annotate(&self, explained: &mut [(String, Explanation)]) {
+        for (_, explanation) in explained {
+            explanation.first_seen = explanation
+                .rule_id
+                .and_then(|rule_id| self.first_seen(rule_id));
         }
+    }
 
-        let rule = nft_rule.build()?;
-        debug!(ctx.logger, "Add forward rule";
-                   o!("part" => "container_to_wider_world",
-                      "rule" => &rule));
+    /// Persist the first-seen times in the file of the history, if it has one.
+    pub fn persist(&self) -> Result<()> {
+        let path = match &self.path {
+            Some(path) => path,
+            None => return Ok(()),
+        };
+        let contents = self
+            .first_seen
+            .lock()
+            .expect("history lock poisoned")
+            .iter()
+            .map(|(rule_id, (section, time))| format!("{} {} {}\n", rule_id, section, time))
+            .collect::<String>();
+        // The file is replaced at once, such that a crash cannot leave a truncated file behind.
+        let mut temporary_path = path.clone().into_os_string();
+        temporary_path.push(".tmp");
+        fs::write(&temporary_path, contents)?;
+        fs::rename(&temporary_path, path)?;
 
-        // Apply the rule
-        rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
-        Ok(Some(rules))
+        Ok(())
     }
 }
 
-impl Process for ContainerToHost {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
+/// Generate the rules for the configuration, retrieving all external data from the inventory.
+///
+/// This has no side effects: Docker is not queried and no rules are applied, unless the inventory
+/// itself does so. Generating the rules twice with the same inventory yields the same rule set.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::inventory::{Container, ContainerInventory, Network};
+/// # use dfw::process::generate;
+/// # use dfw::types::DFW;
+/// # use failure::Error;
+/// struct StaticInventory;
+///
+/// impl ContainerInventory for StaticInventory {
+///     fn containers(&self) -> Result<Vec<Container>, Error> {
+///         Ok(vec![Container {
+///             id: "4f4a1e3a9b2c".to_owned(),
+///             names: vec!["web".to_owned()],
+///             ..Default::default()
+///         }])
+///     }
+///
+///     fn networks(&self) -> Result<Vec<Network>, Error> {
+///         Ok(vec![Network {
+///             id: "0123456789abcdef".to_owned(),
+///             name: "frontend".to_owned(),
+///             ..Default::default()
+///         }])
+///     }
+/// }
+///
+/// let dfw: DFW = toml::from_str("").unwrap();
+/// let ruleset = generate(&dfw, &StaticInventory).unwrap();
+/// assert!(ruleset.commands().contains(&"add table inet dfw".to_owned()));
+/// ```
+pub fn generate(dfw: &DFW, inventory: &dyn ContainerInventory) -> Result<RuleSet> {
+    generate_sections(dfw, inventory, Sections::ALL)
+}
 
-        if let Some(mut cth_rules) = self.rules.process(&ctx)? {
-            rules.append(&mut cth_rules);
-        }
+/// Generate the rules for the selected sections of the configuration, retrieving all external data
+/// from the inventory. See [`generate`](fn.generate.html).
+pub fn generate_sections(
+    dfw: &DFW,
+    inventory: &dyn ContainerInventory,
+    sections: Sections,
+) -> Result<RuleSet> {
+    let logger = Logger::root(slog::Discard, o!());
+    let ctx = ProcessContext::with_inventory(Box::new(inventory), dfw, sections, &logger, true)?;
+    generate_ruleset(dfw, &ctx)
+}
 
-        // Default policy
-        for network in ctx.network_map.values() {
-            let bridge_name = get_bridge_name(&network.Id)?;
-            trace!(ctx.logger, "Got bridge name";
-                   o!("network_name" => &network.Name,
-                      "bridge_name" => &bridge_name));
+/// Generate the rules for the configuration like [`generate`](fn.generate.html) does, explaining
+/// the origin of every rule.
+///
+/// Every rule is accompanied by the rule of the configuration that produced it, the expansion of
+/// the wildcard network that applied to it and the containers and networks it was resolved to, see
+/// [`Explanation`](struct.Explanation.html). The rules are returned in the order of
+/// [`RuleSet::commands`](struct.RuleSet.html#method.commands).
+pub fn explain(
+    dfw: &DFW,
+    inventory: &dyn ContainerInventory,
+) -> Result<Vec<(String, Explanation)>> {
+    let logger = Logger::root(slog::Discard, o!());
+    let ctx =
+        ProcessContext::with_inventory(Box::new(inventory), dfw, Sections::ALL, &logger, true)?;
+    explain_ruleset(dfw, &ctx)
+}
 
-            let rule = RuleBuilder::default()
-                .in_interface(&bridge_name)
-                .verdict(self.default_policy)
-                .build()?;
+/// Reconcile the rules exactly once: capture the inventory, generate the rules for the selected
+/// sections of the configuration and apply them through the given script runner.
+///
+/// In contrast to the processing loop of the DFW binary, neither Docker events nor signals are
+/// waited for, which allows DFW to be run from cron or orchestration scripts. Stale container
+/// addresses are handled like in the processing loop, see
+/// [`retry_stale_addresses`](fn.retry_stale_addresses.html).
+///
+/// The rule set that was applied is returned, `None` during a dry run. An error is returned if the
+/// rules could not be generated or applied.
+pub fn run_once(
+    dfw: &DFW,
+    inventory: &dyn ContainerInventory,
+    sections: Sections,
+    runner: &dyn ScriptRunner,
+    logger: &Logger,
+    dry_run: bool,
+) -> Result<Option<RuleSet>> {
+    retry_stale_addresses(
+        || {
+            ProcessContext::with_inventory(Box::new(inventory), dfw, sections, logger, dry_run)
+                .and_then(|ctx| ctx.process_with(runner))
+        },
+        logger,
+    )
+}
 
-            trace!(ctx.logger, "Add input rule for default policy";
-                   o!("part" => "container_to_host",
-                      "default_policy" => self.default_policy,
-                      "rule" => &rule));
-            rules.push(nftables::add_rule(Family::Inet, "dfw", "input", &rule));
+/// Run the processing again if a container got a new address while the rules were generated, see
+/// `ProcessContext::verify_addresses`, up to
+/// [`STALE_ADDRESS_ATTEMPTS`](constant.STALE_ADDRESS_ATTEMPTS.html) times. Every run is expected
+/// to capture the inventory anew.
+pub fn retry_stale_addresses<T>(process: impl Fn() -> Result<T>, logger: &Logger) -> Result<T> {
+    let mut attempt = 1;
+    loop {
+        match process() {
+            Err(error) if attempt < STALE_ADDRESS_ATTEMPTS => match error.downcast_ref() {
+                Some(DFWError::StaleAddresses { addresses }) => {
+                    warn!(logger, "Container addresses changed during processing, processing again";
+                          o!("attempt" => attempt,
+                             "stale_addresses" => addresses));
+                    attempt += 1;
+                }
+                _ => return Err(error),
+            },
+            result => return result,
         }
-
-        Ok(Some(rules))
     }
 }
 
-impl Process for ContainerToHostRule {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
-        debug!(ctx.logger, "Process rule";
-                   o!("part" => "container_to_host",
-                      "rule" => format!("{:?}", self)));
-        let mut nft_rule = RuleBuilder::default();
+/// Explanation of the origin of a generated rule, see [`explain`](fn.explain.html).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Explanation {
+    /// Section the rule was generated for, `None` for the rules setting up the DFW tables.
+    pub section: Option<Section>,
+    /// Number of the rule within the section of the configuration that produced the rule, starting
+    /// at 1. This is `None` if the rule was generated by the section itself, e.g. for its default
+    /// policy.
+    pub rule: Option<usize>,
+    /// Location in the configuration the rule was defined at, if known.
+    pub provenance: Option<Provenance>,
+    /// Expansion that applied to the rule of the configuration, e.g. the network the wildcard
+    /// network was expanded to.
+    pub expansion: Option<String>,
+    /// Containers and networks the rule of the configuration was resolved to.
+    pub resolved: Vec<String>,
+    /// Identity of the rule, `None` for the rules setting up the DFW tables.
+    pub rule_id: Option<RuleId>,
+    /// Time the rule was first applied at, in seconds since the epoch, if it is known from the
+    /// history of the rules, see [`RuleHistory::annotate`](struct.RuleHistory.html#method.annotate).
+    pub first_seen: Option<u64>,
+}
 
-        let network = match ctx.network_map.get(&self.network) {
-            Some(network) => network,
-            None => return Ok(None),
+impl fmt::Display for Explanation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let section = match self.section {
+            Some(section) => section,
+            None => return write!(f, "sets up the DFW tables"),
         };
-        trace!(ctx.logger, "Got network";
-                   o!("network_name" => &network.Name,
-                      "network" => format!("{:?}", network)));
+        match self.rule {
+            Some(rule) => write!(f, "rule {} of section `{}`", rule, section)?,
+            None => write!(f, "section `{}`", section)?,
+        }
+        if let Some(provenance) = &self.provenance {
+            write!(f, " ({})", provenance)?;
+        }
+        if let Some(expansion) = &self.expansion {
+            write!(f, ", {}", expansion)?;
+        }
+        if !self.resolved.is_empty() {
+            write!(f, ", resolved {}", self.resolved.join(", "))?;
+        }
+        if let Some(first_seen) = self.first_seen {
+            write!(
+                f,
+                ", first applied at {}",
+                time::OffsetDateTime::from_unix_timestamp(first_seen as i64).format("%FT%T%z")
+            )?;
+        }
+        Ok(())
+    }
+}
 
-        let bridge_name = get_bridge_name(&network.Id)?;
-        trace!(ctx.logger, "Got bridge name";
-                   o!("network_name" => &network.Name,
-                      "bridge_name" => &bridge_name));
+/// Rule of the configuration whose generated rules can be explained, see
+/// [`explain`](fn.explain.html).
+trait Explain: Process + Clone {
+    /// Networks and containers referenced by the rule. A network of `None` applies to all networks.
+    fn endpoints(&self) -> Vec<(Option<&str>, Option<&ContainerSelector>)>;
 
-        nft_rule.in_interface(&bridge_name);
+    /// Get a copy of the rule applying to the given network, if the rule applies to the
+    /// [wildcard network](../types/constant.WILDCARD_NETWORK.html).
+    fn expand_network(&self, _network: String) -> Option<Self> {
+        None
+    }
+}
 
-        if let Some(ref src_container) = self.src_container {
-            if let Some(src_network) = get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                src_container,
-                &network.Id,
-            )? {
-                trace!(ctx.logger, "Got source network";
-                           o!("network_name" => &network.Name,
-                              "src_network" => format!("{:?}", src_network)));
-                nft_rule.source_address(
-                    src_network
-                        .IPv4Address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
-            }
-        }
+/// List the containers referenced on the network, or the network itself if no container is
+/// referenced.
+fn endpoints<'a>(
+    network: Option<&'a str>,
+    containers: &[Option<&'a ContainerSelector>],
+) -> Vec<(Option<&'a str>, Option<&'a ContainerSelector>)> {
+    let endpoints = containers
+        .iter()
+        .filter(|container| container.is_some())
+        .map(|container| (network, *container))
+        .collect::<Vec<_>>();
+    if endpoints.is_empty() {
+        vec![(network, None)]
+    } else {
+        endpoints
+    }
+}
 
-        if let Some(ref matches) = self.matches {
-            nft_rule.matches(matches);
+impl Explain for ContainerToContainerRule {
+    fn endpoints(&self) -> Vec<(Option<&str>, Option<&ContainerSelector>)> {
+        let dst_network = self.dst_network.as_deref().unwrap_or(&self.network);
+        if dst_network == self.network {
+            return endpoints(
+                Some(&self.network),
+                &[self.src_container.as_ref(), self.dst_container.as_ref()],
+            );
         }
 
-        nft_rule.verdict(self.verdict);
+        let mut endpoints = endpoints(Some(&self.network), &[self.src_container.as_ref()]);
+        endpoints.append(&mut self::endpoints(
+            Some(dst_network),
+            &[self.dst_container.as_ref()],
+        ));
+        endpoints
+    }
 
-        // Try to build the rule without the out_interface defined to see if any of the other
-        // mandatory fields has been populated.
-        debug!(ctx.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", nft_rule)));
-        // TODO: maybe add a `verify` method to `Rule`
-        nft_rule.build().context(format!(
-            "failed to build rule, maybe the container `{:?}` doesn't exist",
-            self.src_container
-        ))?;
+    fn expand_network(&self, network: String) -> Option<Self> {
+        Some(ContainerToContainerRule {
+            network,
+            ..self.clone()
+        })
+    }
+}
 
-        let rule = nft_rule.build()?;
-        debug!(ctx.logger, "Add input rule";
-                   o!("part" => "container_to_host",
-                      "rule" => &rule));
+impl Explain for ContainerToWiderWorldRule {
+    fn endpoints(&self) -> Vec<(Option<&str>, Option<&ContainerSelector>)> {
+        endpoints(self.network.as_deref(), &[self.src_container.as_ref()])
+    }
+}
 
-        // Apply the rule
-        rules.push(nftables::add_rule(Family::Inet, "dfw", "input", &rule));
+impl Explain for ContainerToHostRule {
+    fn endpoints(&self) -> Vec<(Option<&str>, Option<&ContainerSelector>)> {
+        endpoints(Some(&self.network), &[self.src_container.as_ref()])
+    }
 
-        Ok(Some(rules))
+    fn expand_network(&self, network: String) -> Option<Self> {
+        Some(ContainerToHostRule {
+            network,
+            ..self.clone()
+        })
     }
 }
 
-impl Process for WiderWorldToContainer {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        if self.rules.is_some() {
-            debug!(ctx.logger, "Process rules";
-                   o!("part" => "wider_world_to_container"));
-            self.rules.process(&ctx)
-        } else {
-            trace!(ctx.logger, "No rules";
-                   o!("part" => "wider_world_to_container"));
-            Ok(None)
-        }
+impl Explain for WiderWorldToContainerRule {
+    fn endpoints(&self) -> Vec<(Option<&str>, Option<&ContainerSelector>)> {
+        let containers = self
+            .from_containers
+            .iter()
+            .chain(Some(&self.dst_container))
+            .map(Some)
+            .collect::<Vec<_>>();
+        endpoints(Some(&self.network), &containers)
     }
 }
 
-impl Process for WiderWorldToContainerRule {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        let mut rules = Vec::new();
-        debug!(ctx.logger, "Process rule";
-                   o!("part" => "wider_world_to_container",
-                      "rule" => format!("{:?}", self)));
-        for expose_port in &self.expose_port {
-            let mut nft_forward_rule = RuleBuilder::default();
-            let mut nft_dnat_rule = RuleBuilder::default();
-            let mut nft_mark_rule = RuleBuilder::default();
+impl Explain for ContainerDNATRule {
+    fn endpoints(&self) -> Vec<(Option<&str>, Option<&ContainerSelector>)> {
+        let mut endpoints = match (&self.src_network, &self.src_container) {
+            (Some(_), _) | (_, Some(_)) => {
+                endpoints(self.src_network.as_deref(), &[self.src_container.as_ref()])
+            }
+            (None, None) => Vec::new(),
+        };
+        endpoints.push((Some(&self.dst_network), Some(&self.dst_container)));
+        endpoints
+    }
+}
 
-            let network = match ctx.network_map.get(&self.network) {
-                Some(network) => network,
-                None => return Ok(None),
-            };
-            trace!(ctx.logger, "Got network";
-                   o!("network_name" => &network.Name,
-                      "network" => format!("{:?}", network)));
+/// Describe what the network and container referenced by a rule resolve to.
+fn describe_endpoint(
+    ctx: &ProcessContext,
+    network_name: Option<&str>,
+    container: Option<&ContainerSelector>,
+) -> Result<String> {
+    let network = network_name.and_then(|network_name| ctx.network_map.get(network_name));
+    let (network_name, network) = match (network_name, network, container) {
+        (None, _, Some(container)) => return Ok(format!("container `{}`", container)),
+        (None, _, None) => return Ok("all networks".to_owned()),
+        (Some(network_name), None, _) => {
+            return Ok(format!("network `{}` (not found)", network_name));
+        }
+        (Some(network_name), Some(network), _) => (network_name, network),
+    };
+    let container = match container {
+        Some(container) => container,
+        None => {
+            return Ok(format!(
+                "network `{}` to bridge {}",
+                network_name,
+                get_bridge_name(&network.id)?
+            ));
+        }
+    };
 
-            let bridge_name = get_bridge_name(&network.Id)?;
-            trace!(ctx.logger, "Got bridge name";
-                   o!("network_name" => &network.Name,
-                      "bridge_name" => &bridge_name));
+    let container_name = ctx.resolve_container(container, network_name)?;
+    let container_description = match (container, &container_name) {
+        (ContainerSelector::Alias(alias), Some(container_name)) => {
+            format!("container `{}` (alias `{}`)", container_name, alias)
+        }
+        _ => format!("container `{}`", container),
+    };
+    Ok(match get_network_for_container(ctx, container, network)? {
+        Some(endpoint) => format!(
+            "{} on network `{}` to {}",
+            container_description,
+            network_name,
+            endpoint.ipv4_addresses().join(", ")
+        ),
+        None => format!(
+            "{} on network `{}` (not attached)",
+            container_description, network_name
+        ),
+    })
+}
 
-            nft_forward_rule.out_interface(&bridge_name);
+/// Explain the rules generated for the rules of a section of the configuration.
+///
+/// The rules are returned as they are generated for the section, i.e. annotated and tagged, such
+/// that they can be matched against the rules of the rule set.
+fn explain_section<T: Explain>(
+    ctx: &ProcessContext,
+    section: Section,
+    rules: &[T],
+    counters: bool,
+) -> Result<Vec<(String, Explanation)>> {
+    let mut explained = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        let generated = vec![rule.clone()].process(ctx)?.unwrap_or_default();
+        let generated = finish_section_rules(section, generated, counters);
+
+        // Determine the variants of the rule that were processed, together with the number of
+        // rules each of them generated.
+        let wildcard = rule
+            .endpoints()
+            .iter()
+            .any(|(network, _)| *network == Some(WILDCARD_NETWORK));
+        let mut variants = Vec::new();
+        if wildcard {
+            let containers = rule
+                .endpoints()
+                .into_iter()
+                .filter_map(|(_, container)| container)
+                .collect::<Vec<_>>();
+            for network in get_wildcard_networks(ctx, &containers)? {
+                let expansion = format!("network `{}` expanded to `{}`", WILDCARD_NETWORK, network);
+                if let Some(variant) = rule.expand_network(network) {
+                    let count = variant.process(ctx)?.map_or(0, |rules| rules.len());
+                    variants.push((Some(expansion), variant, count));
+                }
+            }
+        }
+        if variants.iter().map(|(_, _, count)| count).sum::<usize>() != generated.len() {
+            variants = vec![(None, rule.clone(), generated.len())];
+        }
 
-            if let Some(dst_network) = get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                &self.dst_container,
-                &network.Id,
-            )? {
-                trace!(ctx.logger, "Got destination network";
-                       o!("network_name" => &network.Name,
-                          "dst_network" => format!("{:?}", dst_network)));
+        let mut generated = generated.into_iter();
+        for (expansion, variant, count) in variants {
+            let resolved = variant
+                .endpoints()
+                .into_iter()
+                .map(|(network, container)| describe_endpoint(ctx, network, container))
+                .collect::<Result<Vec<_>>>()?;
+            let explanation = Explanation {
+                section: Some(section),
+                rule: Some(index + 1),
+                provenance: rule.provenance().cloned(),
+                expansion,
+                resolved,
+                ..Default::default()
+            };
+            for generated_rule in generated.by_ref().take(count) {
+                explained.push((generated_rule, explanation.clone()));
+            }
+        }
+    }
 
-                nft_forward_rule.destination_address(
-                    dst_network
-                        .IPv4Address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                );
+    Ok(explained)
+}
 
-                let destination_port = match expose_port.container_port {
-                    Some(destination_port) => destination_port.to_string(),
-                    None => expose_port.host_port.to_string(),
-                };
-                nft_forward_rule.destination_port(&destination_port);
-                nft_dnat_rule.destination_port(&destination_port);
-                nft_dnat_rule.dnat(&format!(
-                    "{}:{}",
-                    dst_network
-                        .IPv4Address
-                        .split('/')
-                        .next()
-                        .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                    destination_port
-                ));
-                nft_mark_rule.destination_port(&destination_port);
-            // TODO: correct IPv6 handling would include actually using IPv6-addresses.
-            // While the code below is correct, the postrouting did not work and I was unable to
-            // actually get traffic from an IPv6-enabled container back.
-            // if !dst_network.IPv6Address.is_empty() {
-            //     nft_mark_rule.dnat(&format!(
-            //         "{}:{}",
-            //         dst_network.IPv6Address
-            //         .split('/')
-            //         .next()
-            //         .ok_or_else(|| format_err!("Invalid IPv6 address"))?,
-            //     destination_port));
-            // }
-            } else {
-                // Network for container has to exist
-                return Ok(None);
-            }
+fn explain_ruleset(dfw: &DFW, ctx: &ProcessContext) -> Result<Vec<(String, Explanation)>> {
+    let ruleset = generate_ruleset(dfw, ctx)?;
+    let counters = dfw
+        .runtime
+        .as_ref()
+        .is_some_and(|runtime| runtime.metrics_address.is_some());
+
+    let mut explained = ruleset
+        .preamble
+        .iter()
+        .map(|rule| (rule.clone(), Explanation::default()))
+        .collect::<Vec<_>>();
+    let mut rule_ids = ruleset
+        .rule_ids()
+        .into_iter()
+        .map(|(rule_id, _, _)| rule_id);
+    for (section, rules) in &ruleset.sections {
+        let section_explained = match section {
+            Section::ContainerToContainer => explain_section(
+                ctx,
+                *section,
+                dfw.container_to_container
+                    .as_ref()
+                    .and_then(|section| section.rules.as_deref())
+                    .unwrap_or_default(),
+                counters,
+            )?,
+            Section::ContainerToWiderWorld => explain_section(
+                ctx,
+                *section,
+                dfw.container_to_wider_world
+                    .as_ref()
+                    .and_then(|section| section.rules.as_deref())
+                    .unwrap_or_default(),
+                counters,
+            )?,
+            Section::ContainerToHost => explain_section(
+                ctx,
+                *section,
+                dfw.container_to_host
+                    .as_ref()
+                    .and_then(|section| section.rules.as_deref())
+                    .unwrap_or_default(),
+                counters,
+            )?,
+            Section::WiderWorldToContainer => explain_section(
+                ctx,
+                *section,
+                dfw.wider_world_to_container
+                    .as_ref()
+                    .and_then(|section| section.rules.as_deref())
+                    .unwrap_or_default(),
+                counters,
+            )?,
+            Section::ContainerDNAT => explain_section(
+                ctx,
+                *section,
+                dfw.container_dnat
+                    .as_ref()
+                    .and_then(|section| section.rules.as_deref())
+                    .unwrap_or_default(),
+                counters,
+            )?,
+            _ => Vec::new(),
+        };
 
-            // Set correct protocol
-            nft_forward_rule.protocol(&expose_port.family);
-            nft_dnat_rule.protocol(&expose_port.family);
-            nft_mark_rule.protocol(&expose_port.family);
+        // Rules that were not generated for a rule of the configuration on their own, e.g. the
+        // default policy of the section, are attributed to the section itself.
+        let mut origins: Map<String, Vec<Explanation>> = Map::new();
+        for (rule, explanation) in section_explained.into_iter().rev() {
+            origins.entry(rule).or_default().push(explanation);
+        }
+        // Rules moved into a chain per network are explained by the rule of the base chain, which
+        // may have been copied into multiple chains.
+        for rule in rules {
+            let explanation = origins
+                .get_mut(&base_chain_command(&strip_rule_id(rule)))
+                .and_then(|explanations| match explanations.len() {
+                    1 => explanations.last().cloned(),
+                    _ => explanations.pop(),
+                })
+                .unwrap_or_else(|| Explanation {
+                    section: Some(*section),
+                    ..Default::default()
+                });
+            explained.push((
+                rule.clone(),
+                Explanation {
+                    rule_id: rule_ids.next(),
+                    ..explanation
+                },
+            ));
+        }
+    }
 
-            nft_forward_rule.verdict(RuleVerdict::Accept);
+    Ok(explained)
+}
 
-            // Try to build the rule without the out_interface defined to see if any of the
-            // other mandatory fields has been populated.
-            debug!(ctx.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", nft_forward_rule)));
-            nft_forward_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
-            debug!(ctx.logger, "build rule to verify contents";
-                   o!("args" => format!("{:?}", nft_dnat_rule)));
-            nft_dnat_rule.build()?; // todo: maybe add a `verify` method to `rule`
-            debug!(ctx.logger, "Build rule to verify contents";
-                   o!("args" => format!("{:?}", nft_mark_rule)));
-            nft_mark_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
+/// Generate the commands setting up the DFW tables and their base chains for the configuration,
+/// see [`table_preamble`](fn.table_preamble.html).
+fn base_preamble(dfw: &DFW) -> Vec<String> {
+    let drop_invalid = dfw
+        .defaults
+        .as_ref()
+        .is_none_or(|defaults| defaults.drop_invalid);
+    let dnat_accept_shortcut = dfw
+        .defaults
+        .as_ref()
+        .is_none_or(|defaults| defaults.dnat_accept_shortcut);
+    let conntrack_zones = dfw
+        .defaults
+        .as_ref()
+        .is_some_and(|defaults| defaults.conntrack_zones);
+    let notrack = dfw
+        .wider_world_to_container
+        .as_ref()
+        .and_then(|wwtc| wwtc.rules.as_ref())
+        .is_some_and(|rules| rules.iter().any(|rule| rule.notrack));
+    let ct_timeout = dfw
+        .wider_world_to_container
+        .as_ref()
+        .and_then(|wwtc| wwtc.rules.as_ref())
+        .is_some_and(|rules| rules.iter().any(|rule| rule.ct_timeout.is_some()));
+    let base_chains = dfw
+        .defaults
+        .as_ref()
+        .map(|defaults| defaults.base_chains.clone())
+        .unwrap_or_default();
+
+    table_preamble(
+        drop_invalid,
+        dnat_accept_shortcut,
+        conntrack_zones || notrack || ct_timeout,
+        &base_chains,
+    )
+}
 
-            if let Some(ref external_network_interface) = self.external_network_interface {
-                trace!(ctx.logger, "Rule has specific external network interface";
-                       o!("external_network_interface" => external_network_interface));
+fn generate_ruleset(dfw: &DFW, ctx: &ProcessContext) -> Result<RuleSet> {
+    info!(ctx.logger, "Starting processing";
+          o!("started_processing_at" => format!("{}", time::OffsetDateTime::now().format("%FT%T%z"))));
+    ctx.generated_rules.set(0);
+    let counters = dfw
+        .runtime
+        .as_ref()
+        .is_some_and(|runtime| runtime.metrics_address.is_some());
+    let log = dfw
+        .container_to_container
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten())
+        .any(|rule| rule.log)
+        || dfw
+            .container_to_wider_world
+            .iter()
+            .flat_map(|section| section.rules.iter().flatten())
+            .any(|rule| rule.log)
+        || dfw
+            .container_to_host
+            .iter()
+            .flat_map(|section| section.rules.iter().flatten())
+            .any(|rule| rule.log)
+        || dfw
+            .wider_world_to_container
+            .as_ref()
+            .is_some_and(|section| section.log_unmatched.is_some());
+    let network_chains = dfw
+        .defaults
+        .as_ref()
+        .is_some_and(|defaults| defaults.network_chains);
+    let external_interface_chains = dfw
+        .defaults
+        .as_ref()
+        .is_some_and(|defaults| defaults.external_interface_chains);
+    let order = section_order(dfw).map_err(|problem| format_err!("{}", problem))?;
+    let mut parts: Vec<(Section, &dyn Process)> = vec![
+        (Section::Initialization, &dfw.initialization),
+        (Section::Defaults, &dfw.defaults),
+        (Section::ContainerToContainer, &dfw.container_to_container),
+        (Section::Dns, &dfw.dns),
+        (
+            Section::ContainerToWiderWorld,
+            &dfw.container_to_wider_world,
+        ),
+        (Section::ContainerToHost, &dfw.container_to_host),
+        (
+            Section::WiderWorldToContainer,
+            &dfw.wider_world_to_container,
+        ),
+        (Section::ContainerDNAT, &dfw.container_dnat),
+    ];
+    parts.sort_by_key(|(section, _)| order.iter().position(|other| other == section));
+    trace!(ctx.logger, "Determined order of sections";
+           o!("order" => format!("{:?}", order)));
+    let mut sections = Vec::new();
+    for (section, part) in parts {
+        if !ctx.sections.contains(section) {
+            trace!(ctx.logger, "Skip section, not selected for processing";
+                   o!("section" => section.to_string()));
+            continue;
+        }
+        let sub_rules = part.process(ctx)?.unwrap_or_default();
+        sections.push((section, finish_section_rules(section, sub_rules, counters)));
+    }
 
-                nft_forward_rule.in_interface(external_network_interface);
-                nft_dnat_rule.in_interface(external_network_interface);
-                nft_mark_rule.in_interface(external_network_interface);
-            } else if let Some(ref primary_external_network_interface) =
-                ctx.primary_external_network_interface
-            {
-                trace!(ctx.logger, "Rule uses primary external network interface";
-                       o!("external_network_interface" => primary_external_network_interface));
+    info!(ctx.logger, "Finished processing";
+         o!("finished_processing_at" => format!("{}", time::OffsetDateTime::now().format("%FT%T%z"))));
+
+    let mut preamble = base_preamble(dfw);
+    let ct_timeouts = dfw
+        .defaults
+        .as_ref()
+        .and_then(|defaults| defaults.ct_timeouts.as_ref());
+    for (name, policy) in ct_timeouts.into_iter().flatten() {
+        let (protocol, timeouts) =
+            ct_timeout_policy(name, policy).map_err(|problem| format_err!("{}", problem))?;
+        preamble.push(nftables::add_ct_timeout(
+            Family::Inet,
+            "dfw",
+            name,
+            protocol,
+            &timeouts,
+        ));
+    }
+    if log {
+        let log_rate = dfw
+            .defaults
+            .as_ref()
+            .map_or(DEFAULT_LOG_RATE, |defaults| defaults.log_rate.as_str());
+        preamble.push(nftables::add_limit(
+            Family::Inet,
+            "dfw",
+            LOG_LIMIT,
+            log_rate,
+        ));
+    }
+    if network_chains {
+        preamble.append(&mut split_network_chains(&mut sections));
+    } else if external_interface_chains {
+        preamble.append(&mut split_external_interface_chains(
+            &mut sections,
+            &ctx.external_network_interfaces
+                .as_ref()
+                .map(FamilyInterfaces::all)
+                .unwrap_or_default(),
+        ));
+    }
 
-                nft_forward_rule.in_interface(primary_external_network_interface);
-                nft_dnat_rule.in_interface(primary_external_network_interface);
-                nft_mark_rule.in_interface(primary_external_network_interface);
-            } else {
-                // The DNAT rule requires the external interface
-                return Ok(None);
+    let ruleset = RuleSet {
+        preamble,
+        sections,
+        resolved_names: ctx.resolved_names(),
+    };
+    let ruleset = match dfw.defaults.as_ref().map(|defaults| defaults.table_family) {
+        Some(TableFamily::Split) => split_table_family(ruleset),
+        _ => ruleset,
+    };
+    if counters {
+        Ok(identify_rules(ruleset))
+    } else {
+        Ok(ruleset)
+    }
+}
+
+/// Move the commands of the `inet` table into the `ip` and `ip6` tables, see
+/// [`TableFamily::Split`](../types/enum.TableFamily.html#variant.Split).
+///
+/// Rules and sets specific to one address family are only moved into the table of that family,
+/// everything else is duplicated into both tables. Since the `ip` and `ip6` tables are set up by
+/// the preamble already, they are only added and flushed once, before any of their chains are
+/// added. The `inet` table is deleted, such that no rules of a previous run remain.
+fn split_table_family(ruleset: RuleSet) -> RuleSet {
+    let mut set_families: Map<String, Vec<Family>> = Map::new();
+    let mut table_commands = BTreeSet::new();
+    let mut split = |commands: Vec<String>| -> Vec<String> {
+        let mut split_commands = Vec::with_capacity(commands.len() * 2);
+        for command in commands {
+            // Conntrack timeouts only apply to IPv4, see `nftables::add_ct_timeout`
+            if let Some(rest) = command.strip_prefix("add ct timeout inet dfw ") {
+                split_commands.push(format!("add ct timeout {} dfw {}", Family::Ip, rest));
+                continue;
             }
+            let words = command.splitn(6, ' ').collect::<Vec<_>>();
+            let (verb, object, name, rest) = match words[..] {
+                [_, _, family, "dfw"] if family != "inet" => {
+                    if table_commands.insert(command.clone()) {
+                        split_commands.push(command);
+                    }
+                    continue;
+                }
+                [verb, object, "inet", "dfw"] => (verb, object, None, None),
+                [verb, object, "inet", "dfw", name] => (verb, object, Some(name), None),
+                [verb, object, "inet", "dfw", name, rest] => (verb, object, Some(name), Some(rest)),
+                _ => {
+                    split_commands.push(command);
+                    continue;
+                }
+            };
 
-            // If source CIDRs have been specified, create the FORWARD-rules as required to
-            // restrict the traffic as intended.
-            if let Some(source_cidrs_v4) = &self.source_cidr_v4 {
-                self.apply_source_cidrs_v4(
-                    ctx,
-                    &mut rules,
-                    source_cidrs_v4,
-                    nft_forward_rule.clone(),
-                    nft_dnat_rule.clone(),
-                )?;
+            let families = match (object, name, rest) {
+                ("rule", _, Some(rule)) => rule_families(rule),
+                ("set", Some(set), _) if verb == "add" => {
+                    let families = if command.contains("ipv4_addr") {
+                        vec![Family::Ip]
+                    } else if command.contains("ipv6_addr") {
+                        vec![Family::Ip6]
+                    } else {
+                        vec![Family::Ip, Family::Ip6]
+                    };
+                    set_families.insert(set.to_owned(), families.clone());
+                    families
+                }
+                ("set", Some(set), _) | ("element", Some(set), _) => set_families
+                    .get(set)
+                    .cloned()
+                    .unwrap_or_else(|| vec![Family::Ip, Family::Ip6]),
+                _ => vec![Family::Ip, Family::Ip6],
+            };
+            // The chain running before conntrack would collide with the NAT chain
+            let name = match (object, name) {
+                ("chain", Some("prerouting")) | ("rule", Some("prerouting")) => {
+                    Some("raw_prerouting")
+                }
+                _ => name,
+            };
+            for family in families {
+                let split_command = match (name, rest) {
+                    (Some(name), Some(rest)) => {
+                        format!("{} {} {} dfw {} {}", verb, object, family, name, rest)
+                    }
+                    (Some(name), None) => format!("{} {} {} dfw {}", verb, object, family, name),
+                    _ => format!("{} {} {} dfw", verb, object, family),
+                };
+                if object != "table" || table_commands.insert(split_command.clone()) {
+                    split_commands.push(split_command);
+                }
             }
-            if let Some(source_cidrs_v6) = &self.source_cidr_v6 {
-                self.apply_source_cidrs_v6(
-                    ctx,
-                    &mut rules,
-                    source_cidrs_v6,
-                    nft_mark_rule.clone(),
-                )?;
+        }
+        split_commands
+    };
+
+    let mut preamble = vec![
+        nftables::add_table(Family::Inet, "dfw"),
+        nftables::delete_table(Family::Inet, "dfw"),
+    ];
+    preamble.append(&mut split(ruleset.preamble));
+    let sections = ruleset
+        .sections
+        .into_iter()
+        .map(|(section, rules)| (section, split(rules)))
+        .collect();
+
+    RuleSet {
+        preamble,
+        sections,
+        resolved_names: ruleset.resolved_names,
+    }
+}
+
+/// Get the families of the tables a rule of the `inet` table is moved into, depending on the
+/// address family it matches on.
+fn rule_families(rule: &str) -> Vec<Family> {
+    // Comments and log prefixes must not be mistaken for matches
+    let matches = rule.split('"').step_by(2).collect::<Vec<_>>().join(" ");
+    let words = matches.split_whitespace().collect::<Vec<_>>();
+    let v4 = words.contains(&"ip") || words.contains(&"ipv4");
+    let v6 = words.contains(&"ip6") || words.contains(&"ipv6");
+    match (v4, v6) {
+        (true, false) => vec![Family::Ip],
+        (false, true) => vec![Family::Ip6],
+        _ => vec![Family::Ip, Family::Ip6],
+    }
+}
+
+/// Move the rules of the input- and forward-chains that only match packets received on a single
+/// interface into a chain per interface, returning the commands adding these chains and the rules
+/// dispatching packets to them.
+///
+/// The dispatching rules `goto` the chain of the interface, such that the policy of the base chain
+/// applies if no rule of the interface chain decides the fate of the packet. Rules matching packets
+/// of any interface stay in the base chain and are copied into every interface chain, preserving
+/// the order all rules applying to a packet are evaluated in.
+fn split_network_chains(sections: &mut [(Section, Vec<String>)]) -> Vec<String> {
+    let mut dispatch = Vec::new();
+    for base_chain in &["input", "forward"] {
+        let interfaces = sections
+            .iter()
+            .flat_map(|(_, rules)| rules.iter())
+            .filter_map(|rule| match split_rule_command(rule) {
+                Some(("add", "inet", "dfw", chain, rule)) if chain == *base_chain => {
+                    single_interface(rule, "iifname")
+                }
+                _ => None,
+            })
+            .map(str::to_owned)
+            .collect::<BTreeSet<_>>();
+        if interfaces.is_empty() {
+            continue;
+        }
+
+        for interface in &interfaces {
+            let chain = network_chain_name(base_chain, interface);
+            dispatch.push(nftables::add_chain(Family::Inet, "dfw", &chain));
+            dispatch.push(nftables::add_rule(
+                Family::Inet,
+                "dfw",
+                base_chain,
+                &format!("meta iifname {} goto {}", interface, chain),
+            ));
+        }
+
+        for (_, rules) in sections.iter_mut() {
+            let mut split_rules = Vec::with_capacity(rules.len());
+            for command in rules.drain(..) {
+                let rule = match split_rule_command(&command) {
+                    Some(("add", "inet", "dfw", chain, rule)) if chain == *base_chain => rule,
+                    _ => {
+                        split_rules.push(command);
+                        continue;
+                    }
+                };
+                match single_interface(rule, "iifname") {
+                    Some(interface) => split_rules.push(nftables::add_rule(
+                        Family::Inet,
+                        "dfw",
+                        &network_chain_name(base_chain, interface),
+                        rule,
+                    )),
+                    None => {
+                        for interface in &interfaces {
+                            split_rules.push(nftables::add_rule(
+                                Family::Inet,
+                                "dfw",
+                                &network_chain_name(base_chain, interface),
+                                rule,
+                            ));
+                        }
+                        split_rules.push(command);
+                    }
+                }
             }
+            *rules = split_rules;
+        }
+    }
 
-            // If no source CIDRs were specified, we create the default rules that allow all
-            // connections from any IP.
-            if self.source_cidr_v4.is_none() && self.source_cidr_v6.is_none() {
-                let forward_rule = nft_forward_rule.build()?;
-                debug!(ctx.logger, "Add forward rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &forward_rule));
-                let dnat_rule = nft_dnat_rule.build()?;
-                debug!(ctx.logger, "Add DNAT rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &dnat_rule));
-                let mark_rule = nft_mark_rule.build()?;
-                debug!(ctx.logger, "Add mark rule";
-                       o!("part" => "wider_world_to_container",
-                          "rule" => &mark_rule));
-                // Apply the rule
-                rules.push(nftables::add_rule(
+    dispatch
+}
+
+/// Move the rules of the input- and forward-chains that only match packets of a single external
+/// network interface into a chain per external network interface, returning the commands adding
+/// these chains and the rules dispatching packets to them.
+///
+/// Packets received on an external interface are dispatched to its chain first, forwarded packets
+/// leaving through an external interface afterwards. The chain of an interface thus holds every
+/// rule that can match packets received on the interface, and every rule of the forward-chain that
+/// can match packets leaving through it which weren't received on another external interface.
+/// Rules that can match packets that aren't dispatched stay in the base chain, such that the order
+/// all rules applying to a packet are evaluated in is preserved like it is by
+/// [`split_network_chains`](fn.split_network_chains.html).
+fn split_external_interface_chains(
+    sections: &mut [(Section, Vec<String>)],
+    external_network_interfaces: &[String],
+) -> Vec<String> {
+    let mut dispatch = Vec::new();
+    if external_network_interfaces.is_empty() {
+        return dispatch;
+    }
+    let is_external = |interface: Option<&str>| {
+        interface.is_some_and(|interface| {
+            external_network_interfaces
+                .iter()
+                .any(|external_network_interface| external_network_interface == interface)
+        })
+    };
+
+    for base_chain in &["input", "forward"] {
+        // Only forwarded packets leave through an interface.
+        let outgoing = *base_chain == "forward";
+
+        for interface in external_network_interfaces {
+            let chain = network_chain_name(base_chain, interface);
+            dispatch.push(nftables::add_chain(Family::Inet, "dfw", &chain));
+            dispatch.push(nftables::add_rule(
+                Family::Inet,
+                "dfw",
+                base_chain,
+                &format!("meta iifname {} goto {}", interface, chain),
+            ));
+        }
+        if outgoing {
+            for interface in external_network_interfaces {
+                dispatch.push(nftables::add_rule(
                     Family::Inet,
                     "dfw",
-                    "forward",
-                    &forward_rule,
-                ));
-                rules.push(nftables::add_rule(
-                    Family::Ip,
-                    "dfw",
-                    "prerouting",
-                    &dnat_rule,
-                ));
-                rules.push(nftables::add_rule(
-                    Family::Ip6,
-                    "dfw",
-                    "prerouting",
-                    &mark_rule,
+                    base_chain,
+                    &format!(
+                        "meta oifname {} goto {}",
+                        interface,
+                        network_chain_name(base_chain, interface)
+                    ),
                 ));
             }
         }
 
-        Ok(Some(rules))
+        for (_, rules) in sections.iter_mut() {
+            let mut split_rules = Vec::with_capacity(rules.len());
+            for command in rules.drain(..) {
+                let rule = match split_rule_command(&command) {
+                    Some(("add", "inet", "dfw", chain, rule)) if chain == *base_chain => rule,
+                    _ => {
+                        split_rules.push(command);
+                        continue;
+                    }
+                };
+                let input = single_interface(rule, "iifname");
+                let output = single_interface(rule, "oifname");
+                for interface in external_network_interfaces {
+                    let received = input.is_none_or(|input| input == interface);
+                    let sent = outgoing
+                        && !is_external(input)
+                        && output.is_none_or(|output| output == interface);
+                    if received || sent {
+                        split_rules.push(nftables::add_rule(
+                            Family::Inet,
+                            "dfw",
+                            &network_chain_name(base_chain, interface),
+                            rule,
+                        ));
+                    }
+                }
+                if !(is_external(input) || outgoing && is_external(output)) {
+                    split_rules.push(command);
+                }
+            }
+            *rules = split_rules;
+        }
+    }
+
+    dispatch
+}
+
+/// Get the interface a rule matches the input (`iifname`) or output interface (`oifname`)
+/// against, if it only matches packets of this single interface.
+fn single_interface<'a>(rule: &'a str, key: &str) -> Option<&'a str> {
+    let tokens = simulate::tokenize(rule);
+    let mut interfaces = tokens
+        .windows(2)
+        .filter(|tokens| tokens[0] == key)
+        .map(|tokens| tokens[1]);
+    let interface = interfaces.next()?;
+    let is_name = interface
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if interfaces.next().is_some() || !is_name {
+        return None;
+    }
+    Some(interface)
+}
+
+/// Get the name of the chain holding the rules of the base chain for the given interface.
+fn network_chain_name(base_chain: &str, interface: &str) -> String {
+    format!("{}_{}", base_chain, interface)
+}
+
+/// Get the command adding the rule to the base chain, if the command adds it to the chain of a
+/// network or an external network interface (see
+/// [`split_network_chains`](fn.split_network_chains.html) and
+/// [`split_external_interface_chains`](fn.split_external_interface_chains.html)).
+fn base_chain_command(command: &str) -> String {
+    match split_rule_command(command) {
+        Some(("add", "inet", "dfw", chain, rule)) => {
+            match ["input", "forward"]
+                .iter()
+                .find(|base_chain| chain.starts_with(&format!("{}_", base_chain)))
+            {
+                Some(base_chain) => nftables::add_rule(Family::Inet, "dfw", base_chain, rule),
+                None => command.to_owned(),
+            }
+        }
+        _ => command.to_owned(),
+    }
+}
+
+/// Generate the commands setting up the DFW tables and their base chains.
+///
+/// The input- and forward-chains start with the stateful preamble: packets in the conntrack state
+/// `invalid` are dropped (if requested), packets of established or related connections are accepted
+/// right away. The forward-chain additionally marks the established connections translated by
+/// DNAT before accepting them (if requested).
+///
+/// The preamble precedes the commands of all sections, including the `initialization` section.
+/// Together with the ruleset being applied in a single transaction, this ensures that connections
+/// established before the ruleset is rebuilt continue to be accepted.
+///
+/// If conntrack zones are used or traffic is excluded from connection tracking, a
+/// prerouting-chain running before conntrack is added, which assigns the zones or marks the traffic
+/// as untracked.
+///
+/// The priorities and policies of the base chains can be overridden per hook, see
+/// [`BaseChains`](../types/struct.BaseChains.html).
+fn table_preamble(
+    drop_invalid: bool,
+    dnat_accept_shortcut: bool,
+    raw_prerouting: bool,
+    base_chains: &BaseChains,
+) -> Vec<String> {
+    let mut rules = vec![
+        nftables::add_table(Family::Inet, "dfw"),
+        nftables::flush_table(Family::Inet, "dfw"),
+    ];
+    if raw_prerouting {
+        rules.push(nftables::add_base_chain(
+            Family::Inet,
+            "dfw",
+            "prerouting",
+            Type::Filter,
+            Hook::Prerouting,
+            NF_PRIORITY_INET_RAW_PREROUTING_DFW,
+        ));
+    }
+    for (chain, hook, base_chain) in &[
+        ("input", Hook::Input, base_chains.input),
+        ("forward", Hook::Forward, base_chains.forward),
+    ] {
+        rules.append(&mut add_base_chain(
+            Family::Inet,
+            chain,
+            Type::Filter,
+            *hook,
+            NF_PRIORITY_INET_FILTER_ANY_DFW,
+            *base_chain,
+        ));
+        if drop_invalid {
+            rules.push(nftables::add_rule(
+                Family::Inet,
+                "dfw",
+                chain,
+                "ct state invalid drop",
+            ));
+        }
+        if dnat_accept_shortcut && *chain == "forward" {
+            rules.push(nftables::add_rule(
+                Family::Inet,
+                "dfw",
+                chain,
+                &format!(
+                    "ct state {{ related, established }} ct status dnat meta mark set {} accept",
+                    DFW_MARK
+                ),
+            ));
+        }
+        rules.push(nftables::add_rule(
+            Family::Inet,
+            "dfw",
+            chain,
+            "ct state { related, established } accept",
+        ));
+    }
+    for (family, prerouting_priority, postrouting_priority) in &[
+        (
+            Family::Ip,
+            NF_PRIORITY_IP_NAT_PREROUTING_DFW,
+            NF_PRIORITY_IP_NAT_POSTROUTING_DFW,
+        ),
+        (
+            Family::Ip6,
+            NF_PRIORITY_IP6_NAT_PREROUTING_DFW,
+            NF_PRIORITY_IP6_NAT_POSTROUTING_DFW,
+        ),
+    ] {
+        rules.push(nftables::add_table(*family, "dfw"));
+        rules.push(nftables::flush_table(*family, "dfw"));
+        rules.append(&mut add_base_chain(
+            *family,
+            "prerouting",
+            Type::Nat,
+            Hook::Prerouting,
+            *prerouting_priority,
+            base_chains.prerouting,
+        ));
+        rules.append(&mut add_base_chain(
+            *family,
+            "postrouting",
+            Type::Nat,
+            Hook::Postrouting,
+            *postrouting_priority,
+            base_chains.postrouting,
+        ));
+    }
+
+    rules
+}
+
+/// Generate the commands adding a base chain to the DFW table of the family, applying the override
+/// of its priority and policy (if any).
+fn add_base_chain(
+    family: Family,
+    chain: &str,
+    r#type: Type,
+    hook: Hook,
+    priority: i16,
+    base_chain: Option<BaseChain>,
+) -> Vec<String> {
+    let base_chain = base_chain.unwrap_or_default();
+    let mut rules = vec![nftables::add_base_chain(
+        family,
+        "dfw",
+        chain,
+        r#type,
+        hook,
+        base_chain.priority.unwrap_or(priority),
+    )];
+    if let Some(policy) = base_chain.policy {
+        rules.push(nftables::set_chain_policy(family, "dfw", chain, policy));
+    }
+    rules
+}
+
+/// Tables and chains managed by DFW, e.g. for other tooling on the host to avoid modifying them.
+///
+/// See [`managed_objects`](fn.managed_objects.html) and
+/// [`RuleSet::managed_objects`](struct.RuleSet.html#method.managed_objects).
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ManagedObjects {
+    /// Name of the tables managed by DFW, which exist in the families `inet`, `ip` and `ip6`.
+    pub table: String,
+    /// Chains within the tables managed by DFW, in the order they are created in.
+    pub chains: Vec<ManagedChain>,
+}
+
+/// A chain within one of the tables managed by DFW, see
+/// [`ManagedObjects`](struct.ManagedObjects.html).
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ManagedChain {
+    /// Family of the table the chain is part of, e.g. `inet`.
+    pub family: String,
+    /// Name of the chain.
+    pub name: String,
+}
+
+/// Get the tables and base chains DFW creates for the given configuration, without generating any
+/// rules. They are taken from the commands setting up the DFW tables that every generated rule set
+/// starts with.
+///
+/// DFW can create additional chains depending on the rules: a chain per network if
+/// [`Defaults.network_chains`](../types/struct.Defaults.html#structfield.network_chains) is
+/// enabled and a chain per external network interface if
+/// [`Defaults.external_interface_chains`](../types/struct.Defaults.html#structfield.external_interface_chains)
+/// is enabled. Use [`RuleSet::managed_objects`](struct.RuleSet.html#method.managed_objects) to get
+/// all chains of a generated rule set.
+pub fn managed_objects(dfw: &DFW) -> ManagedObjects {
+    let ruleset = RuleSet {
+        preamble: base_preamble(dfw),
+        ..Default::default()
+    };
+    match dfw.defaults.as_ref().map(|defaults| defaults.table_family) {
+        Some(TableFamily::Split) => split_table_family(ruleset),
+        _ => ruleset,
+    }
+    .managed_objects()
+}
+
+/// Process the rules of a section, counting the rules generated for each of them towards
+/// [`Defaults.max_generated_rules`](../types/struct.Defaults.html#structfield.max_generated_rules).
+///
+/// The rules are otherwise processed like a `Vec` of them, expansions of a single rule (e.g. of the
+/// wildcard network) are thus counted towards the rule they were expanded from.
+fn process_section_rules<T: Process>(
+    ctx: &ProcessContext,
+    section: Section,
+    rules: &Option<Vec<T>>,
+) -> Result<Option<Vec<String>>> {
+    let rules = match rules {
+        Some(rules) => rules,
+        None => return Ok(None),
+    };
+
+    let mut generated = Vec::new();
+    for (index, rule) in rules.iter().enumerate() {
+        if let Some(mut sub_rules) = rule.process(ctx)? {
+            ctx.count_generated_rules(section, index, rule.provenance(), sub_rules.len())?;
+            if let Some(provenance) = rule.provenance().filter(|_| ctx.annotate_rules()) {
+                sub_rules = annotate_provenance(provenance, sub_rules);
+            }
+            generated.append(&mut sub_rules);
+        }
+    }
+
+    Ok(Some(generated))
+}
+
+/// Check if the given section is enabled, logging if it is skipped.
+fn section_enabled(ctx: &ProcessContext, part: &str, enabled: Option<bool>) -> bool {
+    let enabled = enabled.unwrap_or(true);
+    if !enabled {
+        debug!(ctx.logger, "Skip section, it is disabled";
+               o!("part" => part));
+    }
+
+    enabled
+}
+
+impl Process for Initialization {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !section_enabled(ctx, "initialization", self.enabled) {
+            return Ok(None);
+        }
+
+        Ok(self.rules.clone())
+    }
+}
+
+impl Process for Defaults {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        let mut rules = Vec::new();
+
+        // Hook into other chains if requested
+        if let Some(ref custom_tables) = self.custom_tables {
+            for custom_table in custom_tables {
+                for chain in &custom_table.chains {
+                    let mut additional_rules = Vec::new();
+
+                    // This is a small helper macro to de-duplicate the "add if it doesn't already
+                    // exist" logic. It essentially generates a marker which uniquely identifies a
+                    // rule, checks if that marker is already in the current ruleset, and if not,
+                    // adds it. This enables us to not duplicate rules that are outside of our table
+                    // where flushing is not an option.
+                    macro_rules! m {
+                        ($mark:expr, $rule:expr) => {
+                            let marker =
+                                generate_marker(&["defaults", &custom_table.name, chain, $mark]);
+                            if !ctx.marker_in_current_ruleset(&marker) {
+                                additional_rules.push(nftables::insert_rule(
+                                    Family::Inet,
+                                    &custom_table.name,
+                                    chain,
+                                    &format!("{} comment \"{}\"", $rule, marker),
+                                    None,
+                                ));
+                            }
+                        };
+                    }
+                    // Handle `ct state invalid drop` rule
+                    if self.drop_invalid {
+                        m!("ct-state-invalid-drop", "ct state invalid drop");
+                    }
+                    // Handle `ct state { related, established } accept` rule
+                    m!(
+                        "ct-state-relatedestablished-accept",
+                        "ct state { related, established } accept"
+                    );
+                    // Handle `meta mark ... accept` rule
+                    m!(
+                        "meta-mark",
+                        &format!("meta mark and {} == {} accept", DFW_MARK, DFW_MARK)
+                    );
+
+                    // The rules above are not added at the end, but inserted at the top. We thus
+                    // reverse the order of the rules here to effectively retain the initial order
+                    // of the vec.
+                    additional_rules.reverse();
+                    rules.append(&mut additional_rules);
+                }
+            }
+        }
+
+        // Enforce policy for default Docker-bridge (usually docker0) to access host-resources
+        if let Some(bridge_network) = ctx.network_map.get("bridge") {
+            if let Some(bridge_name) = bridge_network.options.get("com.docker.network.bridge.name")
+            {
+                // Set policy for input-chain
+                rules.push(nftables::add_rule(
+                    Family::Inet,
+                    "dfw",
+                    "input",
+                    &format!(
+                        "meta iifname {} meta mark set {} {}",
+                        bridge_name, DFW_MARK, self.default_docker_bridge_to_host_policy,
+                    ),
+                ));
+
+                // Set policy for forward-chain, divided by the external network interfaces.
+                if let Some(ref external_network_interfaces) = ctx.external_network_interfaces {
+                    for (external_network_interface, family) in
+                        external_network_interfaces.with_families()
+                    {
+                        rules.push(nftables::add_rule(
+                            Family::Inet,
+                            "dfw",
+                            "forward",
+                            &format!(
+                                "meta iifname {} oifname {}{} meta mark set {} {}",
+                                bridge_name,
+                                external_network_interface,
+                                family
+                                    .map(|family| format!(" meta nfproto {}", family.nfproto()))
+                                    .unwrap_or_default(),
+                                DFW_MARK,
+                                self.default_docker_bridge_to_host_policy,
+                            ),
+                        ))
+                    }
+                }
+            }
+        }
+
+        // Track the connections originating from each network in a distinct conntrack zone. Only
+        // the zone of the original direction is set, replies to connections originating from
+        // elsewhere are thus still matched to their connection.
+        if self.conntrack_zones {
+            for (network, zone) in assign_conntrack_zones(ctx.network_map.values()) {
+                let bridge_name = match network.options.get("com.docker.network.bridge.name") {
+                    Some(bridge_name) => bridge_name.to_owned(),
+                    None => get_bridge_name(&network.id)?,
+                };
+                trace!(ctx.logger, "Assigned conntrack zone";
+                       o!("network_name" => &network.name,
+                          "bridge_name" => &bridge_name,
+                          "zone" => zone));
+                rules.push(nftables::add_rule(
+                    Family::Inet,
+                    "dfw",
+                    "prerouting",
+                    &format!("meta iifname {} ct original zone set {}", bridge_name, zone),
+                ));
+            }
+        }
+
+        // Configure postrouting
+        if let Some(ref external_network_interfaces) = ctx.external_network_interfaces {
+            // IPv6 traffic is only translated if requested, either through a static address or
+            // through `ipv6_masquerade`.
+            let (nat_v4, nat_v6) = match self.egress_nat {
+                EgressNat::Masquerade => ("masquerade".to_owned(), None),
+                EgressNat::Snat(IpAddr::V4(address)) => (format!("snat to {}", address), None),
+                EgressNat::Snat(IpAddr::V6(address)) => (
+                    "masquerade".to_owned(),
+                    Some(format!("snat to {}", address)),
+                ),
+            };
+            let nat_v6 =
+                nat_v6.or_else(|| Some("masquerade".to_owned()).filter(|_| self.ipv6_masquerade));
+            // Traffic to loopback, link-local and container networks must never be translated,
+            // otherwise routing between containers breaks.
+            let (excluded_v4, excluded_v6) = get_nat_excluded_subnets(ctx);
+            trace!(ctx.logger, "Got subnets excluded from NAT";
+                   o!("excluded_v4" => excluded_v4.join(","),
+                      "excluded_v6" => excluded_v6.join(",")));
+            // Older versions of nft can't negate sets, the subnets are then excluded through
+            // separate rules accepting the traffic before it reaches the NAT rule. Accepting a
+            // packet in the NAT chain ends the NAT processing without translating it.
+            let negated_sets = ctx.nft_supports(NftVersion::NEGATED_SETS);
+            if !negated_sets {
+                warn!(ctx.logger, "nft doesn't support negated sets, falling back to separate rules";
+                      o!("nft_version" => format!("{:?}", ctx.host_facts.nft_version),
+                         "required_nft_version" => NftVersion::NEGATED_SETS.to_string()));
+            }
+            let mut nat_families = vec![(Family::Ip, AddressFamily::V4, &excluded_v4, nat_v4)];
+            if let Some(nat_v6) = nat_v6 {
+                nat_families.push((Family::Ip6, AddressFamily::V6, &excluded_v6, nat_v6));
+            }
+            for external_network_interface in &external_network_interfaces.all() {
+                // Configure postrouting, only for the families the interface carries
+                for (family, address_family, excluded, nat) in &nat_families {
+                    if !external_network_interfaces
+                        .of(*address_family)
+                        .contains(external_network_interface)
+                    {
+                        continue;
+                    }
+                    if negated_sets {
+                        rules.push(nftables::add_rule(
+                            *family,
+                            "dfw",
+                            "postrouting",
+                            &format!(
+                                "meta oifname {} {} daddr != {{{}}} meta mark set {} {}",
+                                external_network_interface,
+                                family,
+                                excluded.join(","),
+                                DFW_MARK,
+                                nat,
+                            ),
+                        ));
+                        continue;
+                    }
+
+                    for subnet in excluded.iter() {
+                        rules.push(nftables::add_rule(
+                            *family,
+                            "dfw",
+                            "postrouting",
+                            &format!(
+                                "meta oifname {} {} daddr {} accept",
+                                external_network_interface, family, subnet,
+                            ),
+                        ));
+                    }
+                    rules.push(nftables::add_rule(
+                        *family,
+                        "dfw",
+                        "postrouting",
+                        &format!(
+                            "meta oifname {} meta mark set {} {}",
+                            external_network_interface, DFW_MARK, nat,
+                        ),
+                    ));
+                }
+            }
+        }
+        Ok(Some(rules))
+    }
+}
+
+impl Process for ContainerToContainer {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !section_enabled(ctx, "container_to_container", self.enabled) {
+            return Ok(None);
+        }
+
+        let mut rules = Vec::new();
+
+        // Enforce default policy for container-to-container communication.
+        rules.push(nftables::set_chain_policy(
+            Family::Inet,
+            "dfw",
+            "forward",
+            self.default_policy,
+        ));
+
+        if let Some(mut ctc_rules) =
+            process_section_rules(ctx, Section::ContainerToContainer, &self.rules)?
+        {
+            rules.append(&mut ctc_rules);
+        }
+
+        Ok(Some(rules))
+    }
+}
+
+impl Process for ContainerToContainerRule {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        check_missing_containers(ctx, self, self.on_missing, &self.when)?;
+
+        if self.src_tags.is_some() || self.dst_tags.is_some() {
+            let select = |tags: &Option<BTreeMap<String, String>>,
+                          container: &Option<ContainerSelector>|
+             -> Vec<Option<ContainerSelector>> {
+                match tags {
+                    Some(tags) => get_tagged_containers(ctx, tags)
+                        .into_iter()
+                        .map(Some)
+                        .collect(),
+                    None => vec![container.clone()],
+                }
+            };
+            let src_containers = select(&self.src_tags, &self.src_container);
+            let dst_containers = select(&self.dst_tags, &self.dst_container);
+            debug!(ctx.logger, "Expand tags to containers";
+                   o!("part" => "container_to_container",
+                      "src_containers" => format!("{:?}", src_containers),
+                      "dst_containers" => format!("{:?}", dst_containers)));
+
+            let mut rules = Vec::new();
+            for src_container in &src_containers {
+                for dst_container in &dst_containers {
+                    if src_container.is_some() && src_container == dst_container {
+                        continue;
+                    }
+                    rules.push(ContainerToContainerRule {
+                        src_container: src_container.clone(),
+                        dst_container: dst_container.clone(),
+                        src_tags: None,
+                        dst_tags: None,
+                        ..self.clone()
+                    });
+                }
+            }
+            return rules.process(ctx);
+        }
+
+        if let Some(dnat_rule) =
+            bridging_rule(self).map_err(|problem| format_err!("{}", problem))?
+        {
+            debug!(ctx.logger, "Bridge networks using DNAT";
+                   o!("part" => "container_to_container",
+                      "src_network" => &self.network,
+                      "dst_network" => &dnat_rule.dst_network));
+            return dnat_rule.process(ctx);
+        }
+
+        if self.network == WILDCARD_NETWORK {
+            let containers = self
+                .src_container
+                .iter()
+                .chain(self.dst_container.iter())
+                .collect::<Vec<_>>();
+            let networks = get_wildcard_networks(ctx, &containers)?;
+            debug!(ctx.logger, "Expand wildcard network";
+                   o!("part" => "container_to_container",
+                      "networks" => networks.join(", ")));
+            return networks
+                .into_iter()
+                .map(|network| ContainerToContainerRule {
+                    network,
+                    ..self.clone()
+                })
+                .collect::<Vec<_>>()
+                .process(ctx);
+        }
+
+        if !ctx.condition_holds(&self.when)? {
+            debug!(ctx.logger, "Skip rule, condition does not hold";
+                   o!("part" => "container_to_container",
+                      "condition" => format!("{:?}", self.when)));
+            return Ok(None);
+        }
+
+        let mut rules = Vec::new();
+        let mut nft_rule = RuleBuilder::default();
+        let network = match ctx.network_map.get(&self.network) {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        trace!(ctx.logger, "Got network";
+                    o!("network_name" => &self.network,
+                        "network" => format!("{:?}", network)));
+        let bridge_name = get_bridge_name(&network.id)?;
+        trace!(ctx.logger, "Got bridge name";
+                    o!("network_name" => &network.name,
+                        "bridge_name" => &bridge_name));
+
+        nft_rule
+            .in_interface(&bridge_name)
+            .out_interface(&bridge_name);
+
+        if let Some(ref src_container) = self.src_container {
+            let src_network = match get_network_for_container(ctx, src_container, network)? {
+                Some(src_network) => src_network,
+                None => return Ok(None),
+            };
+            trace!(ctx.logger, "Got source network";
+                        o!("network_name" => &network.name,
+                            "src_network" => format!("{:?}", src_network)));
+
+            let bridge_name = get_bridge_name(&network.id)?;
+            trace!(ctx.logger, "Got bridge name";
+                        o!("network_name" => &network.name,
+                            "bridge_name" => &bridge_name));
+
+            nft_rule
+                .in_interface(&bridge_name)
+                .out_interface(&bridge_name)
+                .source_address(ipv4_address_match(&src_network)?);
+        }
+
+        if let Some(ref dst_container) = self.dst_container {
+            let dst_network = match get_network_for_container(ctx, dst_container, network)? {
+                Some(dst_network) => dst_network,
+                None => return Ok(None),
+            };
+            trace!(ctx.logger, "Got destination network";
+                        o!("network_name" => &network.name,
+                            "dst_network" => format!("{:?}", dst_network)));
+
+            let bridge_name = get_bridge_name(&network.id)?;
+            trace!(ctx.logger, "Got bridge name";
+                        o!("network_name" => &network.name,
+                            "bridge_name" => &bridge_name));
+
+            nft_rule
+                .out_interface(&bridge_name)
+                .destination_address(ipv4_address_match(&dst_network)?);
+        }
+
+        if let Some(matches) = &self.matches {
+            nft_rule.matches(matches);
+        }
+        if let Some(min_ct_bytes) = &self.min_ct_bytes {
+            nft_rule.min_ct_bytes(ct_bytes(min_ct_bytes)?);
+        }
+        if let Some(mirror_to) = self.mirror_to {
+            nft_rule.dup(get_mirror_target(ctx, mirror_to, None)?);
+        }
+        let mut nft_rules = vec![nft_rule];
+        if let Some(dscp) = self.dscp {
+            // Setting the DSCP value restricts the rule to the family of the statement. Rules
+            // that don't match on container addresses are generated for both families to keep the
+            // verdict applying to all traffic on the network.
+            if self.src_container.is_none() && self.dst_container.is_none() {
+                let mut nft_rule_v6 = nft_rules[0].clone();
+                nft_rule_v6.dscp_v6(dscp.to_string());
+                nft_rules.push(nft_rule_v6);
+            }
+            nft_rules[0].dscp(dscp.to_string());
+        }
+        let nft_rules = restrict_families(nft_rules, &self.families);
+        if nft_rules.is_empty() {
+            debug!(ctx.logger, "Skip rule, it only applies to excluded families";
+                   o!("part" => "container_to_container",
+                      "families" => format!("{:?}", self.families)));
+            return Ok(None);
+        }
+
+        if self.log || !self.verdict.is_terminal() {
+            let rule = build_log_rule(&nft_rules[0], Section::ContainerToContainer)?;
+            rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
+        }
+        for nft_rule in &nft_rules {
+            for rule in build_verdict_rules(nft_rule, self.verdict)? {
+                rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
+            }
+        }
+
+        Ok(Some(rules))
+    }
+
+    fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+}
+
+impl Process for ContainerToWiderWorld {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !section_enabled(ctx, "container_to_wider_world", self.enabled) {
+            return Ok(None);
+        }
+
+        let mut rules = Vec::new();
+
+        if let Some(mut ctww_rules) =
+            process_section_rules(ctx, Section::ContainerToWiderWorld, &self.rules)?
+        {
+            rules.append(&mut ctww_rules);
+        }
+
+        // Enforce default policy for container-to-wider-world communication. A policy that
+        // differs between the families results in one rule per family.
+        let default_policies = if self.default_policy.is_split() {
+            vec![
+                (Some("ipv4"), self.default_policy.v4),
+                (Some("ipv6"), self.default_policy.v6),
+            ]
+        } else {
+            vec![(None, self.default_policy.v4)]
+        };
+        if let Some(external_network_interfaces) = &ctx.external_network_interfaces {
+            debug!(ctx.logger, "Set default policy for external network interfaces";
+                   o!("part" => "container_to_wider_world",
+                      "external_network_interfaces" => format!("{:?}", external_network_interfaces),
+                      "default_policy" => self.default_policy.to_string()));
+            for (external_network_interface, family) in external_network_interfaces.with_families()
+            {
+                trace!(ctx.logger, "Process default policy for external network interface";
+                       o!("part" => "container_to_wider_world",
+                          "external_network_interface" => &external_network_interface,
+                          "default_policy" => self.default_policy.to_string()));
+                // An interface carrying a single family only gets the policy of that family.
+                let default_policies = match family {
+                    Some(AddressFamily::V4) => vec![(Some("ipv4"), self.default_policy.v4)],
+                    Some(AddressFamily::V6) => vec![(Some("ipv6"), self.default_policy.v6)],
+                    None => default_policies.clone(),
+                };
+                for network in ctx.network_map.values() {
+                    let bridge_name = get_bridge_name(&network.id)?;
+                    trace!(ctx.logger, "Got bridge name";
+                           o!("network_name" => &network.name,
+                              "bridge_name" => &bridge_name));
+
+                    for (nfproto, default_policy) in &default_policies {
+                        let mut nft_rule = RuleBuilder::default();
+                        nft_rule
+                            .in_interface(&bridge_name)
+                            .out_interface(&external_network_interface)
+                            .verdict(*default_policy);
+                        if let Some(nfproto) = nfproto {
+                            nft_rule.nfproto(*nfproto);
+                        }
+                        let rule = nft_rule.build()?;
+
+                        debug!(ctx.logger, "Add forward rule for default policy";
+                               o!("part" => "container_to_wider_world",
+                                  "external_network_interface" => &external_network_interface,
+                                  "default_policy" => default_policy,
+                                  "rule" => &rule));
+
+                        rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
+                    }
+                }
+            }
+        }
+
+        Ok(Some(rules))
+    }
+}
+
+impl Process for ContainerToWiderWorldRule {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        check_missing_containers(ctx, self, self.on_missing, &self.when)?;
+
+        if !ctx.condition_holds(&self.when)? {
+            debug!(ctx.logger, "Skip rule, condition does not hold";
+                   o!("part" => "container_to_wider_world",
+                      "condition" => format!("{:?}", self.when)));
+            return Ok(None);
+        }
+
+        let mut rules = Vec::new();
+        debug!(ctx.logger, "Process rule";
+                   o!("part" => "container_to_wider_world",
+                      "rule" => format!("{:?}", self)));
+        let mut nft_rule = RuleBuilder::default();
+
+        if let Some(ref network) = self.network {
+            if let Some(network) = ctx.network_map.get(network) {
+                if let Some(ref src_container) = self.src_container {
+                    if let Some(src_network) =
+                        get_network_for_container(ctx, src_container, network)?
+                    {
+                        trace!(ctx.logger, "Got source network";
+                                   o!("network_name" => &network.name,
+                                      "src_network" => format!("{:?}", src_network)));
+
+                        let bridge_name = get_bridge_name(&network.id)?;
+                        trace!(ctx.logger, "Got bridge name";
+                                   o!("network_name" => &network.name,
+                                      "bridge_name" => &bridge_name));
+
+                        nft_rule
+                            .in_interface(&bridge_name)
+                            .source_address(ipv4_address_match(&src_network)?);
+                    }
+                } else {
+                    let bridge_name = get_bridge_name(&network.id)?;
+                    trace!(ctx.logger, "Got bridge name";
+                               o!("network_name" => &network.name,
+                                  "bridge_name" => &bridge_name));
+
+                    nft_rule.in_interface(&bridge_name);
+                }
+            }
+        }
+
+        if let Some(vlan_id) = self.vlan_id {
+            nft_rule.vlan_id(vlan_id.to_string());
+        }
+
+        if let Some(ref matches) = self.matches {
+            nft_rule.matches(matches);
+        }
+
+        if let Some(ref min_ct_bytes) = self.min_ct_bytes {
+            nft_rule.min_ct_bytes(ct_bytes(min_ct_bytes)?);
+        }
+
+        if let Some(mirror_to) = self.mirror_to {
+            nft_rule.dup(get_mirror_target(
+                ctx,
+                mirror_to,
+                match ExternalNetworkInterfaces::of(&self.external_network_interface) {
+                    ExternalNetworkInterfaces::Explicit(external_network_interfaces) => {
+                        external_network_interfaces.first()
+                    }
+                    _ => None,
+                },
+            )?);
+        }
+
+        // Try to build the rule without the out_interface defined to see if any of the other
+        // mandatory fields has been populated.
+        debug!(ctx.logger, "Build rule to verify contents";
+                   o!("args" => format!("{:?}", nft_rule)));
+        // TODO: maybe add a `verify` method to `Rule`
+        nft_rule.build().context(format!(
+            "failed to build rule, maybe the network `{:?}` or container `{:?}` doesn't exist",
+            self.network, self.src_container
+        ))?;
+
+        let nft_rules = match ExternalNetworkInterfaces::of(&self.external_network_interface) {
+            ExternalNetworkInterfaces::Explicit(external_network_interfaces) => {
+                trace!(ctx.logger, "Rule has specific external network interfaces";
+                       o!("external_network_interfaces" => external_network_interfaces.join(", ")));
+                nft_rule.out_interface(interface_match(external_network_interfaces));
+                vec![nft_rule]
+            }
+            ExternalNetworkInterfaces::Inherit => {
+                // The primary interface can differ between the families, the rule is then split
+                // into one rule per family, unless it only applies to a single family anyway.
+                let families = match nft_rule
+                    .family()
+                    .or_else(|| restricted_family(&self.families))
+                {
+                    Some(family) => vec![family],
+                    None => vec![AddressFamily::V4, AddressFamily::V6],
+                };
+                let primary_external_network_interfaces = families
+                    .into_iter()
+                    .map(|family| (family, ctx.primary_external_network_interface(family)))
+                    .collect::<Vec<_>>();
+                let split = primary_external_network_interfaces
+                    .windows(2)
+                    .any(|primaries| primaries[0].1 != primaries[1].1);
+                if !split {
+                    if let Some(primary_external_network_interface) =
+                        primary_external_network_interfaces[0].1
+                    {
+                        trace!(ctx.logger, "Rule uses primary external network interface";
+                               o!("external_network_interface" => primary_external_network_interface));
+                        nft_rule.out_interface(primary_external_network_interface);
+                    }
+                    vec![nft_rule]
+                } else {
+                    primary_external_network_interfaces
+                        .into_iter()
+                        .map(|(family, primary_external_network_interface)| {
+                            trace!(ctx.logger, "Rule uses primary external network interface of family";
+                                   o!("family" => family.nfproto(),
+                                      "external_network_interface" => primary_external_network_interface));
+                            let mut nft_rule = nft_rule.clone();
+                            nft_rule.nfproto(family.nfproto());
+                            if let Some(primary_external_network_interface) =
+                                primary_external_network_interface
+                            {
+                                nft_rule.out_interface(primary_external_network_interface);
+                            }
+                            nft_rule
+                        })
+                        .collect()
+                }
+            }
+            ExternalNetworkInterfaces::All => {
+                trace!(ctx.logger, "Rule applies to all network interfaces");
+                vec![nft_rule]
+            }
+        };
+        let nft_rules = restrict_families(nft_rules, &self.families);
+        if nft_rules.is_empty() {
+            debug!(ctx.logger, "Skip rule, it only applies to excluded families";
+                   o!("part" => "container_to_wider_world",
+                      "families" => format!("{:?}", self.families)));
+            return Ok(None);
+        }
+
+        for nft_rule in &nft_rules {
+            if self.log || !self.verdict.is_terminal() {
+                let rule = build_log_rule(nft_rule, Section::ContainerToWiderWorld)?;
+                rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
+            }
+            for rule in build_verdict_rules(nft_rule, self.verdict)? {
+                debug!(ctx.logger, "Add forward rule";
+                           o!("part" => "container_to_wider_world",
+                              "rule" => &rule));
+
+                // Apply the rule
+                rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
+            }
+        }
+        Ok(Some(rules))
+    }
+
+    fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+}
+
+impl Process for Dns {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !section_enabled(ctx, "dns", self.enabled) {
+            return Ok(None);
+        }
+
+        debug!(ctx.logger, "Process DNS servers";
+               o!("part" => "dns",
+                  "servers" => format!("{:?}", self.servers)));
+
+        // Restrict the destination to the servers of the respective family, if given.
+        let destinations = match self.servers {
+            Some(ref servers) => {
+                let (v4, v6): (Vec<&IpAddr>, Vec<&IpAddr>) =
+                    servers.iter().partition(|server| server.is_ipv4());
+                [("ip", v4), ("ip6", v6)]
+                    .iter()
+                    .filter(|(_, servers)| !servers.is_empty())
+                    .map(|(family, servers)| {
+                        let servers = servers.iter().map(ToString::to_string).collect::<Vec<_>>();
+                        Some(format!("{} daddr {{ {} }}", family, servers.join(", ")))
+                    })
+                    .collect()
+            }
+            None => vec![None],
+        };
+        let ports = [
+            "udp dport 53",
+            if self.dns_over_tls {
+                "tcp dport { 53, 853 }"
+            } else {
+                "tcp dport 53"
+            },
+        ];
+
+        // Without a network, DNS traffic is allowed from all networks.
+        let networks = match self.network {
+            Some(ref network) => vec![network.to_owned()],
+            None if self.src_container.is_some() => {
+                bail!("the source container of the DNS section requires a network")
+            }
+            None => ctx.network_map.keys().cloned().collect(),
+        };
+
+        // The rules are regular container-to-wider-world rules, accepting the DNS traffic.
+        let mut rules = Vec::new();
+        for network in &networks {
+            for destination in &destinations {
+                for ports in &ports {
+                    let matches = match destination {
+                        Some(destination) => format!("{} {}", destination, ports),
+                        None => (*ports).to_owned(),
+                    };
+                    let rule = ContainerToWiderWorldRule {
+                        network: Some(network.to_owned()),
+                        src_container: self.src_container.clone(),
+                        matches: Some(matches),
+                        min_ct_bytes: None,
+                        verdict: RuleVerdict::Accept.into(),
+                        external_network_interface: None,
+                        mirror_to: None,
+                        log: false,
+                        vlan_id: None,
+                        families: None,
+                        on_missing: None,
+                        when: None,
+                        provenance: None,
+                    };
+                    if let Some(mut dns_rules) = rule.process(ctx)? {
+                        rules.append(&mut dns_rules);
+                    }
+                }
+            }
+        }
+
+        Ok(Some(rules))
+    }
+}
+
+impl Process for ContainerToHost {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !section_enabled(ctx, "container_to_host", self.enabled) {
+            return Ok(None);
+        }
+
+        let mut rules = Vec::new();
+
+        // Allow the embedded DNS server ahead of the rules, which might otherwise reject it. Docker
+        // answers the queries within the network namespace of the container, these rules are thus
+        // only a safeguard in case the traffic reaches the input chain of the host after all.
+        if ctx.allow_embedded_dns() {
+            for network in ctx.network_map.values() {
+                if network.name == "bridge" || !network.is_bridge() {
+                    continue;
+                }
+                let bridge_name = get_bridge_name(&network.id)?;
+                for protocol in &["tcp", "udp"] {
+                    let rule = RuleBuilder::default()
+                        .protocol(*protocol)
+                        .destination_port("53")
+                        .destination_address(EMBEDDED_DNS_ADDRESS)
+                        .in_interface(&bridge_name)
+                        .verdict(RuleVerdict::Accept)
+                        .build()?;
+
+                    trace!(ctx.logger, "Add input rule for embedded DNS";
+                           o!("part" => "container_to_host",
+                              "network_name" => &network.name,
+                              "rule" => &rule));
+                    rules.push(nftables::add_rule(Family::Inet, "dfw", "input", &rule));
+                }
+            }
+        }
+
+        if let Some(mut cth_rules) =
+            process_section_rules(ctx, Section::ContainerToHost, &self.rules)?
+        {
+            rules.append(&mut cth_rules);
+        }
+
+        // Default policy
+        for network in ctx.network_map.values() {
+            let bridge_name = get_bridge_name(&network.id)?;
+            trace!(ctx.logger, "Got bridge name";
+                   o!("network_name" => &network.name,
+                      "bridge_name" => &bridge_name));
+
+            let rule = RuleBuilder::default()
+                .in_interface(&bridge_name)
+                .verdict(self.default_policy)
+                .build()?;
+
+            trace!(ctx.logger, "Add input rule for default policy";
+                   o!("part" => "container_to_host",
+                      "default_policy" => self.default_policy,
+                      "rule" => &rule));
+            rules.push(nftables::add_rule(Family::Inet, "dfw", "input", &rule));
+        }
+
+        Ok(Some(rules))
+    }
+}
+
+impl Process for ContainerToHostRule {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        check_missing_containers(ctx, self, self.on_missing, &self.when)?;
+
+        if self.network == WILDCARD_NETWORK {
+            let containers = self.src_container.iter().collect::<Vec<_>>();
+            let networks = get_wildcard_networks(ctx, &containers)?;
+            debug!(ctx.logger, "Expand wildcard network";
+                   o!("part" => "container_to_host",
+                      "networks" => networks.join(", ")));
+            return networks
+                .into_iter()
+                .map(|network| ContainerToHostRule {
+                    network,
+                    ..self.clone()
+                })
+                .collect::<Vec<_>>()
+                .process(ctx);
+        }
+
+        if !ctx.condition_holds(&self.when)? {
+            debug!(ctx.logger, "Skip rule, condition does not hold";
+                   o!("part" => "container_to_host",
+                      "condition" => format!("{:?}", self.when)));
+            return Ok(None);
+        }
+
+        let mut rules = Vec::new();
+        debug!(ctx.logger, "Process rule";
+                   o!("part" => "container_to_host",
+                      "rule" => format!("{:?}", self)));
+        let mut nft_rule = RuleBuilder::default();
+
+        let network = match ctx.network_map.get(&self.network) {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        trace!(ctx.logger, "Got network";
+                   o!("network_name" => &network.name,
+                      "network" => format!("{:?}", network)));
+
+        let bridge_name = get_bridge_name(&network.id)?;
+        trace!(ctx.logger, "Got bridge name";
+                   o!("network_name" => &network.name,
+                      "bridge_name" => &bridge_name));
+
+        nft_rule.in_interface(&bridge_name);
+
+        if let Some(ref src_container) = self.src_container {
+            if let Some(src_network) = get_network_for_container(ctx, src_container, network)? {
+                trace!(ctx.logger, "Got source network";
+                           o!("network_name" => &network.name,
+                              "src_network" => format!("{:?}", src_network)));
+                nft_rule.source_address(ipv4_address_match(&src_network)?);
+            }
+        }
+
+        if let Some(ref matches) = self.matches {
+            nft_rule.matches(matches);
+        }
+
+        if let Some(ref min_ct_bytes) = self.min_ct_bytes {
+            nft_rule.min_ct_bytes(ct_bytes(min_ct_bytes)?);
+        }
+
+        if let Some(mirror_to) = self.mirror_to {
+            nft_rule.dup(get_mirror_target(ctx, mirror_to, None)?);
+        }
+
+        // Try to build the rule without the out_interface defined to see if any of the other
+        // mandatory fields has been populated.
+        debug!(ctx.logger, "Build rule to verify contents";
+                   o!("args" => format!("{:?}", nft_rule)));
+        // TODO: maybe add a `verify` method to `Rule`
+        nft_rule.build().context(format!(
+            "failed to build rule, maybe the container `{:?}` doesn't exist",
+            self.src_container
+        ))?;
+        let nft_rule = match restrict_families(vec![nft_rule], &self.families).pop() {
+            Some(nft_rule) => nft_rule,
+            None => {
+                debug!(ctx.logger, "Skip rule, it only applies to excluded families";
+                       o!("part" => "container_to_host",
+                          "families" => format!("{:?}", self.families)));
+                return Ok(None);
+            }
+        };
+
+        if self.log || !self.verdict.is_terminal() {
+            let rule = build_log_rule(&nft_rule, Section::ContainerToHost)?;
+            rules.push(nftables::add_rule(Family::Inet, "dfw", "input", &rule));
+        }
+        for rule in build_verdict_rules(&nft_rule, self.verdict)? {
+            debug!(ctx.logger, "Add input rule";
+                       o!("part" => "container_to_host",
+                          "rule" => &rule));
+
+            // Apply the rule
+            rules.push(nftables::add_rule(Family::Inet, "dfw", "input", &rule));
+        }
+
+        Ok(Some(rules))
+    }
+
+    fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+}
+
+impl Process for WiderWorldToContainer {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !section_enabled(ctx, "wider_world_to_container", self.enabled) {
+            return Ok(None);
+        }
+
+        let mut rules = if self.rules.is_some() {
+            debug!(ctx.logger, "Process rules";
+                   o!("part" => "wider_world_to_container"));
+            let rules = process_section_rules(ctx, Section::WiderWorldToContainer, &self.rules)?;
+            if !ctx.nft_supports(NftVersion::NAT_CONCATENATIONS) {
+                debug!(ctx.logger, "nft doesn't support NAT concatenations, keep DNAT rules";
+                       o!("nft_version" => format!("{:?}", ctx.host_facts.nft_version),
+                          "required_nft_version" => NftVersion::NAT_CONCATENATIONS.to_string()));
+                rules
+            } else {
+                rules.map(collapse_dnat_rules)
+            }
+        } else {
+            trace!(ctx.logger, "No rules";
+                   o!("part" => "wider_world_to_container"));
+            None
+        };
+
+        if let Some(ref log_unmatched) = self.log_unmatched {
+            if let Some(rule) = self.log_unmatched_rule(ctx, log_unmatched)? {
+                rules.get_or_insert_with(Vec::new).push(nftables::add_rule(
+                    Family::Inet,
+                    "dfw",
+                    "forward",
+                    &rule,
+                ));
+            }
+        }
+
+        Ok(rules)
+    }
+}
+
+impl WiderWorldToContainer {
+    /// Build the rule logging the traffic from the external network interfaces that none of the
+    /// rules matched. It is added after the rules of the section, the traffic reaching it is thus
+    /// subject to the default policy of the forward chain.
+    fn log_unmatched_rule(
+        &self,
+        ctx: &ProcessContext,
+        log_unmatched: &LogUnmatched,
+    ) -> Result<Option<String>> {
+        let external_network_interfaces = match ctx.external_network_interfaces {
+            Some(ref external_network_interfaces)
+                if !external_network_interfaces.all().is_empty() =>
+            {
+                external_network_interfaces.all()
+            }
+            _ => {
+                debug!(ctx.logger, "Skip logging unmatched traffic, no external network interfaces";
+                       o!("part" => "wider_world_to_container"));
+                return Ok(None);
+            }
+        };
+
+        let mut nft_rule = RuleBuilder::default();
+        nft_rule
+            .in_interface(interface_match(&external_network_interfaces))
+            .log(&log_unmatched.prefix);
+        if let Some(level) = log_unmatched.level {
+            nft_rule.log_level(level);
+        }
+        let rule = nft_rule.build()?;
+        debug!(ctx.logger, "Add rule logging unmatched traffic";
+               o!("part" => "wider_world_to_container",
+                  "rule" => &rule));
+
+        Ok(Some(rule))
+    }
+}
+
+impl WiderWorldToContainerRule {
+    /// Get the exposed ports of the rule, replacing the placeholder for published ports by the
+    /// ports the destination container publishes through Docker, and the placeholder for the port
+    /// declared by the image of the destination container by that port.
+    ///
+    /// The family of every port is taken from the protocol of its Docker binding.
+    fn resolve_expose_ports(&self, ctx: &ProcessContext) -> Result<Vec<ExposePort>> {
+        let mut expose_ports = Vec::new();
+        for expose_port in &self.expose_port {
+            if expose_port.exposed {
+                expose_ports.extend(resolve_image_ports(
+                    ctx,
+                    &self.dst_container,
+                    &self.network,
+                    expose_port,
+                )?);
+                continue;
+            }
+            if !expose_port.published {
+                expose_ports.push(expose_port.clone());
+                continue;
+            }
+
+            let published_ports = ctx
+                .resolve_container(&self.dst_container, &self.network)?
+                .and_then(|container_name| ctx.container_map.get(&container_name))
+                .map_or(&[][..], |container| &container.published_ports[..]);
+            trace!(ctx.logger, "Got published ports";
+                   o!("part" => "wider_world_to_container",
+                      "dst_container" => self.dst_container.to_string(),
+                      "published_ports" => format!("{:?}", published_ports)));
+            for published_port in published_ports {
+                expose_ports.push(ExposePort {
+                    host_port: published_port.host_port,
+                    container_port: Some(published_port.container_port),
+                    family: published_port.protocol.clone(),
+                    host_ip: published_port.host_ip,
+                    host_port_range: None,
+                    container_port_range: None,
+                    dnat: true,
+                    published: false,
+                    exposed: false,
+                });
+            }
+        }
+        Ok(expose_ports)
+    }
+}
+
+/// Replace the placeholder for the port declared by the image of the container through `EXPOSE`
+/// by that port.
+///
+/// If the image declares multiple ports, the container port of the placeholder has to select one
+/// of them. A port declared for multiple protocols results in one exposed port per protocol. The
+/// host port defaults to the container port.
+fn resolve_image_ports(
+    ctx: &ProcessContext,
+    container: &ContainerSelector,
+    network_name: &str,
+    expose_port: &ExposePort,
+) -> Result<Vec<ExposePort>> {
+    let image_ports = match ctx.resolve_container(container, network_name)? {
+        Some(container_name) => ctx.image_ports(&container_name)?,
+        None => return Ok(Vec::new()),
+    };
+    trace!(ctx.logger, "Got image ports";
+           o!("container" => container.to_string(),
+              "image_ports" => format!("{:?}", image_ports)));
+
+    let mut ports = image_ports
+        .iter()
+        .map(|image_port| image_port.port)
+        .collect::<Vec<_>>();
+    ports.dedup();
+    let container_port = match (expose_port.container_port, &ports[..]) {
+        (Some(container_port), _) if ports.contains(&container_port) => container_port,
+        (Some(container_port), _) => bail!(
+            "the image of container {} does not expose port {}",
+            container,
+            container_port
+        ),
+        (None, []) => {
+            debug!(ctx.logger, "Skip exposed port, the image does not expose any ports";
+                   o!("container" => container.to_string()));
+            return Ok(Vec::new());
+        }
+        (None, [container_port]) => *container_port,
+        (None, _) => bail!(
+            "the image of container {} exposes multiple ports ({}), select one of them through \
+             `container_port`",
+            container,
+            image_ports
+                .iter()
+                .map(|image_port| format!("{}/{}", image_port.port, image_port.protocol))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+    };
+
+    Ok(image_ports
+        .into_iter()
+        .filter(|image_port| image_port.port == container_port)
+        .map(|image_port| ExposePort {
+            host_port: match expose_port.host_port {
+                AUTO_HOST_PORT => container_port,
+                host_port => host_port,
+            },
+            container_port: Some(container_port),
+            family: image_port.protocol,
+            host_ip: expose_port.host_ip,
+            host_port_range: None,
+            container_port_range: None,
+            dnat: expose_port.dnat,
+            published: false,
+            exposed: false,
+        })
+        .collect())
+}
+
+impl Process for WiderWorldToContainerRule {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        check_missing_containers(ctx, self, self.on_missing, &self.when)?;
+
+        if !ctx.condition_holds(&self.when)? {
+            debug!(ctx.logger, "Skip rule, condition does not hold";
+                   o!("part" => "wider_world_to_container",
+                      "condition" => format!("{:?}", self.when)));
+            return Ok(None);
+        }
+
+        if self.require_healthy {
+            let health = ctx
+                .resolve_container(&self.dst_container, &self.network)?
+                .and_then(|container_name| ctx.container_map.get(&container_name))
+                .and_then(|container| container.health);
+            if let Some(health) = health.filter(|health| *health != HealthStatus::Healthy) {
+                debug!(ctx.logger, "Skip rule, destination container is not healthy";
+                       o!("part" => "wider_world_to_container",
+                          "dst_container" => self.dst_container.to_string(),
+                          "health" => health.to_string()));
+                return Ok(None);
+            }
+        }
+
+        if self.notrack && self.drain {
+            bail!("draining cannot be combined with bypassing connection tracking");
+        }
+        let ct_timeout = self.resolve_ct_timeout(ctx)?;
+
+        if self.network == HOST_NETWORK {
+            debug!(ctx.logger, "Expose ports of container attached to the host network";
+                   o!("part" => "wider_world_to_container",
+                      "dst_container" => self.dst_container.to_string()));
+            return self.process_host_network(ctx);
+        }
+
+        if self.interior_only {
+            debug!(ctx.logger, "Expose ports to containers only";
+                   o!("part" => "wider_world_to_container",
+                      "from_containers" => format!("{:?}", self.from_containers)));
+            return self.process_interior(ctx);
+        }
+
+        let mut rules = Vec::new();
+        debug!(ctx.logger, "Process rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => format!("{:?}", self)));
+        if let Some(ref quota) = self.quota {
+            rules.push(nftables::add_quota(
+                Family::Inet,
+                "dfw",
+                &self.quota_name(),
+                &quota.bytes,
+                quota.over,
+            ));
+        }
+        for expose_port in &self.resolve_expose_ports(ctx)? {
+            // The address of the port takes precedence over the address of the rule.
+            let host_ip = expose_port.host_ip.or(self.host_ip);
+            let mut nft_forward_rule = RuleBuilder::default();
+            let mut nft_dnat_rule = RuleBuilder::default();
+            let mut nft_mark_rule = RuleBuilder::default();
+            let mut nft_notrack_rule = RuleBuilder::default();
+            let mut nft_notrack_reply_rule = RuleBuilder::default();
+            let mut nft_ct_timeout_rule = RuleBuilder::default();
+            let mut nft_reply_rule = RuleBuilder::default();
+            if ctx.dnat_new_only() {
+                nft_dnat_rule.ct_state("new");
+            }
+
+            let network = match ctx.network_map.get(&self.network) {
+                Some(network) => network,
+                None => return Ok(None),
+            };
+            trace!(ctx.logger, "Got network";
+                   o!("network_name" => &network.name,
+                      "network" => format!("{:?}", network)));
+
+            let bridge_name = get_bridge_name(&network.id)?;
+            trace!(ctx.logger, "Got bridge name";
+                   o!("network_name" => &network.name,
+                      "bridge_name" => &bridge_name));
+
+            nft_forward_rule.out_interface(&bridge_name);
+            nft_notrack_reply_rule.in_interface(&bridge_name);
+            nft_reply_rule.in_interface(&bridge_name);
+
+            if let Some(dst_network) = get_network_for_container(ctx, &self.dst_container, network)?
+            {
+                trace!(ctx.logger, "Got destination network";
+                       o!("network_name" => &network.name,
+                          "dst_network" => format!("{:?}", dst_network)));
+
+                let (host_port, destination_port) = match (
+                    expose_port.host_port_range,
+                    expose_port.container_port_range,
+                ) {
+                    // Without DNAT the traffic reaches the container on the host port itself
+                    (Some(host_port_range), _) if !expose_port.dnat => {
+                        (host_port_range.to_string(), host_port_range.to_string())
+                    }
+                    // A range of host ports is translated to the range of container ports
+                    (Some(host_port_range), Some(container_port_range)) => (
+                        host_port_range.to_string(),
+                        container_port_range.to_string(),
+                    ),
+                    _ => {
+                        let host_port =
+                            ctx.host_port(&self.dst_container, expose_port)?.to_string();
+                        let destination_port = match expose_port.container_port {
+                            Some(destination_port) if expose_port.dnat => {
+                                destination_port.to_string()
+                            }
+                            _ => host_port.clone(),
+                        };
+                        (host_port, destination_port)
+                    }
+                };
+                // Traffic from the wider world targets the host port, which is translated to the
+                // port of the container.
+                nft_dnat_rule.destination_port(&host_port);
+                nft_mark_rule.destination_port(&host_port);
+                nft_ct_timeout_rule.destination_port(&host_port);
+
+                if let Some((ct_timeout, protocol)) =
+                    ct_timeout.filter(|(_, protocol)| *protocol != expose_port.family)
+                {
+                    bail!(
+                        "conntrack timeout policy `{}` applies to {}, it cannot be assigned to the \
+                         {} port {}",
+                        ct_timeout,
+                        protocol,
+                        expose_port.family,
+                        host_port
+                    );
+                }
+
+                if self.notrack && expose_port.dnat {
+                    bail!(
+                        "exposed port {} cannot bypass connection tracking, DNAT relies on it",
+                        host_port
+                    );
+                }
+
+                // An explicit DNAT target overrides the address (and port) of the container
+                let (dnat_address, dnat_port) = match self.dnat_to {
+                    Some(ref dnat_to) if !expose_port.dnat => bail!(
+                        "DNAT target {} cannot be used for exposed port {}, which disables DNAT",
+                        dnat_to,
+                        host_port
+                    ),
+                    Some(ref dnat_to) => {
+                        match (dnat_to.address, host_ip) {
+                            (IpAddr::V6(_), _) => bail!(
+                                "DNAT target {} is an IPv6 address, DNAT is only supported for IPv4",
+                                dnat_to
+                            ),
+                            (_, Some(host_ip @ IpAddr::V6(_))) => bail!(
+                                "address family of DNAT target {} doesn't match host address {}",
+                                dnat_to,
+                                host_ip
+                            ),
+                            _ => {}
+                        }
+                        if let (Some(_), Some(host_port_range)) =
+                            (dnat_to.port, expose_port.host_port_range)
+                        {
+                            bail!(
+                                "DNAT target {} specifies a port, which cannot be used for the \
+                                 exposed port range {}",
+                                dnat_to,
+                                host_port_range
+                            );
+                        }
+                        trace!(ctx.logger, "Rule overrides DNAT target";
+                               o!("dnat_to" => dnat_to.to_string()));
+                        (
+                            dnat_to.address.to_string(),
+                            dnat_to
+                                .port
+                                .map(|port| port.to_string())
+                                .unwrap_or_else(|| destination_port.clone()),
+                        )
+                    }
+                    None => (
+                        self.destination_address(&dst_network)?,
+                        destination_port.clone(),
+                    ),
+                };
+                nft_forward_rule.destination_address(&dnat_address);
+                nft_forward_rule.destination_port(&dnat_port);
+                nft_dnat_rule.dnat(format!("{}:{}", dnat_address, dnat_port));
+                nft_notrack_rule.destination_address(&dnat_address);
+                nft_notrack_rule.destination_port(&dnat_port);
+                nft_notrack_reply_rule.source_address(&dnat_address);
+                nft_notrack_reply_rule.source_port(&dnat_port);
+                nft_reply_rule.source_address(&dnat_address);
+                nft_reply_rule.source_port(&dnat_port);
+            // TODO: correct IPv6 handling would include actually using IPv6-addresses.
+            // While the code below is correct, the postrouting did not work and I was unable to
+            // actually get traffic from an IPv6-enabled container back.
+            // if !dst_network.ipv6_address.is_empty() {
+            //     nft_mark_rule.dnat(&format!(
+            //         "{}:{}",
+            //         dst_network.ipv6_address
+            //         .split('/')
+            //         .next()
+            //         .ok_or_else(|| format_err!("Invalid IPv6 address"))?,
+            //     destination_port));
+            // }
+            } else {
+                // Network for container has to exist
+                return Ok(None);
+            }
+
+            // Set correct protocol
+            nft_forward_rule.protocol(&expose_port.family);
+            nft_dnat_rule.protocol(&expose_port.family);
+            nft_mark_rule.protocol(&expose_port.family);
+            nft_notrack_rule.protocol(&expose_port.family);
+            nft_notrack_reply_rule.protocol(&expose_port.family);
+            nft_ct_timeout_rule.protocol(&expose_port.family);
+            nft_reply_rule.protocol(&expose_port.family);
+
+            if let Some(vlan_id) = self.vlan_id {
+                nft_forward_rule.vlan_id(vlan_id.to_string());
+                nft_dnat_rule.vlan_id(vlan_id.to_string());
+                nft_mark_rule.vlan_id(vlan_id.to_string());
+                nft_notrack_rule.vlan_id(vlan_id.to_string());
+                nft_ct_timeout_rule.vlan_id(vlan_id.to_string());
+            }
+
+            // Restrict the exposed port to a specific address of the host, if requested. Only the
+            // rules for the family of the address are generated in that case.
+            let (mut expose_v4, mut expose_v6) = match host_ip {
+                Some(IpAddr::V4(host_ip)) => {
+                    nft_dnat_rule.destination_address(host_ip.to_string());
+                    nft_ct_timeout_rule.destination_address(host_ip.to_string());
+                    (true, false)
+                }
+                Some(IpAddr::V6(host_ip)) => {
+                    nft_mark_rule.destination_address_v6(host_ip.to_string());
+                    (false, true)
+                }
+                None => (true, true),
+            };
+            // The families of the rule restrict the families further.
+            match restricted_family(&self.families) {
+                Some(AddressFamily::V4) => expose_v6 = false,
+                Some(AddressFamily::V6) => expose_v4 = false,
+                None => {}
+            }
+            trace!(ctx.logger, "Determined families to expose port on";
+                   o!("host_ip" => format!("{:?}", host_ip),
+                      "expose_v4" => expose_v4,
+                      "expose_v6" => expose_v6));
+
+            // Try to build the rule without the out_interface defined to see if any of the
+            // other mandatory fields has been populated.
+            debug!(ctx.logger, "Build rule to verify contents";
+                   o!("args" => format!("{:?}", nft_forward_rule)));
+            nft_forward_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
+            debug!(ctx.logger, "build rule to verify contents";
+                   o!("args" => format!("{:?}", nft_dnat_rule)));
+            nft_dnat_rule.build()?; // todo: maybe add a `verify` method to `rule`
+            debug!(ctx.logger, "Build rule to verify contents";
+                   o!("args" => format!("{:?}", nft_mark_rule)));
+            nft_mark_rule.build()?; // TODO: maybe add a `verify` method to `Rule`
+
+            // The IPv6 traffic is only matched by the mark rule, the primary interface of IPv6
+            // applies to it.
+            let (external_network_interface, external_network_interface_v6) =
+                match ExternalNetworkInterfaces::of(&self.external_network_interface) {
+                    ExternalNetworkInterfaces::Explicit(external_network_interfaces) => {
+                        trace!(ctx.logger, "Rule has specific external network interfaces";
+                               o!("external_network_interfaces" => external_network_interfaces.join(", ")));
+                        let external_network_interface =
+                            Some(interface_match(external_network_interfaces));
+                        (
+                            external_network_interface.clone(),
+                            external_network_interface,
+                        )
+                    }
+                    ExternalNetworkInterfaces::Inherit => {
+                        let primary_v4 = ctx.primary_external_network_interface(AddressFamily::V4);
+                        let primary_v6 = ctx.primary_external_network_interface(AddressFamily::V6);
+                        trace!(ctx.logger, "Rule uses primary external network interfaces";
+                               o!("external_network_interface" => format!("{:?}", primary_v4),
+                                  "external_network_interface_v6" => format!("{:?}", primary_v6)));
+                        // The DNAT rule requires the external interface, unless the rule
+                        // explicitly applies to all interfaces. Families without interface are
+                        // thus not exposed.
+                        if primary_v4.is_none() && primary_v6.is_none() {
+                            return Ok(None);
+                        }
+                        expose_v4 &= primary_v4.is_some();
+                        expose_v6 &= primary_v6.is_some();
+                        (primary_v4.cloned(), primary_v6.cloned())
+                    }
+                    ExternalNetworkInterfaces::All => {
+                        trace!(ctx.logger, "Rule applies to all network interfaces");
+                        (None, None)
+                    }
+                };
+            if let Some(ref external_network_interface) = external_network_interface {
+                nft_forward_rule.in_interface(external_network_interface);
+                nft_dnat_rule.in_interface(external_network_interface);
+                nft_notrack_rule.in_interface(external_network_interface);
+                nft_ct_timeout_rule.in_interface(external_network_interface);
+                nft_reply_rule.out_interface(external_network_interface);
+            }
+            if let Some(ref external_network_interface_v6) = external_network_interface_v6 {
+                nft_mark_rule.in_interface(external_network_interface_v6);
+            }
+
+            // The quota rules share the matches of the forward and reply rules, but neither their
+            // verdicts nor their statements.
+            if let Some(quota) = self.quota.as_ref().filter(|_| expose_v4) {
+                rules.append(&mut self.quota_rules(
+                    ctx,
+                    quota,
+                    &nft_forward_rule,
+                    &nft_reply_rule,
+                )?);
+            }
+
+            // The MSS is clamped in both directions.
+            if self.clamp_mss_to_pmtu && expose_v4 && expose_port.family == "tcp" {
+                rules.append(&mut self.clamp_mss_rules(ctx, &nft_forward_rule, &nft_reply_rule)?);
+            }
+
+            nft_forward_rule.verdict(RuleVerdict::Accept);
+
+            // IPv4 traffic is marked while being forwarded to the container, IPv6 traffic already
+            // in prerouting.
+            if let Some(dscp) = self.dscp {
+                nft_forward_rule.dscp(dscp.to_string());
+                nft_mark_rule.dscp_v6(dscp.to_string());
+            }
+
+            if let Some((ct_timeout, _)) = ct_timeout.filter(|_| expose_v4) {
+                rules.push(self.ct_timeout_rule(ctx, ct_timeout, nft_ct_timeout_rule)?);
+            }
+
+            // The incoming untracked traffic is still restricted by the FORWARD-rules below.
+            if self.notrack && expose_v4 {
+                rules.append(&mut self.notrack_rules(
+                    ctx,
+                    nft_notrack_rule,
+                    nft_notrack_reply_rule,
+                    nft_reply_rule,
+                )?);
+            }
+
+            // If source CIDRs have been specified, create the FORWARD-rules as required to
+            // restrict the traffic as intended. Without DNAT, only the FORWARD-rules are created.
+            if let Some(source_cidrs_v4) = self.source_cidr_v4.as_ref().filter(|_| expose_v4) {
+                self.apply_source_cidrs_v4(
+                    ctx,
+                    &mut rules,
+                    source_cidrs_v4,
+                    nft_forward_rule.clone(),
+                    Some(nft_dnat_rule.clone()).filter(|_| expose_port.dnat),
+                )?;
+            }
+            if let Some(source_cidrs_v6) = self
+                .source_cidr_v6
+                .as_ref()
+                .filter(|_| expose_v6 && expose_port.dnat)
+            {
+                self.apply_source_cidrs_v6(
+                    ctx,
+                    &mut rules,
+                    source_cidrs_v6,
+                    nft_mark_rule.clone(),
+                )?;
+            }
+
+            // If no source CIDRs were specified, we create the default rules that allow all
+            // connections from any IP.
+            if self.source_cidr_v4.is_none() && self.source_cidr_v6.is_none() {
+                let forward_rules = build_verdict_rules(&nft_forward_rule, self.forward_verdict())?;
+                for forward_rule in &forward_rules {
+                    debug!(ctx.logger, "Add forward rule";
+                           o!("part" => "wider_world_to_container",
+                              "rule" => forward_rule));
+                }
+                let dnat_rule = nft_dnat_rule.build()?;
+                debug!(ctx.logger, "Add DNAT rule";
+                       o!("part" => "wider_world_to_container",
+                          "rule" => &dnat_rule));
+                let mark_rule = nft_mark_rule.build()?;
+                debug!(ctx.logger, "Add mark rule";
+                       o!("part" => "wider_world_to_container",
+                          "rule" => &mark_rule));
+                // Apply the rule
+                if expose_v4 {
+                    for forward_rule in &forward_rules {
+                        rules.push(nftables::add_rule(
+                            Family::Inet,
+                            "dfw",
+                            "forward",
+                            forward_rule,
+                        ));
+                    }
+                    if expose_port.dnat {
+                        rules.push(nftables::add_rule(
+                            Family::Ip,
+                            "dfw",
+                            "prerouting",
+                            &dnat_rule,
+                        ));
+                    }
+                }
+                if expose_v6 && expose_port.dnat {
+                    rules.push(nftables::add_rule(
+                        Family::Ip6,
+                        "dfw",
+                        "prerouting",
+                        &mark_rule,
+                    ));
+                }
+            }
+        }
+
+        Ok(Some(rules))
+    }
+
+    fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+}
+
+impl WiderWorldToContainerRule {
+    /// Get the verdict of the forward rules. While the rule is draining, only established and
+    /// related connections are accepted, new connections are dropped.
+    fn forward_verdict(&self) -> StatefulVerdict {
+        if self.drain {
+            StatefulVerdict {
+                new: RuleVerdict::Drop,
+                established: RuleVerdict::Accept,
+            }
+        } else {
+            StatefulVerdict::from(RuleVerdict::Accept)
+        }
+    }
+
+    /// Get the name of the quota object of the rule. The name is derived from the network, the
+    /// destination container and the exposed ports, it is thus stable across runs.
+    fn quota_name(&self) -> String {
+        let rule = format!(
+            "{}/{}/{:?}",
+            self.network, self.dst_container, self.expose_port
+        );
+        format!("quota_{:016x}", fnv1a(rule.as_bytes()))
+    }
+
+    /// Generate the rules accounting the traffic to the container and its replies to the quota of
+    /// the rule, dropping it once the quota is exceeded if requested.
+    ///
+    /// The rules are inserted at the top of the forward chain, ahead of the rule accepting
+    /// established connections, since only the first packet of every connection would be
+    /// accounted otherwise.
+    fn quota_rules(
+        &self,
+        ctx: &ProcessContext,
+        quota: &Quota,
+        nft_forward_rule: &RuleBuilder,
+        nft_reply_rule: &RuleBuilder,
+    ) -> Result<Vec<String>> {
+        let quota_name = self.quota_name();
+        let mut rules = Vec::with_capacity(2);
+        for nft_rule in &[nft_forward_rule, nft_reply_rule] {
+            let mut nft_quota_rule = (*nft_rule).clone();
+            nft_quota_rule.quota(&quota_name);
+            if quota.over {
+                nft_quota_rule.verdict(RuleVerdict::Drop);
+            }
+            let quota_rule = nft_quota_rule.build()?;
+            debug!(ctx.logger, "Add quota rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => &quota_rule));
+            rules.push(nftables::insert_rule(
+                Family::Inet,
+                "dfw",
+                "forward",
+                &quota_rule,
+                None,
+            ));
+        }
+
+        Ok(rules)
+    }
+
+    /// Generate the rules clamping the MSS of the traffic to the container and its replies to the
+    /// path MTU, sharing the matches of the rules accepting the traffic.
+    ///
+    /// The rules are inserted at the top of the forward chain, since the replies are otherwise
+    /// accepted by the stateful preamble before reaching them.
+    fn clamp_mss_rules(
+        &self,
+        ctx: &ProcessContext,
+        nft_forward_rule: &RuleBuilder,
+        nft_reply_rule: &RuleBuilder,
+    ) -> Result<Vec<String>> {
+        let mut rules = Vec::with_capacity(2);
+        for nft_rule in &[nft_forward_rule, nft_reply_rule] {
+            let mut nft_clamp_rule = (*nft_rule).clone();
+            nft_clamp_rule.clamp_mss(true);
+            let clamp_rule = nft_clamp_rule.build()?;
+            debug!(ctx.logger, "Add MSS clamping rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => &clamp_rule));
+            rules.push(nftables::insert_rule(
+                Family::Inet,
+                "dfw",
+                "forward",
+                &clamp_rule,
+                None,
+            ));
+        }
+
+        Ok(rules)
+    }
+
+    /// Get the conntrack timeout policy of the rule along with the protocol it applies to, see
+    /// [`WiderWorldToContainerRule.ct_timeout`](../types/struct.WiderWorldToContainerRule.html#structfield.ct_timeout).
+    fn resolve_ct_timeout(&self, ctx: &ProcessContext) -> Result<Option<(&str, &'static str)>> {
+        match self.ct_timeout {
+            Some(_) if self.notrack => bail!(
+                "conntrack timeout policies cannot be combined with bypassing connection tracking"
+            ),
+            Some(ref ct_timeout) => Ok(Some((ct_timeout, ctx.ct_timeout_protocol(ct_timeout)?))),
+            None => Ok(None),
+        }
+    }
+
+    /// Generate the rule assigning the conntrack timeout policy to the IPv4 traffic to the
+    /// container.
+    ///
+    /// The policy is assigned in prerouting, before conntrack sees the first packet of the
+    /// connection, i.e. ahead of DNAT.
+    fn ct_timeout_rule(
+        &self,
+        ctx: &ProcessContext,
+        ct_timeout: &str,
+        mut nft_ct_timeout_rule: RuleBuilder,
+    ) -> Result<String> {
+        nft_ct_timeout_rule.nfproto("ipv4");
+        nft_ct_timeout_rule.ct_timeout(ct_timeout);
+        let ct_timeout_rule = nft_ct_timeout_rule.build()?;
+        debug!(ctx.logger, "Add conntrack timeout rule";
+               o!("part" => "wider_world_to_container",
+                  "rule" => &ct_timeout_rule));
+
+        Ok(nftables::add_rule(
+            Family::Inet,
+            "dfw",
+            "prerouting",
+            &ct_timeout_rule,
+        ))
+    }
+
+    /// Generate the rules bypassing conntrack for the traffic to the container and its replies.
+    ///
+    /// The replies don't belong to an established connection, they are thus accepted explicitly
+    /// in the forward chain.
+    fn notrack_rules(
+        &self,
+        ctx: &ProcessContext,
+        mut nft_notrack_rule: RuleBuilder,
+        mut nft_notrack_reply_rule: RuleBuilder,
+        mut nft_reply_rule: RuleBuilder,
+    ) -> Result<Vec<String>> {
+        nft_notrack_rule.notrack(true);
+        nft_notrack_reply_rule.notrack(true);
+        nft_reply_rule.verdict(RuleVerdict::Accept);
+
+        let mut rules = Vec::with_capacity(3);
+        for (chain, nft_rule) in &[
+            ("prerouting", &nft_notrack_rule),
+            ("prerouting", &nft_notrack_reply_rule),
+            ("forward", &nft_reply_rule),
+        ] {
+            let rule = nft_rule.build()?;
+            debug!(ctx.logger, "Add untracked rule";
+                   o!("part" => "wider_world_to_container",
+                      "chain" => *chain,
+                      "rule" => &rule));
+            rules.push(nftables::add_rule(Family::Inet, "dfw", chain, &rule));
+        }
+
+        Ok(rules)
+    }
+
+    /// Get the address of the destination container the traffic is translated to, i.e. the
+    /// pinned destination address or the primary address of the container.
+    fn destination_address(&self, dst_network: &NetworkEndpoint) -> Result<String> {
+        let dst_ip = match self.dst_ip {
+            Some(IpAddr::V4(dst_ip)) => dst_ip.to_string(),
+            Some(dst_ip @ IpAddr::V6(_)) => bail!(
+                "destination address {} is an IPv6 address, DNAT is only supported for IPv4",
+                dst_ip
+            ),
+            None => {
+                return Ok(dst_network
+                    .ipv4_address
+                    .split('/')
+                    .next()
+                    .ok_or_else(|| format_err!("IPv4 address is empty"))?
+                    .to_owned());
+            }
+        };
+
+        let addresses = dst_network.ipv4_addresses();
+        if !addresses.contains(&dst_ip.as_str()) {
+            bail!(
+                "destination address {} is not an address of container `{}` on network `{}`, \
+                 its addresses are {}",
+                dst_ip,
+                self.dst_container,
+                self.network,
+                addresses.join(", ")
+            );
+        }
+
+        Ok(dst_ip)
+    }
+
+    /// Generate the rules exposing the ports to the containers of `from_containers` only, through
+    /// the network of the rule, see
+    /// [`interior_only`](../types/struct.WiderWorldToContainerRule.html#structfield.interior_only).
+    fn process_interior(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        let network = match ctx.network_map.get(&self.network) {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        let bridge_name = get_bridge_name(&network.id)?;
+        trace!(ctx.logger, "Got bridge name";
+               o!("network_name" => &network.name,
+                  "bridge_name" => &bridge_name));
+        let dst_network = match get_network_for_container(ctx, &self.dst_container, network)? {
+            Some(dst_network) => dst_network,
+            None => return Ok(None),
+        };
+        let dst_address = self.destination_address(&dst_network)?;
+
+        let mut rules = Vec::new();
+        for src_container in &self.from_containers {
+            let src_network = match get_network_for_container(ctx, src_container, network)? {
+                Some(src_network) => src_network,
+                None => {
+                    debug!(ctx.logger, "Skip container, it is not attached to the network";
+                           o!("part" => "wider_world_to_container",
+                              "src_container" => src_container.to_string(),
+                              "network_name" => &network.name));
+                    continue;
+                }
+            };
+
+            for expose_port in &self.resolve_expose_ports(ctx)? {
+                // The containers reach the destination container on its container port directly
+                let destination_port = match (
+                    expose_port.host_port_range,
+                    expose_port.container_port_range,
+                ) {
+                    (Some(host_port_range), _) if !expose_port.dnat => host_port_range.to_string(),
+                    (Some(_), Some(container_port_range)) => container_port_range.to_string(),
+                    _ => match expose_port.container_port {
+                        Some(container_port) if expose_port.dnat => container_port.to_string(),
+                        _ => ctx.host_port(&self.dst_container, expose_port)?.to_string(),
+                    },
+                };
+
+                let mut nft_rule = RuleBuilder::default();
+                nft_rule
+                    .protocol(&expose_port.family)
+                    .destination_port(&destination_port)
+                    .source_address(ipv4_address_match(&src_network)?)
+                    .destination_address(&dst_address)
+                    .in_interface(&bridge_name)
+                    .out_interface(&bridge_name);
+                if let Some(vlan_id) = self.vlan_id {
+                    nft_rule.vlan_id(vlan_id.to_string());
+                }
+                if let Some(dscp) = self.dscp {
+                    nft_rule.dscp(dscp.to_string());
+                }
+                for rule in build_verdict_rules(&nft_rule, self.forward_verdict())? {
+                    debug!(ctx.logger, "Add interior FORWARD rule";
+                           o!("part" => "wider_world_to_container",
+                              "rule" => &rule));
+                    rules.push(nftables::add_rule(Family::Inet, "dfw", "forward", &rule));
+                }
+            }
+        }
+
+        Ok(Some(rules))
+    }
+
+    /// Generate the rules exposing the ports of a container attached to the
+    /// [host network](../types/constant.HOST_NETWORK.html).
+    ///
+    /// The container listens on the ports of the host itself, the traffic is thus accepted in the
+    /// input chain on the host port instead of being translated and forwarded to the container.
+    fn process_host_network(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        for (used, option) in &[
+            (self.dnat_to.is_some(), "dnat_to"),
+            (self.dst_ip.is_some(), "dst_ip"),
+            (self.dscp.is_some(), "dscp"),
+            (self.notrack, "notrack"),
+            (self.quota.is_some(), "quota"),
+            (self.ct_timeout.is_some(), "ct_timeout"),
+            (self.clamp_mss_to_pmtu, "clamp_mss_to_pmtu"),
+            (self.interior_only, "interior_only"),
+        ] {
+            if *used {
+                bail!(
+                    "`{}` cannot be used for container `{}`, it is attached to the host network",
+                    option,
+                    self.dst_container
+                );
+            }
+        }
+
+        let network = match ctx.network_map.get(&self.network) {
+            Some(network) => network,
+            None => return Ok(None),
+        };
+        if get_network_for_container(ctx, &self.dst_container, network)?.is_none() {
+            // Container has to be attached to the host network
+            return Ok(None);
+        }
+
+        // The IPv4 and IPv6 traffic might arrive through different interfaces, the rules are
+        // generated per family in that case.
+        let (external_network_interface, external_network_interface_v6, inherited) =
+            match ExternalNetworkInterfaces::of(&self.external_network_interface) {
+                ExternalNetworkInterfaces::Explicit(external_network_interfaces) => {
+                    let external_network_interface =
+                        Some(interface_match(external_network_interfaces));
+                    (
+                        external_network_interface.clone(),
+                        external_network_interface,
+                        false,
+                    )
+                }
+                ExternalNetworkInterfaces::Inherit => (
+                    ctx.primary_external_network_interface(AddressFamily::V4)
+                        .cloned(),
+                    ctx.primary_external_network_interface(AddressFamily::V6)
+                        .cloned(),
+                    true,
+                ),
+                ExternalNetworkInterfaces::All => (None, None, false),
+            };
+
+        let mut rules = Vec::new();
+        for expose_port in &self.resolve_expose_ports(ctx)? {
+            let host_ip = expose_port.host_ip.or(self.host_ip);
+            let host_port = match expose_port.host_port_range {
+                Some(host_port_range) => host_port_range.to_string(),
+                None => ctx.host_port(&self.dst_container, expose_port)?.to_string(),
+            };
+            // Without DNAT, the traffic has to reach the container on the host port itself.
+            let container_port =
+                match (expose_port.container_port_range, expose_port.container_port) {
+                    (Some(container_port_range), _) => Some(container_port_range.to_string()),
+                    (None, Some(container_port)) => Some(container_port.to_string()),
+                    (None, None) => None,
+                };
+            if let Some(container_port) = container_port
+                .filter(|container_port| expose_port.dnat && *container_port != host_port)
+            {
+                bail!(
+                    "host port {} cannot be translated to port {} of container `{}`, it is \
+                     attached to the host network",
+                    host_port,
+                    container_port,
+                    self.dst_container
+                );
+            }
+
+            let mut nft_rule = RuleBuilder::default();
+            nft_rule
+                .protocol(&expose_port.family)
+                .destination_port(&host_port);
+            if let Some(vlan_id) = self.vlan_id {
+                nft_rule.vlan_id(vlan_id.to_string());
+            }
+
+            let (mut expose_v4, mut expose_v6) = match host_ip {
+                Some(IpAddr::V4(host_ip)) => {
+                    nft_rule.destination_address(host_ip.to_string());
+                    (true, false)
+                }
+                Some(IpAddr::V6(host_ip)) => {
+                    nft_rule.destination_address_v6(host_ip.to_string());
+                    (false, true)
+                }
+                None => (true, true),
+            };
+            match restricted_family(&self.families) {
+                Some(AddressFamily::V4) => expose_v6 = false,
+                Some(AddressFamily::V6) => expose_v4 = false,
+                None => {}
+            }
+            // Families without external network interface are not exposed, unless the rule
+            // explicitly applies to all interfaces.
+            if inherited {
+                expose_v4 &= external_network_interface.is_some();
+                expose_v6 &= external_network_interface_v6.is_some();
+            }
+
+            // If source CIDRs have been specified, only the traffic from them is accepted, and
+            // only for the families they were specified for.
+            let restricted_sources = self.source_cidr_v4.is_some() || self.source_cidr_v6.is_some();
+            let mut nft_rules = Vec::new();
+            if expose_v4
+                && expose_v6
+                && external_network_interface == external_network_interface_v6
+                && !restricted_sources
+            {
+                if let Some(ref external_network_interface) = external_network_interface {
+                    nft_rule.in_interface(external_network_interface);
+                }
+                nft_rules.push(nft_rule);
+            } else {
+                for &(family, expose, external_network_interface) in &[
+                    (AddressFamily::V4, expose_v4, &external_network_interface),
+                    (AddressFamily::V6, expose_v6, &external_network_interface_v6),
+                ] {
+                    if !expose {
+                        continue;
+                    }
+                    let mut nft_family_rule = nft_rule.clone();
+                    if let Some(external_network_interface) = external_network_interface {
+                        nft_family_rule.in_interface(external_network_interface);
+                    }
+                    match (family, &self.source_cidr_v4, &self.source_cidr_v6) {
+                        (AddressFamily::V4, Some(source_cidrs), _) => {
+                            for source_cidr in resolve_source_cidrs(
+                                ctx,
+                                &mut rules,
+                                source_cidrs,
+                                AddressFamily::V4,
+                                &[Family::Inet],
+                            )? {
+                                let mut nft_source_rule = nft_family_rule.clone();
+                                nft_source_rule.source_address(source_cidr);
+                                nft_rules.push(nft_source_rule);
+                            }
+                        }
+                        (AddressFamily::V6, _, Some(source_cidrs)) => {
+                            for source_cidr in resolve_source_cidrs(
+                                ctx,
+                                &mut rules,
+                                source_cidrs,
+                                AddressFamily::V6,
+                                &[Family::Inet],
+                            )? {
+                                let mut nft_source_rule = nft_family_rule.clone();
+                                nft_source_rule.source_address_v6(source_cidr);
+                                nft_rules.push(nft_source_rule);
+                            }
+                        }
+                        _ if restricted_sources => {}
+                        _ => {
+                            if nft_family_rule.family().is_none() {
+                                nft_family_rule.nfproto(family.nfproto());
+                            }
+                            nft_rules.push(nft_family_rule);
+                        }
+                    }
+                }
+            }
+
+            for nft_rule in &nft_rules {
+                for rule in build_verdict_rules(nft_rule, self.forward_verdict())? {
+                    debug!(ctx.logger, "Add input rule";
+                           o!("part" => "wider_world_to_container",
+                              "rule" => &rule));
+                    rules.push(nftables::add_rule(Family::Inet, "dfw", "input", &rule));
+                }
+            }
+        }
+
+        Ok(Some(rules))
+    }
+
+    fn apply_source_cidrs_v4(
+        &self,
+        ctx: &ProcessContext,
+        rules: &mut Vec<String>,
+        source_cidrs: &[String],
+        nft_forward_rule: RuleBuilder,
+        nft_dnat_rule: Option<RuleBuilder>,
+    ) -> Result<()> {
+        debug!(ctx.logger, "Generate extended FORWARD rules, source CIDRs (IPv4) were specified";
+               o!("args" => format!("{:?}", nft_dnat_rule),
+                  "source_cidrs" => source_cidrs.join(", ")));
+        let families: &[Family] = if nft_dnat_rule.is_some() {
+            &[Family::Inet, Family::Ip]
+        } else {
+            &[Family::Inet]
+        };
+        let source_cidrs =
+            resolve_source_cidrs(ctx, rules, source_cidrs, AddressFamily::V4, families)?;
+        for additional_forward_rule in source_cidrs
+            .iter()
+            .map(|source_cidr| {
+                let mut forward_rule = nft_forward_rule.clone();
+                forward_rule.source_address(source_cidr);
+                forward_rule
+            })
+            .map(|forward_rule| build_verdict_rules(&forward_rule, self.forward_verdict()))
+            .collect::<Result<Vec<_>>>()?
+            .into_iter()
+            .flatten()
+        {
+            debug!(ctx.logger, "Add FORWARD rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => &additional_forward_rule));
+            rules.push(nftables::add_rule(
+                Family::Inet,
+                "dfw",
+                "forward",
+                &additional_forward_rule,
+            ));
+        }
+        let nft_dnat_rule = match nft_dnat_rule {
+            Some(nft_dnat_rule) => nft_dnat_rule,
+            None => return Ok(()),
+        };
+        for additional_dnat_rule in source_cidrs
+            .iter()
+            .map(|source_cidr| {
+                let mut dnat_rule = nft_dnat_rule.clone();
+                dnat_rule.source_address(source_cidr);
+                dnat_rule
+            })
+            .map(|dnat_rule| dnat_rule.build())
+            .collect::<Result<Vec<_>>>()?
+        {
+            debug!(ctx.logger, "Add DNAT rule";
+                   o!("part" => "wider_world_to_container",
+                      "rule" => &additional_dnat_rule));
+            rules.push(nftables::add_rule(
+                Family::Ip,
+                "dfw",
+                "prerouting",
+                &additional_dnat_rule,
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn apply_source_cidrs_v6(
+        &self,
+        ctx: &ProcessContext,
+        rules: &mut Vec<String>,
+        source_cidrs: &[String],
+        nft_mark_rule: RuleBuilder,
+    ) -> Result<()> {
+        debug!(ctx.logger, "Generate extended prerouting rules, source CIDRs (IPv6) were specified";
+               o!("args" => format!("{:?}", nft_mark_rule),
+                  "source_cidrs" => source_cidrs.join(", ")));
+        let source_cidrs =
+            resolve_source_cidrs(ctx, rules, source_cidrs, AddressFamily::V6, &[Family::Ip6])?;
+        for additional_mark_rule in source_cidrs
+            .iter()
+            .map(|source_cidr| {
+                let mut mark_rule = nft_mark_rule.clone();
+                mark_rule.source_address_v6(source_cidr);
+                mark_rule
+            })
+            .map(|dnat_rule| dnat_rule.build())
+            .collect::<Result<Vec<_>>>()?
+        {
+            debug!(ctx.logger, "Add mark rule";
+                            o!("part" => "wider_world_to_container",
+                               "rule" => &additional_mark_rule));
+            rules.push(nftables::add_rule(
+                Family::Ip6,
+                "dfw",
+                "prerouting",
+                &additional_mark_rule,
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Process for ContainerDNAT {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !section_enabled(ctx, "container_dnat", self.enabled) {
+            return Ok(None);
+        }
+
+        if self.rules.is_some() {
+            debug!(ctx.logger, "Process rules";
+                o!("part" => "container_dnat"));
+            process_section_rules(ctx, Section::ContainerDNAT, &self.rules)
+        } else {
+            trace!(ctx.logger, "No rules";
+                    o!("part" => "container_dnat"));
+            Ok(None)
+        }
+    }
+}
+
+impl Process for ContainerDNATRule {
+    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
+        if !ctx.condition_holds(&self.when)? {
+            debug!(ctx.logger, "Skip rule, condition does not hold";
+                   o!("part" => "container_dnat",
+                      "condition" => format!("{:?}", self.when)));
+            return Ok(None);
+        }
+
+        debug!(ctx.logger, "Process rule";
+                   o!("part" => "container_dnat",
+                      "rule" => format!("{:?}", self)));
+        let mut expose_ports = Vec::new();
+        for expose_port in &self.expose_port {
+            if expose_port.published {
+                bail!("published ports can only be exposed to the wider world");
+            }
+            if expose_port.exposed {
+                expose_ports.extend(resolve_image_ports(
+                    ctx,
+                    &self.dst_container,
+                    &self.dst_network,
+                    expose_port,
+                )?);
+            } else {
+                expose_ports.push(expose_port.clone());
+            }
+        }
+
+        let mut rules = Vec::new();
+        for expose_port in &expose_ports {
+            let mut nft_rule = RuleBuilder::default();
+            if ctx.dnat_new_only() {
+                nft_rule.ct_state("new");
+            }
+
+            if let Some(ref network) = self.src_network {
+                if let Some(network) = ctx.network_map.get(network) {
+                    trace!(ctx.logger, "Got network";
+                               o!("network_name" => &network.name,
+                                  "network" => format!("{:?}", network)));
+
+                    let bridge_name = get_bridge_name(&network.id)?;
+                    trace!(ctx.logger, "Got bridge name";
+                               o!("network_name" => &network.name,
+                                  "bridge_name" => &bridge_name));
+
+                    nft_rule.in_interface(&bridge_name);
+
+                    if let Some(ref src_container) = self.src_container {
+                        if let Some(src_network) =
+                            get_network_for_container(ctx, src_container, network)?
+                        {
+                            trace!(ctx.logger, "Got source network";
+                                       o!("network_name" => &network.name,
+                                          "src_network" => format!("{:?}", src_network)));
+
+                            let bridge_name = get_bridge_name(&network.id)?;
+                            trace!(ctx.logger, "Got bridge name";
+                                       o!("network_name" => &network.name,
+                                          "bridge_name" => &bridge_name));
+
+                            nft_rule
+                                .in_interface(&bridge_name)
+                                .source_address(ipv4_address_match(&src_network)?);
+                        }
+                    }
+                }
+            }
+
+            let network = match ctx.network_map.get(&self.dst_network) {
+                Some(network) => network,
+                None => return Ok(None),
+            };
+            let dst_network = match get_network_for_container(ctx, &self.dst_container, network)? {
+                Some(dst_network) => dst_network,
+                None => return Ok(None),
+            };
+            trace!(ctx.logger, "Got destination network";
+                       o!("network_name" => &network.name,
+                          "dst_network" => format!("{:?}", dst_network)));
+
+            let bridge_name = get_bridge_name(&network.id)?;
+            trace!(ctx.logger, "Got bridge name";
+                       o!("network_name" => &network.name,
+                          "bridge_name" => &bridge_name));
+
+            nft_rule.out_interface(&bridge_name);
+
+            match expose_port.host_ip {
+                Some(IpAddr::V4(host_ip)) => {
+                    nft_rule.destination_address(host_ip.to_string());
+                }
+                Some(IpAddr::V6(_)) => {
+                    // DNAT is only supported for IPv4
+                    trace!(ctx.logger, "Skip exposed port restricted to IPv6 host address";
+                           o!("part" => "container_dnat",
+                              "expose_port" => format!("{:?}", expose_port)));
+                    continue;
+                }
+                None => {}
+            }
+
+            let destination_port =
+                match (expose_port.container_port_range, expose_port.container_port) {
+                    (Some(container_port_range), _) => container_port_range.to_string(),
+                    (None, Some(destination_port)) => destination_port.to_string(),
+                    (None, None) => expose_port.host_port.to_string(),
+                };
+            nft_rule.destination_port(&destination_port);
+            nft_rule.dnat(format!(
+                "{}:{}",
+                dst_network
+                    .ipv4_address
+                    .split('/')
+                    .next()
+                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
+                destination_port
+            ));
+
+            let rule = nft_rule.build()?;
+            debug!(ctx.logger, "Add prerouting rule";
+                       o!("part" => "container_dnat",
+                          "rule" => &rule));
+
+            // Apply the rule
+            rules.push(nftables::add_rule(Family::Ip, "dfw", "prerouting", &rule));
+            // TODO: verify what is needed for ipt6
+        }
+
+        Ok(Some(rules))
+    }
+
+    fn provenance(&self) -> Option<&Provenance> {
+        self.provenance.as_ref()
+    }
+}
+
+/// Enclosing struct to manage rule processing.
+pub struct ProcessContext<'a> {
+    inventory: InventorySnapshot<'a>,
+    dfw: &'a DFW,
+    container_map: Map<String, Container>,
+    network_map: BTreeMap<String, Network>,
+    external_network_interfaces: Option<FamilyInterfaces>,
+    logger: Logger,
+    dry_run: bool,
+    current_ruleset: Option<String>,
+    host_facts: HostFacts,
+    sections: Sections,
+    container_aliases: RefCell<Option<ContainerAliases>>,
+    image_ports: RefCell<Option<ImagePorts>>,
+    resolved_hostnames: RefCell<BTreeMap<String, Option<Vec<IpAddr>>>>,
+    auto_host_ports: BTreeMap<(String, u16), u16>,
+    generated_rules: Cell<usize>,
+}
+
+impl<'a> ProcessContext<'a> {
+    /// Create a new instance of `ProcessDFW` for rule processing.
+    pub fn new(
+        docker: &'a Docker,
+        dfw: &'a DFW,
+        processing_options: &'a ProcessingOptions,
+        logger: &'a Logger,
+        dry_run: bool,
+    ) -> Result<ProcessContext<'a>> {
+        Self::with_inventory(
+            docker_inventory(docker, dfw, processing_options),
+            dfw,
+            processing_options.sections,
+            logger,
+            dry_run,
+        )
+    }
+
+    /// Create a new instance of `ProcessDFW` for rule processing, keeping the rules of containers
+    /// that vanished within the grace period configured through
+    /// [`Defaults.rule_removal_grace_s`](../types/struct.Defaults.html#structfield.rule_removal_grace_s).
+    ///
+    /// The containers are tracked in the given history, which has to be shared by all reconciles.
+    pub fn with_history(
+        docker: &'a Docker,
+        dfw: &'a DFW,
+        processing_options: &'a ProcessingOptions,
+        history: &'a ContainerHistory,
+        logger: &'a Logger,
+        dry_run: bool,
+    ) -> Result<ProcessContext<'a>> {
+        let grace_period = dfw
+            .defaults
+            .as_ref()
+            .map_or(0, |defaults| defaults.rule_removal_grace_s);
+        let inventory = GracePeriodInventory::new(
+            docker_inventory(docker, dfw, processing_options),
+            history,
+            Duration::from_secs(grace_period),
+            Instant::now(),
+        );
+        Self::with_inventory(
+            Box::new(inventory),
+            dfw,
+            processing_options.sections,
+            logger,
+            dry_run,
+        )
+    }
+
+    /// Create a new instance of `ProcessDFW` for rule processing, retrieving all external data from
+    /// the given inventory.
+    ///
+    /// The data is retrieved once, when the instance is created, see
+    /// [`InventorySnapshot`](../inventory/struct.InventorySnapshot.html).
+    pub fn with_inventory(
+        inventory: Box<dyn ContainerInventory + 'a>,
+        dfw: &'a DFW,
+        sections: Sections,
+        logger: &Logger,
+        dry_run: bool,
+    ) -> Result<ProcessContext<'a>> {
+        let logger = logger.new(o!());
+
+        let inventory = InventorySnapshot::capture(inventory)?;
+        let containers = inventory.containers()?;
+        debug!(logger, "Got list of containers";
+               o!("containers" => format!("{:#?}", containers)));
+
+        let container_map =
+            get_container_map(&containers)?.ok_or_else(|| format_err!("no containers found"))?;
+        trace!(logger, "Got map of containers";
+               o!("container_map" => format!("{:#?}", container_map)));
+
+        let networks = inventory.networks()?;
+        debug!(logger, "Got list of networks";
+               o!("networks" => format!("{:#?}", networks)));
+
+        let network_map =
+            get_network_map(&networks)?.ok_or_else(|| format_err!("no networks found"))?;
+        trace!(logger, "Got map of networks";
+               o!("network_map" => format!("{:#?}", network_map)));
+
+        let host_facts = inventory.host_facts()?;
+        debug!(logger, "Collected host facts";
+               o!("host_facts" => format!("{:?}", host_facts)));
+
+        let external_network_interfaces = match dfw.defaults.as_ref() {
+            Some(defaults) if defaults.auto_external_network_interfaces() => {
+                if host_facts.default_route_interfaces.is_empty() {
+                    bail!(
+                        "external network interfaces cannot be determined automatically, \
+                         the host has no default route"
+                    );
+                }
+                debug!(logger, "Determined external network interfaces from default routes";
+                       o!("external_network_interfaces" =>
+                          host_facts.default_route_interfaces.join(", ")));
+                Some(host_facts.default_route_interfaces.clone().into())
+            }
+            defaults => defaults.and_then(|d| d.external_network_interfaces.clone()),
+        };
+
+        let current_ruleset = inventory.current_ruleset();
+
+        let auto_host_ports = assign_auto_host_ports(dfw)?;
+        debug!(logger, "Assigned host ports automatically";
+               o!("auto_host_ports" => format!("{:?}", auto_host_ports)));
+
+        Ok(ProcessContext {
+            inventory,
+            dfw,
+            container_map,
+            network_map,
+            external_network_interfaces,
+            logger,
+            dry_run,
+            current_ruleset,
+            host_facts,
+            sections,
+            container_aliases: RefCell::new(None),
+            image_ports: RefCell::new(None),
+            resolved_hostnames: RefCell::new(BTreeMap::new()),
+            auto_host_ports,
+            generated_rules: Cell::new(0),
+        })
+    }
+
+    /// Start the processing using the configuration given at creation.
+    ///
+    /// The rules are applied through a single invocation of `nft -f`, i.e. within a single
+    /// transaction, see [`process_with`](#method.process_with).
+    pub fn process(&self) -> Result<Option<RuleSet>> {
+        let nft_binary = nft_binary(self.dfw);
+        info!(self.logger, "Applying rules (using nft)";
+              o!("netns" => format!("{:?}", nft_binary.netns),
+                 "nft_path" => format!("{:?}", nft_binary.nft_path)));
+        self.process_with(&nft_binary)
+    }
+
+    /// Start the processing using the configuration given at creation, applying the rules through
+    /// the given script runner.
+    ///
+    /// All rules are assembled into a single script which is passed to the runner exactly once,
+    /// such that either all rules are applied or none of them are.
+    ///
+    /// The rule set that was applied is returned, `None` during a dry run.
+    pub fn process_with(&self, runner: &dyn ScriptRunner) -> Result<Option<RuleSet>> {
+        let ruleset = generate_ruleset(self.dfw, self)?;
+        let rules = ruleset_commands(self.dfw, self, &ruleset)?;
+        if self.dry_run {
+            info!(self.logger, "Performing dry-run, will not update any rules");
+            return Ok(None);
+        }
+
+        // Resolving the addresses as late as possible keeps the window in which the rules can
+        // reference stale addresses small.
+        self.verify_addresses(&rules)?;
+
+        let script = nftables::script(&rules);
+        debug!(self.logger, "Applying script";
+               o!("rules" => rules.len()));
+        trace!(self.logger, "Script to apply";
+               o!("script" => &script));
+        runner.run_script(&script)?;
+
+        Ok(Some(ruleset))
+    }
+
+    /// Verify that the container addresses the rules reference are still current, i.e. that no
+    /// container got a new address since the inventory was captured.
+    ///
+    /// If an address is stale, `DFWError::StaleAddresses` is returned. The rules have to be
+    /// generated again from a new instance of `ProcessContext` then, which captures the inventory
+    /// anew.
+    pub fn verify_addresses(&self, rules: &[String]) -> Result<()> {
+        let stale_addresses = self
+            .inventory
+            .stale_addresses()?
+            .into_iter()
+            .filter(|address| rules.iter().any(|rule| references_address(rule, address)))
+            .collect::<Vec<_>>();
+        if stale_addresses.is_empty() {
+            return Ok(());
+        }
+
+        warn!(self.logger, "Container addresses changed since the inventory was captured";
+              o!("stale_addresses" => stale_addresses.join(", ")));
+        Err(DFWError::StaleAddresses {
+            addresses: stale_addresses.join(", "),
+        }
+        .into())
+    }
+
+    /// Generate the rules for the configuration given at creation without applying them,
+    /// explaining the origin of every rule, see [`explain`](fn.explain.html).
+    pub fn explain(&self) -> Result<Vec<(String, Explanation)>> {
+        explain_ruleset(self.dfw, self)
+    }
+
+    /// Generate the rules for the configuration given at creation and apply them within a
+    /// throwaway network namespace, see [`RuleSet::self_test`](struct.RuleSet.html#method.self_test).
+    pub fn self_test(&self) -> Result<()> {
+        let ruleset = generate_ruleset(self.dfw, self)?;
+        info!(self.logger, "Applying rules in throwaway network namespace (using nft)";
+              o!("rules" => ruleset.commands().len()));
+        ruleset.self_test()
+    }
+
+    /// Get the host port of the exposed port of the container, resolving automatically assigned
+    /// host ports.
+    fn host_port(&self, container: &ContainerSelector, expose_port: &ExposePort) -> Result<u16> {
+        if expose_port.host_port != AUTO_HOST_PORT {
+            return Ok(expose_port.host_port);
+        }
+
+        expose_port
+            .container_port
+            .and_then(|container_port| {
+                self.auto_host_ports
+                    .get(&(container.to_string(), container_port))
+                    .copied()
+            })
+            .ok_or_else(|| {
+                format_err!(
+                    "no host port was assigned to exposed port {:?} of container {}",
+                    expose_port,
+                    container
+                )
+            })
+    }
+
+    /// Check if the `nft` binary of the host supports constructs introduced with the given version.
+    ///
+    /// This is never the case if `compat_mode` is enabled. If the version of `nft` is unknown, it
+    /// is assumed to support everything.
+    fn nft_supports(&self, version: NftVersion) -> bool {
+        let compat_mode = self
+            .dfw
+            .defaults
+            .as_ref()
+            .is_some_and(|defaults| defaults.compat_mode);
+        !compat_mode
+            && self
+                .host_facts
+                .nft_version
+                .is_none_or(|nft_version| nft_version >= version)
+    }
+
+    /// Check if the rules are to be annotated with their location in the configuration.
+    fn annotate_rules(&self) -> bool {
+        self.dfw
+            .defaults
+            .as_ref()
+            .is_some_and(|defaults| defaults.annotate_rules)
+    }
+
+    /// Count the rules generated for a rule of the configuration, failing if the limit of
+    /// [`Defaults.max_generated_rules`](../types/struct.Defaults.html#structfield.max_generated_rules)
+    /// is exceeded.
+    fn count_generated_rules(
+        &self,
+        section: Section,
+        index: usize,
+        provenance: Option<&Provenance>,
+        count: usize,
+    ) -> Result<()> {
+        let generated_rules = self.generated_rules.get() + count;
+        self.generated_rules.set(generated_rules);
+
+        let max_generated_rules = match self
+            .dfw
+            .defaults
+            .as_ref()
+            .and_then(|defaults| defaults.max_generated_rules)
+        {
+            Some(max_generated_rules) => max_generated_rules,
+            None => return Ok(()),
+        };
+        if generated_rules > max_generated_rules {
+            bail!(
+                "rule {} of section `{}`{} expanded to {} rules, exceeding the limit of {} \
+                 generated rules set through `max_generated_rules`",
+                index + 1,
+                section,
+                provenance.map_or_else(String::new, |provenance| format!(" ({})", provenance)),
+                count,
+                max_generated_rules
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Check if the provided string-marker is part of the current ruleset (if available).
+    pub fn marker_in_current_ruleset(&self, marker: &str) -> bool {
+        self.current_ruleset
+            .as_ref()
+            .map(|current_ruleset| current_ruleset.contains(marker))
+            .unwrap_or(false)
+    }
+
+    /// Get the primary external network interface of the given family, see
+    /// [`Defaults.external_network_interfaces`](../types/struct.Defaults.html#structfield.external_network_interfaces).
+    fn primary_external_network_interface(&self, family: AddressFamily) -> Option<&String> {
+        self.external_network_interfaces
+            .as_ref()
+            .and_then(|external_network_interfaces| external_network_interfaces.primary(family))
+    }
+
+    /// Check if the DNAT rules should only apply to new connections, see
+    /// [`Defaults.dnat_new_only`](../types/struct.Defaults.html#structfield.dnat_new_only).
+    fn dnat_new_only(&self) -> bool {
+        self.dfw
+            .defaults
+            .as_ref()
+            .is_none_or(|defaults| defaults.dnat_new_only)
+    }
+
+    /// Get the protocol of the conntrack timeout policy of the given name, see
+    /// [`Defaults.ct_timeouts`](../types/struct.Defaults.html#structfield.ct_timeouts).
+    fn ct_timeout_protocol(&self, name: &str) -> Result<&'static str> {
+        let policy = self
+            .dfw
+            .defaults
+            .as_ref()
+            .and_then(|defaults| defaults.ct_timeouts.as_ref())
+            .and_then(|ct_timeouts| ct_timeouts.get(name))
+            .ok_or_else(|| format_err!("conntrack timeout policy `{}` is not defined", name))?;
+        let (protocol, _) =
+            ct_timeout_policy(name, policy).map_err(|problem| format_err!("{}", problem))?;
+
+        Ok(protocol)
+    }
+
+    /// Get the policy for hostnames that cannot be resolved, see
+    /// [`Defaults.dns_failure_policy`](../types/struct.Defaults.html#structfield.dns_failure_policy).
+    fn dns_failure_policy(&self) -> DnsFailurePolicy {
+        self.dfw
+            .defaults
+            .as_ref()
+            .map(|defaults| defaults.dns_failure_policy)
+            .unwrap_or_default()
+    }
+
+    /// Check if the containers may reach the embedded DNS server of Docker, see
+    /// [`Defaults.allow_embedded_dns`](../types/struct.Defaults.html#structfield.allow_embedded_dns).
+    fn allow_embedded_dns(&self) -> bool {
+        self.dfw
+            .defaults
+            .as_ref()
+            .is_none_or(|defaults| defaults.allow_embedded_dns)
+    }
+
+    /// Check if the provided rule-condition holds for the host DFW is running on. If no condition is
+    /// given, it always holds.
+    pub fn condition_holds(&self, condition: &Option<Condition>) -> Result<bool> {
+        match condition {
+            Some(condition) => condition.holds(&self.host_facts),
+            None => Ok(true),
+        }
+    }
+
+    /// Get the names of the containers and networks the addresses, subnets and bridges of the
+    /// inventory belong to, see
+    /// [`RuleSet::resolved_names`](struct.RuleSet.html#structfield.resolved_names).
+    fn resolved_names(&self) -> BTreeMap<String, String> {
+        let container_names = self
+            .container_map
+            .values()
+            .filter_map(|container| {
+                let name = container.names.first()?.trim_start_matches('/');
+                Some((container.id.as_str(), name))
+            })
+            .collect::<BTreeMap<_, _>>();
+
+        let mut resolved_names = BTreeMap::new();
+        for network in self.network_map.values() {
+            let network_name = format!("network:{}", network.name);
+            if let Ok(bridge_name) = get_bridge_name(&network.id) {
+                resolved_names.insert(bridge_name, network_name.clone());
+            }
+            if let Some(bridge_name) = network.options.get("com.docker.network.bridge.name") {
+                resolved_names.insert(bridge_name.to_owned(), network_name.clone());
+            }
+            for subnet in &network.subnets {
+                resolved_names.insert(subnet.to_owned(), network_name.clone());
+            }
+            for (container_id, endpoint) in &network.containers {
+                let container_name = match container_names.get(container_id.as_str()) {
+                    Some(container_name) => format!("container:{}", container_name),
+                    None => continue,
+                };
+                let ipv6_address = endpoint.ipv6_address.split('/').next().unwrap_or_default();
+                for address in endpoint
+                    .ipv4_addresses()
+                    .into_iter()
+                    .chain(Some(ipv6_address))
+                    .filter(|address| !address.is_empty())
+                {
+                    resolved_names.insert(address.to_owned(), container_name.clone());
+                }
+            }
+        }
+        resolved_names
+    }
+
+    /// Resolve the name of the container referenced by the selector on the given network.
+    ///
+    /// Aliases are retrieved from the inventory once, when the first alias is resolved. If multiple
+    /// containers carry the same alias, the first one (ordered by name) is used.
+    pub fn resolve_container(
+        &self,
+        container: &ContainerSelector,
+        network_name: &str,
+    ) -> Result<Option<String>> {
+        let alias = match container {
+            ContainerSelector::Name(name) => return Ok(Some(name.to_owned())),
+            ContainerSelector::Alias(alias) => alias,
+        };
+
+        let mut container_aliases = self.container_aliases.borrow_mut();
+        if container_aliases.is_none() {
+            let aliases = self.inventory.container_aliases()?;
+            debug!(self.logger, "Got container aliases";
+                   o!("container_aliases" => format!("{:?}", aliases)));
+            *container_aliases = Some(aliases);
+        }
+        let container_names = container_aliases
+            .as_ref()
+            .map(|aliases| aliases.resolve(network_name, alias))
+            .unwrap_or_default();
+        if container_names.len() > 1 {
+            warn!(self.logger, "Alias is carried by multiple containers, using the first one";
+                  o!("network_name" => network_name,
+                     "alias" => alias,
+                     "container_names" => format!("{:?}", container_names)));
+        }
+
+        Ok(container_names.first().cloned())
+    }
+
+    /// Get the addresses of the given family the hostname resolves to, as elements of the named
+    /// set of the hostname, see [`CIDR_DNS_PREFIX`](../types/constant.CIDR_DNS_PREFIX.html).
+    ///
+    /// Every hostname is resolved through the inventory once per reconcile. If it cannot be
+    /// resolved, the [`dns_failure_policy`](#method.dns_failure_policy) decides whether the set
+    /// keeps its elements of the current ruleset or processing fails.
+    fn resolve_hostname(
+        &self,
+        hostname: &str,
+        family: AddressFamily,
+        set: &str,
+    ) -> Result<Vec<String>> {
+        let mut resolved_hostnames = self.resolved_hostnames.borrow_mut();
+        if !resolved_hostnames.contains_key(hostname) {
+            let addresses = match self.inventory.resolve_hostname(hostname) {
+                Ok(addresses) => {
+                    debug!(self.logger, "Resolved hostname";
+                           o!("hostname" => hostname,
+                              "addresses" => format!("{:?}", addresses)));
+                    Some(addresses)
+                }
+                Err(e) => match self.dns_failure_policy() {
+                    DnsFailurePolicy::Keep => {
+                        warn!(self.logger, "Failed to resolve hostname, keeping its current addresses";
+                              o!("hostname" => hostname,
+                                 "error" => format!("{}", e)));
+                        None
+                    }
+                    DnsFailurePolicy::Error => {
+                        bail!("failed to resolve hostname `{}`: {}", hostname, e)
+                    }
+                },
+            };
+            resolved_hostnames.insert(hostname.to_owned(), addresses);
+        }
+
+        Ok(match &resolved_hostnames[hostname] {
+            Some(addresses) => addresses
+                .iter()
+                .filter(|address| match family {
+                    AddressFamily::V4 => address.is_ipv4(),
+                    AddressFamily::V6 => address.is_ipv6(),
+                })
+                .map(IpAddr::to_string)
+                .collect(),
+            None => self
+                .current_ruleset
+                .as_ref()
+                .map(|current_ruleset| parse_set_elements(current_ruleset, set))
+                .unwrap_or_default(),
+        })
+    }
+
+    /// Get the ports the image of the container declares through `EXPOSE`.
+    ///
+    /// The image ports are only retrieved from the inventory once they are first required.
+    fn image_ports(&self, container_name: &str) -> Result<Vec<ImagePort>> {
+        let mut image_ports = self.image_ports.borrow_mut();
+        if image_ports.is_none() {
+            let ports = self.inventory.image_ports()?;
+            debug!(self.logger, "Got image ports";
+                   o!("image_ports" => format!("{:?}", ports)));
+            *image_ports = Some(ports);
+        }
+
+        Ok(image_ports
+            .as_ref()
+            .map(|image_ports| image_ports.resolve(container_name))
+            .unwrap_or_default())
+    }
+}
+
+/// Option to filter the containers to be processed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ContainerFilter {
+    /// Process all containers, i.e. don't filter.
+    All,
+    /// Only process running containers.
+    Running,
+}
+
+/// Options to configure the processing procedure.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProcessingOptions {
+    /// Option to filter the containers to be processed, see
+    /// [`ContainerFilter`](enum.ContainerFilter.html).
+    pub container_filter: ContainerFilter,
+    /// Sections of the configuration to process, see [`Sections`](struct.Sections.html).
+    pub sections: Sections,
+}
+
+impl Default for ProcessingOptions {
+    fn default() -> Self {
+        ProcessingOptions {
+            container_filter: ContainerFilter::All,
+            sections: Sections::ALL,
+        }
+    }
+}
+
+impl Section {
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+
+    fn marker(self) -> String {
+        generate_marker(&["section", &self.to_string()])
     }
 }
 
-impl WiderWorldToContainerRule {
-    fn apply_source_cidrs_v4(
-        &self,
-        ctx: &ProcessContext,
-        rules: &mut Vec<String>,
-        source_cidrs: &Vec<String>,
-        nft_forward_rule: RuleBuilder,
-        nft_dnat_rule: RuleBuilder,
-    ) -> Result<()> {
-        debug!(ctx.logger, "Generate extended FORWARD rules, source CIDRs (IPv4) were specified";
-               o!("args" => format!("{:?}", nft_dnat_rule),
-                  "source_cidrs" => source_cidrs.join(", ")));
-        for additional_forward_rule in source_cidrs
+/// Set of [`Section`s](../types/enum.Section.html) to be processed.
+///
+/// If not all sections are selected, only the rules of the selected sections are replaced in the
+/// current ruleset, while the rules of all other sections are left as they are.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::process::{Section, Sections};
+/// let sections: Sections = "defaults,wider_world_to_container".parse().unwrap();
+/// assert!(sections.contains(Section::WiderWorldToContainer));
+/// assert!(!sections.contains(Section::ContainerToHost));
+/// ```
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sections(u8);
+
+impl Sections {
+    /// Set containing all sections.
+    pub const ALL: Sections = Sections(0b1111_1111);
+    /// Set containing no section.
+    pub const NONE: Sections = Sections(0);
+
+    /// Check if the section is part of the set.
+    pub fn contains(self, section: Section) -> bool {
+        self.0 & section.bit() != 0
+    }
+
+    /// Add the section to the set.
+    pub fn insert(&mut self, section: Section) {
+        self.0 |= section.bit();
+    }
+
+    /// Iterate over the sections in the set, in the order they are processed in.
+    pub fn iter(self) -> impl Iterator<Item = Section> {
+        Section::VALUES
             .iter()
-            .map(|source_cidr| {
-                let mut forward_rule = nft_forward_rule.clone();
-                forward_rule.source_address(source_cidr);
-                forward_rule
-            })
-            .map(|forward_rule| forward_rule.build())
-            .collect::<Result<Vec<_>>>()?
-        {
-            debug!(ctx.logger, "Add FORWARD rule";
-                   o!("part" => "wider_world_to_container",
-                      "rule" => &additional_forward_rule));
-            rules.push(nftables::add_rule(
-                Family::Inet,
-                "dfw",
-                "forward",
-                &additional_forward_rule,
-            ));
+            .cloned()
+            .filter(move |section| self.contains(*section))
+    }
+}
+
+impl Default for Sections {
+    fn default() -> Self {
+        Sections::ALL
+    }
+}
+
+impl From<Section> for Sections {
+    fn from(section: Section) -> Self {
+        Sections(section.bit())
+    }
+}
+
+impl FromIterator<Section> for Sections {
+    fn from_iter<I: IntoIterator<Item = Section>>(iter: I) -> Self {
+        let mut sections = Sections::NONE;
+        for section in iter {
+            sections.insert(section);
         }
-        for additional_dnat_rule in source_cidrs
-            .iter()
-            .map(|source_cidr| {
-                let mut dnat_rule = nft_dnat_rule.clone();
-                dnat_rule.source_address(source_cidr);
-                dnat_rule
+        sections
+    }
+}
+
+impl FromStr for Sections {
+    type Err = Error;
+
+    /// Parse a comma-separated list of section names.
+    fn from_str(s: &str) -> Result<Sections> {
+        s.split(',')
+            .map(str::trim)
+            .filter(|section| !section.is_empty())
+            .map(|section| {
+                Section::from_str(section).map_err(|_| format_err!("unknown section: {}", section))
             })
-            .map(|dnat_rule| dnat_rule.build())
-            .collect::<Result<Vec<_>>>()?
-        {
-            debug!(ctx.logger, "Add DNAT rule";
-                   o!("part" => "wider_world_to_container",
-                      "rule" => &additional_dnat_rule));
-            rules.push(nftables::add_rule(
-                Family::Ip,
-                "dfw",
-                "prerouting",
-                &additional_dnat_rule,
-            ));
-        }
+            .collect()
+    }
+}
 
-        Ok(())
+impl fmt::Display for Sections {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sections = self.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+        write!(f, "{}", sections.join(","))
     }
+}
 
-    fn apply_source_cidrs_v6(
-        &self,
-        ctx: &ProcessContext,
-        rules: &mut Vec<String>,
-        source_cidrs: &Vec<String>,
-        nft_mark_rule: RuleBuilder,
-    ) -> Result<()> {
-        debug!(ctx.logger, "Generate extended prerouting rules, source CIDRs (IPv6) were specified";
-               o!("args" => format!("{:?}", nft_mark_rule),
-                  "source_cidrs" => source_cidrs.join(", ")));
-        for additional_mark_rule in source_cidrs
-            .iter()
-            .map(|source_cidr| {
-                let mut mark_rule = nft_mark_rule.clone();
-                mark_rule.source_address_v6(source_cidr);
-                mark_rule
-            })
-            .map(|dnat_rule| dnat_rule.build())
-            .collect::<Result<Vec<_>>>()?
-        {
-            debug!(ctx.logger, "Add mark rule";
-                            o!("part" => "wider_world_to_container",
-                               "rule" => &additional_mark_rule));
-            rules.push(nftables::add_rule(
-                Family::Ip6,
-                "dfw",
-                "prerouting",
-                &additional_mark_rule,
-            ));
-        }
-        Ok(())
+/// Facts about the host DFW is running on, used to evaluate rule-conditions.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostFacts {
+    /// Hostname of the host.
+    pub hostname: String,
+    /// Environment variables DFW is running with.
+    pub env: Map<String, String>,
+    /// Version of the `nft` binary, `None` if it is unknown. An unknown version is assumed to
+    /// support all constructs DFW generates.
+    pub nft_version: Option<NftVersion>,
+    /// Interfaces carrying the default routes of the host, see
+    /// [`default_route_interfaces`](fn.default_route_interfaces.html).
+    pub default_route_interfaces: Vec<String>,
+}
+
+impl HostFacts {
+    /// Collect the facts of the host DFW is currently running on.
+    pub fn collect() -> Result<HostFacts> {
+        let hostname = hostname()?;
+
+        // The version is only used to pick compatible constructs, failing to detect it is not fatal.
+        let nft_version = Command::new("nft")
+            .arg("--version")
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .and_then(|output| String::from_utf8_lossy(&output.stdout).parse().ok());
+
+        // Without routing tables, external network interfaces cannot be determined automatically.
+        // This is reported once they are required.
+        let route = fs::read_to_string(PROC_NET_ROUTE).unwrap_or_default();
+        let ipv6_route = fs::read_to_string(PROC_NET_IPV6_ROUTE).unwrap_or_default();
+
+        Ok(HostFacts {
+            hostname,
+            env: std::env::vars().collect(),
+            nft_version,
+            default_route_interfaces: default_route_interfaces(&route, &ipv6_route),
+        })
     }
 }
 
-impl Process for ContainerDNAT {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        if self.rules.is_some() {
-            debug!(ctx.logger, "Process rules";
-                o!("part" => "container_dnat"));
-            self.rules.process(&ctx)
-        } else {
-            trace!(ctx.logger, "No rules";
-                    o!("part" => "container_dnat"));
-            Ok(None)
+/// IPv4 routing table of the host.
+const PROC_NET_ROUTE: &str = "/proc/net/route";
+/// IPv6 routing table of the host.
+const PROC_NET_IPV6_ROUTE: &str = "/proc/net/ipv6_route";
+/// Flag of a route that is up, see `linux/route.h`.
+const RTF_UP: u32 = 0x0001;
+/// Flag of a route rejecting packets, e.g. an `unreachable` route, see `linux/route.h`.
+const RTF_REJECT: u32 = 0x0200;
+
+/// Get the interfaces carrying the default routes of the host, given its IPv4 and IPv6 routing
+/// tables in the format of `/proc/net/route` and `/proc/net/ipv6_route`.
+///
+/// The interfaces are ordered by the metric of their routes, the interfaces of IPv4 routes before
+/// the ones of IPv6 routes. Routes that are down or reject packets (like the `unreachable` default
+/// route of the loopback interface) are ignored.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::process::default_route_interfaces;
+/// let route = "\
+/// Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+/// eth0\t00000000\t0102000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+/// eth0\t0002000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+/// ";
+/// assert_eq!(vec!["eth0".to_owned()], default_route_interfaces(route, ""));
+/// ```
+pub fn default_route_interfaces(route: &str, ipv6_route: &str) -> Vec<String> {
+    let usable = |flags: &str, radix| {
+        u32::from_str_radix(flags, radix)
+            .map(|flags| flags & RTF_UP != 0 && flags & RTF_REJECT == 0)
+            .unwrap_or(false)
+    };
+
+    let mut routes_v4 = route
+        .lines()
+        .skip(1)
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                [interface, "00000000", _, flags, _, _, metric, "00000000", ..]
+                    if usable(flags, 16) =>
+                {
+                    Some((metric.parse::<u32>().ok()?, *interface))
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    routes_v4.sort_by_key(|(metric, _)| *metric);
+
+    let mut routes_v6 = ipv6_route
+        .lines()
+        .filter_map(|line| {
+            let fields = line.split_whitespace().collect::<Vec<_>>();
+            match fields.as_slice() {
+                [destination, "00", _, _, _, metric, _, _, flags, interface]
+                    if destination.chars().all(|c| c == '0') && usable(flags, 16) =>
+                {
+                    Some((u32::from_str_radix(metric, 16).ok()?, *interface))
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>();
+    routes_v6.sort_by_key(|(metric, _)| *metric);
+
+    let mut interfaces: Vec<String> = Vec::new();
+    for (_, interface) in routes_v4.into_iter().chain(routes_v6) {
+        if !interfaces.iter().any(|known| known == interface) {
+            interfaces.push(interface.to_owned());
         }
     }
+    interfaces
 }
 
-impl Process for ContainerDNATRule {
-    fn process(&self, ctx: &ProcessContext) -> Result<Option<Vec<String>>> {
-        debug!(ctx.logger, "Process rule";
-                   o!("part" => "container_dnat",
-                      "rule" => format!("{:?}", self)));
-        let mut rules = Vec::new();
-        for expose_port in &self.expose_port {
-            let mut nft_rule = RuleBuilder::default();
+impl Condition {
+    /// Evaluate whether this condition holds given the provided host facts.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::process::HostFacts;
+    /// # use dfw::types::Condition;
+    /// let facts = HostFacts {
+    ///     hostname: "edge-1".to_owned(),
+    ///     env: vec![("ROLE".to_owned(), "edge".to_owned())]
+    ///         .into_iter()
+    ///         .collect(),
+    ///     nft_version: None,
+    ///     default_route_interfaces: Vec::new(),
+    /// };
+    /// let condition = Condition {
+    ///     hostname: Some("edge-*".to_owned()),
+    ///     env: Some(vec!["ROLE=edge".to_owned()]),
+    /// };
+    /// assert!(condition.holds(&facts).unwrap());
+    /// ```
+    pub fn holds(&self, facts: &HostFacts) -> Result<bool> {
+        if let Some(hostname) = &self.hostname {
+            let pattern = glob::Pattern::new(hostname)
+                .with_context(|_| format!("invalid hostname pattern `{}`", hostname))?;
+            if !pattern.matches(&facts.hostname) {
+                return Ok(false);
+            }
+        }
 
-            if let Some(ref network) = self.src_network {
-                if let Some(network) = ctx.network_map.get(network) {
-                    trace!(ctx.logger, "Got network";
-                               o!("network_name" => &network.Name,
-                                  "network" => format!("{:?}", network)));
+        if let Some(env) = &self.env {
+            for variable in env {
+                let mut split = variable.splitn(2, '=');
+                let name = split.next().unwrap_or_default();
+                let holds = match (facts.env.get(name), split.next()) {
+                    (Some(actual), Some(expected)) => actual == expected,
+                    (Some(_), None) => true,
+                    (None, _) => false,
+                };
+                if !holds {
+                    return Ok(false);
+                }
+            }
+        }
 
-                    let bridge_name = get_bridge_name(&network.Id)?;
-                    trace!(ctx.logger, "Got bridge name";
-                               o!("network_name" => &network.Name,
-                                  "bridge_name" => &bridge_name));
+        Ok(true)
+    }
+}
 
-                    nft_rule.in_interface(&bridge_name);
+/// Get the number of bytes of the minimum size of a connection, as matched by `ct bytes`.
+fn ct_bytes(min_ct_bytes: &str) -> Result<u64> {
+    size_in_bytes(min_ct_bytes).ok_or_else(|| {
+        format_err!(
+            "minimum connection size '{}' is not a valid size, e.g. '100 mbytes'",
+            min_ct_bytes
+        )
+    })
+}
 
-                    if let Some(ref src_container) = self.src_container {
-                        if let Some(src_network) = get_network_for_container(
-                            ctx.docker,
-                            &ctx.container_map,
-                            src_container,
-                            &network.Id,
-                        )? {
-                            trace!(ctx.logger, "Got source network";
-                                       o!("network_name" => &network.Name,
-                                          "src_network" => format!("{:?}", src_network)));
+fn get_bridge_name(network_id: &str) -> Result<String> {
+    if network_id.len() < 12 {
+        bail!("network has to be longer than 12 characters");
+    }
+    Ok(format!("br-{}", &network_id[..12]))
+}
 
-                            let bridge_name = get_bridge_name(&network.Id)?;
-                            trace!(ctx.logger, "Got bridge name";
-                                       o!("network_name" => &network.Name,
-                                          "bridge_name" => &bridge_name));
+fn get_network_for_container(
+    ctx: &ProcessContext,
+    container: &ContainerSelector,
+    network: &Network,
+) -> Result<Option<NetworkEndpoint>> {
+    let container_name = match ctx.resolve_container(container, &network.name)? {
+        Some(container_name) => container_name,
+        None => return Ok(None),
+    };
+    Ok(ctx
+        .container_map
+        .get(&container_name)
+        .and_then(|container| network.containers.get(&container.id))
+        .cloned())
+}
 
-                            nft_rule.in_interface(&bridge_name).source_address(
-                                src_network
-                                    .IPv4Address
-                                    .split('/')
-                                    .next()
-                                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                            );
-                        }
+/// Fail if a container referenced by the rule is missing and the rule requests to fail in that case
+/// through `on_missing`, see [`OnMissing`](../types/enum.OnMissing.html).
+///
+/// Rules whose condition does not hold are not checked, since they are skipped regardless.
+fn check_missing_containers<T: Explain>(
+    ctx: &ProcessContext,
+    rule: &T,
+    on_missing: Option<OnMissing>,
+    when: &Option<Condition>,
+) -> Result<()> {
+    if on_missing.unwrap_or_default() != OnMissing::Error || !ctx.condition_holds(when)? {
+        return Ok(());
+    }
+
+    for (network_name, container) in rule.endpoints() {
+        let container = match container {
+            Some(container) => container,
+            None => continue,
+        };
+        let present = match network_name {
+            Some(network_name) if network_name != WILDCARD_NETWORK => {
+                match ctx.network_map.get(network_name) {
+                    Some(network) => get_network_for_container(ctx, container, network)?.is_some(),
+                    None => false,
+                }
+            }
+            // Without a specific network, the container only has to exist on any network.
+            _ => {
+                let mut present = false;
+                for network in ctx.network_map.values() {
+                    if get_network_for_container(ctx, container, network)?.is_some() {
+                        present = true;
+                        break;
                     }
                 }
+                present
             }
+        };
+        if !present {
+            bail!(
+                "container `{}` of rule is missing on network `{}`, which the rule requires \
+                 through `on_missing = \"error\"`",
+                container,
+                network_name.unwrap_or(WILDCARD_NETWORK)
+            );
+        }
+    }
 
-            let network = match ctx.network_map.get(&self.dst_network) {
-                Some(network) => network,
-                None => return Ok(None),
-            };
-            let dst_network = match get_network_for_container(
-                ctx.docker,
-                &ctx.container_map,
-                &self.dst_container,
-                &network.Id,
-            )? {
-                Some(dst_network) => dst_network,
-                None => return Ok(None),
-            };
-            trace!(ctx.logger, "Got destination network";
-                       o!("network_name" => &network.Name,
-                          "dst_network" => format!("{:?}", dst_network)));
+    Ok(())
+}
 
-            let bridge_name = get_bridge_name(&network.Id)?;
-            trace!(ctx.logger, "Got bridge name";
-                       o!("network_name" => &network.Name,
-                          "bridge_name" => &bridge_name));
+/// Check if the rule references the address, e.g. as `ip saddr 172.18.0.2` or as target of a
+/// `dnat 172.18.0.2:80`, but not as part of another address like `172.18.0.20`.
+fn references_address(rule: &str, address: &str) -> bool {
+    let is_address_char = |c: char| c.is_ascii_hexdigit() || c == '.' || c == ':';
+    rule.match_indices(address).any(|(start, _)| {
+        let before = rule[..start].chars().next_back();
+        let after = rule[start + address.len()..].chars().next();
+        !before.is_some_and(is_address_char)
+            && !after.is_some_and(|c| c != ':' && is_address_char(c))
+    })
+}
 
-            nft_rule.out_interface(&bridge_name);
+/// Get the match for the IPv4 addresses of the container endpoint, covering all of its distinct
+/// addresses.
+fn ipv4_address_match(endpoint: &NetworkEndpoint) -> Result<String> {
+    match endpoint.ipv4_addresses().as_slice() {
+        [] => bail!("IPv4 address is empty"),
+        [address] => Ok((*address).to_owned()),
+        addresses => Ok(format!("{{ {} }}", addresses.join(", "))),
+    }
+}
 
-            let destination_port = match expose_port.container_port {
-                Some(destination_port) => destination_port.to_string(),
-                None => expose_port.host_port.to_string(),
-            };
-            nft_rule.destination_port(&destination_port);
-            nft_rule.dnat(&format!(
-                "{}:{}",
-                dst_network
-                    .IPv4Address
-                    .split('/')
-                    .next()
-                    .ok_or_else(|| format_err!("IPv4 address is empty"))?,
-                destination_port
-            ));
+/// Get the containers carrying all of the given labels with the given values, selected by their
+/// names.
+fn get_tagged_containers(
+    ctx: &ProcessContext,
+    tags: &BTreeMap<String, String>,
+) -> Vec<ContainerSelector> {
+    ctx.container_map
+        .values()
+        .filter(|container| {
+            tags.iter()
+                .all(|(label, value)| container.labels.get(label) == Some(value))
+        })
+        .filter_map(|container| container.names.first())
+        .map(|name| name.trim_start_matches('/').to_owned())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .map(ContainerSelector::Name)
+        .collect()
+}
 
-            let rule = nft_rule.build()?;
-            debug!(ctx.logger, "Add prerouting rule";
-                       o!("part" => "container_dnat",
-                          "rule" => &rule));
+/// Get the names of all networks the given containers are all attached to, which is what the
+/// [wildcard network](../types/constant.WILDCARD_NETWORK.html) of a rule expands to.
+fn get_wildcard_networks(
+    ctx: &ProcessContext,
+    containers: &[&ContainerSelector],
+) -> Result<Vec<String>> {
+    if containers.is_empty() {
+        bail!(
+            "network `{}` requires the rule to reference a container",
+            WILDCARD_NETWORK
+        );
+    }
 
-            // Apply the rule
-            rules.push(nftables::add_rule(Family::Ip, "dfw", "prerouting", &rule));
-            // TODO: verify what is needed for ipt6
+    let mut networks = Vec::new();
+    for network in ctx.network_map.values() {
+        let mut attached = true;
+        for container in containers {
+            attached &= get_network_for_container(ctx, container, network)?.is_some();
+        }
+        if attached {
+            networks.push(network.name.clone());
         }
-
-        Ok(Some(rules))
     }
+    Ok(networks)
 }
 
-/// Enclosing struct to manage rule processing.
-pub struct ProcessContext<'a> {
-    docker: &'a Docker,
-    dfw: &'a DFW,
-    container_map: Map<String, Container>,
-    network_map: Map<String, NetworkDetails>,
-    external_network_interfaces: Option<Vec<String>>,
-    primary_external_network_interface: Option<String>,
-    logger: Logger,
-    dry_run: bool,
-    current_ruleset: Option<String>,
+/// Build the rule logging the packets matched by the given rule, rate-limited by the limit object
+/// shared by all logging rules. It has to precede the rule it logs for.
+/// Get the family a rule is restricted to by its `families`, if it is restricted to a single one.
+fn restricted_family(families: &Option<Vec<AddressFamily>>) -> Option<AddressFamily> {
+    let families = families.as_ref()?;
+    match families.first() {
+        Some(family) if families.iter().all(|other| other == family) => Some(*family),
+        _ => None,
+    }
 }
 
-impl<'a> ProcessContext<'a> {
-    /// Create a new instance of `ProcessDFW` for rule processing.
-    pub fn new(
-        docker: &'a Docker,
-        dfw: &'a DFW,
-        processing_options: &'a ProcessingOptions,
-        logger: &'a Logger,
-        dry_run: bool,
-    ) -> Result<ProcessContext<'a>> {
-        let logger = logger.new(o!());
-
-        let container_list_options = match processing_options.container_filter {
-            ContainerFilter::All => Default::default(),
-            ContainerFilter::Running => ContainerListOptions::builder()
-                .filter(vec![ContainerFilterShiplift::Status("running".to_owned())])
-                .build(),
-        };
-        let containers = docker.containers().list(&container_list_options)?;
-        debug!(logger, "Got list of containers";
-               o!("containers" => format!("{:#?}", containers)));
+/// Restrict the variants of a rule to the families of the rule: variants restricted to another
+/// family by their matches are dropped, all other variants are restricted through `meta nfproto`.
+fn restrict_families(
+    nft_rules: Vec<RuleBuilder>,
+    families: &Option<Vec<AddressFamily>>,
+) -> Vec<RuleBuilder> {
+    let family = match restricted_family(families) {
+        Some(family) => family,
+        None => return nft_rules,
+    };
+    nft_rules
+        .into_iter()
+        .filter_map(|mut nft_rule| match nft_rule.family() {
+            Some(rule_family) if rule_family != family => None,
+            Some(_) => Some(nft_rule),
+            None => {
+                nft_rule.nfproto(family.nfproto());
+                Some(nft_rule)
+            }
+        })
+        .collect()
+}
 
-        let container_map =
-            get_container_map(&containers)?.ok_or_else(|| format_err!("no containers found"))?;
-        trace!(logger, "Got map of containers";
-               o!("container_map" => format!("{:#?}", container_map)));
+fn build_log_rule(nft_rule: &RuleBuilder, section: Section) -> Result<String> {
+    let mut nft_rule = nft_rule.clone();
+    nft_rule.log(format!("dfw:{} ", section));
+    nft_rule.build()
+}
 
-        let networks = docker.networks().list(&Default::default())?;
-        debug!(logger, "Got list of networks";
-               o!("networks" => format!("{:#?}", networks)));
+/// Build the rule with the given verdict. If the verdict depends on the conntrack state, one rule
+/// per state is built instead, guarded by the respective `ct state`.
+///
+/// No rule is built for the `log` pseudo-verdict, the packets are only logged through the rule
+/// built by [`build_log_rule`](fn.build_log_rule.html).
+fn build_verdict_rules(nft_rule: &RuleBuilder, verdict: StatefulVerdict) -> Result<Vec<String>> {
+    if !verdict.is_terminal() {
+        if verdict.is_stateful() {
+            bail!(
+                "the `log` pseudo-verdict cannot depend on the conntrack state of the connection"
+            );
+        }
+        return Ok(Vec::new());
+    }
+    if !verdict.is_stateful() {
+        let mut nft_rule = nft_rule.clone();
+        nft_rule.verdict(verdict.new);
+        return Ok(vec![nft_rule.build()?]);
+    }
 
-        let network_map =
-            get_network_map(&networks)?.ok_or_else(|| format_err!("no networks found"))?;
-        trace!(logger, "Got map of networks";
-               o!("container_map" => format!("{:#?}", container_map)));
+    vec![
+        ("new", verdict.new),
+        ("{ related, established }", verdict.established),
+    ]
+    .into_iter()
+    .map(|(ct_state, verdict)| {
+        let mut nft_rule = nft_rule.clone();
+        nft_rule.ct_state(ct_state).verdict(verdict);
+        nft_rule.build()
+    })
+    .collect()
+}
 
-        let external_network_interfaces = dfw
-            .defaults
-            .as_ref()
-            .and_then(|d| d.external_network_interfaces.as_ref())
-            .cloned();
-        let primary_external_network_interface = external_network_interfaces
-            .as_ref()
-            .and_then(|v| v.get(0))
-            .map(|s| s.to_owned());
+/// Get the expression matching any of the given interfaces, i.e. the interface itself or a set of
+/// multiple interfaces.
+fn interface_match(interfaces: &[String]) -> String {
+    match interfaces {
+        [interface] => interface.to_owned(),
+        interfaces => format!("{{ {} }}", interfaces.join(", ")),
+    }
+}
 
-        let current_ruleset = Self::get_current_ruleset().ok();
+/// Get the target of a `dup`-statement mirroring packets to the given address. The packets are sent
+/// through the given external network interface, or the primary one if none is given.
+fn get_mirror_target(
+    ctx: &ProcessContext,
+    address: IpAddr,
+    external_network_interface: Option<&String>,
+) -> Result<String> {
+    let device = external_network_interface
+        .or_else(|| ctx.primary_external_network_interface(AddressFamily::of(address)))
+        .ok_or_else(|| {
+            format_err!(
+                "mirroring packets to {} requires an external network interface",
+                address
+            )
+        })?;
+    Ok(format!("{} device \"{}\"", address, device))
+}
 
-        Ok(ProcessContext {
-            docker,
-            dfw,
-            container_map,
-            network_map,
-            external_network_interfaces,
-            primary_external_network_interface,
-            logger,
-            dry_run,
-            current_ruleset,
+/// Assign a conntrack zone to each of the networks.
+///
+/// The zone is derived from a hash of the network ID, zone `0` being the default zone is never
+/// assigned. If the zone is already taken, the following zones are probed in order. The networks
+/// are processed in the order of their IDs, which keeps the assignment stable across runs.
+fn assign_conntrack_zones<'a>(
+    networks: impl Iterator<Item = &'a Network>,
+) -> Vec<(&'a Network, u16)> {
+    let mut networks = networks.collect::<Vec<_>>();
+    networks.sort_by(|a, b| a.id.cmp(&b.id));
+
+    let zones = u64::from(u16::MAX);
+    let mut taken = BTreeSet::new();
+    networks
+        .into_iter()
+        .filter_map(|network| {
+            let offset = fnv1a(network.id.as_bytes()) % zones;
+            let zone = (0..zones)
+                .map(|index| 1 + ((offset + index) % zones) as u16)
+                .find(|zone| !taken.contains(zone))?;
+            taken.insert(zone);
+            Some((network, zone))
         })
-    }
+        .collect()
+}
 
-    /// Start the processing using the configuration given at creation.
-    pub fn process(&self) -> Result<()> {
-        if let Some(rules) = self.dfw.process(&self)? {
-            if self.dry_run {
-                info!(self.logger, "Performing dry-run, will not update any rules");
-            } else {
-                // To atomically update the ruleset, we need to write a file and pass that to `nft -f`.
-                let rule_file = tempfile::Builder::new().tempfile()?;
-                let rule_file_path = rule_file.as_ref().as_os_str().to_os_string();
-                debug!(self.logger, "Writing rules to temporary file";
-                       o!("file_path" => rule_file_path.to_string_lossy().into_owned()));
-                let mut writer = BufWriter::new(rule_file);
-
-                for rule in rules {
-                    writeln!(writer, "{}", rule)?;
-                }
-                writer.flush()?;
-                trace!(self.logger, "Finished writing rules to temporary file");
-
-                info!(self.logger, "Applying rules (using nft)");
-                let output = Command::new("nft").arg("-f").arg(rule_file_path).output()?;
-                if !output.status.success() {
-                    return Err(DFWError::NFTablesError {
-                        stdout: String::from_utf8_lossy(&output.stdout).into_owned(),
-                        stderr: String::from_utf8_lossy(&output.stderr).into_owned(),
-                    }
-                    .into());
-                } else {
-                    return Ok(());
-                }
+/// Assign host ports to all exposed ports of the wider-world-to-container rules whose host port is
+/// to be assigned automatically.
+///
+/// The host port is derived from a hash of the container and the container port within the
+/// configured `auto_port_range`, which keeps the assignment stable across runs. If the port is
+/// already taken, the following ports of the range are probed in order. Ports assigned explicitly
+/// by any rule are never assigned automatically.
+fn assign_auto_host_ports(dfw: &DFW) -> Result<BTreeMap<(String, u16), u16>> {
+    let rules = dfw
+        .wider_world_to_container
+        .as_ref()
+        .and_then(|wider_world_to_container| wider_world_to_container.rules.as_ref());
+    let expose_ports = rules.into_iter().flatten().flat_map(|rule| {
+        rule.expose_port
+            .iter()
+            .filter(|expose_port| !expose_port.published && !expose_port.exposed)
+            .map(move |expose_port| (&rule.dst_container, expose_port))
+    });
+
+    let mut taken = BTreeSet::new();
+    let mut requested = BTreeSet::new();
+    for (container, expose_port) in expose_ports {
+        if let Some(host_port_range) = expose_port.host_port_range {
+            taken.extend(host_port_range.start..=host_port_range.end);
+            continue;
+        }
+        match (expose_port.host_port, expose_port.container_port) {
+            (AUTO_HOST_PORT, Some(container_port)) => {
+                requested.insert((container.to_string(), container_port));
+            }
+            (AUTO_HOST_PORT, None) => bail!(
+                "exposed port of container {} with automatically assigned host port requires a \
+                 container port",
+                container
+            ),
+            (host_port, _) => {
+                taken.insert(host_port);
             }
         }
+    }
 
-        Ok(())
+    let mut assigned = BTreeMap::new();
+    if requested.is_empty() {
+        return Ok(assigned);
+    }
+    let port_range = dfw
+        .defaults
+        .as_ref()
+        .and_then(|defaults| defaults.auto_port_range)
+        .ok_or_else(|| {
+            format_err!("host ports are to be assigned automatically, but no port range is defined")
+        })?;
+
+    for (container, container_port) in requested {
+        let offset = fnv1a(format!("{}:{}", container, container_port).as_bytes())
+            % u64::from(port_range.size());
+        let host_port = (0..u64::from(port_range.size()))
+            .map(|index| {
+                port_range.start + ((offset + index) % u64::from(port_range.size())) as u16
+            })
+            .find(|host_port| !taken.contains(host_port))
+            .ok_or_else(|| {
+                format_err!(
+                    "port range {} is exhausted, cannot assign host port for container port {} of \
+                     container {}",
+                    port_range,
+                    container_port,
+                    container
+                )
+            })?;
+        taken.insert(host_port);
+        assigned.insert((container, container_port), host_port);
     }
 
-    /// Check if the provided string-marker is part of the current ruleset (if available).
-    pub fn marker_in_current_ruleset(&self, marker: &str) -> bool {
-        self.current_ruleset
-            .as_ref()
-            .map(|current_ruleset| current_ruleset.contains(marker))
-            .unwrap_or(false)
+    Ok(assigned)
+}
+
+/// Replace the references to CIDR files and hostnames among the source CIDRs of the given address
+/// family by named sets, see [`CIDR_FILE_PREFIX`](../types/constant.CIDR_FILE_PREFIX.html) and
+/// [`CIDR_DNS_PREFIX`](../types/constant.CIDR_DNS_PREFIX.html).
+///
+/// The files are read and the hostnames are resolved every time the rules are processed. The
+/// commands creating the sets in the `dfw` tables of the given families are added to the rules.
+/// Every set is flushed before the CIDRs are added, such that CIDRs removed from the file or
+/// addresses no longer returned for the hostname are removed from the set as well.
+fn resolve_source_cidrs(
+    ctx: &ProcessContext,
+    rules: &mut Vec<String>,
+    source_cidrs: &[String],
+    family: AddressFamily,
+    families: &[Family],
+) -> Result<Vec<String>> {
+    let mut resolved = Vec::with_capacity(source_cidrs.len());
+    for source_cidr in source_cidrs {
+        let (set, cidrs) = if let Some(file) = source_cidr.strip_prefix(CIDR_FILE_PREFIX) {
+            if family != AddressFamily::V4 {
+                bail!(
+                    "CIDR file `{}` can only be referenced by IPv4 source CIDRs",
+                    file
+                );
+            }
+            let cidrs = parse_cidr_file(file, &ctx.inventory.cidr_file(file)?)?;
+            let set = format!("cidrs_{:016x}", fnv1a(file.as_bytes()));
+            trace!(ctx.logger, "Loaded CIDR file";
+                   o!("file" => file,
+                      "set" => &set,
+                      "cidrs" => cidrs.len()));
+            (set, cidrs)
+        } else if let Some(hostname) = source_cidr.strip_prefix(CIDR_DNS_PREFIX) {
+            let set = match family {
+                AddressFamily::V4 => format!("fqdn4_{:016x}", fnv1a(hostname.as_bytes())),
+                AddressFamily::V6 => format!("fqdn6_{:016x}", fnv1a(hostname.as_bytes())),
+            };
+            let addresses = ctx.resolve_hostname(hostname, family, &set)?;
+            trace!(ctx.logger, "Resolved hostname for source CIDRs";
+                   o!("hostname" => hostname,
+                      "set" => &set,
+                      "addresses" => addresses.join(", ")));
+            (set, addresses)
+        } else {
+            resolved.push(source_cidr.to_owned());
+            continue;
+        };
+        for table_family in families {
+            rules.push(match family {
+                AddressFamily::V4 => nftables::add_ipv4_prefix_set(*table_family, "dfw", &set),
+                AddressFamily::V6 => nftables::add_ipv6_prefix_set(*table_family, "dfw", &set),
+            });
+            rules.push(nftables::flush_set(*table_family, "dfw", &set));
+            if !cidrs.is_empty() {
+                rules.push(nftables::add_elements(*table_family, "dfw", &set, &cidrs));
+            }
+        }
+        resolved.push(format!("@{}", set));
     }
 
-    fn get_current_ruleset() -> Result<String> {
-        let output = Command::new("nft").args(&["list", "ruleset"]).output()?;
-        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    Ok(resolved)
+}
+
+/// Get the elements of the named set within the `dfw` tables, as listed by `nft list ruleset`.
+///
+/// Elements spanning multiple lines are joined. If the set is not part of the ruleset, it has no
+/// elements.
+fn parse_set_elements(ruleset: &str, set: &str) -> Vec<String> {
+    let mut in_dfw_table = false;
+    let mut in_set = false;
+    let mut elements = String::new();
+    for line in ruleset.lines().map(str::trim) {
+        if !elements.is_empty() {
+            elements.push(' ');
+            elements.push_str(line);
+        } else {
+            let mut words = line.split_whitespace();
+            match words.next() {
+                Some("table") => {
+                    in_dfw_table = words.nth(1) == Some("dfw");
+                    in_set = false;
+                }
+                Some("set") => in_set = in_dfw_table && words.next() == Some(set),
+                Some("elements") if in_set => elements.push_str(line),
+                _ => {}
+            }
+        }
+        if elements.contains('}') {
+            break;
+        }
     }
+
+    elements
+        .split(['{', '}'])
+        .nth(1)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|element| !element.is_empty())
+        .map(str::to_owned)
+        .collect()
 }
 
-/// Option to filter the containers to be processed
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub enum ContainerFilter {
-    /// Process all containers, i.e. don't filter.
-    All,
-    /// Only process running containers.
-    Running,
+/// Replace the addresses, subnets and bridges within the rule by the names of the containers and
+/// networks they belong to, see
+/// [`RuleSet::resolved_names`](struct.RuleSet.html#structfield.resolved_names).
+///
+/// Values are only replaced as a whole, e.g. `172.18.0.2` within `dnat 172.18.0.2:80`, but not
+/// within `172.18.0.20`.
+fn name_resolved_values(rule: &str, resolved_names: &BTreeMap<String, String>) -> String {
+    let is_value_char = |c: char| c.is_ascii_alphanumeric() || ".:/-".contains(c);
+    let mut named_rule = String::with_capacity(rule.len());
+    let mut rest = rule;
+    while let Some(start) = rest.find(is_value_char) {
+        named_rule.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let end = rest.find(|c: char| !is_value_char(c)).unwrap_or(rest.len());
+        let value = &rest[..end];
+        let port = value.rfind(':').map(|index| value.split_at(index));
+        match (resolved_names.get(value), port) {
+            (Some(name), _) => named_rule.push_str(name),
+            (None, Some((address, port))) if resolved_names.contains_key(address) => {
+                named_rule.push_str(&resolved_names[address]);
+                named_rule.push_str(port);
+            }
+            _ => named_rule.push_str(value),
+        }
+        rest = &rest[end..];
+    }
+    named_rule.push_str(rest);
+    named_rule
 }
 
-/// Options to configure the processing procedure.
-#[derive(Debug, Clone, PartialEq, Eq)]
-pub struct ProcessingOptions {
-    /// Option to filter the containers to be processed, see
-    /// [`ContainerFilter`](enum.ContainerFilter.html).
-    pub container_filter: ContainerFilter,
+/// Calculate the 64-bit FNV-1a hash of the data, which unlike the hasher of the standard library is
+/// guaranteed to be stable.
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
 }
 
-impl Default for ProcessingOptions {
-    fn default() -> Self {
-        ProcessingOptions {
-            container_filter: ContainerFilter::All,
+/// Get the sets of IPv4 and IPv6 subnets that are excluded from egress NAT: loopback, link-local
+/// and the subnets of all container networks.
+fn get_nat_excluded_subnets(ctx: &ProcessContext) -> (Vec<String>, Vec<String>) {
+    let mut excluded_v4 = vec!["127.0.0.0/8".to_owned(), "169.254.0.0/16".to_owned()];
+    let mut excluded_v6 = vec!["::1/128".to_owned(), "fe80::/10".to_owned()];
+    for network in ctx.network_map.values() {
+        for subnet in &network.subnets {
+            let excluded = if subnet.contains(':') {
+                &mut excluded_v6
+            } else {
+                &mut excluded_v4
+            };
+            if !excluded.contains(subnet) {
+                excluded.push(subnet.to_owned());
+            }
         }
     }
+
+    (excluded_v4, excluded_v6)
 }
 
-fn get_bridge_name(network_id: &str) -> Result<String> {
-    if network_id.len() < 12 {
-        bail!("network has to be longer than 12 characters");
+/// Create the script runner applying the rules through the `nft` binary, within the network
+/// namespace and using the binary configured in the [`defaults`](../types/struct.Defaults.html).
+pub fn nft_binary(dfw: &DFW) -> nftables::NftBinary {
+    let defaults = dfw.defaults.as_ref();
+    nftables::NftBinary {
+        netns: defaults.and_then(|defaults| defaults.netns.clone()),
+        nft_path: defaults.and_then(|defaults| defaults.nft_path.clone()),
     }
-    Ok(format!("br-{}", &network_id[..12]))
 }
 
-fn get_network_for_container(
-    docker: &Docker,
-    container_map: &Map<String, Container>,
-    container_name: &str,
-    network_id: &str,
-) -> Result<Option<NetworkContainerDetails>> {
-    Ok(match container_map.get(container_name) {
-        Some(container) => match docker
-            .networks()
-            .get(network_id)
-            .inspect()?
-            .Containers
-            .get(&container.Id)
-        {
-            Some(network) => Some(network.clone()),
-            None => None,
-        },
-        None => None,
-    })
+/// Create the inventory retrieving the containers and networks from the given Docker instance,
+/// retrying failed requests as configured in the [`defaults`](../types/struct.Defaults.html).
+pub fn docker_inventory<'a>(
+    docker: &'a Docker,
+    dfw: &DFW,
+    processing_options: &ProcessingOptions,
+) -> Box<dyn ContainerInventory + 'a> {
+    let concurrency = dfw
+        .defaults
+        .as_ref()
+        .map_or(DEFAULT_DOCKER_CONCURRENCY, |defaults| {
+            defaults.docker_concurrency
+        });
+    let (retries, backoff) = dfw.defaults.as_ref().map_or(
+        (DEFAULT_DOCKER_RETRIES, DEFAULT_DOCKER_RETRY_BACKOFF),
+        |defaults| (defaults.docker_retries, defaults.docker_retry_backoff),
+    );
+    let inventory: Box<dyn ContainerInventory + 'a> = Box::new(DockerInventory::new(
+        docker,
+        processing_options.container_filter.clone(),
+        concurrency,
+    ));
+    #[cfg(feature = "swarm")]
+    let inventory: Box<dyn ContainerInventory + 'a> = if dfw
+        .defaults
+        .as_ref()
+        .is_some_and(|defaults| defaults.swarm_services)
+    {
+        Box::new(crate::swarm::SwarmInventory::new(
+            inventory,
+            crate::swarm::docker_services,
+        ))
+    } else {
+        inventory
+    };
+    Box::new(RetryInventory::new(
+        inventory,
+        retries,
+        Duration::from_millis(backoff),
+    ))
 }
 
 fn get_container_map(containers: &[Container]) -> Result<Option<Map<String, Container>>> {
     let mut container_map: Map<String, Container> = Map::new();
     for container in containers {
-        for name in &container.Names {
+        for name in &container.names {
             container_map.insert(
                 name.clone().trim_start_matches('/').to_owned(),
                 container.clone(),
@@ -1252,10 +5498,10 @@ fn get_container_map(containers: &[Container]) -> Result<Option<Map<String, Cont
     }
 }
 
-fn get_network_map(networks: &[NetworkDetails]) -> Result<Option<Map<String, NetworkDetails>>> {
-    let mut network_map: Map<String, NetworkDetails> = Map::new();
+fn get_network_map(networks: &[Network]) -> Result<Option<BTreeMap<String, Network>>> {
+    let mut network_map: BTreeMap<String, Network> = BTreeMap::new();
     for network in networks {
-        network_map.insert(network.Name.clone(), network.clone());
+        network_map.insert(network.name.clone(), network.clone());
     }
 
     if network_map.is_empty() {
@@ -1268,3 +5514,894 @@ fn get_network_map(networks: &[NetworkDetails]) -> Result<Option<Map<String, Net
 fn generate_marker(components: &[&str]) -> String {
     format!("DFW-MARKER:{}", components.join(";"))
 }
+
+/// Split an nft command of the form `<add|insert> rule <family> <table> <chain> <rule>` into its
+/// components.
+fn split_rule_command(command: &str) -> Option<(&str, &str, &str, &str, &str)> {
+    let mut parts = command.splitn(6, ' ');
+    let verb = parts.next()?;
+    if verb != "add" && verb != "insert" || parts.next()? != "rule" {
+        return None;
+    }
+    Some((
+        verb,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+        parts.next()?,
+    ))
+}
+
+/// Prefix of the comment annotating a rule with its provenance, see
+/// [`annotate_provenance`](fn.annotate_provenance.html).
+const PROVENANCE_COMMENT: &str = " comment \"source=";
+
+/// Annotate all rules added to the DFW tables with the location in the configuration they were
+/// generated from. The annotation is merged into the section marker by
+/// [`tag_section_rules`](fn.tag_section_rules.html).
+fn annotate_provenance(provenance: &Provenance, rules: Vec<String>) -> Vec<String> {
+    rules
+        .into_iter()
+        .map(|rule| match split_rule_command(&rule) {
+            Some((_, _, "dfw", _, _)) => format!("{}{}{}\"", rule, PROVENANCE_COMMENT, provenance),
+            _ => rule,
+        })
+        .collect()
+}
+
+/// Mark all rules added to the DFW tables with the section they belong to, such that they can be
+/// identified when applying sections selectively.
+fn tag_section_rules(section: Section, rules: Vec<String>) -> Vec<String> {
+    rules
+        .into_iter()
+        .map(|rule| match split_rule_command(&rule) {
+            Some((_, _, "dfw", _, _)) => match rule.find(PROVENANCE_COMMENT) {
+                // nft only supports a single comment per rule, the provenance becomes part of the
+                // marker.
+                Some(index) => format!(
+                    "{} comment \"{};{}",
+                    &rule[..index],
+                    section.marker(),
+                    &rule[index + " comment \"".len()..]
+                ),
+                None => format!("{} comment \"{}\"", rule, section.marker()),
+            },
+            _ => rule,
+        })
+        .collect()
+}
+
+/// Finish the rules generated for a section, adding counters if requested (see
+/// [`count_rules`](fn.count_rules.html)) and tagging them with the section.
+fn finish_section_rules(section: Section, rules: Vec<String>, counters: bool) -> Vec<String> {
+    let rules = if counters { count_rules(rules) } else { rules };
+    tag_section_rules(section, rules)
+}
+
+/// Add a `counter` statement to all rules generated by DFW, allowing the per-rule packet- and
+/// byte-counters to be exported as metrics.
+///
+/// Every rule built by DFW sets the DFW mark after all of its matches, the counter is placed right
+/// behind it such that only matching packets are counted.
+fn count_rules(rules: Vec<String>) -> Vec<String> {
+    let mark = format!(" meta mark set {} ", DFW_MARK);
+    rules
+        .into_iter()
+        .map(|rule| match split_rule_command(&rule) {
+            Some((_, _, "dfw", _, _)) => rule.replacen(&mark, &format!("{}counter ", mark), 1),
+            _ => rule,
+        })
+        .collect()
+}
+
+/// Prefix of the identity of a rule within its section marker, see
+/// [`identify_rules`](fn.identify_rules.html).
+const RULE_ID_MARKER: &str = ";rule=";
+
+/// Add the [`RuleId`](struct.RuleId.html) of every rule of the DFW tables to its section marker,
+/// such that the rules listed by nft, e.g. along with their counters, can be attributed to the
+/// rules of the rule set.
+///
+/// The identity is the one of the rule without it, as determined by
+/// [`RuleSet::rule_ids`](struct.RuleSet.html#method.rule_ids), which then keeps it.
+fn identify_rules(mut ruleset: RuleSet) -> RuleSet {
+    let rule_ids = ruleset
+        .rule_ids()
+        .into_iter()
+        .map(|(rule_id, _, _)| rule_id)
+        .collect::<Vec<_>>();
+    let rules = ruleset
+        .sections
+        .iter_mut()
+        .flat_map(|(section, rules)| rules.iter_mut().map(move |rule| (*section, rule)));
+    for ((section, rule), rule_id) in rules.zip(rule_ids) {
+        if let Some((_, _, "dfw", _, _)) = split_rule_command(rule) {
+            let marker = section.marker();
+            if let Some(index) = rule.find(&marker) {
+                rule.insert_str(
+                    index + marker.len(),
+                    &format!("{}{}", RULE_ID_MARKER, rule_id),
+                );
+            }
+        }
+    }
+
+    ruleset
+}
+
+/// Get the identity of the rule added to its section marker by
+/// [`identify_rules`](fn.identify_rules.html), if any.
+fn embedded_rule_id(rule: &str) -> Option<RuleId> {
+    let marker = rule.find(&generate_marker(&["section", ""]))?;
+    let start = marker + rule[marker..].find(RULE_ID_MARKER)? + RULE_ID_MARKER.len();
+    rule.get(start..start + 16)?.parse().ok()
+}
+
+/// Remove the identity added to the section marker of the rule by
+/// [`identify_rules`](fn.identify_rules.html), if any.
+fn strip_rule_id(rule: &str) -> String {
+    match embedded_rule_id(rule) {
+        Some(rule_id) => rule.replacen(&format!("{}{}", RULE_ID_MARKER, rule_id), "", 1),
+        None => rule.to_owned(),
+    }
+}
+
+/// Minimum number of DNAT rules that are collapsed into a single rule using a map, see
+/// [`collapse_dnat_rules`](fn.collapse_dnat_rules.html).
+const DNAT_MAP_MIN_RULES: usize = 8;
+
+/// Placeholders of the parts of a DNAT rule that are collected into the map, see
+/// [`DnatRule`](struct.DnatRule.html).
+const DNAT_PORT_PLACEHOLDER: &str = "{port}";
+const DNAT_ADDRESS_PLACEHOLDER: &str = "{address}";
+const DNAT_TARGET_PLACEHOLDER: &str = "{target}";
+
+/// A DNAT rule of the `prerouting` chain translating a single host port (optionally restricted to a
+/// single host address) to a single address and port.
+#[derive(Debug)]
+struct DnatRule<'a> {
+    /// Tokens of the rule, with the host port, host address and target replaced by placeholders.
+    template: Vec<&'a str>,
+    protocol: &'a str,
+    host_address: Option<&'a str>,
+    host_port: &'a str,
+    target_address: &'a str,
+    target_port: &'a str,
+}
+
+impl<'a> DnatRule<'a> {
+    fn parse(command: &'a str) -> Option<DnatRule<'a>> {
+        let rule = match split_rule_command(command)? {
+            ("add", "ip", "dfw", "prerouting", rule) => rule,
+            _ => return None,
+        };
+
+        let tokens = simulate::tokenize(rule);
+        let mut template = Vec::with_capacity(tokens.len());
+        let mut protocol = None;
+        let mut host_address = None;
+        let mut host_port = None;
+        let mut target = None;
+        let mut index = 0;
+        while index < tokens.len() {
+            match (tokens[index], tokens.get(index + 1), tokens.get(index + 2)) {
+                (token @ "tcp", Some(&"dport"), Some(port))
+                | (token @ "udp", Some(&"dport"), Some(port))
+                    if protocol.is_none() && port.parse::<u16>().is_ok() =>
+                {
+                    protocol = Some(token);
+                    host_port = Some(*port);
+                    template.push(DNAT_PORT_PLACEHOLDER);
+                    index += 3;
+                }
+                ("ip", Some(&"daddr"), Some(address))
+                    if host_address.is_none() && address.parse::<Ipv4Addr>().is_ok() =>
+                {
+                    host_address = Some(*address);
+                    template.push(DNAT_ADDRESS_PLACEHOLDER);
+                    index += 3;
+                }
+                ("dnat", Some(dnat_target), _) if target.is_none() => {
+                    let mut parts = dnat_target.splitn(2, ':');
+                    let address = parts.next().filter(|a| a.parse::<Ipv4Addr>().is_ok())?;
+                    let port = parts.next().filter(|p| p.parse::<u16>().is_ok())?;
+                    target = Some((address, port));
+                    template.push(DNAT_TARGET_PLACEHOLDER);
+                    index += 2;
+                }
+                (token, _, _) => {
+                    template.push(token);
+                    index += 1;
+                }
+            }
+        }
+        let (target_address, target_port) = target?;
+
+        Some(DnatRule {
+            template,
+            protocol: protocol?,
+            host_address,
+            host_port: host_port?,
+            target_address,
+            target_port,
+        })
+    }
+
+    /// Check if the rule only differs from the other rule by its host port, host address and
+    /// target.
+    fn same_group(&self, other: &DnatRule) -> bool {
+        self.template == other.template && self.protocol == other.protocol
+    }
+
+    fn key(&self) -> String {
+        match self.host_address {
+            Some(host_address) => format!("{} . {}", host_address, self.host_port),
+            None => self.host_port.to_owned(),
+        }
+    }
+}
+
+/// Render a single DNAT rule using a map from the host ports (and addresses) to the targets of the
+/// given group of rules.
+fn render_dnat_map_rule(group: &[DnatRule]) -> String {
+    let first = &group[0];
+    let mut host_ports = group.iter().map(|rule| rule.host_port).collect::<Vec<_>>();
+    host_ports.sort_by_key(|host_port| host_port.parse::<u16>().unwrap_or_default());
+    host_ports.dedup();
+    let mut host_addresses = group
+        .iter()
+        .filter_map(|rule| rule.host_address)
+        .collect::<Vec<_>>();
+    host_addresses.sort();
+    host_addresses.dedup();
+    let map = group
+        .iter()
+        .map(|rule| {
+            format!(
+                "{} : {} . {}",
+                rule.key(),
+                rule.target_address,
+                rule.target_port
+            )
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let key = match first.host_address {
+        Some(_) => format!("ip daddr . {} dport", first.protocol),
+        None => format!("{} dport", first.protocol),
+    };
+
+    let rule = first
+        .template
+        .iter()
+        .map(|token| match *token {
+            DNAT_PORT_PLACEHOLDER => {
+                format!("{} dport {{ {} }}", first.protocol, host_ports.join(", "))
+            }
+            DNAT_ADDRESS_PLACEHOLDER => format!("ip daddr {{ {} }}", host_addresses.join(", ")),
+            DNAT_TARGET_PLACEHOLDER => format!("dnat ip addr . port to {} map {{ {} }}", key, map),
+            token => token.to_owned(),
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    nftables::add_rule(Family::Ip, "dfw", "prerouting", &rule)
+}
+
+/// Collapse groups of DNAT rules that only differ by their host port, host address and target into
+/// a single rule, using a map from the host port (and address) to the target.
+///
+/// Only consecutive rules of the `prerouting` chain are grouped, rules of other chains in between
+/// are kept as they are, which preserves the order in which nftables evaluates the rules. Groups
+/// with less than [`DNAT_MAP_MIN_RULES`](constant.DNAT_MAP_MIN_RULES.html) rules or with
+/// duplicate host ports are kept as they are, as are all IPv6 DNAT rules.
+fn collapse_dnat_rules(rules: Vec<String>) -> Vec<String> {
+    let mut collapsed = rules.iter().cloned().map(Some).collect::<Vec<_>>();
+    let mut group: Vec<(usize, DnatRule)> = Vec::new();
+    let mut flush = |group: &mut Vec<(usize, DnatRule)>| {
+        let dnat_rules = std::mem::take(group);
+        let keys = dnat_rules
+            .iter()
+            .map(|(_, rule)| rule.key())
+            .collect::<BTreeSet<_>>();
+        if dnat_rules.len() < DNAT_MAP_MIN_RULES || keys.len() != dnat_rules.len() {
+            return;
+        }
+        let (indices, dnat_rules): (Vec<_>, Vec<_>) = dnat_rules.into_iter().unzip();
+        collapsed[indices[0]] = Some(render_dnat_map_rule(&dnat_rules));
+        for index in &indices[1..] {
+            collapsed[*index] = None;
+        }
+    };
+
+    for (index, rule) in rules.iter().enumerate() {
+        match DnatRule::parse(rule) {
+            Some(dnat_rule) => {
+                if group
+                    .first()
+                    .is_some_and(|(_, first)| !first.same_group(&dnat_rule))
+                {
+                    flush(&mut group);
+                }
+                group.push((index, dnat_rule));
+            }
+            None => {
+                if let Some(("add", "ip", "dfw", "prerouting", _)) = split_rule_command(rule) {
+                    flush(&mut group);
+                }
+            }
+        }
+    }
+    flush(&mut group);
+
+    collapsed.into_iter().flatten().collect()
+}
+
+/// A rule within one of the DFW tables, as listed by `nft --handle list ruleset`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct ListedRule {
+    pub(crate) family: String,
+    pub(crate) chain: String,
+    pub(crate) handle: u64,
+    pub(crate) section: Option<Section>,
+    /// Identity of the rule, if it carries one, see [`identify_rules`](fn.identify_rules.html).
+    pub(crate) rule_id: Option<RuleId>,
+    /// Packets and bytes of the `counter` statement of the rule, if it has one.
+    pub(crate) counter: Option<(u64, u64)>,
+    /// Whether the rule carries a DFW marker, i.e. was created by DFW.
+    pub(crate) marked: bool,
+}
+
+/// Parse the rules of the DFW tables from the output of `nft --handle list ruleset`. Rules without
+/// a handle are skipped.
+pub(crate) fn parse_listed_rules(ruleset: &str) -> Vec<ListedRule> {
+    let marker = generate_marker(&[""]);
+    let section_marker = generate_marker(&["section", ""]);
+
+    let mut listed_rules = Vec::new();
+    let mut family = None;
+    let mut chain = None;
+    for line in ruleset.lines().map(str::trim) {
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("table") => {
+                family = match (words.next(), words.next()) {
+                    (Some(family), Some("dfw")) => Some(family.to_owned()),
+                    _ => None,
+                };
+                chain = None;
+            }
+            Some("chain") => chain = words.next().map(|chain| chain.to_owned()),
+            Some("}") => {}
+            _ => {
+                let (family, chain) = match (&family, &chain) {
+                    (Some(family), Some(chain)) => (family, chain),
+                    _ => continue,
+                };
+                let handle = match line
+                    .rsplit("# handle ")
+                    .next()
+                    .and_then(|handle| handle.parse().ok())
+                {
+                    Some(handle) => handle,
+                    None => continue,
+                };
+                let section = line.find(&section_marker).and_then(|start| {
+                    let name = &line[start + section_marker.len()..];
+                    name.split(['"', ';'])
+                        .next()
+                        .and_then(|name| name.parse().ok())
+                });
+                listed_rules.push(ListedRule {
+                    family: family.clone(),
+                    chain: chain.clone(),
+                    handle,
+                    section,
+                    rule_id: embedded_rule_id(line),
+                    counter: parse_counter(line),
+                    marked: line.contains(&marker),
+                });
+            }
+        }
+    }
+
+    listed_rules
+}
+
+/// Parse the packets and bytes of the `counter packets <packets> bytes <bytes>` statement of a
+/// listed rule.
+fn parse_counter(line: &str) -> Option<(u64, u64)> {
+    let words = line.split_whitespace().collect::<Vec<_>>();
+    let start = words.iter().position(|word| *word == "counter")?;
+    match words.get(start + 1..start + 5)? {
+        ["packets", packets, "bytes", bytes] => Some((packets.parse().ok()?, bytes.parse().ok()?)),
+        _ => None,
+    }
+}
+
+/// Generate the nft commands replacing the rules of the selected sections within the current
+/// ruleset.
+///
+/// All rules previously created for the selected sections are deleted. The new rules are inserted
+/// in front of the first rule of a following section that is retained, which keeps the order of the
+/// rules identical to a full processing run. Which sections follow is determined by the given order
+/// of all sections.
+fn reconcile_sections(
+    current_ruleset: &str,
+    sections: Sections,
+    section_rules: Vec<(Section, Vec<String>)>,
+    order: &[Section],
+) -> Vec<String> {
+    let listed_rules = parse_listed_rules(current_ruleset);
+    let position = |section: Section| order.iter().position(|other| *other == section);
+
+    let mut commands = listed_rules
+        .iter()
+        .filter(|listed_rule| {
+            listed_rule
+                .section
+                .map(|section| sections.contains(section))
+                .unwrap_or(false)
+        })
+        .map(|listed_rule| {
+            format!(
+                "delete rule {} dfw {} handle {}",
+                listed_rule.family, listed_rule.chain, listed_rule.handle
+            )
+        })
+        .collect::<Vec<_>>();
+
+    for (section, rules) in section_rules {
+        for rule in rules {
+            let anchor = match split_rule_command(&rule) {
+                Some(("add", family, "dfw", chain, _)) => listed_rules.iter().find(|listed_rule| {
+                    listed_rule.family == family
+                        && listed_rule.chain == chain
+                        && listed_rule
+                            .section
+                            .map(|other| {
+                                position(other) > position(section) && !sections.contains(other)
+                            })
+                            .unwrap_or(false)
+                }),
+                _ => None,
+            };
+            match (anchor, split_rule_command(&rule)) {
+                (Some(anchor), Some((_, family, table, chain, rule))) => commands.push(format!(
+                    "insert rule {} {} {} position {} {}",
+                    family, table, chain, anchor.handle, rule
+                )),
+                _ => commands.push(rule),
+            }
+        }
+    }
+
+    commands
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn table_preamble_drop_invalid() {
+        let rules = table_preamble(true, false, false, &BaseChains::default());
+        for chain in &["input", "forward"] {
+            let chain_rules = rules
+                .iter()
+                .filter(|rule| rule.starts_with(&format!("add rule inet dfw {} ", chain)))
+                .cloned()
+                .collect::<Vec<_>>();
+            assert_eq!(
+                vec![
+                    format!("add rule inet dfw {} ct state invalid drop", chain),
+                    format!(
+                        "add rule inet dfw {} ct state {{ related, established }} accept",
+                        chain
+                    ),
+                ],
+                chain_rules
+            );
+        }
+    }
+
+    #[test]
+    fn table_preamble_keep_invalid() {
+        let rules = table_preamble(false, false, false, &BaseChains::default());
+        assert!(!rules.iter().any(|rule| rule.contains("ct state invalid")));
+        assert!(rules.contains(
+            &"add rule inet dfw input ct state { related, established } accept".to_owned()
+        ));
+    }
+
+    #[test]
+    fn table_preamble_dnat_accept_shortcut() {
+        let rules = table_preamble(true, true, false, &BaseChains::default());
+        let forward_rules = rules
+            .iter()
+            .filter(|rule| rule.starts_with("add rule inet dfw forward "))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                "add rule inet dfw forward ct state invalid drop",
+                "add rule inet dfw forward ct state { related, established } ct status dnat \
+                 meta mark set 0xdf accept",
+                "add rule inet dfw forward ct state { related, established } accept",
+            ],
+            forward_rules
+        );
+        assert!(!rules
+            .iter()
+            .any(|rule| rule.starts_with("add rule inet dfw input ct status")));
+    }
+
+    const CURRENT_RULESET: &str = r#"table inet dfw { # handle 1
+	chain input { # handle 1
+		type filter hook input priority -5; policy accept;
+		ct state invalid drop # handle 3
+		ct state { established, related } accept # handle 4
+		iifname "docker0" meta mark set 0x000000df accept comment "DFW-MARKER:section;defaults" # handle 9
+	}
+
+	chain forward { # handle 2
+		type filter hook forward priority -5; policy accept;
+		ct state invalid drop # handle 5
+		ct state { established, related } accept # handle 6
+		iifname "docker0" oifname "eth0" meta mark set 0x000000df accept comment "DFW-MARKER:section;defaults" # handle 10
+		tcp dport 80 ip daddr 172.17.0.2 iifname "eth0" oifname "docker0" meta mark set 0x000000df accept comment "DFW-MARKER:section;wider_world_to_container" # handle 11
+	}
+}
+table inet filter { # handle 2
+	chain forward { # handle 1
+		type filter hook forward priority 0; policy accept;
+		ct state invalid drop comment "DFW-MARKER:defaults;filter;forward;ct-state-invalid-drop" # handle 2
+	}
+}
+table ip dfw { # handle 3
+	chain prerouting { # handle 1
+		type nat hook prerouting priority -105; policy accept;
+		tcp dport 80 iifname "eth0" meta mark set 0x000000df dnat to 172.17.0.2:80 comment "DFW-MARKER:section;wider_world_to_container" # handle 7
+		tcp dport 53 ip saddr 172.18.0.2 oifname "br-0123456789ab" meta mark set 0x000000df dnat to 172.17.0.3:53 comment "DFW-MARKER:section;container_dnat" # handle 8
+	}
+}
+"#;
+
+    #[test]
+    fn sections_fromstr() {
+        assert_eq!(Sections::NONE, "".parse().unwrap());
+        assert_eq!(
+            Sections::from(Section::ContainerDNAT),
+            "container_dnat".parse().unwrap()
+        );
+        assert_eq!(
+            Sections::from_iter(vec![Section::Defaults, Section::WiderWorldToContainer]),
+            "defaults, wider_world_to_container".parse().unwrap()
+        );
+        assert!("defaults,unknown".parse::<Sections>().is_err());
+    }
+
+    #[test]
+    fn sections_tostring() {
+        assert_eq!(
+            "initialization,defaults,container_to_container,dns,container_to_wider_world,\
+             container_to_host,wider_world_to_container,container_dnat",
+            Sections::ALL.to_string()
+        );
+        assert_eq!("", Sections::NONE.to_string());
+    }
+
+    #[test]
+    fn tag_section_rules_only_dfw_tables() {
+        let rules = vec![
+            "add rule inet dfw input accept".to_owned(),
+            "insert rule inet filter input accept".to_owned(),
+            "add chain inet dfw forward { policy drop ; }".to_owned(),
+        ];
+
+        assert_eq!(
+            vec![
+                "add rule inet dfw input accept comment \"DFW-MARKER:section;defaults\"",
+                "insert rule inet filter input accept",
+                "add chain inet dfw forward { policy drop ; }",
+            ],
+            tag_section_rules(Section::Defaults, rules)
+        );
+    }
+
+    #[test]
+    fn tag_section_rules_with_provenance() {
+        let provenance = Provenance {
+            file: "conf.d/10-web.toml".to_owned(),
+            line: 12,
+        };
+        let rules = annotate_provenance(
+            &provenance,
+            vec![
+                "add rule inet dfw forward accept".to_owned(),
+                "insert rule inet filter input accept".to_owned(),
+            ],
+        );
+        let rules = tag_section_rules(Section::WiderWorldToContainer, rules);
+
+        assert_eq!(
+            vec![
+                "add rule inet dfw forward accept comment \
+                 \"DFW-MARKER:section;wider_world_to_container;source=conf.d/10-web.toml:12\"",
+                "insert rule inet filter input accept",
+            ],
+            rules
+        );
+
+        // The section of annotated rules is still recognized in the current ruleset
+        let listed_rules = parse_listed_rules(&format!(
+            "table inet dfw {{\n\tchain forward {{\n\t\t{} # handle 4\n\t}}\n}}",
+            rules[0].splitn(6, ' ').last().unwrap()
+        ));
+        assert_eq!(
+            vec![ListedRule {
+                family: "inet".to_owned(),
+                chain: "forward".to_owned(),
+                handle: 4,
+                section: Some(Section::WiderWorldToContainer),
+                rule_id: None,
+                counter: None,
+                marked: true,
+            }],
+            listed_rules
+        );
+    }
+
+    #[test]
+    fn parse_listed_rules_dfw_tables() {
+        let listed_rules = parse_listed_rules(CURRENT_RULESET);
+
+        assert_eq!(9, listed_rules.len());
+        assert_eq!(
+            ListedRule {
+                family: "inet".to_owned(),
+                chain: "forward".to_owned(),
+                handle: 11,
+                section: Some(Section::WiderWorldToContainer),
+                rule_id: None,
+                counter: None,
+                marked: true,
+            },
+            listed_rules[6]
+        );
+        assert_eq!(
+            ListedRule {
+                family: "ip".to_owned(),
+                chain: "prerouting".to_owned(),
+                handle: 8,
+                section: Some(Section::ContainerDNAT),
+                rule_id: None,
+                counter: None,
+                marked: true,
+            },
+            listed_rules[8]
+        );
+        assert!(listed_rules[..2]
+            .iter()
+            .all(|listed_rule| listed_rule.section.is_none()));
+    }
+
+    #[test]
+    fn parse_listed_rules_identity_and_counter() {
+        let listed_rules = parse_listed_rules(
+            "table inet dfw {\n\tchain input {\n\t\tmeta mark set 0x000000df counter packets 12 \
+             bytes 1440 accept comment \"DFW-MARKER:section;container_to_host;rule=0123456789abcdef\" \
+             # handle 7\n\t}\n}",
+        );
+
+        assert_eq!(
+            vec![ListedRule {
+                family: "inet".to_owned(),
+                chain: "input".to_owned(),
+                handle: 7,
+                section: Some(Section::ContainerToHost),
+                rule_id: Some("0123456789abcdef".parse().unwrap()),
+                counter: Some((12, 1440)),
+                marked: true,
+            }],
+            listed_rules
+        );
+    }
+
+    #[test]
+    fn identify_rules_keeps_identity() {
+        let ruleset = RuleSet {
+            preamble: Vec::new(),
+            sections: vec![(
+                Section::ContainerToHost,
+                vec![
+                    "add rule inet dfw input accept \
+                     comment \"DFW-MARKER:section;container_to_host\""
+                        .to_owned(),
+                    "add rule inet dfw input accept \
+                     comment \"DFW-MARKER:section;container_to_host\""
+                        .to_owned(),
+                    "add rule inet filter input accept".to_owned(),
+                ],
+            )],
+            ..Default::default()
+        };
+        let identified = identify_rules(ruleset.clone());
+
+        assert_eq!(ruleset.rule_ids().len(), identified.rule_ids().len());
+        for ((rule_id, _, rule), (identified_rule_id, _, identified_rule)) in
+            ruleset.rule_ids().iter().zip(identified.rule_ids())
+        {
+            assert_eq!(*rule_id, identified_rule_id);
+            assert_eq!(*rule, strip_rule_id(identified_rule));
+            if identified_rule.contains(" dfw ") {
+                assert!(identified_rule.contains(&format!(";rule={}\"", rule_id)));
+            }
+        }
+    }
+
+    #[test]
+    fn reconcile_single_section() {
+        let sections = Sections::from(Section::WiderWorldToContainer);
+        let section_rules = vec![(
+            Section::WiderWorldToContainer,
+            tag_section_rules(
+                Section::WiderWorldToContainer,
+                vec![
+                    "add rule inet dfw forward tcp dport 443 accept".to_owned(),
+                    "add rule ip dfw prerouting tcp dport 443 dnat 172.17.0.2:443".to_owned(),
+                ],
+            ),
+        )];
+
+        assert_eq!(
+            vec![
+                "delete rule inet dfw forward handle 11",
+                "delete rule ip dfw prerouting handle 7",
+                "add rule inet dfw forward tcp dport 443 accept \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+                "insert rule ip dfw prerouting position 8 tcp dport 443 dnat 172.17.0.2:443 \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+            ],
+            reconcile_sections(CURRENT_RULESET, sections, section_rules, &Section::VALUES)
+        );
+    }
+
+    #[test]
+    fn reconcile_custom_section_order() {
+        let sections = Sections::from(Section::WiderWorldToContainer);
+        let section_rules = vec![(
+            Section::WiderWorldToContainer,
+            vec!["add rule ip dfw prerouting tcp dport 443 dnat 172.17.0.2:443".to_owned()],
+        )];
+        let mut order = Section::VALUES.to_vec();
+        order.retain(|section| *section != Section::WiderWorldToContainer);
+        order.push(Section::WiderWorldToContainer);
+
+        // The `container_dnat` section precedes the selected section, its rules are no anchor
+        assert_eq!(
+            vec![
+                "delete rule inet dfw forward handle 11",
+                "delete rule ip dfw prerouting handle 7",
+                "add rule ip dfw prerouting tcp dport 443 dnat 172.17.0.2:443",
+            ],
+            reconcile_sections(CURRENT_RULESET, sections, section_rules, &order)
+        );
+    }
+
+    #[test]
+    fn reconcile_section_without_rules() {
+        let sections = Sections::from(Section::ContainerDNAT);
+        let section_rules = vec![(Section::ContainerDNAT, vec![])];
+
+        assert_eq!(
+            vec!["delete rule ip dfw prerouting handle 8"],
+            reconcile_sections(CURRENT_RULESET, sections, section_rules, &Section::VALUES)
+        );
+    }
+
+    #[test]
+    fn preserve_foreign_rules_mixed_chain() {
+        let current_ruleset = r#"table inet dfw { # handle 1
+	chain forward { # handle 2
+		type filter hook forward priority -5; policy accept;
+		ct state { established, related } accept comment "DFW-MARKER:preamble" # handle 6
+		iifname "wg0" oifname "br-0123456789ab" accept # handle 12
+		tcp dport 80 ip daddr 172.17.0.2 iifname "eth0" oifname "docker0" meta mark set 0x000000df accept comment "DFW-MARKER:section;wider_world_to_container" # handle 11
+		iifname "wg0" counter drop # handle 13
+	}
+}
+"#;
+        let ruleset = RuleSet {
+            preamble: vec![
+                nftables::add_table(Family::Inet, "dfw"),
+                nftables::flush_table(Family::Inet, "dfw"),
+                "add rule inet dfw forward ct state { related, established } accept".to_owned(),
+            ],
+            sections: vec![(
+                Section::WiderWorldToContainer,
+                tag_section_rules(
+                    Section::WiderWorldToContainer,
+                    vec!["add rule inet dfw forward tcp dport 443 accept".to_owned()],
+                ),
+            )],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vec![
+                "delete rule inet dfw forward handle 6",
+                "delete rule inet dfw forward handle 11",
+                "add table inet dfw",
+                "insert rule inet dfw forward position 12 ct state { related, established } accept \
+                 comment \"DFW-MARKER:preamble\"",
+                "insert rule inet dfw forward position 13 tcp dport 443 accept \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+            ],
+            ruleset.preserve_foreign_rules(current_ruleset)
+        );
+    }
+
+    #[test]
+    fn preserve_foreign_rules_keeps_positions() {
+        let current_ruleset = r#"table inet dfw { # handle 1
+	chain input { # handle 2
+		type filter hook input priority -5; policy accept;
+		iifname "lo" accept # handle 20
+		ct state invalid drop comment "DFW-MARKER:preamble" # handle 21
+		tcp dport 22 accept # handle 22
+	}
+	chain forward { # handle 3
+		type filter hook forward priority -5; policy accept;
+		ct state invalid drop comment "DFW-MARKER:preamble" # handle 30
+		ip saddr 172.17.0.2 ip daddr 172.17.0.3 accept comment "DFW-MARKER:section;container_to_container" # handle 31
+		iifname "wg0" accept # handle 32
+		tcp dport 80 ip daddr 172.17.0.2 accept comment "DFW-MARKER:section;wider_world_to_container" # handle 33
+	}
+}
+"#;
+        let ruleset = RuleSet {
+            preamble: vec![
+                nftables::add_table(Family::Inet, "dfw"),
+                nftables::flush_table(Family::Inet, "dfw"),
+                "add rule inet dfw input ct state invalid drop".to_owned(),
+                "add rule inet dfw forward ct state invalid drop".to_owned(),
+            ],
+            sections: vec![
+                (
+                    Section::ContainerToContainer,
+                    tag_section_rules(
+                        Section::ContainerToContainer,
+                        vec!["add rule inet dfw forward ip saddr 172.17.0.4 accept".to_owned()],
+                    ),
+                ),
+                (
+                    Section::WiderWorldToContainer,
+                    tag_section_rules(
+                        Section::WiderWorldToContainer,
+                        vec!["add rule inet dfw forward tcp dport 443 accept".to_owned()],
+                    ),
+                ),
+            ],
+            ..Default::default()
+        };
+
+        assert_eq!(
+            vec![
+                "delete rule inet dfw input handle 21",
+                "delete rule inet dfw forward handle 30",
+                "delete rule inet dfw forward handle 31",
+                "delete rule inet dfw forward handle 33",
+                "add table inet dfw",
+                "insert rule inet dfw input position 22 ct state invalid drop \
+                 comment \"DFW-MARKER:preamble\"",
+                "insert rule inet dfw forward position 32 ct state invalid drop \
+                 comment \"DFW-MARKER:preamble\"",
+                "insert rule inet dfw forward position 32 ip saddr 172.17.0.4 accept \
+                 comment \"DFW-MARKER:section;container_to_container\"",
+                "add rule inet dfw forward tcp dport 443 accept \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+            ],
+            ruleset.preserve_foreign_rules(current_ruleset)
+        );
+    }
+}
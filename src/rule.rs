@@ -3,8 +3,9 @@
 #![allow(missing_docs)]
 
 use crate::errors::*;
-use crate::nftables::RuleVerdict;
-use crate::process::DFW_MARK;
+use crate::nftables::{LogLevel, RuleVerdict};
+use crate::process::{DFW_MARK, LOG_LIMIT};
+use crate::types::AddressFamily;
 use derive_builder::Builder;
 use failure::bail;
 
@@ -30,6 +31,14 @@ pub struct Rule {
     #[builder(setter(into))]
     pub destination_port: String,
     #[builder(setter(into))]
+    pub vlan_id: String,
+    #[builder(setter(into))]
+    pub nfproto: String,
+    #[builder(setter(into))]
+    pub ct_state: String,
+    #[builder(setter(into))]
+    pub min_ct_bytes: u64,
+    #[builder(setter(into))]
     pub matches: String,
     #[builder(setter(into))]
     pub comment: String,
@@ -37,6 +46,24 @@ pub struct Rule {
     pub verdict: RuleVerdict,
     #[builder(setter(into))]
     pub dnat: String,
+    #[builder(setter(into))]
+    pub dup: String,
+    #[builder(setter(into))]
+    pub dscp: String,
+    #[builder(setter(into))]
+    pub dscp_v6: String,
+    #[builder(setter(into))]
+    pub notrack: bool,
+    #[builder(setter(into))]
+    pub ct_timeout: String,
+    #[builder(setter(into))]
+    pub clamp_mss: bool,
+    #[builder(setter(into))]
+    pub log: String,
+    #[builder(setter(into))]
+    pub log_level: LogLevel,
+    #[builder(setter(into))]
+    pub quota: String,
 }
 
 impl RuleBuilder {
@@ -98,6 +125,31 @@ impl RuleBuilder {
             bail!("one of `{source,destination}_{port,address{,_v6}}`, `{in,out}_interface` must be initialized");
         }
 
+        if let Some(vlan_id) = &self.vlan_id {
+            args.push("vlan".to_owned());
+            args.push("id".to_owned());
+            args.push(vlan_id.to_owned());
+        }
+
+        if let Some(nfproto) = &self.nfproto {
+            args.push("meta".to_owned());
+            args.push("nfproto".to_owned());
+            args.push(nfproto.to_owned());
+        }
+
+        if let Some(ct_state) = &self.ct_state {
+            args.push("ct".to_owned());
+            args.push("state".to_owned());
+            args.push(ct_state.to_owned());
+        }
+
+        if let Some(min_ct_bytes) = &self.min_ct_bytes {
+            args.push("ct".to_owned());
+            args.push("bytes".to_owned());
+            args.push(">".to_owned());
+            args.push(min_ct_bytes.to_string());
+        }
+
         // Unconditionally set mark
         args.push("meta".to_owned());
         args.push("mark".to_owned());
@@ -108,6 +160,77 @@ impl RuleBuilder {
             args.push(matches.to_owned());
         }
 
+        if let Some(quota) = &self.quota {
+            args.push("quota".to_owned());
+            args.push("name".to_owned());
+            args.push(quota.to_owned());
+        }
+
+        // A rule logging packets only shares the matches of the rule it logs for, the statements
+        // and the verdict of the logged rule are left out.
+        if let Some(log) = &self.log {
+            args.push("limit".to_owned());
+            args.push("name".to_owned());
+            args.push(LOG_LIMIT.to_owned());
+            args.push("log".to_owned());
+            args.push("prefix".to_owned());
+            args.push(format!(r#""{}""#, log));
+            if let Some(log_level) = &self.log_level {
+                args.push("level".to_owned());
+                args.push(log_level.to_string());
+            }
+
+            if let Some(comment) = &self.comment {
+                args.push(format!(r#"comment "{}""#, comment));
+            }
+
+            return Ok(args.join(" "));
+        }
+
+        if let Some(dscp) = &self.dscp {
+            args.push("ip".to_owned());
+            args.push("dscp".to_owned());
+            args.push("set".to_owned());
+            args.push(dscp.to_owned());
+        }
+        if let Some(dscp) = &self.dscp_v6 {
+            args.push("ip6".to_owned());
+            args.push("dscp".to_owned());
+            args.push("set".to_owned());
+            args.push(dscp.to_owned());
+        }
+
+        // Only the SYN packets carry the MSS option
+        if let Some(true) = self.clamp_mss {
+            args.push("tcp".to_owned());
+            args.push("flags".to_owned());
+            args.push("syn".to_owned());
+            args.push("tcp".to_owned());
+            args.push("option".to_owned());
+            args.push("maxseg".to_owned());
+            args.push("size".to_owned());
+            args.push("set".to_owned());
+            args.push("rt".to_owned());
+            args.push("mtu".to_owned());
+        }
+
+        if let Some(dup) = &self.dup {
+            args.push("dup".to_owned());
+            args.push("to".to_owned());
+            args.push(dup.to_owned());
+        }
+
+        if let Some(true) = self.notrack {
+            args.push("notrack".to_owned());
+        }
+
+        if let Some(ct_timeout) = &self.ct_timeout {
+            args.push("ct".to_owned());
+            args.push("timeout".to_owned());
+            args.push("set".to_owned());
+            args.push(format!(r#""{}""#, ct_timeout));
+        }
+
         if let Some(verdict) = &self.verdict {
             args.push(verdict.to_string());
         } else if let Some(dnat) = &self.dnat {
@@ -121,8 +244,27 @@ impl RuleBuilder {
 
         Ok(args.join(" "))
     }
+
+    /// Get the address family the rule is restricted to by its address matches and DSCP
+    /// statements, or `None` if it applies to both families.
+    pub fn family(&self) -> Option<AddressFamily> {
+        if self.source_address.is_some()
+            || self.destination_address.is_some()
+            || self.dscp.is_some()
+        {
+            Some(AddressFamily::V4)
+        } else if self.source_address_v6.is_some()
+            || self.destination_address_v6.is_some()
+            || self.dscp_v6.is_some()
+        {
+            Some(AddressFamily::V6)
+        } else {
+            None
+        }
+    }
 }
 
+#[cfg(test)]
 mod test {
     use super::*;
 
@@ -138,4 +280,105 @@ mod test {
         rule.source_port("1");
         assert!(rule.build().is_ok());
     }
+
+    #[test]
+    fn builder_dup_before_verdict() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eth0")
+            .dup(r#"10.0.0.250 device "eth0""#)
+            .verdict(RuleVerdict::Accept);
+        assert_eq!(
+            r#"meta iifname eth0 meta mark set 0xdf dup to 10.0.0.250 device "eth0" accept"#,
+            rule.build().unwrap()
+        );
+    }
+
+    #[test]
+    fn builder_log_without_statements() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eth0")
+            .matches("tcp dport 22")
+            .dup(r#"10.0.0.250 device "eth0""#)
+            .verdict(RuleVerdict::Drop)
+            .log("dfw:container_to_host ");
+        assert_eq!(
+            r#"meta iifname eth0 meta mark set 0xdf tcp dport 22 limit name dfw_log log prefix "dfw:container_to_host ""#,
+            rule.build().unwrap()
+        );
+    }
+
+    #[test]
+    fn builder_log_level() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eth0")
+            .log("wwtc-nomatch: ")
+            .log_level(LogLevel::Notice);
+        assert_eq!(
+            r#"meta iifname eth0 meta mark set 0xdf limit name dfw_log log prefix "wwtc-nomatch: " level notice"#,
+            rule.build().unwrap()
+        );
+    }
+
+    #[test]
+    fn builder_family() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eth0");
+        assert_eq!(None, rule.family());
+        rule.dscp_v6("46");
+        assert_eq!(Some(AddressFamily::V6), rule.family());
+        rule.source_address("172.18.0.2");
+        assert_eq!(Some(AddressFamily::V4), rule.family());
+    }
+
+    #[test]
+    fn builder_min_ct_bytes() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eth0")
+            .ct_state("new")
+            .min_ct_bytes(104_857_600u64)
+            .verdict(RuleVerdict::Drop);
+        assert_eq!(
+            "meta iifname eth0 ct state new ct bytes > 104857600 meta mark set 0xdf drop",
+            rule.build().unwrap()
+        );
+    }
+
+    #[test]
+    fn builder_ct_timeout() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eth0")
+            .protocol("tcp")
+            .destination_port("22")
+            .ct_timeout("scanned");
+        assert_eq!(
+            r#"tcp dport 22 meta iifname eth0 meta mark set 0xdf ct timeout set "scanned""#,
+            rule.build().unwrap()
+        );
+    }
+
+    #[test]
+    fn builder_clamp_mss() {
+        let mut rule = RuleBuilder::default();
+        rule.out_interface("eth0")
+            .protocol("tcp")
+            .source_port("443")
+            .clamp_mss(true);
+        assert_eq!(
+            "tcp sport 443 meta oifname eth0 meta mark set 0xdf tcp flags syn tcp option maxseg \
+             size set rt mtu",
+            rule.build().unwrap()
+        );
+    }
+
+    #[test]
+    fn builder_dscp_before_verdict() {
+        let mut rule = RuleBuilder::default();
+        rule.in_interface("eth0")
+            .dscp("46")
+            .verdict(RuleVerdict::Accept);
+        assert_eq!(
+            "meta iifname eth0 meta mark set 0xdf ip dscp set 46 accept",
+            rule.build().unwrap()
+        );
+    }
 }
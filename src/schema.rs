@@ -0,0 +1,219 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module describes the keys of the configuration, e.g. for editor tooling providing
+//! hover-docs or completion.
+//!
+//! The description is derived from the definitions of the [`types`](../types/index.html) module,
+//! including their doc comments, such that it cannot diverge from the types DFW deserializes the
+//! configuration into.
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Source of the types module the configuration keys are derived from.
+const TYPES_SOURCE: &str = include_str!("types.rs");
+
+/// Name of the type the configuration is deserialized into.
+const ROOT_STRUCT: &str = "DFW";
+
+/// Description of a single key of the configuration.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct ConfigKeyDoc {
+    /// Path of the key, its components separated by dots, e.g.
+    /// `container_to_container.rules.verdict`.
+    ///
+    /// Keys of tables within an array of tables are joined like keys of regular tables.
+    pub path: String,
+
+    /// Type of the value as it is defined in the [`types`](../types/index.html) module, e.g.
+    /// `Vec<String>`. The `Option` of optional keys is removed.
+    pub value_type: String,
+
+    /// This defines whether the key can be left blank.
+    pub optional: bool,
+
+    /// Alternative names the key can be given as.
+    pub aliases: Vec<String>,
+
+    /// Documentation of the key, in Markdown.
+    pub doc: String,
+}
+
+#[derive(Debug, Default)]
+struct Field {
+    name: String,
+    value_type: String,
+    doc: Vec<String>,
+    attributes: Vec<String>,
+}
+
+#[derive(Debug, Default)]
+struct SerdeAttributes {
+    rename: Option<String>,
+    aliases: Vec<String>,
+    default: bool,
+    skip: bool,
+}
+
+/// Describe every key of the configuration.
+///
+/// The keys are returned in the order they are defined in, the keys of a table directly following
+/// the key of the table itself.
+pub fn config_keys() -> Vec<ConfigKeyDoc> {
+    let structs = parse_structs(TYPES_SOURCE);
+    let mut keys = Vec::new();
+    describe_struct(&structs, ROOT_STRUCT, "", &mut keys);
+
+    keys
+}
+
+fn describe_struct(
+    structs: &BTreeMap<String, Vec<Field>>,
+    name: &str,
+    prefix: &str,
+    keys: &mut Vec<ConfigKeyDoc>,
+) {
+    let fields = match structs.get(name) {
+        Some(fields) => fields,
+        None => return,
+    };
+    for field in fields {
+        let attributes = parse_serde_attributes(&field.attributes);
+        if attributes.skip {
+            continue;
+        }
+
+        let path = format!(
+            "{}{}",
+            prefix,
+            attributes.rename.as_ref().unwrap_or(&field.name)
+        );
+        let (value_type, optional) = match field
+            .value_type
+            .strip_prefix("Option<")
+            .and_then(|value_type| value_type.strip_suffix('>'))
+        {
+            Some(value_type) => (value_type.to_owned(), true),
+            None => (field.value_type.clone(), attributes.default),
+        };
+        keys.push(ConfigKeyDoc {
+            path: path.clone(),
+            value_type: value_type.clone(),
+            optional,
+            aliases: attributes.aliases,
+            doc: field.doc.join("\n").trim().to_owned(),
+        });
+
+        if let Some(nested) = value_type
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .find(|ident| structs.contains_key(*ident))
+        {
+            describe_struct(structs, nested, &format!("{}.", path), keys);
+        }
+    }
+}
+
+/// Parse the fields of the structs the configuration is deserialized into.
+///
+/// Structs that are deserialized from another type, e.g. a string, are not considered, since the
+/// configuration does not specify their fields.
+fn parse_structs(source: &str) -> BTreeMap<String, Vec<Field>> {
+    let mut structs = BTreeMap::new();
+    let mut current: Option<(String, Vec<Field>)> = None;
+    let mut doc = Vec::new();
+    let mut attributes: Vec<String> = Vec::new();
+    let mut open_attribute: Option<String> = None;
+
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if let Some(mut attribute) = open_attribute.take() {
+            attribute.push_str(trimmed);
+            if trimmed.ends_with(")]") {
+                attributes.push(attribute);
+            } else {
+                open_attribute = Some(attribute);
+            }
+            continue;
+        }
+
+        if let Some(line_doc) = trimmed.strip_prefix("///") {
+            doc.push(line_doc.strip_prefix(' ').unwrap_or(line_doc).to_owned());
+        } else if trimmed.starts_with("#[") {
+            if trimmed.ends_with(']') {
+                attributes.push(trimmed.to_owned());
+            } else {
+                open_attribute = Some(trimmed.to_owned());
+            }
+        } else if let Some(name) = line
+            .strip_prefix("pub struct ")
+            .and_then(|line| line.strip_suffix(" {"))
+        {
+            let deserialized = attributes.iter().any(|attribute| {
+                attribute.starts_with("#[derive(") && attribute.contains("Deserialize")
+            }) && !attributes
+                .iter()
+                .any(|attribute| attribute.contains("try_from"));
+            if deserialized {
+                current = Some((name.to_owned(), Vec::new()));
+            }
+            doc.clear();
+            attributes.clear();
+        } else if line == "}" {
+            if let Some((name, fields)) = current.take() {
+                structs.insert(name, fields);
+            }
+        } else if let (Some((_, fields)), Some(field)) =
+            (current.as_mut(), trimmed.strip_prefix("pub "))
+        {
+            if let Some((name, value_type)) = split_field(field) {
+                fields.push(Field {
+                    name: name.to_owned(),
+                    value_type: value_type.to_owned(),
+                    doc: doc.split_off(0),
+                    attributes: attributes.split_off(0),
+                });
+            }
+        } else {
+            doc.clear();
+            attributes.clear();
+        }
+    }
+
+    structs
+}
+
+fn split_field(field: &str) -> Option<(&str, &str)> {
+    let separator = field.find(": ")?;
+    let value_type = field[separator + 2..].strip_suffix(',')?;
+
+    Some((&field[..separator], value_type))
+}
+
+fn parse_serde_attributes(attributes: &[String]) -> SerdeAttributes {
+    let mut serde_attributes = SerdeAttributes::default();
+    let arguments = attributes.iter().filter_map(|attribute| {
+        attribute
+            .strip_prefix("#[serde(")
+            .and_then(|attribute| attribute.strip_suffix(")]"))
+    });
+    for argument in arguments.flat_map(|arguments| arguments.split(',')) {
+        let mut parts = argument.splitn(2, '=').map(str::trim);
+        let key = parts.next().unwrap_or_default();
+        let value = parts.next().map(|value| value.trim_matches('"').to_owned());
+        match (key, value) {
+            ("rename", Some(value)) => serde_attributes.rename = Some(value),
+            ("alias", Some(value)) => serde_attributes.aliases.push(value),
+            ("default", _) => serde_attributes.default = true,
+            ("skip", None) => serde_attributes.skip = true,
+            _ => {}
+        }
+    }
+
+    serde_attributes
+}
@@ -0,0 +1,679 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module allows evaluating a generated [`RuleSet`] in software, determining which rule a
+//! packet would match and which verdict it would get, without applying any rules.
+//!
+//! Only the `dfw` tables are evaluated. Within a chain, the first rule with a terminal statement
+//! (`accept`, `drop`, `reject`, `dnat`, `snat`, `masquerade`) that matches the packet decides its
+//! fate, just as in nftables. If no such rule matches, the policy of the chain applies. A matching
+//! `goto` continues with the rules of the target chain, whose end leads to the policy of the base
+//! chain. Named sets of addresses are resolved to the elements added to them.
+//!
+//! [`RuleSet`]: ../process/struct.RuleSet.html
+
+use crate::errors::*;
+use crate::inventory::ContainerInventory;
+use crate::nftables::ChainPolicy;
+use crate::process::{RuleSet, Section};
+use failure::{bail, format_err};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::str::FromStr;
+use strum_macros::{Display, EnumString};
+
+/// The chain a simulated packet traverses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, Default)]
+#[strum(serialize_all = "snake_case")]
+pub enum Chain {
+    /// The `prerouting` chain of the `ip` and `ip6` tables, responsible for DNAT.
+    Prerouting,
+    /// The `input` chain of the `inet` table, for packets destined to the host.
+    Input,
+    /// The `forward` chain of the `inet` table, for packets routed by the host.
+    #[default]
+    Forward,
+    /// The `postrouting` chain of the `ip` and `ip6` tables, responsible for SNAT.
+    Postrouting,
+}
+
+/// The address family of a simulated packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, Default)]
+#[strum(serialize_all = "snake_case")]
+pub enum PacketFamily {
+    /// IPv4
+    #[default]
+    Ipv4,
+    /// IPv6
+    Ipv6,
+}
+
+/// The conntrack state of a simulated packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Display, EnumString, Default)]
+#[strum(serialize_all = "snake_case")]
+pub enum ConntrackState {
+    /// The packet starts a new connection.
+    #[default]
+    New,
+    /// The packet belongs to an established connection.
+    Established,
+    /// The packet is related to an established connection.
+    Related,
+    /// The packet could not be associated with any connection.
+    Invalid,
+}
+
+/// Description of a packet to simulate.
+///
+/// Fields that are not set never match a rule that depends on them, e.g. a packet without
+/// destination port is not matched by a rule with `tcp dport 80`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Packet {
+    /// Chain the packet traverses.
+    pub chain: Chain,
+    /// Address family of the packet.
+    pub family: PacketFamily,
+    /// Conntrack state of the packet.
+    pub ct_state: ConntrackState,
+    /// This defines whether the destination of the packet's connection was translated by DNAT,
+    /// as matched by `ct status dnat`.
+    pub dnat: bool,
+    /// Interface the packet was received on.
+    pub in_interface: Option<String>,
+    /// Interface the packet is sent out on.
+    pub out_interface: Option<String>,
+    /// Source address of the packet.
+    pub source_address: Option<IpAddr>,
+    /// Destination address of the packet.
+    pub destination_address: Option<IpAddr>,
+    /// Layer 4 protocol of the packet, e.g. `tcp` or `udp`.
+    pub protocol: Option<String>,
+    /// Source port of the packet.
+    pub source_port: Option<u16>,
+    /// Destination port of the packet.
+    pub destination_port: Option<u16>,
+}
+
+/// Outcome of a simulation, see [`simulate`](fn.simulate.html).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Simulation {
+    /// The verdict of the packet, e.g. `accept`, `drop` or `dnat 172.18.0.2:80`.
+    pub verdict: String,
+    /// The command of the rule that decided the verdict, `None` if the chain policy applied.
+    pub rule: Option<String>,
+    /// The section the deciding rule was generated by, `None` if the rule is part of the preamble
+    /// or the chain policy applied.
+    pub section: Option<Section>,
+}
+
+/// Rule of a chain, consisting of the command adding it, the rule itself and the section it was
+/// generated for.
+type ChainRule<'a> = (&'a str, &'a str, Option<Section>);
+
+/// Simulate which rule of the rule set the packet matches first, and the resulting verdict.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::inventory::StaticInventory;
+/// # use dfw::process::generate;
+/// # use dfw::simulate::{simulate, ConntrackState, Packet};
+/// # use dfw::types::DFW;
+/// let inventory: StaticInventory = toml::from_str(
+///     r#"
+///     [networks.frontend]
+///     id = "6d4c1b5e9f0a8c3d2e1f"
+///
+///     [containers.web]
+///     networks.frontend = { ipv4_address = "172.18.0.2/16" }
+///     "#,
+/// )
+/// .unwrap();
+/// let dfw: DFW = toml::from_str("").unwrap();
+/// let ruleset = generate(&dfw, &inventory).unwrap();
+///
+/// let packet = Packet {
+///     ct_state: ConntrackState::Established,
+///     ..Default::default()
+/// };
+/// assert_eq!("accept", simulate(&ruleset, &packet).unwrap().verdict);
+/// ```
+pub fn simulate(ruleset: &RuleSet, packet: &Packet) -> Result<Simulation> {
+    if let Some(address) = packet.source_address.or(packet.destination_address) {
+        if address.is_ipv4() != (packet.family == PacketFamily::Ipv4) {
+            bail!(
+                "address {} doesn't match the packet family {}",
+                address,
+                packet.family
+            );
+        }
+    }
+
+    let chain = packet.chain.to_string();
+    let families: &[&str] = match (packet.chain, packet.family) {
+        // The filter chains are part of the `ip` and `ip6` tables if the table family is split
+        (Chain::Input, PacketFamily::Ipv4) | (Chain::Forward, PacketFamily::Ipv4) => {
+            &["inet", "ip"]
+        }
+        (Chain::Input, PacketFamily::Ipv6) | (Chain::Forward, PacketFamily::Ipv6) => {
+            &["inet", "ip6"]
+        }
+        (_, PacketFamily::Ipv4) => &["ip"],
+        (_, PacketFamily::Ipv6) => &["ip6"],
+    };
+
+    let mut policy = ChainPolicy::Accept;
+    let mut chains: HashMap<&str, Vec<ChainRule>> = HashMap::new();
+    let mut sets: HashMap<&str, Vec<&str>> = HashMap::new();
+    let commands = ruleset
+        .preamble
+        .iter()
+        .map(|command| (command, None))
+        .chain(ruleset.sections.iter().flat_map(|(section, commands)| {
+            commands
+                .iter()
+                .map(move |command| (command, Some(*section)))
+        }));
+    for (command, section) in commands {
+        let tokens: Vec<&str> = command.splitn(6, ' ').collect();
+        if tokens.len() >= 5 && tokens[3] == "dfw" && families.contains(&tokens[2]) {
+            match (tokens[0], tokens[1], tokens.get(5)) {
+                ("add", "set", _) => {
+                    sets.entry(tokens[4]).or_default();
+                }
+                ("flush", "set", _) => {
+                    sets.insert(tokens[4], Vec::new());
+                }
+                ("add", "element", Some(elements)) => {
+                    sets.entry(tokens[4]).or_default().extend(values(elements))
+                }
+                _ => {}
+            }
+        }
+        if tokens.len() < 6 || tokens[3] != "dfw" || !families.contains(&tokens[2]) {
+            continue;
+        }
+        let rules = chains.entry(tokens[4]).or_default();
+        match (tokens[0], tokens[1]) {
+            ("add", "rule") => rules.push((command, tokens[5], section)),
+            ("insert", "rule") => rules.insert(0, (command, tokens[5], section)),
+            ("add", "chain") if tokens[4] == chain => {
+                if let Some(chain_policy) = parse_chain_policy(tokens[5])? {
+                    policy = chain_policy;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // A `goto` continues with the rules of the target chain, without returning to the base chain.
+    // The number of jumps is bounded by the number of chains, which rules out loops.
+    let mut current_chain = chain.as_str();
+    let mut jumps = 0;
+    'chains: loop {
+        for (command, rule, section) in chains.get(current_chain).into_iter().flatten() {
+            let verdict = match evaluate_rule(rule, packet, &sets)
+                .map_err(|e| format_err!("failed to simulate rule `{}`: {}", command, e))?
+            {
+                Some(verdict) => verdict,
+                None => continue,
+            };
+            if let Some(target) = verdict.strip_prefix("goto ") {
+                jumps += 1;
+                if jumps > chains.len() {
+                    bail!("rule `{}` results in a loop of chains", command);
+                }
+                current_chain = chains
+                    .keys()
+                    .find(|chain| **chain == target)
+                    .copied()
+                    .ok_or_else(|| format_err!("rule `{}` jumps to unknown chain", command))?;
+                continue 'chains;
+            }
+            return Ok(Simulation {
+                verdict,
+                rule: Some((*command).to_owned()),
+                section: *section,
+            });
+        }
+        break;
+    }
+
+    Ok(Simulation {
+        verdict: policy.to_string(),
+        rule: None,
+        section: None,
+    })
+}
+
+/// Get the IPv4 address a container has within a network, e.g. to use it as the source or
+/// destination of a simulated packet.
+pub fn container_address(
+    inventory: &dyn ContainerInventory,
+    container_name: &str,
+    network_name: &str,
+) -> Result<IpAddr> {
+    let container = inventory
+        .containers()?
+        .into_iter()
+        .find(|container| {
+            container
+                .names
+                .iter()
+                .any(|name| name.trim_start_matches('/') == container_name)
+        })
+        .ok_or_else(|| format_err!("container `{}` doesn't exist", container_name))?;
+    let network = inventory
+        .networks()?
+        .into_iter()
+        .find(|network| network.name == network_name)
+        .ok_or_else(|| format_err!("network `{}` doesn't exist", network_name))?;
+    let endpoint = network.containers.get(&container.id).ok_or_else(|| {
+        format_err!(
+            "container `{}` is not attached to network `{}`",
+            container_name,
+            network_name
+        )
+    })?;
+
+    let address = endpoint.ipv4_address.split('/').next().unwrap_or_default();
+    IpAddr::from_str(address).map_err(|_| {
+        format_err!(
+            "container `{}` has invalid address '{}' in network `{}`",
+            container_name,
+            endpoint.ipv4_address,
+            network_name
+        )
+    })
+}
+
+/// Get the policy set by the body of an `add chain` command, e.g. `{ policy drop ; }`.
+fn parse_chain_policy(body: &str) -> Result<Option<ChainPolicy>> {
+    let tokens = tokenize(body);
+    let definitions = match tokens.first() {
+        Some(definitions) if definitions.starts_with('{') => tokenize(set_content(definitions)),
+        _ => return Ok(None),
+    };
+    match definitions.iter().position(|token| *token == "policy") {
+        Some(index) => match definitions.get(index + 1) {
+            Some(policy) => Ok(Some(
+                ChainPolicy::from_str(policy.trim_end_matches(';'))
+                    .map_err(|problem| format_err!("{}", problem))?,
+            )),
+            None => bail!("chain policy is missing in `{}`", body),
+        },
+        None => Ok(None),
+    }
+}
+
+/// Evaluate a rule against the packet, returning the verdict if the rule matches and ends with a
+/// terminal statement.
+fn evaluate_rule(
+    rule: &str,
+    packet: &Packet,
+    sets: &HashMap<&str, Vec<&str>>,
+) -> Result<Option<String>> {
+    let mut tokens = Tokens::new(rule);
+    let mut matches = true;
+    while let Some(token) = tokens.next() {
+        match token {
+            "meta" => {}
+            "nfproto" => {
+                let (negate, value) = tokens.value()?;
+                let family = packet.family.to_string();
+                matches &= values(value).any(|value| value == family) != negate;
+            }
+            "iifname" | "oifname" => {
+                let (negate, value) = tokens.value()?;
+                let interface = if token == "iifname" {
+                    &packet.in_interface
+                } else {
+                    &packet.out_interface
+                };
+                matches &= interface.as_ref().is_some_and(|interface| {
+                    values(value).any(|value| interface_matches(value, interface)) != negate
+                });
+            }
+            "mark" => match tokens.expect("mark statement")? {
+                "set" => {
+                    tokens.expect("mark")?;
+                }
+                other => bail!("unsupported mark expression `{}`", other),
+            },
+            "ct" => match tokens.expect("conntrack key")? {
+                "state" => {
+                    let (negate, value) = tokens.value()?;
+                    let ct_state = packet.ct_state.to_string();
+                    matches &= values(value).any(|value| value == ct_state) != negate;
+                }
+                "status" => {
+                    let (negate, value) = tokens.value()?;
+                    matches &= values(value).any(|value| match value {
+                        "dnat" => packet.dnat,
+                        _ => false,
+                    }) != negate;
+                }
+                other => bail!("unsupported conntrack key `{}`", other),
+            },
+            "ip" | "ip6" => {
+                let family = if token == "ip" {
+                    PacketFamily::Ipv4
+                } else {
+                    PacketFamily::Ipv6
+                };
+                let field = tokens.expect("address field")?;
+                if field == "dscp" {
+                    // Setting the DSCP value implies the family of the packet
+                    match tokens.expect("dscp statement")? {
+                        "set" => {
+                            tokens.expect("dscp")?;
+                        }
+                        other => bail!("unsupported dscp expression `{}`", other),
+                    }
+                    matches &= packet.family == family;
+                    continue;
+                }
+                let (negate, value) = tokens.value()?;
+                let address = match field {
+                    "saddr" => packet.source_address,
+                    "daddr" => packet.destination_address,
+                    other => bail!("unsupported {} field `{}`", token, other),
+                };
+                let addresses = match value.strip_prefix('@') {
+                    Some(set) => sets
+                        .get(set)
+                        .cloned()
+                        .ok_or_else(|| format_err!("unknown set '{}'", set))?,
+                    None => values(value).collect(),
+                };
+                let mut matched = false;
+                for value in addresses {
+                    matched |=
+                        address.map_or(Ok(false), |address| address_matches(value, address))?;
+                }
+                matches &= packet.family == family && address.is_some() && matched != negate;
+            }
+            "tcp" | "udp" => {
+                let field = tokens.expect("port field")?;
+                let (negate, value) = tokens.value()?;
+                let port = match field {
+                    "sport" => packet.source_port,
+                    "dport" => packet.destination_port,
+                    other => bail!("unsupported {} field `{}`", token, other),
+                };
+                let mut matched = false;
+                for value in values(value) {
+                    matched |= port.map_or(Ok(false), |port| port_matches(value, port))?;
+                }
+                matches &= packet
+                    .protocol
+                    .as_ref()
+                    .is_some_and(|protocol| protocol == token)
+                    && port.is_some()
+                    && matched != negate;
+            }
+            "dup" => {
+                tokens.expect("`to`")?;
+                tokens.expect("duplication target")?;
+                if tokens.peek() == Some("device") {
+                    tokens.next();
+                    tokens.expect("duplication device")?;
+                }
+            }
+            "comment" => {
+                tokens.expect("comment")?;
+            }
+            // Rates are not simulated, packets are always assumed to be within the limit.
+            "limit" => {
+                tokens.expect("`name`")?;
+                tokens.expect("limit name")?;
+            }
+            // Quotas are not simulated either, packets are always assumed to be within the quota.
+            // DFW only uses quotas matching once they are exceeded to drop traffic, the rule thus
+            // never matches.
+            "quota" => {
+                tokens.expect("`name`")?;
+                tokens.expect("quota name")?;
+                matches = false;
+            }
+            "log" => {
+                if tokens.peek() == Some("prefix") {
+                    tokens.next();
+                    tokens.expect("log prefix")?;
+                }
+                if tokens.peek() == Some("level") {
+                    tokens.next();
+                    tokens.expect("log level")?;
+                }
+            }
+            "counter" => {}
+            "goto" => {
+                let chain = tokens.expect("chain")?;
+                return Ok(if matches {
+                    Some(format!("{} {}", token, chain))
+                } else {
+                    None
+                });
+            }
+            "accept" | "drop" | "reject" | "masquerade" => {
+                return Ok(if matches {
+                    Some(token.to_owned())
+                } else {
+                    None
+                });
+            }
+            "dnat" | "snat" => {
+                let mut target = tokens.expect("NAT target")?;
+                if target == "ip" && tokens.peek() == Some("addr") {
+                    let target = nat_map_target(&mut tokens, packet)?;
+                    return Ok(match target {
+                        Some(target) if matches => Some(format!("{} {}", token, target)),
+                        _ => None,
+                    });
+                }
+                if target == "to" {
+                    target = tokens.expect("NAT target")?;
+                }
+                return Ok(if matches {
+                    Some(format!("{} {}", token, target))
+                } else {
+                    None
+                });
+            }
+            other => bail!("unsupported expression `{}`", other),
+        }
+    }
+
+    // The rule has no terminal statement, the packet continues with the next rule.
+    Ok(None)
+}
+
+/// Look up the NAT target of a packet in a map of the form
+/// `ip addr . port to <key> map { <key> : <address> . <port>, ... }`.
+///
+/// Returns `None` if the map has no entry for the packet, in which case the rule doesn't match.
+fn nat_map_target(tokens: &mut Tokens, packet: &Packet) -> Result<Option<String>> {
+    for expected in &["addr", ".", "port", "to"] {
+        let token = tokens.expect(expected)?;
+        if token != *expected {
+            bail!("unsupported NAT expression `{}`", token);
+        }
+    }
+
+    let mut key = Vec::new();
+    loop {
+        match tokens.expect("NAT map")? {
+            "map" => break,
+            "." => {}
+            "ip" | "ip6" => match tokens.expect("address field")? {
+                "daddr" => key.push(packet.destination_address.map(|a| a.to_string())),
+                other => bail!("unsupported NAT map key `{}`", other),
+            },
+            protocol @ "tcp" | protocol @ "udp" => match tokens.expect("port field")? {
+                "dport" if packet.protocol.as_ref().is_some_and(|p| p == protocol) => {
+                    key.push(packet.destination_port.map(|p| p.to_string()))
+                }
+                "dport" => key.push(None),
+                other => bail!("unsupported NAT map key `{}`", other),
+            },
+            other => bail!("unsupported NAT map key `{}`", other),
+        }
+    }
+    let map = tokens.expect("NAT map")?;
+    let key = match key.into_iter().collect::<Option<Vec<_>>>() {
+        Some(key) => key.join(" . "),
+        None => return Ok(None),
+    };
+
+    for entry in values(map) {
+        let mut parts = entry.splitn(2, " : ");
+        let (entry_key, target) = match (parts.next(), parts.next()) {
+            (Some(entry_key), Some(target)) => (entry_key.trim(), target.trim()),
+            _ => bail!("invalid NAT map entry '{}'", entry),
+        };
+        if entry_key == key {
+            let mut target = target.splitn(2, " . ");
+            return match (target.next(), target.next()) {
+                (Some(address), Some(port)) => Ok(Some(format!("{}:{}", address, port))),
+                _ => bail!("invalid NAT map entry '{}'", entry),
+            };
+        }
+    }
+
+    Ok(None)
+}
+
+/// The tokens of a rule, see [`tokenize`](fn.tokenize.html).
+struct Tokens<'a> {
+    tokens: Vec<&'a str>,
+    position: usize,
+}
+
+impl<'a> Tokens<'a> {
+    fn new(rule: &'a str) -> Tokens<'a> {
+        Tokens {
+            tokens: tokenize(rule),
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<&'a str> {
+        self.tokens.get(self.position).copied()
+    }
+
+    fn next(&mut self) -> Option<&'a str> {
+        let token = self.peek();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<&'a str> {
+        self.next()
+            .ok_or_else(|| format_err!("expected {} at end of rule", expected))
+    }
+
+    /// Get the next value, which is optionally preceded by a `==` or `!=` operator. Returns
+    /// whether the value is negated.
+    fn value(&mut self) -> Result<(bool, &'a str)> {
+        match self.expect("value")? {
+            "!=" => Ok((true, self.expect("value")?)),
+            "==" => Ok((false, self.expect("value")?)),
+            value => Ok((false, value)),
+        }
+    }
+}
+
+/// Split a rule into its tokens, keeping sets (`{ ... }`) and quoted strings intact.
+pub(crate) fn tokenize(rule: &str) -> Vec<&str> {
+    let mut tokens = Vec::new();
+    let mut start = None;
+    let mut depth = 0;
+    let mut quoted = false;
+    for (index, c) in rule.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '{' if !quoted => depth += 1,
+            '}' if !quoted => depth -= 1,
+            ' ' if !quoted && depth == 0 => {
+                if let Some(start) = start.take() {
+                    tokens.push(&rule[start..index]);
+                }
+                continue;
+            }
+            _ => {}
+        }
+        if start.is_none() {
+            start = Some(index);
+        }
+    }
+    if let Some(start) = start {
+        tokens.push(&rule[start..]);
+    }
+    tokens
+}
+
+/// Get the content of a set, without the surrounding braces.
+fn set_content(set: &str) -> &str {
+    set.trim_start_matches('{').trim_end_matches('}')
+}
+
+/// Get the individual values of a value that is either a single value or a set.
+fn values(value: &str) -> impl Iterator<Item = &str> {
+    set_content(value)
+        .split(',')
+        .map(str::trim)
+        .filter(|value| !value.is_empty())
+}
+
+fn interface_matches(pattern: &str, interface: &str) -> bool {
+    let pattern = pattern.trim_matches('"');
+    if pattern.ends_with('*') {
+        interface.starts_with(pattern.trim_end_matches('*'))
+    } else {
+        pattern == interface
+    }
+}
+
+fn address_matches(value: &str, address: IpAddr) -> Result<bool> {
+    let mut parts = value.splitn(2, '/');
+    let network = IpAddr::from_str(parts.next().unwrap_or_default())
+        .map_err(|_| format_err!("invalid address '{}'", value))?;
+    let max_prefix_length = if network.is_ipv4() { 32 } else { 128 };
+    let prefix_length = match parts.next() {
+        Some(prefix_length) => u32::from_str(prefix_length)
+            .ok()
+            .filter(|prefix_length| *prefix_length <= max_prefix_length)
+            .ok_or_else(|| format_err!("invalid address '{}'", value))?,
+        None => max_prefix_length,
+    };
+
+    Ok(match (network, address) {
+        (IpAddr::V4(network), IpAddr::V4(address)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix_length).unwrap_or(0);
+            u32::from(network) & mask == u32::from(address) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(address)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix_length).unwrap_or(0);
+            u128::from(network) & mask == u128::from(address) & mask
+        }
+        _ => false,
+    })
+}
+
+fn port_matches(value: &str, port: u16) -> Result<bool> {
+    let parse =
+        |port: &str| u16::from_str(port).map_err(|_| format_err!("invalid port '{}'", value));
+    let mut parts = value.splitn(2, '-');
+    let from = parse(parts.next().unwrap_or_default())?;
+    let to = match parts.next() {
+        Some(to) => parse(to)?,
+        None => from,
+    };
+
+    Ok(from <= port && port <= to)
+}
@@ -0,0 +1,233 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! This module resolves Docker Swarm services to their virtual IPs (VIPs), such that rules can
+//! reference a service by name like any other container.
+//!
+//! Every service is represented as a container named after the service, which is attached to the
+//! networks the service has a VIP on. Rules thus target the VIP of the service instead of the
+//! addresses of its individual tasks.
+//!
+//! Swarm services are enabled through
+//! [`Defaults::swarm_services`](../types/struct.Defaults.html#structfield.swarm_services) and are
+//! only available if DFW was built with the `swarm` feature.
+
+use crate::errors::*;
+use crate::inventory::{
+    Container, ContainerAliases, ContainerInventory, ImagePorts, Network, NetworkEndpoint,
+};
+use crate::process::HostFacts;
+use failure::{bail, ResultExt};
+use serde::Deserialize;
+use std::collections::HashMap as Map;
+use std::net::IpAddr;
+use std::process::Command;
+
+/// A Docker Swarm service, as returned by `GET /services` of the Docker API.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SwarmService {
+    /// ID of the service.
+    #[serde(rename = "ID")]
+    pub id: String,
+    /// Specification of the service.
+    #[serde(rename = "Spec")]
+    pub spec: SwarmServiceSpec,
+    /// Endpoint of the service, carrying its virtual IPs.
+    #[serde(rename = "Endpoint", default)]
+    pub endpoint: SwarmServiceEndpoint,
+}
+
+/// The specification of a [`SwarmService`](struct.SwarmService.html).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct SwarmServiceSpec {
+    /// Name of the service.
+    #[serde(rename = "Name")]
+    pub name: String,
+    /// Labels of the service.
+    #[serde(rename = "Labels", default)]
+    pub labels: Map<String, String>,
+}
+
+/// The endpoint of a [`SwarmService`](struct.SwarmService.html).
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+pub struct SwarmServiceEndpoint {
+    /// Virtual IPs of the service, one per network. Services using DNS round-robin instead of a
+    /// VIP have none.
+    #[serde(rename = "VirtualIPs", default)]
+    pub virtual_ips: Vec<VirtualIp>,
+}
+
+/// A virtual IP of a [`SwarmService`](struct.SwarmService.html) within a network.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct VirtualIp {
+    /// ID of the network the virtual IP is assigned within.
+    #[serde(rename = "NetworkID")]
+    pub network_id: String,
+    /// Virtual IP in CIDR notation, e.g. `10.0.1.2/24`.
+    #[serde(rename = "Addr")]
+    pub address: String,
+}
+
+impl SwarmService {
+    /// Parse the services from a response of `GET /services` of the Docker API, or equivalently
+    /// from the output of `docker service inspect`.
+    pub fn from_api_response(response: &str) -> Result<Vec<SwarmService>> {
+        Ok(serde_json::from_str(response).context("failed to parse Swarm services")?)
+    }
+
+    /// Get the virtual IP of the service within the given network, if it has one.
+    pub fn virtual_ip(&self, network_id: &str) -> Option<&str> {
+        self.endpoint
+            .virtual_ips
+            .iter()
+            .find(|virtual_ip| virtual_ip.network_id == network_id)
+            .map(|virtual_ip| virtual_ip.address.as_str())
+    }
+}
+
+/// Query the services of the Swarm through the docker CLI, in the form of a response of
+/// `GET /services` of the Docker API.
+///
+/// If the Docker daemon is not a manager of a Swarm, the services cannot be listed and an empty
+/// list is returned.
+pub fn docker_services() -> Result<String> {
+    let output = Command::new("docker")
+        .args(["info", "--format", "{{.Swarm.ControlAvailable}}"])
+        .output()
+        .context("failed to query Swarm state, is the docker CLI available?")?;
+    if String::from_utf8_lossy(&output.stdout).trim() != "true" {
+        return Ok("[]".to_owned());
+    }
+
+    let output = Command::new("docker")
+        .args(["service", "ls", "--quiet"])
+        .output()
+        .context("failed to list Swarm services")?;
+    if !output.status.success() {
+        bail!(
+            "failed to list Swarm services: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    let service_ids = String::from_utf8_lossy(&output.stdout)
+        .split_whitespace()
+        .map(str::to_owned)
+        .collect::<Vec<_>>();
+    if service_ids.is_empty() {
+        return Ok("[]".to_owned());
+    }
+
+    let output = Command::new("docker")
+        .args(["service", "inspect"])
+        .args(&service_ids)
+        .output()
+        .context("failed to inspect Swarm services")?;
+    if !output.status.success() {
+        bail!(
+            "failed to inspect Swarm services: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Inventory adding the services of a Docker Swarm to the containers of another inventory.
+///
+/// Every service is listed as a container named after the service, carrying the labels of the
+/// service. Within every network the service has a virtual IP on, the virtual IP is listed as the
+/// address of the container. Services without virtual IPs are listed without networks.
+pub struct SwarmInventory<'a> {
+    inventory: Box<dyn ContainerInventory + 'a>,
+    query: Box<dyn Fn() -> Result<String> + 'a>,
+}
+
+impl<'a> SwarmInventory<'a> {
+    /// Create a new inventory adding the services returned by `query` to the given inventory.
+    ///
+    /// The query returns the services in the form of a response of `GET /services` of the Docker
+    /// API, see [`docker_services`](fn.docker_services.html).
+    pub fn new<F>(inventory: Box<dyn ContainerInventory + 'a>, query: F) -> SwarmInventory<'a>
+    where
+        F: Fn() -> Result<String> + 'a,
+    {
+        SwarmInventory {
+            inventory,
+            query: Box::new(query),
+        }
+    }
+
+    fn services(&self) -> Result<Vec<SwarmService>> {
+        SwarmService::from_api_response(&(self.query)()?)
+    }
+}
+
+impl<'a> ContainerInventory for SwarmInventory<'a> {
+    fn containers(&self) -> Result<Vec<Container>> {
+        let mut containers = self.inventory.containers()?;
+        containers.extend(self.services()?.into_iter().map(|service| Container {
+            id: service.id,
+            names: vec![service.spec.name],
+            labels: service.spec.labels,
+            ..Default::default()
+        }));
+
+        Ok(containers)
+    }
+
+    fn networks(&self) -> Result<Vec<Network>> {
+        let mut networks = self.inventory.networks()?;
+        let services = self.services()?;
+        for network in &mut networks {
+            for service in &services {
+                let virtual_ip = match service.virtual_ip(&network.id) {
+                    Some(virtual_ip) => virtual_ip,
+                    None => continue,
+                };
+                let address = virtual_ip.split('/').next().unwrap_or_default();
+                let endpoint = match address.parse::<IpAddr>() {
+                    Ok(IpAddr::V6(_)) => NetworkEndpoint {
+                        ipv6_address: virtual_ip.to_owned(),
+                        ..Default::default()
+                    },
+                    _ => NetworkEndpoint {
+                        ipv4_address: virtual_ip.to_owned(),
+                        ..Default::default()
+                    },
+                };
+                network.containers.insert(service.id.clone(), endpoint);
+            }
+        }
+
+        Ok(networks)
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases> {
+        self.inventory.container_aliases()
+    }
+
+    fn image_ports(&self) -> Result<ImagePorts> {
+        self.inventory.image_ports()
+    }
+
+    fn current_ruleset(&self) -> Option<String> {
+        self.inventory.current_ruleset()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts> {
+        self.inventory.host_facts()
+    }
+
+    fn resolve_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>> {
+        self.inventory.resolve_hostname(hostname)
+    }
+
+    fn cidr_file(&self, file: &str) -> Result<String> {
+        self.inventory.cidr_file(file)
+    }
+}
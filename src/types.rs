@@ -56,22 +56,98 @@
 //! expose_port = { host_port = 8080, container_port = 80, family = "tcp" }
 //! ```
 
+use crate::errors::DFWError;
 use crate::nftables::*;
 use derive_builder::Builder;
-use serde::{de, Deserialize};
+use serde::ser::SerializeStruct;
+use serde::{de, Deserialize, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::fmt;
+use std::io::Read;
 use std::marker::PhantomData;
+use std::net::{IpAddr, SocketAddr};
 use std::str::FromStr;
+use strum_macros::{Display, EnumString};
 
 const DEFAULT_PROTOCOL: &str = "tcp";
 
+/// Latest version of the configuration schema supported by DFW, see [`DFW`](struct.DFW.html).
+pub const CONFIG_VERSION: u32 = 1;
+
+/// Host port of an [`ExposePort`](struct.ExposePort.html) that is to be assigned automatically,
+/// given as `auto` in the configuration.
+pub const AUTO_HOST_PORT: u16 = 0;
+
+/// Network of a rule matching every network the containers of the rule are attached to.
+pub const WILDCARD_NETWORK: &str = "*";
+
+/// Network of the containers sharing the network namespace of the host, i.e. containers run with
+/// `--network host`. Such containers have no address of their own, their ports are ports of the
+/// host.
+pub const HOST_NETWORK: &str = "host";
+
+/// External network interfaces that are determined from the default routes of the host, see
+/// [`Defaults.external_network_interfaces`](struct.Defaults.html#structfield.external_network_interfaces).
+pub const AUTO_EXTERNAL_NETWORK_INTERFACES: &str = "auto";
+
+/// External network interface of a rule disabling the interface match, the rule thus applies to
+/// all interfaces, see e.g.
+/// [`WiderWorldToContainerRule.external_network_interface`](struct.WiderWorldToContainerRule.html#structfield.external_network_interface).
+pub const NO_EXTERNAL_NETWORK_INTERFACE: &str = "none";
+
+/// Prefix of a source CIDR referencing a file the CIDRs are read from, see
+/// [`WiderWorldToContainerRule.source_cidr_v4`](struct.WiderWorldToContainerRule.html#structfield.source_cidr_v4).
+pub const CIDR_FILE_PREFIX: &str = "@file:";
+
+/// Prefix of a source CIDR referencing a hostname whose addresses are allowed, see
+/// [`WiderWorldToContainerRule.source_cidr_v4`](struct.WiderWorldToContainerRule.html#structfield.source_cidr_v4).
+pub const CIDR_DNS_PREFIX: &str = "@dns:";
+
+/// Default number of requests DFW sends to the Docker API concurrently, see
+/// [`Defaults.docker_concurrency`](struct.Defaults.html#structfield.docker_concurrency).
+pub const DEFAULT_DOCKER_CONCURRENCY: usize = 8;
+
+/// Default rate packets are logged at, see
+/// [`Defaults.log_rate`](struct.Defaults.html#structfield.log_rate).
+pub const DEFAULT_LOG_RATE: &str = "100/second";
+
+/// Address of the embedded DNS server Docker provides to containers on user-defined networks, see
+/// [`Defaults.allow_embedded_dns`](struct.Defaults.html#structfield.allow_embedded_dns).
+pub const EMBEDDED_DNS_ADDRESS: &str = "127.0.0.11";
+
+/// Default number of times DFW retries a failed query of the Docker API, see
+/// [`Defaults.docker_retries`](struct.Defaults.html#structfield.docker_retries).
+pub const DEFAULT_DOCKER_RETRIES: usize = 3;
+
+/// Default delay in milliseconds before the first retry of a failed query of the Docker API, see
+/// [`Defaults.docker_retry_backoff`](struct.Defaults.html#structfield.docker_retry_backoff).
+pub const DEFAULT_DOCKER_RETRY_BACKOFF: u64 = 250;
+
+/// Family of an exposed port, e.g. `tcp` or `udp`.
+pub type PortFamily = String;
+
 /// `DFW` is the parent type defining the complete configuration used by DFW to build up the
 /// firewall rules.
 ///
 /// Every section is optional.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
 pub struct DFW {
+    /// Version of the configuration schema the configuration was written for.
+    ///
+    /// Can be left blank, the configuration is then assumed to match the latest version supported
+    /// by DFW, see [`CONFIG_VERSION`](constant.CONFIG_VERSION.html). Configurations declaring a
+    /// newer version are rejected, see
+    /// [`check_config_version`](../util/fn.check_config_version.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// version = 1
+    /// ```
+    #[serde(default)]
+    pub version: Option<u32>,
     /// The `defaults` configuration section
     #[serde(default)]
     pub defaults: Option<Defaults>,
@@ -80,6 +156,8 @@ pub struct DFW {
     pub initialization: Option<Initialization>,
     /// The `container_to_container` configuration section
     pub container_to_container: Option<ContainerToContainer>,
+    /// The `dns` configuration section
+    pub dns: Option<Dns>,
     /// The `container_to_wider_world` configuration section
     pub container_to_wider_world: Option<ContainerToWiderWorld>,
     /// The `container_to_host` configuration section
@@ -88,10 +166,142 @@ pub struct DFW {
     pub wider_world_to_container: Option<WiderWorldToContainer>,
     /// The `container_dnat` configuration section
     pub container_dnat: Option<ContainerDNAT>,
+    /// The `runtime` configuration section
+    #[serde(default)]
+    pub runtime: Option<Runtime>,
+}
+
+impl DFW {
+    /// Deserialize the TOML-configuration read from the reader, e.g. a pipe or an embedded
+    /// resource.
+    ///
+    /// TOML can't be parsed incrementally, the reader is thus read to its end before the
+    /// configuration is deserialized.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::DFW;
+    /// # use std::io::Cursor;
+    /// let reader = Cursor::new("[defaults]\nexternal_network_interfaces = \"eth0\"\n");
+    /// let dfw = DFW::from_reader(reader).unwrap();
+    /// assert!(dfw.defaults.is_some());
+    /// ```
+    pub fn from_reader<R: Read>(mut reader: R) -> Result<DFW, DFWError> {
+        let mut contents = Vec::new();
+        reader
+            .read_to_end(&mut contents)
+            .map_err(|e| DFWError::ConfigError {
+                message: format!("failed to read configuration: {}", e),
+            })?;
+        DFW::from_slice(&contents)
+    }
+
+    /// Deserialize the TOML-configuration contained in the byte slice.
+    ///
+    /// The variables of the configuration are resolved and the rules of the top-level `[[rules]]`
+    /// array are moved into their sections, see [`resolve_vars`](../util/fn.resolve_vars.html) and
+    /// [`distribute_rules`](../util/fn.distribute_rules.html).
+    pub fn from_slice(contents: &[u8]) -> Result<DFW, DFWError> {
+        let contents = std::str::from_utf8(contents).map_err(|e| DFWError::ConfigError {
+            message: e.to_string(),
+        })?;
+        crate::util::from_config_str(contents).map_err(|e| DFWError::ConfigError {
+            message: e.to_string(),
+        })
+    }
+}
+
+/// Sections of the configuration, which can be processed individually.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Display, EnumString)]
+#[strum(serialize_all = "snake_case")]
+pub enum Section {
+    /// The `initialization` section
+    Initialization,
+    /// The `defaults` section
+    Defaults,
+    /// The `container_to_container` section
+    ContainerToContainer,
+    /// The `dns` section
+    Dns,
+    /// The `container_to_wider_world` section
+    ContainerToWiderWorld,
+    /// The `container_to_host` section
+    ContainerToHost,
+    /// The `wider_world_to_container` section
+    WiderWorldToContainer,
+    /// The `container_dnat` section
+    #[strum(to_string = "container_dnat")]
+    ContainerDNAT,
+}
+
+impl Section {
+    /// All sections, in the order they are processed in.
+    pub const VALUES: [Section; 8] = [
+        Section::Initialization,
+        Section::Defaults,
+        Section::ContainerToContainer,
+        Section::Dns,
+        Section::ContainerToWiderWorld,
+        Section::ContainerToHost,
+        Section::WiderWorldToContainer,
+        Section::ContainerDNAT,
+    ];
+}
+
+/// Get the order the sections of the configuration are processed in.
+///
+/// The sections named in `defaults.section_order` come first, in the given order, followed by all
+/// other sections in their default order (see [`Section::VALUES`]). The names have to be distinct
+/// section names, e.g. `container_to_host`.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::types::{section_order, Section, DFW};
+/// let dfw: DFW = toml::from_str(r#"
+///     [defaults]
+///     section_order = ["container_to_host", "container_to_container"]
+/// "#).unwrap();
+///
+/// let order = section_order(&dfw).unwrap();
+/// assert_eq!(
+///     &[Section::ContainerToHost, Section::ContainerToContainer, Section::Initialization],
+///     &order[..3]
+/// );
+/// ```
+///
+/// [`Section::VALUES`]: enum.Section.html#associatedconstant.VALUES
+pub fn section_order(dfw: &DFW) -> std::result::Result<Vec<Section>, String> {
+    let names = dfw
+        .defaults
+        .as_ref()
+        .and_then(|defaults| defaults.section_order.as_ref())
+        .map_or(&[][..], |names| &names[..]);
+
+    let mut order = Vec::with_capacity(Section::VALUES.len());
+    for name in names {
+        let section = Section::from_str(name)
+            .map_err(|_| format!("section order contains unknown section '{}'", name))?;
+        if order.contains(&section) {
+            return Err(format!(
+                "section order contains section '{}' more than once",
+                name
+            ));
+        }
+        order.push(section);
+    }
+    for section in Section::VALUES.iter() {
+        if !order.contains(section) {
+            order.push(*section);
+        }
+    }
+
+    Ok(order)
 }
 
 /// The default configuration section, used by DFW for rule processing.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct Defaults {
     /// Specify the names of custom nft-tables that should be partially managed.
@@ -121,16 +331,27 @@ pub struct Defaults {
     pub custom_tables: Option<Vec<Table>>,
 
     /// This defines the external network interfaces of the host to consider during building the
-    /// rules. The value can be non-existant, a string, or a sequence of strings.
+    /// rules. The value can be non-existant, a string, a sequence of strings, or a struct listing
+    /// the interfaces per address family, see [`FamilyInterfaces`](struct.FamilyInterfaces.html).
+    ///
+    /// The first interface is the primary one, which rules inherit if they don't specify an
+    /// interface themselves. If the interfaces are listed per family, each family has its own
+    /// primary interface.
+    ///
+    /// If the value is `"auto"` or an empty sequence, the interfaces carrying the default routes
+    /// (IPv4 and IPv6) of the host are used, the interface of the preferred IPv4 default route
+    /// being the primary one. This is determined whenever the rules are generated.
     ///
     /// # Example
     ///
     /// ```toml
     /// external_network_interfaces = "eth0"
     /// external_network_interfaces = ["eth0", "eth1"]
+    /// external_network_interfaces = "auto"
+    /// external_network_interfaces = { v4 = ["eth0"], v6 = ["eth1"] }
     /// ```
-    #[serde(default, deserialize_with = "option_string_or_seq_string")]
-    pub external_network_interfaces: Option<Vec<String>>,
+    #[serde(default, deserialize_with = "option_family_interfaces")]
+    pub external_network_interfaces: Option<FamilyInterfaces>,
 
     /// This defines whether the default Docker bridge (usually `docker0`) is allowed to access host
     /// resources.
@@ -140,263 +361,1669 @@ pub struct Defaults {
     /// [container-to-host section]: struct.ContainerToHostRule.html
     #[serde(default)]
     pub default_docker_bridge_to_host_policy: ChainPolicy,
-}
-
-/// Reference to an nftables table, specifically to the input- and forward-chains within it.
-///
-/// This is used by DFW when managing other tables is required.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
-#[serde(deny_unknown_fields)]
-pub struct Table {
-    /// Name of the custom table.
-    pub name: String,
 
-    /// Names of the input and forward chains defined within the custom table.
-    pub chains: Vec<String>,
-}
-
-/// The initialization section allows you to execute any commands against nftables.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Default)]
-#[serde(deny_unknown_fields)]
-pub struct Initialization {
-    /// Initialization rules for nftables
+    /// This defines whether packets in the conntrack state `invalid` are dropped at the top of the
+    /// input- and forward-chains, before any other rule is evaluated. This also applies to the
+    /// chains of the custom tables.
+    ///
+    /// Defaults to `true`.
     ///
     /// # Example
     ///
     /// ```toml
-    /// [initialization]
-    /// rules = [
-    ///     "add table inet custom",
-    ///     "flush table inet custom",
-    ///     # ...
-    /// ]
+    /// drop_invalid = false
     /// ```
-    pub rules: Option<Vec<String>>,
-}
+    #[serde(default = "default_drop_invalid")]
+    pub drop_invalid: bool,
 
-/// The container-to-container section, defining how containers can communicate amongst each other.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct ContainerToContainer {
-    /// The `default_policy` defines the default for when there is not a specific rule.
-    ///
-    /// # Filtering traffic within the same bridge
+    /// This defines how the source address of traffic leaving the host through the external
+    /// network interfaces is translated, see [`EgressNat`](enum.EgressNat.html).
     ///
-    /// Depending on how your host is configured, traffic whose origin and destination interface are
-    /// the same bridge is _not_ filtered by the kernel netfilter module. This means that this
-    /// default policy is not honored for traffic between containers that are on the same Docker
-    /// network, but only for traffic that traverses two bridges.
+    /// Defaults to `masquerade`.
     ///
-    /// If your kernel has the `br_netfilter` kernel-module available, you can set the sysctl
-    /// `net.bridge.bridge-nf-call-iptables` to `1` to have the netfilter-module act on traffic
-    /// within the same bridge, too. You can set this value temporarily like this:
+    /// # Example
     ///
-    /// ```norun
-    /// sysctl net.bridge.bridge-nf-call-iptables=1
+    /// ```toml
+    /// egress_nat = "masquerade"
+    /// egress_nat = { snat = "203.0.113.1" }
     /// ```
+    #[serde(default)]
+    pub egress_nat: EgressNat,
+
+    /// This defines whether IPv6 traffic leaving the host through the external network interfaces
+    /// is masqueraded as well, e.g. for container networks using unique local addresses without
+    /// a routed IPv6 prefix.
     ///
-    /// To permanently set this configuration, take a look at `man sysctl.d` and `man sysctl.conf`.
-    pub default_policy: ChainPolicy,
-    /// An optional list of rules, see
-    /// [`ContainerToContainerRule`](struct.ContainerToContainerRule.html).
+    /// Without it, IPv6 traffic is only translated if `egress_nat` translates it to a static IPv6
+    /// address.
     ///
-    /// # Example
+    /// Defaults to `false`.
     ///
-    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
+    /// # Example
     ///
     /// ```toml
-    /// [[container_to_container.rules]]
-    /// # first rule here
-    /// [[container_to_container.rules]]
-    /// # second rule here
+    /// ipv6_masquerade = true
     /// ```
-    ///
-    /// [toml-aot]:
-    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
-    pub rules: Option<Vec<ContainerToContainerRule>>,
-}
-
-/// Definition for a rule to be used in the container-to-container section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct ContainerToContainerRule {
-    /// Common network between the source container and the destination container to apply the rule
-    /// to.
-    pub network: String,
-    /// Source container to apply the rule to.
-    pub src_container: Option<String>,
-    /// Destination container to apply the rule to.
-    pub dst_container: Option<String>,
-    /// Additional match-string, which will be added to the nftables command.
-    pub matches: Option<String>,
-    /// Verdict for rule (accept, drop or reject).
-    #[serde(alias = "action")]
-    pub verdict: RuleVerdict,
-}
+    #[serde(default)]
+    pub ipv6_masquerade: bool,
 
-/// The container-to-wider-world section, defining how containers can communicate with the wider
-/// world.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct ContainerToWiderWorld {
-    /// The `default_policy` defines the default for when there is not a specific rule.
-    pub default_policy: RuleVerdict,
-    /// An optional list of rules, see
-    /// [`ContainerToWiderWorldRule`](struct.ContainerToWiderWorldRule.html).
+    /// This defines the range of host ports exposed ports are assigned from, if their host port is
+    /// to be assigned automatically (see [`ExposePort`](struct.ExposePort.html)).
     ///
     /// # Example
     ///
-    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
-    ///
     /// ```toml
-    /// [[container_to_wider_world.rules]]
-    /// # first rule here
-    /// [[container_to_wider_world.rules]]
-    /// # second rule here
+    /// auto_port_range = "30000-32767"
     /// ```
-    ///
-    /// [toml-aot]:
-    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
-    pub rules: Option<Vec<ContainerToWiderWorldRule>>,
-}
-
-/// Definition for a rule to be used in the container-to-wider-world section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct ContainerToWiderWorldRule {
-    /// Network of the source container to apply the rule to.
-    pub network: Option<String>,
-    /// Source container to apply the rule to.
-    pub src_container: Option<String>,
-    /// Additional match-string, which will be added to the nftables command.
-    pub matches: Option<String>,
-    /// Verdict for rule (accept, drop or reject).
-    #[serde(alias = "action")]
-    pub verdict: RuleVerdict,
-    /// Specific external network interface to target.
-    pub external_network_interface: Option<String>,
-}
+    #[serde(default)]
+    pub auto_port_range: Option<PortRange>,
 
-/// The container-to-host section, defining how containers can communicate with the host.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct ContainerToHost {
-    /// The `default_policy` defines the default for when there is not a specific rule.
-    pub default_policy: RuleVerdict,
-    /// An optional list of rules, see
-    /// [`ContainerToHostRule`](struct.ContainerToHostRule.html).
+    /// This defines whether the rules are generated using only constructs supported by old
+    /// versions of `nft`, independent of the version detected on the host.
     ///
-    /// # Example
+    /// Without it, DFW falls back to the compatible constructs only if the detected version of
+    /// `nft` is too old.
     ///
-    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
+    /// Defaults to `false`.
+    ///
+    /// # Example
     ///
     /// ```toml
-    /// [[container_to_host.rules]]
-    /// # first rule here
-    /// [[container_to_host.rules]]
-    /// # second rule here
+    /// compat_mode = true
     /// ```
-    ///
-    /// [toml-aot]:
-    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
-    pub rules: Option<Vec<ContainerToHostRule>>,
-}
-
-/// Definition for a rule to be used in the container-to-host section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct ContainerToHostRule {
-    /// Network of the source container to apply the rule to.
-    pub network: String,
-    /// Source container to apply the rule to.
-    pub src_container: Option<String>,
-    /// Additional match-string, which will be added to the nftables command.
-    pub matches: Option<String>,
-    /// Verdict for rule (accept, drop or reject).
-    #[serde(alias = "action")]
-    pub verdict: RuleVerdict,
-}
+    #[serde(default)]
+    pub compat_mode: bool,
 
-/// The wider-world-to-container section, defining how containers can reached from the wider world.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct WiderWorldToContainer {
-    /// An optional list of rules, see
-    /// [`WiderWorldToContainerRule`](struct.WiderWorldToContainerRule.html).
+    /// Name of the network namespace to apply the rules in.
     ///
-    /// # Example
+    /// If set, `nft` is executed through `ip netns exec` within the namespace, which allows running
+    /// a DFW instance per namespace. The generated rules are the same either way.
     ///
-    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
+    /// # Example
     ///
     /// ```toml
-    /// [[wider_world_to_container.rules]]
-    /// # first rule here
-    /// [[wider_world_to_container.rules]]
-    /// # second rule here
+    /// netns = "tenant-a"
     /// ```
-    ///
-    /// [toml-aot]:
-    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
-    pub rules: Option<Vec<WiderWorldToContainerRule>>,
-}
-
-/// Definition for a rule to be used in the wider-world-to-container section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
-#[serde(deny_unknown_fields)]
-pub struct WiderWorldToContainerRule {
-    /// Network of the destination container to apply the rule to.
-    pub network: String,
-
-    /// Destination container to apply the rule to.
-    pub dst_container: String,
+    pub netns: Option<String>,
 
-    /// Ports to apply the rule to.
+    /// Path of the `nft` binary the rules are applied with.
     ///
-    /// Defined as:
+    /// Can be left blank, `nft` is then looked up through the `PATH` of DFW. This allows using
+    /// `nft` from a non-standard location or pinning a specific build.
     ///
-    /// * a single integer
+    /// # Example
     ///
-    /// * a single string
+    /// ```toml
+    /// nft_path = "/opt/nftables/sbin/nft"
+    /// ```
+    pub nft_path: Option<String>,
+
+    /// This defines how many requests DFW sends to the Docker API concurrently, when it retrieves
+    /// the details of the networks.
     ///
-    /// * a single struct
+    /// Defaults to [`DEFAULT_DOCKER_CONCURRENCY`](constant.DEFAULT_DOCKER_CONCURRENCY.html).
     ///
-    /// * a list of integers
+    /// # Example
     ///
-    /// * a list of strings
+    /// ```toml
+    /// docker_concurrency = 4
+    /// ```
+    #[serde(default = "default_docker_concurrency")]
+    pub docker_concurrency: usize,
+
+    /// This defines how many times DFW retries a query of the Docker API that failed, e.g. because
+    /// the Docker daemon is restarting, before it gives up on the reconcile.
     ///
-    /// * a list of structs
+    /// Defaults to [`DEFAULT_DOCKER_RETRIES`](constant.DEFAULT_DOCKER_RETRIES.html).
     ///
     /// # Example
     ///
-    /// All of the following are legal TOML fragments:
-    ///
     /// ```toml
-    /// expose_port = 80
-    /// expose_port = [80, 443]
-    /// expose_port = "53/udp"
-    /// expose_port = ["80/tcp", "53/udp"]
-    ///
-    /// # The following four all result in the same definition
-    /// expose_port = { host_port = 8080 }
-    /// expose_port = { host_port = 8080, container_port = 8080 }
-    /// expose_port = { host_port = 8080, family = "tcp" }
-    /// expose_port = { host_port = 8080, container_port = 8080, family = "tcp" }
-    ///
-    /// expose_port = [
-    ///     { host_port = 80 },
-    ///     { host_port = 53, family = "udp" },
-    ///     { host_port = 443, container_port = 8443 },
-    /// ]
+    /// docker_retries = 5
     /// ```
-    #[serde(deserialize_with = "single_or_seq_string_or_struct")]
-    pub expose_port: Vec<ExposePort>,
-
-    /// Specific external network interface to target.
-    pub external_network_interface: Option<String>,
+    #[serde(default = "default_docker_retries")]
+    pub docker_retries: usize,
 
-    /// Source CIDRs (IPv4) to which incoming traffic should be restricted.
+    /// This defines the delay in milliseconds before the first retry of a failed query of the
+    /// Docker API. The delay doubles with every further retry.
     ///
-    /// This can be:
+    /// Defaults to
+    /// [`DEFAULT_DOCKER_RETRY_BACKOFF`](constant.DEFAULT_DOCKER_RETRY_BACKOFF.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// docker_retry_backoff = 500
+    /// ```
+    #[serde(default = "default_docker_retry_backoff")]
+    pub docker_retry_backoff: u64,
+
+    /// This defines whether the services of a Docker Swarm are resolved, such that rules can
+    /// reference a service by its name like a container. The rules then target the virtual IP of
+    /// the service within the network, instead of the addresses of its individual tasks.
+    ///
+    /// This requires DFW to be built with the `swarm` feature and the Docker daemon to be a
+    /// manager of the Swarm.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// swarm_services = true
+    /// ```
+    #[serde(default)]
+    pub swarm_services: bool,
+
+    /// This defines whether the rules are annotated with their location in the configuration, see
+    /// [`Provenance`](struct.Provenance.html).
+    ///
+    /// If set, the comment of every rule generated from a rule of the configuration contains the
+    /// file and line the rule was defined at, e.g. `source=conf.d/10-web.toml:12`. Note that nft
+    /// limits comments to 128 characters, which has to fit the path of the file as it was given to
+    /// DFW.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub annotate_rules: bool,
+
+    /// This defines whether the connections originating from each network are tracked in a
+    /// distinct conntrack zone.
+    ///
+    /// If multiple networks use overlapping subnets, connections of containers with the same
+    /// address are otherwise indistinguishable for conntrack. If set, every network is assigned a
+    /// zone, derived from the ID of the network, such that the zone is stable while the network
+    /// exists.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub conntrack_zones: bool,
+
+    /// This defines the conntrack timeout policies rules can assign to the connections they
+    /// match, keyed by the name of the policy, see
+    /// [`WiderWorldToContainerRule.ct_timeout`](struct.WiderWorldToContainerRule.html#structfield.ct_timeout).
+    ///
+    /// Every policy is created as an nftables `ct timeout` object of the same name, e.g. to reclaim
+    /// the conntrack entries of a frequently scanned port faster. The keys of a policy are the
+    /// protocol and the conntrack state joined by an underscore, e.g. `tcp_established` or
+    /// `udp_replied`, and all keys of a policy have to share the protocol. The timeouts are given
+    /// as a number followed by one of the units `s`, `m`, `h` or `d`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// ct_timeouts.scanned = { tcp_established = "1h", tcp_close = "10s" }
+    /// ```
+    pub ct_timeouts: Option<BTreeMap<String, BTreeMap<String, String>>>,
+
+    /// This defines the order the sections are processed in, which determines the precedence of
+    /// the rules of different sections that apply to the same traffic.
+    ///
+    /// The sections named here are processed first, in the given order, all other sections follow
+    /// in their default order. Every section may only be named once.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// section_order = ["container_to_host", "container_to_container"]
+    /// ```
+    pub section_order: Option<Vec<String>>,
+
+    /// This defines the rate packets are logged at by the rules that enable logging, e.g. through
+    /// [`ContainerToContainerRule.log`](struct.ContainerToContainerRule.html#structfield.log).
+    ///
+    /// All logging rules share a single limit, such that a burst of traffic matching many rules
+    /// cannot flood the log. Packets exceeding the rate are not logged, the verdict of their rule
+    /// still applies.
+    ///
+    /// Defaults to [`DEFAULT_LOG_RATE`](constant.DEFAULT_LOG_RATE.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// log_rate = "10/second"
+    /// ```
+    #[serde(default = "default_log_rate")]
+    pub log_rate: String,
+
+    /// This defines whether the rules of the input- and forward-chains are split into a chain per
+    /// network interface, which packets are dispatched to through `goto` based on the interface
+    /// they were received on.
+    ///
+    /// With many networks, a packet then only traverses the rules of the network it originates
+    /// from instead of the rules of all networks. Rules that don't depend on the input interface
+    /// are copied into every chain, such that the first matching rule stays the same.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// network_chains = true
+    /// ```
+    #[serde(default)]
+    pub network_chains: bool,
+
+    /// This defines whether the rules of the input- and forward-chains that only apply to the
+    /// traffic of a single external network interface are split into a chain per external network
+    /// interface. Packets are dispatched to these chains through `goto`, based on the interface
+    /// they were received on or, if forwarded, are sent out on.
+    ///
+    /// With many external network interfaces, a packet then only traverses the rules of its
+    /// external network interface instead of the rules duplicated for all of them. Rules that
+    /// don't depend on the interface are copied into every chain, such that the first matching
+    /// rule stays the same.
+    ///
+    /// This cannot be combined with `network_chains`.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// external_interface_chains = true
+    /// ```
+    #[serde(default)]
+    pub external_interface_chains: bool,
+
+    /// This defines whether the DNAT rules of the prerouting chains only apply to new connections,
+    /// through a `ct state new` match. Packets of established connections are translated by
+    /// conntrack, evaluating the DNAT rules for them is redundant.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// dnat_new_only = false
+    /// ```
+    #[serde(default = "default_dnat_new_only")]
+    pub dnat_new_only: bool,
+
+    /// This defines whether the forward-chain marks and accepts the established connections that
+    /// were translated by DNAT, through a `ct status dnat` rule at the start of the stateful
+    /// preamble.
+    ///
+    /// The packets of these connections are then marked, such that custom tables accept them as
+    /// well. New connections are not affected: they are only accepted by the forward rules of the
+    /// sections, such that e.g. draining ports and the rules of the `container_to_container`
+    /// section apply to them.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// dnat_accept_shortcut = false
+    /// ```
+    #[serde(default = "default_dnat_accept_shortcut")]
+    pub dnat_accept_shortcut: bool,
+
+    /// This limits the number of rules generated for the rules of the configuration, across all
+    /// sections.
+    ///
+    /// A single rule can expand into many rules, e.g. through the wildcard network, tags or
+    /// containers with multiple addresses. If the limit is exceeded, generating the rules fails
+    /// with an error naming the rule of the configuration whose expansion exceeded it, such that
+    /// a typo cannot result in an excessive number of rules. The rules DFW generates on its own,
+    /// e.g. for the default policies, are not counted.
+    ///
+    /// Can be left blank, the number of rules is then unlimited.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// max_generated_rules = 10000
+    /// ```
+    #[serde(default)]
+    pub max_generated_rules: Option<usize>,
+
+    /// This defines how a hostname referenced by a source CIDR through `@dns:` is handled if it
+    /// cannot be resolved, see [`DnsFailurePolicy`](enum.DnsFailurePolicy.html).
+    ///
+    /// Defaults to `keep`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// dns_failure_policy = "error"
+    /// ```
+    #[serde(default)]
+    pub dns_failure_policy: DnsFailurePolicy,
+
+    /// This defines whether the containers on user-defined networks may reach the embedded DNS
+    /// server of Docker, at [`EMBEDDED_DNS_ADDRESS`](constant.EMBEDDED_DNS_ADDRESS.html) on port 53
+    /// over TCP and UDP.
+    ///
+    /// If set, the `container_to_host` section allows the DNS traffic of every user-defined bridge
+    /// network ahead of its rules and default policy, such that a strict default policy does not
+    /// break name resolution. The default Docker bridge is not served by the embedded DNS server
+    /// and is thus skipped, as are networks without a bridge such as `host` and `none`.
+    ///
+    /// Docker answers the queries to the embedded DNS server within the network namespace of the
+    /// container, they never traverse the input chain of the host. With current Docker versions
+    /// these rules are thus a no-op, they only serve as a safeguard for setups in which the
+    /// queries reach the host after all.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// allow_embedded_dns = false
+    /// ```
+    #[serde(default = "default_allow_embedded_dns")]
+    pub allow_embedded_dns: bool,
+
+    /// This overrides the priority and policy of the base chains DFW creates, per hook, see
+    /// [`BaseChains`](struct.BaseChains.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// [defaults.base_chains]
+    /// input = { priority = 10, policy = "drop" }
+    /// postrouting = { priority = 50 }
+    /// ```
+    #[serde(default)]
+    pub base_chains: BaseChains,
+
+    /// This defines for how many seconds the rules of a container are kept after the container
+    /// vanished, e.g. while it restarts after a crash, see
+    /// [`GracePeriodInventory`](../inventory/struct.GracePeriodInventory.html).
+    ///
+    /// Vanished containers are only tracked while DFW keeps running, i.e. not across invocations
+    /// with `--run-once`.
+    ///
+    /// Defaults to `0`, removing the rules right away.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// rule_removal_grace_s = 30
+    /// ```
+    #[serde(default)]
+    pub rule_removal_grace_s: u64,
+
+    /// This defines whether rules added to the DFW tables by someone else than DFW are preserved
+    /// when the rules are applied.
+    ///
+    /// Instead of flushing the DFW tables, only the rules carrying a DFW marker comment are
+    /// replaced, which requires the current ruleset to be available. The rules set up by DFW
+    /// itself, e.g. accepting established connections, are marked as well in that case. Foreign
+    /// rules keep their position relative to the rules added by DFW.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub preserve_foreign_rules: bool,
+
+    /// This defines the families of the tables the filter rules are created in, see
+    /// [`TableFamily`](enum.TableFamily.html).
+    ///
+    /// Defaults to `inet`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// table_family = "split"
+    /// ```
+    #[serde(default)]
+    pub table_family: TableFamily,
+}
+
+impl Defaults {
+    /// Check if the external network interfaces are determined from the default routes of the
+    /// host, see
+    /// [`external_network_interfaces`](#structfield.external_network_interfaces).
+    pub fn auto_external_network_interfaces(&self) -> bool {
+        match &self.external_network_interfaces {
+            Some(interfaces) if !interfaces.is_split() => {
+                interfaces.v4.is_empty() || interfaces.v4[..] == [AUTO_EXTERNAL_NETWORK_INTERFACES]
+            }
+            _ => false,
+        }
+    }
+}
+
+impl Default for Defaults {
+    fn default() -> Defaults {
+        Defaults {
+            custom_tables: None,
+            external_network_interfaces: None,
+            default_docker_bridge_to_host_policy: ChainPolicy::default(),
+            drop_invalid: default_drop_invalid(),
+            egress_nat: EgressNat::default(),
+            ipv6_masquerade: false,
+            auto_port_range: None,
+            compat_mode: false,
+            netns: None,
+            nft_path: None,
+            docker_concurrency: default_docker_concurrency(),
+            docker_retries: default_docker_retries(),
+            docker_retry_backoff: default_docker_retry_backoff(),
+            swarm_services: false,
+            annotate_rules: false,
+            conntrack_zones: false,
+            ct_timeouts: None,
+            section_order: None,
+            log_rate: default_log_rate(),
+            network_chains: false,
+            external_interface_chains: false,
+            dnat_new_only: default_dnat_new_only(),
+            dnat_accept_shortcut: default_dnat_accept_shortcut(),
+            max_generated_rules: None,
+            dns_failure_policy: DnsFailurePolicy::default(),
+            allow_embedded_dns: default_allow_embedded_dns(),
+            base_chains: BaseChains::default(),
+            rule_removal_grace_s: 0,
+            preserve_foreign_rules: false,
+            table_family: TableFamily::default(),
+        }
+    }
+}
+
+/// Families of the tables the filter rules are created in, see
+/// [`Defaults.table_family`](struct.Defaults.html#structfield.table_family).
+///
+/// The NAT rules are always created in the `ip` and `ip6` tables.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum TableFamily {
+    /// The filter rules of both address families are created in a single `inet` table.
+    #[default]
+    Inet,
+    /// The filter rules are created in separate `ip` and `ip6` tables, e.g. for tooling not
+    /// supporting `inet` tables. Rules matching on addresses of one family are only created in the
+    /// table of that family, all other rules in both tables.
+    ///
+    /// The `inet` table is deleted, the chain running before conntrack is named `raw_prerouting`
+    /// to not collide with the NAT chain `prerouting`.
+    Split,
+}
+
+/// Overrides of the base chains DFW creates, per hook.
+///
+/// The base chains of the `input` and `forward` hooks are the filter chains of the `inet` table,
+/// the base chains of the `prerouting` and `postrouting` hooks are the NAT chains of the `ip` and
+/// `ip6` tables. The prerouting chain running before conntrack, which DFW creates for conntrack
+/// zones, cannot be overridden.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BaseChains {
+    /// Override of the base chain of the input hook.
+    pub input: Option<BaseChain>,
+    /// Override of the base chain of the forward hook.
+    ///
+    /// The policy of this chain is set through the default policy of the
+    /// [`container_to_container`](struct.ContainerToContainer.html#structfield.default_policy)
+    /// section if the section is given, it cannot be overridden then.
+    pub forward: Option<BaseChain>,
+    /// Override of the base chains of the prerouting hook.
+    pub prerouting: Option<BaseChain>,
+    /// Override of the base chains of the postrouting hook.
+    pub postrouting: Option<BaseChain>,
+}
+
+/// Priority and policy of a base chain, see [`BaseChains`](struct.BaseChains.html).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct BaseChain {
+    /// Priority of the chain, relative to the chains of other tables on the same hook.
+    ///
+    /// Defaults to 5 less than the priority of the corresponding iptables table, i.e. `-5` for
+    /// `input` and `forward`, `-105` for `prerouting` and `95` for `postrouting`.
+    pub priority: Option<i16>,
+    /// Policy of the chain.
+    ///
+    /// Defaults to `accept`.
+    pub policy: Option<ChainPolicy>,
+}
+
+/// Source NAT applied to traffic leaving the host through the external network interfaces.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum EgressNat {
+    /// Translate the source address to the address of the outgoing interface, which is looked up
+    /// for every packet.
+    ///
+    /// IPv6 traffic is only masqueraded if
+    /// [`Defaults.ipv6_masquerade`](struct.Defaults.html#structfield.ipv6_masquerade) is set.
+    #[default]
+    Masquerade,
+    /// Translate the source address to the given, static address.
+    ///
+    /// Only traffic of the address family of the given address is translated that way, traffic
+    /// of the other family is masqueraded like with `Masquerade`.
+    Snat(IpAddr),
+}
+
+/// Inclusive range of ports.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub struct PortRange {
+    /// First port of the range.
+    pub start: u16,
+
+    /// Last port of the range.
+    pub end: u16,
+}
+
+impl PortRange {
+    /// Get the number of ports within the range.
+    pub fn size(&self) -> u32 {
+        u32::from(self.end - self.start) + 1
+    }
+}
+
+impl FromStr for PortRange {
+    type Err = String;
+
+    /// Convert a formatted string into a [`PortRange`](struct.PortRange.html).
+    ///
+    /// The string has to be in the format `<START>-<END>`, i.e. `30000-32767`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let split: Vec<&str> = s.split('-').collect();
+        let (start, end) = match split.as_slice() {
+            [start, end] => (
+                start.trim().parse::<u16>().map_err(|e| format!("{}", e))?,
+                end.trim().parse::<u16>().map_err(|e| format!("{}", e))?,
+            ),
+            _ => return Err(format!("port range has invalid format '{}'", s)),
+        };
+        if start == 0 || start > end {
+            return Err(format!("port range '{}' is empty or starts at port 0", s));
+        }
+
+        Ok(PortRange { start, end })
+    }
+}
+
+impl TryFrom<String> for PortRange {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for PortRange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.start, self.end)
+    }
+}
+
+impl Serialize for PortRange {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// Reference to an nftables table, specifically to the input- and forward-chains within it.
+///
+/// This is used by DFW when managing other tables is required.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Table {
+    /// Name of the custom table.
+    pub name: String,
+
+    /// Names of the input and forward chains defined within the custom table.
+    pub chains: Vec<String>,
+}
+
+/// The initialization section allows you to execute any commands against nftables.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Initialization {
+    /// This defines whether the section is processed. The rules of a disabled section are not
+    /// executed, they are still validated though.
+    ///
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+
+    /// Initialization rules for nftables
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// [initialization]
+    /// rules = [
+    ///     "add table inet custom",
+    ///     "flush table inet custom",
+    ///     # ...
+    /// ]
+    /// ```
+    pub rules: Option<Vec<String>>,
+}
+
+/// The runtime section configures the behaviour of the DFW process itself, independent of the
+/// rules it generates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Runtime {
+    /// Address to serve Prometheus metrics on.
+    ///
+    /// The metrics include the number, duration and errors of the reconciliation runs, the number
+    /// of rules per section and the packet- and byte-counters of every rule managed by DFW. To
+    /// collect the latter, a `counter` statement is added to all rules generated by DFW while this
+    /// is set, and the comment of every rule carries its [`RuleId`](../process/struct.RuleId.html),
+    /// which the counters are labeled with. The rule statistics are read from the ruleset whenever
+    /// the metrics are scraped.
+    ///
+    /// The endpoint is only available if DFW was built with the `metrics` feature, it is ignored
+    /// (with a warning) otherwise.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// [runtime]
+    /// metrics_address = "127.0.0.1:9198"
+    /// ```
+    #[serde(default)]
+    pub metrics_address: Option<SocketAddr>,
+}
+
+/// The container-to-container section, defining how containers can communicate amongst each other.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerToContainer {
+    /// This defines whether the section is processed. A disabled section generates no rules at
+    /// all, its default policy is not enforced either.
+    ///
+    /// Defaults to `true`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// [container_to_container]
+    /// enabled = false
+    /// default_policy = "drop"
+    /// ```
+    pub enabled: Option<bool>,
+
+    /// The `default_policy` defines the default for when there is not a specific rule.
+    ///
+    /// # Filtering traffic within the same bridge
+    ///
+    /// Depending on how your host is configured, traffic whose origin and destination interface are
+    /// the same bridge is _not_ filtered by the kernel netfilter module. This means that this
+    /// default policy is not honored for traffic between containers that are on the same Docker
+    /// network, but only for traffic that traverses two bridges.
+    ///
+    /// If your kernel has the `br_netfilter` kernel-module available, you can set the sysctl
+    /// `net.bridge.bridge-nf-call-iptables` to `1` to have the netfilter-module act on traffic
+    /// within the same bridge, too. You can set this value temporarily like this:
+    ///
+    /// ```norun
+    /// sysctl net.bridge.bridge-nf-call-iptables=1
+    /// ```
+    ///
+    /// To permanently set this configuration, take a look at `man sysctl.d` and `man sysctl.conf`.
+    pub default_policy: ChainPolicy,
+    /// An optional list of rules, see
+    /// [`ContainerToContainerRule`](struct.ContainerToContainerRule.html).
+    ///
+    /// # Example
+    ///
+    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
+    ///
+    /// ```toml
+    /// [[container_to_container.rules]]
+    /// # first rule here
+    /// [[container_to_container.rules]]
+    /// # second rule here
+    /// ```
+    ///
+    /// [toml-aot]:
+    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
+    pub rules: Option<Vec<ContainerToContainerRule>>,
+}
+
+/// Verdict of a rule, which can differ between new and established connections.
+///
+/// This allows, for example, to gradually close a previously open port: new connections are
+/// rejected, while established connections are still accepted. A rule with differing verdicts
+/// generates two rules, guarded by the conntrack state `new` and the conntrack states
+/// `established` and `related` respectively.
+///
+/// # Example
+///
+/// ```toml
+/// verdict = "accept"
+/// verdict = { new = "reject", established = "accept" }
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct StatefulVerdict {
+    /// Verdict for new connections.
+    pub new: RuleVerdict,
+
+    /// Verdict for established and related connections.
+    pub established: RuleVerdict,
+}
+
+impl StatefulVerdict {
+    /// Check if the verdict depends on the conntrack state of the connection.
+    pub fn is_stateful(&self) -> bool {
+        self.new != self.established
+    }
+
+    /// Check if the verdict decides on all connections, i.e. if neither of the verdicts is the
+    /// [`Log`](../nftables/enum.RuleVerdict.html#variant.Log) pseudo-verdict.
+    pub fn is_terminal(&self) -> bool {
+        self.new.is_terminal() && self.established.is_terminal()
+    }
+}
+
+impl From<RuleVerdict> for StatefulVerdict {
+    fn from(verdict: RuleVerdict) -> StatefulVerdict {
+        StatefulVerdict {
+            new: verdict,
+            established: verdict,
+        }
+    }
+}
+
+impl FromStr for StatefulVerdict {
+    type Err = String;
+
+    /// Convert a single verdict into a [`StatefulVerdict`](struct.StatefulVerdict.html) applying
+    /// to all connections.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<RuleVerdict>()
+            .map(StatefulVerdict::from)
+            .map_err(|_| format!("invalid verdict '{}'", s))
+    }
+}
+
+impl Serialize for StatefulVerdict {
+    /// Serialize the verdict as a single verdict if it applies to all connections, and in its
+    /// struct-form otherwise.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if !self.is_stateful() {
+            return self.new.serialize(serializer);
+        }
+
+        let mut state = serializer.serialize_struct("StatefulVerdict", 2)?;
+        state.serialize_field("new", &self.new)?;
+        state.serialize_field("established", &self.established)?;
+        state.end()
+    }
+}
+
+/// Definition for a rule to be used in the container-to-container section.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerToContainerRule {
+    /// Common network between the source container and the destination container to apply the rule
+    /// to.
+    ///
+    /// The [wildcard `*`](constant.WILDCARD_NETWORK.html) applies the rule to every network the
+    /// source and destination containers are both attached to, generating one rule per network. It
+    /// requires at least one of the containers to be given.
+    ///
+    /// If `dst_network` is given, this is the network of the source container only. It can then
+    /// also be specified as `src_network`.
+    #[serde(alias = "src_network")]
+    pub network: String,
+    /// Network of the destination container, if it differs from the network of the source
+    /// container.
+    ///
+    /// The containers are then bridged by translating the traffic from the source network to the
+    /// ports of the destination container given in `expose_port`, exactly like a rule of the
+    /// [`container_dnat`](struct.ContainerDNAT.html) section does. This requires the
+    /// `dst_container` and the exposed ports to be given and the verdict to be `accept`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// [[container_to_container.rules]]
+    /// src_network = "frontend"
+    /// src_container = "nginx"
+    /// dst_network = "backend"
+    /// dst_container = "api"
+    /// expose_port = 8080
+    /// verdict = "accept"
+    /// ```
+    pub dst_network: Option<String>,
+    /// Source container to apply the rule to, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    pub src_container: Option<ContainerSelector>,
+    /// Destination container to apply the rule to, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    pub dst_container: Option<ContainerSelector>,
+    /// Labels selecting the source containers to apply the rule to, instead of a single
+    /// `src_container`. A container is selected if it carries all of the labels with the given
+    /// values.
+    ///
+    /// The rule is applied to every pair of selected source and destination containers, but never
+    /// from a container to itself.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// [[container_to_container.rules]]
+    /// network = "mesh"
+    /// src_tags = { tier = "web", env = "prod" }
+    /// dst_tags = { tier = "db", env = "prod" }
+    /// verdict = "accept"
+    /// ```
+    pub src_tags: Option<BTreeMap<String, String>>,
+    /// Labels selecting the destination containers to apply the rule to, instead of a single
+    /// `dst_container`, see `src_tags`.
+    pub dst_tags: Option<BTreeMap<String, String>>,
+    /// Ports of the destination container to bridge to, if the destination container is attached
+    /// to a different network, see `dst_network`. Defined like the ports of the
+    /// [`container_dnat`](struct.ContainerDNATRule.html#structfield.expose_port) section.
+    #[serde(
+        default,
+        deserialize_with = "expose_ports",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub expose_port: Vec<ExposePort>,
+    /// Additional match-string, which will be added to the nftables command.
+    pub matches: Option<String>,
+    /// Minimum number of bytes the connection has to have transferred in both directions for the
+    /// rule to apply, e.g. to treat bulk transfers differently. The size is a number followed by
+    /// one of the units `bytes`, `kbytes`, `mbytes` or `gbytes`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// min_ct_bytes = "100 mbytes"
+    /// ```
+    pub min_ct_bytes: Option<String>,
+    /// Verdict for rule (accept, drop or reject), optionally depending on the conntrack state of
+    /// the connection, see [`StatefulVerdict`](struct.StatefulVerdict.html).
+    ///
+    /// The pseudo-verdict `log` logs the matched packets like [`log`](#structfield.log), without
+    /// deciding on them: the packets continue to be evaluated by the subsequent rules. It cannot
+    /// depend on the conntrack state of the connection.
+    #[serde(alias = "action", deserialize_with = "string_or_struct")]
+    pub verdict: StatefulVerdict,
+    /// Address to mirror the matched packets to, e.g. for an intrusion detection system. The
+    /// packets are duplicated through the primary external network interface.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// mirror_to = "10.0.0.250"
+    /// ```
+    pub mirror_to: Option<IpAddr>,
+    /// Log the packets matched by this rule, with the prefix `dfw:container_to_container`. The rate
+    /// packets are logged at is limited by
+    /// [`Defaults.log_rate`](struct.Defaults.html#structfield.log_rate).
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub log: bool,
+    /// DSCP value to set on the matched packets, see [`Dscp`](struct.Dscp.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// dscp = "af21"
+    /// ```
+    pub dscp: Option<Dscp>,
+    /// Address families to generate the rule for, e.g. `["v4"]`. A rule matching on the addresses
+    /// of containers only applies to IPv4 traffic, restricting it to IPv6 thus skips the rule.
+    ///
+    /// Defaults to both families.
+    pub families: Option<Vec<AddressFamily>>,
+    /// Action to take if a container referenced by the rule is missing, i.e. does not exist or is
+    /// not attached to the network of the rule, see [`OnMissing`](enum.OnMissing.html).
+    ///
+    /// Defaults to `skip`.
+    pub on_missing: Option<OnMissing>,
+    /// Condition which has to hold on the host for this rule to be applied, see
+    /// [`Condition`](struct.Condition.html).
+    pub when: Option<Condition>,
+
+    /// Location in the configuration this rule was defined at, see
+    /// [`Provenance`](struct.Provenance.html).
+    ///
+    /// This is recorded while loading the configuration and cannot be set in the configuration
+    /// itself.
+    #[serde(skip)]
+    pub provenance: Option<Provenance>,
+}
+
+/// The container-to-wider-world section, defining how containers can communicate with the wider
+/// world.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerToWiderWorld {
+    /// This defines whether the section is processed. A disabled section generates no rules at
+    /// all, its default policy is not enforced either.
+    ///
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+
+    /// The `default_policy` defines the default for when there is not a specific rule.
+    ///
+    /// The policy can differ between IPv4 and IPv6 traffic, see
+    /// [`FamilyVerdict`](struct.FamilyVerdict.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// default_policy = "accept"
+    /// default_policy = { v4 = "accept", v6 = "drop" }
+    /// ```
+    #[serde(deserialize_with = "string_or_struct")]
+    pub default_policy: FamilyVerdict,
+    /// An optional list of rules, see
+    /// [`ContainerToWiderWorldRule`](struct.ContainerToWiderWorldRule.html).
+    ///
+    /// # Example
+    ///
+    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
+    ///
+    /// ```toml
+    /// [[container_to_wider_world.rules]]
+    /// # first rule here
+    /// [[container_to_wider_world.rules]]
+    /// # second rule here
+    /// ```
+    ///
+    /// [toml-aot]:
+    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
+    pub rules: Option<Vec<ContainerToWiderWorldRule>>,
+}
+
+/// Verdict which can differ between IPv4 and IPv6 traffic.
+///
+/// A single verdict applies to both families.
+///
+/// # Example
+///
+/// ```toml
+/// default_policy = "accept"
+/// default_policy = { v4 = "accept", v6 = "drop" }
+/// ```
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct FamilyVerdict {
+    /// Verdict for IPv4 traffic.
+    pub v4: RuleVerdict,
+
+    /// Verdict for IPv6 traffic.
+    pub v6: RuleVerdict,
+}
+
+impl FamilyVerdict {
+    /// Check if the verdict depends on the family of the traffic.
+    pub fn is_split(&self) -> bool {
+        self.v4 != self.v6
+    }
+}
+
+impl From<RuleVerdict> for FamilyVerdict {
+    fn from(verdict: RuleVerdict) -> FamilyVerdict {
+        FamilyVerdict {
+            v4: verdict,
+            v6: verdict,
+        }
+    }
+}
+
+impl FromStr for FamilyVerdict {
+    type Err = String;
+
+    /// Convert a single verdict into a [`FamilyVerdict`](struct.FamilyVerdict.html) applying to
+    /// both families.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse::<RuleVerdict>()
+            .map(FamilyVerdict::from)
+            .map_err(|_| format!("invalid verdict '{}'", s))
+    }
+}
+
+impl fmt::Display for FamilyVerdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_split() {
+            write!(f, "v4={}, v6={}", self.v4, self.v6)
+        } else {
+            write!(f, "{}", self.v4)
+        }
+    }
+}
+
+impl Serialize for FamilyVerdict {
+    /// Serialize the verdict as a single verdict if it applies to both families, and in its
+    /// struct-form otherwise.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if !self.is_split() {
+            return self.v4.serialize(serializer);
+        }
+
+        let mut state = serializer.serialize_struct("FamilyVerdict", 2)?;
+        state.serialize_field("v4", &self.v4)?;
+        state.serialize_field("v6", &self.v6)?;
+        state.end()
+    }
+}
+
+/// External network interfaces which can differ between IPv4 and IPv6 traffic, see
+/// [`Defaults.external_network_interfaces`](struct.Defaults.html#structfield.external_network_interfaces).
+///
+/// A single interface or a sequence of interfaces applies to both families.
+///
+/// # Example
+///
+/// ```toml
+/// external_network_interfaces = ["eth0", "eth1"]
+/// external_network_interfaces = { v4 = ["eth0"], v6 = ["eth1"] }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct FamilyInterfaces {
+    /// Interfaces carrying IPv4 traffic, the first one being the primary one.
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    pub v4: Vec<String>,
+
+    /// Interfaces carrying IPv6 traffic, the first one being the primary one.
+    #[serde(default, deserialize_with = "string_or_seq_string")]
+    pub v6: Vec<String>,
+}
+
+impl FamilyInterfaces {
+    /// Check if the interfaces differ between the families.
+    pub fn is_split(&self) -> bool {
+        self.v4 != self.v6
+    }
+
+    /// Get the interfaces carrying traffic of the given family.
+    pub fn of(&self, family: AddressFamily) -> &[String] {
+        match family {
+            AddressFamily::V4 => &self.v4,
+            AddressFamily::V6 => &self.v6,
+        }
+    }
+
+    /// Get the primary interface of the given family.
+    pub fn primary(&self, family: AddressFamily) -> Option<&String> {
+        self.of(family).first()
+    }
+
+    /// Get the interfaces of both families, the IPv4 interfaces first, without duplicates.
+    pub fn all(&self) -> Vec<String> {
+        let mut interfaces = self.v4.clone();
+        for interface in &self.v6 {
+            if !interfaces.contains(interface) {
+                interfaces.push(interface.to_owned());
+            }
+        }
+
+        interfaces
+    }
+
+    /// Get the interfaces of both families like [`all`](#method.all), along with the family
+    /// each interface is restricted to, or `None` if it carries the traffic of both families.
+    pub fn with_families(&self) -> Vec<(String, Option<AddressFamily>)> {
+        self.all()
+            .into_iter()
+            .map(|interface| {
+                let family = match (self.v4.contains(&interface), self.v6.contains(&interface)) {
+                    (true, false) => Some(AddressFamily::V4),
+                    (false, true) => Some(AddressFamily::V6),
+                    _ => None,
+                };
+                (interface, family)
+            })
+            .collect()
+    }
+}
+
+impl From<Vec<String>> for FamilyInterfaces {
+    fn from(interfaces: Vec<String>) -> FamilyInterfaces {
+        FamilyInterfaces {
+            v4: interfaces.clone(),
+            v6: interfaces,
+        }
+    }
+}
+
+impl Serialize for FamilyInterfaces {
+    /// Serialize the interfaces as a single sequence if they apply to both families, and in their
+    /// struct-form otherwise.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if !self.is_split() {
+            return self.v4.serialize(serializer);
+        }
+
+        let mut state = serializer.serialize_struct("FamilyInterfaces", 2)?;
+        state.serialize_field("v4", &self.v4)?;
+        state.serialize_field("v6", &self.v6)?;
+        state.end()
+    }
+}
+
+/// Address family a rule can be restricted to, see e.g.
+/// [`WiderWorldToContainerRule.families`](struct.WiderWorldToContainerRule.html#structfield.families).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressFamily {
+    /// IPv4
+    V4,
+    /// IPv6
+    V6,
+}
+
+impl AddressFamily {
+    /// Get the family of an address.
+    pub fn of(address: IpAddr) -> AddressFamily {
+        match address {
+            IpAddr::V4(_) => AddressFamily::V4,
+            IpAddr::V6(_) => AddressFamily::V6,
+        }
+    }
+
+    /// Get the value of the `meta nfproto` match for the family.
+    pub fn nfproto(self) -> &'static str {
+        match self {
+            AddressFamily::V4 => "ipv4",
+            AddressFamily::V6 => "ipv6",
+        }
+    }
+}
+
+/// External network interfaces a rule applies to, as determined from its
+/// `external_network_interface` by [`of`](#method.of).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ExternalNetworkInterfaces<'a> {
+    /// The interface is not set, the rule inherits the primary external network interface from
+    /// [`Defaults.external_network_interfaces`](struct.Defaults.html#structfield.external_network_interfaces).
+    Inherit,
+    /// The rule applies to the given interfaces.
+    Explicit(&'a [String]),
+    /// The interface is set to `"none"`, the rule does not match on an interface at all.
+    All,
+}
+
+impl<'a> ExternalNetworkInterfaces<'a> {
+    /// Determine the external network interfaces from the `external_network_interface` of a rule.
+    pub fn of(
+        external_network_interface: &'a Option<Vec<String>>,
+    ) -> ExternalNetworkInterfaces<'a> {
+        match external_network_interface {
+            None => ExternalNetworkInterfaces::Inherit,
+            Some(interfaces) if interfaces[..] == [NO_EXTERNAL_NETWORK_INTERFACE] => {
+                ExternalNetworkInterfaces::All
+            }
+            Some(interfaces) => ExternalNetworkInterfaces::Explicit(interfaces),
+        }
+    }
+}
+
+/// Action to take if a container referenced by a rule is missing, see e.g.
+/// [`WiderWorldToContainerRule.on_missing`](struct.WiderWorldToContainerRule.html#structfield.on_missing).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum OnMissing {
+    /// Fail the processing, no rules are applied. This marks rules that are load-bearing.
+    Error,
+    /// Skip the rule, the remaining rules are applied.
+    #[default]
+    Skip,
+}
+
+/// Handling of a hostname that cannot be resolved, see
+/// [`Defaults.dns_failure_policy`](struct.Defaults.html#structfield.dns_failure_policy).
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DnsFailurePolicy {
+    /// Keep the addresses the hostname resolved to before, as found in the currently applied
+    /// rules. If there are none, no address of the hostname is allowed.
+    #[default]
+    Keep,
+    /// Fail the processing, no rules are applied.
+    Error,
+}
+
+/// Definition for a rule to be used in the container-to-wider-world section.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerToWiderWorldRule {
+    /// Network of the source container to apply the rule to.
+    pub network: Option<String>,
+    /// Source container to apply the rule to, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    pub src_container: Option<ContainerSelector>,
+    /// Additional match-string, which will be added to the nftables command.
+    pub matches: Option<String>,
+    /// Minimum number of bytes the connection has to have transferred in both directions for the
+    /// rule to apply, e.g. to treat bulk transfers differently. The size is a number followed by
+    /// one of the units `bytes`, `kbytes`, `mbytes` or `gbytes`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// min_ct_bytes = "100 mbytes"
+    /// ```
+    pub min_ct_bytes: Option<String>,
+    /// Verdict for rule (accept, drop or reject), optionally depending on the conntrack state of
+    /// the connection, see [`StatefulVerdict`](struct.StatefulVerdict.html).
+    ///
+    /// The pseudo-verdict `log` logs the matched packets like [`log`](#structfield.log), without
+    /// deciding on them: the packets continue to be evaluated by the subsequent rules. It cannot
+    /// depend on the conntrack state of the connection.
+    #[serde(alias = "action", deserialize_with = "string_or_struct")]
+    pub verdict: StatefulVerdict,
+    /// Specific external network interfaces to target. The value can be non-existant, a string,
+    /// or a sequence of strings.
+    ///
+    /// If the value is non-existant, the rule inherits the primary external network interface
+    /// from [`Defaults.external_network_interfaces`][external_network_interfaces]. If the value is
+    /// `"none"`, the rule does not match on an interface at all and thus applies to traffic on
+    /// all interfaces.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// external_network_interface = "eth0"
+    /// external_network_interface = ["eth0", "eth1"]
+    /// external_network_interface = "none"
+    /// ```
+    ///
+    /// [external_network_interfaces]: struct.Defaults.html#structfield.external_network_interfaces
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    pub external_network_interface: Option<Vec<String>>,
+    /// Address to mirror the matched packets to, e.g. for an intrusion detection system. The
+    /// packets are duplicated through the external network interface of the rule, or the primary
+    /// external network interface if none is given.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// mirror_to = "10.0.0.250"
+    /// ```
+    pub mirror_to: Option<IpAddr>,
+    /// Log the packets matched by this rule, with the prefix `dfw:container_to_wider_world`. The
+    /// rate packets are logged at is limited by
+    /// [`Defaults.log_rate`](struct.Defaults.html#structfield.log_rate).
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub log: bool,
+    /// VLAN the traffic has to be tagged with, see [`VlanId`](struct.VlanId.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// vlan_id = 100
+    /// ```
+    pub vlan_id: Option<VlanId>,
+    /// Address families to generate the rule for, e.g. `["v4"]`. A rule matching on the addresses
+    /// of containers only applies to IPv4 traffic, restricting it to IPv6 thus skips the rule.
+    ///
+    /// Defaults to both families.
+    pub families: Option<Vec<AddressFamily>>,
+    /// Action to take if a container referenced by the rule is missing, i.e. does not exist or is
+    /// not attached to the network of the rule, see [`OnMissing`](enum.OnMissing.html).
+    ///
+    /// Defaults to `skip`.
+    pub on_missing: Option<OnMissing>,
+    /// Condition which has to hold on the host for this rule to be applied, see
+    /// [`Condition`](struct.Condition.html).
+    pub when: Option<Condition>,
+
+    /// Location in the configuration this rule was defined at, see
+    /// [`Provenance`](struct.Provenance.html).
+    ///
+    /// This is recorded while loading the configuration and cannot be set in the configuration
+    /// itself.
+    #[serde(skip)]
+    pub provenance: Option<Provenance>,
+}
+
+/// The DNS section, allowing containers to reach their DNS servers.
+///
+/// DNS traffic is allowed on port 53 over UDP and TCP, and optionally on port 853 over TCP for DNS
+/// over TLS. The rules are generated before the rules of the container-to-wider-world section, the
+/// traffic is thus allowed independent of its default policy.
+///
+/// # Example
+///
+/// ```toml
+/// [dns]
+/// servers = ["192.0.2.53", "2001:db8::53"]
+/// dns_over_tls = true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Dns {
+    /// This defines whether the section is processed. A disabled section allows no DNS traffic.
+    ///
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+
+    /// Addresses of the DNS servers the containers may reach.
+    ///
+    /// Can be left blank, the containers may then reach any DNS server.
+    pub servers: Option<Vec<IpAddr>>,
+
+    /// Network of the source container to allow DNS traffic from.
+    ///
+    /// Can be left blank, DNS traffic is then allowed from all networks.
+    pub network: Option<String>,
+
+    /// Source container to allow DNS traffic from, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html). This requires `network` to be set.
+    ///
+    /// Can be left blank, DNS traffic is then allowed from all containers on the network.
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    pub src_container: Option<ContainerSelector>,
+
+    /// This defines whether DNS over TLS, i.e. port 853 over TCP, is allowed as well.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub dns_over_tls: bool,
+}
+
+/// The container-to-host section, defining how containers can communicate with the host.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerToHost {
+    /// This defines whether the section is processed. A disabled section generates no rules at
+    /// all, its default policy is not enforced either.
+    ///
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+
+    /// The `default_policy` defines the default for when there is not a specific rule.
+    pub default_policy: RuleVerdict,
+    /// An optional list of rules, see
+    /// [`ContainerToHostRule`](struct.ContainerToHostRule.html).
+    ///
+    /// # Example
+    ///
+    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
+    ///
+    /// ```toml
+    /// [[container_to_host.rules]]
+    /// # first rule here
+    /// [[container_to_host.rules]]
+    /// # second rule here
+    /// ```
+    ///
+    /// [toml-aot]:
+    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
+    pub rules: Option<Vec<ContainerToHostRule>>,
+}
+
+/// Definition for a rule to be used in the container-to-host section.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct ContainerToHostRule {
+    /// Network of the source container to apply the rule to.
+    ///
+    /// The [wildcard `*`](constant.WILDCARD_NETWORK.html) applies the rule to every network the
+    /// source container is attached to, generating one rule per network. It requires the source
+    /// container to be given.
+    pub network: String,
+    /// Source container to apply the rule to, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    pub src_container: Option<ContainerSelector>,
+    /// Additional match-string, which will be added to the nftables command.
+    pub matches: Option<String>,
+    /// Minimum number of bytes the connection has to have transferred in both directions for the
+    /// rule to apply, e.g. to treat bulk transfers differently. The size is a number followed by
+    /// one of the units `bytes`, `kbytes`, `mbytes` or `gbytes`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// min_ct_bytes = "100 mbytes"
+    /// ```
+    pub min_ct_bytes: Option<String>,
+    /// Verdict for rule (accept, drop or reject), optionally depending on the conntrack state of
+    /// the connection, see [`StatefulVerdict`](struct.StatefulVerdict.html).
+    ///
+    /// The pseudo-verdict `log` logs the matched packets like [`log`](#structfield.log), without
+    /// deciding on them: the packets continue to be evaluated by the subsequent rules. It cannot
+    /// depend on the conntrack state of the connection.
+    #[serde(alias = "action", deserialize_with = "string_or_struct")]
+    pub verdict: StatefulVerdict,
+    /// Address to mirror the matched packets to, e.g. for an intrusion detection system. The
+    /// packets are duplicated through the primary external network interface.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// mirror_to = "10.0.0.250"
+    /// ```
+    pub mirror_to: Option<IpAddr>,
+    /// Log the packets matched by this rule, with the prefix `dfw:container_to_host`. The rate
+    /// packets are logged at is limited by
+    /// [`Defaults.log_rate`](struct.Defaults.html#structfield.log_rate).
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub log: bool,
+    /// Address families to generate the rule for, e.g. `["v4"]`. A rule matching on the addresses
+    /// of containers only applies to IPv4 traffic, restricting it to IPv6 thus skips the rule.
+    ///
+    /// Defaults to both families.
+    pub families: Option<Vec<AddressFamily>>,
+    /// Action to take if a container referenced by the rule is missing, i.e. does not exist or is
+    /// not attached to the network of the rule, see [`OnMissing`](enum.OnMissing.html).
+    ///
+    /// Defaults to `skip`.
+    pub on_missing: Option<OnMissing>,
+    /// Condition which has to hold on the host for this rule to be applied, see
+    /// [`Condition`](struct.Condition.html).
+    pub when: Option<Condition>,
+
+    /// Location in the configuration this rule was defined at, see
+    /// [`Provenance`](struct.Provenance.html).
+    ///
+    /// This is recorded while loading the configuration and cannot be set in the configuration
+    /// itself.
+    #[serde(skip)]
+    pub provenance: Option<Provenance>,
+}
+
+/// The wider-world-to-container section, defining how containers can reached from the wider world.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct WiderWorldToContainer {
+    /// This defines whether the section is processed. A disabled section exposes no ports and
+    /// does not log unmatched traffic.
+    ///
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+
+    /// An optional list of rules, see
+    /// [`WiderWorldToContainerRule`](struct.WiderWorldToContainerRule.html).
+    ///
+    /// # Example
+    ///
+    /// The easiest way to define the rules is using TOMLs [arrays of tables][toml-aot]:
+    ///
+    /// ```toml
+    /// [[wider_world_to_container.rules]]
+    /// # first rule here
+    /// [[wider_world_to_container.rules]]
+    /// # second rule here
+    /// ```
+    ///
+    /// [toml-aot]:
+    ///  https://github.com/toml-lang/toml/blob/master/versions/en/toml-v0.4.0.md#array-of-tables
+    pub rules: Option<Vec<WiderWorldToContainerRule>>,
+
+    /// Log the traffic from the external network interfaces to the containers that is not matched
+    /// by any of the rules, before the default policy of the forward chain applies to it, see
+    /// [`LogUnmatched`](struct.LogUnmatched.html). The rate packets are logged at is limited by
+    /// [`Defaults.log_rate`](struct.Defaults.html#structfield.log_rate).
+    ///
+    /// The value can be a boolean or a table.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// log_unmatched = true
+    /// log_unmatched = { prefix = "scan: ", level = "warn" }
+    /// ```
+    #[serde(
+        default,
+        deserialize_with = "option_bool_or_struct",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub log_unmatched: Option<LogUnmatched>,
+}
+
+/// Logging of the wider-world traffic that is not matched by any rule, see
+/// [`WiderWorldToContainer.log_unmatched`](struct.WiderWorldToContainer.html#structfield.log_unmatched).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct LogUnmatched {
+    /// Prefix of the log messages.
+    ///
+    /// Defaults to `wwtc-nomatch: `.
+    #[serde(default = "default_log_unmatched_prefix")]
+    pub prefix: String,
+
+    /// Level of the log messages, see [`LogLevel`](../nftables/enum.LogLevel.html).
+    ///
+    /// Defaults to the default level of nftables, i.e. `warn`.
+    pub level: Option<LogLevel>,
+}
+
+impl Default for LogUnmatched {
+    fn default() -> LogUnmatched {
+        LogUnmatched {
+            prefix: default_log_unmatched_prefix(),
+            level: None,
+        }
+    }
+}
+
+/// Definition for a rule to be used in the wider-world-to-container section.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct WiderWorldToContainerRule {
+    /// Network of the destination container to apply the rule to.
+    ///
+    /// For a container attached to the [host network](constant.HOST_NETWORK.html), the ports are
+    /// accepted on the host itself instead of being translated to the container. Options that
+    /// rely on translating or forwarding the traffic, e.g. `dnat_to` or `quota`, cannot be used
+    /// in that case.
+    pub network: String,
+
+    /// Destination container to apply the rule to, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(deserialize_with = "string_or_struct")]
+    pub dst_container: ContainerSelector,
+
+    /// Ports to apply the rule to.
+    ///
+    /// Defined as:
+    ///
+    /// * a single integer
+    ///
+    /// * a single string
+    ///
+    /// * a single struct
+    ///
+    /// * a list of integers
+    ///
+    /// * a list of strings
+    ///
+    /// * a list of structs
+    ///
+    /// # Example
+    ///
+    /// All of the following are legal TOML fragments:
+    ///
+    /// ```toml
+    /// expose_port = 80
+    /// expose_port = [80, 443]
+    /// expose_port = "53/udp"
+    /// expose_port = ["80/tcp", "53/udp"]
+    ///
+    /// # The following four all result in the same definition
+    /// expose_port = { host_port = 8080 }
+    /// expose_port = { host_port = 8080, container_port = 8080 }
+    /// expose_port = { host_port = 8080, family = "tcp" }
+    /// expose_port = { host_port = 8080, container_port = 8080, family = "tcp" }
+    ///
+    /// expose_port = [
+    ///     { host_port = 80 },
+    ///     { host_port = 53, family = "udp" },
+    ///     { host_port = 443, container_port = 8443 },
+    /// ]
+    ///
+    /// # The struct-form allows the family to be a list, generating one definition per family
+    /// expose_port = { host_port = 443, family = ["tcp", "udp"] }
+    ///
+    /// # The port can be restricted to a single address of the host
+    /// expose_port = { host_port = 443, host_ip = "192.0.2.1", family = ["tcp", "udp"] }
+    ///
+    /// # The host port can be assigned automatically from `defaults.auto_port_range`
+    /// expose_port = { host_port = "auto", container_port = 80 }
+    /// expose_port = "auto:80/tcp"
+    ///
+    /// # A range of host ports can be mapped onto a range of container ports of equal width
+    /// expose_port = { host_port_range = "8000-8010", container_port_range = "9000-9010" }
+    ///
+    /// # The port can be opened without translating the traffic to the container, e.g. for
+    /// # containers that are directly reachable
+    /// expose_port = { host_port = 8080, dnat = false }
+    ///
+    /// # The ports the container publishes through Docker can be exposed, keeping the protocol of
+    /// # each published port
+    /// expose_port = "published"
+    /// expose_port = { published = true }
+    ///
+    /// # The port the image of the container declares through `EXPOSE` can be exposed, the
+    /// # container port selects one of them if the image declares multiple ports
+    /// expose_port = "exposed"
+    /// expose_port = { exposed = true, container_port = 8080, host_port = 80 }
+    /// ```
+    #[serde(deserialize_with = "expose_ports")]
+    pub expose_port: Vec<ExposePort>,
+
+    /// Specific external network interfaces to target. The value can be non-existant, a string,
+    /// or a sequence of strings.
+    ///
+    /// If the value is non-existant, the rule inherits the primary external network interface
+    /// from [`Defaults.external_network_interfaces`][external_network_interfaces]. If the value is
+    /// `"none"`, the rule does not match on an interface at all and thus applies to traffic on
+    /// all interfaces.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// external_network_interface = "eth0"
+    /// external_network_interface = ["eth0", "eth1"]
+    /// external_network_interface = "none"
+    /// ```
+    ///
+    /// [external_network_interfaces]: struct.Defaults.html#structfield.external_network_interfaces
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    pub external_network_interface: Option<Vec<String>>,
+
+    /// Source CIDRs (IPv4) to which incoming traffic should be restricted.
+    ///
+    /// This can be:
     ///
     /// * a single string
     ///
@@ -404,6 +2031,19 @@ pub struct WiderWorldToContainerRule {
     ///
     /// There is no validation whether the provided CIDRs are actually valid.
     ///
+    /// Instead of a CIDR, a file containing CIDRs can be referenced as `@file:<path>`. The file
+    /// lists one CIDR per line, empty lines and lines starting with `#` are ignored. It is read
+    /// every time the rules are processed and its CIDRs are loaded into a named set, such that the
+    /// file can be updated independently of the configuration. Every line of the file has to be a
+    /// valid IPv4 CIDR.
+    ///
+    /// A hostname can be referenced as `@dns:<hostname>`, e.g. for partners whose addresses
+    /// change. The hostname is resolved every time the rules are processed and its IPv4 addresses
+    /// are loaded into a named set, such that the allowed addresses track the changes of the DNS
+    /// records. If the hostname cannot be resolved,
+    /// [`Defaults.dns_failure_policy`](struct.Defaults.html#structfield.dns_failure_policy)
+    /// applies.
+    ///
     /// # Example
     ///
     /// All of the following are legal TOML fragments:
@@ -411,7 +2051,11 @@ pub struct WiderWorldToContainerRule {
     /// ```toml
     /// source_cidr_v4 = "127.0.0.0/8"
     ///
-    /// source_cidr _v4= ["127.0.0.0/8", "192.0.2.1/32"]
+    /// source_cidr _v4= ["127.0.0.0/8", "192.0.2.1/32"]
+    ///
+    /// source_cidr_v4 = "@file:/etc/dfw/corporate-cidrs.txt"
+    ///
+    /// source_cidr_v4 = "@dns:partner.example.com"
     /// ```
     #[serde(
         default,
@@ -430,6 +2074,9 @@ pub struct WiderWorldToContainerRule {
     ///
     /// There is no validation whether the provided CIDRs are actually valid.
     ///
+    /// A hostname can be referenced as `@dns:<hostname>`, its IPv6 addresses are allowed, see
+    /// [`source_cidr_v4`](#structfield.source_cidr_v4).
+    ///
     /// # Example
     ///
     /// All of the following are legal TOML fragments:
@@ -438,6 +2085,8 @@ pub struct WiderWorldToContainerRule {
     /// source_cidr_v6 = "fe80::/10"
     ///
     /// source_cidr_v6 = ["fe80::/10", "2001:db8::/32"]
+    ///
+    /// source_cidr_v6 = "@dns:partner.example.com"
     /// ```
     #[serde(
         default,
@@ -445,6 +2094,527 @@ pub struct WiderWorldToContainerRule {
         alias = "source_cidr"
     )]
     pub source_cidr_v6: Option<Vec<String>>,
+
+    /// Address (and optionally port) to DNAT the traffic to, overriding the address of the
+    /// destination container, see [`DnatTarget`](struct.DnatTarget.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// dnat_to = "192.0.2.10"
+    /// dnat_to = "192.0.2.10:8443"
+    /// ```
+    pub dnat_to: Option<DnatTarget>,
+
+    /// Address of the destination container to DNAT the traffic to, pinning the target if the
+    /// container has multiple addresses on the network of the rule. The address has to be one of
+    /// the IPv4 addresses of the container on that network.
+    ///
+    /// Defaults to the primary address of the container on the network of the rule.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// dst_ip = "172.18.0.12"
+    /// ```
+    pub dst_ip: Option<IpAddr>,
+
+    /// Address of the host the exposed ports are restricted to, i.e. only traffic arriving at this
+    /// destination address is translated to the container. This applies to all exposed ports that
+    /// don't specify a `host_ip` of their own, and only the rules for the family of the address
+    /// are generated.
+    ///
+    /// Can be left blank, the ports are then exposed on all addresses of the host.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// host_ip = "192.0.2.1"
+    /// ```
+    pub host_ip: Option<IpAddr>,
+
+    /// DSCP value to set on the incoming packets, see [`Dscp`](struct.Dscp.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// dscp = "ef"
+    /// ```
+    pub dscp: Option<Dscp>,
+
+    /// This defines whether the rule only applies while the destination container is healthy.
+    ///
+    /// If set, the port is not exposed while the healthcheck of the container is starting or
+    /// failing, it is exposed again once the container is healthy and the rules are processed
+    /// again. Containers without a healthcheck are considered healthy.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub require_healthy: bool,
+
+    /// This defines whether the exposed port is draining.
+    ///
+    /// If set, only connections that are already established (or related to one) are forwarded to
+    /// the container, new connections are dropped. This allows a service to be drained gracefully
+    /// by changing the configuration, unsetting it exposes the port as usual again.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub drain: bool,
+
+    /// This defines whether the traffic to the exposed ports bypasses connection tracking.
+    ///
+    /// If set, the traffic to the container and its replies are marked as untracked before
+    /// conntrack sees them, which avoids the overhead of conntrack for services handling many
+    /// packets per second. Replies are accepted explicitly, since they don't belong to a tracked
+    /// connection.
+    ///
+    /// DNAT relies on connection tracking, the option can thus only be used for exposed ports with
+    /// `dnat = false` and neither with `dnat_to` nor with `drain`.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub notrack: bool,
+
+    /// Byte quota of the traffic of the exposed ports, see [`Quota`](struct.Quota.html).
+    ///
+    /// The traffic in both directions, i.e. to the container and its replies, is accounted to the
+    /// quota. The quota is reset whenever the rules are applied.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// quota = { bytes = "10 gbytes", over = true }
+    /// ```
+    pub quota: Option<Quota>,
+
+    /// Name of the conntrack timeout policy assigned to the connections to the exposed ports, see
+    /// [`Defaults.ct_timeouts`](struct.Defaults.html#structfield.ct_timeouts).
+    ///
+    /// The policy is assigned before conntrack sees the first packet of a connection, it thus only
+    /// applies to IPv4 traffic received on the external network interfaces. It cannot be combined
+    /// with `notrack`, and the protocol of the policy has to match the protocol of the exposed
+    /// ports.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// ct_timeout = "scanned"
+    /// ```
+    pub ct_timeout: Option<String>,
+
+    /// This defines whether the maximum segment size (MSS) of TCP connections to the exposed ports
+    /// is clamped to the MTU of the route the packets take, i.e. whether the MSS option of the SYN
+    /// packets in both directions is rewritten by `tcp option maxseg size set rt mtu`.
+    ///
+    /// This prevents connections from stalling on paths with a reduced MTU, e.g. tunnels or
+    /// overlay networks, on which ICMP messages required for path MTU discovery are lost. The MSS
+    /// is only clamped for TCP ports and IPv4 traffic forwarded to the container. It cannot be
+    /// combined with `interior_only`.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// clamp_mss_to_pmtu = true
+    /// ```
+    #[serde(default)]
+    pub clamp_mss_to_pmtu: bool,
+
+    /// This acknowledges that the exposed ports are reachable from everywhere, i.e. that the rule
+    /// restricts neither the source CIDRs nor the external network interfaces. Such rules are
+    /// reported by [`lint`](../validate/fn.lint.html) otherwise.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    pub allow_public: bool,
+
+    /// This defines whether the ports are only exposed to the containers given in
+    /// `from_containers` instead of the wider world.
+    ///
+    /// Neither DNAT rules nor rules accepting traffic from the external network interfaces are
+    /// generated then. The containers reach the destination container on its container ports
+    /// through the network of the rule instead. This requires `from_containers` to be given and
+    /// cannot be combined with `external_network_interface`, the source CIDRs, `dnat_to`,
+    /// `notrack`, `quota`, `ct_timeout` or `clamp_mss_to_pmtu`.
+    ///
+    /// Defaults to `false`.
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// [[wider_world_to_container.rules]]
+    /// network = "backend"
+    /// dst_container = "db"
+    /// expose_port = 5432
+    /// interior_only = true
+    /// from_containers = ["api", "worker"]
+    /// ```
+    #[serde(default)]
+    pub interior_only: bool,
+
+    /// Containers the ports are exposed to if the rule is `interior_only`, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(
+        default,
+        deserialize_with = "single_or_seq_string_or_struct",
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub from_containers: Vec<ContainerSelector>,
+
+    /// VLAN the incoming traffic has to be tagged with, see [`VlanId`](struct.VlanId.html).
+    ///
+    /// # Example
+    ///
+    /// ```toml
+    /// vlan_id = 100
+    /// ```
+    pub vlan_id: Option<VlanId>,
+
+    /// Address families to expose the ports on, e.g. `["v4"]` to only expose them on the IPv4
+    /// addresses of the host, independent of the addresses of the container.
+    ///
+    /// Defaults to both families.
+    pub families: Option<Vec<AddressFamily>>,
+    /// Action to take if a container referenced by the rule is missing, i.e. does not exist or is
+    /// not attached to the network of the rule, see [`OnMissing`](enum.OnMissing.html).
+    ///
+    /// Defaults to `skip`.
+    pub on_missing: Option<OnMissing>,
+
+    /// Condition which has to hold on the host for this rule to be applied, see
+    /// [`Condition`](struct.Condition.html).
+    pub when: Option<Condition>,
+
+    /// Location in the configuration this rule was defined at, see
+    /// [`Provenance`](struct.Provenance.html).
+    ///
+    /// This is recorded while loading the configuration and cannot be set in the configuration
+    /// itself.
+    #[serde(skip)]
+    pub provenance: Option<Provenance>,
+}
+
+/// Location in the configuration a rule was defined at, i.e. the path of the file and the line of
+/// the first key of the rule.
+///
+/// The provenance is only recorded by the loaders [`load_config_file`] and
+/// [`load_config_path`].
+///
+/// [`load_config_file`]: ../util/fn.load_config_file.html
+/// [`load_config_path`]: ../util/fn.load_config_path.html
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Provenance {
+    /// Path of the file the rule was defined in.
+    pub file: String,
+
+    /// Line the rule was defined at, starting at 1.
+    pub line: usize,
+}
+
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.file, self.line)
+    }
+}
+
+/// Byte quota of a rule, generating a named nftables `quota` object.
+///
+/// # Example
+///
+/// ```toml
+/// quota = { bytes = "10 gbytes" }
+/// quota = { bytes = "500 mbytes", over = false }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+pub struct Quota {
+    /// Size of the quota, a number followed by one of the units `bytes`, `kbytes`, `mbytes` or
+    /// `gbytes`, e.g. `10 gbytes`.
+    pub bytes: String,
+
+    /// This defines whether packets are dropped once the quota is exceeded. Otherwise the traffic
+    /// is only accounted to the quota, its usage can be inspected through `nft list quotas`.
+    ///
+    /// Defaults to `true`.
+    #[serde(default = "default_quota_over")]
+    pub over: bool,
+}
+
+/// Explicit target of a DNAT rule, used instead of the address of the container.
+///
+/// Only IPv4 addresses can be used as DNAT target.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "String")]
+pub struct DnatTarget {
+    /// Address to DNAT to.
+    pub address: IpAddr,
+
+    /// Port to DNAT to.
+    ///
+    /// Can be left blank, the container port of the exposed port will then be used.
+    pub port: Option<u16>,
+}
+
+impl FromStr for DnatTarget {
+    type Err = String;
+
+    /// Convert a formatted string into a [`DnatTarget`](struct.DnatTarget.html).
+    ///
+    /// The string has to be in the format `<ADDRESS>[:<PORT>]`, IPv6 addresses have to be enclosed
+    /// in brackets if a port is given, i.e. `[2001:db8::1]:8080`.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::DnatTarget;
+    /// let target: DnatTarget = "192.0.2.10:8080".parse().unwrap();
+    /// assert_eq!(target.address, "192.0.2.10".parse::<std::net::IpAddr>().unwrap());
+    /// assert_eq!(target.port, Some(8080));
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(address) = s.parse::<IpAddr>() {
+            return Ok(DnatTarget {
+                address,
+                port: None,
+            });
+        }
+
+        s.parse::<SocketAddr>()
+            .map(|socket_address| DnatTarget {
+                address: socket_address.ip(),
+                port: Some(socket_address.port()),
+            })
+            .map_err(|_| format!("DNAT target has invalid format '{}'", s))
+    }
+}
+
+impl TryFrom<String> for DnatTarget {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for DnatTarget {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match (self.address, self.port) {
+            (address, None) => write!(f, "{}", address),
+            (address @ IpAddr::V4(_), Some(port)) => write!(f, "{}:{}", address, port),
+            (address @ IpAddr::V6(_), Some(port)) => write!(f, "[{}]:{}", address, port),
+        }
+    }
+}
+
+impl Serialize for DnatTarget {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.collect_str(self)
+    }
+}
+
+/// DSCP value to set on matched packets, for example to integrate with traffic shaping.
+///
+/// The value can either be given numerically (`0` to `63`) or as a symbolic class, i.e. `be`,
+/// `ef`, `cs0` to `cs7` or `af11` to `af43`.
+///
+/// # Example
+///
+/// ```toml
+/// dscp = 46
+/// dscp = "ef"
+/// ```
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Dscp(pub u8);
+
+impl Dscp {
+    /// Highest valid DSCP value, DSCP is encoded in six bits.
+    pub const MAX: u8 = 63;
+}
+
+impl FromStr for Dscp {
+    type Err = String;
+
+    /// Convert a numeric value or symbolic class into a [`Dscp`](struct.Dscp.html).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::Dscp;
+    /// assert_eq!(Ok(Dscp(46)), "ef".parse());
+    /// assert_eq!(Ok(Dscp(40)), "cs5".parse());
+    /// assert_eq!(Ok(Dscp(10)), "10".parse());
+    /// ```
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let class = s.trim().to_lowercase();
+        let value = match class.as_str() {
+            "be" => Some(0),
+            "ef" => Some(46),
+            _ => {
+                if let Some(precedence) = class.strip_prefix("cs") {
+                    match precedence.parse::<u8>() {
+                        Ok(precedence) if precedence <= 7 => Some(precedence << 3),
+                        _ => None,
+                    }
+                } else if let Some(af) = class.strip_prefix("af") {
+                    // Assured forwarding, `af<CLASS><DROP PRECEDENCE>`
+                    match af.as_bytes() {
+                        [class @ b'1'..=b'4', drop @ b'1'..=b'3'] => {
+                            Some(((class - b'0') << 3) | ((drop - b'0') << 1))
+                        }
+                        _ => None,
+                    }
+                } else {
+                    class.parse::<u8>().ok().filter(|value| *value <= Dscp::MAX)
+                }
+            }
+        };
+
+        value
+            .map(Dscp)
+            .ok_or_else(|| format!("invalid DSCP value or class '{}'", s))
+    }
+}
+
+impl TryFrom<u64> for Dscp {
+    type Error = String;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match u8::try_from(value) {
+            Ok(value) if value <= Dscp::MAX => Ok(Dscp(value)),
+            _ => Err(format!(
+                "DSCP value {} is out of range, has to be at most {}",
+                value,
+                Dscp::MAX
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Dscp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Dscp {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct DscpVisitor;
+
+        impl<'de> de::Visitor<'de> for DscpVisitor {
+            type Value = Dscp;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("DSCP value between 0 and 63 or DSCP class")
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                Dscp::try_from(value).map_err(de::Error::custom)
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                u64::try_from(value)
+                    .map_err(|_| de::Error::invalid_value(de::Unexpected::Signed(value), &self))
+                    .and_then(|value| Dscp::try_from(value).map_err(de::Error::custom))
+            }
+
+            fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                value.parse().map_err(de::Error::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DscpVisitor)
+    }
+}
+
+/// VLAN id to match the 802.1Q tag of packets with, for example on hosts with VLAN-segmented
+/// networks.
+///
+/// Valid VLAN ids range from `0` to `4094`, `4095` is reserved.
+///
+/// # Example
+///
+/// ```toml
+/// vlan_id = 100
+/// ```
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct VlanId(pub u16);
+
+impl VlanId {
+    /// Highest valid VLAN id, `4095` is reserved.
+    pub const MAX: u16 = 4094;
+}
+
+impl TryFrom<u64> for VlanId {
+    type Error = String;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        match u16::try_from(value) {
+            Ok(value) if value <= VlanId::MAX => Ok(VlanId(value)),
+            _ => Err(format!(
+                "VLAN id {} is out of range, has to be at most {}",
+                value,
+                VlanId::MAX
+            )),
+        }
+    }
+}
+
+impl fmt::Display for VlanId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for VlanId {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: de::Deserializer<'de>,
+    {
+        struct VlanIdVisitor;
+
+        impl<'de> de::Visitor<'de> for VlanIdVisitor {
+            type Value = VlanId;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("VLAN id between 0 and 4094")
+            }
+
+            fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                VlanId::try_from(value).map_err(de::Error::custom)
+            }
+
+            fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                u64::try_from(value)
+                    .map_err(|_| de::Error::invalid_value(de::Unexpected::Signed(value), &self))
+                    .and_then(|value| VlanId::try_from(value).map_err(de::Error::custom))
+            }
+        }
+
+        deserializer.deserialize_u64(VlanIdVisitor)
+    }
 }
 
 /// Struct to hold a port definition to expose on the host/between containers.
@@ -452,28 +2622,275 @@ pub struct WiderWorldToContainerRule {
 #[serde(deny_unknown_fields)]
 pub struct ExposePort {
     /// Port the `container_port` should be exposed to on the host.
+    ///
+    /// The value [`AUTO_HOST_PORT`](constant.AUTO_HOST_PORT.html) denotes that the host port is
+    /// assigned automatically from `defaults.auto_port_range` during processing.
     #[builder(field(public))]
     pub host_port: u16,
 
-    /// Port the `host_port` should map to into the container.
-    #[builder(field(public), default = "self.default_container_port()?")]
-    pub container_port: Option<u16>,
+    /// Port the `host_port` should map to into the container.
+    #[builder(field(public), default = "self.default_container_port()?")]
+    pub container_port: Option<u16>,
+
+    /// Family of the exposed port.
+    ///
+    /// Can be left blank, `tcp` will be used as default.
+    #[serde(default = "default_expose_port_family")]
+    #[builder(field(public), default = "self.default_family()?")]
+    pub family: PortFamily,
+
+    /// Address of the host the port should be exposed on.
+    ///
+    /// Can be left blank, the port will then be exposed on all addresses of the host.
+    #[builder(field(public), default = "self.default_host_ip()?")]
+    pub host_ip: Option<IpAddr>,
+
+    /// Range of host ports that is mapped onto the `container_port_range`, instead of a single
+    /// port.
+    ///
+    /// Both ranges are of equal width, `host_port` and `container_port` hold their first ports.
+    #[builder(field(public), default)]
+    pub host_port_range: Option<PortRange>,
+
+    /// Range of container ports the `host_port_range` maps to.
+    #[builder(field(public), default)]
+    pub container_port_range: Option<PortRange>,
+
+    /// This defines whether the traffic to the host port is translated to the container.
+    ///
+    /// If unset, only the traffic to the container itself is allowed on the host port, e.g. for
+    /// containers that are directly reachable through a macvlan network. The `container_port` is
+    /// ignored in that case.
+    ///
+    /// Defaults to `true`.
+    #[serde(default = "default_expose_port_dnat")]
+    #[builder(field(public), default = "true")]
+    pub dnat: bool,
+
+    /// This defines whether this is a placeholder for the ports the destination container
+    /// publishes through Docker.
+    ///
+    /// The placeholder is replaced by one exposed port per published port during processing,
+    /// taking the host port, container port, host address and family from the Docker binding. All
+    /// other fields are ignored.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    #[builder(field(public), default = "false")]
+    pub published: bool,
+
+    /// This defines whether this is a placeholder for a port the image of the destination
+    /// container declares through `EXPOSE`.
+    ///
+    /// The placeholder is replaced by the exposed port during processing, taking the family from
+    /// the protocol the image declares. If the image declares multiple ports, the
+    /// `container_port` has to select one of them. The `host_port` defaults to the container port,
+    /// the port ranges cannot be used.
+    ///
+    /// Defaults to `false`.
+    #[serde(default)]
+    #[builder(field(public), default = "false")]
+    pub exposed: bool,
+}
+
+impl ExposePort {
+    /// Verify that the ports, port ranges and settings of the exposed port are consistent.
+    ///
+    /// Every exposed port of the configuration is verified while it is loaded. Exposed ports that
+    /// are created otherwise, e.g. through the [`ExposePortBuilder`](struct.ExposePortBuilder.html),
+    /// can be verified through this method.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// # use dfw::types::{ExposePort, PortRange};
+    /// let mut port: ExposePort = "80:8080/tcp".parse().unwrap();
+    /// assert!(port.validate().is_ok());
+    ///
+    /// port.host_port_range = Some("8000-8010".parse::<PortRange>().unwrap());
+    /// assert!(port.validate().is_err());
+    /// ```
+    pub fn validate(&self) -> Result<(), String> {
+        if self.published {
+            if self.exposed
+                || self.host_port != AUTO_HOST_PORT
+                || self.container_port.is_some()
+                || self.host_port_range.is_some()
+                || self.container_port_range.is_some()
+                || self.host_ip.is_some()
+                || !self.dnat
+            {
+                return Err(
+                    "published ports are taken from Docker and cannot be combined with other \
+                     port settings"
+                        .to_owned(),
+                );
+            }
+            return Ok(());
+        }
+        if self.exposed {
+            if self.host_port_range.is_some() || self.container_port_range.is_some() {
+                return Err(
+                    "exposed port taken from the image of the container cannot be combined with \
+                     port ranges"
+                        .to_owned(),
+                );
+            }
+            if !self.dnat && self.host_ip.is_some() {
+                return Err(
+                    "exposed port without DNAT cannot be restricted to a host address".to_owned(),
+                );
+            }
+            return Ok(());
+        }
+
+        match (self.host_port_range, self.container_port_range) {
+            (Some(host_port_range), Some(container_port_range)) => {
+                if host_port_range.size() != container_port_range.size() {
+                    return Err(format!(
+                        "host port range {} and container port range {} have to be of equal width",
+                        host_port_range, container_port_range
+                    ));
+                }
+                if self.host_port != host_port_range.start
+                    || self.container_port != Some(container_port_range.start)
+                {
+                    return Err(format!(
+                        "exposed port range {} has to hold the first ports of its ranges as host \
+                         and container port",
+                        host_port_range
+                    ));
+                }
+            }
+            (Some(host_port_range), None) => match self.container_port {
+                Some(container_port) => {
+                    return Err(format!(
+                        "exposed port range {} cannot be mapped onto the single container port \
+                         {}, a container port range of equal width is required",
+                        host_port_range, container_port
+                    ))
+                }
+                None => {
+                    return Err("exposed port range requires both `host_port_range` and \
+                                `container_port_range`"
+                        .to_owned())
+                }
+            },
+            (None, Some(_)) => {
+                return Err("exposed port range requires both `host_port_range` and \
+                            `container_port_range`"
+                    .to_owned())
+            }
+            (None, None) => {
+                if self.host_port == AUTO_HOST_PORT && self.container_port.is_none() {
+                    return Err(
+                        "exposed port with automatically assigned host port requires a \
+                         container port"
+                            .to_owned(),
+                    );
+                }
+            }
+        }
+
+        if !self.dnat {
+            if self.host_port == AUTO_HOST_PORT {
+                return Err("exposed port without DNAT requires an explicit host port".to_owned());
+            }
+            if let Some(host_ip) = self.host_ip {
+                return Err(format!(
+                    "exposed port without DNAT cannot be restricted to host address {}",
+                    host_ip
+                ));
+            }
+        }
+        if self.family.is_empty() {
+            return Err(format!(
+                "exposed port {} requires a family",
+                self.host_port_range
+                    .map_or_else(|| self.host_port.to_string(), |range| range.to_string())
+            ));
+        }
 
-    /// Family of the exposed port.
-    ///
-    /// Can be left blank, `tcp` will be used as default.
-    #[serde(default = "default_expose_port_family")]
-    #[builder(field(public), default = "self.default_family()?")]
-    pub family: String,
+        Ok(())
+    }
+}
+
+impl Serialize for ExposePort {
+    /// Serialize the exposed port in the struct-form it is given in the configuration.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("ExposePort", 9)?;
+        if self.published {
+            state.serialize_field("published", &self.published)?;
+            return state.end();
+        }
+        if self.exposed {
+            if self.host_port != AUTO_HOST_PORT {
+                state.serialize_field("host_port", &self.host_port)?;
+            }
+            if let Some(container_port) = self.container_port {
+                state.serialize_field("container_port", &container_port)?;
+            }
+            if let Some(host_ip) = self.host_ip {
+                state.serialize_field("host_ip", &host_ip)?;
+            }
+            if !self.dnat {
+                state.serialize_field("dnat", &self.dnat)?;
+            }
+            state.serialize_field("exposed", &self.exposed)?;
+            return state.end();
+        }
+        match (self.host_port_range, self.container_port_range) {
+            (Some(host_port_range), Some(container_port_range)) => {
+                state.skip_field("host_port")?;
+                state.skip_field("container_port")?;
+                state.serialize_field("host_port_range", &host_port_range)?;
+                state.serialize_field("container_port_range", &container_port_range)?;
+            }
+            _ => {
+                match self.host_port {
+                    AUTO_HOST_PORT => state.serialize_field("host_port", "auto")?,
+                    host_port => state.serialize_field("host_port", &host_port)?,
+                }
+                match self.container_port {
+                    Some(container_port) => {
+                        state.serialize_field("container_port", &container_port)?
+                    }
+                    None => state.skip_field("container_port")?,
+                }
+                state.skip_field("host_port_range")?;
+                state.skip_field("container_port_range")?;
+            }
+        }
+        state.serialize_field("family", &self.family)?;
+        match self.host_ip {
+            Some(host_ip) => state.serialize_field("host_ip", &host_ip)?,
+            None => state.skip_field("host_ip")?,
+        }
+        if self.dnat {
+            state.skip_field("dnat")?;
+        } else {
+            state.serialize_field("dnat", &self.dnat)?;
+        }
+        state.skip_field("published")?;
+        state.skip_field("exposed")?;
+        state.end()
+    }
 }
 
 impl ExposePortBuilder {
     fn client_and_host_port(&mut self, value: &str) -> Result<&mut Self, String> {
         let split: Vec<&str> = value.split(':').collect();
+        let host_port = |value: &str| match value {
+            "auto" => Ok(AUTO_HOST_PORT),
+            value => value.parse().map_err(|e| format!("{}", e)),
+        };
         match split.len() {
-            1 => self.host_port = Some(split[0].parse().map_err(|e| format!("{}", e))?),
+            1 => self.host_port = Some(host_port(split[0])?),
             2 => {
-                self.host_port = Some(split[0].parse().map_err(|e| format!("{}", e))?);
+                self.host_port = Some(host_port(split[0])?);
                 self.container_port = Some(Some(split[1].parse().map_err(|e| format!("{}", e))?));
             }
             _ => return Err(format!("port string has invalid format '{}'", value)),
@@ -488,6 +2905,10 @@ impl ExposePortBuilder {
     fn default_family(&self) -> Result<String, String> {
         Ok(DEFAULT_PROTOCOL.to_owned())
     }
+
+    fn default_host_ip(&self) -> Result<Option<IpAddr>, String> {
+        Ok(None)
+    }
 }
 
 impl FromStr for ExposePort {
@@ -524,7 +2945,36 @@ impl FromStr for ExposePort {
     /// assert_eq!(port.container_port, Some(8080));
     /// assert_eq!(port.family, "tcp");
     /// ```
+    ///
+    /// The string `published` denotes the ports the container publishes through Docker:
+    ///
+    /// ```
+    /// # use dfw::types::ExposePort;
+    /// let port: ExposePort = "published".parse().unwrap();
+    /// assert!(port.published);
+    /// ```
+    ///
+    /// The string `exposed` denotes the port the image of the container declares through `EXPOSE`:
+    ///
+    /// ```
+    /// # use dfw::types::ExposePort;
+    /// let port: ExposePort = "exposed".parse().unwrap();
+    /// assert!(port.exposed);
+    /// ```
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "published" {
+            return ExposePortBuilder::default()
+                .host_port(0)
+                .published(true)
+                .build();
+        }
+        if s == "exposed" {
+            return ExposePortBuilder::default()
+                .host_port(AUTO_HOST_PORT)
+                .exposed(true)
+                .build();
+        }
+
         let split: Vec<&str> = s.split('/').collect();
         Ok(match split.len() {
             1 => ExposePortBuilder::default()
@@ -539,11 +2989,176 @@ impl FromStr for ExposePort {
     }
 }
 
+/// Definition of one or more [`ExposePort`s](struct.ExposePort.html), as it is given in the
+/// configuration.
+///
+/// In contrast to `ExposePort` the struct-form allows the family to be specified as a list, which is
+/// expanded into one `ExposePort` per family.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(deny_unknown_fields)]
+struct ExposePortDefinition {
+    #[serde(default, deserialize_with = "option_host_port")]
+    host_port: Option<u16>,
+    container_port: Option<u16>,
+    host_port_range: Option<PortRange>,
+    container_port_range: Option<PortRange>,
+    #[serde(
+        default = "default_expose_port_families",
+        deserialize_with = "string_or_seq_string"
+    )]
+    family: Vec<String>,
+    host_ip: Option<IpAddr>,
+    #[serde(default = "default_expose_port_dnat")]
+    dnat: bool,
+    #[serde(default)]
+    published: bool,
+    #[serde(default)]
+    exposed: bool,
+}
+
+impl ExposePortDefinition {
+    fn expand(self) -> Result<Vec<ExposePort>, String> {
+        if self.published {
+            if self.exposed
+                || self.host_port.is_some()
+                || self.container_port.is_some()
+                || self.host_port_range.is_some()
+                || self.container_port_range.is_some()
+                || self.host_ip.is_some()
+                || !self.dnat
+                || self.family != default_expose_port_families()
+            {
+                return Err(
+                    "published ports are taken from Docker and cannot be combined with other \
+                     port settings"
+                        .to_owned(),
+                );
+            }
+            return Ok(vec![ExposePortBuilder::default()
+                .host_port(0)
+                .published(true)
+                .build()?]);
+        }
+        if self.exposed {
+            if self.host_port == Some(AUTO_HOST_PORT) {
+                return Err(
+                    "exposed port taken from the image of the container cannot be assigned a \
+                     host port automatically, it defaults to the container port"
+                        .to_owned(),
+                );
+            }
+            if self.family != default_expose_port_families() {
+                return Err(
+                    "exposed port taken from the image of the container cannot specify a family, \
+                     it is taken from the image"
+                        .to_owned(),
+                );
+            }
+            let expose_port = ExposePort {
+                host_port: self.host_port.unwrap_or(AUTO_HOST_PORT),
+                container_port: self.container_port,
+                family: DEFAULT_PROTOCOL.to_owned(),
+                host_ip: self.host_ip,
+                host_port_range: self.host_port_range,
+                container_port_range: self.container_port_range,
+                dnat: self.dnat,
+                published: false,
+                exposed: true,
+            };
+            return expose_port.validate().map(|_| vec![expose_port]);
+        }
+
+        let (host_port, container_port) = match (
+            self.host_port,
+            self.host_port_range,
+            self.container_port_range,
+        ) {
+            (Some(host_port), None, None) => (host_port, self.container_port),
+            (None, Some(host_port_range), Some(container_port_range)) => {
+                if self.container_port.is_some() {
+                    return Err(format!(
+                        "exposed port range {} cannot be combined with a container port",
+                        host_port_range
+                    ));
+                }
+                (host_port_range.start, Some(container_port_range.start))
+            }
+            (None, Some(_), None) | (None, None, Some(_)) => {
+                return Err("exposed port range requires both `host_port_range` and \
+                            `container_port_range`"
+                    .to_owned())
+            }
+            (None, None, None) => return Err("exposed port requires a host port".to_owned()),
+            (Some(_), _, _) => {
+                return Err(
+                    "exposed port has to specify either `host_port` or a port range, \
+                            not both"
+                        .to_owned(),
+                )
+            }
+        };
+        if self.family.is_empty() {
+            return Err(format!(
+                "family list of exposed port {} must not be empty",
+                self.host_port_range
+                    .map_or_else(|| host_port.to_string(), |range| range.to_string())
+            ));
+        }
+
+        let host_ip = self.host_ip;
+        let host_port_range = self.host_port_range;
+        let container_port_range = self.container_port_range;
+        let dnat = self.dnat;
+        self.family
+            .into_iter()
+            .map(|family| {
+                let expose_port = ExposePort {
+                    host_port,
+                    container_port,
+                    family,
+                    host_ip,
+                    host_port_range,
+                    container_port_range,
+                    dnat,
+                    published: false,
+                    exposed: false,
+                };
+                expose_port.validate().map(|_| expose_port)
+            })
+            .collect()
+    }
+}
+
+impl FromStr for ExposePortDefinition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expose_port: ExposePort = s.parse()?;
+        Ok(ExposePortDefinition {
+            host_port: Some(expose_port.host_port)
+                .filter(|_| !expose_port.published && !expose_port.exposed),
+            container_port: expose_port.container_port,
+            host_port_range: None,
+            container_port_range: None,
+            family: vec![expose_port.family],
+            host_ip: expose_port.host_ip,
+            dnat: expose_port.dnat,
+            published: expose_port.published,
+            exposed: expose_port.exposed,
+        })
+    }
+}
+
 /// The container-DNAT section, defining how containers can communicate with each other over
 /// non-common networks.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerDNAT {
+    /// This defines whether the section is processed. A disabled section generates no rules.
+    ///
+    /// Defaults to `true`.
+    pub enabled: Option<bool>,
+
     /// An optional list of rules, see
     /// [`ContainerDNATRule`](struct.ContainerDNATRule.html).
     ///
@@ -564,20 +3179,24 @@ pub struct ContainerDNAT {
 }
 
 /// Definition for a rule to be used in the container-DNAT section.
-#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
 #[serde(deny_unknown_fields)]
 pub struct ContainerDNATRule {
     /// Network of the source container to apply the rule to.
     pub src_network: Option<String>,
 
-    /// Source container to apply the rule to.
-    pub src_container: Option<String>,
+    /// Source container to apply the rule to, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(default, deserialize_with = "option_string_or_struct")]
+    pub src_container: Option<ContainerSelector>,
 
     /// Network of the destination container to apply the rule to.
     pub dst_network: String,
 
-    /// Destination container to apply the rule to.
-    pub dst_container: String,
+    /// Destination container to apply the rule to, see
+    /// [`ContainerSelector`](enum.ContainerSelector.html).
+    #[serde(deserialize_with = "string_or_struct")]
+    pub dst_container: ContainerSelector,
 
     /// Ports to apply the rule to.
     ///
@@ -616,15 +3235,199 @@ pub struct ContainerDNATRule {
     ///     { host_port = 53, family = "udp" },
     ///     { host_port = 443, container_port = 8443 },
     /// ]
+    ///
+    /// # The struct-form allows the family to be a list, generating one definition per family
+    /// expose_port = { host_port = 443, family = ["tcp", "udp"] }
+    ///
+    /// # The port can be restricted to a single address of the host
+    /// expose_port = { host_port = 443, host_ip = "192.0.2.1", family = ["tcp", "udp"] }
+    ///
+    /// # The port the image of the container declares through `EXPOSE` can be used, the
+    /// # container port selects one of them if the image declares multiple ports
+    /// expose_port = "exposed"
+    /// expose_port = { exposed = true, container_port = 8080 }
     /// ```
-    #[serde(deserialize_with = "single_or_seq_string_or_struct")]
+    #[serde(deserialize_with = "expose_ports")]
     pub expose_port: Vec<ExposePort>,
+
+    /// Condition which has to hold on the host for this rule to be applied, see
+    /// [`Condition`](struct.Condition.html).
+    pub when: Option<Condition>,
+
+    /// Location in the configuration this rule was defined at, see
+    /// [`Provenance`](struct.Provenance.html).
+    ///
+    /// This is recorded while loading the configuration and cannot be set in the configuration
+    /// itself.
+    #[serde(skip)]
+    pub provenance: Option<Provenance>,
+}
+
+/// Reference to a container within a rule.
+///
+/// A container can be referenced either by its name or by a network-scoped alias. Aliases are
+/// resolved on the network the rule applies to. Resolving aliases requires the `docker` CLI to be
+/// available, it is invoked against the same Docker host (honoring `DOCKER_HOST`).
+///
+/// # Example
+///
+/// ```toml
+/// # Reference the container by its name
+/// dst_container = "db"
+/// dst_container = { name = "db" }
+///
+/// # Reference the container carrying the alias `db` on the network of the rule
+/// dst_container = { alias = "db" }
+/// ```
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+#[serde(try_from = "ContainerSelectorDefinition")]
+pub enum ContainerSelector {
+    /// Select the container by its name.
+    Name(String),
+    /// Select the container carrying the network-scoped alias.
+    Alias(String),
+}
+
+impl FromStr for ContainerSelector {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(ContainerSelector::Name(s.to_owned()))
+    }
+}
+
+impl fmt::Display for ContainerSelector {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ContainerSelector::Name(name) => write!(f, "{}", name),
+            ContainerSelector::Alias(alias) => write!(f, "alias:{}", alias),
+        }
+    }
+}
+
+impl Serialize for ContainerSelector {
+    /// Serialize a selector by name as the plain name, and a selector by alias in its struct-form.
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self {
+            ContainerSelector::Name(name) => serializer.serialize_str(name),
+            ContainerSelector::Alias(alias) => {
+                let mut state = serializer.serialize_struct("ContainerSelector", 1)?;
+                state.serialize_field("alias", alias)?;
+                state.end()
+            }
+        }
+    }
+}
+
+/// Struct-form of a [`ContainerSelector`](enum.ContainerSelector.html), as it is given in the
+/// configuration.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ContainerSelectorDefinition {
+    name: Option<String>,
+    alias: Option<String>,
+}
+
+impl TryFrom<ContainerSelectorDefinition> for ContainerSelector {
+    type Error = String;
+
+    fn try_from(definition: ContainerSelectorDefinition) -> Result<Self, Self::Error> {
+        match (definition.name, definition.alias) {
+            (Some(name), None) => Ok(ContainerSelector::Name(name)),
+            (None, Some(alias)) => Ok(ContainerSelector::Alias(alias)),
+            _ => Err("container has to be selected by exactly one of `name` or `alias`".to_owned()),
+        }
+    }
+}
+
+/// Condition that restricts a rule to hosts matching the given facts.
+///
+/// All specified facts have to match for the condition to hold, facts that are not specified are
+/// not considered. The condition is evaluated when the rules are generated, rules whose condition
+/// does not hold are skipped.
+///
+/// # Example
+///
+/// ```toml
+/// when = { hostname = "edge-*" }
+/// when = { env = "ROLE=edge" }
+/// when = { hostname = "edge-*", env = ["ROLE=edge", "DFW_ENABLED"] }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Condition {
+    /// Glob-pattern the hostname of the host has to match, e.g. `edge-*`.
+    pub hostname: Option<String>,
+
+    /// Environment variables that have to be set on the host.
+    ///
+    /// Every entry is either of the form `NAME`, which requires the variable to be set (to any
+    /// value), or `NAME=VALUE`, which requires the variable to be set to exactly `VALUE`.
+    ///
+    /// This can be:
+    ///
+    /// * a single string
+    ///
+    /// * a list of strings
+    #[serde(default, deserialize_with = "option_string_or_seq_string")]
+    pub env: Option<Vec<String>>,
+}
+
+fn default_drop_invalid() -> bool {
+    true
+}
+
+fn default_docker_concurrency() -> usize {
+    DEFAULT_DOCKER_CONCURRENCY
+}
+
+fn default_docker_retries() -> usize {
+    DEFAULT_DOCKER_RETRIES
+}
+
+fn default_docker_retry_backoff() -> u64 {
+    DEFAULT_DOCKER_RETRY_BACKOFF
+}
+
+fn default_log_rate() -> String {
+    DEFAULT_LOG_RATE.to_owned()
+}
+
+fn default_dnat_new_only() -> bool {
+    true
+}
+
+fn default_dnat_accept_shortcut() -> bool {
+    true
+}
+
+fn default_allow_embedded_dns() -> bool {
+    true
+}
+
+fn default_quota_over() -> bool {
+    true
+}
+
+fn default_log_unmatched_prefix() -> String {
+    "wwtc-nomatch: ".to_owned()
 }
 
 fn default_expose_port_family() -> String {
     DEFAULT_PROTOCOL.to_owned()
 }
 
+fn default_expose_port_dnat() -> bool {
+    true
+}
+
+fn default_expose_port_families() -> Vec<String> {
+    vec![default_expose_port_family()]
+}
+
 struct StringOrStruct<T>(PhantomData<T>);
 
 impl<'de, T> de::Visitor<'de> for StringOrStruct<T>
@@ -675,7 +3478,6 @@ where
     }
 }
 
-#[allow(dead_code)]
 fn string_or_struct<'de, T, D>(deserializer: D) -> Result<T, D::Error>
 where
     T: de::Deserialize<'de> + FromStr<Err = String>,
@@ -684,6 +3486,49 @@ where
     deserializer.deserialize_any(StringOrStruct(PhantomData))
 }
 
+fn option_string_or_struct<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: de::Deserialize<'de> + FromStr<Err = String>,
+    D: de::Deserializer<'de>,
+{
+    string_or_struct(deserializer).map(Some)
+}
+
+struct BoolOrStruct<T>(PhantomData<T>);
+
+impl<'de, T> de::Visitor<'de> for BoolOrStruct<T>
+where
+    T: de::Deserialize<'de> + Default,
+{
+    type Value = Option<T>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("boolean or map")
+    }
+
+    fn visit_bool<E>(self, value: bool) -> Result<Option<T>, E>
+    where
+        E: de::Error,
+    {
+        Ok(if value { Some(T::default()) } else { None })
+    }
+
+    fn visit_map<M>(self, visitor: M) -> Result<Option<T>, M::Error>
+    where
+        M: de::MapAccess<'de>,
+    {
+        de::Deserialize::deserialize(de::value::MapAccessDeserializer::new(visitor)).map(Some)
+    }
+}
+
+fn option_bool_or_struct<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: de::Deserialize<'de> + Default,
+    D: de::Deserializer<'de>,
+{
+    deserializer.deserialize_any(BoolOrStruct(PhantomData))
+}
+
 struct SingleOrSeqStringOrStruct<T>(PhantomData<T>);
 
 impl<'de, T> de::Visitor<'de> for SingleOrSeqStringOrStruct<T>
@@ -745,6 +3590,78 @@ where
     deserializer.deserialize_any(SingleOrSeqStringOrStruct(PhantomData))
 }
 
+fn expose_ports<'de, D>(deserializer: D) -> Result<Vec<ExposePort>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    let definitions: Vec<ExposePortDefinition> = single_or_seq_string_or_struct(deserializer)?;
+    let mut expose_ports = Vec::new();
+    for definition in definitions {
+        expose_ports.append(&mut definition.expand().map_err(de::Error::custom)?);
+    }
+    Ok(expose_ports)
+}
+
+fn host_port<'de, D>(deserializer: D) -> Result<u16, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    struct HostPort;
+
+    impl<'de> de::Visitor<'de> for HostPort {
+        type Value = u16;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("port or \"auto\"")
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            match u16::try_from(value) {
+                Ok(port) if port != AUTO_HOST_PORT => Ok(port),
+                _ => Err(de::Error::invalid_value(
+                    de::Unexpected::Unsigned(value),
+                    &self,
+                )),
+            }
+        }
+
+        fn visit_i64<E>(self, value: i64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            match u16::try_from(value) {
+                Ok(port) if port != AUTO_HOST_PORT => Ok(port),
+                _ => Err(de::Error::invalid_value(
+                    de::Unexpected::Signed(value),
+                    &self,
+                )),
+            }
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            match value {
+                "auto" => Ok(AUTO_HOST_PORT),
+                _ => Err(de::Error::invalid_value(de::Unexpected::Str(value), &self)),
+            }
+        }
+    }
+
+    deserializer.deserialize_any(HostPort)
+}
+
+fn option_host_port<'de, D>(deserializer: D) -> Result<Option<u16>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    host_port(deserializer).map(Some)
+}
+
 fn string_or_seq_string<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
 where
     D: de::Deserializer<'de>,
@@ -783,6 +3700,48 @@ where
     string_or_seq_string(deserializer).map(Some)
 }
 
+fn option_family_interfaces<'de, D>(deserializer: D) -> Result<Option<FamilyInterfaces>, D::Error>
+where
+    D: de::Deserializer<'de>,
+{
+    struct FamilyInterfacesVisitor(PhantomData<FamilyInterfaces>);
+
+    impl<'de> de::Visitor<'de> for FamilyInterfacesVisitor {
+        type Value = FamilyInterfaces;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("string, sequence of strings or map")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            Ok(vec![value.to_owned()].into())
+        }
+
+        fn visit_seq<S>(self, visitor: S) -> Result<Self::Value, S::Error>
+        where
+            S: de::SeqAccess<'de>,
+        {
+            let interfaces: Vec<String> =
+                de::Deserialize::deserialize(de::value::SeqAccessDeserializer::new(visitor))?;
+            Ok(interfaces.into())
+        }
+
+        fn visit_map<M>(self, visitor: M) -> Result<Self::Value, M::Error>
+        where
+            M: de::MapAccess<'de>,
+        {
+            de::Deserialize::deserialize(de::value::MapAccessDeserializer::new(visitor))
+        }
+    }
+
+    deserializer
+        .deserialize_any(FamilyInterfacesVisitor(PhantomData))
+        .map(Some)
+}
+
 fn struct_or_seq_struct<'de, T, D>(deserializer: D) -> Result<Vec<T>, D::Error>
 where
     T: de::Deserialize<'de>,
@@ -9,13 +9,44 @@
 //! Utilities module
 
 use crate::errors::*;
+use crate::types::{
+    AddressFamily, ExternalNetworkInterfaces, PortFamily, Provenance, CONFIG_VERSION, DFW,
+};
+use failure::{bail, format_err};
 
 use glob::glob;
-use serde::de::DeserializeOwned;
+use serde::de::{DeserializeOwned, Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
 use std::io::BufReader;
-use toml;
+use std::net::Ipv4Addr;
+use toml::{self, Spanned};
+
+/// Key of the top-level table defining the variables of the configuration, see
+/// [`resolve_vars`](fn.resolve_vars.html).
+const VARS_KEY: &str = "vars";
+
+/// Prefix of a reference to a variable, which is terminated by a closing brace.
+const VAR_REFERENCE_PREFIX: &str = "${vars.";
+
+/// Key of the top-level array of tables defining rules of any section, see
+/// [`distribute_rules`](fn.distribute_rules.html).
+const RULES_KEY: &str = "rules";
+
+/// Key of a rule within the top-level array of rules naming the section of the rule.
+const RULE_SECTION_KEY: &str = "section";
+
+/// Sections whose rules can be defined within the top-level array of rules.
+const RULE_SECTIONS: [&str; 5] = [
+    "container_to_container",
+    "container_to_wider_world",
+    "container_to_host",
+    "wider_world_to_container",
+    "container_dnat",
+];
 
 /// Load single TOML-file from path and deserialize it into type `T`.
 pub fn load_file<T>(file: &str) -> Result<T>
@@ -25,7 +56,208 @@ where
     let mut contents = String::new();
     let mut file = BufReader::new(File::open(file)?);
     file.read_to_string(&mut contents)?;
-    Ok(toml::from_str(&contents)?)
+    from_config_str(&contents)
+}
+
+/// Load the IPv4 CIDRs listed in a file, one per line, see
+/// [`parse_cidr_file`](fn.parse_cidr_file.html).
+pub fn load_cidr_file(file: &str) -> Result<Vec<String>> {
+    parse_cidr_file(file, &read_cidr_file(file)?)
+}
+
+/// Read the contents of a CIDR file.
+pub fn read_cidr_file(file: &str) -> Result<String> {
+    let mut contents = String::new();
+    let mut reader = BufReader::new(
+        File::open(file).map_err(|e| format_err!("failed to open CIDR file {}: {}", file, e))?,
+    );
+    reader.read_to_string(&mut contents)?;
+
+    Ok(contents)
+}
+
+/// Parse the IPv4 CIDRs listed in the contents of a CIDR file, one per line.
+///
+/// Leading and trailing whitespace is ignored, as are empty lines and lines starting with `#`.
+/// Every other line has to be an IPv4 address, optionally followed by a prefix length, otherwise
+/// an error naming the offending line is returned.
+pub fn parse_cidr_file(file: &str, contents: &str) -> Result<Vec<String>> {
+    let mut cidrs = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if !is_ipv4_cidr(line) {
+            bail!(
+                "line {} of CIDR file {} is not a valid IPv4 CIDR: '{}'",
+                index + 1,
+                file,
+                line
+            );
+        }
+        cidrs.push(line.to_owned());
+    }
+
+    Ok(cidrs)
+}
+
+fn is_ipv4_cidr(cidr: &str) -> bool {
+    let mut parts = cidr.splitn(2, '/');
+    let address_valid = parts
+        .next()
+        .is_some_and(|address| address.parse::<Ipv4Addr>().is_ok());
+    let prefix_length_valid = parts.next().is_none_or(|prefix_length| {
+        prefix_length
+            .parse::<u8>()
+            .is_ok_and(|prefix_length| prefix_length <= 32)
+    });
+    address_valid && prefix_length_valid
+}
+
+/// Get the number of bytes of a size like `100 mbytes`, i.e. a positive number followed by one of
+/// the units `bytes`, `kbytes`, `mbytes` or `gbytes`. The units are multiples of 1024 like they are
+/// for nft.
+///
+/// Returns `None` if the size is invalid.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::util::size_in_bytes;
+/// assert_eq!(Some(104_857_600), size_in_bytes("100 mbytes"));
+/// assert_eq!(None, size_in_bytes("100 mb"));
+/// ```
+pub fn size_in_bytes(size: &str) -> Option<u64> {
+    let (count, unit) = match size.split_whitespace().collect::<Vec<_>>()[..] {
+        [count, unit] => (count.parse::<u64>().ok().filter(|count| *count > 0)?, unit),
+        _ => return None,
+    };
+    let exponent = ["bytes", "kbytes", "mbytes", "gbytes"]
+        .iter()
+        .position(|other| *other == unit)?;
+
+    count.checked_mul(1024u64.pow(exponent as u32))
+}
+
+/// Get the number of seconds of a timeout like `1h`, i.e. a positive number followed by one of the
+/// units `s`, `m`, `h` or `d`.
+///
+/// Returns `None` if the timeout is invalid.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::util::timeout_in_seconds;
+/// assert_eq!(Some(5400), timeout_in_seconds("90m"));
+/// assert_eq!(None, timeout_in_seconds("90"));
+/// ```
+pub fn timeout_in_seconds(timeout: &str) -> Option<u64> {
+    let unit_start = timeout.find(|c: char| !c.is_ascii_digit())?;
+    let count = timeout[..unit_start]
+        .parse::<u64>()
+        .ok()
+        .filter(|count| *count > 0)?;
+    let multiplier = match &timeout[unit_start..] {
+        "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return None,
+    };
+
+    count.checked_mul(multiplier)
+}
+
+/// Conntrack states of the protocols a timeout policy can be defined for, see
+/// [`Defaults.ct_timeouts`](../types/struct.Defaults.html#structfield.ct_timeouts).
+const CT_TIMEOUT_STATES: &[(&str, &[&str])] = &[
+    (
+        "tcp",
+        &[
+            "syn_sent",
+            "syn_recv",
+            "established",
+            "fin_wait",
+            "close_wait",
+            "last_ack",
+            "time_wait",
+            "close",
+            "syn_sent2",
+            "retrans",
+            "unacknowledged",
+        ],
+    ),
+    ("udp", &["unreplied", "replied"]),
+];
+
+/// Protocol and timeouts in seconds per conntrack state of a timeout policy, see
+/// [`ct_timeout_policy`](fn.ct_timeout_policy.html).
+type CtTimeoutPolicy = (&'static str, Vec<(String, u64)>);
+
+/// Get the protocol and the timeouts in seconds per conntrack state of a timeout policy, see
+/// [`Defaults.ct_timeouts`](../types/struct.Defaults.html#structfield.ct_timeouts).
+pub(crate) fn ct_timeout_policy(
+    name: &str,
+    policy: &BTreeMap<String, String>,
+) -> std::result::Result<CtTimeoutPolicy, String> {
+    let mut name_chars = name.chars();
+    if !name_chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        || !name_chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+    {
+        return Err(format!(
+            "conntrack timeout policy name `{}` has to consist of letters, digits and \
+             underscores, starting with a letter",
+            name
+        ));
+    }
+
+    let mut protocol = None;
+    let mut timeouts = Vec::with_capacity(policy.len());
+    for (key, timeout) in policy {
+        let (key_protocol, state) = CT_TIMEOUT_STATES
+            .iter()
+            .find_map(|(protocol, states)| {
+                let state = key.strip_prefix(protocol)?.strip_prefix('_')?;
+                states
+                    .iter()
+                    .find(|other| **other == state)
+                    .map(|state| (*protocol, *state))
+            })
+            .ok_or_else(|| {
+                format!(
+                    "conntrack timeout policy `{}` has the unknown timeout `{}`, expected the \
+                     protocol and the state, e.g. `tcp_established`",
+                    name, key
+                )
+            })?;
+        match protocol {
+            Some(protocol) if protocol != key_protocol => {
+                return Err(format!(
+                    "conntrack timeout policy `{}` defines timeouts of both `{}` and `{}`, a \
+                     policy only applies to a single protocol",
+                    name, protocol, key_protocol
+                ))
+            }
+            _ => protocol = Some(key_protocol),
+        }
+        let seconds = timeout_in_seconds(timeout).ok_or_else(|| {
+            format!(
+                "conntrack timeout policy `{}` has the timeout '{}' for `{}`, which is not a valid \
+                 timeout, e.g. '1h'",
+                name, timeout, key
+            )
+        })?;
+        timeouts.push((state.to_owned(), seconds));
+    }
+
+    match protocol {
+        Some(protocol) => Ok((protocol, timeouts)),
+        None => Err(format!(
+            "conntrack timeout policy `{}` defines no timeouts",
+            name
+        )),
+    }
 }
 
 /// Load all TOML-files from a path, concatenate their contents and deserialize the result into
@@ -45,5 +277,567 @@ where
         }
     }
 
-    Ok(toml::from_str(&contents)?)
+    from_config_str(&contents)
+}
+
+/// Deserialize the configuration, resolving the variables defined in its `[vars]` table and
+/// moving the rules of its `[[rules]]` array into their sections, see
+/// [`resolve_vars`](fn.resolve_vars.html) and [`distribute_rules`](fn.distribute_rules.html).
+///
+/// Configurations without variables and without a `[[rules]]` array are deserialized directly,
+/// such that errors keep pointing at the offending line.
+pub(crate) fn from_config_str<T>(contents: &str) -> Result<T>
+where
+    T: DeserializeOwned,
+{
+    let config: toml::Value = toml::from_str(contents)?;
+    if config.get(VARS_KEY).is_none() && config.get(RULES_KEY).is_none() {
+        return Ok(toml::from_str(contents)?);
+    }
+
+    Ok(distribute_rules(resolve_vars(config)?)?.try_into()?)
+}
+
+/// Move the rules defined in the top-level `[[rules]]` array of the configuration into the
+/// sections named by their `section` key, removing the array.
+///
+/// This allows writing the rules of all sections within a single flat array, e.g. when the
+/// configuration is generated by a tool. Apart from the `section` key, a rule has the fields of
+/// the rules of its section. The rules are appended to the rules defined within the section
+/// itself, in the order they are defined in.
+///
+/// # Example
+///
+/// ```toml
+/// [[rules]]
+/// section = "container_to_container"
+/// network = "common_network"
+/// src_container = "container_a"
+/// dst_container = "container_b"
+/// verdict = "accept"
+///
+/// [[rules]]
+/// section = "wider_world_to_container"
+/// network = "reverseproxy_network"
+/// dst_container = "reverseproxy"
+/// expose_port = 443
+/// ```
+pub fn distribute_rules(mut config: toml::Value) -> Result<toml::Value> {
+    let rules = match config
+        .as_table_mut()
+        .and_then(|config| config.remove(RULES_KEY))
+    {
+        Some(toml::Value::Array(rules)) => rules,
+        Some(_) => bail!("`{}` has to be an array of tables", RULES_KEY),
+        None => return Ok(config),
+    };
+
+    let sections = config
+        .as_table_mut()
+        .expect("configuration with rules is a table");
+    for (index, rule) in rules.into_iter().enumerate() {
+        let mut rule = match rule {
+            toml::Value::Table(rule) => rule,
+            _ => bail!("rule {} of `{}` has to be a table", index + 1, RULES_KEY),
+        };
+        let section = match rule.remove(RULE_SECTION_KEY) {
+            Some(toml::Value::String(section)) if RULE_SECTIONS.contains(&section.as_str()) => {
+                section
+            }
+            Some(section) => bail!(
+                "rule {} of `{}` has invalid section {}, expected one of: {}",
+                index + 1,
+                RULES_KEY,
+                section,
+                RULE_SECTIONS.join(", ")
+            ),
+            None => bail!(
+                "rule {} of `{}` is missing the key `{}`",
+                index + 1,
+                RULES_KEY,
+                RULE_SECTION_KEY
+            ),
+        };
+
+        let section_rules = sections
+            .entry(section.clone())
+            .or_insert_with(|| toml::Value::Table(Default::default()))
+            .as_table_mut()
+            .ok_or_else(|| format_err!("`{}` has to be a table", section))?
+            .entry(RULES_KEY)
+            .or_insert_with(|| toml::Value::Array(Vec::new()));
+        match section_rules {
+            toml::Value::Array(section_rules) => section_rules.push(toml::Value::Table(rule)),
+            _ => bail!("`{}.{}` has to be an array of tables", section, RULES_KEY),
+        }
+    }
+
+    Ok(config)
+}
+
+/// Resolve the variables defined in the top-level `[vars]` table of the configuration, removing
+/// the table.
+///
+/// A variable is referenced within any string of the configuration as `${vars.<name>}`. A string
+/// consisting of nothing but the reference is replaced by the value of the variable, which can
+/// thus be of any type, e.g. a list of CIDRs. A reference to a list within a list is spliced into
+/// the surrounding list. Otherwise the reference is replaced within the string, which requires the
+/// variable to be a string, a number or a boolean.
+///
+/// The values of the variables are not resolved themselves. Referencing an undefined variable is
+/// an error.
+///
+/// # Example
+///
+/// ```toml
+/// [vars]
+/// admin_cidrs = ["192.0.2.0/24", "198.51.100.7/32"]
+/// proxy = "reverseproxy"
+///
+/// [[wider_world_to_container.rules]]
+/// network = "${vars.proxy}_network"
+/// dst_container = "${vars.proxy}"
+/// expose_port = 22
+/// source_cidr_v4 = "${vars.admin_cidrs}"
+/// ```
+pub fn resolve_vars(mut config: toml::Value) -> Result<toml::Value> {
+    let vars = match config
+        .as_table_mut()
+        .and_then(|config| config.remove(VARS_KEY))
+    {
+        Some(toml::Value::Table(vars)) => vars,
+        Some(_) => bail!("`{}` has to be a table", VARS_KEY),
+        None => return Ok(config),
+    };
+
+    resolve_value(&vars, "", config)
+}
+
+fn resolve_value(vars: &toml::value::Table, path: &str, value: toml::Value) -> Result<toml::Value> {
+    Ok(match value {
+        toml::Value::String(string) => interpolate_vars(vars, path, &string)?,
+        toml::Value::Array(values) => {
+            let mut resolved = Vec::new();
+            for value in values {
+                let name = value.as_str().and_then(var_reference);
+                match name.map(|name| lookup_var(vars, path, name)).transpose()? {
+                    Some(toml::Value::Array(values)) => resolved.extend(values.iter().cloned()),
+                    _ => resolved.push(resolve_value(vars, path, value)?),
+                }
+            }
+            toml::Value::Array(resolved)
+        }
+        toml::Value::Table(table) => toml::Value::Table(
+            table
+                .into_iter()
+                .map(|(key, value)| {
+                    let path = match path {
+                        "" => key.clone(),
+                        path => format!("{}.{}", path, key),
+                    };
+                    let value = resolve_value(vars, &path, value)?;
+                    Ok((key, value))
+                })
+                .collect::<Result<_>>()?,
+        ),
+        value => value,
+    })
+}
+
+/// Get the name of the variable the string references, if it consists of nothing but the
+/// reference.
+fn var_reference(string: &str) -> Option<&str> {
+    string
+        .strip_prefix(VAR_REFERENCE_PREFIX)?
+        .strip_suffix('}')
+        .filter(|name| !name.contains('}'))
+}
+
+fn lookup_var<'a>(vars: &'a toml::value::Table, path: &str, name: &str) -> Result<&'a toml::Value> {
+    vars.get(name)
+        .ok_or_else(|| format_err!("undefined variable `{}` referenced by `{}`", name, path))
+}
+
+fn interpolate_vars(vars: &toml::value::Table, path: &str, string: &str) -> Result<toml::Value> {
+    if let Some(name) = var_reference(string) {
+        return Ok(lookup_var(vars, path, name)?.clone());
+    }
+
+    let mut interpolated = String::new();
+    let mut rest = string;
+    while let Some(start) = rest.find(VAR_REFERENCE_PREFIX) {
+        interpolated.push_str(&rest[..start]);
+        let reference = &rest[start + VAR_REFERENCE_PREFIX.len()..];
+        let end = reference
+            .find('}')
+            .ok_or_else(|| format_err!("unterminated variable reference in `{}`", path))?;
+        let name = &reference[..end];
+        match lookup_var(vars, path, name)? {
+            toml::Value::String(value) => interpolated.push_str(value),
+            toml::Value::Integer(value) => interpolated.push_str(&value.to_string()),
+            toml::Value::Float(value) => interpolated.push_str(&value.to_string()),
+            toml::Value::Boolean(value) => interpolated.push_str(&value.to_string()),
+            _ => bail!(
+                "variable `{}` referenced by `{}` has to be a string, a number or a boolean to be \
+                 interpolated",
+                name,
+                path
+            ),
+        }
+        rest = &reference[end + 1..];
+    }
+    interpolated.push_str(rest);
+
+    Ok(toml::Value::String(interpolated))
+}
+
+/// Get the hostname of the host DFW is currently running on.
+pub fn hostname() -> Result<String> {
+    let mut buffer = vec![0u8; 256];
+    if unsafe { libc::gethostname(buffer.as_mut_ptr() as *mut libc::c_char, buffer.len()) } != 0 {
+        bail!("failed to retrieve hostname");
+    }
+    let length = buffer.iter().position(|&b| b == 0).unwrap_or(buffer.len());
+    Ok(String::from_utf8_lossy(&buffer[..length]).into_owned())
+}
+
+/// Load a single configuration file, recording the location of every rule, see
+/// [`Provenance`](../types/struct.Provenance.html).
+///
+/// The file can be scoped to hosts like the files loaded by
+/// [`load_config_path`](fn.load_config_path.html).
+pub fn load_config_file(file: &str) -> Result<DFW> {
+    load_config(vec![file.to_owned()], &hostname()?)
+}
+
+/// Load all configuration files from a path, recording the location of every rule, see
+/// [`Provenance`](../types/struct.Provenance.html).
+///
+/// The files are merged like they are by [`load_path`](fn.load_path.html). A file can be scoped to
+/// a set of hosts through a top-level `hosts` key, preceding all tables of the file. The value is
+/// a hostname pattern or a sequence thereof, using the syntax of
+/// [`Condition.hostname`](../types/struct.Condition.html#structfield.hostname). Files whose
+/// patterns don't match the hostname of the current host are skipped entirely.
+///
+/// # Example
+///
+/// ```toml
+/// hosts = ["edge-*", "core-01"]
+///
+/// [[wider_world_to_container.rules]]
+/// network = "reverseproxy_network"
+/// dst_container = "reverseproxy"
+/// expose_port = 443
+/// ```
+pub fn load_config_path(path: &str) -> Result<DFW> {
+    load_config_path_for_host(path, &hostname()?)
+}
+
+/// Load all configuration files from a path like [`load_config_path`](fn.load_config_path.html),
+/// scoping the files to the given hostname instead of the hostname of the current host.
+pub fn load_config_path_for_host(path: &str, hostname: &str) -> Result<DFW> {
+    let mut files = Vec::new();
+    for entry in glob(&format!("{}/*.toml", path)).expect("Failed to read glob pattern") {
+        match entry {
+            Ok(path) => files.push(path.to_string_lossy().into_owned()),
+            Err(e) => println!("{:?}", e),
+        }
+    }
+    load_config(files, hostname)
+}
+
+fn load_config(files: Vec<String>, hostname: &str) -> Result<DFW> {
+    // Remember where each file starts within the concatenated contents, such that the position of
+    // a rule can be attributed to its file.
+    let mut contents = String::new();
+    let mut starts = Vec::new();
+    for file in files {
+        let mut file_contents = String::new();
+        BufReader::new(File::open(&file)?).read_to_string(&mut file_contents)?;
+        let file_contents = match scope_to_host(&file, file_contents, hostname)? {
+            Some(file_contents) => file_contents,
+            None => continue,
+        };
+        starts.push((contents.len(), file));
+        contents.push_str(&file_contents);
+    }
+
+    let mut dfw: DFW = from_config_str(&contents)?;
+    let locations: RuleLocations = toml::from_str(&contents)?;
+    let provenance = |location: &RuleLocation| {
+        let offset = location.start?;
+        let (start, file) = starts.iter().rev().find(|(start, _)| *start <= offset)?;
+        Some(Provenance {
+            file: file.clone(),
+            line: contents[*start..offset].matches('\n').count() + 1,
+        })
+    };
+
+    macro_rules! assign_provenance {
+        ($section:ident) => {
+            let rules = dfw
+                .$section
+                .iter_mut()
+                .flat_map(|section| section.rules.iter_mut().flatten());
+            // Rules of the top-level array follow the rules defined within the section
+            let flat_locations = locations.rules.iter().filter(|location| {
+                location.section.as_ref().map(String::as_str) == Some(stringify!($section))
+            });
+            for (rule, location) in rules.zip(locations.$section.rules.iter().chain(flat_locations))
+            {
+                rule.provenance = provenance(location);
+            }
+        };
+    }
+    assign_provenance!(container_to_container);
+    assign_provenance!(container_to_wider_world);
+    assign_provenance!(container_to_host);
+    assign_provenance!(wider_world_to_container);
+    assign_provenance!(container_dnat);
+
+    Ok(dfw)
+}
+
+/// Top-level keys of a single configuration file scoping it to a set of hosts, see
+/// [`load_config_path`](fn.load_config_path.html).
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct FileScope {
+    hosts: Option<Spanned<toml::Value>>,
+}
+
+/// Get the contents of the configuration file if it applies to the host with the given hostname,
+/// `None` if it is to be skipped.
+///
+/// The `hosts` key is blanked out of the contents, such that the files can be merged. Lines are
+/// kept, the locations of the rules thus remain unchanged.
+fn scope_to_host(file: &str, mut contents: String, hostname: &str) -> Result<Option<String>> {
+    // A file might only be valid TOML once it is merged with the other files, it cannot be scoped
+    // then. Errors are reported when the merged contents are deserialized.
+    let hosts = match toml::from_str::<FileScope>(&contents) {
+        Ok(FileScope { hosts: Some(hosts) }) => hosts,
+        _ => return Ok(Some(contents)),
+    };
+
+    let patterns = match hosts.get_ref() {
+        toml::Value::String(pattern) => vec![pattern.as_str()],
+        toml::Value::Array(patterns) => patterns
+            .iter()
+            .map(|pattern| {
+                pattern.as_str().ok_or_else(|| {
+                    format_err!(
+                        "`hosts` of {} has to be a string or sequence of strings",
+                        file
+                    )
+                })
+            })
+            .collect::<Result<Vec<_>>>()?,
+        _ => bail!(
+            "`hosts` of {} has to be a string or sequence of strings",
+            file
+        ),
+    };
+    let mut applies = false;
+    for pattern in patterns {
+        let pattern = glob::Pattern::new(pattern)
+            .map_err(|e| format_err!("invalid host pattern `{}` in {}: {}", pattern, file, e))?;
+        applies |= pattern.matches(hostname);
+    }
+    if !applies {
+        return Ok(None);
+    }
+
+    // The key is on the same line as the start of its value.
+    let start = contents[..hosts.start()]
+        .rfind('\n')
+        .map_or(0, |index| index + 1);
+    let blank = contents[start..hosts.end()]
+        .chars()
+        .map(|c| if c == '\n' { '\n' } else { ' ' })
+        .collect::<String>();
+    contents.replace_range(start..hosts.end(), &blank);
+
+    Ok(Some(contents))
+}
+
+/// Locations of the rules within the configuration, deserialized alongside the configuration
+/// itself.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct RuleLocations {
+    container_to_container: SectionLocations,
+    container_to_wider_world: SectionLocations,
+    container_to_host: SectionLocations,
+    wider_world_to_container: SectionLocations,
+    container_dnat: SectionLocations,
+    rules: Vec<RuleLocation>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default)]
+struct SectionLocations {
+    rules: Vec<RuleLocation>,
+}
+
+/// Byte offset of the first key of a rule, if the deserializer reported one, and the section
+/// named by the rule, if it is part of the top-level array of rules.
+///
+/// TOML doesn't provide the position of tables themselves, only of their keys.
+struct RuleLocation {
+    start: Option<usize>,
+    section: Option<String>,
+}
+
+impl<'de> Deserialize<'de> for RuleLocation {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<RuleLocation, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct RuleLocationVisitor;
+
+        impl<'de> Visitor<'de> for RuleLocationVisitor {
+            type Value = RuleLocation;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("table")
+            }
+
+            fn visit_map<M>(self, mut map: M) -> std::result::Result<RuleLocation, M::Error>
+            where
+                M: MapAccess<'de>,
+            {
+                let mut start: Option<usize> = None;
+                let mut section = None;
+                while let Some(key) = map.next_key::<Spanned<String>>()? {
+                    start = Some(start.map_or(key.start(), |start| start.min(key.start())));
+                    if key.get_ref() == RULE_SECTION_KEY {
+                        section = map.next_value::<toml::Value>()?.as_str().map(str::to_owned);
+                    } else {
+                        map.next_value::<IgnoredAny>()?;
+                    }
+                }
+                Ok(RuleLocation { start, section })
+            }
+        }
+
+        deserializer.deserialize_map(RuleLocationVisitor)
+    }
+}
+
+/// Render the configuration as canonical TOML.
+///
+/// The configuration is rendered as DFW understands it after loading, i.e. with all files of a
+/// configuration path merged and all defaults applied, but before any references to Docker are
+/// resolved. Values that were given in a shorthand-form, e.g. a port string, are rendered in
+/// their struct-form, optional values that were left out are omitted. Loading the rendered
+/// configuration results in the same configuration again.
+///
+/// Keys are sorted alphabetically within each table, so the renderings of two equivalent
+/// configurations can be compared directly.
+pub fn normalized_config(dfw: &DFW) -> Result<String> {
+    let value = toml::Value::try_from(dfw)?;
+    Ok(toml::to_string(&value)?)
+}
+
+/// Check if the version declared by the configuration is supported.
+///
+/// Configurations declaring a newer version than [`CONFIG_VERSION`] are rejected. If the
+/// configuration declares an older version, a note on migrating the configuration is returned.
+/// Configurations that don't declare a version are assumed to match the latest version.
+///
+/// [`CONFIG_VERSION`]: ../types/constant.CONFIG_VERSION.html
+pub fn check_config_version(dfw: &DFW) -> Result<Option<String>> {
+    match dfw.version {
+        Some(version) if version > CONFIG_VERSION => bail!(
+            "configuration version {} is not supported, the latest supported version is {}",
+            version,
+            CONFIG_VERSION
+        ),
+        Some(version) if version < CONFIG_VERSION => Ok(Some(format!(
+            "configuration version {} is outdated, consider migrating it to version {}",
+            version, CONFIG_VERSION
+        ))),
+        _ => Ok(None),
+    }
+}
+
+/// List all host ports DFW will open through the `wider_world_to_container` section.
+///
+/// Every entry consists of the host port, its family and the external network interface the
+/// port is restricted to. Exposed port ranges result in one entry per port of the range. The
+/// interfaces are either the ones specified on the rule, resulting in one entry per interface,
+/// or, if none are given, the primary (i.e. first) external network interface from the
+/// `defaults` section. If the rule sets the interface to `none`, the port is opened on all
+/// interfaces and listed without interface. Rules without interface are not listed if the
+/// `defaults` section doesn't define one either, DFW opens no port for them.
+///
+/// This only inspects the configuration, neither Docker nor the host are queried. Rules are
+/// listed independent of their `when` condition, ports published through Docker (`expose_port =
+/// "published"`), ports declared by the image of the container (`expose_port = "exposed"`) and
+/// ports only exposed to other containers (`interior_only`) are not listed.
+/// External network interfaces determined from the default routes of the host are thus unknown,
+/// the ports using them are listed without interface.
+pub fn exposed_host_ports(dfw: &DFW) -> Vec<(u16, PortFamily, Option<String>)> {
+    let auto_external_network_interfaces = dfw
+        .defaults
+        .as_ref()
+        .is_some_and(|defaults| defaults.auto_external_network_interfaces());
+    let primary_external_network_interface = dfw
+        .defaults
+        .as_ref()
+        .filter(|_| !auto_external_network_interfaces)
+        .and_then(|defaults| defaults.external_network_interfaces.as_ref())
+        .and_then(|interfaces| {
+            interfaces
+                .primary(AddressFamily::V4)
+                .or_else(|| interfaces.primary(AddressFamily::V6))
+        });
+
+    dfw.wider_world_to_container
+        .iter()
+        .flat_map(|wwtc| wwtc.rules.iter().flatten())
+        .filter(|rule| !rule.interior_only)
+        .flat_map(|rule| {
+            let external_network_interfaces: Vec<Option<String>> =
+                match ExternalNetworkInterfaces::of(&rule.external_network_interface) {
+                    ExternalNetworkInterfaces::Explicit(external_network_interfaces) => {
+                        external_network_interfaces
+                            .iter()
+                            .cloned()
+                            .map(Some)
+                            .collect()
+                    }
+                    ExternalNetworkInterfaces::Inherit if auto_external_network_interfaces => {
+                        vec![None]
+                    }
+                    ExternalNetworkInterfaces::Inherit => primary_external_network_interface
+                        .cloned()
+                        .map(Some)
+                        .into_iter()
+                        .collect(),
+                    ExternalNetworkInterfaces::All => vec![None],
+                };
+            rule.expose_port
+                .iter()
+                .filter(|expose_port| !expose_port.published && !expose_port.exposed)
+                .flat_map(move |expose_port| {
+                    let host_ports = match expose_port.host_port_range {
+                        Some(host_port_range) => host_port_range.start..=host_port_range.end,
+                        None => expose_port.host_port..=expose_port.host_port,
+                    };
+                    let external_network_interfaces = external_network_interfaces.clone();
+                    host_ports.flat_map(move |host_port| {
+                        external_network_interfaces.clone().into_iter().map(
+                            move |external_network_interface| {
+                                (
+                                    host_port,
+                                    expose_port.family.clone(),
+                                    external_network_interface,
+                                )
+                            },
+                        )
+                    })
+                })
+        })
+        .collect()
 }
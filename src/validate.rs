@@ -0,0 +1,945 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+//! Validation of the configuration, see [`validate`](fn.validate.html) and [`lint`](fn.lint.html).
+
+use crate::errors::*;
+use crate::nftables::RuleVerdict;
+use crate::types::{
+    section_order, AddressFamily, Condition, ContainerDNATRule, ContainerSelector,
+    ContainerToContainerRule, ExternalNetworkInterfaces, StatefulVerdict,
+    AUTO_EXTERNAL_NETWORK_INTERFACES, CIDR_DNS_PREFIX, CIDR_FILE_PREFIX, DEFAULT_LOG_RATE, DFW,
+    NO_EXTERNAL_NETWORK_INTERFACE, WILDCARD_NETWORK,
+};
+use crate::util::{ct_timeout_policy, load_cidr_file, size_in_bytes};
+use failure::bail;
+use serde::Serialize;
+use std::fmt;
+use std::net::IpAddr;
+
+/// Severity of a [`Diagnostic`](struct.Diagnostic.html).
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Severity {
+    /// The configuration is rejected, see [`validate`](fn.validate.html).
+    Error,
+    /// The configuration is accepted, but likely doesn't do what was intended, see
+    /// [`lint`](fn.lint.html).
+    Warning,
+}
+
+/// Problem found in the configuration by [`validate`] or [`lint`].
+///
+/// [`validate`]: fn.validate.html
+/// [`lint`]: fn.lint.html
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Diagnostic {
+    /// Severity of the problem.
+    pub severity: Severity,
+
+    /// Section of the configuration the problem was found in.
+    pub section: String,
+
+    /// Number of the rule within the section the problem was found in, starting at 1. This is
+    /// `None` if the problem doesn't concern a specific rule.
+    pub rule: Option<usize>,
+
+    /// Human readable description of the problem.
+    pub message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+/// Validate the configuration, catching mistakes that would otherwise only surface once the rules
+/// are applied.
+///
+/// Currently this checks the `matches` strings of all rules, see [`check_matches`], the name of the
+/// network namespace to apply the rules in, the concurrency of requests to Docker, the rate packets
+/// are logged at, the overrides of the base chains, the order of the sections (see
+/// [`section_order`]), container-to-container rules bridging networks or selecting containers both
+/// by name and by tags, the files source CIDRs are read from, that hostnames referenced by source
+/// CIDRs are named, that rules bypassing connection
+/// tracking don't rely on it, that rules don't pin a destination address and a DNAT target at once,
+/// that rules exposing ports only to other containers list these containers, that rules
+/// restricted to families name at least one and that the `log` pseudo-verdict neither depends on
+/// the conntrack state nor serves as default policy. The first problem found is returned as error,
+/// see [`diagnostics`] to retrieve all of them.
+///
+/// [`check_matches`]: fn.check_matches.html
+/// [`diagnostics`]: fn.diagnostics.html
+/// [`section_order`]: ../types/fn.section_order.html
+pub fn validate(dfw: &DFW) -> Result<()> {
+    match validation_errors(dfw).into_iter().next() {
+        Some(error) => bail!("{}", error),
+        None => Ok(()),
+    }
+}
+
+/// Get all problems found in the configuration, i.e. the errors found by [`validate`] followed by
+/// the warnings found by [`lint`].
+///
+/// [`validate`]: fn.validate.html
+/// [`lint`]: fn.lint.html
+pub fn diagnostics(dfw: &DFW) -> Vec<Diagnostic> {
+    let mut diagnostics = validation_errors(dfw);
+    diagnostics.append(&mut lint_warnings(dfw));
+    diagnostics
+}
+
+/// Render all problems found in the configuration as JSON, see [`diagnostics`].
+///
+/// The result is an array of objects with the fields `severity` (`error` or `warning`),
+/// `section`, `rule` and `message`, e.g. for consumption by a CI job.
+///
+/// [`diagnostics`]: fn.diagnostics.html
+pub fn diagnostics_json(dfw: &DFW) -> Result<String> {
+    Ok(serde_json::to_string(&diagnostics(dfw))?)
+}
+
+fn validation_errors(dfw: &DFW) -> Vec<Diagnostic> {
+    let mut errors = defaults_errors(dfw);
+    errors.append(&mut container_to_container_errors(dfw));
+    errors.append(&mut container_to_wider_world_errors(dfw));
+    errors.append(&mut container_to_host_errors(dfw));
+    errors.append(&mut wider_world_to_container_errors(dfw));
+    errors
+}
+
+/// Create an error concerning a section, or one of its rules, of the configuration.
+fn error(section: &str, rule: Option<usize>, message: String) -> Diagnostic {
+    Diagnostic {
+        severity: Severity::Error,
+        section: section.to_owned(),
+        rule,
+        message,
+    }
+}
+
+/// Get the errors of the `defaults` section.
+fn defaults_errors(dfw: &DFW) -> Vec<Diagnostic> {
+    let mut errors = Vec::new();
+    let defaults = match &dfw.defaults {
+        Some(defaults) => defaults,
+        None => return errors,
+    };
+
+    if let Some(netns) = defaults.netns.as_ref() {
+        if netns.is_empty() || netns.contains('/') {
+            errors.push(error(
+                "defaults",
+                None,
+                format!(
+                    "network namespace '{}' is not a valid namespace name",
+                    netns
+                ),
+            ));
+        }
+    }
+    if defaults.docker_concurrency == 0 {
+        errors.push(error(
+            "defaults",
+            None,
+            "Docker concurrency has to be at least 1".to_owned(),
+        ));
+    }
+    if !is_rate(&defaults.log_rate) {
+        errors.push(error(
+            "defaults",
+            None,
+            format!(
+                "log rate '{}' is not a valid rate, e.g. '{}'",
+                defaults.log_rate, DEFAULT_LOG_RATE
+            ),
+        ));
+    }
+    let forward_policy = defaults
+        .base_chains
+        .forward
+        .and_then(|base_chain| base_chain.policy);
+    if forward_policy.is_some() && dfw.container_to_container.is_some() {
+        errors.push(error(
+            "defaults",
+            None,
+            "the policy of the forward chain is set by the default policy of the section \
+             `container_to_container`, it cannot be overridden through `base_chains`"
+                .to_owned(),
+        ));
+    }
+    if defaults.network_chains && defaults.external_interface_chains {
+        errors.push(error(
+            "defaults",
+            None,
+            "`network_chains` and `external_interface_chains` cannot be combined".to_owned(),
+        ));
+    }
+    if let Some(external_network_interfaces) = defaults
+        .external_network_interfaces
+        .as_ref()
+        .filter(|interfaces| interfaces.is_split())
+    {
+        if external_network_interfaces
+            .all()
+            .iter()
+            .any(|interface| interface == AUTO_EXTERNAL_NETWORK_INTERFACES)
+        {
+            errors.push(error(
+                "defaults",
+                None,
+                format!(
+                    "external network interfaces listed per address family cannot be \
+                     determined automatically through '{}'",
+                    AUTO_EXTERNAL_NETWORK_INTERFACES
+                ),
+            ));
+        }
+    }
+    if let Err(problem) = section_order(dfw) {
+        errors.push(error("defaults", None, problem));
+    }
+    for (name, policy) in defaults.ct_timeouts.iter().flatten() {
+        if let Err(problem) = ct_timeout_policy(name, policy) {
+            errors.push(error("defaults", None, problem));
+        }
+    }
+
+    errors
+}
+
+/// Get the errors of the `container_to_container` section and its rules.
+fn container_to_container_errors(dfw: &DFW) -> Vec<Diagnostic> {
+    let section = "container_to_container";
+    let mut errors = Vec::new();
+    let rules = dfw
+        .container_to_container
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten());
+    for (index, rule) in rules.enumerate() {
+        let selections = [
+            ("source", &rule.src_container, &rule.src_tags),
+            ("destination", &rule.dst_container, &rule.dst_tags),
+        ];
+        for (end, container, tags) in &selections {
+            if container.is_some() && tags.is_some() {
+                errors.push(error(
+                    section,
+                    Some(index + 1),
+                    format!(
+                        "rule {} of section `{}` cannot select the {} container by name and by \
+                         tags at once",
+                        index + 1,
+                        section,
+                        end
+                    ),
+                ));
+            }
+        }
+        if let Err(problem) = bridging_rule(rule) {
+            errors.push(error(
+                section,
+                Some(index + 1),
+                format!(
+                    "rule {} of section `{}` is invalid: {}",
+                    index + 1,
+                    section,
+                    problem
+                ),
+            ));
+        }
+        errors.extend(matches_error(section, index, rule.matches.as_deref()));
+        errors.extend(min_ct_bytes_error(
+            section,
+            index,
+            rule.min_ct_bytes.as_deref(),
+        ));
+        errors.extend(families_error(section, index, rule.families.as_deref()));
+        errors.extend(verdict_error(section, index, rule.verdict));
+    }
+
+    errors
+}
+
+/// Get the errors of the `container_to_wider_world` section and its rules.
+fn container_to_wider_world_errors(dfw: &DFW) -> Vec<Diagnostic> {
+    let section = "container_to_wider_world";
+    let mut errors = Vec::new();
+    let rules = dfw
+        .container_to_wider_world
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten());
+    for (index, rule) in rules.enumerate() {
+        errors.extend(external_network_interface_error(
+            section,
+            index,
+            rule.external_network_interface.as_deref(),
+        ));
+        errors.extend(matches_error(section, index, rule.matches.as_deref()));
+        errors.extend(min_ct_bytes_error(
+            section,
+            index,
+            rule.min_ct_bytes.as_deref(),
+        ));
+        errors.extend(families_error(section, index, rule.families.as_deref()));
+        errors.extend(verdict_error(section, index, rule.verdict));
+    }
+
+    if let Some(container_to_wider_world) = &dfw.container_to_wider_world {
+        let default_policy = &container_to_wider_world.default_policy;
+        if !default_policy.v4.is_terminal() || !default_policy.v6.is_terminal() {
+            errors.push(default_policy_error(section));
+        }
+    }
+
+    errors
+}
+
+/// Get the errors of the `container_to_host` section and its rules.
+fn container_to_host_errors(dfw: &DFW) -> Vec<Diagnostic> {
+    let section = "container_to_host";
+    let mut errors = Vec::new();
+    let rules = dfw
+        .container_to_host
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten());
+    for (index, rule) in rules.enumerate() {
+        errors.extend(matches_error(section, index, rule.matches.as_deref()));
+        errors.extend(min_ct_bytes_error(
+            section,
+            index,
+            rule.min_ct_bytes.as_deref(),
+        ));
+        errors.extend(families_error(section, index, rule.families.as_deref()));
+        errors.extend(verdict_error(section, index, rule.verdict));
+    }
+
+    if let Some(container_to_host) = &dfw.container_to_host {
+        if !container_to_host.default_policy.is_terminal() {
+            errors.push(default_policy_error(section));
+        }
+    }
+
+    errors
+}
+
+/// Get the errors of the rules of the `wider_world_to_container` section.
+fn wider_world_to_container_errors(dfw: &DFW) -> Vec<Diagnostic> {
+    let section = "wider_world_to_container";
+    let mut errors = Vec::new();
+    let ct_timeouts = dfw.defaults.as_ref().and_then(|d| d.ct_timeouts.as_ref());
+    let rules = dfw
+        .wider_world_to_container
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten());
+    for (index, rule) in rules.enumerate() {
+        errors.extend(external_network_interface_error(
+            section,
+            index,
+            rule.external_network_interface.as_deref(),
+        ));
+        let mut rule_error = |problem: String| {
+            errors.push(error(
+                section,
+                Some(index + 1),
+                format!("rule {} of section `{}` {}", index + 1, section, problem),
+            ))
+        };
+
+        for file in rule
+            .source_cidr_v4
+            .iter()
+            .flatten()
+            .filter_map(|source_cidr| source_cidr.strip_prefix(CIDR_FILE_PREFIX))
+        {
+            if let Err(problem) = load_cidr_file(file) {
+                rule_error(format!("has invalid source CIDRs: {}", problem));
+            }
+        }
+        if rule
+            .source_cidr_v4
+            .iter()
+            .chain(rule.source_cidr_v6.iter())
+            .flatten()
+            .filter_map(|source_cidr| source_cidr.strip_prefix(CIDR_DNS_PREFIX))
+            .any(|hostname| hostname.trim().is_empty())
+        {
+            rule_error(format!(
+                "references a hostname through `{}` without naming it",
+                CIDR_DNS_PREFIX
+            ));
+        }
+
+        if let (Some(dst_ip), Some(dnat_to)) = (&rule.dst_ip, &rule.dnat_to) {
+            rule_error(format!(
+                "cannot pin the destination address {} and DNAT to {} at once",
+                dst_ip, dnat_to
+            ));
+        }
+        if let Some(host_ip) = rule.host_ip.filter(|host_ip| {
+            rule.families
+                .as_ref()
+                .is_some_and(|families| !families.contains(&AddressFamily::of(*host_ip)))
+        }) {
+            rule_error(format!(
+                "has the host address {}, which is not of the families of the rule",
+                host_ip
+            ));
+        }
+        if let (Some(host_ip @ IpAddr::V6(_)), Some(dnat_to)) = (rule.host_ip, &rule.dnat_to) {
+            rule_error(format!(
+                "has the IPv6 host address {}, which cannot be combined with the DNAT target {}",
+                host_ip, dnat_to
+            ));
+        }
+
+        if let Some(quota) = rule
+            .quota
+            .as_ref()
+            .filter(|quota| !is_quota_size(&quota.bytes))
+        {
+            rule_error(format!(
+                "has the quota '{}', which is not a valid size, e.g. '10 gbytes'",
+                quota.bytes
+            ));
+        }
+
+        let interior_problem = if !rule.interior_only {
+            Some("lists containers in `from_containers`, which requires `interior_only = true`")
+                .filter(|_| !rule.from_containers.is_empty())
+        } else if rule.from_containers.is_empty() {
+            Some("is interior only and thus requires the containers in `from_containers`")
+        } else if rule.external_network_interface.is_some()
+            || rule.source_cidr_v4.is_some()
+            || rule.source_cidr_v6.is_some()
+            || rule.dnat_to.is_some()
+            || rule.notrack
+            || rule.quota.is_some()
+            || rule.ct_timeout.is_some()
+            || rule.clamp_mss_to_pmtu
+        {
+            Some(
+                "is interior only and thus cannot use `external_network_interface`, \
+                 `source_cidr`, `dnat_to`, `notrack`, `quota`, `ct_timeout` or \
+                 `clamp_mss_to_pmtu`",
+            )
+        } else {
+            None
+        };
+        if let Some(problem) = interior_problem {
+            rule_error(problem.to_owned());
+        }
+
+        if let Some(ct_timeout) = rule.ct_timeout.as_ref().filter(|ct_timeout| {
+            !ct_timeouts.is_some_and(|ct_timeouts| ct_timeouts.contains_key(*ct_timeout))
+        }) {
+            rule_error(format!(
+                "references the undefined conntrack timeout policy `{}`",
+                ct_timeout
+            ));
+        }
+
+        if rule.notrack {
+            let dnat_port = rule
+                .expose_port
+                .iter()
+                .find(|expose_port| expose_port.dnat)
+                .map(|expose_port| {
+                    if expose_port.published {
+                        "the published ports".to_owned()
+                    } else if expose_port.exposed {
+                        "the port exposed by the image".to_owned()
+                    } else if let Some(host_port_range) = expose_port.host_port_range {
+                        format!("exposed ports {}", host_port_range)
+                    } else {
+                        format!("exposed port {}", expose_port.host_port)
+                    }
+                });
+            let problem = if rule.drain {
+                Some("cannot be drained".to_owned())
+            } else if rule.ct_timeout.is_some() {
+                Some("cannot use a conntrack timeout policy".to_owned())
+            } else if let Some(dnat_to) = &rule.dnat_to {
+                Some(format!("cannot use the DNAT target {}", dnat_to))
+            } else {
+                dnat_port.map(|dnat_port| format!("cannot DNAT {}", dnat_port))
+            };
+            if let Some(problem) = problem {
+                rule_error(format!("bypasses connection tracking and thus {}", problem));
+            }
+        }
+
+        errors.extend(families_error(section, index, rule.families.as_deref()));
+    }
+
+    errors
+}
+
+/// Check that the `none` external network interface of a rule isn't combined with other
+/// interfaces.
+fn external_network_interface_error(
+    section: &str,
+    index: usize,
+    external_network_interface: Option<&[String]>,
+) -> Option<Diagnostic> {
+    let interfaces = external_network_interface.unwrap_or_default();
+    if interfaces.len() > 1
+        && interfaces
+            .iter()
+            .any(|interface| interface == NO_EXTERNAL_NETWORK_INTERFACE)
+    {
+        Some(error(
+            section,
+            Some(index + 1),
+            format!(
+                "rule {} of section `{}` cannot combine the external network interface `{}` \
+                 with other interfaces",
+                index + 1,
+                section,
+                NO_EXTERNAL_NETWORK_INTERFACE
+            ),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Check the `matches` string of a rule, see [`check_matches`](fn.check_matches.html).
+fn matches_error(section: &str, index: usize, matches: Option<&str>) -> Option<Diagnostic> {
+    let matches = matches?;
+    check_matches(matches).err().map(|problem| {
+        error(
+            section,
+            Some(index + 1),
+            format!(
+                "rule {} of section `{}` has invalid matches '{}': {}",
+                index + 1,
+                section,
+                matches,
+                problem
+            ),
+        )
+    })
+}
+
+/// Check that the minimum connection size of a rule is a valid size.
+fn min_ct_bytes_error(
+    section: &str,
+    index: usize,
+    min_ct_bytes: Option<&str>,
+) -> Option<Diagnostic> {
+    min_ct_bytes
+        .filter(|size| size_in_bytes(size).is_none())
+        .map(|min_ct_bytes| {
+            error(
+                section,
+                Some(index + 1),
+                format!(
+                    "rule {} of section `{}` has the minimum connection size '{}', which is not a \
+                     valid size, e.g. '100 mbytes'",
+                    index + 1,
+                    section,
+                    min_ct_bytes
+                ),
+            )
+        })
+}
+
+/// Check that a rule restricted to families names at least one.
+fn families_error(
+    section: &str,
+    index: usize,
+    families: Option<&[AddressFamily]>,
+) -> Option<Diagnostic> {
+    families.filter(|families| families.is_empty()).map(|_| {
+        error(
+            section,
+            Some(index + 1),
+            format!(
+                "rule {} of section `{}` has no families, omit `families` to apply it to both",
+                index + 1,
+                section
+            ),
+        )
+    })
+}
+
+/// Check that the `log` pseudo-verdict of a rule doesn't depend on the conntrack state.
+fn verdict_error(section: &str, index: usize, verdict: StatefulVerdict) -> Option<Diagnostic> {
+    if verdict.is_stateful() && !verdict.is_terminal() {
+        Some(error(
+            section,
+            Some(index + 1),
+            format!(
+                "rule {} of section `{}` uses the `log` pseudo-verdict, which cannot depend on \
+                 the conntrack state of the connection",
+                index + 1,
+                section
+            ),
+        ))
+    } else {
+        None
+    }
+}
+
+/// Create the error of a default policy that doesn't decide on the packets.
+fn default_policy_error(section: &str) -> Diagnostic {
+    error(
+        section,
+        None,
+        format!(
+            "the default policy of section `{}` has to decide on the packets, it cannot be the \
+             `log` pseudo-verdict",
+            section
+        ),
+    )
+}
+
+/// Get the `container_dnat` rule bridging the networks of a container-to-container rule whose
+/// destination container is attached to a different network than the source container.
+///
+/// Returns `None` if the rule applies within a single network, or if it selects its destination
+/// containers by tags. The rules the tags expand to are bridged individually.
+pub(crate) fn bridging_rule(
+    rule: &ContainerToContainerRule,
+) -> std::result::Result<Option<ContainerDNATRule>, String> {
+    let dst_network = match &rule.dst_network {
+        Some(dst_network) if *dst_network != rule.network => dst_network,
+        _ => {
+            if !rule.expose_port.is_empty() {
+                return Err(
+                    "exposed ports require the destination network to differ from the network"
+                        .to_owned(),
+                );
+            }
+            return Ok(None);
+        }
+    };
+
+    if rule.network == WILDCARD_NETWORK || *dst_network == WILDCARD_NETWORK {
+        return Err("bridging networks does not support the wildcard network".to_owned());
+    }
+    if rule.dst_container.is_none() && rule.dst_tags.is_some() {
+        return Ok(None);
+    }
+    let dst_container = rule
+        .dst_container
+        .clone()
+        .ok_or_else(|| "bridging networks requires the destination container".to_owned())?;
+    if rule.expose_port.is_empty() {
+        return Err("bridging networks requires the exposed ports".to_owned());
+    }
+    if rule.verdict != StatefulVerdict::from(RuleVerdict::Accept) {
+        return Err("bridging networks requires the verdict to be `accept`".to_owned());
+    }
+    if rule.matches.is_some()
+        || rule.min_ct_bytes.is_some()
+        || rule.mirror_to.is_some()
+        || rule.dscp.is_some()
+    {
+        return Err(
+            "bridging networks cannot be combined with `matches`, `min_ct_bytes`, \
+             `mirror_to` or `dscp`"
+                .to_owned(),
+        );
+    }
+
+    Ok(Some(ContainerDNATRule {
+        src_network: Some(rule.network.clone()),
+        src_container: rule.src_container.clone(),
+        dst_network: dst_network.clone(),
+        dst_container,
+        expose_port: rule.expose_port.clone(),
+        when: rule.when.clone(),
+        provenance: rule.provenance.clone(),
+    }))
+}
+
+/// Check a `matches` string of a rule for common mistakes.
+///
+/// This doesn't parse the nftables syntax, it only catches frequent errors: unbalanced braces,
+/// brackets, parentheses or quotes, `iptables`-style options, and verdicts, comments or command
+/// separators that conflict with the rest of the generated rule.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::validate::check_matches;
+/// assert!(check_matches("tcp dport { 80, 443 }").is_ok());
+/// assert!(check_matches("-p tcp --dport 80").is_err());
+/// ```
+pub fn check_matches(matches: &str) -> std::result::Result<(), String> {
+    if matches.trim().is_empty() {
+        return Err("matches must not be empty".to_owned());
+    }
+
+    let mut open = Vec::new();
+    let mut quoted = false;
+    for c in matches.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            _ if quoted => {}
+            '{' | '[' | '(' => open.push(c),
+            '}' | ']' | ')' => {
+                let expected = match c {
+                    '}' => '{',
+                    ']' => '[',
+                    _ => '(',
+                };
+                if open.pop() != Some(expected) {
+                    return Err(format!("unbalanced `{}`", c));
+                }
+            }
+            ';' => return Err("`;` would terminate the rule".to_owned()),
+            _ => {}
+        }
+    }
+    if quoted {
+        return Err("unterminated quote".to_owned());
+    }
+    if let Some(c) = open.pop() {
+        return Err(format!("unbalanced `{}`", c));
+    }
+
+    for token in matches.split_whitespace() {
+        let is_option = token.starts_with("--")
+            || (token.len() == 2
+                && token.starts_with('-')
+                && token.chars().nth(1).is_some_and(char::is_alphabetic));
+        if is_option {
+            return Err(format!(
+                "`{}` looks like an iptables option, use the nftables syntax instead",
+                token
+            ));
+        }
+        match token.to_lowercase().as_str() {
+            "accept" | "drop" | "reject" | "jump" | "goto" | "return" => {
+                return Err(format!(
+                    "verdict `{}` conflicts with the verdict of the rule",
+                    token
+                ))
+            }
+            "comment" => return Err("DFW already sets the comment of the rule".to_owned()),
+            _ => {}
+        }
+    }
+
+    Ok(())
+}
+
+/// Find rules that can never match because an earlier rule of the same section shadows them.
+///
+/// A rule is shadowed if an earlier rule applies to the same or a broader network, the same or
+/// broader containers and either has no `matches` or the same ones. Since all verdicts but the
+/// `log` pseudo-verdict are terminal, the later rule is never reached. Only these obvious cases
+/// are detected, `matches` strings are compared literally and rules with a `when` condition only
+/// shadow rules with the same condition.
+///
+/// Additionally, rules of the `wider_world_to_container` section exposing ports to everyone, i.e.
+/// restricting neither the source CIDRs nor the external network interfaces, are reported unless
+/// they acknowledge it through
+/// [`allow_public`](../types/struct.WiderWorldToContainerRule.html#structfield.allow_public).
+///
+/// Every reported rule results in one message, the configuration is not rejected.
+///
+/// # Example
+///
+/// ```
+/// # use dfw::types::DFW;
+/// # use dfw::validate::lint;
+/// let dfw: DFW = toml::from_str(r#"
+///     [container_to_host]
+///     default_policy = "drop"
+///
+///     [[container_to_host.rules]]
+///     network = "backend"
+///     verdict = "accept"
+///
+///     [[container_to_host.rules]]
+///     network = "backend"
+///     src_container = "app"
+///     matches = "tcp dport 22"
+///     verdict = "reject"
+/// "#).unwrap();
+///
+/// assert_eq!(
+///     vec!["rule 2 of section `container_to_host` can never match, it is shadowed by rule 1"],
+///     lint(&dfw)
+/// );
+/// ```
+pub fn lint(dfw: &DFW) -> Vec<String> {
+    lint_warnings(dfw)
+        .into_iter()
+        .map(|warning| warning.message)
+        .collect()
+}
+
+fn lint_warnings(dfw: &DFW) -> Vec<Diagnostic> {
+    let container_to_container = dfw
+        .container_to_container
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten())
+        .map(|rule| RuleScope {
+            network: Some(&rule.network),
+            containers: vec![rule.src_container.as_ref(), rule.dst_container.as_ref()],
+            matches: rule.matches.as_deref(),
+            min_ct_bytes: rule.min_ct_bytes.as_deref(),
+            external_network_interface: None,
+            when: rule.when.as_ref(),
+            terminal: rule.verdict.is_terminal(),
+        });
+    let container_to_wider_world = dfw
+        .container_to_wider_world
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten())
+        .map(|rule| RuleScope {
+            network: rule.network.as_deref(),
+            containers: vec![rule.src_container.as_ref()],
+            matches: rule.matches.as_deref(),
+            min_ct_bytes: rule.min_ct_bytes.as_deref(),
+            external_network_interface: rule.external_network_interface.as_ref(),
+            when: rule.when.as_ref(),
+            terminal: rule.verdict.is_terminal(),
+        });
+    let container_to_host = dfw
+        .container_to_host
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten())
+        .map(|rule| RuleScope {
+            network: Some(&rule.network),
+            containers: vec![rule.src_container.as_ref()],
+            matches: rule.matches.as_deref(),
+            min_ct_bytes: rule.min_ct_bytes.as_deref(),
+            external_network_interface: None,
+            when: rule.when.as_ref(),
+            terminal: rule.verdict.is_terminal(),
+        });
+
+    let mut warnings = Vec::new();
+    for (section, scopes) in &[
+        (
+            "container_to_container",
+            container_to_container.collect::<Vec<_>>(),
+        ),
+        (
+            "container_to_wider_world",
+            container_to_wider_world.collect(),
+        ),
+        ("container_to_host", container_to_host.collect()),
+    ] {
+        for (index, scope) in scopes.iter().enumerate() {
+            if let Some(shadowing) = scopes[..index]
+                .iter()
+                .position(|earlier| earlier.terminal && earlier.covers(scope))
+            {
+                warnings.push(Diagnostic {
+                    severity: Severity::Warning,
+                    section: (*section).to_owned(),
+                    rule: Some(index + 1),
+                    message: format!(
+                        "rule {} of section `{}` can never match, it is shadowed by rule {}",
+                        index + 1,
+                        section,
+                        shadowing + 1
+                    ),
+                });
+            }
+        }
+    }
+
+    let wider_world_to_container = dfw
+        .wider_world_to_container
+        .iter()
+        .flat_map(|section| section.rules.iter().flatten());
+    for (index, rule) in wider_world_to_container.enumerate() {
+        let restricted = rule.source_cidr_v4.is_some()
+            || rule.source_cidr_v6.is_some()
+            || match ExternalNetworkInterfaces::of(&rule.external_network_interface) {
+                ExternalNetworkInterfaces::Explicit(_) => true,
+                ExternalNetworkInterfaces::Inherit | ExternalNetworkInterfaces::All => false,
+            };
+        if restricted || rule.allow_public || rule.interior_only || rule.expose_port.is_empty() {
+            continue;
+        }
+        warnings.push(Diagnostic {
+            severity: Severity::Warning,
+            section: "wider_world_to_container".to_owned(),
+            rule: Some(index + 1),
+            message: format!(
+                "rule {} of section `wider_world_to_container` exposes its ports to everyone, \
+                 restrict `source_cidr` or `external_network_interface` or acknowledge it through \
+                 `allow_public = true`",
+                index + 1
+            ),
+        });
+    }
+
+    warnings
+}
+
+/// Traffic a rule applies to, as far as it is relevant for detecting shadowed rules.
+struct RuleScope<'a> {
+    /// The network of the rule, `None` applying to all networks.
+    network: Option<&'a str>,
+    containers: Vec<Option<&'a ContainerSelector>>,
+    matches: Option<&'a str>,
+    min_ct_bytes: Option<&'a str>,
+    external_network_interface: Option<&'a Vec<String>>,
+    when: Option<&'a Condition>,
+    /// Whether the rule decides on the traffic, i.e. doesn't only log it.
+    terminal: bool,
+}
+
+impl<'a> RuleScope<'a> {
+    /// Check if this rule applies to all traffic the other rule applies to.
+    fn covers(&self, other: &RuleScope) -> bool {
+        let network = match (self.network, other.network) {
+            (None, _) => true,
+            (Some(network), Some(other_network)) => {
+                network == other_network
+                    || (network == WILDCARD_NETWORK && other_network != WILDCARD_NETWORK)
+            }
+            (Some(_), None) => false,
+        };
+        let containers =
+            self.containers
+                .iter()
+                .zip(&other.containers)
+                .all(|(container, other_container)| {
+                    container.is_none() || container == other_container
+                });
+        let matches = self.matches.is_none() || self.matches == other.matches;
+        let min_ct_bytes = self.min_ct_bytes.is_none() || self.min_ct_bytes == other.min_ct_bytes;
+        let when = self.when.is_none() || self.when == other.when;
+
+        network
+            && containers
+            && matches
+            && min_ct_bytes
+            && self.external_network_interface == other.external_network_interface
+            && when
+    }
+}
+
+/// Check if the rate is a valid nft rate in packets, e.g. `100/second`.
+fn is_rate(rate: &str) -> bool {
+    let mut parts = rate.splitn(2, '/');
+    let count_valid = parts
+        .next()
+        .is_some_and(|count| count.parse::<u64>().is_ok_and(|count| count > 0));
+    let unit_valid = parts
+        .next()
+        .is_some_and(|unit| ["second", "minute", "hour", "day", "week"].contains(&unit));
+    count_valid && unit_valid
+}
+
+/// Check if the size is a valid nft quota size, e.g. `10 gbytes`.
+fn is_quota_size(size: &str) -> bool {
+    size_in_bytes(size).is_some()
+}
@@ -25,6 +25,7 @@ use std::process::Command;
 
 static PROCESSING_OPTIONS: ProcessingOptions = ProcessingOptions {
     container_filter: ContainerFilter::Running,
+    sections: Sections::ALL,
 };
 
 fn logger() -> Logger {
@@ -0,0 +1,938 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+#![cfg(feature = "backend")]
+
+use dfw::errors::DFWError;
+use dfw::inventory::*;
+use dfw::nftables::ScriptRunner;
+use dfw::process::{
+    generate, generate_sections, run_once, HostFacts, Process, ProcessContext, Section, Sections,
+};
+use dfw::types::DFW;
+use dfw::util::load_file;
+use failure::Error;
+use slog::{o, Discard, Logger};
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::time::{Duration, Instant};
+
+const RESOURCES: &str = "resources/test/inventory";
+
+#[test]
+fn static_inventory_load_toml_and_json() {
+    let toml = StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap();
+    let json = StaticInventory::load(&format!("{}/inventory.json", RESOURCES)).unwrap();
+
+    assert_eq!(toml, json);
+    assert_eq!("edge-1", toml.host_facts().unwrap().hostname);
+}
+
+#[test]
+fn static_inventory_containers_and_networks() {
+    let inventory = StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap();
+
+    let containers = inventory.containers().unwrap();
+    let app = containers
+        .iter()
+        .find(|container| container.names == vec!["app"])
+        .unwrap();
+    // Containers without explicit ID are identified by their name
+    assert_eq!("app", app.id);
+    assert_eq!(Some(&"app".to_owned()), app.labels.get("com.example.role"));
+
+    let networks = inventory.networks().unwrap();
+    let backend = networks
+        .iter()
+        .find(|network| network.name == "backend")
+        .unwrap();
+    assert_eq!(2, backend.containers.len());
+    assert_eq!(
+        "172.19.0.2/16",
+        backend.containers["3f2e1d0c9b8a"].ipv4_address
+    );
+    assert_eq!("172.19.0.3/16", backend.containers["app"].ipv4_address);
+
+    let aliases = inventory.container_aliases().unwrap();
+    assert_eq!(vec!["app"], aliases.resolve("backend", "application"));
+    assert!(aliases.resolve("frontend", "application").is_empty());
+}
+
+#[test]
+fn static_inventory_undefined_network() {
+    let inventory: StaticInventory = toml::from_str(
+        r#"
+        [containers.web]
+        networks.unknown = { ipv4_address = "172.18.0.2/16" }
+        "#,
+    )
+    .unwrap();
+
+    let error = inventory.networks().unwrap_err();
+    assert_eq!(
+        "network `unknown` of container `web` is not defined",
+        error.to_string()
+    );
+}
+
+#[test]
+fn static_inventory_generate() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap();
+    let expected = fs::read_to_string(format!("{}/expected-nftables.txt", RESOURCES)).unwrap();
+
+    let commands = generate(&dfw, &inventory).unwrap().commands();
+
+    assert_eq!(expected.lines().collect::<Vec<_>>(), commands);
+}
+
+#[test]
+fn static_inventory_write_file() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap();
+    let expected = fs::read_to_string(format!("{}/expected-nftables.nft", RESOURCES)).unwrap();
+
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("dfw.nft");
+    let ruleset = generate(&dfw, &inventory).unwrap();
+    ruleset.write_file(&path).unwrap();
+
+    assert_eq!(expected, fs::read_to_string(&path).unwrap());
+    assert_eq!(expected, ruleset.render());
+    // Only the rendered file remains, the temporary file has been moved into place
+    assert_eq!(1, fs::read_dir(directory.path()).unwrap().count());
+}
+
+/// Inventory counting the queries against the wrapped inventory.
+#[derive(Default)]
+struct CountingInventory {
+    inventory: StaticInventory,
+    containers: Cell<usize>,
+    networks: Cell<usize>,
+    container_aliases: Cell<usize>,
+    host_facts: Cell<usize>,
+}
+
+impl CountingInventory {
+    fn load() -> CountingInventory {
+        CountingInventory {
+            inventory: StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn counts(&self) -> (usize, usize, usize, usize) {
+        (
+            self.containers.get(),
+            self.networks.get(),
+            self.container_aliases.get(),
+            self.host_facts.get(),
+        )
+    }
+}
+
+impl ContainerInventory for CountingInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        self.containers.set(self.containers.get() + 1);
+        self.inventory.containers()
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        self.networks.set(self.networks.get() + 1);
+        self.inventory.networks()
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases, Error> {
+        self.container_aliases.set(self.container_aliases.get() + 1);
+        self.inventory.container_aliases()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts, Error> {
+        self.host_facts.set(self.host_facts.get() + 1);
+        self.inventory.host_facts()
+    }
+}
+
+#[test]
+fn inventory_snapshot_queries_once() {
+    let inventory = CountingInventory::load();
+    let snapshot = InventorySnapshot::capture(Box::new(&inventory)).unwrap();
+    assert_eq!((1, 1, 0, 1), inventory.counts());
+
+    for _ in 0..3 {
+        assert_eq!(
+            inventory.inventory.containers().unwrap(),
+            snapshot.containers().unwrap()
+        );
+        assert_eq!(
+            inventory.inventory.networks().unwrap(),
+            snapshot.networks().unwrap()
+        );
+        snapshot.host_facts().unwrap();
+    }
+    assert_eq!((1, 1, 0, 1), inventory.counts());
+}
+
+#[test]
+fn generate_queries_inventory_once() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+
+    for sections in &[Sections::ALL, Section::ContainerToContainer.into()] {
+        let inventory = CountingInventory::load();
+        generate_sections(&dfw, &inventory, *sections).unwrap();
+
+        let (containers, networks, container_aliases, host_facts) = inventory.counts();
+        assert_eq!((1, 1, 1), (containers, networks, host_facts));
+        assert!(container_aliases <= 1);
+    }
+}
+
+/// Inventory reporting the network `backend` twice, with container `a` having a different address
+/// in each report and container `b` being reported identically twice.
+struct DuplicateEndpointInventory;
+
+impl ContainerInventory for DuplicateEndpointInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        Ok(["a", "b"]
+            .iter()
+            .map(|name| Container {
+                id: (*name).to_owned(),
+                names: vec![format!("/{}", name)],
+                ..Default::default()
+            })
+            .collect())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        let network = |a: &str| Network {
+            id: "0123456789abcdef".to_owned(),
+            name: "backend".to_owned(),
+            subnets: vec!["172.18.0.0/16".to_owned()],
+            containers: vec![
+                (
+                    "a".to_owned(),
+                    NetworkEndpoint {
+                        ipv4_address: a.to_owned(),
+                        ..Default::default()
+                    },
+                ),
+                (
+                    "b".to_owned(),
+                    NetworkEndpoint {
+                        ipv4_address: "172.18.0.3/16".to_owned(),
+                        ..Default::default()
+                    },
+                ),
+            ]
+            .into_iter()
+            .collect(),
+            ..Default::default()
+        };
+        Ok(vec![network("172.18.0.2/16"), network("172.18.0.5/16")])
+    }
+}
+
+#[test]
+fn inventory_snapshot_merges_duplicate_endpoints() {
+    let snapshot = InventorySnapshot::capture(Box::new(DuplicateEndpointInventory)).unwrap();
+    let networks = snapshot.networks().unwrap();
+
+    assert_eq!(1, networks.len());
+    assert_eq!(vec!["172.18.0.0/16"], networks[0].subnets);
+    assert_eq!(
+        vec!["172.18.0.2", "172.18.0.5"],
+        networks[0].containers["a"].ipv4_addresses()
+    );
+    assert_eq!(
+        vec!["172.18.0.3"],
+        networks[0].containers["b"].ipv4_addresses()
+    );
+}
+
+#[test]
+fn generate_multiple_addresses_on_network() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "a"
+        dst_container = "b"
+        verdict = "accept"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "b"
+        dst_container = "a"
+        verdict = "accept"
+
+        [[wider_world_to_container.rules]]
+        network = "backend"
+        dst_container = "a"
+        expose_port = 80
+        "#,
+    )
+    .unwrap();
+    let commands = generate(&dfw, &DuplicateEndpointInventory)
+        .map_err(|error| error.to_string())
+        .unwrap()
+        .commands();
+
+    // Rules matching on the container cover both of its addresses, in a single rule
+    let container_to_container = commands
+        .iter()
+        .filter(|command| command.contains("section;container_to_container"))
+        .filter(|command| command.starts_with("add rule"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward ip saddr { 172.18.0.2, 172.18.0.5 } ip daddr 172.18.0.3 \
+             meta iifname br-0123456789ab oifname br-0123456789ab meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;container_to_container\"",
+            "add rule inet dfw forward ip saddr 172.18.0.3 ip daddr { 172.18.0.2, 172.18.0.5 } \
+             meta iifname br-0123456789ab oifname br-0123456789ab meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;container_to_container\"",
+        ],
+        container_to_container
+    );
+
+    // Traffic from the wider world is translated to the primary address only
+    let dnat = commands
+        .iter()
+        .filter(|command| command.contains(" prerouting ") && command.contains(" dnat "))
+        .collect::<Vec<_>>();
+    assert_eq!(1, dnat.len());
+    assert!(dnat[0].contains("dnat 172.18.0.2:80"), "{}", dnat[0]);
+}
+
+#[test]
+fn health_status_from_docker_status() {
+    for (status, health) in &[
+        ("Up 5 minutes (healthy)", Some(HealthStatus::Healthy)),
+        ("Up 2 hours (unhealthy)", Some(HealthStatus::Unhealthy)),
+        (
+            "Up 3 seconds (health: starting)",
+            Some(HealthStatus::Starting),
+        ),
+        ("Up 5 minutes", None),
+        ("Exited (0) 3 minutes ago", None),
+        ("Up 1 minute (Paused)", None),
+    ] {
+        assert_eq!(
+            *health,
+            HealthStatus::from_docker_status(status),
+            "{}",
+            status
+        );
+    }
+}
+
+#[test]
+fn generate_published_ports_keep_protocol() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "dns"
+        expose_port = "published"
+        "#,
+    )
+    .unwrap();
+    let inventory: StaticInventory = toml::from_str(
+        r#"
+        [networks.frontend]
+        id = "6d4c1b5e9f0a8c3d2e1f0a9b"
+
+        [containers.dns]
+        networks.frontend = { ipv4_address = "172.18.0.2/16" }
+        published_ports = [
+            { host_port = 8080, container_port = 80 },
+            { host_port = 53, container_port = 53, protocol = "udp" },
+        ]
+        "#,
+    )
+    .unwrap();
+    let commands = generate(&dfw, &inventory)
+        .map_err(|error| error.to_string())
+        .unwrap()
+        .commands();
+
+    let dnat = commands
+        .iter()
+        .filter(|command| command.contains(" prerouting ") && command.contains(" dnat "))
+        .collect::<Vec<_>>();
+    assert_eq!(2, dnat.len());
+    assert!(
+        dnat[0].contains("tcp dport 8080") && dnat[0].contains("dnat 172.18.0.2:80"),
+        "{}",
+        dnat[0]
+    );
+    assert!(
+        dnat[1].contains("udp dport 53") && dnat[1].contains("dnat 172.18.0.2:53"),
+        "{}",
+        dnat[1]
+    );
+    assert!(!commands
+        .iter()
+        .any(|command| command.contains("tcp dport 53")));
+}
+
+fn generate_exposed_ports(expose_port: &str, exposed_ports: &str) -> Result<Vec<String>, String> {
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "app"
+        expose_port = {}
+        "#,
+        expose_port
+    ))
+    .unwrap();
+    let inventory: StaticInventory = toml::from_str(&format!(
+        r#"
+        [networks.frontend]
+        id = "6d4c1b5e9f0a8c3d2e1f0a9b"
+
+        [containers.app]
+        networks.frontend = {{ ipv4_address = "172.18.0.2/16" }}
+        exposed_ports = {}
+        "#,
+        exposed_ports
+    ))
+    .unwrap();
+
+    Ok(generate(&dfw, &inventory)
+        .map_err(|error| error.to_string())?
+        .commands()
+        .into_iter()
+        .filter(|command| command.contains(" prerouting ") && command.contains(" dnat "))
+        .collect())
+}
+
+#[test]
+fn generate_exposed_port_single() {
+    let dnat =
+        generate_exposed_ports(r#""exposed""#, r#"[{ port = 53, protocol = "udp" }]"#).unwrap();
+    assert_eq!(1, dnat.len());
+    assert!(
+        dnat[0].contains("udp dport 53") && dnat[0].contains("dnat 172.18.0.2:53"),
+        "{}",
+        dnat[0]
+    );
+
+    let dnat =
+        generate_exposed_ports("{ exposed = true, host_port = 8080 }", "[{ port = 80 }]").unwrap();
+    assert_eq!(1, dnat.len());
+    assert!(
+        dnat[0].contains("tcp dport 8080") && dnat[0].contains("dnat 172.18.0.2:80"),
+        "{}",
+        dnat[0]
+    );
+}
+
+#[test]
+fn generate_exposed_port_multiple() {
+    let exposed_ports = r#"[{ port = 80 }, { port = 443 }, { port = 443, protocol = "udp" }]"#;
+
+    let error = generate_exposed_ports(r#""exposed""#, exposed_ports).unwrap_err();
+    assert!(
+        error.contains(
+            "the image of container app exposes multiple ports (80/tcp, 443/tcp, 443/udp)"
+        ),
+        "{}",
+        error
+    );
+
+    let dnat =
+        generate_exposed_ports("{ exposed = true, container_port = 80 }", exposed_ports).unwrap();
+    assert_eq!(1, dnat.len());
+    assert!(
+        dnat[0].contains("tcp dport 80") && dnat[0].contains("dnat 172.18.0.2:80"),
+        "{}",
+        dnat[0]
+    );
+
+    // A port exposed for multiple protocols is exposed once per protocol
+    let dnat =
+        generate_exposed_ports("{ exposed = true, container_port = 443 }", exposed_ports).unwrap();
+    assert_eq!(2, dnat.len());
+    assert!(dnat[0].contains("tcp dport 443"), "{}", dnat[0]);
+    assert!(dnat[1].contains("udp dport 443"), "{}", dnat[1]);
+
+    let error = generate_exposed_ports("{ exposed = true, container_port = 8080 }", exposed_ports)
+        .unwrap_err();
+    assert!(
+        error.contains("the image of container app does not expose port 8080"),
+        "{}",
+        error
+    );
+}
+
+/// Inventory failing the first queries of the containers and networks, like a restarting Docker
+/// daemon.
+struct FlakyInventory {
+    inventory: CountingInventory,
+    failures: usize,
+}
+
+impl ContainerInventory for FlakyInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        let containers = self.inventory.containers()?;
+        if self.inventory.containers.get() <= self.failures {
+            failure::bail!("connection to the Docker daemon was refused");
+        }
+        Ok(containers)
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        let networks = self.inventory.networks()?;
+        if self.inventory.networks.get() <= self.failures {
+            failure::bail!("connection to the Docker daemon was refused");
+        }
+        Ok(networks)
+    }
+}
+
+#[test]
+fn retry_inventory_recovers_from_failures() {
+    let inventory = FlakyInventory {
+        inventory: CountingInventory::load(),
+        failures: 2,
+    };
+    let retry_inventory = RetryInventory::new(Box::new(&inventory), 3, Duration::from_millis(1));
+    let snapshot = InventorySnapshot::capture(Box::new(retry_inventory))
+        .map_err(|error| error.to_string())
+        .unwrap();
+
+    assert_eq!(
+        inventory.inventory.inventory.containers().unwrap(),
+        snapshot.containers().unwrap()
+    );
+    assert_eq!((3, 3, 0, 0), inventory.inventory.counts());
+}
+
+#[test]
+fn retry_inventory_gives_up() {
+    let inventory = FlakyInventory {
+        inventory: CountingInventory::load(),
+        failures: 2,
+    };
+    let retry_inventory = RetryInventory::new(Box::new(&inventory), 1, Duration::from_millis(1));
+    let error = retry_inventory.containers().unwrap_err();
+
+    assert_eq!(
+        "connection to the Docker daemon was refused",
+        error.to_string()
+    );
+    assert_eq!((2, 0, 0, 0), inventory.inventory.counts());
+}
+
+/// Inventory assigning a new address to the container `app` after the networks were first queried,
+/// like a container restarted while the rules are generated.
+struct ReassigningInventory {
+    inventory: CountingInventory,
+}
+
+impl ContainerInventory for ReassigningInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        self.inventory.containers()
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        let mut networks = self.inventory.networks()?;
+        if self.inventory.networks.get() > 1 {
+            for network in &mut networks {
+                if let Some(endpoint) = network.containers.get_mut("app") {
+                    endpoint.ipv4_address = "172.19.0.9/16".to_owned();
+                }
+            }
+        }
+        Ok(networks)
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases, Error> {
+        self.inventory.container_aliases()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts, Error> {
+        self.inventory.host_facts()
+    }
+}
+
+#[test]
+fn inventory_snapshot_stale_addresses() {
+    let inventory = CountingInventory::load();
+    let snapshot = InventorySnapshot::capture(Box::new(&inventory)).unwrap();
+    assert!(snapshot.stale_addresses().unwrap().is_empty());
+
+    let inventory = ReassigningInventory {
+        inventory: CountingInventory::load(),
+    };
+    let snapshot = InventorySnapshot::capture(Box::new(&inventory)).unwrap();
+    assert_eq!(vec!["172.19.0.3"], snapshot.stale_addresses().unwrap());
+}
+
+#[test]
+fn verify_addresses_after_reassignment() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let logger = Logger::root(Discard, o!());
+
+    // The addresses didn't change, the rules can be applied
+    let inventory = CountingInventory::load();
+    let ctx =
+        ProcessContext::with_inventory(Box::new(&inventory), &dfw, Sections::ALL, &logger, true)
+            .unwrap();
+    let rules = dfw.process(&ctx).unwrap().unwrap();
+    ctx.verify_addresses(&rules).unwrap();
+
+    // `app` got a new address between capturing the inventory and applying the rules
+    let inventory = ReassigningInventory {
+        inventory: CountingInventory::load(),
+    };
+    let ctx =
+        ProcessContext::with_inventory(Box::new(&inventory), &dfw, Sections::ALL, &logger, true)
+            .unwrap();
+    let rules = dfw.process(&ctx).unwrap().unwrap();
+    assert!(rules
+        .iter()
+        .any(|rule| rule.contains("ip daddr 172.19.0.3 ")));
+    let error = ctx.verify_addresses(&rules).unwrap_err();
+    assert_eq!(
+        "container addresses changed since the inventory was captured: 172.19.0.3",
+        error.to_string()
+    );
+    match error.downcast_ref() {
+        Some(DFWError::StaleAddresses { .. }) => {}
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    // Stale addresses no rule references are irrelevant
+    let rules = rules
+        .into_iter()
+        .filter(|rule| !rule.contains("172.19.0.3"))
+        .collect::<Vec<_>>();
+    ctx.verify_addresses(&rules).unwrap();
+}
+
+/// Script runner recording the scripts instead of applying them.
+#[derive(Default)]
+struct RecordingRunner {
+    scripts: RefCell<Vec<String>>,
+}
+
+impl ScriptRunner for RecordingRunner {
+    fn run_script(&self, script: &str) -> Result<(), Error> {
+        self.scripts.borrow_mut().push(script.to_owned());
+        Ok(())
+    }
+}
+
+#[test]
+fn process_applies_single_script() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap();
+    let logger = Logger::root(Discard, o!());
+
+    let ctx =
+        ProcessContext::with_inventory(Box::new(&inventory), &dfw, Sections::ALL, &logger, false)
+            .unwrap();
+    let runner = RecordingRunner::default();
+    let applied = ctx.process_with(&runner).unwrap();
+
+    // All rules are applied through a single transaction, rebuilding the table from scratch.
+    assert_eq!(Some(generate(&dfw, &inventory).unwrap()), applied);
+    let scripts = runner.scripts.into_inner();
+    assert_eq!(1, scripts.len());
+    let script = &scripts[0];
+    assert!(script.starts_with("#!/usr/sbin/nft -f\n"));
+    let add_table = script.find("add table inet dfw\n").unwrap();
+    let flush_table = script.find("flush table inet dfw\n").unwrap();
+    assert!(add_table < flush_table);
+    assert_eq!(generate(&dfw, &inventory).unwrap().render(), *script);
+
+    // Nothing is applied during a dry-run
+    let ctx =
+        ProcessContext::with_inventory(Box::new(&inventory), &dfw, Sections::ALL, &logger, true)
+            .unwrap();
+    let runner = RecordingRunner::default();
+    assert_eq!(None, ctx.process_with(&runner).unwrap());
+    assert!(runner.scripts.into_inner().is_empty());
+}
+
+/// Script runner failing like `nft` rejecting the script.
+#[derive(Default)]
+struct FailingRunner {
+    attempts: Cell<usize>,
+}
+
+impl ScriptRunner for FailingRunner {
+    fn run_script(&self, _script: &str) -> Result<(), Error> {
+        self.attempts.set(self.attempts.get() + 1);
+        Err(DFWError::NFTablesError {
+            stdout: String::new(),
+            stderr: "Error: syntax error".to_owned(),
+        }
+        .into())
+    }
+}
+
+#[test]
+fn run_once_applies_single_script() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap();
+    let logger = Logger::root(Discard, o!());
+
+    let runner = RecordingRunner::default();
+    let applied = run_once(&dfw, &inventory, Sections::ALL, &runner, &logger, false).unwrap();
+
+    let scripts = runner.scripts.into_inner();
+    assert_eq!(1, scripts.len());
+    let ruleset = generate(&dfw, &inventory).unwrap();
+    assert_eq!(ruleset.render(), scripts[0]);
+    // The applied rule set is returned, e.g. to record it in the rule history
+    assert_eq!(Some(ruleset), applied);
+}
+
+#[test]
+fn run_once_reports_failure() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap();
+    let logger = Logger::root(Discard, o!());
+
+    let runner = FailingRunner::default();
+    let error = run_once(&dfw, &inventory, Sections::ALL, &runner, &logger, false).unwrap_err();
+
+    // A rejected script is not retried
+    assert_eq!(1, runner.attempts.get());
+    match error.downcast_ref() {
+        Some(DFWError::NFTablesError { stderr, .. }) => assert_eq!("Error: syntax error", stderr),
+        other => panic!("unexpected error: {:?}", other),
+    }
+}
+
+#[test]
+fn run_once_retries_stale_addresses() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = ReassigningInventory {
+        inventory: CountingInventory::load(),
+    };
+    let logger = Logger::root(Discard, o!());
+
+    // The first attempt is discarded since `app` got a new address, the second attempt applies
+    // the rules referencing the new address.
+    let runner = RecordingRunner::default();
+    run_once(&dfw, &inventory, Sections::ALL, &runner, &logger, false).unwrap();
+
+    let scripts = runner.scripts.into_inner();
+    assert_eq!(1, scripts.len());
+    assert!(scripts[0].contains("ip daddr 172.19.0.9 "));
+    assert!(!scripts[0].contains("ip daddr 172.19.0.3 "));
+}
+
+/// Inventory hiding a container of the static inventory while it has vanished.
+struct VanishingInventory {
+    inventory: StaticInventory,
+    vanished: Cell<bool>,
+}
+
+impl VanishingInventory {
+    const CONTAINER: &'static str = "app";
+
+    fn load() -> VanishingInventory {
+        VanishingInventory {
+            inventory: StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap(),
+            vanished: Cell::new(false),
+        }
+    }
+
+    fn container_id(&self) -> String {
+        self.inventory
+            .containers()
+            .unwrap()
+            .into_iter()
+            .find(|container| container.names.contains(&Self::CONTAINER.to_owned()))
+            .unwrap()
+            .id
+    }
+}
+
+impl ContainerInventory for VanishingInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        let container_id = self.container_id();
+        Ok(self
+            .inventory
+            .containers()?
+            .into_iter()
+            .filter(|container| !self.vanished.get() || container.id != container_id)
+            .collect())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        let mut networks = self.inventory.networks()?;
+        if self.vanished.get() {
+            for network in &mut networks {
+                network.containers.remove(&self.container_id());
+            }
+        }
+        Ok(networks)
+    }
+
+    fn container_aliases(&self) -> Result<ContainerAliases, Error> {
+        self.inventory.container_aliases()
+    }
+
+    fn host_facts(&self) -> Result<HostFacts, Error> {
+        self.inventory.host_facts()
+    }
+}
+
+#[test]
+fn grace_period_retains_vanished_container() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = VanishingInventory::load();
+    let history = ContainerHistory::default();
+    let grace_period = Duration::from_secs(30);
+    let start = Instant::now();
+    let generate_at = |seconds: u64| {
+        let inventory = GracePeriodInventory::new(
+            Box::new(&inventory),
+            &history,
+            grace_period,
+            start + Duration::from_secs(seconds),
+        );
+        generate(&dfw, &inventory).unwrap()
+    };
+
+    let present = generate_at(0);
+    assert!(present
+        .commands()
+        .iter()
+        .any(|command| command.contains("172.19.0.3")));
+    inventory.vanished.set(true);
+    let vanished = generate(&dfw, &inventory).unwrap();
+    assert_ne!(present, vanished);
+
+    // The container vanishes and reappears within the grace period, its rules are kept throughout
+    assert_eq!(present, generate_at(10));
+    inventory.vanished.set(false);
+    assert_eq!(present, generate_at(20));
+
+    // The grace period starts over once the container was seen again
+    inventory.vanished.set(true);
+    assert_eq!(present, generate_at(45));
+
+    // Once the grace period elapsed, the rules are removed
+    assert_eq!(vanished, generate_at(50));
+    assert_eq!(vanished, generate_at(60));
+}
+
+#[test]
+fn grace_period_zero_removes_rules_right_away() {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    let inventory = VanishingInventory::load();
+    let history = ContainerHistory::default();
+    let start = Instant::now();
+    let generate_at = |seconds: u64| {
+        let inventory = GracePeriodInventory::new(
+            Box::new(&inventory),
+            &history,
+            Duration::from_secs(0),
+            start + Duration::from_secs(seconds),
+        );
+        generate(&dfw, &inventory).unwrap()
+    };
+
+    let present = generate_at(0);
+    inventory.vanished.set(true);
+    let vanished = generate(&dfw, &inventory).unwrap();
+    assert_ne!(present, vanished);
+    assert_eq!(vanished, generate_at(1));
+}
+
+fn event(status: &str, time_nano: u64) -> ContainerEvent {
+    ContainerEvent {
+        status: Some(status.to_owned()),
+        time_nano,
+    }
+}
+
+#[test]
+fn event_tracker_replays_missed_events() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("events");
+    let mut tracker = EventTracker::load(&path).unwrap();
+    assert_eq!(None, tracker.since());
+
+    let stream = vec![
+        event("exec_start", 10_000_000_000),
+        event("start", 10_500_000_000),
+        event("stop", 11_000_000_000),
+    ];
+    assert_eq!(
+        Some(event("start", 10_500_000_000)),
+        tracker.next_trigger(stream)
+    );
+    tracker.persist().unwrap();
+    assert_eq!(Some(10), tracker.since());
+
+    // Events since the last processed second are received again after re-subscribing, the
+    // processed event is skipped
+    let stream = vec![
+        event("exec_start", 10_000_000_000),
+        event("start", 10_500_000_000),
+        event("stop", 11_000_000_000),
+    ];
+    assert_eq!(
+        Some(event("stop", 11_000_000_000)),
+        tracker.next_trigger(stream)
+    );
+    tracker.persist().unwrap();
+
+    // After a restart, the events missed in the meantime are replayed
+    let mut tracker = EventTracker::load(&path).unwrap();
+    assert_eq!(Some(11), tracker.since());
+    let stream = vec![
+        event("stop", 11_000_000_000),
+        event("die", 12_000_000_000),
+        event("destroy", 13_000_000_000),
+    ];
+    let mut stream = stream.into_iter();
+    assert_eq!(
+        Some(event("die", 12_000_000_000)),
+        tracker.next_trigger(&mut stream)
+    );
+    assert_eq!(
+        Some(event("destroy", 13_000_000_000)),
+        tracker.next_trigger(&mut stream)
+    );
+    assert_eq!(None, tracker.next_trigger(&mut stream));
+    tracker.persist().unwrap();
+    assert_eq!("13000000000", fs::read_to_string(&path).unwrap().trim());
+    // Only the state file remains, the temporary file has been moved into place
+    assert_eq!(1, fs::read_dir(directory.path()).unwrap().count());
+}
+
+#[test]
+fn event_tracker_invalid_state_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("events");
+    fs::write(&path, "yesterday").unwrap();
+
+    assert!(EventTracker::load(&path).is_err());
+}
@@ -22,6 +22,7 @@ lazy_static! {
         let mut m = Map::new();
         m.insert("ip", r"\d{1,3}\.\d{1,3}\.\d{1,3}\.\d{1,3}");
         m.insert("bridge", r"br-[a-f0-9]{12}");
+        m.insert("subnets", r"\{[0-9a-f.:/,]+\}");
         m
     };
 }
@@ -0,0 +1,145 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+#![cfg(feature = "metrics")]
+
+use dfw::metrics::*;
+use dfw::types::Section;
+use failure::format_err;
+use std::io::{Read, Write};
+use std::net::TcpStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+const RULESET: &str = r#"table inet filter {
+	chain input {
+		type filter hook input priority 0; policy accept;
+		counter packets 7 bytes 700 accept # handle 2
+	}
+}
+table inet dfw {
+	chain input {
+		type filter hook input priority -5; policy drop;
+		ct state invalid drop # handle 4
+		meta iifname "docker0" meta mark set 0x000000df counter packets 12 bytes 1440 accept comment "DFW-MARKER:section;container_to_host;rule=0123456789abcdef" # handle 7
+	}
+	chain forward {
+		type filter hook forward priority -5; policy drop;
+		tcp dport 443 ip daddr 172.18.0.2 meta iifname "eth0" meta oifname "br-123" meta mark set 0x000000df counter packets 3 bytes 180 accept comment "DFW-MARKER:section;wider_world_to_container;rule=fedcba9876543210;source=conf.d/10-web.toml:12" # handle 11
+	}
+}
+"#;
+
+fn get(address: std::net::SocketAddr, path: &str) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    write!(stream, "GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    response
+}
+
+#[test]
+fn parse_rule_counters_dfw_tables() {
+    let counters = parse_rule_counters(RULESET);
+
+    assert_eq!(
+        vec![
+            RuleCounter {
+                family: "inet".to_owned(),
+                chain: "input".to_owned(),
+                section: Section::ContainerToHost,
+                rule_id: "0123456789abcdef".parse().unwrap(),
+                packets: 12,
+                bytes: 1440,
+            },
+            RuleCounter {
+                family: "inet".to_owned(),
+                chain: "forward".to_owned(),
+                section: Section::WiderWorldToContainer,
+                rule_id: "fedcba9876543210".parse().unwrap(),
+                packets: 3,
+                bytes: 180,
+            },
+        ],
+        counters
+    );
+}
+
+#[test]
+fn serve_metrics() {
+    let metrics = Arc::new(Metrics::with_ruleset(|| Ok(RULESET.to_owned())));
+    metrics.record_reconcile(Duration::from_millis(250), true);
+    metrics.record_reconcile(Duration::from_millis(500), false);
+
+    let address = serve("127.0.0.1:0".parse().unwrap(), Arc::clone(&metrics)).unwrap();
+    let response = get(address, "/metrics");
+
+    let (head, body) = response.split_at(response.find("\r\n\r\n").unwrap() + 4);
+    assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(head.contains("Content-Type: text/plain; version=0.0.4\r\n"));
+
+    let samples = body
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "dfw_reconciles_total 2",
+            "dfw_reconcile_errors_total 1",
+            "dfw_reconcile_duration_seconds 0.5",
+            "dfw_rules{section=\"container_to_host\"} 1",
+            "dfw_rules{section=\"wider_world_to_container\"} 1",
+            "dfw_rule_packets_total{section=\"container_to_host\",rule=\"0123456789abcdef\",family=\"inet\",chain=\"input\"} 12",
+            "dfw_rule_packets_total{section=\"wider_world_to_container\",rule=\"fedcba9876543210\",family=\"inet\",chain=\"forward\"} 3",
+            "dfw_rule_bytes_total{section=\"container_to_host\",rule=\"0123456789abcdef\",family=\"inet\",chain=\"input\"} 1440",
+            "dfw_rule_bytes_total{section=\"wider_world_to_container\",rule=\"fedcba9876543210\",family=\"inet\",chain=\"forward\"} 180",
+        ],
+        samples
+    );
+    // Every metric is described by its type
+    for name in &[
+        "dfw_reconciles_total",
+        "dfw_reconcile_errors_total",
+        "dfw_reconcile_duration_seconds",
+        "dfw_rules",
+        "dfw_rule_packets_total",
+        "dfw_rule_bytes_total",
+    ] {
+        assert!(body.contains(&format!("# TYPE {} ", name)));
+    }
+
+    let response = get(address, "/other");
+    assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+}
+
+#[test]
+fn serve_metrics_ruleset_unavailable() {
+    let metrics = Arc::new(Metrics::with_ruleset(|| {
+        Err(format_err!("failed to list ruleset: permission denied"))
+    }));
+
+    let address = serve("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+    let response = get(address, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 500 Internal Server Error\r\n"));
+    assert!(response.ends_with("\r\n\r\nfailed to list ruleset: permission denied\n"));
+}
+
+#[test]
+fn serve_metrics_slow_client() {
+    let metrics = Arc::new(Metrics::new());
+    let address = serve("127.0.0.1:0".parse().unwrap(), metrics).unwrap();
+
+    // A client that never sends its request doesn't hold up the others
+    let _slow_client = TcpStream::connect(address).unwrap();
+    let response = get(address, "/metrics");
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    assert!(response.contains("\ndfw_reconciles_total 0\n"));
+}
@@ -0,0 +1,114 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+#![cfg(feature = "backend")]
+
+use dfw::errors::*;
+use dfw::nftables::NftVersion;
+use dfw::preflight::*;
+use failure::format_err;
+
+struct MockProbes {
+    capabilities: u64,
+    nft_version: Option<NftVersion>,
+    modules: Vec<&'static str>,
+}
+
+impl Default for MockProbes {
+    fn default() -> MockProbes {
+        MockProbes {
+            // CAP_NET_ADMIN and CAP_NET_RAW
+            capabilities: 0x3000,
+            nft_version: Some(NftVersion::new(0, 9, 3)),
+            modules: REQUIRED_KERNEL_MODULES.to_vec(),
+        }
+    }
+}
+
+impl Probes for MockProbes {
+    fn effective_capabilities(&self) -> Result<u64> {
+        Ok(self.capabilities)
+    }
+
+    fn nft_version(&self) -> Result<NftVersion> {
+        self.nft_version
+            .ok_or_else(|| format_err!("failed to invoke nft: No such file or directory"))
+    }
+
+    fn kernel_module_available(&self, module: &str) -> Result<bool> {
+        Ok(self.modules.contains(&module))
+    }
+}
+
+#[test]
+fn preflight_passing() {
+    let report = preflight(&MockProbes::default());
+
+    assert!(report.passed(), "{}", report);
+    assert_eq!(Some(NftVersion::new(0, 9, 3)), report.nft_version);
+    assert_eq!("all prerequisites are met", report.to_string());
+}
+
+#[test]
+fn preflight_failing() {
+    let report = preflight(&MockProbes {
+        capabilities: 0x2000,
+        nft_version: None,
+        modules: vec!["nf_tables", "nf_conntrack"],
+    });
+
+    // Every prerequisite is checked, not only the first failing one
+    assert!(!report.passed());
+    assert_eq!(None, report.nft_version);
+    assert_eq!(
+        vec![
+            Prerequisite::CapNetAdmin,
+            Prerequisite::NftBinary,
+            Prerequisite::KernelModule("nf_nat".to_owned()),
+        ],
+        report
+            .problems
+            .iter()
+            .map(|problem| problem.prerequisite.clone())
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        "nft binary: failed to invoke nft: No such file or directory",
+        report.to_string().split("; ").nth(1).unwrap()
+    );
+}
+
+#[test]
+fn preflight_outdated_nft() {
+    let report = preflight(&MockProbes {
+        nft_version: Some(NftVersion::new(0, 8, 4)),
+        ..Default::default()
+    });
+
+    assert_eq!(
+        vec![Problem {
+            prerequisite: Prerequisite::NftBinary,
+            message: "version 0.8.4 is not supported, at least version 0.9.0 is required"
+                .to_owned(),
+        }],
+        report.problems
+    );
+    assert_eq!(Some(NftVersion::new(0, 8, 4)), report.nft_version);
+}
+
+#[test]
+fn parse_effective_capabilities_from_status() {
+    let status = "Name:\tdfw\nCapPrm:\t000001ffffffffff\nCapEff:\t000001ffffffffff\n";
+    assert_eq!(
+        0x1ff_ffff_ffff,
+        parse_effective_capabilities(status).unwrap()
+    );
+
+    assert!(parse_effective_capabilities("Name:\tdfw\n").is_err());
+    assert!(parse_effective_capabilities("CapEff:\tcapabilities\n").is_err());
+}
@@ -0,0 +1,4678 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+#![cfg(feature = "backend")]
+
+use dfw::errors::DFWError;
+use dfw::inventory::{Container, ContainerInventory, Network, NetworkEndpoint, StaticInventory};
+use dfw::nftables::ScriptRunner;
+use dfw::process::{
+    default_route_interfaces, explain, generate, managed_objects, nft_binary, HostFacts,
+    RuleHistory, RuleId, RuleSet, Section,
+};
+use dfw::types::{Condition, TableFamily, DFW, EMBEDDED_DNS_ADDRESS};
+use dfw::util::{load_config_file, load_config_path, load_file};
+use failure::{format_err, Error};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::net::IpAddr;
+use std::os::unix::fs::PermissionsExt;
+use std::process::Command;
+
+fn host_facts(hostname: &str, env: &[(&str, &str)]) -> HostFacts {
+    HostFacts {
+        hostname: hostname.to_owned(),
+        env: env
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value.to_owned()))
+            .collect(),
+        nft_version: None,
+        default_route_interfaces: Vec::new(),
+    }
+}
+
+#[test]
+fn condition_empty_holds() {
+    let facts = host_facts("host", &[]);
+
+    assert!(Condition::default().holds(&facts).unwrap());
+}
+
+#[test]
+fn condition_matching_host_holds() {
+    let facts = host_facts("edge-01", &[("ROLE", "edge"), ("DFW_ENABLED", "")]);
+    let condition = Condition {
+        hostname: Some("edge-*".to_owned()),
+        env: Some(vec!["ROLE=edge".to_owned(), "DFW_ENABLED".to_owned()]),
+    };
+
+    assert!(condition.holds(&facts).unwrap());
+}
+
+#[test]
+fn condition_non_matching_host_does_not_hold() {
+    let condition = Condition {
+        hostname: Some("edge-*".to_owned()),
+        env: Some(vec!["ROLE=edge".to_owned()]),
+    };
+
+    // Hostname doesn't match
+    let facts = host_facts("core-01", &[("ROLE", "edge")]);
+    assert!(!condition.holds(&facts).unwrap());
+
+    // Environment variable has a different value
+    let facts = host_facts("edge-01", &[("ROLE", "core")]);
+    assert!(!condition.holds(&facts).unwrap());
+
+    // Environment variable is missing
+    let facts = host_facts("edge-01", &[]);
+    assert!(!condition.holds(&facts).unwrap());
+}
+
+#[test]
+fn condition_invalid_hostname_pattern() {
+    let facts = host_facts("edge-01", &[]);
+    let condition = Condition {
+        hostname: Some("edge-[".to_owned()),
+        env: None,
+    };
+
+    assert!(condition.holds(&facts).is_err());
+}
+
+#[test]
+fn host_facts_collect() {
+    let facts = HostFacts::collect().unwrap();
+
+    assert!(!facts.hostname.is_empty());
+}
+
+/// Inventory serving a fixed set of containers and networks, each container being attached to the
+/// networks given for it.
+struct MockInventory {
+    containers: Vec<(&'static str, Vec<&'static str>)>,
+}
+
+impl MockInventory {
+    fn container_id(container_name: &str) -> String {
+        format!("{:0<64}", container_name.replace('_', ""))
+    }
+
+    fn network_id(network_name: &str) -> String {
+        format!("{:f<64}", network_name.replace('_', ""))
+    }
+}
+
+impl ContainerInventory for MockInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        Ok(self
+            .containers
+            .iter()
+            .map(|(name, _)| Container {
+                id: Self::container_id(name),
+                names: vec![format!("/{}", name)],
+                labels: Default::default(),
+                health: None,
+                published_ports: Vec::new(),
+            })
+            .collect())
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        let mut network_names = self
+            .containers
+            .iter()
+            .flat_map(|(_, networks)| networks.iter().cloned())
+            .chain(Some("bridge"))
+            .collect::<Vec<_>>();
+        network_names.sort();
+        network_names.dedup();
+
+        Ok(network_names
+            .into_iter()
+            .enumerate()
+            .map(|(network_index, network_name)| Network {
+                id: Self::network_id(network_name),
+                name: network_name.to_owned(),
+                driver: match network_name {
+                    "host" => "host",
+                    "none" => "null",
+                    _ => "bridge",
+                }
+                .to_owned(),
+                options: if network_name == "bridge" {
+                    vec![(
+                        "com.docker.network.bridge.name".to_owned(),
+                        "docker0".to_owned(),
+                    )]
+                    .into_iter()
+                    .collect()
+                } else {
+                    Default::default()
+                },
+                subnets: vec![format!("172.{}.0.0/16", 18 + network_index)],
+                containers: self
+                    .containers
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, (_, networks))| networks.contains(&network_name))
+                    .map(|(container_index, (container_name, _))| {
+                        (
+                            Self::container_id(container_name),
+                            NetworkEndpoint {
+                                ipv4_address: format!(
+                                    "172.{}.0.{}/16",
+                                    18 + network_index,
+                                    2 + container_index
+                                ),
+                                ipv6_address: String::new(),
+                                secondary_ipv4_addresses: Vec::new(),
+                            },
+                        )
+                    })
+                    .collect(),
+            })
+            .collect())
+    }
+
+    fn cidr_file(&self, file: &str) -> Result<String, Error> {
+        match file {
+            "allow-list.txt" => Ok(
+                "# Corporate networks\n192.0.2.0/24\n\n198.51.100.7\n  203.0.113.0/25  \n"
+                    .to_owned(),
+            ),
+            "malformed.txt" => Ok("192.0.2.0/24\n198.51.100.0/33\n".to_owned()),
+            _ => Err(format_err!("failed to open CIDR file {}", file)),
+        }
+    }
+}
+
+fn full_example_inventory() -> MockInventory {
+    MockInventory {
+        containers: vec![
+            ("container_a", vec!["common_network", "network_a"]),
+            ("container_b", vec!["common_network", "network_b"]),
+            ("my_reverseproxy", vec!["reverseproxy_network"]),
+            (
+                "my_webserver",
+                vec!["reverseproxy_network", "internal_network"],
+            ),
+            ("logstash", vec!["log_network"]),
+        ],
+    }
+}
+
+/// Generate the rules twice, verifying that generation is deterministic.
+fn generate_idempotent(dfw: &DFW, inventory: &dyn ContainerInventory) -> RuleSet {
+    let ruleset = generate(dfw, inventory).unwrap();
+    assert_eq!(ruleset, generate(dfw, inventory).unwrap());
+
+    ruleset
+}
+
+#[test]
+fn generate_full_example() {
+    let dfw: DFW = load_file("examples/full-single-file/dfw.toml").unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+    let commands = ruleset.commands();
+
+    assert_eq!(commands[..ruleset.preamble.len()], ruleset.preamble[..]);
+    assert_eq!(8, ruleset.sections.len());
+    for expected in &[
+        "add table inet custom",
+        "add rule inet dfw input meta iifname docker0 meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;defaults\"",
+        "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+         meta iifname br-commonnetwor oifname br-commonnetwor meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;container_to_container\"",
+        "add rule inet dfw input meta iifname br-internalnetw meta mark set 0xdf reject \
+         comment \"DFW-MARKER:section;container_to_host\"",
+        "add rule ip dfw prerouting tcp dport 443 meta iifname tun0 \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 50000 ip saddr 172.22.0.2 \
+         meta iifname br-networkaffff oifname br-networkbffff ct state new meta mark set 0xdf \
+         dnat 172.23.0.3:50000 comment \"DFW-MARKER:section;container_dnat\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_fast_path_precedes_destructive_commands() {
+    let dfw: DFW = load_file("examples/full-single-file/dfw.toml").unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+    let position = |command: &str| {
+        commands
+            .iter()
+            .position(|c| c == command)
+            .unwrap_or_else(|| panic!("missing command: {}", command))
+    };
+
+    for chain in &["input", "forward"] {
+        let fast_path = position(&format!(
+            "add rule inet dfw {} ct state {{ related, established }} accept",
+            chain
+        ));
+        assert!(
+            position(&format!(
+                "add rule inet dfw {} ct state invalid drop",
+                chain
+            )) < fast_path
+        );
+
+        // Established connections are accepted before any other rule or policy of the chain is
+        // added, and before any other table is flushed.
+        let chain_prefixes = [
+            format!("add rule inet dfw {} ", chain),
+            format!("add chain inet dfw {} {{ policy", chain),
+        ];
+        for (index, command) in commands.iter().enumerate() {
+            let touches_chain = chain_prefixes
+                .iter()
+                .any(|prefix| command.starts_with(prefix.as_str()))
+                && !command.contains("ct state");
+            let destructive = (command.starts_with("flush ") || command.starts_with("delete "))
+                && command != "flush table inet dfw";
+            if touches_chain || destructive {
+                assert!(fast_path < index, "{} precedes the fast-path", command);
+            }
+        }
+    }
+}
+
+fn dns_rules(dns: &str) -> Vec<String> {
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_wider_world]
+        default_policy = "drop"
+
+        [dns]
+        {}
+        "#,
+        dns
+    ))
+    .unwrap();
+    let ruleset = generate(&dfw, &full_example_inventory())
+        .map_err(|error| error.to_string())
+        .unwrap();
+
+    ruleset
+        .sections
+        .into_iter()
+        .find(|(section, _)| *section == Section::Dns)
+        .map(|(_, rules)| rules)
+        .unwrap()
+}
+
+#[test]
+fn generate_dns_default_servers() {
+    let rules = dns_rules("");
+
+    // Two rules for each of the seven networks, allowing DNS to any server
+    assert_eq!(14, rules.len());
+    for expected in &[
+        "add rule inet dfw forward meta iifname br-commonnetwor oifname eth0 meta mark set 0xdf \
+         udp dport 53 accept comment \"DFW-MARKER:section;dns\"",
+        "add rule inet dfw forward meta iifname br-commonnetwor oifname eth0 meta mark set 0xdf \
+         tcp dport 53 accept comment \"DFW-MARKER:section;dns\"",
+    ] {
+        assert!(
+            rules.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_dns_custom_servers() {
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+             oifname eth0 meta mark set 0xdf ip daddr { 192.0.2.53, 198.51.100.53 } \
+             udp dport 53 accept comment \"DFW-MARKER:section;dns\"",
+            "add rule inet dfw forward ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+             oifname eth0 meta mark set 0xdf ip daddr { 192.0.2.53, 198.51.100.53 } \
+             tcp dport { 53, 853 } accept comment \"DFW-MARKER:section;dns\"",
+            "add rule inet dfw forward ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+             oifname eth0 meta mark set 0xdf ip6 daddr { 2001:db8::53 } udp dport 53 \
+             accept comment \"DFW-MARKER:section;dns\"",
+            "add rule inet dfw forward ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+             oifname eth0 meta mark set 0xdf ip6 daddr { 2001:db8::53 } \
+             tcp dport { 53, 853 } accept comment \"DFW-MARKER:section;dns\"",
+        ],
+        dns_rules(
+            r#"
+            servers = ["192.0.2.53", "2001:db8::53", "198.51.100.53"]
+            network = "common_network"
+            src_container = "container_a"
+            dns_over_tls = true
+            "#
+        )
+    );
+}
+
+#[test]
+fn generate_dns_src_container_without_network() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [dns]
+        src_container = "container_a"
+        "#,
+    )
+    .unwrap();
+
+    let error = generate(&dfw, &full_example_inventory()).unwrap_err();
+    assert_eq!(
+        "the source container of the DNS section requires a network",
+        error.to_string()
+    );
+}
+
+#[test]
+fn generate_without_containers() {
+    let dfw: DFW = load_file("examples/full-single-file/dfw.toml").unwrap();
+    let inventory = MockInventory {
+        containers: Vec::new(),
+    };
+
+    assert!(generate(&dfw, &inventory).is_err());
+}
+
+#[test]
+fn generate_dnat_to_overrides_container_address() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = [80, 443]
+        dnat_to = "192.0.2.10"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 8443
+        dnat_to = "192.0.2.10:443"
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+    let commands = ruleset.commands();
+
+    for expected in &[
+        "add rule inet dfw forward tcp dport 80 ip daddr 192.0.2.10 meta iifname eth0 \
+         oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+         ct state new meta mark set 0xdf dnat 192.0.2.10:443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule inet dfw forward tcp dport 443 ip daddr 192.0.2.10 meta iifname eth0 \
+         oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 8443 meta iifname eth0 \
+         ct state new meta mark set 0xdf dnat 192.0.2.10:443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+    // The address of the container is not used
+    assert!(!commands
+        .iter()
+        .any(|command| command.contains("172.24.0.4")));
+}
+
+#[test]
+fn generate_expose_port_range() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = { host_port_range = "8000-8010", container_port_range = "9000-9010" }
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+    let commands = ruleset.commands();
+
+    for expected in &[
+        "add rule inet dfw forward tcp dport 9000-9010 ip daddr 172.24.0.4 meta iifname eth0 \
+         oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 8000-8010 meta iifname eth0 \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:9000-9010 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_dnat_to_family_mismatch() {
+    for rule in &[
+        r#"expose_port = 443
+           dnat_to = "2001:db8::10""#,
+        r#"expose_port = { host_port = 443, host_ip = "2001:db8::1" }
+           dnat_to = "192.0.2.10""#,
+    ] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            {}
+            "#,
+            rule
+        ))
+        .unwrap();
+
+        assert!(generate(&dfw, &full_example_inventory()).is_err());
+    }
+}
+
+#[test]
+fn generate_rule_host_ip() {
+    let prerouting_rules = |host_ip: &str| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = [{{ host_port = 80 }}, {{ host_port = 443, host_ip = "192.0.2.2" }}]
+            {}
+            "#,
+            host_ip
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains(" dfw prerouting tcp dport 80 "))
+            .collect()
+    };
+
+    // Without an address of the host, the port is exposed on all addresses of both families
+    assert_eq!(
+        vec![
+            "add rule ip dfw prerouting tcp dport 80 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:80 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip6 dfw prerouting tcp dport 80 meta iifname eth0 meta mark set 0xdf \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        prerouting_rules("")
+    );
+
+    // The address of the rule restricts the DNAT to traffic arriving at it
+    assert_eq!(
+        vec![
+            "add rule ip dfw prerouting tcp dport 80 ip daddr 192.0.2.1 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:80 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        prerouting_rules(r#"host_ip = "192.0.2.1""#)
+    );
+
+    // The address of the port takes precedence over the address of the rule
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = { host_port = 443, host_ip = "192.0.2.2" }
+        host_ip = "192.0.2.1"
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+    assert!(commands.contains(
+        &"add rule ip dfw prerouting tcp dport 443 ip daddr 192.0.2.2 meta iifname eth0 \
+          ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+          comment \"DFW-MARKER:section;wider_world_to_container\""
+            .to_owned()
+    ));
+    assert!(!commands.iter().any(|command| command.contains("192.0.2.1")));
+}
+
+fn auto_host_ports(rules: &str) -> Result<BTreeMap<String, u16>, Error> {
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        auto_port_range = "30000-30099"
+
+        {}
+        "#,
+        rules
+    ))
+    .unwrap();
+
+    Ok(generate(&dfw, &full_example_inventory())?
+        .commands()
+        .into_iter()
+        .filter_map(|command| {
+            let rule = command.strip_prefix("add rule ip dfw prerouting tcp dport ")?;
+            let host_port = rule.split(' ').next()?.parse().ok()?;
+            let dnat = rule.split(" dnat ").nth(1)?.split(' ').next()?;
+            Some((dnat.to_owned(), host_port))
+        })
+        .collect())
+}
+
+#[test]
+fn generate_auto_host_port_deterministic() {
+    let reverseproxy = r#"
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = [
+            { host_port = "auto", container_port = 80 },
+            { host_port = "auto", container_port = 443 },
+        ]
+        "#;
+    let webserver = r#"
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_webserver"
+        expose_port = "auto:8080/tcp"
+        "#;
+
+    let host_ports = auto_host_ports(&format!("{}{}", reverseproxy, webserver)).unwrap();
+    assert_eq!(3, host_ports.len());
+    assert!(host_ports
+        .values()
+        .all(|host_port| (30000..=30099).contains(host_port)));
+    let mut distinct: Vec<_> = host_ports.values().collect();
+    distinct.sort();
+    distinct.dedup();
+    assert_eq!(3, distinct.len());
+
+    // The assignment neither changes between runs nor depends on the order of the rules
+    assert_eq!(
+        host_ports,
+        auto_host_ports(&format!("{}{}", reverseproxy, webserver)).unwrap()
+    );
+    assert_eq!(
+        host_ports,
+        auto_host_ports(&format!("{}{}", webserver, reverseproxy)).unwrap()
+    );
+}
+
+#[test]
+fn generate_auto_host_port_range_exhausted() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        auto_port_range = "30000-30001"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = ["30000", "auto:80"]
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_webserver"
+        expose_port = "auto:8080"
+        "#,
+    )
+    .unwrap();
+
+    let error = generate(&dfw, &full_example_inventory()).unwrap_err();
+    assert_eq!(
+        "port range 30000-30001 is exhausted, cannot assign host port for container port 8080 of \
+         container my_webserver",
+        error.to_string()
+    );
+}
+
+fn postrouting_rules(egress_nat: &str) -> Vec<String> {
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        {}
+        "#,
+        egress_nat
+    ))
+    .unwrap();
+
+    let inventory = MockInventory {
+        containers: vec![("container_a", vec!["bridge"])],
+    };
+
+    generate_idempotent(&dfw, &inventory)
+        .commands()
+        .into_iter()
+        .filter(|command| command.contains(" dfw postrouting meta oifname"))
+        .collect()
+}
+
+#[test]
+fn generate_egress_nat_masquerade() {
+    let expected = vec![
+        "add rule ip dfw postrouting meta oifname eth0 \
+         ip daddr != {127.0.0.0/8,169.254.0.0/16,172.18.0.0/16} meta mark set 0xdf masquerade \
+         comment \"DFW-MARKER:section;defaults\"",
+    ];
+
+    assert_eq!(expected, postrouting_rules(""));
+    assert_eq!(expected, postrouting_rules(r#"egress_nat = "masquerade""#));
+}
+
+#[test]
+fn generate_ipv6_masquerade() {
+    let nat_v4 = "add rule ip dfw postrouting meta oifname eth0 \
+                  ip daddr != {127.0.0.0/8,169.254.0.0/16,172.18.0.0/16} meta mark set 0xdf \
+                  masquerade comment \"DFW-MARKER:section;defaults\"";
+    let nat_v6 = "add rule ip6 dfw postrouting meta oifname eth0 \
+                  ip6 daddr != {::1/128,fe80::/10} meta mark set 0xdf masquerade \
+                  comment \"DFW-MARKER:section;defaults\"";
+
+    // IPv6 traffic is only masqueraded if enabled
+    assert_eq!(vec![nat_v4], postrouting_rules("ipv6_masquerade = false"));
+    assert_eq!(
+        vec![nat_v4, nat_v6],
+        postrouting_rules("ipv6_masquerade = true")
+    );
+}
+
+#[test]
+fn generate_egress_nat_excludes_container_subnets() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        "#,
+    )
+    .unwrap();
+
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+    let nat_v4 = commands
+        .iter()
+        .find(|command| command.starts_with("add rule ip dfw postrouting meta oifname eth0"))
+        .unwrap();
+    let excluded = &nat_v4[nat_v4.find('{').unwrap() + 1..nat_v4.find('}').unwrap()];
+    let excluded: Vec<&str> = excluded.split(',').collect();
+
+    assert_eq!(
+        vec![
+            "127.0.0.0/8",
+            "169.254.0.0/16",
+            "172.18.0.0/16",
+            "172.19.0.0/16",
+            "172.20.0.0/16",
+            "172.21.0.0/16",
+            "172.22.0.0/16",
+            "172.23.0.0/16",
+            "172.24.0.0/16",
+        ],
+        excluded
+    );
+}
+
+fn compat_postrouting_rules(compat_mode: bool, nft_version: Option<&str>) -> Vec<String> {
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        ipv6_masquerade = true
+        compat_mode = {}
+        "#,
+        compat_mode
+    ))
+    .unwrap();
+    let mut inventory = StaticInventory::load("resources/test/inventory/inventory.toml").unwrap();
+    inventory.host_facts.nft_version = nft_version.map(|version| version.parse().unwrap());
+
+    generate(&dfw, &inventory)
+        .unwrap()
+        .commands()
+        .into_iter()
+        .filter(|command| command.contains(" dfw postrouting meta oifname"))
+        .collect()
+}
+
+#[test]
+fn generate_egress_nat_nft_version_gate() {
+    let negated_sets = vec![
+        "add rule ip dfw postrouting meta oifname eth0 \
+         ip daddr != {127.0.0.0/8,169.254.0.0/16,172.19.0.0/16,172.17.0.0/16,172.18.0.0/16} \
+         meta mark set 0xdf masquerade comment \"DFW-MARKER:section;defaults\"",
+        "add rule ip6 dfw postrouting meta oifname eth0 ip6 daddr != {::1/128,fe80::/10} \
+         meta mark set 0xdf masquerade comment \"DFW-MARKER:section;defaults\"",
+    ];
+    let separate_rules: Vec<String> = [
+        "ip daddr 127.0.0.0/8 accept",
+        "ip daddr 169.254.0.0/16 accept",
+        "ip daddr 172.19.0.0/16 accept",
+        "ip daddr 172.17.0.0/16 accept",
+        "ip daddr 172.18.0.0/16 accept",
+        "meta mark set 0xdf masquerade",
+    ]
+    .iter()
+    .map(|rule| format!("add rule ip dfw postrouting meta oifname eth0 {}", rule))
+    .chain(
+        [
+            "ip6 daddr ::1/128 accept",
+            "ip6 daddr fe80::/10 accept",
+            "meta mark set 0xdf masquerade",
+        ]
+        .iter()
+        .map(|rule| format!("add rule ip6 dfw postrouting meta oifname eth0 {}", rule)),
+    )
+    .map(|command| format!("{} comment \"DFW-MARKER:section;defaults\"", command))
+    .collect();
+
+    // Unknown versions are assumed to support negated sets
+    assert_eq!(negated_sets, compat_postrouting_rules(false, None));
+    assert_eq!(
+        negated_sets,
+        compat_postrouting_rules(false, Some("nftables v0.9.1 (Headless Horseman)"))
+    );
+    assert_eq!(
+        separate_rules,
+        compat_postrouting_rules(false, Some("0.9.0"))
+    );
+    assert_eq!(separate_rules, compat_postrouting_rules(true, None));
+    assert_eq!(
+        separate_rules,
+        compat_postrouting_rules(true, Some("1.0.0"))
+    );
+}
+
+#[test]
+fn generate_egress_nat_snat() {
+    assert_eq!(
+        vec![
+            "add rule ip dfw postrouting meta oifname eth0 \
+         ip daddr != {127.0.0.0/8,169.254.0.0/16,172.18.0.0/16} meta mark set 0xdf \
+             snat to 203.0.113.1 comment \"DFW-MARKER:section;defaults\"",
+            "add rule ip6 dfw postrouting meta oifname eth0 \
+         ip6 daddr != {::1/128,fe80::/10} meta mark set 0xdf masquerade \
+             comment \"DFW-MARKER:section;defaults\"",
+        ],
+        postrouting_rules(
+            r#"egress_nat = { snat = "203.0.113.1" }
+               ipv6_masquerade = true"#
+        )
+    );
+    assert_eq!(
+        vec![
+            "add rule ip dfw postrouting meta oifname eth0 \
+             ip daddr != {127.0.0.0/8,169.254.0.0/16,172.18.0.0/16} meta mark set 0xdf \
+             snat to 203.0.113.1 comment \"DFW-MARKER:section;defaults\"",
+        ],
+        postrouting_rules(r#"egress_nat = { snat = "203.0.113.1" }"#)
+    );
+    // A static IPv6 address translates IPv6 traffic without `ipv6_masquerade`
+    assert_eq!(
+        vec![
+            "add rule ip dfw postrouting meta oifname eth0 \
+         ip daddr != {127.0.0.0/8,169.254.0.0/16,172.18.0.0/16} meta mark set 0xdf masquerade \
+             comment \"DFW-MARKER:section;defaults\"",
+            "add rule ip6 dfw postrouting meta oifname eth0 \
+         ip6 daddr != {::1/128,fe80::/10} meta mark set 0xdf \
+             snat to 2001:db8::1 comment \"DFW-MARKER:section;defaults\"",
+        ],
+        postrouting_rules(r#"egress_nat = { snat = "2001:db8::1" }"#)
+    );
+}
+
+#[test]
+fn generate_mirror_to() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "reverseproxy_network"
+        src_container = "my_reverseproxy"
+        dst_container = "my_webserver"
+        verdict = "accept"
+        mirror_to = "10.0.0.250"
+
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        network = "internal_network"
+        verdict = "reject"
+        external_network_interface = "eth1"
+        mirror_to = "10.0.0.250"
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        "add rule inet dfw forward ip saddr 172.24.0.4 ip daddr 172.24.0.5 \
+         meta iifname br-reverseproxy oifname br-reverseproxy meta mark set 0xdf \
+         dup to 10.0.0.250 device \"eth0\" accept \
+         comment \"DFW-MARKER:section;container_to_container\"",
+        "add rule inet dfw forward meta iifname br-internalnetw oifname eth1 meta mark set 0xdf \
+         dup to 10.0.0.250 device \"eth1\" reject \
+         comment \"DFW-MARKER:section;container_to_wider_world\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_wildcard_network() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "*"
+        src_container = "multi_homed"
+        dst_container = "peer"
+        verdict = "accept"
+
+        [container_to_host]
+        default_policy = "drop"
+
+        [[container_to_host.rules]]
+        network = "*"
+        src_container = "multi_homed"
+        matches = "tcp dport 53"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+    let inventory = MockInventory {
+        containers: vec![
+            ("multi_homed", vec!["network_a", "network_b", "network_c"]),
+            ("peer", vec!["network_a", "network_c"]),
+        ],
+    };
+    let ruleset = generate_idempotent(&dfw, &inventory);
+
+    let section_rules = |section: Section| -> Vec<String> {
+        ruleset
+            .sections
+            .iter()
+            .filter(|(s, _)| *s == section)
+            .flat_map(|(_, rules)| rules.iter())
+            .filter(|rule| {
+                rule.starts_with("add rule")
+                    && !rule.contains(" drop ")
+                    && !rule.contains(EMBEDDED_DNS_ADDRESS)
+            })
+            .cloned()
+            .collect()
+    };
+
+    // The containers share only two of the networks
+    let container_to_container = section_rules(Section::ContainerToContainer);
+    assert_eq!(2, container_to_container.len());
+    for (rule, bridge) in container_to_container
+        .iter()
+        .zip(&["br-networkaffff", "br-networkcffff"])
+    {
+        assert!(
+            rule.contains(&format!("meta iifname {} oifname {}", bridge, bridge)),
+            "{}",
+            rule
+        );
+    }
+
+    let container_to_host = section_rules(Section::ContainerToHost);
+    assert_eq!(3, container_to_host.len());
+    for (rule, bridge) in
+        container_to_host
+            .iter()
+            .zip(&["br-networkaffff", "br-networkbffff", "br-networkcffff"])
+    {
+        assert!(
+            rule.contains(&format!(
+                "meta iifname {} meta mark set 0xdf tcp dport 53",
+                bridge
+            )),
+            "{}",
+            rule
+        );
+    }
+}
+
+#[test]
+fn generate_max_generated_rules() {
+    let generate_limited = |max_generated_rules: usize| {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            max_generated_rules = {}
+
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "*"
+            src_container = "multi_homed"
+            dst_container = "peer"
+            verdict = "accept"
+
+            [container_to_host]
+            default_policy = "drop"
+
+            [[container_to_host.rules]]
+            network = "*"
+            src_container = "multi_homed"
+            verdict = "accept"
+            "#,
+            max_generated_rules
+        ))
+        .unwrap();
+        let inventory = MockInventory {
+            containers: vec![
+                ("multi_homed", vec!["network_a", "network_b", "network_c"]),
+                ("peer", vec!["network_a", "network_c"]),
+            ],
+        };
+        generate(&dfw, &inventory).map_err(|error| error.to_string())
+    };
+
+    // The rules expand to two and three rules, the rules of the default policies are not counted
+    assert!(generate_limited(5).is_ok());
+    assert_eq!(
+        Err(
+            "rule 1 of section `container_to_host` expanded to 3 rules, exceeding the limit of 4 \
+             generated rules set through `max_generated_rules`"
+                .to_owned()
+        ),
+        generate_limited(4)
+    );
+}
+
+#[test]
+fn generate_wildcard_network_without_container() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_host]
+        default_policy = "drop"
+
+        [[container_to_host.rules]]
+        network = "*"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+
+    let error = generate(&dfw, &full_example_inventory()).unwrap_err();
+    assert_eq!(
+        "network `*` requires the rule to reference a container",
+        error.to_string()
+    );
+}
+
+#[test]
+fn generate_annotate_rules() {
+    let mut dfw = load_config_path("resources/test/provenance").unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+    let commands = ruleset.commands();
+
+    for expected in &[
+        "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+         oifname br-reverseproxy meta mark set 0xdf accept comment \"DFW-MARKER:section;\
+         wider_world_to_container;source=resources/test/provenance/10-web.toml:6\"",
+        "add rule inet dfw forward tcp dport 80 ip daddr 172.24.0.4 meta iifname eth0 \
+         oifname br-reverseproxy meta mark set 0xdf accept comment \"DFW-MARKER:section;\
+         wider_world_to_container;source=resources/test/provenance/20-egress.toml:10\"",
+        "add rule inet dfw forward ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+         oifname eth0 meta mark set 0xdf drop comment \"DFW-MARKER:section;\
+         container_to_wider_world;source=resources/test/provenance/20-egress.toml:5\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+
+    // Without `annotate_rules` only the section is part of the comment
+    dfw.defaults.as_mut().unwrap().annotate_rules = false;
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+    assert!(!commands.iter().any(|command| command.contains("source=")));
+}
+
+#[test]
+fn generate_conntrack_zones() {
+    let zone_rules = |conntrack_zones: bool| -> (Vec<String>, Vec<String>) {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            conntrack_zones = {}
+            "#,
+            conntrack_zones
+        ))
+        .unwrap();
+        let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+        let chains = ruleset
+            .preamble
+            .iter()
+            .filter(|command| command.starts_with("add chain inet dfw prerouting"))
+            .cloned()
+            .collect();
+        let rules = ruleset
+            .commands()
+            .into_iter()
+            .filter(|command| command.starts_with("add rule inet dfw prerouting"))
+            .collect();
+        (chains, rules)
+    };
+
+    let (chains, rules) = zone_rules(true);
+    assert_eq!(
+        vec!["add chain inet dfw prerouting { type filter hook prerouting priority -305 ; }"],
+        chains
+    );
+
+    // Every network is assigned its own zone, on the bridge of the network
+    let zones = rules
+        .iter()
+        .map(|rule| {
+            let zone_rule = rule
+                .strip_prefix("add rule inet dfw prerouting meta iifname ")
+                .and_then(|rule| rule.strip_suffix(" comment \"DFW-MARKER:section;defaults\""))
+                .and_then(|rule| {
+                    let mut parts = rule.splitn(2, " ct original zone set ");
+                    Some((parts.next()?.to_owned(), parts.next()?.parse::<u16>().ok()?))
+                });
+            zone_rule.unwrap_or_else(|| panic!("unexpected rule: {}", rule))
+        })
+        .collect::<BTreeMap<_, _>>();
+    assert_eq!(7, zones.len());
+    assert_ne!(zones["br-networkaffff"], zones["br-networkbffff"]);
+    assert_eq!(
+        zones.len(),
+        zones.values().collect::<BTreeSet<_>>().len(),
+        "{:?}",
+        zones
+    );
+    assert!(!zones.values().any(|zone| *zone == 0));
+
+    // The zones are stable across runs
+    assert_eq!(rules, zone_rules(true).1);
+
+    assert_eq!(
+        (Vec::<String>::new(), Vec::<String>::new()),
+        zone_rules(false)
+    );
+}
+
+#[test]
+fn generate_expose_port_without_dnat() {
+    let exposed = |expose_port: &str| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = {}
+            source_cidr_v4 = "192.0.2.0/24"
+            "#,
+            expose_port
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains("section;wider_world_to_container"))
+            .collect()
+    };
+
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward tcp dport 80 ip saddr 192.0.2.0/24 ip daddr 172.24.0.4 \
+             meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip dfw prerouting tcp dport 8080 ip saddr 192.0.2.0/24 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:80 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        exposed("{ host_port = 8080, container_port = 80 }")
+    );
+
+    // Without DNAT only the traffic to the container on the host port is allowed, the container
+    // port is ignored.
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward tcp dport 8080 ip saddr 192.0.2.0/24 ip daddr 172.24.0.4 \
+             meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        exposed("{ host_port = 8080, container_port = 80, dnat = false }")
+    );
+}
+
+#[test]
+fn generate_notrack() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = { host_port = 8080, dnat = false }
+        notrack = true
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+
+    // The untracking happens before conntrack sees the packets
+    assert!(ruleset.preamble.contains(
+        &"add chain inet dfw prerouting { type filter hook prerouting priority -305 ; }".to_owned()
+    ));
+    assert_eq!(
+        vec![
+            "add rule inet dfw prerouting tcp dport 8080 ip daddr 172.24.0.4 meta iifname eth0 \
+             meta mark set 0xdf notrack comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule inet dfw prerouting tcp sport 8080 ip saddr 172.24.0.4 \
+             meta iifname br-reverseproxy meta mark set 0xdf notrack \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule inet dfw forward tcp sport 8080 ip saddr 172.24.0.4 \
+             meta iifname br-reverseproxy oifname eth0 meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule inet dfw forward tcp dport 8080 ip daddr 172.24.0.4 meta iifname eth0 \
+             oifname br-reverseproxy meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        ruleset
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains("section;wider_world_to_container"))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn generate_notrack_with_dnat() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        notrack = true
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "exposed port 443 cannot bypass connection tracking, DNAT relies on it",
+        generate(&dfw, &full_example_inventory())
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+#[test]
+fn generate_ct_timeout() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        ct_timeouts.scanned = { tcp_established = "1h", tcp_close = "10s" }
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        ct_timeout = "scanned"
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+
+    // The policy is assigned before conntrack sees the packets
+    assert!(ruleset.preamble.contains(
+        &"add chain inet dfw prerouting { type filter hook prerouting priority -305 ; }".to_owned()
+    ));
+    assert!(ruleset.preamble.contains(
+        &"add ct timeout inet dfw scanned { protocol tcp ; l3proto ip ; \
+          policy = { close: 10, established: 3600 } ; }"
+            .to_owned()
+    ));
+    assert_eq!(
+        vec![
+            "add rule inet dfw prerouting tcp dport 443 meta iifname eth0 meta nfproto ipv4 \
+             meta mark set 0xdf ct timeout set \"scanned\" \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        ruleset
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains("ct timeout set"))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn generate_ct_timeout_protocol_mismatch() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        ct_timeouts.dns = { udp_replied = "30s" }
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        ct_timeout = "dns"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "conntrack timeout policy `dns` applies to udp, it cannot be assigned to the tcp port 443",
+        generate(&dfw, &full_example_inventory())
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+#[test]
+fn generate_clamp_mss_to_pmtu() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = ["443/tcp", "443/udp"]
+        clamp_mss_to_pmtu = true
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    // The SYN packets are clamped in both directions ahead of the stateful preamble, UDP ports
+    // are left alone
+    assert_eq!(
+        vec![
+            "insert rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+             oifname br-reverseproxy meta mark set 0xdf tcp flags syn tcp option maxseg size set \
+             rt mtu comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "insert rule inet dfw forward tcp sport 443 ip saddr 172.24.0.4 \
+             meta iifname br-reverseproxy oifname eth0 meta mark set 0xdf tcp flags syn \
+             tcp option maxseg size set rt mtu \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        commands
+            .iter()
+            .filter(|command| command.contains("maxseg"))
+            .map(String::as_str)
+            .collect::<Vec<_>>()
+    );
+    assert!(commands.contains(
+        &"add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+          oifname br-reverseproxy meta mark set 0xdf accept \
+          comment \"DFW-MARKER:section;wider_world_to_container\""
+            .to_owned()
+    ));
+}
+
+#[test]
+fn generate_source_cidr_file() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        source_cidr_v4 = ["@file:allow-list.txt", "192.0.2.250"]
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    let set = commands
+        .iter()
+        .find_map(|command| {
+            command
+                .strip_prefix("add set inet dfw ")?
+                .split(' ')
+                .next()
+                .map(str::to_owned)
+        })
+        .unwrap();
+    for family in &["inet", "ip"] {
+        let set_commands = commands
+            .iter()
+            .filter(|command| command.contains(&format!(" {} dfw {} ", family, set)))
+            .cloned()
+            .collect::<Vec<_>>();
+        assert_eq!(
+            vec![
+                format!(
+                    "add set {} dfw {} {{ type ipv4_addr ; flags interval ; auto-merge ; }}",
+                    family, set
+                ),
+                format!(
+                    "add element {} dfw {} {{ 192.0.2.0/24, 198.51.100.7, 203.0.113.0/25 }}",
+                    family, set
+                ),
+            ],
+            set_commands
+        );
+    }
+    assert!(commands.contains(&format!("flush set inet dfw {}", set)));
+
+    // The set takes the place of the file, the other CIDRs are kept as they are
+    let source_addresses = commands
+        .iter()
+        .filter(|command| command.contains("section;wider_world_to_container"))
+        .filter_map(|command| command.split(" ip saddr ").nth(1)?.split(' ').next())
+        .collect::<Vec<_>>();
+    let set_reference = format!("@{}", set);
+    assert_eq!(
+        vec![
+            set_reference.as_str(),
+            "192.0.2.250",
+            set_reference.as_str(),
+            "192.0.2.250"
+        ],
+        source_addresses
+    );
+
+    // The CIDR files are read through the inventory, their contents are validated nevertheless
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        source_cidr_v4 = ["@file:malformed.txt"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        "line 2 of CIDR file malformed.txt is not a valid IPv4 CIDR: '198.51.100.0/33'",
+        generate(&dfw, &full_example_inventory())
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+/// Inventory resolving hostnames from a fixed list instead of through DNS, reporting the given
+/// current ruleset.
+struct ResolvingInventory {
+    inventory: MockInventory,
+    hostnames: Vec<(&'static str, Vec<&'static str>)>,
+    current_ruleset: Option<String>,
+}
+
+impl ContainerInventory for ResolvingInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        self.inventory.containers()
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        self.inventory.networks()
+    }
+
+    fn current_ruleset(&self) -> Option<String> {
+        self.current_ruleset.clone()
+    }
+
+    fn resolve_hostname(&self, hostname: &str) -> Result<Vec<IpAddr>, Error> {
+        self.hostnames
+            .iter()
+            .find(|(name, _)| *name == hostname)
+            .map(|(_, addresses)| {
+                addresses
+                    .iter()
+                    .map(|address| address.parse().unwrap())
+                    .collect()
+            })
+            .ok_or_else(|| format_err!("Name or service not known"))
+    }
+}
+
+#[test]
+fn generate_source_cidr_dns() {
+    let dfw = |dns_failure_policy: &str| -> DFW {
+        toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+            dns_failure_policy = "{}"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            source_cidr_v4 = "@dns:partner.example.com"
+            source_cidr_v6 = "@dns:partner.example.com"
+            "#,
+            dns_failure_policy
+        ))
+        .unwrap()
+    };
+    let inventory = |hostnames, current_ruleset| ResolvingInventory {
+        inventory: full_example_inventory(),
+        hostnames,
+        current_ruleset,
+    };
+    let set_elements = |commands: &[String]| -> BTreeSet<String> {
+        commands
+            .iter()
+            .filter(|command| command.starts_with("add element "))
+            .cloned()
+            .collect()
+    };
+
+    // The addresses of the hostname are loaded into one set per address family
+    let commands = generate_idempotent(
+        &dfw("keep"),
+        &inventory(
+            vec![(
+                "partner.example.com",
+                vec!["192.0.2.7", "2001:db8::7", "198.51.100.7"],
+            )],
+            None,
+        ),
+    )
+    .commands();
+    let set_name = |prefix: &str| {
+        commands
+            .iter()
+            .find_map(|command| {
+                command
+                    .split(' ')
+                    .nth(4)
+                    .filter(|set| command.starts_with("add set ") && set.starts_with(prefix))
+                    .map(str::to_owned)
+            })
+            .unwrap()
+    };
+    let (set_v4, set_v6) = (set_name("fqdn4_"), set_name("fqdn6_"));
+    assert_eq!(
+        vec![
+            format!(
+                "add element inet dfw {} {{ 192.0.2.7, 198.51.100.7 }}",
+                set_v4
+            ),
+            format!(
+                "add element ip dfw {} {{ 192.0.2.7, 198.51.100.7 }}",
+                set_v4
+            ),
+            format!("add element ip6 dfw {} {{ 2001:db8::7 }}", set_v6),
+        ]
+        .into_iter()
+        .collect::<BTreeSet<_>>(),
+        set_elements(&commands)
+    );
+    assert!(commands.contains(&format!(
+        "add set ip6 dfw {} {{ type ipv6_addr ; flags interval ; auto-merge ; }}",
+        set_v6
+    )));
+    assert!(commands
+        .iter()
+        .any(|command| command.contains(&format!(" ip saddr @{} ", set_v4))));
+    assert!(commands
+        .iter()
+        .any(|command| command.contains(&format!(" ip6 saddr @{} ", set_v6))));
+
+    // If the hostname cannot be resolved, the sets keep the elements of the current ruleset
+    let current_ruleset = format!(
+        "table inet dfw {{ # handle 1\n\
+         \tset {} {{ # handle 4\n\
+         \t\ttype ipv4_addr\n\
+         \t\tflags interval\n\
+         \t\tauto-merge\n\
+         \t\telements = {{ 192.0.2.7,\n\
+         \t\t\t     198.51.100.7 }}\n\
+         \t}}\n\
+         }}\n",
+        set_v4
+    );
+    let commands = generate(
+        &dfw("keep"),
+        &inventory(Vec::new(), Some(current_ruleset.clone())),
+    )
+    .unwrap()
+    .commands();
+    assert_eq!(
+        vec![
+            format!(
+                "add element inet dfw {} {{ 192.0.2.7, 198.51.100.7 }}",
+                set_v4
+            ),
+            format!(
+                "add element ip dfw {} {{ 192.0.2.7, 198.51.100.7 }}",
+                set_v4
+            ),
+        ]
+        .into_iter()
+        .collect::<BTreeSet<_>>(),
+        set_elements(&commands)
+    );
+    assert!(commands.contains(&format!("flush set ip6 dfw {}", set_v6)));
+
+    // Unless the policy requires the resolution to succeed
+    let error = generate(&dfw("error"), &inventory(Vec::new(), Some(current_ruleset))).unwrap_err();
+    assert_eq!(
+        "failed to resolve hostname `partner.example.com`: Name or service not known",
+        error.to_string()
+    );
+}
+
+#[test]
+fn generate_drain() {
+    let forward_rules = |drain: bool| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            drain = {}
+            "#,
+            drain
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains("section;wider_world_to_container"))
+            .collect()
+    };
+
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+             oifname br-reverseproxy meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip6 dfw prerouting tcp dport 443 meta iifname eth0 meta mark set 0xdf \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        forward_rules(false)
+    );
+
+    // While draining, new connections are dropped and only ongoing connections are accepted. The
+    // DNAT rule is kept, established connections continue to be translated by conntrack.
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+             oifname br-reverseproxy ct state new meta mark set 0xdf drop \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+             oifname br-reverseproxy ct state { related, established } meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip6 dfw prerouting tcp dport 443 meta iifname eth0 meta mark set 0xdf \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        forward_rules(true)
+    );
+}
+
+#[test]
+fn generate_host_network_container() {
+    let inventory = MockInventory {
+        containers: vec![
+            ("my_dnsmasq", vec!["host"]),
+            ("my_reverseproxy", vec!["reverseproxy_network"]),
+        ],
+    };
+    let wider_world_rules = |rule: &str| -> Result<Vec<String>, Error> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "host"
+            dst_container = "my_dnsmasq"
+            {}
+            "#,
+            rule
+        ))
+        .unwrap();
+
+        Ok(generate(&dfw, &inventory)?
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains("section;wider_world_to_container"))
+            .collect())
+    };
+
+    // The ports of the container are ports of the host, they are accepted in the input chain
+    // without being translated.
+    assert_eq!(
+        vec![
+            "add rule inet dfw input udp dport 53 meta iifname eth0 meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule inet dfw input tcp dport 8080 meta iifname eth0 meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        wider_world_rules(r#"expose_port = ["53/udp", "8080"]"#).unwrap()
+    );
+
+    // Source CIDRs restrict the traffic to the families they are specified for.
+    assert_eq!(
+        vec![
+            "add rule inet dfw input tcp dport 443 ip saddr 192.0.2.0/24 meta iifname eth0 \
+             meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        wider_world_rules(
+            r#"
+            expose_port = 443
+            source_cidr_v4 = ["192.0.2.0/24"]
+            "#
+        )
+        .unwrap()
+    );
+
+    // Restricting the families restricts the rules through `meta nfproto`.
+    assert_eq!(
+        vec![
+            "add rule inet dfw input tcp dport 443 meta iifname eth0 meta nfproto ipv6 \
+             meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        wider_world_rules(
+            r#"
+            expose_port = 443
+            families = ["v6"]
+            "#
+        )
+        .unwrap()
+    );
+
+    // The traffic cannot be translated to another port of the container.
+    let error = wider_world_rules(r#"expose_port = { host_port = 8080, container_port = 80 }"#)
+        .unwrap_err();
+    assert!(error.to_string().contains(
+        "host port 8080 cannot be translated to port 80 of container `my_dnsmasq`, it is \
+         attached to the host network"
+    ));
+
+    let error = wider_world_rules(
+        r#"
+        expose_port = 53
+        dnat_to = "192.0.2.53"
+        "#,
+    )
+    .unwrap_err();
+    assert!(error.to_string().contains(
+        "`dnat_to` cannot be used for container `my_dnsmasq`, it is attached to the host network"
+    ));
+}
+
+#[test]
+fn generate_require_healthy() {
+    let inventory: StaticInventory = toml::from_str(
+        r#"
+        [networks.frontend]
+        id = "6d4c1b5e9f0a8c3d2e1f0a9b"
+
+        [containers.healthy]
+        health = "healthy"
+        networks.frontend = { ipv4_address = "172.18.0.2/16" }
+
+        [containers.unhealthy]
+        health = "unhealthy"
+        networks.frontend = { ipv4_address = "172.18.0.3/16" }
+
+        [containers.starting]
+        health = "starting"
+        networks.frontend = { ipv4_address = "172.18.0.4/16" }
+
+        [containers.unchecked]
+        networks.frontend = { ipv4_address = "172.18.0.5/16" }
+        "#,
+    )
+    .unwrap();
+    let exposed = |require_healthy: bool| -> Vec<String> {
+        let rules = ["healthy", "unhealthy", "starting", "unchecked"]
+            .iter()
+            .enumerate()
+            .map(|(index, container)| {
+                format!(
+                    r#"
+                    [[wider_world_to_container.rules]]
+                    network = "frontend"
+                    dst_container = "{}"
+                    expose_port = {}
+                    require_healthy = {}
+                    "#,
+                    container,
+                    8000 + index,
+                    require_healthy
+                )
+            })
+            .collect::<String>();
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+            {}
+            "#,
+            rules
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &inventory)
+            .commands()
+            .into_iter()
+            .filter(|command| command.starts_with("add rule ip dfw prerouting"))
+            .collect()
+    };
+
+    // Only the healthy container and the container without healthcheck are exposed
+    assert_eq!(
+        vec![
+            "add rule ip dfw prerouting tcp dport 8000 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.18.0.2:8000 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip dfw prerouting tcp dport 8003 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.18.0.5:8003 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        exposed(true)
+    );
+    assert_eq!(4, exposed(false).len());
+}
+
+fn dnat_map_rules(containers: u16, nft_version: Option<&str>) -> Vec<String> {
+    let inventory = (0..containers)
+        .map(|index| {
+            format!(
+                r#"
+                [containers.web{}]
+                networks.frontend = {{ ipv4_address = "172.18.0.{}/16" }}
+                "#,
+                index,
+                index + 2
+            )
+        })
+        .collect::<String>();
+    let mut inventory: StaticInventory = toml::from_str(&format!(
+        r#"
+        [networks.frontend]
+        id = "6d4c1b5e9f0a8c3d2e1f0a9b"
+        {}
+        "#,
+        inventory
+    ))
+    .unwrap();
+    inventory.host_facts.nft_version = nft_version.map(|version| version.parse().unwrap());
+    let rules = (0..containers)
+        .map(|index| {
+            format!(
+                r#"
+                [[wider_world_to_container.rules]]
+                network = "frontend"
+                dst_container = "web{}"
+                expose_port = {{ host_port = {}, container_port = 80 }}
+                "#,
+                index,
+                8000 + index
+            )
+        })
+        .collect::<String>();
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        {}
+        "#,
+        rules
+    ))
+    .unwrap();
+
+    generate_idempotent(&dfw, &inventory)
+        .commands()
+        .into_iter()
+        .filter(|command| command.starts_with("add rule ip dfw prerouting"))
+        .collect()
+}
+
+#[test]
+fn generate_dnat_map() {
+    assert_eq!(
+        vec![
+            "add rule ip dfw prerouting \
+             tcp dport { 8000, 8001, 8002, 8003, 8004, 8005, 8006, 8007 } \
+             meta iifname eth0 ct state new meta mark set 0xdf \
+             dnat ip addr . port to tcp dport map { \
+             8000 : 172.18.0.2 . 80, 8001 : 172.18.0.3 . 80, 8002 : 172.18.0.4 . 80, \
+             8003 : 172.18.0.5 . 80, 8004 : 172.18.0.6 . 80, 8005 : 172.18.0.7 . 80, \
+             8006 : 172.18.0.8 . 80, 8007 : 172.18.0.9 . 80 } \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        dnat_map_rules(8, None)
+    );
+
+    // Small groups are kept as individual rules
+    assert_eq!(7, dnat_map_rules(7, None).len());
+
+    // Versions of nft without support for NAT concatenations get individual rules
+    let rules = dnat_map_rules(8, Some("0.9.3"));
+    assert_eq!(8, rules.len());
+    assert!(rules.iter().all(|rule| !rule.contains(" map ")));
+}
+
+#[test]
+fn generate_section_order() {
+    let ruleset = |section_order: &str| -> RuleSet {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+            {}
+
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            dst_container = "container_b"
+            verdict = "accept"
+
+            [container_to_host]
+            default_policy = "accept"
+
+            [[container_to_host.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            verdict = "drop"
+            "#,
+            section_order
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+    };
+    let sections = |ruleset: &RuleSet| -> Vec<Section> {
+        ruleset
+            .sections
+            .iter()
+            .map(|(section, _)| *section)
+            .collect()
+    };
+    let chains = |ruleset: &RuleSet| -> Vec<String> {
+        ruleset
+            .commands()
+            .into_iter()
+            .filter(|command| {
+                command.contains("section;container_to_container")
+                    || command.contains("section;container_to_host")
+            })
+            .filter_map(|command| {
+                let marker = command.rsplit("DFW-MARKER:section;").next()?;
+                Some(marker.trim_end_matches('"').to_owned())
+            })
+            .collect()
+    };
+
+    let default_order = ruleset("");
+    assert_eq!(Section::VALUES.to_vec(), sections(&default_order));
+
+    let custom_order =
+        ruleset(r#"section_order = ["container_to_host", "container_to_container"]"#);
+    assert_eq!(
+        vec![
+            Section::ContainerToHost,
+            Section::ContainerToContainer,
+            Section::Initialization,
+            Section::Defaults,
+            Section::Dns,
+            Section::ContainerToWiderWorld,
+            Section::WiderWorldToContainer,
+            Section::ContainerDNAT,
+        ],
+        sections(&custom_order)
+    );
+
+    // The rules of both sections are emitted in the requested order
+    let default_chains = chains(&default_order);
+    let custom_chains = chains(&custom_order);
+    assert!(!custom_chains.is_empty());
+    assert_eq!(default_chains.len(), custom_chains.len());
+    let first_c2c = |chains: &[String]| {
+        chains
+            .iter()
+            .position(|chain| chain == "container_to_container")
+    };
+    let last_c2h = |chains: &[String]| {
+        chains
+            .iter()
+            .rposition(|chain| chain == "container_to_host")
+    };
+    assert!(last_c2h(&custom_chains) < first_c2c(&custom_chains));
+    assert!(last_c2h(&default_chains) > first_c2c(&default_chains));
+}
+
+#[test]
+fn generate_disabled_section() {
+    let mut dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        enabled = false
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = "accept"
+
+        [container_to_host]
+        default_policy = "accept"
+
+        [[container_to_host.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        verdict = "drop"
+        "#,
+    )
+    .unwrap();
+    let section_rules = |dfw: &DFW, section: Section| -> Vec<String> {
+        generate_idempotent(dfw, &full_example_inventory())
+            .sections
+            .into_iter()
+            .filter(|(s, _)| *s == section)
+            .flat_map(|(_, rules)| rules)
+            .collect()
+    };
+
+    // Neither the rules nor the default policy of the disabled section are generated
+    assert!(section_rules(&dfw, Section::ContainerToContainer).is_empty());
+    let container_to_host = section_rules(&dfw, Section::ContainerToHost);
+    assert!(container_to_host
+        .iter()
+        .any(|rule| rule.ends_with("drop comment \"DFW-MARKER:section;container_to_host\"")));
+
+    dfw.container_to_container.as_mut().unwrap().enabled = Some(true);
+    let container_to_container = section_rules(&dfw, Section::ContainerToContainer);
+    assert!(
+        container_to_container.contains(&"add chain inet dfw forward { policy drop ; }".to_owned())
+    );
+    assert_eq!(
+        container_to_host,
+        section_rules(&dfw, Section::ContainerToHost)
+    );
+}
+
+#[test]
+fn generate_family_default_policy() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_wider_world]
+        default_policy = { v4 = "accept", v6 = "drop" }
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    let default_rules = commands
+        .iter()
+        .filter(|command| command.contains("meta iifname br-networkaffff oifname eth0"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward meta iifname br-networkaffff oifname eth0 \
+             meta nfproto ipv4 meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;container_to_wider_world\"",
+            "add rule inet dfw forward meta iifname br-networkaffff oifname eth0 \
+             meta nfproto ipv6 meta mark set 0xdf drop \
+             comment \"DFW-MARKER:section;container_to_wider_world\"",
+        ],
+        default_rules
+    );
+}
+
+#[test]
+fn generate_stateful_verdict() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = { new = "reject", established = "accept" }
+
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        network = "network_a"
+        verdict = { new = "drop", established = "drop" }
+
+        [container_to_host]
+        default_policy = "accept"
+
+        [[container_to_host.rules]]
+        network = "network_b"
+        matches = "tcp dport 22"
+        verdict = { new = "reject", established = "accept" }
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+         meta iifname br-commonnetwor oifname br-commonnetwor ct state new \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_container\"",
+        "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+         meta iifname br-commonnetwor oifname br-commonnetwor ct state { related, established } \
+         meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_container\"",
+        // Equal verdicts don't depend on the conntrack state
+        "add rule inet dfw forward meta iifname br-networkaffff oifname eth0 meta mark set 0xdf \
+         drop comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw input meta iifname br-networkbffff ct state new meta mark set 0xdf \
+         tcp dport 22 reject comment \"DFW-MARKER:section;container_to_host\"",
+        "add rule inet dfw input meta iifname br-networkbffff ct state { related, established } \
+         meta mark set 0xdf tcp dport 22 accept comment \"DFW-MARKER:section;container_to_host\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_min_ct_bytes() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        min_ct_bytes = "500 kbytes"
+        verdict = "accept"
+
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        min_ct_bytes = "100 mbytes"
+        verdict = "drop"
+
+        [container_to_host]
+        default_policy = "accept"
+
+        [[container_to_host.rules]]
+        network = "network_b"
+        min_ct_bytes = "1 gbytes"
+        verdict = "reject"
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+         meta iifname br-commonnetwor oifname br-commonnetwor ct bytes > 512000 \
+         meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_container\"",
+        "add rule inet dfw forward ip saddr 172.19.0.2 meta iifname br-commonnetwor oifname eth0 \
+         ct bytes > 104857600 meta mark set 0xdf drop \
+         comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw input meta iifname br-networkbffff ct bytes > 1073741824 \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_host\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_min_ct_bytes_invalid() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_host]
+        default_policy = "accept"
+
+        [[container_to_host.rules]]
+        network = "network_b"
+        min_ct_bytes = "100 mb"
+        verdict = "reject"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "minimum connection size '100 mb' is not a valid size, e.g. '100 mbytes'",
+        generate(&dfw, &full_example_inventory())
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+#[test]
+fn generate_vlan_id() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_wider_world]
+        default_policy = "drop"
+
+        [[container_to_wider_world.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        verdict = "accept"
+        vlan_id = 100
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        vlan_id = 4094
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        "add rule inet dfw forward ip saddr 172.19.0.2 meta iifname br-commonnetwor oifname eth0 \
+         vlan id 100 meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+         oifname br-reverseproxy vlan id 4094 meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 vlan id 4094 \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip6 dfw prerouting tcp dport 443 meta iifname eth0 vlan id 4094 \
+         meta mark set 0xdf comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_dscp() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "reverseproxy_network"
+        src_container = "my_reverseproxy"
+        dst_container = "my_webserver"
+        verdict = "accept"
+        dscp = 18
+
+        [[container_to_container.rules]]
+        network = "internal_network"
+        verdict = "accept"
+        dscp = "cs1"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = "5060/udp"
+        dscp = "ef"
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        "add rule inet dfw forward ip saddr 172.24.0.4 ip daddr 172.24.0.5 \
+         meta iifname br-reverseproxy oifname br-reverseproxy meta mark set 0xdf \
+         ip dscp set 18 accept comment \"DFW-MARKER:section;container_to_container\"",
+        "add rule inet dfw forward meta iifname br-internalnetw oifname br-internalnetw \
+         meta mark set 0xdf ip dscp set 8 accept \
+         comment \"DFW-MARKER:section;container_to_container\"",
+        "add rule inet dfw forward meta iifname br-internalnetw oifname br-internalnetw \
+         meta mark set 0xdf ip6 dscp set 8 accept \
+         comment \"DFW-MARKER:section;container_to_container\"",
+        "add rule inet dfw forward udp dport 5060 ip daddr 172.24.0.4 \
+         meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf ip dscp set 46 accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip6 dfw prerouting udp dport 5060 meta iifname eth0 meta mark set 0xdf \
+         ip6 dscp set 46 comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_multiple_external_network_interfaces() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        network = "internal_network"
+        verdict = "reject"
+        external_network_interface = ["eth1", "eth2"]
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        external_network_interface = ["eth1", "eth2"]
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        "add rule inet dfw forward meta iifname br-internalnetw oifname { eth1, eth2 } \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 \
+         meta iifname { eth1, eth2 } oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 443 meta iifname { eth1, eth2 } \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_external_network_interface_inheritance() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        network = "internal_network"
+        verdict = "reject"
+
+        [[container_to_wider_world.rules]]
+        network = "internal_network"
+        verdict = "drop"
+        external_network_interface = "eth1"
+
+        [[container_to_wider_world.rules]]
+        network = "internal_network"
+        verdict = "accept"
+        external_network_interface = "none"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 8443
+        external_network_interface = "eth1"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 9443
+        external_network_interface = "none"
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        // Inherited from the defaults
+        "add rule inet dfw forward meta iifname br-internalnetw oifname eth0 \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 \
+         meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        // Explicit
+        "add rule inet dfw forward meta iifname br-internalnetw oifname eth1 \
+         meta mark set 0xdf drop comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward tcp dport 8443 ip daddr 172.24.0.4 \
+         meta iifname eth1 oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 8443 meta iifname eth1 \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:8443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        // No interface match at all
+        "add rule inet dfw forward meta iifname br-internalnetw \
+         meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward tcp dport 9443 ip daddr 172.24.0.4 \
+         meta oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 9443 \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:9443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+}
+
+#[test]
+fn generate_external_network_interfaces_per_family() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = { v4 = "eth0", v6 = "eth1" }
+        ipv6_masquerade = true
+
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        network = "internal_network"
+        verdict = "reject"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    for expected in &[
+        // Rules applying to both families are split into one rule per family
+        "add rule inet dfw forward meta iifname br-internalnetw oifname eth0 meta nfproto ipv4 \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward meta iifname br-internalnetw oifname eth1 meta nfproto ipv6 \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward meta iifname br-internalnetw oifname eth0 meta nfproto ipv4 \
+         meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule inet dfw forward meta iifname br-internalnetw oifname eth1 meta nfproto ipv6 \
+         meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_wider_world\"",
+        // The rules of a single family use the interface of that family
+        "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 \
+         meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+         ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+        "add rule ip6 dfw prerouting tcp dport 443 meta iifname eth1 meta mark set 0xdf \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+
+    // The traffic of each family is only translated on the interface of the family
+    let nat = commands
+        .iter()
+        .filter(|command| command.contains(" dfw postrouting meta oifname "))
+        .map(|command| {
+            let words = command.split_whitespace().collect::<Vec<_>>();
+            (words[2], words[7])
+        })
+        .collect::<BTreeSet<_>>();
+    assert_eq!(
+        vec![("ip", "eth0"), ("ip6", "eth1")],
+        nat.into_iter().collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn generate_no_external_network_interface_without_defaults() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 9443
+        external_network_interface = "none"
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    // Without an interface to inherit the rule is skipped, unless it explicitly applies to all
+    // interfaces.
+    assert!(!commands
+        .iter()
+        .any(|command| command.contains("dport 443 ")));
+    assert!(commands.contains(
+        &"add rule ip dfw prerouting tcp dport 9443 \
+          ct state new meta mark set 0xdf dnat 172.24.0.4:9443 \
+          comment \"DFW-MARKER:section;wider_world_to_container\""
+            .to_owned()
+    ));
+}
+
+#[test]
+fn generate_log_unmatched() {
+    let generate_log_rules = |section: &str| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = ["eth0", "eth1"]
+
+            [wider_world_to_container]
+            {}
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            "#,
+            section
+        ))
+        .unwrap();
+        generate_idempotent(&dfw, &full_example_inventory())
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains(" log "))
+            .collect()
+    };
+
+    assert!(generate_log_rules("").is_empty());
+    assert!(generate_log_rules("log_unmatched = false").is_empty());
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward meta iifname { eth0, eth1 } meta mark set 0xdf \
+             limit name dfw_log log prefix \"wwtc-nomatch: \" \
+             comment \"DFW-MARKER:section;wider_world_to_container\""
+        ],
+        generate_log_rules("log_unmatched = true")
+    );
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward meta iifname { eth0, eth1 } meta mark set 0xdf \
+             limit name dfw_log log prefix \"scan: \" level notice \
+             comment \"DFW-MARKER:section;wider_world_to_container\""
+        ],
+        generate_log_rules(r#"log_unmatched = { prefix = "scan: ", level = "notice" }"#)
+    );
+}
+
+#[test]
+fn generate_quota() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        quota = { bytes = "10 gbytes" }
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_webserver"
+        expose_port = 8080
+        quota = { bytes = "500 mbytes", over = false }
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    let quota_name = |bytes: &str| -> String {
+        let suffix = format!(" {{ {} ; }}", bytes);
+        let command = commands
+            .iter()
+            .find(|command| {
+                command.starts_with("add quota inet dfw ") && command.ends_with(&suffix)
+            })
+            .unwrap_or_else(|| panic!("missing quota: {}", bytes));
+        command["add quota inet dfw ".len()..command.len() - suffix.len()].to_owned()
+    };
+    let over = quota_name("over 10 gbytes");
+    let until = quota_name("until 500 mbytes");
+    assert_ne!(over, until);
+
+    for expected in &[
+        format!(
+            "insert rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 \
+             meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf quota name {} drop \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            over
+        ),
+        format!(
+            "insert rule inet dfw forward tcp sport 443 ip saddr 172.24.0.4 \
+             meta iifname br-reverseproxy oifname eth0 meta mark set 0xdf quota name {} drop \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            over
+        ),
+        format!(
+            "insert rule inet dfw forward tcp dport 8080 ip daddr 172.24.0.5 \
+             meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf quota name {} \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            until
+        ),
+        // The rules accepting the traffic are unaffected by the quota
+        "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 \
+         meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\""
+            .to_owned(),
+    ] {
+        assert!(commands.contains(expected), "missing command: {}", expected);
+    }
+}
+
+#[test]
+fn generate_mirror_to_without_external_network_interface() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_host]
+        default_policy = "accept"
+
+        [[container_to_host.rules]]
+        network = "internal_network"
+        verdict = "accept"
+        mirror_to = "10.0.0.250"
+        "#,
+    )
+    .unwrap();
+
+    assert!(generate(&dfw, &full_example_inventory()).is_err());
+}
+
+fn c2c_rule_ids(rules: &str) -> Vec<RuleId> {
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+        {}
+        "#,
+        rules
+    ))
+    .unwrap();
+    generate_idempotent(&dfw, &full_example_inventory())
+        .rule_ids()
+        .into_iter()
+        .filter(|(_, section, rule)| {
+            *section == Section::ContainerToContainer && rule.starts_with("add rule")
+        })
+        .map(|(rule_id, _, _)| rule_id)
+        .collect()
+}
+
+#[test]
+fn rule_id_stable() {
+    let rule_a = r#"
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = "accept"
+        "#;
+    let rule_b = r#"
+        [[container_to_container.rules]]
+        network = "network_a"
+        verdict = "reject"
+        matches = "tcp dport 80"
+        "#;
+
+    let ids = c2c_rule_ids(&format!("{}{}", rule_a, rule_b));
+    assert_eq!(2, ids.len());
+    assert_eq!(ids, c2c_rule_ids(&format!("{}{}", rule_a, rule_b)));
+
+    // The identity doesn't depend on the order the rules are generated in
+    let mut reordered = c2c_rule_ids(&format!("{}{}", rule_b, rule_a));
+    reordered.reverse();
+    assert_eq!(ids, reordered);
+
+    // Identical rules still have distinct identities
+    let ids = c2c_rule_ids(&format!("{}{}", rule_a, rule_a));
+    assert_eq!(2, ids.len());
+    assert_ne!(ids[0], ids[1]);
+}
+
+#[test]
+fn rule_id_sensitive_to_inputs() {
+    let rule = |network: &str, containers: &str, verdict: &str, matches: &str| {
+        c2c_rule_ids(&format!(
+            r#"
+            [[container_to_container.rules]]
+            network = "{}"
+            {}
+            verdict = "{}"
+            matches = "{}"
+            "#,
+            network, containers, verdict, matches
+        ))
+    };
+    let containers = r#"src_container = "container_a""#;
+
+    let base = rule("common_network", containers, "accept", "tcp dport 80");
+    for changed in &[
+        rule("network_a", containers, "accept", "tcp dport 80"),
+        rule(
+            "common_network",
+            r#"src_container = "container_b""#,
+            "accept",
+            "tcp dport 80",
+        ),
+        rule(
+            "common_network",
+            r#"dst_container = "container_a""#,
+            "accept",
+            "tcp dport 80",
+        ),
+        rule("common_network", containers, "drop", "tcp dport 80"),
+        rule("common_network", containers, "accept", "tcp dport 81"),
+        rule("common_network", containers, "accept", "udp dport 80"),
+    ] {
+        assert_eq!(1, changed.len());
+        assert_ne!(base, *changed);
+    }
+
+    // The section is part of the identity
+    let command = "add rule inet dfw forward tcp dport 80 accept";
+    assert_ne!(
+        RuleId::new(Section::ContainerToContainer, command),
+        RuleId::new(Section::ContainerToHost, command)
+    );
+}
+
+#[test]
+fn rule_id_from_str() {
+    let rule_id = RuleId::new(Section::Defaults, "add rule inet dfw input accept");
+    assert_eq!(Ok(rule_id), rule_id.to_string().parse());
+    assert_eq!(16, rule_id.to_string().len());
+
+    assert!("0123".parse::<RuleId>().is_err());
+    assert!("0123456789abcdeg".parse::<RuleId>().is_err());
+}
+
+/// Inventory recreating the networks of the wrapped inventory, which gives them new IDs and thus new
+/// bridges.
+struct RecreatedNetworks(MockInventory);
+
+impl ContainerInventory for RecreatedNetworks {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        self.0.containers()
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        Ok(self
+            .0
+            .networks()?
+            .into_iter()
+            .map(|network| Network {
+                id: format!("1{}", network.id),
+                ..network
+            })
+            .collect())
+    }
+}
+
+#[test]
+fn rule_id_independent_of_resolved_values() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+    let rule_ids = |ruleset: &RuleSet| -> Vec<RuleId> {
+        ruleset
+            .rule_ids()
+            .into_iter()
+            .map(|(rule_id, _, _)| rule_id)
+            .collect()
+    };
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+
+    // The rule is identified by the names of the containers and networks it was resolved from
+    assert!(rule_ids(&ruleset).contains(&RuleId::new(
+        Section::ContainerToContainer,
+        "add rule inet dfw forward ip saddr container:container_a ip daddr container:container_b \
+         meta iifname network:common_network oifname network:common_network meta mark set 0xdf \
+         accept comment \"DFW-MARKER:section;container_to_container\"",
+    )));
+
+    // Containers are addressed anew once another container is started before them
+    let mut containers = full_example_inventory().containers;
+    containers.insert(0, ("container_z", vec!["common_network"]));
+    let readdressed = generate_idempotent(&dfw, &MockInventory { containers });
+    assert_ne!(ruleset.commands(), readdressed.commands());
+    assert_eq!(rule_ids(&ruleset), rule_ids(&readdressed));
+
+    let recreated = generate_idempotent(&dfw, &RecreatedNetworks(full_example_inventory()));
+    assert_ne!(ruleset.commands(), recreated.commands());
+    assert_eq!(rule_ids(&ruleset), rule_ids(&recreated));
+}
+
+#[test]
+fn identity_map_stable_and_complete() {
+    let dfw = load_config_file("resources/test/explain/conf.toml").unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+    let identity_map = ruleset.identity_map();
+
+    assert_eq!(
+        identity_map,
+        generate_idempotent(&dfw, &full_example_inventory()).identity_map()
+    );
+
+    // Every rule of every section is part of the map
+    let rules = ruleset
+        .sections
+        .iter()
+        .flat_map(|(_, rules)| rules.iter())
+        .collect::<Vec<_>>();
+    assert!(!rules.is_empty());
+    assert_eq!(rules.len(), identity_map.len());
+    for (rule_id, _, rule) in ruleset.rule_ids() {
+        assert_eq!(Some(rule), identity_map.get(&rule_id).map(String::as_str));
+    }
+}
+
+#[test]
+fn rule_history_preserves_first_seen() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("rules");
+    let rule_a = r#"
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = "accept"
+        "#;
+    let rule_b = r#"
+        [[container_to_container.rules]]
+        network = "network_a"
+        verdict = "reject"
+        matches = "tcp dport 80"
+        "#;
+    let rule_ab = format!("{}{}", rule_a, rule_b);
+    let ruleset = |rules: &str| -> RuleSet {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [container_to_container]
+            default_policy = "drop"
+            {}
+            "#,
+            rules
+        ))
+        .unwrap();
+        generate_idempotent(&dfw, &full_example_inventory())
+    };
+    let ids = c2c_rule_ids(&rule_ab);
+    let (id_a, id_b) = (ids[0], ids[1]);
+    assert_eq!(vec![id_a], c2c_rule_ids(rule_a));
+
+    let history = RuleHistory::load(&path).unwrap();
+    history.record(&ruleset(rule_a), 100);
+    history.record(&ruleset(rule_a), 200);
+    assert_eq!(Some(100), history.first_seen(id_a));
+    assert_eq!(None, history.first_seen(id_b));
+    history.persist().unwrap();
+
+    // The first-seen times are kept across restarts, only the new rule is seen at the new time
+    let history = RuleHistory::load(&path).unwrap();
+    history.record(&ruleset(&rule_ab), 300);
+    assert_eq!(Some(100), history.first_seen(id_a));
+    assert_eq!(Some(300), history.first_seen(id_b));
+
+    // Rules no longer generated are forgotten, they are new once they are generated again
+    history.record(&ruleset(rule_b), 400);
+    assert_eq!(None, history.first_seen(id_a));
+    history.record(&ruleset(&rule_ab), 500);
+    assert_eq!(Some(500), history.first_seen(id_a));
+    assert_eq!(Some(300), history.first_seen(id_b));
+}
+
+#[test]
+fn rule_history_annotates_explanations() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+    let inventory = full_example_inventory();
+    let ruleset = generate_idempotent(&dfw, &inventory);
+
+    let history = RuleHistory::default();
+    history.record(&ruleset, 1_600_000_000);
+    let mut explained = explain(&dfw, &inventory).unwrap();
+    history.annotate(&mut explained);
+
+    // Only the rules of the sections are identified, the preamble is set up on every reconcile
+    let (preamble, sections) = explained.split_at(ruleset.preamble.len());
+    assert!(preamble
+        .iter()
+        .all(|(_, explanation)| explanation.rule_id.is_none() && explanation.first_seen.is_none()));
+    assert_eq!(
+        ruleset
+            .rule_ids()
+            .into_iter()
+            .map(|(rule_id, _, _)| Some(rule_id))
+            .collect::<Vec<_>>(),
+        sections
+            .iter()
+            .map(|(_, explanation)| explanation.rule_id)
+            .collect::<Vec<_>>()
+    );
+    assert!(sections
+        .iter()
+        .all(|(_, explanation)| explanation.first_seen == Some(1_600_000_000)));
+    assert!(sections[0]
+        .1
+        .to_string()
+        .ends_with(", first applied at 2020-09-13T12:26:40+0000"));
+}
+
+#[test]
+fn rule_history_invalid_state_file() {
+    let directory = tempfile::tempdir().unwrap();
+    let path = directory.path().join("rules");
+    fs::write(&path, "0123456789abcdef container_to_container yesterday\n").unwrap();
+
+    assert!(RuleHistory::load(&path).is_err());
+}
+
+#[test]
+fn generate_rule_counters() {
+    let ruleset = |runtime: &str| -> RuleSet {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [container_to_host]
+            default_policy = "accept"
+
+            [[container_to_host.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            verdict = "accept"
+
+            {}
+            "#,
+            runtime
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+    };
+
+    let without_metrics = ruleset("");
+    assert!(without_metrics
+        .commands()
+        .iter()
+        .all(|command| !command.contains(" counter ")));
+
+    let with_metrics = ruleset(
+        r#"
+        [runtime]
+        metrics_address = "127.0.0.1:9198"
+        "#,
+    );
+    let commands = with_metrics.commands();
+    let c2h_rules = commands
+        .iter()
+        .filter(|command| command.contains("section;container_to_host"))
+        .collect::<Vec<_>>();
+    assert!(!c2h_rules.is_empty());
+    assert!(c2h_rules
+        .iter()
+        .all(|command| command.contains("meta mark set 0xdf counter ")));
+    // Rules of foreign tables are left untouched
+    assert!(commands
+        .iter()
+        .filter(|command| command.contains(" counter "))
+        .all(|command| command.contains(" dfw ")));
+
+    // Every counted rule carries its identity, such that its counters can be attributed to it
+    let rule_ids = with_metrics.rule_ids();
+    let counted = rule_ids
+        .iter()
+        .filter(|(_, _, rule)| rule.contains(" counter "))
+        .collect::<Vec<_>>();
+    assert!(!counted.is_empty());
+    for (rule_id, section, rule) in counted {
+        assert!(rule.contains(&format!(
+            "\"DFW-MARKER:section;{};rule={}",
+            section, rule_id
+        )));
+    }
+    assert_eq!(
+        rule_ids.len(),
+        rule_ids
+            .iter()
+            .map(|(rule_id, _, _)| rule_id)
+            .collect::<BTreeSet<_>>()
+            .len(),
+        "rule IDs are not distinct"
+    );
+}
+
+#[test]
+fn generate_cross_network_container_to_container() {
+    let section_rules = |config: &str, section: Section| -> Vec<String> {
+        let dfw: DFW = toml::from_str(config).unwrap();
+        let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+        ruleset
+            .sections
+            .into_iter()
+            .filter(|(rules_section, _)| *rules_section == section)
+            .flat_map(|(_, rules)| rules)
+            .filter(|rule| rule.starts_with("add rule"))
+            .map(|rule| rule.replace(&format!("section;{}", section), "section;"))
+            .collect()
+    };
+
+    // Rules within a single network are unaffected by the destination network
+    let same_network = section_rules(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        src_network = "common_network"
+        src_container = "container_a"
+        dst_network = "common_network"
+        dst_container = "container_b"
+        verdict = "accept"
+        "#,
+        Section::ContainerToContainer,
+    );
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+             meta iifname br-commonnetwor oifname br-commonnetwor meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;\""
+        ],
+        same_network
+    );
+
+    // Rules across networks are translated like their `container_dnat` equivalent
+    let cross_network = section_rules(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        src_network = "network_a"
+        src_container = "container_a"
+        dst_network = "network_b"
+        dst_container = "container_b"
+        expose_port = 50000
+        verdict = "accept"
+        "#,
+        Section::ContainerToContainer,
+    );
+    let container_dnat = section_rules(
+        r#"
+        [[container_dnat.rules]]
+        src_network = "network_a"
+        src_container = "container_a"
+        dst_network = "network_b"
+        dst_container = "container_b"
+        expose_port = 50000
+        "#,
+        Section::ContainerDNAT,
+    );
+    assert_eq!(
+        vec![
+            "add rule ip dfw prerouting tcp dport 50000 ip saddr 172.22.0.2 \
+             meta iifname br-networkaffff oifname br-networkbffff ct state new meta mark set 0xdf \
+             dnat 172.23.0.3:50000 comment \"DFW-MARKER:section;\""
+        ],
+        container_dnat
+    );
+    assert_eq!(container_dnat, cross_network);
+}
+
+#[test]
+fn explain_wildcard_network() {
+    let dfw = load_config_file("resources/test/explain/conf.toml").unwrap();
+    let inventory = full_example_inventory();
+    let explained = explain(&dfw, &inventory).unwrap();
+
+    // Every generated rule is explained, in the order of the rule set
+    let ruleset = generate(&dfw, &inventory).unwrap();
+    assert_eq!(
+        ruleset.commands(),
+        explained
+            .iter()
+            .map(|(rule, _)| rule.clone())
+            .collect::<Vec<_>>()
+    );
+    assert!(explained[..ruleset.preamble.len()]
+        .iter()
+        .all(|(_, explanation)| explanation.section.is_none()));
+
+    let c2h_rules = explained
+        .iter()
+        .filter(|(_, explanation)| {
+            explanation.section == Some(Section::ContainerToHost) && explanation.rule.is_some()
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(2, c2h_rules.len());
+    for ((rule, explanation), (network, address)) in c2h_rules.iter().zip(&[
+        ("common_network", "172.19.0.2"),
+        ("network_a", "172.22.0.2"),
+    ]) {
+        assert!(rule.contains(&format!("ip saddr {}", address)), "{}", rule);
+        assert_eq!(Some(1), explanation.rule);
+        assert_eq!(
+            "resources/test/explain/conf.toml:8",
+            explanation.provenance.as_ref().unwrap().to_string()
+        );
+        assert_eq!(
+            Some(format!("network `*` expanded to `{}`", network)),
+            explanation.expansion
+        );
+        assert_eq!(
+            vec![format!(
+                "container `container_a` on network `{}` to {}",
+                network, address
+            )],
+            explanation.resolved
+        );
+    }
+    assert_eq!(
+        "rule 1 of section `container_to_host` (resources/test/explain/conf.toml:8), \
+         network `*` expanded to `common_network`, \
+         resolved container `container_a` on network `common_network` to 172.19.0.2",
+        c2h_rules[0].1.to_string()
+    );
+
+    // The default policy is attributed to the section itself
+    let (policy_rule, policy_explanation) = explained
+        .iter()
+        .find(|(rule, explanation)| {
+            explanation.section == Some(Section::ContainerToHost)
+                && explanation.rule.is_none()
+                && !rule.contains(EMBEDDED_DNS_ADDRESS)
+        })
+        .unwrap();
+    assert!(policy_rule.contains("drop"), "{}", policy_rule);
+    assert_eq!(
+        "section `container_to_host`",
+        policy_explanation.to_string()
+    );
+}
+
+#[test]
+fn generate_log_limit() {
+    let ruleset = |log_rate: &str, log: bool| -> RuleSet {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+            {}
+
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            dst_container = "container_b"
+            verdict = "accept"
+            log = {}
+
+            [[container_to_container.rules]]
+            network = "common_network"
+            src_container = "container_b"
+            dst_container = "container_a"
+            verdict = "reject"
+            log = {}
+
+            [container_to_host]
+            default_policy = "accept"
+
+            [[container_to_host.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            verdict = "drop"
+            log = {}
+            "#,
+            log_rate, log, log, log
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+    };
+
+    let commands = ruleset("", true).commands();
+    let limits = commands
+        .iter()
+        .filter(|command| command.starts_with("add limit"))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        vec!["add limit inet dfw dfw_log { rate 100/second ; }"],
+        limits
+    );
+    let log_rules = commands
+        .iter()
+        .filter(|command| command.contains(" log prefix "))
+        .collect::<Vec<_>>();
+    assert_eq!(3, log_rules.len());
+    assert!(log_rules
+        .iter()
+        .all(|command| command.contains("limit name dfw_log log prefix")));
+    // Every log rule directly precedes the rule it logs for
+    for (log_rule, prefix, verdict) in &[
+        (
+            "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+             meta iifname br-commonnetwor oifname br-commonnetwor meta mark set 0xdf \
+             limit name dfw_log log prefix \"dfw:container_to_container \" \
+             comment \"DFW-MARKER:section;container_to_container\"",
+            "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3",
+            "accept",
+        ),
+        (
+            "add rule inet dfw input ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+             meta mark set 0xdf limit name dfw_log log prefix \"dfw:container_to_host \" \
+             comment \"DFW-MARKER:section;container_to_host\"",
+            "add rule inet dfw input ip saddr 172.19.0.2",
+            "drop",
+        ),
+    ] {
+        let index = commands
+            .iter()
+            .position(|command| command == log_rule)
+            .unwrap_or_else(|| panic!("missing command: {}", log_rule));
+        assert!(commands[index + 1].starts_with(prefix));
+        assert!(commands[index + 1].contains(&format!(" {} comment", verdict)));
+    }
+    // The limit object is created before any rule references it
+    let limit_index = commands
+        .iter()
+        .position(|command| command.starts_with("add limit"))
+        .unwrap();
+    assert!(commands[..limit_index]
+        .iter()
+        .all(|command| !command.contains("limit name")));
+
+    let commands = ruleset(r#"log_rate = "10/minute""#, true).commands();
+    assert!(commands.contains(&"add limit inet dfw dfw_log { rate 10/minute ; }".to_owned()));
+
+    // Without logging rules, no limit object is created
+    let commands = ruleset("", false).commands();
+    assert!(commands
+        .iter()
+        .all(|command| !command.contains("limit") && !command.contains(" log ")));
+}
+
+#[test]
+fn generate_log_verdict() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [container_to_host]
+        default_policy = "accept"
+
+        [[container_to_host.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        verdict = "log"
+
+        [[container_to_host.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        verdict = "drop"
+        "#,
+    )
+    .unwrap();
+    let commands = generate_idempotent(&dfw, &full_example_inventory()).commands();
+
+    // The log-only rule emits the log statement without a verdict, the packets are thus decided on
+    // by the subsequent rule.
+    assert_eq!(
+        vec![
+            "add rule inet dfw input ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+             meta mark set 0xdf limit name dfw_log log prefix \"dfw:container_to_host \" \
+             comment \"DFW-MARKER:section;container_to_host\"",
+            "add rule inet dfw input ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+             meta mark set 0xdf drop comment \"DFW-MARKER:section;container_to_host\"",
+        ],
+        commands
+            .into_iter()
+            .filter(|command| command.starts_with("add rule inet dfw input ip saddr 172.19.0.2 "))
+            .collect::<Vec<_>>()
+    );
+}
+
+#[test]
+fn generate_network_chains() {
+    let generate_ruleset = |network_chains: bool| -> RuleSet {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            network_chains = {}
+
+            [initialization]
+            rules = ["add rule inet dfw forward tcp dport 22 accept"]
+
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            dst_container = "container_b"
+            verdict = "accept"
+
+            [container_to_host]
+            default_policy = "reject"
+
+            [[container_to_host.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            verdict = "accept"
+            "#,
+            network_chains
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+    };
+
+    let ruleset = generate_ruleset(true);
+    let commands = ruleset.commands();
+    let position = |command: &str| -> usize {
+        commands
+            .iter()
+            .position(|other| other == command)
+            .unwrap_or_else(|| panic!("missing command: {}", command))
+    };
+
+    // Every network chain is added in the preamble and dispatched to after the stateful rules of
+    // its base chain
+    for (base_chain, interface) in &[
+        ("input", "br-commonnetwor"),
+        ("input", "br-networkaffff"),
+        ("forward", "br-commonnetwor"),
+    ] {
+        let chain = format!("{}_{}", base_chain, interface);
+        let add_chain = position(&format!("add chain inet dfw {}", chain));
+        let dispatch = position(&format!(
+            "add rule inet dfw {} meta iifname {} goto {}",
+            base_chain, interface, chain
+        ));
+        let stateful = position(&format!(
+            "add rule inet dfw {} ct state {{ related, established }} accept",
+            base_chain
+        ));
+        assert!(add_chain < dispatch);
+        assert!(stateful < dispatch);
+        assert!(dispatch < ruleset.preamble.len());
+    }
+
+    // Rules of a single interface are moved into its chain
+    let container_rule = position(
+        "add rule inet dfw forward_br-commonnetwor ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+         meta iifname br-commonnetwor oifname br-commonnetwor meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;container_to_container\"",
+    );
+    position(
+        "add rule inet dfw input_br-commonnetwor ip saddr 172.19.0.2 \
+         meta iifname br-commonnetwor meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;container_to_host\"",
+    );
+    position(
+        "add rule inet dfw input_br-networkaffff meta iifname br-networkaffff \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_host\"",
+    );
+    assert!(commands
+        .iter()
+        .filter(|command| command.starts_with("add rule inet dfw input ")
+            || command.starts_with("add rule inet dfw forward "))
+        .filter(|command| command.contains(" iifname br-"))
+        .all(|command| command.contains(" goto ")));
+
+    // Rules of any interface stay in the base chain and precede the rules of the interface within
+    // its chain
+    position(
+        "add rule inet dfw forward tcp dport 22 accept \
+         comment \"DFW-MARKER:section;initialization\"",
+    );
+    let generic_rule = position(
+        "add rule inet dfw forward_br-commonnetwor tcp dport 22 accept \
+         comment \"DFW-MARKER:section;initialization\"",
+    );
+    assert!(generic_rule < container_rule);
+    position("add chain inet dfw forward { policy drop ; }");
+
+    // The layout is opt-in
+    assert!(generate_ruleset(false)
+        .commands()
+        .iter()
+        .all(|command| !command.contains("goto")));
+}
+
+#[test]
+fn generate_external_interface_chains() {
+    let generate_ruleset = |external_interface_chains: bool| -> RuleSet {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = ["eth0", "eth1"]
+            external_interface_chains = {}
+
+            [initialization]
+            rules = ["add rule inet dfw forward tcp dport 22 accept"]
+
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            dst_container = "container_b"
+            verdict = "accept"
+
+            [container_to_wider_world]
+            default_policy = "drop"
+
+            [[container_to_wider_world.rules]]
+            network = "common_network"
+            src_container = "container_a"
+            external_network_interface = "eth1"
+            verdict = "accept"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            "#,
+            external_interface_chains
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+    };
+
+    let ruleset = generate_ruleset(true);
+    let commands = ruleset.commands();
+    let position = |command: &str| -> usize {
+        commands
+            .iter()
+            .position(|other| other == command)
+            .unwrap_or_else(|| panic!("missing command: {}", command))
+    };
+
+    // Every interface chain is added in the preamble and dispatched to after the stateful rules
+    // of its base chain, forwarded packets by their input interface first
+    for base_chain in &["input", "forward"] {
+        let stateful = position(&format!(
+            "add rule inet dfw {} ct state {{ related, established }} accept",
+            base_chain
+        ));
+        for interface in &["eth0", "eth1"] {
+            let chain = format!("{}_{}", base_chain, interface);
+            let add_chain = position(&format!("add chain inet dfw {}", chain));
+            let dispatch = position(&format!(
+                "add rule inet dfw {} meta iifname {} goto {}",
+                base_chain, interface, chain
+            ));
+            assert!(stateful < add_chain);
+            assert!(add_chain < dispatch);
+            assert!(dispatch < ruleset.preamble.len());
+        }
+    }
+    let dispatch_output = position("add rule inet dfw forward meta oifname eth0 goto forward_eth0");
+    assert!(
+        position("add rule inet dfw forward meta iifname eth1 goto forward_eth1") < dispatch_output
+    );
+    assert!(dispatch_output < ruleset.preamble.len());
+    position("add rule inet dfw forward meta oifname eth1 goto forward_eth1");
+    assert!(
+        !commands.contains(&"add rule inet dfw input meta oifname eth0 goto input_eth0".to_owned())
+    );
+
+    // Rules of a single external interface are moved into its chain
+    let wider_world_rule = position(
+        "add rule inet dfw forward_eth0 tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+         oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+    );
+    position(
+        "add rule inet dfw forward_eth1 ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+         oifname eth1 meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;container_to_wider_world\"",
+    );
+    assert!(!commands.contains(
+        &"add rule inet dfw forward_eth0 ip saddr 172.19.0.2 meta iifname br-commonnetwor \
+          oifname eth1 meta mark set 0xdf accept \
+          comment \"DFW-MARKER:section;container_to_wider_world\""
+            .to_owned()
+    ));
+    assert!(commands
+        .iter()
+        .filter(|command| command.starts_with("add rule inet dfw input ")
+            || command.starts_with("add rule inet dfw forward "))
+        .filter(|command| command.contains("ifname eth"))
+        .all(|command| command.contains(" goto ")));
+
+    // Rules of other interfaces stay in the base chain only
+    position(
+        "add rule inet dfw forward ip saddr 172.19.0.2 ip daddr 172.19.0.3 \
+         meta iifname br-commonnetwor oifname br-commonnetwor meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;container_to_container\"",
+    );
+    assert!(commands
+        .iter()
+        .filter(|command| command.starts_with("add rule inet dfw forward_eth"))
+        .all(|command| !command.contains("oifname br-commonnetwor")));
+
+    // Rules of any interface stay in the base chain and precede the rules of the interface within
+    // its chain
+    position(
+        "add rule inet dfw forward tcp dport 22 accept \
+         comment \"DFW-MARKER:section;initialization\"",
+    );
+    for interface in &["eth0", "eth1"] {
+        position(&format!(
+            "add rule inet dfw forward_{} tcp dport 22 accept \
+             comment \"DFW-MARKER:section;initialization\"",
+            interface
+        ));
+    }
+    assert!(
+        position(
+            "add rule inet dfw forward_eth0 tcp dport 22 accept \
+             comment \"DFW-MARKER:section;initialization\""
+        ) < wider_world_rule
+    );
+
+    // The layout is opt-in
+    assert!(generate_ruleset(false)
+        .commands()
+        .iter()
+        .all(|command| !command.contains("goto")));
+}
+
+#[test]
+fn generate_families() {
+    let section_rules = |families: &str, section: &str| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [container_to_wider_world]
+            default_policy = "drop"
+
+            [[container_to_wider_world.rules]]
+            network = "reverseproxy_network"
+            verdict = "accept"
+            {families}
+
+            [container_to_host]
+            default_policy = "drop"
+
+            [[container_to_host.rules]]
+            network = "reverseproxy_network"
+            src_container = "my_reverseproxy"
+            verdict = "accept"
+            {families}
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            {families}
+            "#,
+            families = families
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains(&format!("section;{}", section)))
+            .filter(|command| {
+                command.contains("172.24.0.4")
+                    || command.contains("dport 443")
+                    || command.contains("br-reverseproxy oifname eth0")
+            })
+            // Skip the default policies
+            .filter(|command| !command.contains(" drop "))
+            .collect()
+    };
+
+    // By default, rules apply to both families
+    for families in &["", r#"families = ["v4", "v6"]"#] {
+        assert_eq!(
+            vec![
+                "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+                 oifname br-reverseproxy meta mark set 0xdf accept \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+                "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+                 ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+                "add rule ip6 dfw prerouting tcp dport 443 meta iifname eth0 meta mark set 0xdf \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+            ],
+            section_rules(families, "wider_world_to_container")
+        );
+        assert_eq!(
+            vec![
+                "add rule inet dfw forward meta iifname br-reverseproxy oifname eth0 \
+                 meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_wider_world\""
+            ],
+            section_rules(families, "container_to_wider_world")
+        );
+        assert_eq!(
+            vec![
+                "add rule inet dfw input ip saddr 172.24.0.4 meta iifname br-reverseproxy \
+                 meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_host\""
+            ],
+            section_rules(families, "container_to_host")
+        );
+    }
+
+    // Restricted to IPv4, the ports are not exposed on IPv6 and rules without addresses only match
+    // IPv4 traffic
+    let families = r#"families = ["v4"]"#;
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward tcp dport 443 ip daddr 172.24.0.4 meta iifname eth0 \
+             oifname br-reverseproxy meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        section_rules(families, "wider_world_to_container")
+    );
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward meta iifname br-reverseproxy oifname eth0 \
+             meta nfproto ipv4 meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;container_to_wider_world\""
+        ],
+        section_rules(families, "container_to_wider_world")
+    );
+    assert_eq!(
+        vec![
+            "add rule inet dfw input ip saddr 172.24.0.4 meta iifname br-reverseproxy \
+             meta mark set 0xdf accept comment \"DFW-MARKER:section;container_to_host\""
+        ],
+        section_rules(families, "container_to_host")
+    );
+
+    // Restricted to IPv6, the IPv4 addresses of the container are not used at all
+    let families = r#"families = ["v6"]"#;
+    assert_eq!(
+        vec![
+            "add rule ip6 dfw prerouting tcp dport 443 meta iifname eth0 meta mark set 0xdf \
+             comment \"DFW-MARKER:section;wider_world_to_container\""
+        ],
+        section_rules(families, "wider_world_to_container")
+    );
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward meta iifname br-reverseproxy oifname eth0 \
+             meta nfproto ipv6 meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;container_to_wider_world\""
+        ],
+        section_rules(families, "container_to_wider_world")
+    );
+    assert!(section_rules(families, "container_to_host").is_empty());
+}
+
+/// Whether network namespaces can be created and `nft` is available, which is required by
+/// `RuleSet::self_test`.
+fn self_test_available() -> bool {
+    let tool_available = |tool: &str, arg: &str| {
+        Command::new(tool)
+            .arg(arg)
+            .output()
+            .is_ok_and(|output| output.status.success())
+    };
+
+    let root = unsafe { libc::geteuid() } == 0;
+    root && tool_available("ip", "-V") && tool_available("nft", "-v")
+}
+
+#[test]
+fn self_test() {
+    if !self_test_available() {
+        eprintln!("skipping self-test: requires root privileges, `ip` and `nft`");
+        return;
+    }
+
+    let dfw = load_file::<DFW>("resources/test/inventory/conf.toml").unwrap();
+    let inventory = StaticInventory::load("resources/test/inventory/inventory.toml").unwrap();
+    let ruleset = generate(&dfw, &inventory).unwrap();
+    ruleset.self_test().unwrap();
+
+    let mut invalid_ruleset = ruleset.clone();
+    invalid_ruleset.sections.push((
+        Section::ContainerToHost,
+        vec!["add rule inet dfw input meta iifname eth0 nonsense accept".to_owned()],
+    ));
+    let error = invalid_ruleset.self_test().unwrap_err();
+    match error.downcast_ref::<DFWError>() {
+        Some(DFWError::NFTablesError { stderr, .. }) => assert!(stderr.contains("nonsense")),
+        _ => panic!("unexpected error: {}", error),
+    }
+
+    // The throwaway network namespaces are deleted regardless of the outcome.
+    let namespaces = Command::new("ip").args(["netns", "list"]).output().unwrap();
+    assert!(!String::from_utf8_lossy(&namespaces.stdout).contains("dfw-self-test"));
+}
+
+#[test]
+fn nft_binary_configured_path() {
+    let directory = tempfile::tempdir().unwrap();
+    let stub = directory.path().join("nft");
+    let applied = directory.path().join("applied.nft");
+    fs::write(
+        &stub,
+        format!(
+            "#!/bin/sh\n[ \"$1\" = \"-f\" ] && cp \"$2\" {}\n",
+            applied.display()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        [defaults]
+        nft_path = "{}"
+        "#,
+        stub.display()
+    ))
+    .unwrap();
+    let nft_binary = nft_binary(&dfw);
+    assert_eq!(None, nft_binary.netns);
+    nft_binary.run_script("add table inet dfw\n").unwrap();
+
+    assert_eq!(
+        "add table inet dfw\n",
+        fs::read_to_string(&applied).unwrap()
+    );
+}
+
+#[test]
+fn generate_on_missing() {
+    let generate_rules = |dst_container: &str, on_missing: &str| -> Result<Vec<String>, Error> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "{}"
+            expose_port = 8443
+            {}
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            "#,
+            dst_container, on_missing
+        ))
+        .unwrap();
+
+        Ok(generate(&dfw, &full_example_inventory())?
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains("section;wider_world_to_container"))
+            .filter(|command| command.contains("add rule ip dfw prerouting"))
+            .collect())
+    };
+
+    // By default, and if requested, rules referencing missing containers are skipped
+    for on_missing in &["", r#"on_missing = "skip""#] {
+        assert_eq!(
+            vec![
+                "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+                 ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+            ],
+            generate_rules("missing_container", on_missing).unwrap()
+        );
+    }
+
+    // Failing on missing containers doesn't affect rules whose container is present
+    assert_eq!(
+        vec![
+            "add rule ip dfw prerouting tcp dport 8443 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:8443 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        generate_rules("my_reverseproxy", r#"on_missing = "error""#).unwrap()
+    );
+
+    let error = generate_rules("missing_container", r#"on_missing = "error""#).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("container `missing_container` of rule is missing"));
+
+    // Containers that exist but aren't attached to the network of the rule are missing as well
+    let error = generate_rules("container_a", r#"on_missing = "error""#).unwrap_err();
+    assert!(error
+        .to_string()
+        .contains("container `container_a` of rule is missing"));
+}
+
+#[test]
+fn generate_dnat_new_only() {
+    let dnat_rules = |dnat_new_only: &str| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+            {}
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+
+            [[container_dnat.rules]]
+            src_network = "network_a"
+            src_container = "container_a"
+            dst_network = "network_b"
+            dst_container = "container_b"
+            expose_port = 50000
+            "#,
+            dnat_new_only
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains(" prerouting ") && command.contains(" dnat "))
+            .collect()
+    };
+
+    // By default, DNAT only applies to new connections
+    for dnat_new_only in &["", "dnat_new_only = true"] {
+        assert_eq!(
+            vec![
+                "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 \
+                 ct state new meta mark set 0xdf dnat 172.24.0.4:443 \
+                 comment \"DFW-MARKER:section;wider_world_to_container\"",
+                "add rule ip dfw prerouting tcp dport 50000 ip saddr 172.22.0.2 \
+                 meta iifname br-networkaffff oifname br-networkbffff ct state new \
+                 meta mark set 0xdf dnat 172.23.0.3:50000 \
+                 comment \"DFW-MARKER:section;container_dnat\"",
+            ],
+            dnat_rules(dnat_new_only)
+        );
+    }
+
+    assert_eq!(
+        vec![
+            "add rule ip dfw prerouting tcp dport 443 meta iifname eth0 meta mark set 0xdf \
+             dnat 172.24.0.4:443 comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule ip dfw prerouting tcp dport 50000 ip saddr 172.22.0.2 \
+             meta iifname br-networkaffff oifname br-networkbffff meta mark set 0xdf \
+             dnat 172.23.0.3:50000 comment \"DFW-MARKER:section;container_dnat\"",
+        ],
+        dnat_rules("dnat_new_only = false")
+    );
+}
+
+#[test]
+fn generate_dnat_accept_shortcut() {
+    let forward_preamble = |dnat_accept_shortcut: &str| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+            {}
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            "#,
+            dnat_accept_shortcut
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &full_example_inventory())
+            .preamble
+            .into_iter()
+            .filter(|command| command.starts_with("add rule inet dfw forward "))
+            .collect()
+    };
+
+    // By default, translated connections are marked and accepted once they are established, new
+    // connections are left to the forward rules of the sections
+    for dnat_accept_shortcut in &["", "dnat_accept_shortcut = true"] {
+        assert_eq!(
+            vec![
+                "add rule inet dfw forward ct state invalid drop",
+                "add rule inet dfw forward ct state { related, established } ct status dnat \
+                 meta mark set 0xdf accept",
+                "add rule inet dfw forward ct state { related, established } accept",
+            ],
+            forward_preamble(dnat_accept_shortcut)
+        );
+    }
+
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward ct state invalid drop",
+            "add rule inet dfw forward ct state { related, established } accept",
+        ],
+        forward_preamble("dnat_accept_shortcut = false")
+    );
+}
+
+#[test]
+fn generate_allow_embedded_dns() {
+    let dns_rules_for = |allow_embedded_dns: &str, inventory: &MockInventory| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            {}
+
+            [container_to_host]
+            default_policy = "reject"
+            "#,
+            allow_embedded_dns
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, inventory)
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains(EMBEDDED_DNS_ADDRESS))
+            .collect()
+    };
+    let dns_rules =
+        |allow_embedded_dns: &str| dns_rules_for(allow_embedded_dns, &full_example_inventory());
+
+    // By default, every user-defined network may reach the embedded DNS server
+    for allow_embedded_dns in &["", "allow_embedded_dns = true"] {
+        let rules = dns_rules(allow_embedded_dns);
+        assert_eq!(12, rules.len());
+        assert_eq!(
+            vec![
+                "add rule inet dfw input tcp dport 53 ip daddr 127.0.0.11 \
+                 meta iifname br-commonnetwor meta mark set 0xdf accept \
+                 comment \"DFW-MARKER:section;container_to_host\"",
+                "add rule inet dfw input udp dport 53 ip daddr 127.0.0.11 \
+                 meta iifname br-commonnetwor meta mark set 0xdf accept \
+                 comment \"DFW-MARKER:section;container_to_host\"",
+            ],
+            rules[..2].to_vec()
+        );
+        // The default bridge isn't served by the embedded DNS server
+        assert!(rules
+            .iter()
+            .all(|rule| !rule.contains("docker0") && !rule.contains("br-bridgeffffff")));
+    }
+
+    assert!(dns_rules("allow_embedded_dns = false").is_empty());
+
+    // Networks without a bridge aren't served by the embedded DNS server either
+    let inventory = MockInventory {
+        containers: vec![
+            ("my_dnsmasq", vec!["host"]),
+            ("my_isolated", vec!["none"]),
+            ("my_reverseproxy", vec!["reverseproxy_network"]),
+        ],
+    };
+    let rules = dns_rules_for("", &inventory);
+    assert_eq!(2, rules.len());
+    assert!(rules
+        .iter()
+        .all(|rule| rule.contains("meta iifname br-reverseproxy ")));
+}
+
+#[test]
+fn default_route_interfaces_from_routing_tables() {
+    let route = "\
+Iface\tDestination\tGateway \tFlags\tRefCnt\tUse\tMetric\tMask\t\tMTU\tWindow\tIRTT
+ens6\t00000000\t0101A8C0\t0003\t0\t0\t200\t00000000\t0\t0\t0
+ens5\t00000000\t0102000A\t0003\t0\t0\t100\t00000000\t0\t0\t0
+ens5\t0002000A\t00000000\t0001\t0\t0\t100\t00FFFFFF\t0\t0\t0
+docker0\t000011AC\t00000000\t0001\t0\t0\t0\t0000FFFF\t0\t0\t0
+ens7\t00000000\t0103000A\t0002\t0\t0\t50\t00000000\t0\t0\t0
+";
+    let ipv6_route = "\
+20010db8000000000000000000000000 40 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 00000100 00000001 00000000 00000001 ens5
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+fe800000000000000000000000000001 00000400 00000001 00000000 00000003 wg0
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+fe800000000000000000000000000001 00000064 00000001 00000000 00000003 ens5
+00000000000000000000000000000000 00 00000000000000000000000000000000 00 \
+00000000000000000000000000000000 ffffffff 00000001 00000000 00200200 lo
+";
+
+    // The route of `ens7` is down, the unreachable route of `lo` rejects packets
+    assert_eq!(
+        vec!["ens5", "ens6", "wg0"],
+        default_route_interfaces(route, ipv6_route)
+    );
+    assert_eq!(
+        vec!["ens5", "wg0"],
+        default_route_interfaces("", ipv6_route)
+    );
+    assert!(default_route_interfaces("", "").is_empty());
+}
+
+#[test]
+fn generate_auto_external_network_interfaces() {
+    let generate_commands = |external_network_interfaces: Vec<&str>,
+                             default_route_interfaces: Vec<&str>|
+     -> Result<Vec<String>, Error> {
+        let mut dfw = load_file::<DFW>("resources/test/inventory/conf.toml").unwrap();
+        dfw.defaults.as_mut().unwrap().external_network_interfaces = Some(
+            external_network_interfaces
+                .into_iter()
+                .map(str::to_owned)
+                .collect::<Vec<_>>()
+                .into(),
+        );
+        let mut inventory =
+            StaticInventory::load("resources/test/inventory/inventory.toml").unwrap();
+        inventory.host_facts.default_route_interfaces = default_route_interfaces
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+
+        Ok(generate(&dfw, &inventory)?.commands())
+    };
+
+    let expected = generate_commands(vec!["ens5", "wg0"], vec![]).unwrap();
+    assert!(expected
+        .iter()
+        .any(|command| command.contains("oifname wg0")));
+    for auto in &[vec!["auto"], vec![]] {
+        assert_eq!(
+            expected,
+            generate_commands(auto.clone(), vec!["ens5", "wg0"]).unwrap()
+        );
+    }
+
+    // Without a default route, the interfaces cannot be determined
+    let error = generate_commands(vec!["auto"], vec![]).unwrap_err();
+    assert!(error.to_string().contains("has no default route"));
+}
+
+/// Inventory adding a secondary address to the endpoint of `my_webserver` on the
+/// `reverseproxy_network`.
+struct SecondaryAddressInventory(MockInventory);
+
+impl ContainerInventory for SecondaryAddressInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        self.0.containers()
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        let mut networks = self.0.networks()?;
+        for network in &mut networks {
+            if network.name == "reverseproxy_network" {
+                for endpoint in network.containers.values_mut() {
+                    if endpoint.ipv4_address == "172.24.0.5/16" {
+                        endpoint
+                            .secondary_ipv4_addresses
+                            .push("172.24.0.100/16".to_owned());
+                    }
+                }
+            }
+        }
+        Ok(networks)
+    }
+}
+
+#[test]
+fn generate_dst_ip() {
+    let dnat_rules = |network: &str, dst_ip: &str| -> Result<Vec<String>, Error> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+
+            [[wider_world_to_container.rules]]
+            network = "{}"
+            dst_container = "my_webserver"
+            expose_port = 8080
+            {}
+            "#,
+            network, dst_ip
+        ))
+        .unwrap();
+
+        Ok(
+            generate(&dfw, &SecondaryAddressInventory(full_example_inventory()))?
+                .commands()
+                .into_iter()
+                .filter(|command| command.contains(" prerouting ") && command.contains(" dnat "))
+                .collect(),
+        )
+    };
+    let dnat_rule = |address: &str| {
+        format!(
+            "add rule ip dfw prerouting tcp dport 8080 meta iifname eth0 \
+             ct state new meta mark set 0xdf dnat {}:8080 \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            address
+        )
+    };
+
+    // The container is attached to multiple networks, the address on the network of the rule is
+    // used.
+    assert_eq!(
+        vec![dnat_rule("172.24.0.5")],
+        dnat_rules("reverseproxy_network", "").unwrap()
+    );
+    assert_eq!(
+        vec![dnat_rule("172.20.0.5")],
+        dnat_rules("internal_network", "").unwrap()
+    );
+
+    // The target can be pinned to any address of the container on the network of the rule
+    for address in &["172.24.0.5", "172.24.0.100"] {
+        assert_eq!(
+            vec![dnat_rule(address)],
+            dnat_rules("reverseproxy_network", &format!("dst_ip = \"{}\"", address)).unwrap()
+        );
+    }
+    let error = dnat_rules("reverseproxy_network", "dst_ip = \"172.20.0.5\"").unwrap_err();
+    assert_eq!(
+        "destination address 172.20.0.5 is not an address of container `my_webserver` on network \
+         `reverseproxy_network`, its addresses are 172.24.0.5, 172.24.0.100",
+        error.to_string()
+    );
+}
+
+/// Inventory labeling the containers of the wrapped inventory with the labels given for them.
+struct LabeledInventory {
+    inventory: MockInventory,
+    labels: Vec<(&'static str, Vec<(&'static str, &'static str)>)>,
+}
+
+impl ContainerInventory for LabeledInventory {
+    fn containers(&self) -> Result<Vec<Container>, Error> {
+        let mut containers = self.inventory.containers()?;
+        for container in &mut containers {
+            if let Some((_, labels)) = self
+                .labels
+                .iter()
+                .find(|(name, _)| container.names.contains(&format!("/{}", name)))
+            {
+                container.labels = labels
+                    .iter()
+                    .map(|&(label, value)| (label.to_owned(), value.to_owned()))
+                    .collect();
+            }
+        }
+        Ok(containers)
+    }
+
+    fn networks(&self) -> Result<Vec<Network>, Error> {
+        self.inventory.networks()
+    }
+}
+
+#[test]
+fn generate_tags() {
+    let inventory = LabeledInventory {
+        inventory: MockInventory {
+            containers: vec![
+                ("db_a", vec!["mesh_network"]),
+                ("db_b", vec!["mesh_network"]),
+                ("web_a", vec!["mesh_network"]),
+                ("web_b", vec!["mesh_network"]),
+                ("web_c", vec!["mesh_network"]),
+            ],
+        },
+        labels: vec![
+            ("db_a", vec![("tier", "db"), ("env", "prod")]),
+            ("db_b", vec![("tier", "db"), ("env", "dev")]),
+            ("web_a", vec![("tier", "web"), ("env", "prod")]),
+            (
+                "web_b",
+                vec![("tier", "web"), ("env", "prod"), ("team", "a")],
+            ),
+            ("web_c", vec![("tier", "web"), ("env", "dev")]),
+        ],
+    };
+    let generate_rules = |selectors: &str| -> Vec<String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            network = "mesh_network"
+            {}
+            verdict = "accept"
+            "#,
+            selectors
+        ))
+        .unwrap();
+
+        generate_idempotent(&dfw, &inventory)
+            .commands()
+            .into_iter()
+            .filter(|command| command.contains("dfw forward ip saddr"))
+            .collect()
+    };
+    let rule = |src_address: &str, dst_address: &str| {
+        format!(
+            "add rule inet dfw forward ip saddr {} ip daddr {} \
+             meta iifname br-meshnetworkf oifname br-meshnetworkf meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;container_to_container\"",
+            src_address, dst_address
+        )
+    };
+
+    // All tags have to match, the rule applies to every pair of matching containers
+    assert_eq!(
+        vec![
+            rule("172.19.0.4", "172.19.0.2"),
+            rule("172.19.0.5", "172.19.0.2")
+        ],
+        generate_rules(
+            r#"
+            src_tags = { tier = "web", env = "prod" }
+            dst_tags = { tier = "db", env = "prod" }
+            "#
+        )
+    );
+
+    // Tags can be combined with a container selected by name
+    assert_eq!(
+        vec![
+            rule("172.19.0.6", "172.19.0.2"),
+            rule("172.19.0.6", "172.19.0.3")
+        ],
+        generate_rules(
+            r#"
+            src_container = "web_c"
+            dst_tags = { tier = "db" }
+            "#
+        )
+    );
+
+    // A container is never paired with itself
+    assert_eq!(
+        vec![
+            rule("172.19.0.4", "172.19.0.5"),
+            rule("172.19.0.4", "172.19.0.6"),
+            rule("172.19.0.5", "172.19.0.4"),
+            rule("172.19.0.5", "172.19.0.6"),
+            rule("172.19.0.6", "172.19.0.4"),
+            rule("172.19.0.6", "172.19.0.5"),
+        ],
+        generate_rules(
+            r#"
+            src_tags = { tier = "web" }
+            dst_tags = { tier = "web" }
+            "#
+        )
+    );
+
+    // Tags no container carries select nothing
+    assert!(generate_rules(
+        r#"
+        src_tags = { tier = "web" }
+        dst_tags = { tier = "cache" }
+        "#
+    )
+    .is_empty());
+}
+
+#[test]
+fn generate_managed_objects() {
+    let mut dfw: DFW = load_file("examples/full-single-file/dfw.toml").unwrap();
+    let managed_chains = |ruleset: &RuleSet| -> Vec<(String, String)> {
+        let managed_objects = ruleset.managed_objects();
+        assert_eq!("dfw", managed_objects.table);
+        managed_objects
+            .chains
+            .into_iter()
+            .map(|chain| (chain.family, chain.name))
+            .collect()
+    };
+    // Every rule generated for the DFW tables is added to a chain reported as managed
+    let assert_rules_managed = |ruleset: &RuleSet, chains: &[(String, String)]| {
+        for command in ruleset.commands() {
+            let words = command.split_whitespace().take(5).collect::<Vec<_>>();
+            if let ["add", "rule", family, "dfw", chain] = words[..] {
+                assert!(
+                    chains.contains(&(family.to_owned(), chain.to_owned())),
+                    "unmanaged chain: {}",
+                    command
+                );
+            }
+        }
+    };
+
+    // Without rules adding chains, the base chains are all chains
+    let ruleset = generate(&dfw, &full_example_inventory()).unwrap();
+    assert_eq!(managed_objects(&dfw), ruleset.managed_objects());
+    let chains = managed_chains(&ruleset);
+    assert_eq!(
+        vec![
+            ("inet", "input"),
+            ("inet", "forward"),
+            ("ip", "prerouting"),
+            ("ip", "postrouting"),
+            ("ip6", "prerouting"),
+            ("ip6", "postrouting"),
+        ]
+        .into_iter()
+        .map(|(family, name)| (family.to_owned(), name.to_owned()))
+        .collect::<Vec<_>>(),
+        chains
+    );
+    assert_rules_managed(&ruleset, &chains);
+
+    // Conntrack zones add a prerouting chain to the `inet` table
+    dfw.defaults.as_mut().unwrap().conntrack_zones = true;
+    let ruleset = generate(&dfw, &full_example_inventory()).unwrap();
+    assert_eq!(managed_objects(&dfw), ruleset.managed_objects());
+    assert_eq!(
+        Some(&("inet".to_owned(), "prerouting".to_owned())),
+        managed_chains(&ruleset).first()
+    );
+
+    // Excluding traffic from connection tracking or assigning conntrack timeouts adds it as well
+    dfw.defaults.as_mut().unwrap().conntrack_zones = false;
+    let mut untracked: DFW = toml::from_str(
+        r#"
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = { host_port = 8080, dnat = false }
+        notrack = true
+        "#,
+    )
+    .unwrap();
+    untracked.defaults = dfw.defaults.clone();
+    let ruleset = generate(&untracked, &full_example_inventory()).unwrap();
+    assert_eq!(managed_objects(&untracked), ruleset.managed_objects());
+    assert_eq!(
+        Some(&("inet".to_owned(), "prerouting".to_owned())),
+        managed_chains(&ruleset).first()
+    );
+    let mut timed_out: DFW = toml::from_str(
+        r#"
+        [defaults]
+        ct_timeouts.scanned = { tcp_established = "1h" }
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        ct_timeout = "scanned"
+        "#,
+    )
+    .unwrap();
+    timed_out
+        .defaults
+        .as_mut()
+        .unwrap()
+        .external_network_interfaces = dfw
+        .defaults
+        .as_ref()
+        .unwrap()
+        .external_network_interfaces
+        .clone();
+    let ruleset = generate(&timed_out, &full_example_inventory()).unwrap();
+    assert_eq!(managed_objects(&timed_out), ruleset.managed_objects());
+    assert_eq!(
+        Some(&("inet".to_owned(), "prerouting".to_owned())),
+        managed_chains(&ruleset).first()
+    );
+
+    // The tables of both families are reported when the `inet` table is split
+    untracked.defaults.as_mut().unwrap().table_family = TableFamily::Split;
+    let ruleset = generate(&untracked, &full_example_inventory()).unwrap();
+    assert_eq!(managed_objects(&untracked), ruleset.managed_objects());
+    assert!(managed_chains(&ruleset).contains(&("ip6".to_owned(), "raw_prerouting".to_owned())));
+
+    // The chains per network depend on the rules, they are only reported for the rule set
+    dfw.defaults.as_mut().unwrap().network_chains = true;
+    let ruleset = generate(&dfw, &full_example_inventory()).unwrap();
+    let chains = managed_chains(&ruleset);
+    for chain in managed_objects(&dfw).chains {
+        assert!(chains.contains(&(chain.family, chain.name)));
+    }
+    assert!(chains.contains(&("inet".to_owned(), "forward_br-commonnetwor".to_owned())));
+    assert_rules_managed(&ruleset, &chains);
+}
+
+#[test]
+fn generate_base_chains() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [defaults.base_chains]
+        input = { priority = 10, policy = "drop" }
+        postrouting = { priority = 50 }
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+    let chain_commands = ruleset
+        .preamble
+        .iter()
+        .filter(|command| command.starts_with("add chain "))
+        .cloned()
+        .collect::<Vec<_>>();
+
+    // Only the overridden hooks differ from the defaults, the policy is set right after the chain
+    // is added
+    assert_eq!(
+        vec![
+            "add chain inet dfw input { type filter hook input priority 10 ; }",
+            "add chain inet dfw input { policy drop ; }",
+            "add chain inet dfw forward { type filter hook forward priority -5 ; }",
+            "add chain ip dfw prerouting { type nat hook prerouting priority -105 ; }",
+            "add chain ip dfw postrouting { type nat hook postrouting priority 50 ; }",
+            "add chain ip6 dfw prerouting { type nat hook prerouting priority -105 ; }",
+            "add chain ip6 dfw postrouting { type nat hook postrouting priority 50 ; }",
+        ],
+        chain_commands
+    );
+}
+
+#[test]
+fn generate_split_table_family() {
+    let generate_table_family = |table_family: &str| -> RuleSet {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            external_network_interfaces = "eth0"
+            table_family = "{}"
+
+            [container_to_wider_world]
+            default_policy = "accept"
+
+            [[container_to_wider_world.rules]]
+            network = "internal_network"
+            verdict = "reject"
+
+            [[wider_world_to_container.rules]]
+            network = "reverseproxy_network"
+            dst_container = "my_reverseproxy"
+            expose_port = 443
+            "#,
+            table_family
+        ))
+        .unwrap();
+        generate_idempotent(&dfw, &full_example_inventory())
+    };
+    let inet = generate_table_family("inet");
+    let split = generate_table_family("split");
+
+    assert_eq!(
+        vec![
+            "add table inet dfw",
+            "delete table inet dfw",
+            "add table ip dfw",
+            "add table ip6 dfw",
+            "flush table ip dfw",
+            "flush table ip6 dfw",
+            "add chain ip dfw input { type filter hook input priority -5 ; }",
+            "add chain ip6 dfw input { type filter hook input priority -5 ; }",
+            "add rule ip dfw input ct state invalid drop",
+            "add rule ip6 dfw input ct state invalid drop",
+            "add rule ip dfw input ct state { related, established } accept",
+            "add rule ip6 dfw input ct state { related, established } accept",
+            "add chain ip dfw forward { type filter hook forward priority -5 ; }",
+            "add chain ip6 dfw forward { type filter hook forward priority -5 ; }",
+            "add rule ip dfw forward ct state invalid drop",
+            "add rule ip6 dfw forward ct state invalid drop",
+            "add rule ip dfw forward ct state { related, established } ct status dnat \
+             meta mark set 0xdf accept",
+            "add rule ip6 dfw forward ct state { related, established } ct status dnat \
+             meta mark set 0xdf accept",
+            "add rule ip dfw forward ct state { related, established } accept",
+            "add rule ip6 dfw forward ct state { related, established } accept",
+            "add chain ip dfw prerouting { type nat hook prerouting priority -105 ; }",
+            "add chain ip dfw postrouting { type nat hook postrouting priority 95 ; }",
+            "add chain ip6 dfw prerouting { type nat hook prerouting priority -105 ; }",
+            "add chain ip6 dfw postrouting { type nat hook postrouting priority 95 ; }",
+        ],
+        split.preamble
+    );
+
+    // Rules matching on IPv4 addresses only end up in the `ip` table, all other rules of the
+    // `inet` table in both tables. The NAT rules are unaffected.
+    let expected_sections = inet
+        .sections
+        .iter()
+        .map(|(section, rules)| {
+            let rules = rules
+                .iter()
+                .flat_map(|rule| {
+                    if !rule.contains(" inet dfw ") {
+                        vec![rule.clone()]
+                    } else if rule.contains(" ip daddr ") || rule.contains(" ip saddr ") {
+                        vec![rule.replace(" inet dfw ", " ip dfw ")]
+                    } else if rule.contains(" ip6 daddr ") || rule.contains(" ip6 saddr ") {
+                        vec![rule.replace(" inet dfw ", " ip6 dfw ")]
+                    } else {
+                        vec![
+                            rule.replace(" inet dfw ", " ip dfw "),
+                            rule.replace(" inet dfw ", " ip6 dfw "),
+                        ]
+                    }
+                })
+                .collect();
+            (*section, rules)
+        })
+        .collect::<Vec<_>>();
+    assert_eq!(expected_sections, split.sections);
+
+    let commands = split.commands();
+    for expected in &[
+        "add rule ip dfw forward meta iifname br-internalnetw oifname eth0 \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule ip6 dfw forward meta iifname br-internalnetw oifname eth0 \
+         meta mark set 0xdf reject comment \"DFW-MARKER:section;container_to_wider_world\"",
+        "add rule ip dfw forward tcp dport 443 ip daddr 172.24.0.4 \
+         meta iifname eth0 oifname br-reverseproxy meta mark set 0xdf accept \
+         comment \"DFW-MARKER:section;wider_world_to_container\"",
+    ] {
+        assert!(
+            commands.contains(&(*expected).to_owned()),
+            "missing command: {}",
+            expected
+        );
+    }
+    assert!(!commands
+        .iter()
+        .any(|command| command.starts_with("add rule ip6 dfw forward tcp dport 443 ")));
+}
+
+#[test]
+fn generate_interior_only() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_webserver"
+        expose_port = [{ host_port = 8080, container_port = 80 }, { host_port = 53, family = "udp" }]
+        interior_only = true
+        from_containers = ["my_reverseproxy", "container_a"]
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate_idempotent(&dfw, &full_example_inventory());
+
+    // Only the containers attached to the network reach the container ports, neither DNAT rules
+    // nor rules for the external network interfaces are generated
+    assert_eq!(
+        vec![
+            "add rule inet dfw forward tcp dport 80 ip saddr 172.24.0.4 ip daddr 172.24.0.5 \
+             meta iifname br-reverseproxy oifname br-reverseproxy meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+            "add rule inet dfw forward udp dport 53 ip saddr 172.24.0.4 ip daddr 172.24.0.5 \
+             meta iifname br-reverseproxy oifname br-reverseproxy meta mark set 0xdf accept \
+             comment \"DFW-MARKER:section;wider_world_to_container\"",
+        ],
+        ruleset
+            .sections
+            .iter()
+            .filter(|(section, _)| *section == Section::WiderWorldToContainer)
+            .flat_map(|(_, rules)| rules.iter().cloned())
+            .collect::<Vec<_>>()
+    );
+    assert!(ruleset
+        .commands()
+        .iter()
+        .all(|command| !(command.contains(" prerouting ") && command.contains(" dnat "))));
+}
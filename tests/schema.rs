@@ -0,0 +1,194 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+use dfw::schema::*;
+use dfw::types::*;
+use serde::de::{self, value, Deserialize, Deserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+/// Deserializer recording the fields a struct is deserialized from, without deserializing it.
+struct FieldRecorder<'a>(&'a mut &'static [&'static str]);
+
+impl<'de, 'a> Deserializer<'de> for FieldRecorder<'a> {
+    type Error = value::Error;
+
+    fn deserialize_any<V>(self, _visitor: V) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        Err(de::Error::custom("only structs are supported"))
+    }
+
+    fn deserialize_struct<V>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error>
+    where
+        V: Visitor<'de>,
+    {
+        *self.0 = fields;
+        Err(de::Error::custom("fields recorded"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 u8 u16 u32 u64 f32 f64 char str string bytes byte_buf option unit
+        unit_struct newtype_struct seq tuple tuple_struct map enum identifier ignored_any
+    }
+}
+
+fn fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldRecorder(&mut fields));
+    assert!(!fields.is_empty());
+
+    fields
+}
+
+fn keys_below<'a>(keys: &'a [ConfigKeyDoc], path: &str) -> Vec<&'a ConfigKeyDoc> {
+    keys.iter()
+        .filter(|key| match path {
+            "" => !key.path.contains('.'),
+            path => key
+                .path
+                .strip_prefix(path)
+                .and_then(|name| name.strip_prefix('.'))
+                .is_some_and(|name| !name.contains('.')),
+        })
+        .collect()
+}
+
+#[test]
+fn config_keys_represent_every_field() {
+    let keys = config_keys();
+    let structs = vec![
+        (fields::<DFW>(), ""),
+        (fields::<Defaults>(), "defaults"),
+        (fields::<Table>(), "defaults.custom_tables"),
+        (
+            fields::<FamilyInterfaces>(),
+            "defaults.external_network_interfaces",
+        ),
+        (fields::<BaseChains>(), "defaults.base_chains"),
+        (fields::<BaseChain>(), "defaults.base_chains.input"),
+        (fields::<Initialization>(), "initialization"),
+        (fields::<ContainerToContainer>(), "container_to_container"),
+        (
+            fields::<ContainerToContainerRule>(),
+            "container_to_container.rules",
+        ),
+        (
+            fields::<StatefulVerdict>(),
+            "container_to_container.rules.verdict",
+        ),
+        (
+            fields::<ExposePort>(),
+            "container_to_container.rules.expose_port",
+        ),
+        (fields::<Condition>(), "container_to_container.rules.when"),
+        (fields::<Dns>(), "dns"),
+        (
+            fields::<ContainerToWiderWorld>(),
+            "container_to_wider_world",
+        ),
+        (
+            fields::<FamilyVerdict>(),
+            "container_to_wider_world.default_policy",
+        ),
+        (
+            fields::<ContainerToWiderWorldRule>(),
+            "container_to_wider_world.rules",
+        ),
+        (fields::<ContainerToHost>(), "container_to_host"),
+        (fields::<ContainerToHostRule>(), "container_to_host.rules"),
+        (
+            fields::<WiderWorldToContainer>(),
+            "wider_world_to_container",
+        ),
+        (
+            fields::<WiderWorldToContainerRule>(),
+            "wider_world_to_container.rules",
+        ),
+        (fields::<Quota>(), "wider_world_to_container.rules.quota"),
+        (
+            fields::<LogUnmatched>(),
+            "wider_world_to_container.log_unmatched",
+        ),
+        (fields::<ContainerDNAT>(), "container_dnat"),
+        (fields::<ContainerDNATRule>(), "container_dnat.rules"),
+        (fields::<Runtime>(), "runtime"),
+    ];
+
+    for (fields, path) in structs {
+        let keys = keys_below(&keys, path);
+        let names: Vec<&str> = keys
+            .iter()
+            .flat_map(|key| {
+                let name = key.path.rsplit('.').next().unwrap();
+                Some(name)
+                    .into_iter()
+                    .chain(key.aliases.iter().map(String::as_str))
+            })
+            .collect();
+        for field in fields {
+            assert!(
+                names.contains(field),
+                "field `{}` of `{}` is not described",
+                field,
+                path
+            );
+        }
+        for key in keys {
+            let name = key.path.rsplit('.').next().unwrap();
+            assert!(
+                fields.contains(&name),
+                "key `{}` is not a field of the configuration",
+                key.path
+            );
+        }
+    }
+}
+
+#[test]
+fn config_keys_are_documented() {
+    for key in config_keys() {
+        assert!(!key.doc.is_empty(), "key `{}` is undocumented", key.path);
+        assert!(!key.value_type.is_empty());
+    }
+}
+
+#[test]
+fn config_keys_skip_provenance() {
+    assert!(config_keys()
+        .iter()
+        .all(|key| !key.path.ends_with("provenance")));
+}
+
+#[test]
+fn config_keys_describe_key() {
+    let keys = config_keys();
+    let verdict = keys
+        .iter()
+        .find(|key| key.path == "container_to_container.rules.verdict")
+        .unwrap();
+
+    assert_eq!(verdict.value_type, "StatefulVerdict");
+    assert!(!verdict.optional);
+    assert_eq!(verdict.aliases, vec!["action".to_owned()]);
+    assert!(verdict
+        .doc
+        .starts_with("Verdict for rule (accept, drop or reject)"));
+
+    let rules = keys
+        .iter()
+        .find(|key| key.path == "container_to_container.rules")
+        .unwrap();
+    assert_eq!(rules.value_type, "Vec<ContainerToContainerRule>");
+    assert!(rules.optional);
+}
@@ -0,0 +1,381 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+#![cfg(feature = "backend")]
+
+use dfw::inventory::StaticInventory;
+use dfw::nftables::RuleVerdict;
+use dfw::process::{generate, RuleSet, Section};
+use dfw::simulate::*;
+use dfw::types::{FamilyVerdict, DFW};
+use dfw::util::load_file;
+
+const RESOURCES: &str = "resources/test/inventory";
+
+fn inventory() -> StaticInventory {
+    StaticInventory::load(&format!("{}/inventory.toml", RESOURCES)).unwrap()
+}
+
+fn ruleset() -> RuleSet {
+    let dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    generate(&dfw, &inventory()).unwrap()
+}
+
+fn backend_packet(src_container: &str, dst_container: &str) -> Packet {
+    let inventory = inventory();
+    Packet {
+        chain: Chain::Forward,
+        in_interface: Some("br-f0e1d2c3b4a5".to_owned()),
+        out_interface: Some("br-f0e1d2c3b4a5".to_owned()),
+        source_address: Some(container_address(&inventory, src_container, "backend").unwrap()),
+        destination_address: Some(container_address(&inventory, dst_container, "backend").unwrap()),
+        protocol: Some("tcp".to_owned()),
+        destination_port: Some(8080),
+        ..Default::default()
+    }
+}
+
+#[test]
+fn simulate_accept_rule() {
+    let simulation = simulate(&ruleset(), &backend_packet("proxy", "app")).unwrap();
+
+    assert_eq!("accept", simulation.verdict);
+    assert_eq!(Some(Section::ContainerToContainer), simulation.section);
+    assert!(simulation
+        .rule
+        .unwrap()
+        .contains("ip saddr 172.19.0.2 ip daddr 172.19.0.3"));
+}
+
+#[test]
+fn simulate_default_policy_drop() {
+    // The reverse direction is not allowed, the policy of the forward chain applies
+    let simulation = simulate(&ruleset(), &backend_packet("app", "proxy")).unwrap();
+    assert_eq!(
+        Simulation {
+            verdict: "drop".to_owned(),
+            rule: None,
+            section: None,
+        },
+        simulation
+    );
+
+    // A different port is not allowed either
+    let packet = Packet {
+        destination_port: Some(8081),
+        ..backend_packet("proxy", "app")
+    };
+    assert_eq!("drop", simulate(&ruleset(), &packet).unwrap().verdict);
+}
+
+#[test]
+fn simulate_established_connection() {
+    let packet = Packet {
+        ct_state: ConntrackState::Established,
+        ..backend_packet("app", "proxy")
+    };
+    let simulation = simulate(&ruleset(), &packet).unwrap();
+
+    assert_eq!("accept", simulation.verdict);
+    assert_eq!(None, simulation.section);
+    assert_eq!(
+        Some("add rule inet dfw forward ct state { related, established } accept".to_owned()),
+        simulation.rule
+    );
+}
+
+#[test]
+fn simulate_interface_scoped_rule() {
+    let inventory = inventory();
+    let ruleset = ruleset();
+
+    // Only `app` may access the host from the backend network, everything else is dropped by the
+    // interface-scoped rule.
+    let packet = Packet {
+        chain: Chain::Input,
+        in_interface: Some("br-f0e1d2c3b4a5".to_owned()),
+        source_address: Some(container_address(&inventory, "app", "backend").unwrap()),
+        ..Default::default()
+    };
+    assert_eq!("accept", simulate(&ruleset, &packet).unwrap().verdict);
+
+    let packet = Packet {
+        source_address: Some(container_address(&inventory, "proxy", "backend").unwrap()),
+        ..packet
+    };
+    let simulation = simulate(&ruleset, &packet).unwrap();
+    assert_eq!("drop", simulation.verdict);
+    assert_eq!(Some(Section::ContainerToHost), simulation.section);
+    assert_eq!(
+        Some(
+            "add rule inet dfw input meta iifname br-f0e1d2c3b4a5 meta mark set 0xdf drop \
+             comment \"DFW-MARKER:section;container_to_host\""
+                .to_owned()
+        ),
+        simulation.rule
+    );
+
+    // The same packet arriving on the default bridge is accepted by the defaults
+    let packet = Packet {
+        in_interface: Some("docker0".to_owned()),
+        ..packet
+    };
+    let simulation = simulate(&ruleset, &packet).unwrap();
+    assert_eq!("accept", simulation.verdict);
+    assert_eq!(Some(Section::Defaults), simulation.section);
+}
+
+#[test]
+fn simulate_dnat() {
+    let packet = Packet {
+        chain: Chain::Prerouting,
+        in_interface: Some("eth0".to_owned()),
+        source_address: Some("203.0.113.7".parse().unwrap()),
+        protocol: Some("tcp".to_owned()),
+        destination_port: Some(443),
+        ..Default::default()
+    };
+    let simulation = simulate(&ruleset(), &packet).unwrap();
+
+    assert_eq!("dnat 172.18.0.2:443", simulation.verdict);
+    assert_eq!(Some(Section::WiderWorldToContainer), simulation.section);
+}
+
+fn frontend_packet(ct_state: ConntrackState) -> Packet {
+    Packet {
+        chain: Chain::Forward,
+        ct_state,
+        in_interface: Some("eth0".to_owned()),
+        out_interface: Some("br-6d4c1b5e9f0a".to_owned()),
+        source_address: Some("203.0.113.7".parse().unwrap()),
+        destination_address: Some("172.18.0.2".parse().unwrap()),
+        protocol: Some("tcp".to_owned()),
+        destination_port: Some(443),
+        dnat: true,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn simulate_dnat_accept_shortcut() {
+    // New connections are accepted by the rules of the sections
+    let simulation = simulate(&ruleset(), &frontend_packet(ConntrackState::New)).unwrap();
+    assert_eq!("accept", simulation.verdict);
+    assert_eq!(Some(Section::WiderWorldToContainer), simulation.section);
+
+    // Established connections are marked and accepted by the preamble
+    let simulation = simulate(&ruleset(), &frontend_packet(ConntrackState::Established)).unwrap();
+    assert_eq!("accept", simulation.verdict);
+    assert_eq!(None, simulation.section);
+    assert_eq!(
+        Some(
+            "add rule inet dfw forward ct state { related, established } ct status dnat \
+             meta mark set 0xdf accept"
+                .to_owned()
+        ),
+        simulation.rule
+    );
+}
+
+#[test]
+fn simulate_drain_with_dnat_accept_shortcut() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+        dnat_accept_shortcut = true
+
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "proxy"
+        expose_port = 443
+        drain = true
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate(&dfw, &inventory()).unwrap();
+
+    // New connections to the draining port are dropped, even though they were translated by DNAT
+    let simulation = simulate(&ruleset, &frontend_packet(ConntrackState::New)).unwrap();
+    assert_eq!("drop", simulation.verdict);
+    assert_eq!(Some(Section::WiderWorldToContainer), simulation.section);
+
+    // Ongoing connections continue to be accepted
+    let simulation = simulate(&ruleset, &frontend_packet(ConntrackState::Established)).unwrap();
+    assert_eq!("accept", simulation.verdict);
+}
+
+#[test]
+fn simulate_log_verdict() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "proxy"
+        dst_container = "app"
+        verdict = "log"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "proxy"
+        dst_container = "app"
+        matches = "tcp dport 8080"
+        verdict = "reject"
+        "#,
+    )
+    .unwrap();
+    let ruleset = generate(&dfw, &inventory()).unwrap();
+
+    // The log-only rule matches without deciding, the evaluation continues with the next rule
+    let simulation = simulate(&ruleset, &backend_packet("proxy", "app")).unwrap();
+    assert_eq!("reject", simulation.verdict);
+    assert_eq!(Some(Section::ContainerToContainer), simulation.section);
+    assert!(simulation.rule.unwrap().contains("tcp dport 8080"));
+}
+
+#[test]
+fn simulate_dnat_map() {
+    let ruleset = RuleSet {
+        preamble: Vec::new(),
+        sections: vec![(
+            Section::WiderWorldToContainer,
+            vec![
+                "add rule ip dfw prerouting tcp dport { 8000, 8001 } meta iifname eth0 \
+                  dnat ip addr . port to tcp dport map { 8000 : 172.18.0.2 . 80, \
+                  8001 : 172.18.0.3 . 80 } comment \"DFW-MARKER:section;wider_world_to_container\""
+                    .to_owned(),
+            ],
+        )],
+        ..Default::default()
+    };
+    let packet = Packet {
+        chain: Chain::Prerouting,
+        in_interface: Some("eth0".to_owned()),
+        protocol: Some("tcp".to_owned()),
+        destination_port: Some(8001),
+        ..Default::default()
+    };
+    let simulation = simulate(&ruleset, &packet).unwrap();
+    assert_eq!("dnat 172.18.0.3:80", simulation.verdict);
+    assert_eq!(Some(Section::WiderWorldToContainer), simulation.section);
+
+    let packet = Packet {
+        destination_port: Some(8002),
+        ..packet
+    };
+    assert_eq!(None, simulate(&ruleset, &packet).unwrap().rule);
+}
+
+#[test]
+fn simulate_egress_nat_exclusions() {
+    let packet = Packet {
+        chain: Chain::Postrouting,
+        out_interface: Some("eth0".to_owned()),
+        source_address: Some("172.19.0.3".parse().unwrap()),
+        destination_address: Some("198.51.100.1".parse().unwrap()),
+        ..Default::default()
+    };
+    assert_eq!("masquerade", simulate(&ruleset(), &packet).unwrap().verdict);
+
+    let packet = Packet {
+        destination_address: Some("172.18.0.2".parse().unwrap()),
+        ..packet
+    };
+    assert_eq!("accept", simulate(&ruleset(), &packet).unwrap().verdict);
+}
+
+#[test]
+fn simulate_family_mismatch() {
+    let packet = Packet {
+        family: PacketFamily::Ipv6,
+        source_address: Some("172.19.0.3".parse().unwrap()),
+        ..Default::default()
+    };
+    let error = simulate(&ruleset(), &packet).unwrap_err();
+
+    assert_eq!(
+        "address 172.19.0.3 doesn't match the packet family ipv6",
+        error.to_string()
+    );
+}
+
+#[test]
+fn simulate_family_default_policy() {
+    let mut dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    dfw.container_to_wider_world
+        .as_mut()
+        .unwrap()
+        .default_policy = FamilyVerdict {
+        v4: RuleVerdict::Accept,
+        v6: RuleVerdict::Drop,
+    };
+    let ruleset = generate(&dfw, &inventory()).unwrap();
+
+    let packet = Packet {
+        in_interface: Some("br-f0e1d2c3b4a5".to_owned()),
+        out_interface: Some("eth0".to_owned()),
+        ..Default::default()
+    };
+    assert_eq!("accept", simulate(&ruleset, &packet).unwrap().verdict);
+
+    let packet = Packet {
+        family: PacketFamily::Ipv6,
+        ..packet
+    };
+    let simulation = simulate(&ruleset, &packet).unwrap();
+    assert_eq!("drop", simulation.verdict);
+    assert_eq!(Some(Section::ContainerToWiderWorld), simulation.section);
+}
+
+#[test]
+fn simulate_network_chains() {
+    let mut dfw: DFW = load_file(&format!("{}/conf.toml", RESOURCES)).unwrap();
+    dfw.defaults.as_mut().unwrap().network_chains = true;
+    let network_chains = generate(&dfw, &inventory()).unwrap();
+    let inventory = inventory();
+
+    // Splitting the rules into a chain per network doesn't change the fate of any packet
+    let input_packet = Packet {
+        chain: Chain::Input,
+        in_interface: Some("br-f0e1d2c3b4a5".to_owned()),
+        source_address: Some(container_address(&inventory, "proxy", "backend").unwrap()),
+        ..Default::default()
+    };
+    for packet in &[
+        backend_packet("proxy", "app"),
+        backend_packet("app", "proxy"),
+        Packet {
+            ct_state: ConntrackState::Established,
+            ..backend_packet("app", "proxy")
+        },
+        Packet {
+            out_interface: Some("eth0".to_owned()),
+            ..backend_packet("app", "proxy")
+        },
+        input_packet.clone(),
+        Packet {
+            in_interface: Some("docker0".to_owned()),
+            ..input_packet.clone()
+        },
+    ] {
+        let expected = simulate(&ruleset(), packet).unwrap();
+        let simulation = simulate(&network_chains, packet).unwrap();
+        assert_eq!(expected.verdict, simulation.verdict, "{:?}", packet);
+        assert_eq!(expected.section, simulation.section, "{:?}", packet);
+    }
+
+    // The deciding rule is part of the chain of the network
+    let simulation = simulate(&network_chains, &backend_packet("proxy", "app")).unwrap();
+    assert!(simulation
+        .rule
+        .unwrap()
+        .starts_with("add rule inet dfw forward_br-f0e1d2c3b4a5 "));
+}
@@ -0,0 +1,117 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+#![cfg(feature = "swarm")]
+
+use dfw::inventory::{ContainerInventory, StaticInventory};
+use dfw::process::generate;
+use dfw::swarm::*;
+use dfw::types::DFW;
+use std::fs;
+
+const SERVICES: &str = "resources/test/swarm/services.json";
+
+fn swarm_inventory<'a>() -> SwarmInventory<'a> {
+    let inventory: StaticInventory = toml::from_str(
+        r#"
+        [networks.backend]
+        id = "qkd6ff34ryqvq3h2xkvj9ijx1"
+
+        [containers.app]
+        networks.backend = { ipv4_address = "10.0.1.10/24" }
+        "#,
+    )
+    .unwrap();
+
+    SwarmInventory::new(Box::new(inventory), || Ok(fs::read_to_string(SERVICES)?))
+}
+
+#[test]
+fn parse_recorded_services() {
+    let services = SwarmService::from_api_response(&fs::read_to_string(SERVICES).unwrap()).unwrap();
+
+    assert_eq!(2, services.len());
+    let web = &services[0];
+    assert_eq!("9mnpnzenvg8p8tdbtq4wvbkcz", web.id);
+    assert_eq!("web", web.spec.name);
+    assert_eq!(
+        Some(&"web".to_owned()),
+        web.spec.labels.get("com.example.tier")
+    );
+    assert_eq!(
+        Some("10.0.1.2/24"),
+        web.virtual_ip("qkd6ff34ryqvq3h2xkvj9ijx1")
+    );
+    assert_eq!(
+        Some("10.255.0.5/16"),
+        web.virtual_ip("4vdx1v6qydbxl9c7s1b2jd7xh")
+    );
+    assert_eq!(None, web.virtual_ip("unknown"));
+
+    // Services using DNS round-robin don't have a VIP
+    let db = &services[1];
+    assert_eq!("db", db.spec.name);
+    assert!(db.endpoint.virtual_ips.is_empty());
+}
+
+#[test]
+fn swarm_inventory_lists_services() {
+    let inventory = swarm_inventory();
+
+    let containers = inventory.containers().unwrap();
+    let mut names = containers
+        .iter()
+        .flat_map(|container| container.names.iter().map(String::as_str))
+        .collect::<Vec<_>>();
+    names.sort();
+    assert_eq!(vec!["app", "db", "web"], names);
+
+    let networks = inventory.networks().unwrap();
+    let backend = networks
+        .iter()
+        .find(|network| network.name == "backend")
+        .unwrap();
+    assert_eq!(
+        "10.0.1.2/24",
+        backend.containers["9mnpnzenvg8p8tdbtq4wvbkcz"].ipv4_address
+    );
+    assert!(!backend.containers.contains_key("ui7ga0pdxx9ctfj7b8kndaz2v"));
+}
+
+#[test]
+fn generate_rule_for_service_vip() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "app"
+        dst_container = "web"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+    let commands = generate(&dfw, &swarm_inventory())
+        .map_err(|error| error.to_string())
+        .unwrap()
+        .commands();
+
+    let rule = commands
+        .iter()
+        .find(|command| command.contains("ip daddr 10.0.1.2 "))
+        .unwrap();
+    assert!(
+        rule.contains("ip saddr 10.0.1.10 ip daddr 10.0.1.2 ")
+            && rule.contains("meta iifname br-qkd6ff34ryqv oifname br-qkd6ff34ryqv")
+            && rule.ends_with("accept comment \"DFW-MARKER:section;container_to_container\""),
+        "{}",
+        rule
+    );
+}
@@ -9,102 +9,225 @@
 mod common;
 
 use common::resource;
+use dfw::errors::DFWError;
 use dfw::nftables::{ChainPolicy, RuleVerdict};
 use dfw::types::*;
 use dfw::util::*;
+use std::fs;
+use std::io::Cursor;
 
 #[test]
 fn parse_conf_file() {
     let defaults = Defaults {
         custom_tables: None,
-        external_network_interfaces: Some(vec!["eni".to_owned()]),
+        external_network_interfaces: Some(vec!["eni".to_owned()].into()),
         default_docker_bridge_to_host_policy: ChainPolicy::Accept,
+        drop_invalid: true,
+        egress_nat: EgressNat::Masquerade,
+        ipv6_masquerade: false,
+        auto_port_range: None,
+        compat_mode: false,
+        netns: None,
+        nft_path: None,
+        docker_concurrency: DEFAULT_DOCKER_CONCURRENCY,
+        docker_retries: DEFAULT_DOCKER_RETRIES,
+        docker_retry_backoff: DEFAULT_DOCKER_RETRY_BACKOFF,
+        swarm_services: false,
+        annotate_rules: false,
+        conntrack_zones: false,
+        ct_timeouts: None,
+        section_order: None,
+        log_rate: "100/second".to_owned(),
+        network_chains: false,
+        external_interface_chains: false,
+        dnat_new_only: true,
+        dnat_accept_shortcut: true,
+        max_generated_rules: None,
+        dns_failure_policy: DnsFailurePolicy::Keep,
+        allow_embedded_dns: true,
+        base_chains: Default::default(),
+        rule_removal_grace_s: 0,
+        preserve_foreign_rules: false,
+        table_family: TableFamily::Inet,
     };
     let initialization = Initialization {
+        enabled: None,
         rules: Some(vec!["add table inet custom".to_owned()]),
     };
     let container_to_container = ContainerToContainer {
+        enabled: None,
         default_policy: ChainPolicy::Drop,
         rules: Some(vec![ContainerToContainerRule {
             network: "network".to_owned(),
-            src_container: Some("src_container".to_owned()),
-            dst_container: Some("dst_container".to_owned()),
+            dst_network: None,
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
+            dst_container: Some(ContainerSelector::Name("dst_container".to_owned())),
+            src_tags: None,
+            dst_tags: None,
+            expose_port: vec![],
             matches: Some("FILTER".to_owned()),
-            verdict: RuleVerdict::Accept,
+            min_ct_bytes: None,
+            verdict: RuleVerdict::Accept.into(),
+            mirror_to: None,
+            log: false,
+            dscp: None,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         }]),
     };
     let container_to_wider_world = ContainerToWiderWorld {
-        default_policy: RuleVerdict::Accept,
+        enabled: None,
+        default_policy: RuleVerdict::Accept.into(),
         rules: Some(vec![ContainerToWiderWorldRule {
             network: Some("network".to_owned()),
-            src_container: Some("src_container".to_owned()),
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
             matches: Some("FILTER".to_owned()),
-            verdict: RuleVerdict::Accept,
-            external_network_interface: Some("eni".to_owned()),
+            min_ct_bytes: None,
+            verdict: RuleVerdict::Accept.into(),
+            external_network_interface: Some(vec!["eni".to_owned()]),
+            mirror_to: None,
+            log: false,
+            vlan_id: None,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         }]),
     };
     let container_to_host = ContainerToHost {
+        enabled: None,
         default_policy: RuleVerdict::Accept,
         rules: Some(vec![ContainerToHostRule {
             network: "network".to_owned(),
-            src_container: Some("src_container".to_owned()),
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
             matches: Some("FILTER".to_owned()),
-            verdict: RuleVerdict::Accept,
+            min_ct_bytes: None,
+            verdict: RuleVerdict::Accept.into(),
+            mirror_to: None,
+            log: false,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         }]),
     };
     let wider_world_to_container = WiderWorldToContainer {
+        enabled: None,
         rules: Some(vec![
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: ContainerSelector::Name("dst_container".to_owned()),
                 expose_port: vec![ExposePort {
                     host_port: 80,
                     container_port: None,
                     family: "tcp".to_owned(),
+                    host_ip: None,
+                    host_port_range: None,
+                    container_port_range: None,
+                    dnat: true,
+                    published: false,
+                    exposed: false,
                 }],
-                external_network_interface: Some("eni".to_owned()),
+                external_network_interface: Some(vec!["eni".to_owned()]),
                 source_cidr_v4: None,
                 source_cidr_v6: None,
+                dnat_to: None,
+                dst_ip: None,
+                host_ip: None,
+                dscp: None,
+                require_healthy: false,
+                drain: false,
+                notrack: false,
+                quota: None,
+                ct_timeout: None,
+                clamp_mss_to_pmtu: false,
+                allow_public: false,
+                interior_only: false,
+                from_containers: vec![],
+                vlan_id: None,
+                families: None,
+                on_missing: None,
+                when: None,
+                provenance: None,
             },
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: ContainerSelector::Name("dst_container".to_owned()),
                 expose_port: vec![ExposePort {
                     host_port: 22,
                     container_port: None,
                     family: "tcp".to_owned(),
+                    host_ip: None,
+                    host_port_range: None,
+                    container_port_range: None,
+                    dnat: true,
+                    published: false,
+                    exposed: false,
                 }],
-                external_network_interface: Some("eni".to_owned()),
+                external_network_interface: Some(vec!["eni".to_owned()]),
                 source_cidr_v4: Some(vec!["192.0.2.1/32".to_owned(), "192.0.2.2/32".to_owned()]),
                 source_cidr_v6: Some(vec![
                     "2001:db8::1/128".to_owned(),
                     "2001:db8::2/128".to_owned(),
                 ]),
+                dnat_to: None,
+                dst_ip: None,
+                host_ip: None,
+                dscp: None,
+                require_healthy: false,
+                drain: false,
+                notrack: false,
+                quota: None,
+                ct_timeout: None,
+                clamp_mss_to_pmtu: false,
+                allow_public: false,
+                interior_only: false,
+                from_containers: vec![],
+                vlan_id: None,
+                families: None,
+                on_missing: None,
+                when: None,
+                provenance: None,
             },
         ]),
+        log_unmatched: None,
     };
     let container_dnat = ContainerDNAT {
+        enabled: None,
         rules: Some(vec![ContainerDNATRule {
             src_network: Some("src_network".to_owned()),
-            src_container: Some("src_container".to_owned()),
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
             dst_network: "dst_network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: ContainerSelector::Name("dst_container".to_owned()),
             expose_port: vec![ExposePort {
                 host_port: 80,
                 container_port: None,
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             }],
+            when: None,
+            provenance: None,
         }]),
     };
 
     let expected: DFW = DFW {
+        version: None,
         defaults: Some(defaults),
         initialization: Some(initialization),
         container_to_container: Some(container_to_container),
+        dns: None,
         container_to_wider_world: Some(container_to_wider_world),
         container_to_host: Some(container_to_host),
         wider_world_to_container: Some(wider_world_to_container),
         container_dnat: Some(container_dnat),
+        runtime: None,
     };
 
     let actual: DFW = load_file(&resource("conf-file.toml").unwrap()).unwrap();
@@ -116,94 +239,214 @@ fn parse_conf_file() {
 fn parse_conf_path() {
     let defaults = Defaults {
         custom_tables: None,
-        external_network_interfaces: Some(vec!["eni".to_owned()]),
+        external_network_interfaces: Some(vec!["eni".to_owned()].into()),
         default_docker_bridge_to_host_policy: ChainPolicy::Accept,
+        drop_invalid: true,
+        egress_nat: EgressNat::Masquerade,
+        ipv6_masquerade: false,
+        auto_port_range: None,
+        compat_mode: false,
+        netns: None,
+        nft_path: None,
+        docker_concurrency: DEFAULT_DOCKER_CONCURRENCY,
+        docker_retries: DEFAULT_DOCKER_RETRIES,
+        docker_retry_backoff: DEFAULT_DOCKER_RETRY_BACKOFF,
+        swarm_services: false,
+        annotate_rules: false,
+        conntrack_zones: false,
+        ct_timeouts: None,
+        section_order: None,
+        log_rate: "100/second".to_owned(),
+        network_chains: false,
+        external_interface_chains: false,
+        dnat_new_only: true,
+        dnat_accept_shortcut: true,
+        max_generated_rules: None,
+        dns_failure_policy: DnsFailurePolicy::Keep,
+        allow_embedded_dns: true,
+        base_chains: Default::default(),
+        rule_removal_grace_s: 0,
+        preserve_foreign_rules: false,
+        table_family: TableFamily::Inet,
     };
     let initialization = Initialization {
+        enabled: None,
         rules: Some(vec!["add table inet custom".to_owned()]),
     };
     let container_to_container = ContainerToContainer {
+        enabled: None,
         default_policy: ChainPolicy::Drop,
         rules: Some(vec![ContainerToContainerRule {
             network: "network".to_owned(),
-            src_container: Some("src_container".to_owned()),
-            dst_container: Some("dst_container".to_owned()),
+            dst_network: None,
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
+            dst_container: Some(ContainerSelector::Name("dst_container".to_owned())),
+            src_tags: None,
+            dst_tags: None,
+            expose_port: vec![],
             matches: Some("FILTER".to_owned()),
-            verdict: RuleVerdict::Accept,
+            min_ct_bytes: None,
+            verdict: RuleVerdict::Accept.into(),
+            mirror_to: None,
+            log: false,
+            dscp: None,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         }]),
     };
     let container_to_wider_world = ContainerToWiderWorld {
-        default_policy: RuleVerdict::Accept,
+        enabled: None,
+        default_policy: RuleVerdict::Accept.into(),
         rules: Some(vec![ContainerToWiderWorldRule {
             network: Some("network".to_owned()),
-            src_container: Some("src_container".to_owned()),
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
             matches: Some("FILTER".to_owned()),
-            verdict: RuleVerdict::Accept,
-            external_network_interface: Some("eni".to_owned()),
+            min_ct_bytes: None,
+            verdict: RuleVerdict::Accept.into(),
+            external_network_interface: Some(vec!["eni".to_owned()]),
+            mirror_to: None,
+            log: false,
+            vlan_id: None,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         }]),
     };
     let container_to_host = ContainerToHost {
+        enabled: None,
         default_policy: RuleVerdict::Accept,
         rules: Some(vec![ContainerToHostRule {
             network: "network".to_owned(),
-            src_container: Some("src_container".to_owned()),
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
             matches: Some("FILTER".to_owned()),
-            verdict: RuleVerdict::Accept,
+            min_ct_bytes: None,
+            verdict: RuleVerdict::Accept.into(),
+            mirror_to: None,
+            log: false,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         }]),
     };
     let wider_world_to_container = WiderWorldToContainer {
+        enabled: None,
         rules: Some(vec![
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: ContainerSelector::Name("dst_container".to_owned()),
                 expose_port: vec![ExposePort {
                     host_port: 80,
                     container_port: None,
                     family: "tcp".to_owned(),
+                    host_ip: None,
+                    host_port_range: None,
+                    container_port_range: None,
+                    dnat: true,
+                    published: false,
+                    exposed: false,
                 }],
-                external_network_interface: Some("eni".to_owned()),
+                external_network_interface: Some(vec!["eni".to_owned()]),
                 source_cidr_v4: None,
                 source_cidr_v6: None,
+                dnat_to: None,
+                dst_ip: None,
+                host_ip: None,
+                dscp: None,
+                require_healthy: false,
+                drain: false,
+                notrack: false,
+                quota: None,
+                ct_timeout: None,
+                clamp_mss_to_pmtu: false,
+                allow_public: false,
+                interior_only: false,
+                from_containers: vec![],
+                vlan_id: None,
+                families: None,
+                on_missing: None,
+                when: None,
+                provenance: None,
             },
             WiderWorldToContainerRule {
                 network: "network".to_owned(),
-                dst_container: "dst_container".to_owned(),
+                dst_container: ContainerSelector::Name("dst_container".to_owned()),
                 expose_port: vec![ExposePort {
                     host_port: 22,
                     container_port: None,
                     family: "tcp".to_owned(),
+                    host_ip: None,
+                    host_port_range: None,
+                    container_port_range: None,
+                    dnat: true,
+                    published: false,
+                    exposed: false,
                 }],
-                external_network_interface: Some("eni".to_owned()),
+                external_network_interface: Some(vec!["eni".to_owned()]),
                 source_cidr_v4: Some(vec!["192.0.2.1/32".to_owned(), "192.0.2.2/32".to_owned()]),
                 source_cidr_v6: Some(vec![
                     "2001:db8::1/128".to_owned(),
                     "2001:db8::2/128".to_owned(),
                 ]),
+                dnat_to: None,
+                dst_ip: None,
+                host_ip: None,
+                dscp: None,
+                require_healthy: false,
+                drain: false,
+                notrack: false,
+                quota: None,
+                ct_timeout: None,
+                clamp_mss_to_pmtu: false,
+                allow_public: false,
+                interior_only: false,
+                from_containers: vec![],
+                vlan_id: None,
+                families: None,
+                on_missing: None,
+                when: None,
+                provenance: None,
             },
         ]),
+        log_unmatched: None,
     };
     let container_dnat = ContainerDNAT {
+        enabled: None,
         rules: Some(vec![ContainerDNATRule {
             src_network: Some("src_network".to_owned()),
-            src_container: Some("src_container".to_owned()),
+            src_container: Some(ContainerSelector::Name("src_container".to_owned())),
             dst_network: "dst_network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: ContainerSelector::Name("dst_container".to_owned()),
             expose_port: vec![ExposePort {
                 host_port: 80,
                 container_port: None,
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             }],
+            when: None,
+            provenance: None,
         }]),
     };
 
     let expected: DFW = DFW {
+        version: None,
         defaults: Some(defaults),
         initialization: Some(initialization),
         container_to_container: Some(container_to_container),
+        dns: None,
         container_to_wider_world: Some(container_to_wider_world),
         container_to_host: Some(container_to_host),
         wider_world_to_container: Some(wider_world_to_container),
         container_dnat: Some(container_dnat),
+        runtime: None,
     };
 
     let actual: DFW = load_path(&resource("conf_path").unwrap()).unwrap();
@@ -221,15 +464,39 @@ fn parse_expose_port_single_int() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: ContainerSelector::Name("dst_container".to_owned()),
         expose_port: vec![ExposePort {
             host_port: 80,
             container_port: None,
             family: "tcp".to_owned(),
+            host_ip: None,
+            host_port_range: None,
+            container_port_range: None,
+            dnat: true,
+            published: false,
+            exposed: false,
         }],
         external_network_interface: None,
         source_cidr_v4: None,
         source_cidr_v6: None,
+        dnat_to: None,
+        dst_ip: None,
+        host_ip: None,
+        dscp: None,
+        require_healthy: false,
+        drain: false,
+        notrack: false,
+        quota: None,
+        ct_timeout: None,
+        clamp_mss_to_pmtu: false,
+        allow_public: false,
+        interior_only: false,
+        from_containers: vec![],
+        vlan_id: None,
+        families: None,
+        on_missing: None,
+        when: None,
+        provenance: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
@@ -246,22 +513,52 @@ fn parse_expose_port_seq_int() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: ContainerSelector::Name("dst_container".to_owned()),
         expose_port: vec![
             ExposePort {
                 host_port: 80,
                 container_port: None,
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
             ExposePort {
                 host_port: 81,
                 container_port: None,
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
         ],
         external_network_interface: None,
         source_cidr_v4: None,
         source_cidr_v6: None,
+        dnat_to: None,
+        dst_ip: None,
+        host_ip: None,
+        dscp: None,
+        require_healthy: false,
+        drain: false,
+        notrack: false,
+        quota: None,
+        ct_timeout: None,
+        clamp_mss_to_pmtu: false,
+        allow_public: false,
+        interior_only: false,
+        from_containers: vec![],
+        vlan_id: None,
+        families: None,
+        on_missing: None,
+        when: None,
+        provenance: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
@@ -282,15 +579,39 @@ fn parse_expose_port_single_string() {
 
         let expected = WiderWorldToContainerRule {
             network: "network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: ContainerSelector::Name("dst_container".to_owned()),
             expose_port: vec![ExposePort {
                 host_port: port.to_owned(),
                 container_port: None,
                 family: family.to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             }],
             external_network_interface: None,
             source_cidr_v4: None,
             source_cidr_v6: None,
+            dnat_to: None,
+            dst_ip: None,
+            host_ip: None,
+            dscp: None,
+            require_healthy: false,
+            drain: false,
+            notrack: false,
+            quota: None,
+            ct_timeout: None,
+            clamp_mss_to_pmtu: false,
+            allow_public: false,
+            interior_only: false,
+            from_containers: vec![],
+            vlan_id: None,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         };
         let actual: WiderWorldToContainerRule = toml::from_str(&fragment).unwrap();
 
@@ -308,27 +629,63 @@ fn parse_expose_port_seq_string() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: ContainerSelector::Name("dst_container".to_owned()),
         expose_port: vec![
             ExposePort {
                 host_port: 80,
                 container_port: None,
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
             ExposePort {
                 host_port: 53,
                 container_port: None,
                 family: "udp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
             ExposePort {
                 host_port: 1234,
                 container_port: None,
                 family: "other".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
         ],
         external_network_interface: None,
         source_cidr_v4: None,
         source_cidr_v6: None,
+        dnat_to: None,
+        dst_ip: None,
+        host_ip: None,
+        dscp: None,
+        require_healthy: false,
+        drain: false,
+        notrack: false,
+        quota: None,
+        ct_timeout: None,
+        clamp_mss_to_pmtu: false,
+        allow_public: false,
+        interior_only: false,
+        from_containers: vec![],
+        vlan_id: None,
+        families: None,
+        on_missing: None,
+        when: None,
+        provenance: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
@@ -352,15 +709,39 @@ fn parse_expose_port_single_struct() {
 
         let expected = WiderWorldToContainerRule {
             network: "network".to_owned(),
-            dst_container: "dst_container".to_owned(),
+            dst_container: ContainerSelector::Name("dst_container".to_owned()),
             expose_port: vec![ExposePort {
                 host_port: 80,
                 container_port: None,
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             }],
             external_network_interface: None,
             source_cidr_v4: None,
             source_cidr_v6: None,
+            dnat_to: None,
+            dst_ip: None,
+            host_ip: None,
+            dscp: None,
+            require_healthy: false,
+            drain: false,
+            notrack: false,
+            quota: None,
+            ct_timeout: None,
+            clamp_mss_to_pmtu: false,
+            allow_public: false,
+            interior_only: false,
+            from_containers: vec![],
+            vlan_id: None,
+            families: None,
+            on_missing: None,
+            when: None,
+            provenance: None,
         };
         let actual: WiderWorldToContainerRule = toml::from_str(&fragment).unwrap();
 
@@ -383,38 +764,192 @@ fn parse_expose_port_seq_struct() {
 
     let expected = WiderWorldToContainerRule {
         network: "network".to_owned(),
-        dst_container: "dst_container".to_owned(),
+        dst_container: ContainerSelector::Name("dst_container".to_owned()),
         expose_port: vec![
             ExposePort {
                 host_port: 80,
                 container_port: None,
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
             ExposePort {
                 host_port: 8080,
                 container_port: Some(80),
                 family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
             ExposePort {
                 host_port: 8081,
                 container_port: Some(81),
                 family: "udp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
             ExposePort {
                 host_port: 8082,
                 container_port: Some(82),
                 family: "other".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
             },
         ],
         external_network_interface: None,
         source_cidr_v4: None,
         source_cidr_v6: None,
+        dnat_to: None,
+        dst_ip: None,
+        host_ip: None,
+        dscp: None,
+        require_healthy: false,
+        drain: false,
+        notrack: false,
+        quota: None,
+        ct_timeout: None,
+        clamp_mss_to_pmtu: false,
+        allow_public: false,
+        interior_only: false,
+        from_containers: vec![],
+        vlan_id: None,
+        families: None,
+        on_missing: None,
+        when: None,
+        provenance: None,
     };
     let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
 
     assert_eq!(expected, actual);
 }
 
+#[test]
+fn parse_expose_port_struct_family_seq() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = [
+            { host_port = 53, family = ["tcp", "udp"] },
+            { host_port = 8080, container_port = 80, host_ip = "192.0.2.1" },
+            { host_port = 8443, container_port = 443, family = ["tcp"], host_ip = "2001:db8::1" },
+        ]
+        "#;
+
+    let expected = WiderWorldToContainerRule {
+        network: "network".to_owned(),
+        dst_container: ContainerSelector::Name("dst_container".to_owned()),
+        expose_port: vec![
+            ExposePort {
+                host_port: 53,
+                container_port: None,
+                family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
+            },
+            ExposePort {
+                host_port: 53,
+                container_port: None,
+                family: "udp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
+            },
+            ExposePort {
+                host_port: 8080,
+                container_port: Some(80),
+                family: "tcp".to_owned(),
+                host_ip: Some("192.0.2.1".parse().unwrap()),
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
+            },
+            ExposePort {
+                host_port: 8443,
+                container_port: Some(443),
+                family: "tcp".to_owned(),
+                host_ip: Some("2001:db8::1".parse().unwrap()),
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
+            },
+        ],
+        external_network_interface: None,
+        source_cidr_v4: None,
+        source_cidr_v6: None,
+        dnat_to: None,
+        dst_ip: None,
+        host_ip: None,
+        dscp: None,
+        require_healthy: false,
+        drain: false,
+        notrack: false,
+        quota: None,
+        ct_timeout: None,
+        clamp_mss_to_pmtu: false,
+        allow_public: false,
+        interior_only: false,
+        from_containers: vec![],
+        vlan_id: None,
+        families: None,
+        on_missing: None,
+        when: None,
+        provenance: None,
+    };
+    let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+#[should_panic(expected = "must not be empty")]
+fn parse_expose_port_struct_empty_family_seq() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = { host_port = 80, family = [] }
+        "#;
+
+    toml::from_str::<WiderWorldToContainerRule>(fragment).unwrap();
+}
+
+#[test]
+#[should_panic(expected = "invalid IP address syntax")]
+fn parse_expose_port_struct_invalid_host_ip() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = { host_port = 80, host_ip = "192.0.2.300" }
+        "#;
+
+    toml::from_str::<WiderWorldToContainerRule>(fragment).unwrap();
+}
+
 #[test]
 #[should_panic(expected = "port string has invalid format")]
 fn parse_expose_port_string_invalid_format() {
@@ -457,8 +992,35 @@ fn parse_external_network_interfaces_single() {
 
     let expected = Defaults {
         custom_tables: None,
-        external_network_interfaces: Some(vec!["eni".to_owned()]),
+        external_network_interfaces: Some(vec!["eni".to_owned()].into()),
         default_docker_bridge_to_host_policy: ChainPolicy::Accept,
+        drop_invalid: true,
+        egress_nat: EgressNat::Masquerade,
+        ipv6_masquerade: false,
+        auto_port_range: None,
+        compat_mode: false,
+        netns: None,
+        nft_path: None,
+        docker_concurrency: DEFAULT_DOCKER_CONCURRENCY,
+        docker_retries: DEFAULT_DOCKER_RETRIES,
+        docker_retry_backoff: DEFAULT_DOCKER_RETRY_BACKOFF,
+        swarm_services: false,
+        annotate_rules: false,
+        conntrack_zones: false,
+        ct_timeouts: None,
+        section_order: None,
+        log_rate: "100/second".to_owned(),
+        network_chains: false,
+        external_interface_chains: false,
+        dnat_new_only: true,
+        dnat_accept_shortcut: true,
+        max_generated_rules: None,
+        dns_failure_policy: DnsFailurePolicy::Keep,
+        allow_embedded_dns: true,
+        base_chains: Default::default(),
+        rule_removal_grace_s: 0,
+        preserve_foreign_rules: false,
+        table_family: TableFamily::Inet,
     };
     let actual: Defaults = toml::from_str(fragment).unwrap();
 
@@ -471,10 +1033,963 @@ fn parse_external_network_interfaces_seq() {
 
     let expected = Defaults {
         custom_tables: None,
-        external_network_interfaces: Some(vec!["eni1".to_owned(), "eni2".to_owned()]),
+        external_network_interfaces: Some(vec!["eni1".to_owned(), "eni2".to_owned()].into()),
         default_docker_bridge_to_host_policy: ChainPolicy::Accept,
+        drop_invalid: true,
+        egress_nat: EgressNat::Masquerade,
+        ipv6_masquerade: false,
+        auto_port_range: None,
+        compat_mode: false,
+        netns: None,
+        nft_path: None,
+        docker_concurrency: DEFAULT_DOCKER_CONCURRENCY,
+        docker_retries: DEFAULT_DOCKER_RETRIES,
+        docker_retry_backoff: DEFAULT_DOCKER_RETRY_BACKOFF,
+        swarm_services: false,
+        annotate_rules: false,
+        conntrack_zones: false,
+        ct_timeouts: None,
+        section_order: None,
+        log_rate: "100/second".to_owned(),
+        network_chains: false,
+        external_interface_chains: false,
+        dnat_new_only: true,
+        dnat_accept_shortcut: true,
+        max_generated_rules: None,
+        dns_failure_policy: DnsFailurePolicy::Keep,
+        allow_embedded_dns: true,
+        base_chains: Default::default(),
+        rule_removal_grace_s: 0,
+        preserve_foreign_rules: false,
+        table_family: TableFamily::Inet,
     };
     let actual: Defaults = toml::from_str(fragment).unwrap();
 
     assert_eq!(expected, actual);
 }
+
+#[test]
+fn parse_external_network_interfaces_per_family() {
+    let fragment = r#"external_network_interfaces = { v4 = ["eni1", "eni2"], v6 = "eni3" }"#;
+
+    let actual: Defaults = toml::from_str(fragment).unwrap();
+    let interfaces = actual.external_network_interfaces.unwrap();
+    assert_eq!(
+        FamilyInterfaces {
+            v4: vec!["eni1".to_owned(), "eni2".to_owned()],
+            v6: vec!["eni3".to_owned()],
+        },
+        interfaces
+    );
+    assert!(interfaces.is_split());
+    assert_eq!(
+        Some(&"eni3".to_owned()),
+        interfaces.primary(AddressFamily::V6)
+    );
+    assert_eq!(
+        vec![
+            ("eni1".to_owned(), Some(AddressFamily::V4)),
+            ("eni2".to_owned(), Some(AddressFamily::V4)),
+            ("eni3".to_owned(), Some(AddressFamily::V6)),
+        ],
+        interfaces.with_families()
+    );
+
+    // A family can be left out, it then has no external network interfaces
+    let actual: Defaults =
+        toml::from_str(r#"external_network_interfaces = { v4 = "eni1" }"#).unwrap();
+    assert_eq!(
+        Some(FamilyInterfaces {
+            v4: vec!["eni1".to_owned()],
+            v6: vec![],
+        }),
+        actual.external_network_interfaces
+    );
+
+    // The same interfaces for both families are equivalent to a plain list
+    let actual: Defaults =
+        toml::from_str(r#"external_network_interfaces = { v4 = "eni1", v6 = "eni1" }"#).unwrap();
+    let interfaces = actual.external_network_interfaces.unwrap();
+    assert!(!interfaces.is_split());
+    assert_eq!(vec![("eni1".to_owned(), None)], interfaces.with_families());
+
+    assert!(
+        toml::from_str::<Defaults>(r#"external_network_interfaces = { v5 = "eni1" }"#).is_err()
+    );
+}
+
+#[test]
+fn parse_drop_invalid() {
+    let actual: Defaults = toml::from_str("").unwrap();
+    assert_eq!(Defaults::default(), actual);
+    assert!(actual.drop_invalid);
+
+    let actual: Defaults = toml::from_str("drop_invalid = false").unwrap();
+    assert!(!actual.drop_invalid);
+}
+
+#[test]
+fn parse_container_selector() {
+    let fragment = r#"
+        network = "network"
+        src_container = { alias = "web" }
+        dst_container = { name = "db" }
+        verdict = "accept"
+        "#;
+
+    let actual: ContainerToContainerRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(
+        Some(ContainerSelector::Alias("web".to_owned())),
+        actual.src_container
+    );
+    assert_eq!(
+        Some(ContainerSelector::Name("db".to_owned())),
+        actual.dst_container
+    );
+}
+
+#[test]
+#[should_panic(expected = "exactly one of `name` or `alias`")]
+fn parse_container_selector_name_and_alias() {
+    let fragment = r#"
+        network = "network"
+        dst_container = { name = "db", alias = "db" }
+        expose_port = 80
+        "#;
+
+    toml::from_str::<WiderWorldToContainerRule>(fragment).unwrap();
+}
+
+#[test]
+fn parse_when_condition() {
+    let fragment = r#"
+        network = "network"
+        verdict = "accept"
+        when = { hostname = "edge-*", env = "ROLE=edge" }
+        "#;
+
+    let expected = ContainerToHostRule {
+        network: "network".to_owned(),
+        src_container: None,
+        matches: None,
+        min_ct_bytes: None,
+        verdict: RuleVerdict::Accept.into(),
+        mirror_to: None,
+        log: false,
+        families: None,
+        on_missing: None,
+        when: Some(Condition {
+            hostname: Some("edge-*".to_owned()),
+            env: Some(vec!["ROLE=edge".to_owned()]),
+        }),
+        provenance: None,
+    };
+    let actual: ContainerToHostRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(expected, actual);
+}
+
+#[test]
+fn parse_dnat_to() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = 443
+        dnat_to = "192.0.2.10:8443"
+        "#;
+
+    let actual: WiderWorldToContainerRule = toml::from_str(fragment).unwrap();
+
+    assert_eq!(
+        Some(DnatTarget {
+            address: "192.0.2.10".parse().unwrap(),
+            port: Some(8443),
+        }),
+        actual.dnat_to
+    );
+}
+
+#[test]
+fn parse_dnat_to_address_only() {
+    for (dnat_to, address) in &[("192.0.2.10", "192.0.2.10"), ("2001:db8::1", "2001:db8::1")] {
+        let target: DnatTarget = dnat_to.parse().unwrap();
+
+        assert_eq!(address.parse::<std::net::IpAddr>().unwrap(), target.address);
+        assert_eq!(None, target.port);
+        assert_eq!(*dnat_to, target.to_string());
+    }
+
+    let target: DnatTarget = "[2001:db8::1]:8080".parse().unwrap();
+    assert_eq!(Some(8080), target.port);
+    assert_eq!("[2001:db8::1]:8080", target.to_string());
+}
+
+#[test]
+#[should_panic(expected = "DNAT target has invalid format")]
+fn parse_dnat_to_invalid() {
+    let fragment = r#"
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = 443
+        dnat_to = "192.0.2.10:port"
+        "#;
+
+    toml::from_str::<WiderWorldToContainerRule>(fragment).unwrap();
+}
+
+#[test]
+fn parse_egress_nat() {
+    let actual: Defaults = toml::from_str("").unwrap();
+    assert_eq!(EgressNat::Masquerade, actual.egress_nat);
+
+    let actual: Defaults = toml::from_str(r#"egress_nat = "masquerade""#).unwrap();
+    assert_eq!(EgressNat::Masquerade, actual.egress_nat);
+
+    let actual: Defaults = toml::from_str(r#"egress_nat = { snat = "203.0.113.1" }"#).unwrap();
+    assert_eq!(
+        EgressNat::Snat("203.0.113.1".parse().unwrap()),
+        actual.egress_nat
+    );
+}
+
+#[test]
+#[should_panic(expected = "invalid IP address syntax")]
+fn parse_egress_nat_invalid_snat_address() {
+    toml::from_str::<Defaults>(r#"egress_nat = { snat = "203.0.113" }"#).unwrap();
+}
+
+#[test]
+fn parse_expose_port_auto_host_port() {
+    let actual: WiderWorldToContainerRule = toml::from_str(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = [{ host_port = "auto", container_port = 80 }, { host_port = "auto", container_port = 53, family = "udp" }]
+        "#,
+    )
+    .unwrap();
+    let actual_string: WiderWorldToContainerRule = toml::from_str(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = ["auto:80", "auto:53/udp"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(actual, actual_string);
+
+    assert_eq!(
+        vec![
+            ExposePort {
+                host_port: AUTO_HOST_PORT,
+                container_port: Some(80),
+                family: "tcp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
+            },
+            ExposePort {
+                host_port: AUTO_HOST_PORT,
+                container_port: Some(53),
+                family: "udp".to_owned(),
+                host_ip: None,
+                host_port_range: None,
+                container_port_range: None,
+                dnat: true,
+                published: false,
+                exposed: false,
+            },
+        ],
+        actual.expose_port
+    );
+}
+
+#[test]
+#[should_panic(expected = "automatically assigned host port requires a container port")]
+fn parse_expose_port_auto_host_port_without_container_port() {
+    toml::from_str::<WiderWorldToContainerRule>(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = { host_port = "auto" }
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn parse_expose_port_range() {
+    let actual: WiderWorldToContainerRule = toml::from_str(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = { host_port_range = "8000-8010", container_port_range = "9000-9010" }
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![ExposePort {
+            host_port: 8000,
+            container_port: Some(9000),
+            family: "tcp".to_owned(),
+            host_ip: None,
+            host_port_range: Some(PortRange {
+                start: 8000,
+                end: 8010,
+            }),
+            container_port_range: Some(PortRange {
+                start: 9000,
+                end: 9010,
+            }),
+            dnat: true,
+            published: false,
+            exposed: false,
+        }],
+        actual.expose_port
+    );
+}
+
+#[test]
+#[should_panic(
+    expected = "host port range 8000-8010 and container port range 9000-9009 have to be of equal \
+                width"
+)]
+fn parse_expose_port_range_unequal_width() {
+    toml::from_str::<WiderWorldToContainerRule>(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = { host_port_range = "8000-8010", container_port_range = "9000-9009" }
+        "#,
+    )
+    .unwrap();
+}
+
+#[test]
+fn parse_expose_port_range_incomplete() {
+    for expose_port in &[
+        r#"{ host_port_range = "8000-8010" }"#,
+        r#"{ host_port = 8000, host_port_range = "8000-8010", container_port_range = "9000-9010" }"#,
+        r#"{ host_port_range = "8000-8010", container_port = 9000, container_port_range = "9000-9010" }"#,
+        r#"{ exposed = true, published = true }"#,
+        r#"{ exposed = true, host_port = "auto" }"#,
+        r#"{ exposed = true, family = "udp" }"#,
+        r#"{ exposed = true, host_port_range = "8000-8010", container_port_range = "9000-9010" }"#,
+    ] {
+        let actual = toml::from_str::<WiderWorldToContainerRule>(&format!(
+            r#"
+            network = "network"
+            dst_container = "container"
+            expose_port = {}
+            "#,
+            expose_port
+        ));
+
+        assert!(actual.is_err(), "{}", expose_port);
+    }
+}
+
+#[test]
+fn parse_expose_port_without_dnat() {
+    let actual: WiderWorldToContainerRule = toml::from_str(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = [{ host_port = 80 }, { host_port = 8080, container_port = 80, dnat = false }]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        vec![true, false],
+        actual
+            .expose_port
+            .iter()
+            .map(|expose_port| expose_port.dnat)
+            .collect::<Vec<_>>()
+    );
+
+    for (expose_port, expected) in &[
+        (
+            r#"{ host_port = "auto", container_port = 80, dnat = false }"#,
+            "exposed port without DNAT requires an explicit host port",
+        ),
+        (
+            r#"{ host_port = 80, host_ip = "192.0.2.1", dnat = false }"#,
+            "exposed port without DNAT cannot be restricted to host address 192.0.2.1",
+        ),
+    ] {
+        let error = toml::from_str::<WiderWorldToContainerRule>(&format!(
+            r#"
+            network = "network"
+            dst_container = "container"
+            expose_port = {}
+            "#,
+            expose_port
+        ))
+        .unwrap_err();
+
+        assert!(error.to_string().contains(expected), "{}", error);
+    }
+}
+
+#[test]
+fn validate_expose_port() {
+    let range = |range: &str| Some(range.parse::<PortRange>().unwrap());
+    let port = |port: &str| port.parse::<ExposePort>().unwrap();
+    let published = port("published");
+    let exposed = port("exposed");
+
+    for (expose_port, expected) in vec![
+        (port("80"), None),
+        (port("80:8080/udp"), None),
+        (port("auto:80"), None),
+        (published.clone(), None),
+        (exposed.clone(), None),
+        (
+            ExposePort {
+                host_port: 80,
+                container_port: Some(8080),
+                ..exposed.clone()
+            },
+            None,
+        ),
+        (
+            ExposePort {
+                host_port_range: range("8000-8010"),
+                container_port_range: range("9000-9010"),
+                ..exposed.clone()
+            },
+            Some(
+                "exposed port taken from the image of the container cannot be combined with port \
+                 ranges",
+            ),
+        ),
+        (
+            ExposePort {
+                exposed: true,
+                ..published.clone()
+            },
+            Some(
+                "published ports are taken from Docker and cannot be combined with other port \
+                 settings",
+            ),
+        ),
+        (
+            ExposePort {
+                host_port: 8000,
+                container_port: Some(9000),
+                host_port_range: range("8000-8010"),
+                container_port_range: range("9000-9010"),
+                ..port("80")
+            },
+            None,
+        ),
+        (
+            ExposePort {
+                dnat: false,
+                ..port("80")
+            },
+            None,
+        ),
+        (
+            ExposePort {
+                container_port: Some(80),
+                ..published.clone()
+            },
+            Some(
+                "published ports are taken from Docker and cannot be combined with other port \
+                 settings",
+            ),
+        ),
+        (
+            ExposePort {
+                dnat: false,
+                ..published
+            },
+            Some(
+                "published ports are taken from Docker and cannot be combined with other port \
+                 settings",
+            ),
+        ),
+        (
+            ExposePort {
+                host_port: 8000,
+                container_port: Some(9000),
+                host_port_range: range("8000-8010"),
+                container_port_range: range("9000-9009"),
+                ..port("80")
+            },
+            Some(
+                "host port range 8000-8010 and container port range 9000-9009 have to be of equal \
+                 width",
+            ),
+        ),
+        (
+            ExposePort {
+                host_port: 8001,
+                container_port: Some(9000),
+                host_port_range: range("8000-8010"),
+                container_port_range: range("9000-9010"),
+                ..port("80")
+            },
+            Some(
+                "exposed port range 8000-8010 has to hold the first ports of its ranges as host \
+                 and container port",
+            ),
+        ),
+        (
+            ExposePort {
+                host_port: 8000,
+                container_port: None,
+                host_port_range: range("8000-8010"),
+                container_port_range: range("9000-9010"),
+                ..port("80")
+            },
+            Some(
+                "exposed port range 8000-8010 has to hold the first ports of its ranges as host \
+                 and container port",
+            ),
+        ),
+        (
+            ExposePort {
+                host_port: 8000,
+                host_port_range: range("8000-8010"),
+                ..port("8000:80")
+            },
+            Some(
+                "exposed port range 8000-8010 cannot be mapped onto the single container port 80, \
+                 a container port range of equal width is required",
+            ),
+        ),
+        (
+            ExposePort {
+                host_port: 8000,
+                host_port_range: range("8000-8010"),
+                ..port("80")
+            },
+            Some("exposed port range requires both `host_port_range` and `container_port_range`"),
+        ),
+        (
+            ExposePort {
+                container_port: Some(9000),
+                container_port_range: range("9000-9010"),
+                ..port("80")
+            },
+            Some("exposed port range requires both `host_port_range` and `container_port_range`"),
+        ),
+        (
+            ExposePort {
+                container_port: None,
+                ..port("auto:80")
+            },
+            Some("exposed port with automatically assigned host port requires a container port"),
+        ),
+        (
+            ExposePort {
+                dnat: false,
+                ..port("auto:80")
+            },
+            Some("exposed port without DNAT requires an explicit host port"),
+        ),
+        (
+            ExposePort {
+                dnat: false,
+                host_ip: Some("192.0.2.1".parse().unwrap()),
+                ..port("80")
+            },
+            Some("exposed port without DNAT cannot be restricted to host address 192.0.2.1"),
+        ),
+        (
+            ExposePort {
+                family: String::new(),
+                ..port("80")
+            },
+            Some("exposed port 80 requires a family"),
+        ),
+    ] {
+        assert_eq!(
+            expected.map(str::to_owned),
+            expose_port.validate().err(),
+            "{:?}",
+            expose_port
+        );
+    }
+}
+
+#[test]
+fn parse_auto_port_range() {
+    let actual: Defaults = toml::from_str(r#"auto_port_range = "30000-32767""#).unwrap();
+    assert_eq!(
+        Some(PortRange {
+            start: 30000,
+            end: 32767,
+        }),
+        actual.auto_port_range
+    );
+
+    assert!(toml::from_str::<Defaults>(r#"auto_port_range = "32767-30000""#).is_err());
+    assert!(toml::from_str::<Defaults>(r#"auto_port_range = "30000""#).is_err());
+}
+
+#[test]
+fn parse_external_network_interface_list() {
+    let actual: ContainerToWiderWorldRule = toml::from_str(
+        r#"
+        network = "network"
+        verdict = "accept"
+        external_network_interface = ["eth0", "eth1"]
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        Some(vec!["eth0".to_owned(), "eth1".to_owned()]),
+        actual.external_network_interface
+    );
+
+    let actual: WiderWorldToContainerRule = toml::from_str(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = 80
+        external_network_interface = "eth0"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        Some(vec!["eth0".to_owned()]),
+        actual.external_network_interface
+    );
+}
+
+#[test]
+fn dfw_from_reader_and_slice() {
+    let path = "examples/full-single-file/dfw.toml";
+    let expected: DFW = load_file(path).unwrap();
+    let contents = fs::read(path).unwrap();
+
+    assert_eq!(expected, DFW::from_reader(Cursor::new(&contents)).unwrap());
+    assert_eq!(expected, DFW::from_slice(&contents).unwrap());
+}
+
+#[test]
+fn dfw_from_slice_invalid() {
+    match DFW::from_slice(b"[defaults]\nunknown_field = true\n") {
+        Err(DFWError::ConfigError { message }) => assert!(
+            message.starts_with("unknown field `unknown_field`"),
+            "{}",
+            message
+        ),
+        other => panic!("expected configuration error, got {:?}", other),
+    }
+}
+
+#[test]
+fn parse_dscp() {
+    for (dscp, expected) in &[
+        ("dscp = 46", 46),
+        ("dscp = 0", 0),
+        (r#"dscp = "ef""#, 46),
+        (r#"dscp = "cs5""#, 40),
+        (r#"dscp = "CS1""#, 8),
+        (r#"dscp = "af21""#, 18),
+        (r#"dscp = "af43""#, 38),
+        (r#"dscp = "be""#, 0),
+        (r#"dscp = "63""#, 63),
+    ] {
+        let fragment = format!(
+            r#"
+            network = "network"
+            dst_container = "dst_container"
+            expose_port = 5060
+            {}
+            "#,
+            dscp
+        );
+        let actual: WiderWorldToContainerRule = toml::from_str(&fragment).unwrap();
+
+        assert_eq!(Some(Dscp(*expected)), actual.dscp, "{}", dscp);
+    }
+
+    let fragment = r#"
+        network = "network"
+        verdict = "accept"
+        dscp = "af11"
+        "#;
+    let actual: ContainerToContainerRule = toml::from_str(fragment).unwrap();
+    assert_eq!(Some(Dscp(10)), actual.dscp);
+}
+
+#[test]
+fn parse_dscp_invalid() {
+    for dscp in &[
+        "dscp = 64",
+        "dscp = -1",
+        r#"dscp = "cs8""#,
+        r#"dscp = "af44""#,
+        r#"dscp = "af111""#,
+        r#"dscp = "voice""#,
+    ] {
+        let fragment = format!(
+            r#"
+            network = "network"
+            verdict = "accept"
+            {}
+            "#,
+            dscp
+        );
+
+        assert!(
+            toml::from_str::<ContainerToContainerRule>(&fragment).is_err(),
+            "{}",
+            dscp
+        );
+    }
+}
+
+#[test]
+fn parse_vlan_id() {
+    for (vlan_id, expected) in &[
+        ("vlan_id = 0", 0),
+        ("vlan_id = 100", 100),
+        ("vlan_id = 4094", 4094),
+    ] {
+        let fragment = format!(
+            r#"
+            network = "network"
+            dst_container = "dst_container"
+            expose_port = 80
+            {}
+            "#,
+            vlan_id
+        );
+        let actual: WiderWorldToContainerRule = toml::from_str(&fragment).unwrap();
+
+        assert_eq!(Some(VlanId(*expected)), actual.vlan_id, "{}", vlan_id);
+    }
+}
+
+#[test]
+fn parse_vlan_id_invalid() {
+    for vlan_id in &[
+        "vlan_id = 4095",
+        "vlan_id = 65536",
+        "vlan_id = -1",
+        r#"vlan_id = "100""#,
+    ] {
+        let fragment = format!(
+            r#"
+            network = "network"
+            verdict = "accept"
+            {}
+            "#,
+            vlan_id
+        );
+
+        assert!(
+            toml::from_str::<ContainerToWiderWorldRule>(&fragment).is_err(),
+            "{}",
+            vlan_id
+        );
+    }
+
+    let error = toml::from_str::<ContainerToWiderWorldRule>(
+        r#"
+        network = "network"
+        verdict = "accept"
+        vlan_id = 4095
+        "#,
+    )
+    .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("VLAN id 4095 is out of range, has to be at most 4094"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn parse_netns() {
+    let actual: Defaults = toml::from_str(r#"netns = "tenant-a""#).unwrap();
+    assert_eq!(Some("tenant-a".to_owned()), actual.netns);
+
+    let actual: Defaults = toml::from_str("").unwrap();
+    assert_eq!(None, actual.netns);
+}
+
+#[test]
+fn parse_stateful_verdict() {
+    let actual: ContainerToHostRule = toml::from_str(
+        r#"
+        network = "network"
+        verdict = "reject"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(StatefulVerdict::from(RuleVerdict::Reject), actual.verdict);
+    assert!(!actual.verdict.is_stateful());
+
+    let actual: ContainerToHostRule = toml::from_str(
+        r#"
+        network = "network"
+        verdict = { new = "reject", established = "accept" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        StatefulVerdict {
+            new: RuleVerdict::Reject,
+            established: RuleVerdict::Accept,
+        },
+        actual.verdict
+    );
+    assert!(actual.verdict.is_stateful());
+}
+
+#[test]
+fn parse_stateful_verdict_invalid() {
+    for verdict in &[
+        r#"verdict = "allow""#,
+        r#"verdict = { new = "reject" }"#,
+        r#"verdict = { new = "reject", established = "accept", related = "accept" }"#,
+    ] {
+        let fragment = format!(
+            r#"
+            network = "network"
+            {}
+            "#,
+            verdict
+        );
+
+        assert!(
+            toml::from_str::<ContainerToHostRule>(&fragment).is_err(),
+            "{}",
+            verdict
+        );
+    }
+}
+
+#[test]
+fn parse_dns() {
+    let actual: DFW = toml::from_str(
+        r#"
+        [dns]
+        servers = ["192.0.2.53", "2001:db8::53"]
+        src_container = { alias = "resolver-client" }
+        network = "network"
+        dns_over_tls = true
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        Some(Dns {
+            enabled: None,
+            servers: Some(vec![
+                "192.0.2.53".parse().unwrap(),
+                "2001:db8::53".parse().unwrap()
+            ]),
+            network: Some("network".to_owned()),
+            src_container: Some(ContainerSelector::Alias("resolver-client".to_owned())),
+            dns_over_tls: true,
+        }),
+        actual.dns
+    );
+
+    let actual: DFW = toml::from_str("[dns]").unwrap();
+    assert_eq!(Some(Dns::default()), actual.dns);
+}
+
+#[test]
+fn parse_family_default_policy() {
+    let actual: ContainerToWiderWorld = toml::from_str(r#"default_policy = "drop""#).unwrap();
+    assert_eq!(
+        FamilyVerdict::from(RuleVerdict::Drop),
+        actual.default_policy
+    );
+    assert!(!actual.default_policy.is_split());
+
+    let actual: ContainerToWiderWorld =
+        toml::from_str(r#"default_policy = { v4 = "accept", v6 = "drop" }"#).unwrap();
+    assert_eq!(
+        FamilyVerdict {
+            v4: RuleVerdict::Accept,
+            v6: RuleVerdict::Drop,
+        },
+        actual.default_policy
+    );
+    assert!(actual.default_policy.is_split());
+
+    assert!(
+        toml::from_str::<ContainerToWiderWorld>(r#"default_policy = { v4 = "accept" }"#).is_err()
+    );
+}
+
+#[test]
+fn parse_expose_port_published() {
+    for expose_port in &[r#""published""#, "{ published = true }"] {
+        let actual: WiderWorldToContainerRule = toml::from_str(&format!(
+            r#"
+            network = "network"
+            dst_container = "container"
+            expose_port = {}
+            "#,
+            expose_port
+        ))
+        .unwrap();
+        assert_eq!(1, actual.expose_port.len());
+        assert!(actual.expose_port[0].published);
+    }
+
+    let error = toml::from_str::<WiderWorldToContainerRule>(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = { published = true, family = "udp" }
+        "#,
+    )
+    .unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("published ports are taken from Docker"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn parse_expose_port_exposed() {
+    for expose_port in &[r#""exposed""#, "{ exposed = true }"] {
+        let actual: WiderWorldToContainerRule = toml::from_str(&format!(
+            r#"
+            network = "network"
+            dst_container = "container"
+            expose_port = {}
+            "#,
+            expose_port
+        ))
+        .unwrap();
+        assert_eq!(1, actual.expose_port.len());
+        assert!(actual.expose_port[0].exposed);
+        assert_eq!(AUTO_HOST_PORT, actual.expose_port[0].host_port);
+        assert_eq!(None, actual.expose_port[0].container_port);
+    }
+
+    let actual: WiderWorldToContainerRule = toml::from_str(
+        r#"
+        network = "network"
+        dst_container = "container"
+        expose_port = { exposed = true, host_port = 80, container_port = 8080 }
+        "#,
+    )
+    .unwrap();
+    assert!(actual.expose_port[0].exposed);
+    assert_eq!(80, actual.expose_port[0].host_port);
+    assert_eq!(Some(8080), actual.expose_port[0].container_port);
+}
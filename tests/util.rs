@@ -0,0 +1,573 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+use dfw::types::*;
+use dfw::util::*;
+
+#[test]
+fn exposed_host_ports_no_section() {
+    let dfw: DFW = toml::from_str("").unwrap();
+
+    assert!(exposed_host_ports(&dfw).is_empty());
+}
+
+#[test]
+fn exposed_host_ports_without_interfaces() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = ["80", "53/udp"]
+
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "dst_container"
+        expose_port = 443
+        external_network_interface = "none"
+        "#,
+    )
+    .unwrap();
+
+    // Without any external network interface, only the ports opened on all interfaces are listed
+    let expected = vec![(443, "tcp".to_owned(), None)];
+
+    assert_eq!(expected, exposed_host_ports(&dfw));
+}
+
+#[test]
+fn exposed_host_ports_expose_port_forms() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = ["eth0", "eth1"]
+
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "a"
+        expose_port = 80
+
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "b"
+        expose_port = ["443", "8080:80", "53/udp"]
+        external_network_interface = "eth1"
+
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "c"
+        expose_port = [
+            { host_port = 8443, container_port = 443 },
+            { host_port = 5353, family = ["tcp", "udp"] },
+        ]
+        "#,
+    )
+    .unwrap();
+
+    let eth0 = Some("eth0".to_owned());
+    let eth1 = Some("eth1".to_owned());
+    let expected = vec![
+        (80, "tcp".to_owned(), eth0.clone()),
+        (443, "tcp".to_owned(), eth1.clone()),
+        (8080, "tcp".to_owned(), eth1.clone()),
+        (53, "udp".to_owned(), eth1),
+        (8443, "tcp".to_owned(), eth0.clone()),
+        (5353, "tcp".to_owned(), eth0.clone()),
+        (5353, "udp".to_owned(), eth0),
+    ];
+
+    assert_eq!(expected, exposed_host_ports(&dfw));
+}
+
+#[test]
+fn exposed_host_ports_multiple_interfaces() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        external_network_interfaces = "eth0"
+
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "a"
+        expose_port = ["80", "53/udp"]
+        external_network_interface = ["eth1", "eth2"]
+        "#,
+    )
+    .unwrap();
+
+    let eth1 = Some("eth1".to_owned());
+    let eth2 = Some("eth2".to_owned());
+    let expected = vec![
+        (80, "tcp".to_owned(), eth1.clone()),
+        (80, "tcp".to_owned(), eth2.clone()),
+        (53, "udp".to_owned(), eth1),
+        (53, "udp".to_owned(), eth2),
+    ];
+
+    assert_eq!(expected, exposed_host_ports(&dfw));
+}
+
+#[test]
+fn check_config_version_absent() {
+    let dfw: DFW = toml::from_str("").unwrap();
+
+    assert_eq!(None, dfw.version);
+    assert_eq!(None, check_config_version(&dfw).unwrap());
+}
+
+#[test]
+fn check_config_version_supported() {
+    let dfw: DFW = toml::from_str(&format!("version = {}", CONFIG_VERSION)).unwrap();
+
+    assert_eq!(Some(CONFIG_VERSION), dfw.version);
+    assert_eq!(None, check_config_version(&dfw).unwrap());
+}
+
+#[test]
+fn check_config_version_outdated() {
+    let dfw: DFW = toml::from_str("version = 0").unwrap();
+
+    assert!(check_config_version(&dfw)
+        .unwrap()
+        .unwrap()
+        .contains("consider migrating"));
+}
+
+#[test]
+fn check_config_version_too_new() {
+    let dfw: DFW = toml::from_str(&format!("version = {}", CONFIG_VERSION + 1)).unwrap();
+
+    let error = check_config_version(&dfw).unwrap_err();
+    assert!(error.to_string().contains("is not supported"));
+}
+
+#[test]
+fn load_cidr_file_valid() {
+    assert_eq!(
+        vec!["192.0.2.0/24", "198.51.100.7", "203.0.113.0/25"],
+        load_cidr_file("resources/test/cidrs/allow-list.txt").unwrap()
+    );
+}
+
+#[test]
+fn load_cidr_file_malformed_line() {
+    assert_eq!(
+        "line 2 of CIDR file resources/test/cidrs/malformed.txt is not a valid IPv4 CIDR: \
+         '198.51.100.0/33'",
+        load_cidr_file("resources/test/cidrs/malformed.txt")
+            .unwrap_err()
+            .to_string()
+    );
+}
+
+#[test]
+fn size_in_bytes_units() {
+    assert_eq!(Some(512), size_in_bytes("512 bytes"));
+    assert_eq!(Some(2048), size_in_bytes("2 kbytes"));
+    assert_eq!(Some(104_857_600), size_in_bytes("100 mbytes"));
+    assert_eq!(Some(10_737_418_240), size_in_bytes("10 gbytes"));
+    assert_eq!(None, size_in_bytes("18446744073709551615 gbytes"));
+}
+
+#[test]
+fn timeout_in_seconds_units() {
+    assert_eq!(Some(10), timeout_in_seconds("10s"));
+    assert_eq!(Some(120), timeout_in_seconds("2m"));
+    assert_eq!(Some(3600), timeout_in_seconds("1h"));
+    assert_eq!(Some(172_800), timeout_in_seconds("2d"));
+    for timeout in &["10", "0s", "h", "1 h", "1.5h", "1w"] {
+        assert_eq!(None, timeout_in_seconds(timeout), "{}", timeout);
+    }
+}
+
+#[test]
+fn normalized_config_round_trip() {
+    for dfw in &[
+        load_path::<DFW>("resources/test/conf_path").unwrap(),
+        load_file::<DFW>("resources/test/conf-file.toml").unwrap(),
+        load_file::<DFW>("resources/test/inventory/conf.toml").unwrap(),
+    ] {
+        let normalized = normalized_config(dfw).unwrap();
+        let reloaded: DFW = toml::from_str(&normalized).unwrap();
+
+        assert_eq!(*dfw, reloaded, "{}", normalized);
+    }
+}
+
+#[test]
+fn normalized_config_shorthand_forms() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "network"
+        src_container = "a"
+        dst_container = { alias = "b" }
+        action = { new = "reject", established = "accept" }
+
+        [wider_world_to_container]
+
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "a"
+        expose_port = [
+            { host_port = "auto", container_port = 8080 },
+            { host_port = 53, family = ["tcp", "udp"] },
+            { host_port_range = "8000-8010", container_port_range = "9000-9010" },
+            { host_port = 8443, dnat = false },
+        ]
+        "#,
+    )
+    .unwrap();
+
+    let normalized = normalized_config(&dfw).unwrap();
+    assert!(
+        normalized.contains("src_container = \"a\"\n"),
+        "{}",
+        normalized
+    );
+    assert!(
+        normalized.contains("[container_to_container.rules.dst_container]\nalias = \"b\"\n"),
+        "{}",
+        normalized
+    );
+    assert!(
+        normalized.contains(
+            "[container_to_container.rules.verdict]\nestablished = \"accept\"\nnew = \"reject\"\n"
+        ),
+        "{}",
+        normalized
+    );
+    assert!(
+        normalized.contains("container_port = 8080\nfamily = \"tcp\"\nhost_port = \"auto\"\n"),
+        "{}",
+        normalized
+    );
+    assert_eq!(
+        2,
+        normalized.matches("host_port = 53\n").count(),
+        "{}",
+        normalized
+    );
+    assert!(
+        normalized.contains(
+            "container_port_range = \"9000-9010\"\nfamily = \"tcp\"\n\
+             host_port_range = \"8000-8010\"\n"
+        ),
+        "{}",
+        normalized
+    );
+    assert!(
+        normalized.contains("dnat = false\nfamily = \"tcp\"\nhost_port = 8443\n"),
+        "{}",
+        normalized
+    );
+    assert_eq!(1, normalized.matches("dnat = ").count(), "{}", normalized);
+    assert!(!normalized.contains("host_ip"), "{}", normalized);
+    assert_eq!(dfw, toml::from_str(&normalized).unwrap());
+}
+
+#[test]
+fn load_config_path_records_provenance() {
+    let dfw = load_config_path("resources/test/provenance").unwrap();
+    let source = |file: &str, line: usize| {
+        Some(Provenance {
+            file: format!("resources/test/provenance/{}", file),
+            line,
+        })
+    };
+
+    let wider_world_to_container = dfw.wider_world_to_container.unwrap().rules.unwrap();
+    assert_eq!(
+        vec![source("10-web.toml", 6), source("20-egress.toml", 10)],
+        wider_world_to_container
+            .into_iter()
+            .map(|rule| rule.provenance)
+            .collect::<Vec<_>>()
+    );
+    assert_eq!(
+        source("20-egress.toml", 5),
+        dfw.container_to_wider_world.unwrap().rules.unwrap()[0].provenance
+    );
+}
+
+#[test]
+fn load_config_file_records_provenance() {
+    let dfw = load_config_file("resources/test/conf-file.toml").unwrap();
+    let rules = dfw.container_to_container.unwrap().rules.unwrap();
+
+    assert_eq!(
+        Some(Provenance {
+            file: "resources/test/conf-file.toml".to_owned(),
+            line: 11,
+        }),
+        rules[0].provenance
+    );
+
+    // Apart from the provenance the configuration is loaded as usual
+    let mut expected = load_file::<DFW>("resources/test/conf-file.toml")
+        .unwrap()
+        .container_to_container
+        .unwrap()
+        .rules
+        .unwrap();
+    for (expected, rule) in expected.iter_mut().zip(&rules) {
+        expected.provenance = rule.provenance.clone();
+    }
+    assert_eq!(expected, rules);
+}
+
+#[test]
+fn load_config_path_scoped_to_hosts() {
+    let src_containers = |dfw: &DFW| -> Vec<String> {
+        dfw.container_to_wider_world
+            .iter()
+            .flat_map(|section| section.rules.iter().flatten())
+            .map(|rule| rule.src_container.as_ref().unwrap().to_string())
+            .collect()
+    };
+
+    let edge = load_config_path_for_host("resources/test/hosts", "edge-01").unwrap();
+    assert_eq!(vec!["container_a"], src_containers(&edge));
+    let wider_world_to_container = edge.wider_world_to_container.unwrap().rules.unwrap();
+    assert_eq!(1, wider_world_to_container.len());
+    // Blanking the `hosts` key keeps the location of the rules
+    assert_eq!(
+        Some(Provenance {
+            file: "resources/test/hosts/20-edge.toml".to_owned(),
+            line: 7,
+        }),
+        wider_world_to_container[0].provenance
+    );
+
+    let core = load_config_path_for_host("resources/test/hosts", "core-01").unwrap();
+    assert_eq!(vec!["container_a", "container_b"], src_containers(&core));
+    assert!(core.wider_world_to_container.is_none());
+
+    let other = load_config_path_for_host("resources/test/hosts", "worker-01").unwrap();
+    assert_eq!(vec!["container_a"], src_containers(&other));
+    assert!(other.wider_world_to_container.is_none());
+}
+
+#[test]
+fn load_config_file_resolves_vars() {
+    let dfw = load_config_file("resources/test/vars/conf.toml").unwrap();
+    let rules = dfw.wider_world_to_container.unwrap().rules.unwrap();
+    assert_eq!(2, rules.len());
+
+    // Scalars replace a string entirely or are interpolated into it
+    for rule in &rules {
+        assert_eq!("reverseproxy_network", rule.network);
+        assert_eq!("my_reverseproxy", rule.dst_container.to_string());
+    }
+    assert_eq!(22, rules[0].expose_port[0].host_port);
+
+    // Lists replace a string entirely or are spliced into the surrounding list
+    assert_eq!(
+        Some(vec![
+            "192.0.2.0/24".to_owned(),
+            "198.51.100.7/32".to_owned()
+        ]),
+        rules[0].source_cidr_v4
+    );
+    assert_eq!(
+        Some(vec![
+            "203.0.113.0/24".to_owned(),
+            "192.0.2.0/24".to_owned(),
+            "198.51.100.7/32".to_owned(),
+        ]),
+        rules[1].source_cidr_v4
+    );
+
+    // The rules keep their location
+    assert_eq!(
+        Some(Provenance {
+            file: "resources/test/vars/conf.toml".to_owned(),
+            line: 14,
+        }),
+        rules[1].provenance
+    );
+}
+
+#[test]
+fn resolve_vars_undefined() {
+    let config: toml::Value = toml::from_str(
+        r#"
+        [vars]
+        admin_cidr = "192.0.2.0/24"
+
+        [[wider_world_to_container.rules]]
+        network = "network"
+        dst_container = "a"
+        expose_port = 22
+        source_cidr_v4 = "${vars.admin_cidrs}"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "undefined variable `admin_cidrs` referenced by \
+         `wider_world_to_container.rules.source_cidr_v4`",
+        resolve_vars(config).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn resolve_vars_list_within_string() {
+    let config: toml::Value = toml::from_str(
+        r#"
+        [vars]
+        admin_cidrs = ["192.0.2.0/24"]
+
+        [[container_to_host.rules]]
+        network = "network"
+        matches = "ip saddr ${vars.admin_cidrs}"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "variable `admin_cidrs` referenced by `container_to_host.rules.matches` has to be a \
+         string, a number or a boolean to be interpolated",
+        resolve_vars(config).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn resolve_vars_without_vars() {
+    let config: toml::Value = toml::from_str(
+        r#"
+        [container_to_host]
+        default_policy = "${vars.policy}"
+        "#,
+    )
+    .unwrap();
+
+    // Without a `[vars]` table, strings are left as they are
+    assert_eq!(config, resolve_vars(config.clone()).unwrap());
+}
+
+#[test]
+fn flat_rules_match_structured_rules() {
+    let structured = DFW::from_slice(
+        br#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = "accept"
+
+        [[container_to_container.rules]]
+        network = "network_a"
+        verdict = "reject"
+
+        [container_to_host]
+        default_policy = "drop"
+
+        [[container_to_host.rules]]
+        network = "common_network"
+        verdict = "accept"
+
+        [[wider_world_to_container.rules]]
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+        "#,
+    )
+    .unwrap();
+    let flat = DFW::from_slice(
+        br#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "common_network"
+        src_container = "container_a"
+        dst_container = "container_b"
+        verdict = "accept"
+
+        [container_to_host]
+        default_policy = "drop"
+
+        [[rules]]
+        section = "wider_world_to_container"
+        network = "reverseproxy_network"
+        dst_container = "my_reverseproxy"
+        expose_port = 443
+
+        [[rules]]
+        section = "container_to_container"
+        network = "network_a"
+        verdict = "reject"
+
+        [[rules]]
+        section = "container_to_host"
+        network = "common_network"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(structured, flat);
+}
+
+#[test]
+fn load_config_file_records_provenance_of_flat_rules() {
+    let dfw = load_config_file("resources/test/flat_rules/conf.toml").unwrap();
+    let provenance = |line| {
+        Some(Provenance {
+            file: "resources/test/flat_rules/conf.toml".to_owned(),
+            line,
+        })
+    };
+
+    // Rules of the array follow the rules of their section
+    let container_to_container = dfw.container_to_container.unwrap().rules.unwrap();
+    assert_eq!(2, container_to_container.len());
+    assert_eq!(provenance(5), container_to_container[0].provenance);
+    assert_eq!("network_a", container_to_container[1].network);
+    assert_eq!(provenance(17), container_to_container[1].provenance);
+
+    let wider_world_to_container = dfw.wider_world_to_container.unwrap().rules.unwrap();
+    assert_eq!(1, wider_world_to_container.len());
+    assert_eq!(provenance(11), wider_world_to_container[0].provenance);
+}
+
+#[test]
+fn distribute_rules_invalid_section() {
+    let distribute = |section: &str| {
+        let config: toml::Value = toml::from_str(&format!(
+            r#"
+            [[rules]]
+            {}
+            network = "network"
+            verdict = "accept"
+            "#,
+            section
+        ))
+        .unwrap();
+        distribute_rules(config).unwrap_err().to_string()
+    };
+
+    assert_eq!(
+        "rule 1 of `rules` is missing the key `section`",
+        distribute("")
+    );
+    assert_eq!(
+        "rule 1 of `rules` has invalid section \"initialization\", expected one of: \
+         container_to_container, container_to_wider_world, container_to_host, \
+         wider_world_to_container, container_dnat",
+        distribute(r#"section = "initialization""#)
+    );
+}
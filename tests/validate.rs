@@ -0,0 +1,1024 @@
+// Copyright 2017 - 2019 Pit Kleyersburg <pitkley@googlemail.com>
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or
+// http://www.apache.org/licenses/LICENSE-2.0> or the MIT license
+// <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your
+// option. This file may not be copied, modified or distributed
+// except according to those terms.
+
+use dfw::types::*;
+use dfw::validate::*;
+
+#[test]
+fn check_matches_valid() {
+    for matches in &[
+        "tcp dport 8080",
+        "tcp dport { 80, 443 }",
+        "ip saddr != 192.0.2.0/24 udp dport 53",
+        "meta l4proto tcp ct state new",
+        "tcp dport 1000-2000 meta nfproto ipv4",
+        r#"meta iifname "eth0""#,
+        "ip6 daddr 2001:db8::1 tcp dport 8080",
+    ] {
+        assert_eq!(Ok(()), check_matches(matches), "{}", matches);
+    }
+}
+
+#[test]
+fn check_matches_invalid() {
+    for (matches, expected) in &[
+        ("", "matches must not be empty"),
+        ("tcp dport { 80, 443", "unbalanced `{`"),
+        ("tcp dport 80, 443 }", "unbalanced `}`"),
+        ("tcp dport { 80, 443 ]", "unbalanced `]`"),
+        (r#"meta iifname "eth0"#, "unterminated quote"),
+        (
+            "-p tcp --dport 80",
+            "`-p` looks like an iptables option, use the nftables syntax instead",
+        ),
+        (
+            "tcp --dport 80",
+            "`--dport` looks like an iptables option, use the nftables syntax instead",
+        ),
+        (
+            "tcp dport 80 ACCEPT",
+            "verdict `ACCEPT` conflicts with the verdict of the rule",
+        ),
+        (
+            r#"tcp dport 80 comment "web""#,
+            "DFW already sets the comment of the rule",
+        ),
+        (
+            "tcp dport 80; flush ruleset",
+            "`;` would terminate the rule",
+        ),
+    ] {
+        assert_eq!(
+            Err((*expected).to_owned()),
+            check_matches(matches),
+            "{}",
+            matches
+        );
+    }
+}
+
+#[test]
+fn validate_cidr_file() {
+    for (file, valid) in &[("allow-list.txt", true), ("malformed.txt", false)] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [[wider_world_to_container.rules]]
+            network = "frontend"
+            dst_container = "web"
+            expose_port = 443
+            source_cidr_v4 = "@file:resources/test/cidrs/{}"
+            "#,
+            file
+        ))
+        .unwrap();
+
+        assert_eq!(*valid, validate(&dfw).is_ok(), "{}", file);
+    }
+}
+
+#[test]
+fn validate_dns_source_cidr() {
+    for (source_cidr, valid) in &[("@dns:partner.example.com", true), ("@dns:", false)] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [[wider_world_to_container.rules]]
+            network = "frontend"
+            dst_container = "web"
+            expose_port = 443
+            source_cidr_v6 = "{}"
+            "#,
+            source_cidr
+        ))
+        .unwrap();
+
+        assert_eq!(*valid, validate(&dfw).is_ok(), "{}", source_cidr);
+    }
+}
+
+#[test]
+fn validate_points_at_offending_rule() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_host]
+        default_policy = "drop"
+
+        [[container_to_host.rules]]
+        network = "network"
+        matches = "tcp dport 22"
+        verdict = "accept"
+
+        [[container_to_host.rules]]
+        network = "network"
+        matches = "-p tcp"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+
+    let error = validate(&dfw).unwrap_err();
+    assert_eq!(
+        "rule 2 of section `container_to_host` has invalid matches '-p tcp': `-p` looks like an \
+         iptables option, use the nftables syntax instead",
+        error.to_string()
+    );
+}
+
+#[test]
+fn validate_netns() {
+    for (netns, valid) in &[("tenant-a", true), ("", false), ("../tenant-a", false)] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            netns = "{}"
+            "#,
+            netns
+        ))
+        .unwrap();
+
+        assert_eq!(*valid, validate(&dfw).is_ok(), "{}", netns);
+    }
+}
+
+#[test]
+fn validate_docker_concurrency() {
+    for (concurrency, valid) in &[(1, true), (16, true), (0, false)] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            docker_concurrency = {}
+            "#,
+            concurrency
+        ))
+        .unwrap();
+
+        assert_eq!(*valid, validate(&dfw).is_ok(), "{}", concurrency);
+    }
+}
+
+#[test]
+fn validate_interface_chains() {
+    for (network_chains, external_interface_chains, valid) in &[
+        (true, false, true),
+        (false, true, true),
+        (true, true, false),
+    ] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            network_chains = {}
+            external_interface_chains = {}
+            "#,
+            network_chains, external_interface_chains
+        ))
+        .unwrap();
+
+        assert_eq!(
+            *valid,
+            validate(&dfw).is_ok(),
+            "{} {}",
+            network_chains,
+            external_interface_chains
+        );
+    }
+}
+
+#[test]
+fn validate_cross_network_container_to_container() {
+    for (rule, error) in &[
+        (
+            r#"network = "network_a"
+            dst_container = "container_b""#,
+            None,
+        ),
+        (
+            r#"src_network = "network_a"
+            dst_network = "network_b"
+            dst_container = "container_b"
+            expose_port = 50000"#,
+            None,
+        ),
+        (
+            r#"network = "network_a"
+            expose_port = 50000"#,
+            Some("exposed ports require the destination network to differ from the network"),
+        ),
+        (
+            r#"src_network = "network_a"
+            dst_network = "network_b"
+            expose_port = 50000"#,
+            Some("bridging networks requires the destination container"),
+        ),
+        (
+            r#"src_network = "network_a"
+            dst_network = "network_b"
+            dst_container = "container_b""#,
+            Some("bridging networks requires the exposed ports"),
+        ),
+        (
+            r#"src_network = "*"
+            dst_network = "network_b"
+            dst_container = "container_b"
+            expose_port = 50000"#,
+            Some("bridging networks does not support the wildcard network"),
+        ),
+        (
+            r#"src_network = "network_a"
+            dst_network = "network_b"
+            dst_container = "container_b"
+            expose_port = 50000
+            matches = "tcp sport 1024-65535""#,
+            Some(
+                "bridging networks cannot be combined with `matches`, `min_ct_bytes`, \
+                 `mirror_to` or `dscp`",
+            ),
+        ),
+    ] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [container_to_container]
+            default_policy = "drop"
+
+            [[container_to_container.rules]]
+            verdict = "accept"
+            {}
+            "#,
+            rule
+        ))
+        .unwrap();
+
+        assert_eq!(
+            error.map(|error| format!(
+                "rule 1 of section `container_to_container` is invalid: {}",
+                error
+            )),
+            validate(&dfw).err().map(|error| error.to_string()),
+            "{}",
+            rule
+        );
+    }
+
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        src_network = "network_a"
+        dst_network = "network_b"
+        dst_container = "container_b"
+        expose_port = 50000
+        verdict = "reject"
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        "rule 1 of section `container_to_container` is invalid: \
+         bridging networks requires the verdict to be `accept`",
+        validate(&dfw).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn validate_empty_families() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "proxy"
+        expose_port = 443
+        families = ["v4"]
+
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "proxy"
+        expose_port = 80
+        families = []
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "rule 2 of section `wider_world_to_container` has no families, omit `families` to apply it \
+         to both",
+        validate(&dfw).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn validate_log_verdict() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_host]
+        default_policy = "drop"
+
+        [[container_to_host.rules]]
+        network = "backend"
+        verdict = "log"
+
+        [[container_to_host.rules]]
+        network = "backend"
+        verdict = { new = "log", established = "accept" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        "rule 2 of section `container_to_host` uses the `log` pseudo-verdict, which cannot depend \
+         on the conntrack state of the connection",
+        validate(&dfw).unwrap_err().to_string()
+    );
+
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_wider_world]
+        default_policy = { v4 = "accept", v6 = "log" }
+        "#,
+    )
+    .unwrap();
+    assert_eq!(
+        "the default policy of section `container_to_wider_world` has to decide on the packets, it \
+         cannot be the `log` pseudo-verdict",
+        validate(&dfw).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn validate_dst_ip_with_dnat_to() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "proxy"
+        expose_port = 443
+        dst_ip = "172.18.0.2"
+
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "proxy"
+        expose_port = 80
+        dst_ip = "172.18.0.2"
+        dnat_to = "192.0.2.10"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "rule 2 of section `wider_world_to_container` cannot pin the destination address \
+         172.18.0.2 and DNAT to 192.0.2.10 at once",
+        validate(&dfw).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn validate_interior_only() {
+    let validate_rule = |rule: &str| -> Result<(), String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [[wider_world_to_container.rules]]
+            network = "backend"
+            dst_container = "db"
+            expose_port = 5432
+            {}
+            "#,
+            rule
+        ))
+        .unwrap();
+        validate(&dfw).map_err(|error| error.to_string())
+    };
+
+    assert!(validate_rule("").is_ok());
+    assert!(validate_rule(
+        r#"
+        interior_only = true
+        from_containers = [{ name = "api" }, { alias = "worker" }]
+        "#
+    )
+    .is_ok());
+    assert_eq!(
+        "rule 1 of section `wider_world_to_container` is interior only and thus requires the \
+         containers in `from_containers`",
+        validate_rule("interior_only = true").unwrap_err()
+    );
+    assert_eq!(
+        "rule 1 of section `wider_world_to_container` lists containers in \
+         `from_containers`, which requires `interior_only = true`",
+        validate_rule(r#"from_containers = "api""#).unwrap_err()
+    );
+    assert_eq!(
+        "rule 1 of section `wider_world_to_container` is interior only and thus cannot use \
+         `external_network_interface`, `source_cidr`, `dnat_to`, `notrack`, `quota`, \
+         `ct_timeout` or `clamp_mss_to_pmtu`",
+        validate_rule(
+            r#"
+            interior_only = true
+            from_containers = "api"
+            source_cidr = "192.0.2.0/24"
+            "#
+        )
+        .unwrap_err()
+    );
+}
+
+#[test]
+fn validate_no_external_network_interface() {
+    let validate_interface = |interface: &str| -> Result<(), String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [container_to_wider_world]
+            default_policy = "accept"
+
+            [[container_to_wider_world.rules]]
+            network = "backend"
+            verdict = "accept"
+
+            [[wider_world_to_container.rules]]
+            network = "backend"
+            dst_container = "app"
+            expose_port = 8080
+            external_network_interface = {}
+            "#,
+            interface
+        ))
+        .unwrap();
+        validate(&dfw).map_err(|error| error.to_string())
+    };
+
+    assert!(validate_interface(r#""none""#).is_ok());
+    assert!(validate_interface(r#"["eth0", "eth1"]"#).is_ok());
+    assert_eq!(
+        "rule 1 of section `wider_world_to_container` cannot combine the external network \
+         interface `none` with other interfaces",
+        validate_interface(r#"["none", "eth1"]"#).unwrap_err()
+    );
+}
+
+#[test]
+fn validate_quota() {
+    let validate_quota = |quota: &str| -> Result<(), String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [[wider_world_to_container.rules]]
+            network = "backend"
+            dst_container = "app"
+            expose_port = 8080
+            quota = {}
+            "#,
+            quota
+        ))
+        .unwrap();
+        validate(&dfw).map_err(|error| error.to_string())
+    };
+
+    assert!(validate_quota(r#"{ bytes = "10 gbytes" }"#).is_ok());
+    assert!(validate_quota(r#"{ bytes = "500 mbytes", over = false }"#).is_ok());
+    for bytes in &["10", "10 gigabytes", "0 bytes", "-1 kbytes", "ten gbytes"] {
+        assert_eq!(
+            format!(
+                "rule 1 of section `wider_world_to_container` has the quota '{}', which is not a \
+                 valid size, e.g. '10 gbytes'",
+                bytes
+            ),
+            validate_quota(&format!(r#"{{ bytes = "{}" }}"#, bytes)).unwrap_err()
+        );
+    }
+}
+
+#[test]
+fn validate_host_ip() {
+    let validate_host_ip = |rule: &str| -> Result<(), String> {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [[wider_world_to_container.rules]]
+            network = "backend"
+            dst_container = "app"
+            expose_port = 8080
+            {}
+            "#,
+            rule
+        ))
+        .unwrap();
+        validate(&dfw).map_err(|error| error.to_string())
+    };
+
+    assert!(validate_host_ip("").is_ok());
+    assert!(validate_host_ip(r#"host_ip = "192.0.2.1""#).is_ok());
+    assert!(validate_host_ip(
+        r#"host_ip = "2001:db8::1"
+           families = ["v6"]"#
+    )
+    .is_ok());
+    assert_eq!(
+        "rule 1 of section `wider_world_to_container` has the host address 192.0.2.1, which is \
+         not of the families of the rule",
+        validate_host_ip(
+            r#"host_ip = "192.0.2.1"
+               families = ["v6"]"#
+        )
+        .unwrap_err()
+    );
+    assert_eq!(
+        "rule 1 of section `wider_world_to_container` has the IPv6 host address 2001:db8::1, \
+         which cannot be combined with the DNAT target 192.0.2.10",
+        validate_host_ip(
+            r#"host_ip = "2001:db8::1"
+               dnat_to = "192.0.2.10""#
+        )
+        .unwrap_err()
+    );
+}
+
+#[test]
+fn validate_min_ct_bytes() {
+    let validate_min_ct_bytes = |min_ct_bytes: &str| {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [container_to_wider_world]
+            default_policy = "accept"
+
+            [[container_to_wider_world.rules]]
+            network = "network"
+            min_ct_bytes = "{}"
+            verdict = "drop"
+            "#,
+            min_ct_bytes
+        ))
+        .unwrap();
+        validate(&dfw)
+    };
+
+    assert!(validate_min_ct_bytes("100 mbytes").is_ok());
+    for min_ct_bytes in &["100", "100 mb", "0 bytes", "-1 kbytes", "100 mbytes 1"] {
+        assert_eq!(
+            format!(
+                "rule 1 of section `container_to_wider_world` has the minimum connection size \
+                 '{}', which is not a valid size, e.g. '100 mbytes'",
+                min_ct_bytes
+            ),
+            validate_min_ct_bytes(min_ct_bytes).unwrap_err().to_string()
+        );
+    }
+}
+
+#[test]
+fn validate_ct_timeouts() {
+    let validate_ct_timeouts = |ct_timeouts: &str, ct_timeout: &str| {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            ct_timeouts = {}
+
+            [[wider_world_to_container.rules]]
+            network = "network"
+            dst_container = "container"
+            expose_port = 443
+            ct_timeout = "{}"
+            "#,
+            ct_timeouts, ct_timeout
+        ))
+        .unwrap();
+        validate(&dfw).map_err(|error| error.to_string())
+    };
+
+    assert!(validate_ct_timeouts(
+        r#"{ scanned = { tcp_established = "1h", tcp_close = "10s" } }"#,
+        "scanned"
+    )
+    .is_ok());
+    for (ct_timeouts, error) in &[
+        (
+            r#"{ scanned = {} }"#,
+            "conntrack timeout policy `scanned` defines no timeouts",
+        ),
+        (
+            r#"{ scanned = { tcp_closed = "10s" } }"#,
+            "conntrack timeout policy `scanned` has the unknown timeout `tcp_closed`, expected the \
+             protocol and the state, e.g. `tcp_established`",
+        ),
+        (
+            r#"{ scanned = { tcp_close = "10s", udp_replied = "10s" } }"#,
+            "conntrack timeout policy `scanned` defines timeouts of both `tcp` and `udp`, a policy \
+             only applies to a single protocol",
+        ),
+        (
+            r#"{ scanned = { tcp_close = "10" } }"#,
+            "conntrack timeout policy `scanned` has the timeout '10' for `tcp_close`, which is not \
+             a valid timeout, e.g. '1h'",
+        ),
+    ] {
+        assert_eq!(
+            Err(error.to_string()),
+            validate_ct_timeouts(ct_timeouts, "scanned"),
+            "{}",
+            ct_timeouts
+        );
+    }
+    assert_eq!(
+        Err(
+            "conntrack timeout policy name `1-scanned` has to consist of letters, digits and \
+             underscores, starting with a letter"
+                .to_owned()
+        ),
+        validate_ct_timeouts(r#"{ 1-scanned = { tcp_close = "10s" } }"#, "1-scanned")
+    );
+    assert_eq!(
+        Err(
+            "rule 1 of section `wider_world_to_container` references the undefined conntrack \
+             timeout policy `unknown`"
+                .to_owned()
+        ),
+        validate_ct_timeouts(r#"{ scanned = { tcp_close = "10s" } }"#, "unknown")
+    );
+}
+
+#[test]
+fn validate_log_rate() {
+    for (log_rate, error) in &[
+        ("100/second", None),
+        ("5/minute", None),
+        (
+            "100",
+            Some("log rate '100' is not a valid rate, e.g. '100/second'"),
+        ),
+        (
+            "0/second",
+            Some("log rate '0/second' is not a valid rate, e.g. '100/second'"),
+        ),
+        (
+            "100/seconds",
+            Some("log rate '100/seconds' is not a valid rate, e.g. '100/second'"),
+        ),
+    ] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            log_rate = "{}"
+            "#,
+            log_rate
+        ))
+        .unwrap();
+
+        assert_eq!(
+            error.map(str::to_owned),
+            validate(&dfw).err().map(|error| error.to_string()),
+            "{}",
+            log_rate
+        );
+    }
+}
+
+#[test]
+fn validate_section_order() {
+    for (section_order, error) in &[
+        (r#"["container_to_host", "container_to_container"]"#, None),
+        (
+            r#"["container_to_hosts"]"#,
+            Some("section order contains unknown section 'container_to_hosts'"),
+        ),
+        (
+            r#"["dns", "container_to_host", "dns"]"#,
+            Some("section order contains section 'dns' more than once"),
+        ),
+    ] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [defaults]
+            section_order = {}
+            "#,
+            section_order
+        ))
+        .unwrap();
+
+        assert_eq!(
+            error.map(str::to_owned),
+            validate(&dfw).err().map(|error| error.to_string()),
+            "{}",
+            section_order
+        );
+    }
+}
+
+#[test]
+fn validate_notrack() {
+    for (options, error) in &[
+        ("expose_port = { host_port = 8080, dnat = false }", None),
+        (
+            "expose_port = [{ host_port = 8080, dnat = false }, { host_port = 443 }]",
+            Some("cannot DNAT exposed port 443"),
+        ),
+        (
+            "expose_port = { host_port_range = \"8000-8010\", container_port_range = \"9000-9010\" }",
+            Some("cannot DNAT exposed ports 8000-8010"),
+        ),
+        ("expose_port = \"published\"", Some("cannot DNAT the published ports")),
+        (
+            "expose_port = { host_port = 8080, dnat = false }\ndrain = true",
+            Some("cannot be drained"),
+        ),
+    ] {
+        let dfw: DFW = toml::from_str(&format!(
+            r#"
+            [[wider_world_to_container.rules]]
+            network = "frontend"
+            dst_container = "web"
+            notrack = true
+            {}
+            "#,
+            options
+        ))
+        .unwrap();
+
+        assert_eq!(
+            error.map(|error| format!(
+                "rule 1 of section `wider_world_to_container` bypasses connection tracking and \
+                 thus {}",
+                error
+            )),
+            validate(&dfw).err().map(|error| error.to_string()),
+            "{}",
+            options
+        );
+    }
+}
+
+#[test]
+fn lint_shadowed_rule() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "proxy"
+        verdict = "accept"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "proxy"
+        dst_container = "db"
+        verdict = "drop"
+
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        verdict = "reject"
+
+        [[container_to_wider_world.rules]]
+        network = "backend"
+        matches = "tcp dport 25"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            "rule 2 of section `container_to_container` can never match, it is shadowed by rule 1",
+            "rule 2 of section `container_to_wider_world` can never match, it is shadowed by rule 1",
+        ],
+        lint(&dfw)
+    );
+}
+
+#[test]
+fn lint_not_shadowed_rule() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        # The more specific rule comes first
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "proxy"
+        dst_container = "db"
+        verdict = "drop"
+
+        [[container_to_container.rules]]
+        network = "backend"
+        src_container = "proxy"
+        verdict = "accept"
+
+        # Different matches
+        [[container_to_container.rules]]
+        network = "frontend"
+        matches = "tcp dport 80"
+        verdict = "accept"
+
+        [[container_to_container.rules]]
+        network = "frontend"
+        matches = "tcp dport 443"
+        verdict = "accept"
+
+        [container_to_host]
+        default_policy = "drop"
+
+        # Conditional rules only shadow rules with the same condition
+        [[container_to_host.rules]]
+        network = "backend"
+        verdict = "accept"
+        when = { hostname = "edge-*" }
+
+        [[container_to_host.rules]]
+        network = "backend"
+        src_container = "app"
+        verdict = "reject"
+
+        # Log-only rules don't decide on the traffic
+        [container_to_wider_world]
+        default_policy = "accept"
+
+        [[container_to_wider_world.rules]]
+        verdict = "log"
+
+        [[container_to_wider_world.rules]]
+        network = "backend"
+        verdict = "reject"
+        "#,
+    )
+    .unwrap();
+
+    assert!(lint(&dfw).is_empty());
+}
+
+#[test]
+fn lint_public_exposure() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "proxy"
+        expose_port = 443
+
+        [[wider_world_to_container.rules]]
+        network = "frontend"
+        dst_container = "proxy"
+        expose_port = 80
+        allow_public = true
+
+        [[wider_world_to_container.rules]]
+        network = "backend"
+        dst_container = "app"
+        expose_port = 8080
+        source_cidr = "192.0.2.0/24"
+
+        [[wider_world_to_container.rules]]
+        network = "backend"
+        dst_container = "app"
+        expose_port = 8443
+        external_network_interface = "eth1"
+
+        [[wider_world_to_container.rules]]
+        network = "backend"
+        dst_container = "db"
+        expose_port = 5432
+        interior_only = true
+        from_containers = "app"
+
+        [[wider_world_to_container.rules]]
+        network = "backend"
+        dst_container = "app"
+        expose_port = 9443
+        external_network_interface = "none"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        vec![
+            "rule 1 of section `wider_world_to_container` exposes its ports to everyone, restrict \
+             `source_cidr` or `external_network_interface` or acknowledge it through \
+             `allow_public = true`",
+            "rule 6 of section `wider_world_to_container` exposes its ports to everyone, restrict \
+             `source_cidr` or `external_network_interface` or acknowledge it through \
+             `allow_public = true`",
+        ],
+        lint(&dfw)
+    );
+}
+
+#[test]
+fn diagnostics_json_shape() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [defaults]
+        netns = "../tenant-a"
+        docker_concurrency = 0
+
+        [container_to_host]
+        default_policy = "drop"
+
+        [[container_to_host.rules]]
+        network = "network"
+        verdict = "accept"
+
+        [[container_to_host.rules]]
+        network = "network"
+        matches = "-p tcp"
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+
+    let json: serde_json::Value = serde_json::from_str(&diagnostics_json(&dfw).unwrap()).unwrap();
+    assert_eq!(
+        serde_json::json!([
+            {
+                "severity": "error",
+                "section": "defaults",
+                "rule": null,
+                "message": "network namespace '../tenant-a' is not a valid namespace name",
+            },
+            {
+                "severity": "error",
+                "section": "defaults",
+                "rule": null,
+                "message": "Docker concurrency has to be at least 1",
+            },
+            {
+                "severity": "error",
+                "section": "container_to_host",
+                "rule": 2,
+                "message": "rule 2 of section `container_to_host` has invalid matches '-p tcp': \
+                            `-p` looks like an iptables option, use the nftables syntax instead",
+            },
+            {
+                "severity": "warning",
+                "section": "container_to_host",
+                "rule": 2,
+                "message": "rule 2 of section `container_to_host` can never match, it is \
+                            shadowed by rule 1",
+            },
+        ]),
+        json
+    );
+
+    // `validate` reports the first error only
+    assert_eq!(
+        "network namespace '../tenant-a' is not a valid namespace name",
+        validate(&dfw).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn validate_tags_with_container() {
+    let dfw: DFW = toml::from_str(
+        r#"
+        [container_to_container]
+        default_policy = "drop"
+
+        [[container_to_container.rules]]
+        network = "mesh"
+        src_tags = { tier = "web" }
+        dst_tags = { tier = "db" }
+        verdict = "accept"
+
+        [[container_to_container.rules]]
+        network = "mesh"
+        src_tags = { tier = "web" }
+        dst_container = "db"
+        dst_tags = { tier = "db" }
+        verdict = "accept"
+        "#,
+    )
+    .unwrap();
+
+    assert_eq!(
+        "rule 2 of section `container_to_container` cannot select the destination container by \
+         name and by tags at once",
+        validate(&dfw).unwrap_err().to_string()
+    );
+}
+
+#[test]
+fn validate_base_chains_forward_policy() {
+    let base_chains = r#"
+        [defaults.base_chains]
+        input = { policy = "drop" }
+        forward = { priority = 10, policy = "drop" }
+    "#;
+    let dfw: DFW = toml::from_str(base_chains).unwrap();
+    assert!(validate(&dfw).is_ok());
+
+    // The policy of the forward chain is set by the container-to-container section
+    let dfw: DFW = toml::from_str(&format!(
+        r#"
+        {}
+
+        [container_to_container]
+        default_policy = "drop"
+        "#,
+        base_chains
+    ))
+    .unwrap();
+    assert_eq!(
+        "the policy of the forward chain is set by the default policy of the section \
+         `container_to_container`, it cannot be overridden through `base_chains`",
+        validate(&dfw).unwrap_err().to_string()
+    );
+}